@@ -0,0 +1,116 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::stats::{OpStats, Stats};
+
+/// The parts of a `--output json` file a new run is compared against.
+#[derive(Debug, Deserialize)]
+pub struct Baseline {
+    throughput: f64,
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Operation {
+    operation:   String,
+    throughput:  f64,
+    percentiles: Vec<Percentile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Percentile {
+    percentile: f64,
+    latency_us: u64,
+}
+
+/// How much worse than the baseline a run may be, in percent.
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub throughput_drop_pct: f64,
+    pub p99_increase_pct:    f64,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        serde_json::from_str(&text).map_err(|e| format!("{} is not a lumen-bench JSON result: {e}", path.display()))
+    }
+
+    /// Print how `stats` compares and return the regressions beyond
+    /// `thresholds`.
+    pub fn compare(&self, stats: &Stats, elapsed_secs: f64, thresholds: Thresholds) -> Vec<String> {
+        let mut regressions = Vec::new();
+        let mut throughput  = |label: &str, before: f64, after: f64| {
+            let change = delta_pct(before, after);
+            let failed = change < -thresholds.throughput_drop_pct;
+            println!("{} {} throughput: {:.2} → {:.2} ops/sec ({:+.1}%)", mark(failed), label, before, after, change);
+            if failed {
+                regressions.push(format!("{label} throughput dropped {:.1}%", -change));
+            }
+        };
+
+        println!("\n📐 Compared with baseline");
+        throughput("Total", self.throughput, stats.count() as f64 / elapsed_secs);
+        for op in stats.issued() {
+            let Some(before) = self.operations.iter().find(|b| b.operation == op.label()) else {
+                println!("➖ {}: not in the baseline", op.label());
+                continue;
+            };
+            throughput(op.label(), before.throughput, op.count() as f64 / elapsed_secs);
+        }
+
+        for op in stats.issued() {
+            let Some(before) = self.operations.iter().find(|b| b.operation == op.label()) else {
+                continue;
+            };
+            for (percentile, gated) in [(50.0, false), (99.0, true)] {
+                let Some(base) = before.latency_at(percentile) else {
+                    continue;
+                };
+                let now    = latency_at(op, percentile);
+                let change = delta_pct(base as f64, now as f64);
+                let failed = gated && change > thresholds.p99_increase_pct;
+                println!(
+                    "{} {} P{}: {} → {} µs ({:+.1}%)",
+                    mark(failed),
+                    op.label(),
+                    percentile,
+                    base,
+                    now,
+                    change
+                );
+                if failed {
+                    regressions.push(format!("{} P99 rose {:.1}%", op.label(), change));
+                }
+            }
+        }
+        regressions
+    }
+}
+
+impl Operation {
+    fn latency_at(&self, percentile: f64) -> Option<u64> {
+        self.percentiles.iter().find(|p| p.percentile == percentile).map(|p| p.latency_us)
+    }
+}
+
+fn latency_at(op: &OpStats, percentile: f64) -> u64 {
+    op.hist.value_at_percentile(percentile)
+}
+
+fn delta_pct(before: f64, after: f64) -> f64 {
+    if before == 0.0 {
+        0.0
+    } else {
+        (after - before) / before * 100.0
+    }
+}
+
+fn mark(failed: bool) -> &'static str {
+    if failed {
+        "❌"
+    } else {
+        "✔️ "
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::Rng;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::Channel;
+use tonic::{Code, Status};
+
+use crate::pb::key_value_store_client::KeyValueStoreClient;
+use crate::pb::{PutRequest, PutResponse};
+
+/// Just over tonic's default 4 MiB limit on a decoded message.
+const OVERSIZED: usize = (4 << 20) + 1;
+
+/// Faults are cut short somewhere in this window after they are sent.
+const MAX_ABORT_DELAY: Duration = Duration::from_micros(500);
+
+/// A fault injected in place of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A PUT abandoned before its response, which resets its stream.
+    Cancel,
+    /// A PUT on a fresh connection that is closed before the response.
+    Disconnect,
+    /// A PUT larger than the server accepts.
+    Oversized,
+    /// A PUT whose body does not decode as a `PutRequest`.
+    Malformed,
+}
+
+const FAULTS: [Fault; 4] = [Fault::Cancel, Fault::Disconnect, Fault::Oversized, Fault::Malformed];
+
+impl Fault {
+    fn label(self) -> &'static str {
+        match self {
+            Fault::Cancel => "Cancelled RPCs",
+            Fault::Disconnect => "Dropped connections",
+            Fault::Oversized => "Oversized requests",
+            Fault::Malformed => "Malformed requests",
+        }
+    }
+
+    /// Faults the server should answer with an error, rather than ones the
+    /// client walks away from.
+    fn expects_rejection(self) -> bool {
+        matches!(self, Fault::Oversized | Fault::Malformed)
+    }
+}
+
+/// A `PutRequest` with the wrong wire types: each field is a varint where
+/// the server expects length-delimited bytes.
+#[derive(Clone, PartialEq, prost::Message)]
+struct Malformed {
+    #[prost(uint64, tag = "1")]
+    key:   u64,
+    #[prost(uint64, tag = "2")]
+    value: u64,
+}
+
+/// How a fault played out.
+enum Outcome {
+    /// The client gave up first, as intended.
+    Aborted,
+    Rejected(Code),
+    /// The server answered as if the request were fine.
+    Accepted,
+}
+
+/// What the faults of a run did, shared by the workers.
+pub struct Chaos {
+    /// Chance that a request is replaced by a fault.
+    pub probability: f64,
+    faults:          [FaultCounts; 4],
+    /// Requests that directly followed a fault on the same worker.
+    after_fault:     AtomicU64,
+    /// Of those, the ones that succeeded.
+    recovered:       AtomicU64,
+}
+
+#[derive(Default)]
+struct FaultCounts {
+    aborted:  AtomicU64,
+    accepted: AtomicU64,
+    /// Rejections by status code.
+    rejected: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Chaos {
+    pub fn new(probability: f64) -> Self {
+        Self {
+            probability,
+            faults: Default::default(),
+            after_fault: AtomicU64::new(0),
+            recovered: AtomicU64::new(0),
+        }
+    }
+
+    /// Count how the first request after a fault went.
+    pub fn record_after_fault(&self, succeeded: bool) {
+        self.after_fault.fetch_add(1, Ordering::Relaxed);
+        self.recovered.fetch_add(u64::from(succeeded), Ordering::Relaxed);
+    }
+
+    fn record(&self, fault: Fault, outcome: Outcome) {
+        let counts = &self.faults[fault as usize];
+        match outcome {
+            Outcome::Aborted => {
+                counts.aborted.fetch_add(1, Ordering::Relaxed);
+            }
+            Outcome::Accepted => {
+                counts.accepted.fetch_add(1, Ordering::Relaxed);
+            }
+            Outcome::Rejected(code) => {
+                let mut rejected = counts.rejected.lock().unwrap_or_else(|e| e.into_inner());
+                *rejected.entry(format!("{code:?}")).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn report(&self) {
+        println!("\n💥 Chaos ({:.1}% of requests)", self.probability * 100.0);
+        for fault in FAULTS {
+            let counts   = &self.faults[fault as usize];
+            let rejected = counts.rejected.lock().unwrap_or_else(|e| e.into_inner());
+            let aborted  = counts.aborted.load(Ordering::Relaxed);
+            let accepted = counts.accepted.load(Ordering::Relaxed);
+            let total    = aborted + accepted + rejected.values().sum::<u64>();
+            if total == 0 {
+                continue;
+            }
+            let mut outcomes: Vec<_> = rejected.iter().map(|(code, n)| format!("{code} {n}")).collect();
+            if aborted > 0 {
+                outcomes.push(format!("aborted {aborted}"));
+            }
+            if accepted > 0 {
+                let mark = if fault.expects_rejection() { "❌ " } else { "" };
+                outcomes.push(format!("{mark}accepted {accepted}"));
+            }
+            println!("🔸 {}: {} ({})", fault.label(), total, outcomes.join(", "));
+        }
+        let after_fault = self.after_fault.load(Ordering::Relaxed);
+        if after_fault > 0 {
+            println!(
+                "🩹 Requests right after a fault that succeeded: {} of {}",
+                self.recovered.load(Ordering::Relaxed),
+                after_fault
+            );
+        }
+    }
+}
+
+/// A worker's means of injecting faults at one node.
+pub struct Injector {
+    url:       String,
+    channel:   Channel,
+    /// The value of every oversized PUT, allocated once.
+    oversized: Vec<u8>,
+}
+
+impl Injector {
+    pub fn new(url: String, channel: Channel) -> Self {
+        Self { url, channel, oversized: Vec::new() }
+    }
+
+    /// Inject a random fault and record how it went.
+    pub async fn inject(&mut self, chaos: &Chaos, rng: &mut impl Rng) {
+        let fault   = FAULTS[rng.gen_range(0..FAULTS.len())];
+        let abort   = rng.gen_range(Duration::ZERO..MAX_ABORT_DELAY);
+        let request = || PutRequest { key: "bench-chaos".into(), value: vec![0; 128], ..Default::default() };
+
+        let outcome = match fault {
+            Fault::Cancel => {
+                let mut client = KeyValueStoreClient::new(self.channel.clone());
+                abort_after(abort, client.put(request())).await
+            }
+            // The fresh connection's only user is this client, so dropping
+            // the client closes it.
+            Fault::Disconnect => {
+                let endpoint = Channel::from_shared(self.url.clone()).expect("the run connected to it");
+                match endpoint.connect().await {
+                    Ok(channel) => abort_after(abort, KeyValueStoreClient::new(channel).put(request())).await,
+                    Err(_) => Outcome::Rejected(Code::Unavailable),
+                }
+            }
+            Fault::Oversized => {
+                if self.oversized.is_empty() {
+                    self.oversized = vec![0; OVERSIZED];
+                }
+                let request = PutRequest { key: "bench-chaos".into(), value: self.oversized.clone(), ..Default::default() };
+                outcome(KeyValueStoreClient::new(self.channel.clone()).put(request).await)
+            }
+            Fault::Malformed => outcome(self.send_malformed(rng.gen()).await),
+        };
+        chaos.record(fault, outcome);
+    }
+
+    async fn send_malformed(&self, junk: u64) -> Result<tonic::Response<PutResponse>, Status> {
+        let mut grpc = tonic::client::Grpc::new(self.channel.clone());
+        grpc.ready().await.map_err(|e| Status::unavailable(e.to_string()))?;
+        let codec = ProstCodec::<Malformed, PutResponse>::default();
+        let path  = PathAndQuery::from_static("/kv.KeyValueStore/Put");
+        grpc.unary(tonic::Request::new(Malformed { key: junk, value: junk }), path, codec).await
+    }
+}
+
+async fn abort_after<T>(delay: Duration, call: impl std::future::Future<Output = Result<T, Status>>) -> Outcome {
+    match tokio::time::timeout(delay, call).await {
+        Ok(result) => outcome(result),
+        Err(_) => Outcome::Aborted,
+    }
+}
+
+fn outcome<T>(result: Result<T, Status>) -> Outcome {
+    match result {
+        Ok(_) => Outcome::Accepted,
+        Err(status) => Outcome::Rejected(status.code()),
+    }
+}
@@ -0,0 +1,228 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use clap::ValueEnum;
+use hdrhistogram::serialization::interval_log::{IntervalLogWriterBuilder, Tag};
+use hdrhistogram::serialization::V2Serializer;
+use serde::Serialize;
+
+use crate::server::Sample;
+use crate::stats::{OpStats, Stats};
+
+/// Percentiles listed in every operation's summary.
+const SUMMARY: [f64; 7] = [50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 99.99];
+
+/// Spectrum resolution: points per halving of the distance to 100%, as in
+/// HdrHistogram's own percentile distribution output.
+const TICKS_PER_HALF: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// One JSON document with settings, summaries and spectra.
+    Json,
+    /// The percentile spectra, one row per point, after `#` settings lines.
+    Csv,
+    /// An HdrHistogram interval log with one histogram per operation.
+    Hdr,
+}
+
+impl Format {
+    pub fn default_path(self) -> PathBuf {
+        PathBuf::from(match self {
+            Format::Json => "lumen-bench.json",
+            Format::Csv => "lumen-bench.csv",
+            Format::Hdr => "lumen-bench.hlog",
+        })
+    }
+}
+
+/// How the run was configured.
+#[derive(Debug, Serialize)]
+pub struct RunInfo {
+    pub started_unix_ms: u64,
+    /// Node URLs, comma-separated, or `embedded:DIR`.
+    pub target:          String,
+    pub workload:        String,
+    pub distribution:    String,
+    pub key_space:       u64,
+    pub value_size:      String,
+    pub concurrency:     usize,
+    pub connections:     usize,
+    pub rate:            Option<f64>,
+    pub warmup_seconds:  u64,
+}
+
+/// Everything the run measured.
+pub struct Measured<'a> {
+    pub stats:      &'a Stats,
+    /// Each target's name and share of `stats`.
+    pub per_target: &'a [(&'a str, &'a Stats)],
+    /// The node's resource gauges, if `--server-metrics` was given.
+    pub server:     &'a [Sample],
+    pub elapsed:    Duration,
+}
+
+#[derive(Debug, Serialize)]
+struct Results<'a> {
+    run:             &'a RunInfo,
+    elapsed_seconds: f64,
+    requests:        u64,
+    throughput:      f64,
+    operations:      Vec<Operation>,
+    /// Each target's share, when there were several.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    targets:         Vec<TargetResults>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    server:          &'a [Sample],
+}
+
+#[derive(Debug, Serialize)]
+struct TargetResults {
+    target:     String,
+    requests:   u64,
+    throughput: f64,
+    errors:     u64,
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Serialize)]
+struct Operation {
+    operation:   &'static str,
+    requests:    u64,
+    throughput:  f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hits:        Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes:       Option<u64>,
+    errors:      u64,
+    min_us:      u64,
+    mean_us:     f64,
+    max_us:      u64,
+    stdev_us:    f64,
+    percentiles: Vec<Percentile>,
+    spectrum:    Vec<Point>,
+}
+
+#[derive(Debug, Serialize)]
+struct Percentile {
+    percentile: f64,
+    latency_us: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Point {
+    percentile: f64,
+    latency_us: u64,
+    /// Requests at or below `latency_us`.
+    total:      u64,
+}
+
+impl Operation {
+    fn new(stats: &OpStats, elapsed: Duration) -> Self {
+        let hist = &stats.hist;
+        Self {
+            operation:   stats.label(),
+            requests:    stats.count(),
+            throughput:  stats.count() as f64 / elapsed.as_secs_f64(),
+            hits:        stats.hits(),
+            bytes:       stats.bytes(),
+            errors:      stats.errors,
+            min_us:      hist.min(),
+            mean_us:     hist.mean(),
+            max_us:      hist.max(),
+            stdev_us:    hist.stdev(),
+            percentiles: SUMMARY
+                .iter()
+                .map(|&percentile| Percentile { percentile, latency_us: hist.value_at_percentile(percentile) })
+                .collect(),
+            spectrum:    spectrum(stats),
+        }
+    }
+}
+
+fn spectrum(stats: &OpStats) -> Vec<Point> {
+    let mut total = 0;
+    stats
+        .hist
+        .iter_quantiles(TICKS_PER_HALF)
+        .map(|step| {
+            total += step.count_since_last_iteration();
+            Point { percentile: step.percentile(), latency_us: step.value_iterated_to(), total }
+        })
+        .collect()
+}
+
+/// Write the results of a run to `path`.  Only JSON lists the per-target
+/// results and server samples.
+pub fn write(path: &Path, format: Format, info: &RunInfo, run: &Measured, started: SystemTime) -> io::Result<()> {
+    let Measured { stats, per_target, server, elapsed } = *run;
+    let mut out = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Json => {
+            let results = Results {
+                run:             info,
+                elapsed_seconds: elapsed.as_secs_f64(),
+                requests:        stats.count(),
+                throughput:      stats.count() as f64 / elapsed.as_secs_f64(),
+                operations:      stats.issued().map(|op| Operation::new(op, elapsed)).collect(),
+                targets:         match per_target {
+                    [_] => Vec::new(),
+                    _ => per_target
+                        .iter()
+                        .map(|&(name, stats)| TargetResults {
+                            target:     name.to_owned(),
+                            requests:   stats.count(),
+                            throughput: stats.count() as f64 / elapsed.as_secs_f64(),
+                            errors:     stats.errors(),
+                            operations: stats.issued().map(|op| Operation::new(op, elapsed)).collect(),
+                        })
+                        .collect(),
+                },
+                server,
+            };
+            serde_json::to_writer_pretty(&mut out, &results)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            write_settings(&mut out, info, elapsed, "# ")?;
+            writeln!(out, "operation,percentile,latency_us,total")?;
+            for op in stats.issued() {
+                for point in spectrum(op) {
+                    writeln!(out, "{},{},{},{}", op.label(), point.percentile, point.latency_us, point.total)?;
+                }
+            }
+        }
+        Format::Hdr => {
+            let mut settings = Vec::new();
+            write_settings(&mut settings, info, elapsed, "")?;
+            let mut serializer = V2Serializer::new();
+            let mut builder    = IntervalLogWriterBuilder::new();
+            for line in String::from_utf8_lossy(&settings).lines() {
+                builder.add_comment(line);
+            }
+            let mut log = builder
+                .with_start_time(started)
+                .begin_log_with(&mut out, &mut serializer)?;
+            for op in stats.issued() {
+                log.write_histogram(&op.hist, Duration::ZERO, elapsed, Tag::new(op.label()))
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// The run settings as `key=value` lines.
+fn write_settings(out: &mut impl Write, info: &RunInfo, elapsed: Duration, prefix: &str) -> io::Result<()> {
+    let rate = info.rate.map_or_else(|| "closed-loop".to_owned(), |rate| rate.to_string());
+    writeln!(out, "{prefix}lumen-bench run started_unix_ms={}", info.started_unix_ms)?;
+    writeln!(out, "{prefix}target={}", info.target)?;
+    writeln!(out, "{prefix}workload={}", info.workload)?;
+    writeln!(out, "{prefix}distribution={}", info.distribution)?;
+    writeln!(out, "{prefix}key_space={} value_size={}", info.key_space, info.value_size)?;
+    writeln!(out, "{prefix}concurrency={} connections={}", info.concurrency, info.connections)?;
+    writeln!(out, "{prefix}rate={} warmup_seconds={}", rate, info.warmup_seconds)?;
+    writeln!(out, "{prefix}elapsed_seconds={:.3} latency_unit=us", elapsed.as_secs_f64())
+}
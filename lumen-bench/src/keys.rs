@@ -0,0 +1,89 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use rand_distr::{Distribution as _, Zipf};
+
+/// YCSB's default skew.
+const DEFAULT_THETA: f64 = 0.99;
+
+/// How requests pick their key among `bench-key-0 .. n`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    /// Request `i` uses key `i % n`.
+    Sequential,
+    Uniform,
+    /// Key `k` is chosen with probability proportional to `1 / (k+1)^theta`,
+    /// so the lowest-numbered keys are the hot ones.
+    Zipfian(f64),
+    /// Zipfian over recency: the most recently inserted keys are hot.
+    Latest,
+}
+
+impl FromStr for Distribution {
+    type Err = String;
+
+    /// `sequential`, `uniform`, `zipfian`, `zipfian(THETA)` or `latest`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => return Ok(Distribution::Sequential),
+            "uniform" => return Ok(Distribution::Uniform),
+            "zipfian" => return Ok(Distribution::Zipfian(DEFAULT_THETA)),
+            "latest" => return Ok(Distribution::Latest),
+            _ => {}
+        }
+        let theta = s
+            .strip_prefix("zipfian(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .ok_or_else(|| {
+                format!("unknown distribution {s:?}; expected sequential, uniform, zipfian(THETA) or latest")
+            })?;
+        match theta.trim().parse::<f64>() {
+            Ok(theta) if theta > 0.0 && theta.is_finite() => Ok(Distribution::Zipfian(theta)),
+            _ => Err(format!("zipfian theta must be a positive number, got {theta:?}")),
+        }
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Distribution::Sequential => f.write_str("sequential"),
+            Distribution::Uniform => f.write_str("uniform"),
+            Distribution::Zipfian(theta) => write!(f, "zipfian({theta})"),
+            Distribution::Latest => f.write_str("latest"),
+        }
+    }
+}
+
+/// A worker's key picker; caches the Zipf sampler for the last key count.
+pub struct KeyChooser {
+    distribution: Distribution,
+    zipf:         Option<(u64, Zipf<f64>)>,
+}
+
+impl KeyChooser {
+    pub fn new(distribution: Distribution) -> Self {
+        Self { distribution, zipf: None }
+    }
+
+    /// A key index below `n` (which must be at least 1) for request `ticket`.
+    pub fn pick(&mut self, rng: &mut impl Rng, ticket: u64, n: u64) -> u64 {
+        match self.distribution {
+            Distribution::Sequential => ticket % n,
+            Distribution::Uniform => rng.gen_range(0..n),
+            Distribution::Zipfian(theta) => self.zipf_rank(rng, n, theta),
+            Distribution::Latest => n - 1 - self.zipf_rank(rng, n, DEFAULT_THETA),
+        }
+    }
+
+    /// A Zipf-distributed rank in `0..n`, 0 being the most likely.
+    fn zipf_rank(&mut self, rng: &mut impl Rng, n: u64, theta: f64) -> u64 {
+        let zipf = match &self.zipf {
+            Some((cached, zipf)) if *cached == n => zipf,
+            _ => &self.zipf.insert((n, Zipf::new(n, theta).expect("validated theta"))).1,
+        };
+        // Samples are ranks in 1..=n.
+        (zipf.sample(rng) as u64).clamp(1, n) - 1
+    }
+}
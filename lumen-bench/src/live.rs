@@ -0,0 +1,74 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use hdrhistogram::Histogram;
+
+use crate::worker::Workload;
+
+/// A worker's latencies since the last progress line.  Each worker has its
+/// own, so the lock is only contended when the reporter drains it.
+pub struct LiveRecorder(Arc<Mutex<Histogram<u64>>>);
+
+impl LiveRecorder {
+    pub fn record(&mut self, latency: Duration) {
+        lock(&self.0).saturating_record(latency.as_micros() as u64);
+    }
+}
+
+fn lock(hist: &Mutex<Histogram<u64>>) -> std::sync::MutexGuard<'_, Histogram<u64>> {
+    hist.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Prints a line of stats for the last `interval` until stopped.
+pub struct Reporter {
+    stop:   mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Reporter {
+    /// Start reporting on `workload`; returns one recorder per worker.
+    pub fn start(workload: Arc<Workload>, interval: Duration, workers: usize) -> (Self, Vec<LiveRecorder>) {
+        let shared: Vec<_> = (0..workers).map(|_| Arc::new(Mutex::new(Histogram::new(3).unwrap()))).collect();
+        let recorders = shared.iter().cloned().map(LiveRecorder).collect();
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut hist   = Histogram::<u64>::new(3).unwrap();
+            let mut since  = Instant::now();
+            let mut errors = 0;
+            loop {
+                match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+                for worker in &shared {
+                    let mut worker = lock(worker);
+                    hist.add(&*worker).unwrap();
+                    worker.reset();
+                }
+                let now    = Instant::now();
+                let total  = workload.errors();
+                let secs   = now.duration_since(since).as_secs_f64();
+                println!(
+                    "⏳ [{:>5.0?}] {:>10.2} ops/sec | P50 {:>6} µs | P99 {:>6} µs | errors {}",
+                    workload.start.elapsed(),
+                    hist.len() as f64 / secs,
+                    hist.value_at_quantile(0.50),
+                    hist.value_at_quantile(0.99),
+                    total - errors
+                );
+                hist.reset();
+                since  = now;
+                errors = total;
+            }
+        });
+        (Self { stop, thread }, recorders)
+    }
+
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
@@ -1,6 +1,25 @@
-use std::time::Instant;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::{Parser, ValueEnum};
+use lumen_core::Engine;
 use tonic::transport::Channel;
-use hdrhistogram::Histogram;
+
+mod baseline;
+mod chaos;
+mod export;
+mod keys;
+mod live;
+mod node;
+mod recovery;
+mod server;
+mod stats;
+mod target;
+mod values;
+mod verify;
+mod worker;
+mod ycsb;
 
 // Import generated proto code
 pub mod pb {
@@ -8,53 +27,561 @@ pub mod pb {
     tonic::include_proto!("kv");
 }
 
-// Service name is "KeyValueStore", so client is "KeyValueStoreClient"
-use pb::key_value_store_client::KeyValueStoreClient;
-// Message is "PutRequest"
-use pb::PutRequest;
+use baseline::{Baseline, Thresholds};
+use chaos::{Chaos, Injector};
+use export::{Format, Measured, RunInfo};
+use keys::Distribution;
+use live::Reporter;
+use recovery::Recovery;
+use server::{MetricsUrl, Sampler};
+use stats::Stats;
+use target::{Endpoint, Target};
+use values::ValueSize;
+use verify::{Store, Verify};
+use worker::{Hooks, Progress, Workload, WriteKind};
+use ycsb::Preset;
+
+#[derive(Debug, Parser)]
+#[command(name = "lumen-bench", about = "Load-test a LumenKV node")]
+struct Cli {
+    /// gRPC URL of the node to benchmark.  Repeat it (or separate URLs
+    /// with commas) to spread the workers over several nodes.
+    #[arg(long, env = "LUMEN_ADDR", value_delimiter = ',', default_value = "http://127.0.0.1:50051")]
+    addr: Vec<String>,
+
+    /// Benchmark a node over gRPC, or an engine opened in this process.
+    #[arg(long, value_enum, default_value_t = Mode::Grpc)]
+    mode: Mode,
+
+    /// Data directory for --mode embedded [default: a scratch directory,
+    /// removed afterwards].
+    #[arg(long, value_name = "PATH")]
+    data_dir: Option<PathBuf>,
+
+    /// Number of requests to send.
+    #[arg(long, default_value_t = 10_000)]
+    requests: u64,
+
+    /// Run for this many seconds instead of a fixed number of requests.
+    #[arg(long, value_name = "SECS")]
+    duration: Option<u64>,
+
+    /// Size of each PUT value in bytes: `SIZE`, a range `MIN..MAX`, or
+    /// `lognormal(MEDIAN,SIGMA)`.
+    #[arg(long, value_name = "BYTES", default_value = "128")]
+    value_size: ValueSize,
+
+    /// Number of distinct keys (defaults to --requests, so a sequential
+    /// PUT run writes every key once).
+    #[arg(long, value_name = "N")]
+    key_space: Option<u64>,
+
+    /// How requests pick keys: `sequential`, `uniform` or `zipfian(THETA)`
+    /// (`zipfian` alone uses 0.99) [default: sequential, or uniform for the
+    /// GETs of a mixed workload].
+    #[arg(long, value_name = "DIST")]
+    distribution: Option<Distribution>,
+
+    /// Operation to issue [default: put, or mixed with --read-pct].
+    #[arg(long, value_enum, conflicts_with = "workload")]
+    op: Option<Op>,
+
+    /// Most entries each SCAN reads, from the key it picks on.
+    #[arg(long, value_name = "N", default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    scan_length: u32,
+
+    /// Keys each MULTI-GET reads at once.
+    #[arg(long, value_name = "N", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    batch_size: u64,
+
+    /// Run a YCSB core workload over --key-space records instead.
+    #[arg(long, value_enum, conflicts_with = "read_pct")]
+    workload: Option<Preset>,
+
+    /// With --workload, assume the records were loaded by an earlier run.
+    #[arg(long, requires = "workload")]
+    no_load: bool,
+
+    /// Percentage of GETs in a mixed workload; the rest are PUTs.
+    #[arg(long, value_name = "PCT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    read_pct: Option<u8>,
+
+    /// Send requests on a fixed schedule of N per second (open loop),
+    /// measuring latency from each request's scheduled start.
+    #[arg(long, value_name = "N")]
+    rate: Option<f64>,
+
+    /// Run the workload this long first, without measuring it.
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    warmup_seconds: u64,
+
+    /// Print throughput, latency and errors of the last SECS while the
+    /// benchmark runs (0: only the summary at the end).
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    interval: u64,
+
+    /// Also write the full results (percentile spectra and run settings)
+    /// to a file in this format.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    output: Option<Format>,
+
+    /// Where --output writes [default: lumen-bench.json, .csv or .hlog].
+    #[arg(long, value_name = "PATH", requires = "output")]
+    output_path: Option<PathBuf>,
+
+    /// Compare against an earlier `--output json` result, and exit with an
+    /// error if this run is worse beyond the thresholds below.
+    #[arg(long, value_name = "PATH")]
+    baseline: Option<PathBuf>,
+
+    /// With --baseline, fail if throughput (total or of any operation)
+    /// drops by more than this many percent.
+    #[arg(long, value_name = "PCT", default_value_t = 5.0, requires = "baseline")]
+    max_throughput_drop: f64,
+
+    /// With --baseline, fail if any operation's P99 latency rises by more
+    /// than this many percent.
+    #[arg(long, value_name = "PCT", default_value_t = 10.0, requires = "baseline")]
+    max_p99_increase: f64,
+
+    /// Sample the node's Prometheus endpoint (its ADMIN_ADDR, e.g.
+    /// `http://127.0.0.1:9090`) every second of the run, and report its CPU,
+    /// memory and WAL growth alongside the latencies.
+    #[arg(long, value_name = "URL")]
+    server_metrics: Option<MetricsUrl>,
+
+    /// Measure recovery instead: load --key-space records, then restart
+    /// the store --restarts times, timing WAL replay and the first
+    /// successful request.  Over gRPC this spawns its own node from
+    /// --server-bin, on a free local port, rather than using --addr.
+    #[arg(long, conflicts_with_all = ["workload", "op", "read_pct", "duration", "rate", "baseline", "output"])]
+    recovery: bool,
+
+    /// With --recovery, how many times to restart.
+    #[arg(long, value_name = "N", default_value_t = 3, requires = "recovery")]
+    restarts: usize,
+
+    /// With --recovery (or --verify --restart over gRPC), the lumen-server
+    /// to spawn [default: the one next to this binary].
+    #[arg(long, value_name = "PATH")]
+    server_bin: Option<PathBuf>,
+
+    /// Check durability instead: PUT --key-space keys once each with values
+    /// derived from the key, then read them all back and report any that
+    /// are missing or changed.
+    #[arg(
+        long,
+        conflicts_with_all = ["workload", "op", "read_pct", "duration", "rate", "baseline", "output", "recovery"]
+    )]
+    verify: bool,
+
+    /// With --verify, restart the store between writing and reading back.
+    /// Over gRPC this spawns its own node from --server-bin rather than
+    /// using --addr.
+    #[arg(long, requires = "verify")]
+    restart: bool,
+
+    /// Replace this percentage of the measured requests with faults:
+    /// cancelled RPCs, connections dropped mid-request, and oversized or
+    /// malformed PUTs, reporting how the server answered each and whether
+    /// the next request succeeded.
+    #[arg(long, value_name = "PCT")]
+    chaos: Option<f64>,
+
+    /// Seed for key selection, operation mix and value contents, to replay
+    /// a run [default: random, and printed].  With one worker the replay is
+    /// exact; with more, each worker repeats its choices, but how their
+    /// requests interleave still depends on timing.
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+
+    /// Requests in flight at once, each from its own task.
+    #[arg(long, default_value_t = 1)]
+    concurrency: usize,
+
+    /// gRPC channels per --addr to spread its tasks over.
+    #[arg(long, default_value_t = 1)]
+    connections: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    Grpc,
+    /// Call `lumen_core::Engine` directly, without network or gRPC.
+    Embedded,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Op {
+    Put,
+    Get,
+    Delete,
+    /// Interleave GETs of already-written keys with PUTs (see --read-pct).
+    Mixed,
+    /// Read up to --scan-length keys in order, from the key picked on.
+    Scan,
+    /// GET --batch-size keys at once, timing the batch as one request.
+    #[value(name = "multi-get")]
+    MultiGet,
+}
+
+impl Op {
+    fn label(self) -> &'static str {
+        match self {
+            Op::Put => "PUT",
+            Op::Get => "GET",
+            Op::Delete => "DELETE",
+            Op::Mixed => "MIXED",
+            Op::Scan => "SCAN",
+            Op::MultiGet => "MULTI-GET",
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // 1. Connect to the Server
-    let channel = Channel::from_static("http://0.0.0.0:50051")
-        .connect()
-        .await
-        .expect("Failed to connect to LumenKV. Is the server running?");
-
-    let mut client = KeyValueStoreClient::new(channel);
-    let total_requests = 10_000;
-    
-    println!("🚀 Starting Benchmark: {} requests...", total_requests);
-    let start = Instant::now();
-    let mut hist = Histogram::<u64>::new(3).unwrap();
-
-    // 2. The Attack Loop
-    for i in 0..total_requests {
-        let key = format!("bench-key-{}", i);
-        let value = vec![0u8; 128]; // 128-byte payload
-
-        let request = tonic::Request::new(PutRequest { 
-            key, 
-            value 
-        });
-
-        let op_start = Instant::now();
-        
-        // Changed from .set() to .put() to match your Proto
-        client.put(request).await?; 
-        
-        hist.record(op_start.elapsed().as_micros() as u64).unwrap();
-    }
-
-    let duration = start.elapsed();
-    let ops = total_requests as f64 / duration.as_secs_f64();
+    let cli = Cli::parse();
+    if cli.requests == 0 && cli.duration.is_none() {
+        return Err("--requests must be at least 1".into());
+    }
+    let op = match cli.workload {
+        Some(_) => Op::Mixed,
+        None => cli.op.unwrap_or(if cli.read_pct.is_some() { Op::Mixed } else { Op::Put }),
+    };
+    if cli.read_pct.is_some() && op != Op::Mixed {
+        return Err("--read-pct only applies to --op mixed".into());
+    }
+    if cli.concurrency == 0 || cli.connections == 0 {
+        return Err("--concurrency and --connections must be at least 1".into());
+    }
+    if cli.mode == Mode::Grpc && cli.concurrency < cli.addr.len() {
+        return Err("--concurrency must be at least the number of --addr targets".into());
+    }
+    if cli.rate.is_some_and(|rate| !(rate > 0.0 && rate.is_finite())) {
+        return Err("--rate must be a positive number".into());
+    }
+    if cli.chaos.is_some_and(|pct| !(0.0..=100.0).contains(&pct)) {
+        return Err("--chaos must be a percentage between 0 and 100".into());
+    }
+    if cli.chaos.is_some() && (cli.mode == Mode::Embedded || cli.recovery || cli.verify) {
+        return Err("--chaos only applies to a gRPC benchmark run".into());
+    }
+    let seed = cli.seed.unwrap_or_else(rand::random);
+    if cli.recovery {
+        return recovery(&cli, seed).await;
+    }
+    if cli.verify {
+        return verify(&cli, seed).await;
+    }
+    // Load the baseline first, so a bad path fails before the run.
+    let baseline = cli.baseline.as_deref().map(Baseline::load).transpose()?;
+    let read_pct     = cli.workload.map_or(cli.read_pct.unwrap_or(50), Preset::read_pct);
+    let write        = cli.workload.map_or(WriteKind::Insert, Preset::write);
+    let connections  = cli.connections.min(cli.concurrency.div_ceil(cli.addr.len()));
+    let key_space    = cli.key_space.unwrap_or(cli.requests).max(1);
+    let deadline     = cli.duration.map(Duration::from_secs);
+    let distribution = cli.distribution.unwrap_or(match (cli.workload, op) {
+        (Some(preset), _) => preset.distribution(),
+        (None, Op::Mixed) => Distribution::Uniform,
+        (None, _) => Distribution::Sequential,
+    });
+    let ycsb = cli.workload.is_some();
+
+    // 1. Connect to the Servers (or open the engine)
+    let mut endpoints = Vec::with_capacity(cli.addr.len());
+    let mut scratch   = None;
+    match cli.mode {
+        Mode::Grpc => {
+            for addr in &cli.addr {
+                let mut channels = Vec::with_capacity(connections);
+                for _ in 0..connections {
+                    let channel = Channel::from_shared(addr.clone())?
+                        .connect()
+                        .await
+                        .map_err(|e| format!("Failed to connect to LumenKV at {addr}: {e}. Is the server running?"))?;
+                    channels.push(Target::Grpc(channel));
+                }
+                endpoints.push(Endpoint { name: addr.clone(), channels });
+            }
+        }
+        Mode::Embedded => {
+            let dir = match &cli.data_dir {
+                Some(dir) => dir.clone(),
+                None => {
+                    let dir = std::env::temp_dir().join(format!("lumen-bench-{}", std::process::id()));
+                    scratch = Some(dir.clone());
+                    dir
+                }
+            };
+            let engine = Engine::open(&dir).map_err(|e| format!("Failed to open {}: {e}", dir.display()))?;
+            endpoints.push(Endpoint {
+                name:     format!("embedded:{}", dir.display()),
+                channels: vec![Target::Embedded(Arc::new(engine))],
+            });
+        }
+    }
+    let target_name = endpoints.iter().map(|endpoint| endpoint.name.as_str()).collect::<Vec<_>>().join(",");
+
+    // YCSB runs against a table loaded up front, which is not measured.
+    if ycsb && !cli.no_load {
+        println!("📥 Loading {} records...", key_space);
+        let load = Arc::new(Workload::load(key_space, cli.value_size, seed));
+        let (_, elapsed) = drive(&load, &endpoints, cli.concurrency, None).await?;
+        if let Some(error) = load.first_error() {
+            return Err(format!("Failed to load {} of the records: {error}", load.errors()).into());
+        }
+        println!("📥 Loaded in {:.2?}", elapsed);
+    }
+
+    let workload_name = match (cli.workload, op) {
+        (Some(preset), _) => preset.name().to_owned(),
+        (None, Op::Mixed) => format!("{}% GET / {}% PUT", read_pct, 100 - read_pct),
+        (None, Op::Scan) => format!("SCAN of {}", cli.scan_length),
+        (None, Op::MultiGet) => format!("MULTI-GET of {}", cli.batch_size),
+        (None, op) => op.label().to_owned(),
+    };
+    match deadline {
+        Some(limit) => println!("🚀 Starting Benchmark: {} for {:.0?}...", workload_name, limit),
+        None => println!("🚀 Starting Benchmark: {} {} requests...", cli.requests, workload_name),
+    }
+    match cli.mode {
+        Mode::Grpc if endpoints.len() > 1 => println!(
+            "🧵 {} workers over {} nodes, {} connections each",
+            cli.concurrency,
+            endpoints.len(),
+            connections
+        ),
+        Mode::Grpc => println!("🧵 {} workers over {} connections", cli.concurrency, connections),
+        Mode::Embedded => println!("🧵 {} workers on an in-process engine ({})", cli.concurrency, target_name),
+    }
+    println!("🔑 {} keys, {} distribution", key_space, distribution);
+    println!("📦 Values of {} bytes", cli.value_size);
+    println!("🎲 Seed: {}", seed);
+    if let Some(rate) = cli.rate {
+        println!("⏲️  Open loop at {} req/sec; latency includes queueing behind schedule", rate);
+    }
+
+    // Each phase draws from its own seed, so the warmup does not replay
+    // the measured run's exact requests.
+    let workload = |requests, deadline, loaded, seed, chaos| {
+        Arc::new(Workload {
+            op,
+            read_pct,
+            write,
+            distribution,
+            requests,
+            deadline,
+            rate: cli.rate,
+            key_space,
+            loaded,
+            // Inserts of a YCSB run grow the table past the loaded records.
+            max_keys: if ycsb { u64::MAX } else { key_space },
+            value_size: cli.value_size,
+            scan_length: cli.scan_length,
+            batch_size: cli.batch_size,
+            seed,
+            chaos,
+            start: Instant::now(),
+            progress: Progress::default(),
+        })
+    };
+    let mut loaded = if ycsb { key_space } else { 0 };
+
+    if cli.warmup_seconds > 0 {
+        println!("🔥 Warming up for {}s...", cli.warmup_seconds);
+        let warmup_for = Some(Duration::from_secs(cli.warmup_seconds));
+        let warmup     = workload(u64::MAX, warmup_for, loaded, seed.wrapping_add(1), None);
+        drive(&warmup, &endpoints, cli.concurrency, None).await?;
+        loaded = warmup.records();
+    }
+
+    let sampler = match &cli.server_metrics {
+        Some(url) => Some(
+            Sampler::start(url.clone()).map_err(|e| format!("Failed to read server metrics at {url}: {e}"))?,
+        ),
+        None => None,
+    };
+
+    // 2. The Attack Loop, one per worker
+    let started  = SystemTime::now();
+    let chaos    = cli.chaos.map(|pct| Chaos::new(pct / 100.0));
+    let run      = workload(cli.requests, deadline, loaded, seed.wrapping_add(2), chaos);
+    let interval = (cli.interval > 0).then(|| Duration::from_secs(cli.interval));
+    let (per_target, elapsed) = drive(&run, &endpoints, cli.concurrency, interval).await?;
+    let server = sampler.map(Sampler::stop).unwrap_or_default();
+    let mut stats = Stats::new();
+    for target in &per_target {
+        stats.merge(target);
+    }
+    let per_target: Vec<_> = endpoints.iter().map(|endpoint| endpoint.name.as_str()).zip(&per_target).collect();
 
     // 3. The Report
-    println!("\n✅ Benchmark Complete!");
-    println!("⏱️  Total Time: {:.2?}", duration);
-    println!("⚡ Throughput: {:.2} ops/sec", ops);
-    println!("📊 Latency (P50): {} µs", hist.value_at_quantile(0.50));
-    println!("📊 Latency (P99): {} µs", hist.value_at_quantile(0.99));
+    stats.report(elapsed);
+    if per_target.len() > 1 {
+        for (name, stats) in &per_target {
+            stats.report_target(name, elapsed);
+        }
+    }
+    if let Some(error) = run.first_error() {
+        println!("⚠️  First error: {error}");
+    }
+    if let Some(chaos) = &run.chaos {
+        chaos.report();
+    }
+    server::report(&server);
+
+    if let Some(format) = cli.output {
+        let info = RunInfo {
+            started_unix_ms: started.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64),
+            target:          target_name,
+            workload:        workload_name,
+            distribution:    distribution.to_string(),
+            key_space,
+            value_size:      cli.value_size.to_string(),
+            concurrency:     cli.concurrency,
+            connections,
+            rate:            cli.rate,
+            warmup_seconds:  cli.warmup_seconds,
+        };
+        let path = cli.output_path.clone().unwrap_or_else(|| format.default_path());
+        let measured = Measured { stats: &stats, per_target: &per_target, server: &server, elapsed };
+        export::write(&path, format, &info, &measured, started)
+            .map_err(|e| format!("Failed to write {}: {e}", path.display()))?;
+        println!("💾 Results written to {}", path.display());
+    }
+
+    let regressions = match &baseline {
+        Some(baseline) => {
+            let thresholds = Thresholds {
+                throughput_drop_pct: cli.max_throughput_drop,
+                p99_increase_pct:    cli.max_p99_increase,
+            };
+            baseline.compare(&stats, elapsed.as_secs_f64(), thresholds)
+        }
+        None => Vec::new(),
+    };
 
+    if let Some(dir) = scratch {
+        drop(endpoints);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    if !regressions.is_empty() {
+        return Err(format!("Regressed against the baseline: {}", regressions.join("; ")).into());
+    }
     Ok(())
 }
+
+async fn recovery(cli: &Cli, seed: u64) -> Result<(), Box<dyn std::error::Error>> {
+    if cli.restarts == 0 {
+        return Err("--restarts must be at least 1".into());
+    }
+    let server_bin = match cli.mode {
+        Mode::Grpc => Some(server_bin(cli)?),
+        Mode::Embedded => None,
+    };
+    let (data_dir, scratch) = data_dir(cli);
+    let result = Recovery {
+        server_bin,
+        data_dir: data_dir.clone(),
+        records: cli.key_space.unwrap_or(cli.requests).max(1),
+        value_size: cli.value_size,
+        seed,
+        concurrency: cli.concurrency,
+        restarts: cli.restarts,
+    }
+    .run()
+    .await;
+    if scratch {
+        let _ = std::fs::remove_dir_all(data_dir);
+    }
+    result
+}
+
+async fn verify(cli: &Cli, seed: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let store = match (cli.mode, cli.restart) {
+        (Mode::Grpc, false) => match cli.addr.as_slice() {
+            [addr] => Store::Remote(addr.clone()),
+            _ => return Err("--verify takes a single --addr".into()),
+        },
+        (Mode::Grpc, true) => Store::Local(server_bin(cli)?),
+        (Mode::Embedded, reopen) => Store::Embedded { reopen },
+    };
+    // Unused by a remote store.
+    let (data_dir, scratch) = data_dir(cli);
+    let result = Verify {
+        store,
+        data_dir: data_dir.clone(),
+        records: cli.key_space.unwrap_or(cli.requests).max(1),
+        value_size: cli.value_size,
+        seed,
+        concurrency: cli.concurrency,
+    }
+    .run()
+    .await;
+    if scratch {
+        let _ = std::fs::remove_dir_all(data_dir);
+    }
+    result
+}
+
+/// The lumen-server to spawn: --server-bin, or the one next to this binary.
+fn server_bin(cli: &Cli) -> std::io::Result<PathBuf> {
+    match &cli.server_bin {
+        Some(bin) => Ok(bin.clone()),
+        None => Ok(std::env::current_exe()?.with_file_name(format!("lumen-server{}", std::env::consts::EXE_SUFFIX))),
+    }
+}
+
+/// --data-dir, or a scratch directory; returns whether it is scratch.
+fn data_dir(cli: &Cli) -> (PathBuf, bool) {
+    match &cli.data_dir {
+        Some(dir) => (dir.clone(), false),
+        None => (std::env::temp_dir().join(format!("lumen-bench-{}", std::process::id())), true),
+    }
+}
+
+/// Run `workload` on `concurrency` workers spread round-robin over
+/// `endpoints` (and each endpoint's channels), printing a progress line
+/// every `interval` if given.  Returns each endpoint's stats.
+async fn drive(
+    workload: &Arc<Workload>,
+    endpoints: &[Endpoint],
+    concurrency: usize,
+    interval: Option<Duration>,
+) -> Result<(Vec<Stats>, Duration), Box<dyn std::error::Error>> {
+    let (reporter, recorders) = match interval {
+        Some(interval) => {
+            let (reporter, recorders) = Reporter::start(workload.clone(), interval, concurrency);
+            (Some(reporter), recorders.into_iter().map(Some).collect())
+        }
+        None => (None, (0..concurrency).map(|_| None).collect::<Vec<_>>()),
+    };
+    let workers: Vec<_> = recorders
+        .into_iter()
+        .enumerate()
+        .map(|(i, live)| {
+            let endpoint = i % endpoints.len();
+            let channels = &endpoints[endpoint].channels;
+            let target   = channels[(i / endpoints.len()) % channels.len()].clone();
+            let injector = match (&workload.chaos, &target) {
+                (Some(_), Target::Grpc(channel)) => {
+                    Some(Injector::new(endpoints[endpoint].name.clone(), channel.clone()))
+                }
+                _ => None,
+            };
+            let hooks = Hooks { live, injector };
+            (endpoint, tokio::spawn(worker::run(workload.clone(), i as u64, target, hooks)))
+        })
+        .collect();
+
+    let mut stats: Vec<_> = endpoints.iter().map(|_| Stats::new()).collect();
+    for (endpoint, worker) in workers {
+        stats[endpoint].merge(&worker.await?);
+    }
+    let elapsed = workload.start.elapsed();
+    if let Some(reporter) = reporter {
+        reporter.stop();
+    }
+    Ok((stats, elapsed))
+}
@@ -0,0 +1,106 @@
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+use tokio::process::{Child, Command};
+use tonic::transport::Channel;
+
+use crate::target::{Backend, Target};
+
+/// How long a (re)started node gets to answer before the run gives up.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A `lumen-server` this process runs, on a free local port; it is killed
+/// when dropped.
+pub struct LocalNode {
+    bin:      PathBuf,
+    data_dir: PathBuf,
+    addr:     SocketAddr,
+    child:    Child,
+}
+
+/// How long one start of a node took.
+pub struct Startup {
+    /// Until the node accepted connections, which it does once its WAL
+    /// is replayed.
+    pub listening:     Duration,
+    /// Until a GET of the probe key succeeded, if one was given.
+    pub first_request: Duration,
+}
+
+impl LocalNode {
+    /// Start a node on `data_dir` and wait until it accepts connections.
+    pub async fn start(bin: &Path, data_dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        // Bind port 0 to have the OS pick a free port for the node.
+        let addr  = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let child = spawn(bin, data_dir, addr)?;
+        let mut node = Self { bin: bin.to_owned(), data_dir: data_dir.to_owned(), addr, child };
+        node.wait_for(None).await?;
+        Ok(node)
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Kill the node hard, so the next start replays the WAL as after a
+    /// crash, and start it again; with a `probe` key, wait until a GET of
+    /// it succeeds too.
+    pub async fn restart(&mut self, probe: Option<String>) -> Result<Startup, Box<dyn std::error::Error>> {
+        self.child.kill().await?;
+        self.child = spawn(&self.bin, &self.data_dir, self.addr)?;
+        self.wait_for(probe).await
+    }
+
+    pub async fn stop(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(self.child.kill().await?)
+    }
+
+    async fn wait_for(&mut self, probe: Option<String>) -> Result<Startup, Box<dyn std::error::Error>> {
+        let start    = Instant::now();
+        let deadline = start + STARTUP_TIMEOUT;
+        let running  = |child: &mut Child| -> Result<(), Box<dyn std::error::Error>> {
+            match child.try_wait()? {
+                Some(status) => Err(format!("lumen-server exited with {status}").into()),
+                None if Instant::now() > deadline => Err("lumen-server did not come up in time".into()),
+                None => Ok(()),
+            }
+        };
+
+        while tokio::net::TcpStream::connect(self.addr).await.is_err() {
+            running(&mut self.child)?;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        let listening = start.elapsed();
+
+        if let Some(key) = probe {
+            let channel     = Channel::from_shared(self.url())?.connect().await?;
+            let mut backend = Backend::new(Target::Grpc(channel));
+            loop {
+                match backend.get(key.clone()).await {
+                    Ok(true) => break,
+                    Ok(false) => return Err(format!("{key} is missing after the restart").into()),
+                    Err(_) => {
+                        running(&mut self.child)?;
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                    }
+                }
+            }
+        }
+        Ok(Startup { listening, first_request: start.elapsed() })
+    }
+}
+
+fn spawn(bin: &Path, data_dir: &Path, addr: SocketAddr) -> Result<Child, String> {
+    Command::new(bin)
+        .env("DATA_DIR", data_dir)
+        .env("BIND_ADDR", addr.to_string())
+        .env("RUST_LOG", "warn")
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to start {}: {e}", bin.display()))
+}
@@ -0,0 +1,109 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use lumen_core::Engine;
+use tonic::transport::Channel;
+
+use crate::drive;
+use crate::node::{LocalNode, Startup};
+use crate::target::{Endpoint, Target};
+use crate::values::ValueSize;
+use crate::worker::{self, Workload};
+
+/// A recovery-time run: load `records`, then restart `restarts` times.
+pub struct Recovery {
+    /// A `lumen-server` to spawn, or `None` to re-open an embedded engine.
+    pub server_bin:  Option<PathBuf>,
+    pub data_dir:    PathBuf,
+    pub records:     u64,
+    pub value_size:  ValueSize,
+    pub seed:        u64,
+    pub concurrency: usize,
+    pub restarts:    usize,
+}
+
+impl Recovery {
+    pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let timings = match &self.server_bin {
+            Some(bin) => self.run_server(bin).await?,
+            None => self.run_embedded().await?,
+        };
+        report(&timings);
+        Ok(())
+    }
+
+    async fn run_embedded(&self) -> Result<Vec<Startup>, Box<dyn std::error::Error>> {
+        let open = |dir: &Path| Engine::open(dir).map_err(|e| format!("Failed to open {}: {e}", dir.display()));
+        let engine = Arc::new(open(&self.data_dir)?);
+        self.load(Endpoint { name: "embedded".into(), channels: vec![Target::Embedded(engine)] }).await?;
+        self.report_wal();
+
+        let mut timings = Vec::with_capacity(self.restarts);
+        for restart in 1..=self.restarts {
+            let start     = Instant::now();
+            let engine    = open(&self.data_dir)?;
+            let listening = start.elapsed();
+            if engine.get(&worker::key(self.records - 1))?.is_none() {
+                return Err("The last record loaded is missing after reopening".into());
+            }
+            timings.push(Startup { listening, first_request: start.elapsed() });
+            print_restart(restart, &timings[restart - 1]);
+        }
+        Ok(timings)
+    }
+
+    async fn run_server(&self, bin: &Path) -> Result<Vec<Startup>, Box<dyn std::error::Error>> {
+        let mut node     = LocalNode::start(bin, &self.data_dir).await?;
+        let mut channels = Vec::with_capacity(self.concurrency);
+        for _ in 0..self.concurrency {
+            channels.push(Target::Grpc(Channel::from_shared(node.url())?.connect().await?));
+        }
+        self.load(Endpoint { name: node.url(), channels }).await?;
+        self.report_wal();
+
+        let mut timings = Vec::with_capacity(self.restarts);
+        for restart in 1..=self.restarts {
+            let timing = node.restart(Some(worker::key(self.records - 1))).await?;
+            print_restart(restart, &timing);
+            timings.push(timing);
+        }
+        node.stop().await?;
+        Ok(timings)
+    }
+
+    /// PUT keys `0 .. records` in order; the load is not measured.
+    async fn load(&self, endpoint: Endpoint) -> Result<(), Box<dyn std::error::Error>> {
+        println!("📥 Loading {} records...", self.records);
+        let load = Arc::new(Workload::load(self.records, self.value_size, self.seed));
+        let (_, elapsed) = drive(&load, &[endpoint], self.concurrency, None).await?;
+        if let Some(error) = load.first_error() {
+            return Err(format!("Failed to load {} of the records: {error}", load.errors()).into());
+        }
+        println!("📥 Loaded in {:.2?}", elapsed);
+        Ok(())
+    }
+
+    fn report_wal(&self) {
+        if let Ok(meta) = std::fs::metadata(self.data_dir.join("wal.log")) {
+            println!("📝 WAL: {:.1} MiB", meta.len() as f64 / (1 << 20) as f64);
+        }
+    }
+}
+
+fn print_restart(restart: usize, timing: &Startup) {
+    println!(
+        "🔁 Restart {}: started in {:.2?}, first request after {:.2?}",
+        restart, timing.listening, timing.first_request
+    );
+}
+
+fn report(timings: &[Startup]) {
+    let summary = |mut times: Vec<Duration>| {
+        times.sort();
+        format!("min {:.2?} | median {:.2?} | max {:.2?}", times[0], times[times.len() / 2], times[times.len() - 1])
+    };
+    println!("\n✅ Recovery Benchmark Complete!");
+    println!("⏱️  Startup (WAL replay): {}", summary(timings.iter().map(|t| t.listening).collect()));
+    println!("⏱️  First request: {}", summary(timings.iter().map(|t| t.first_request).collect()));
+}
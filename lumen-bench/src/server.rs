@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// How often the node's metrics are read.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const MIB: f64 = (1 << 20) as f64;
+
+/// The node's Prometheus endpoint (`ADMIN_ADDR`), as `http://HOST:PORT[/PATH]`;
+/// the path defaults to `/metrics`.
+#[derive(Debug, Clone)]
+pub struct MetricsUrl {
+    host: String,
+    path: String,
+}
+
+impl FromStr for MetricsUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s.strip_prefix("http://").unwrap_or(s);
+        if rest.contains("://") {
+            return Err(format!("only http:// metrics URLs are supported, got {s:?}"));
+        }
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/metrics"),
+        };
+        if !host.contains(':') {
+            return Err(format!("expected HOST:PORT in {s:?}"));
+        }
+        Ok(Self { host: host.to_owned(), path: path.to_owned() })
+    }
+}
+
+impl fmt::Display for MetricsUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.host, self.path)
+    }
+}
+
+/// The node's resource gauges at one point of the run; `None` where the
+/// node did not report one (it only knows its CPU and RSS on Linux).
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    pub elapsed_seconds:     f64,
+    pub cpu_seconds:         Option<f64>,
+    pub rss_bytes:           Option<f64>,
+    pub wal_bytes:           Option<f64>,
+    /// Commits so far.
+    pub latest_sequence:     Option<f64>,
+    /// Commits folded into the on-disk checkpoint.
+    pub checkpoint_sequence: Option<f64>,
+    /// SSTables, and background merges of them so far, with the bytes they
+    /// read and wrote, summed over the node's engines.
+    pub tables:              Option<f64>,
+    pub merges:              Option<f64>,
+    pub merge_bytes_read:    Option<f64>,
+    pub merge_bytes_written: Option<f64>,
+}
+
+/// Reads the node's metrics every `SAMPLE_INTERVAL` until stopped.
+pub struct Sampler {
+    stop:   mpsc::Sender<()>,
+    thread: JoinHandle<Vec<Sample>>,
+}
+
+impl Sampler {
+    /// Take a first sample, failing if the endpoint cannot be read, and keep
+    /// sampling in the background.  Later failures (say, the node going
+    /// down mid-run) just leave gaps.
+    pub fn start(url: MetricsUrl) -> io::Result<Self> {
+        let start = Instant::now();
+        let first = sample(&url, start)?;
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut samples = vec![first];
+            loop {
+                let last = stopped.recv_timeout(SAMPLE_INTERVAL);
+                samples.extend(sample(&url, start).ok());
+                if last != Err(RecvTimeoutError::Timeout) {
+                    return samples;
+                }
+            }
+        });
+        Ok(Self { stop, thread })
+    }
+
+    /// Take a last sample and return them all.
+    pub fn stop(self) -> Vec<Sample> {
+        let _ = self.stop.send(());
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+fn sample(url: &MetricsUrl, start: Instant) -> io::Result<Sample> {
+    let gauges = scrape(url)?;
+    let gauge  = |name: &str| gauges.get(name).copied();
+    Ok(Sample {
+        elapsed_seconds:     start.elapsed().as_secs_f64(),
+        cpu_seconds:         gauge("lumen_process_cpu_seconds_total"),
+        rss_bytes:           gauge("lumen_process_resident_memory_bytes"),
+        wal_bytes:           gauge("lumen_wal_size_bytes"),
+        latest_sequence:     gauge("lumen_latest_sequence"),
+        checkpoint_sequence: gauge("lumen_checkpoint_sequence"),
+        tables:              gauge("lumen_engine_tables"),
+        merges:              gauge("lumen_engine_table_merges_total"),
+        merge_bytes_read:    gauge("lumen_engine_table_merge_bytes_read_total"),
+        merge_bytes_written: gauge("lumen_engine_table_merge_bytes_written_total"),
+    })
+}
+
+/// GET the endpoint and parse its series (see `parse`).
+fn scrape(url: &MetricsUrl) -> io::Result<HashMap<String, f64>> {
+    let addr = url
+        .host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::other(format!("{} did not resolve", url.host)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", url.path, url.host)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(io::Error::other(format!("unexpected response {status:?}")));
+    }
+    Ok(parse(body))
+}
+
+/// The value of every series in a Prometheus exposition body, by name, with
+/// the series of a name that differ only in their labels (the engines'
+/// `data_dir`, say) summed.
+fn parse(body: &str) -> HashMap<String, f64> {
+    let mut series = HashMap::new();
+    for line in body.lines().filter(|line| !line.starts_with('#')) {
+        // Label values may hold spaces, so the value follows the last brace.
+        let (name, rest) = match (line.find('{'), line.rfind('}')) {
+            (Some(open), Some(close)) if open < close => (&line[..open], &line[close + 1..]),
+            _ => match line.split_once(' ') {
+                Some(split) => split,
+                None => continue,
+            },
+        };
+        // A timestamp may follow the value.
+        let Some(Ok(value)) = rest.split_whitespace().next().map(str::parse::<f64>) else { continue };
+        *series.entry(name.to_owned()).or_default() += value;
+    }
+    series
+}
+
+/// Print what the node did over the run, from the first sample to the last.
+pub fn report(samples: &[Sample]) {
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else { return };
+    println!("\n🖥️  Server ({} samples)", samples.len());
+
+    let cpu: Vec<_> = samples.iter().filter_map(|s| Some((s.elapsed_seconds, s.cpu_seconds?))).collect();
+    if let (Some(&(t0, c0)), Some(&(t1, c1))) = (cpu.first(), cpu.last()) {
+        // The busiest stretch between two samples.
+        let peak = cpu
+            .windows(2)
+            .map(|pair| (pair[1].1 - pair[0].1) / (pair[1].0 - pair[0].0))
+            .fold(0.0, f64::max);
+        println!("🧮 CPU: {:.2} cores on average, {:.2} at peak", (c1 - c0) / (t1 - t0).max(f64::EPSILON), peak);
+    }
+    if let (Some(start), Some(end)) = (first.rss_bytes, last.rss_bytes) {
+        let peak = samples.iter().filter_map(|s| s.rss_bytes).fold(0.0, f64::max);
+        println!("🧠 RSS: {} → {} (peak {})", mib(start), mib(end), mib(peak));
+    }
+    if let (Some(start), Some(end)) = (first.wal_bytes, last.wal_bytes) {
+        println!("📝 WAL: {} → {} ({:+.1} MiB)", mib(start), mib(end), (end - start) / MIB);
+    }
+    if let (Some(start), Some(end)) = (first.checkpoint_sequence, last.checkpoint_sequence) {
+        if end != start {
+            println!("🧱 Checkpoint advanced from sequence {} to {}", start, end);
+        }
+    }
+    if let (Some(start), Some(end)) = (first.latest_sequence, last.latest_sequence) {
+        println!("🔢 Commits: {}", end - start);
+    }
+    if let (Some(start), Some(end)) = (first.merges, last.merges) {
+        let bytes = |first: Option<f64>, last: Option<f64>| mib(last.unwrap_or(0.0) - first.unwrap_or(0.0));
+        println!(
+            "🗜️  Merges: {} ({} read, {} written), SSTables: {} → {}",
+            end - start,
+            bytes(first.merge_bytes_read, last.merge_bytes_read),
+            bytes(first.merge_bytes_written, last.merge_bytes_written),
+            first.tables.unwrap_or(0.0),
+            last.tables.unwrap_or(0.0),
+        );
+    }
+}
+
+fn mib(bytes: f64) -> String {
+    format!("{:.1} MiB", bytes / MIB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labelled_series_are_summed_over_their_labels() {
+        let body = r#"# HELP lumen_engine_table_merges_total Background merges of SSTables completed, per engine.
+# TYPE lumen_engine_table_merges_total counter
+lumen_engine_table_merges_total{data_dir="/data/shard a"} 3
+lumen_engine_table_merges_total{data_dir="/data/shard-b"} 4
+lumen_engine_table_merge_bytes_written_total{data_dir="/data/shard-b"} 1.5e6
+lumen_engine_table_merge_seconds_bucket{data_dir="/data/shard-b",le="+Inf"} 4
+lumen_wal_size_bytes 1024
+lumen_latest_sequence 42 1700000000000
+lumen_garbled{data_dir="x"} not-a-number
+"#;
+        let series = parse(body);
+        assert_eq!(series.get("lumen_engine_table_merges_total"), Some(&7.0));
+        assert_eq!(series.get("lumen_engine_table_merge_bytes_written_total"), Some(&1.5e6));
+        assert_eq!(series.get("lumen_engine_table_merge_seconds_bucket"), Some(&4.0));
+        assert_eq!(series.get("lumen_wal_size_bytes"), Some(&1024.0));
+        assert_eq!(series.get("lumen_latest_sequence"), Some(&42.0));
+        assert_eq!(series.get("lumen_garbled"), None);
+        assert_eq!(series.len(), 5);
+    }
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+/// Latencies and outcomes of one operation type.
+pub struct OpStats {
+    label:      &'static str,
+    /// What `hits` counts, for operations that have an outcome.
+    hits_label: Option<&'static str>,
+    pub hist:   Histogram<u64>,
+    /// GETs that found their key, DELETEs that removed one, entries SCANs
+    /// read, or keys MULTI-GETs found.
+    pub hits:   u64,
+    /// Value bytes SCANs read.
+    pub bytes:  u64,
+    /// Requests that failed; their latency is not recorded.
+    pub errors: u64,
+}
+
+impl OpStats {
+    fn new(label: &'static str, hits_label: Option<&'static str>) -> Self {
+        Self { label, hits_label, hist: Histogram::<u64>::new(3).unwrap(), hits: 0, bytes: 0, errors: 0 }
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    /// `hits`, for operations that count them.
+    pub fn hits(&self) -> Option<u64> {
+        self.hits_label.map(|_| self.hits)
+    }
+
+    /// `bytes`, for operations that read a volume of entries.
+    pub fn bytes(&self) -> Option<u64> {
+        (self.bytes > 0).then_some(self.bytes)
+    }
+
+    /// Requests that succeeded.
+    pub fn count(&self) -> u64 {
+        self.hist.len()
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        self.hist.record(latency.as_micros() as u64).unwrap();
+    }
+
+    fn merge(&mut self, other: &OpStats) {
+        self.hist.add(&other.hist).unwrap();
+        self.hits += other.hits;
+        self.bytes += other.bytes;
+        self.errors += other.errors;
+    }
+
+    fn report(&self, elapsed: Duration) {
+        let ops = self.count() as f64 / elapsed.as_secs_f64();
+        println!("\n🔹 {} ({} requests)", self.label, self.count());
+        println!("⚡ Throughput: {:.2} ops/sec", ops);
+        println!("📊 Latency (P50): {} µs", self.hist.value_at_quantile(0.50));
+        println!("📊 Latency (P99): {} µs", self.hist.value_at_quantile(0.99));
+        if let Some(hits_label) = self.hits_label {
+            println!("🎯 {}: {}", hits_label, self.hits);
+        }
+        if self.bytes > 0 {
+            let secs = elapsed.as_secs_f64();
+            println!(
+                "📜 Read: {:.2} entries/sec, {:.2} MB/sec",
+                self.hits as f64 / secs,
+                self.bytes as f64 / 1e6 / secs
+            );
+        }
+        if self.errors > 0 {
+            println!("⚠️  Errors: {}", self.errors);
+        }
+    }
+}
+
+/// Everything one worker (or, once merged, the whole run) measured.
+pub struct Stats {
+    pub puts:  OpStats,
+    pub gets:  OpStats,
+    pub dels:  OpStats,
+    /// A GET and a PUT of the same key, timed together.
+    pub rmws:  OpStats,
+    pub scans: OpStats,
+    /// Batches of GETs in flight together, timed until the last returns.
+    pub mgets: OpStats,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            puts:  OpStats::new("PUT", None),
+            gets:  OpStats::new("GET", Some("Keys found")),
+            dels:  OpStats::new("DELETE", Some("Keys deleted")),
+            rmws:  OpStats::new("READ-MODIFY-WRITE", None),
+            scans: OpStats::new("SCAN", Some("Entries read")),
+            mgets: OpStats::new("MULTI-GET", Some("Keys found")),
+        }
+    }
+
+    pub fn merge(&mut self, other: &Stats) {
+        self.puts.merge(&other.puts);
+        self.gets.merge(&other.gets);
+        self.dels.merge(&other.dels);
+        self.rmws.merge(&other.rmws);
+        self.scans.merge(&other.scans);
+        self.mgets.merge(&other.mgets);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.issued().map(OpStats::count).sum()
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.issued().map(|stats| stats.errors).sum()
+    }
+
+    /// The operation types that were issued at least once.
+    pub fn issued(&self) -> impl Iterator<Item = &OpStats> {
+        [&self.puts, &self.gets, &self.dels, &self.rmws, &self.scans, &self.mgets]
+            .into_iter()
+            .filter(|stats| stats.count() + stats.errors > 0)
+    }
+
+    /// A short summary of the share of a multi-target run one target served.
+    pub fn report_target(&self, name: &str, elapsed: Duration) {
+        let mut hist = Histogram::<u64>::new(3).unwrap();
+        for stats in self.issued() {
+            hist.add(&stats.hist).unwrap();
+        }
+        println!("\n🌐 {} ({} requests)", name, self.count());
+        println!("⚡ Throughput: {:.2} ops/sec", self.count() as f64 / elapsed.as_secs_f64());
+        println!("📊 Latency (P50/P99): {} / {} µs", hist.value_at_quantile(0.50), hist.value_at_quantile(0.99));
+        if self.errors() > 0 {
+            let by_op: Vec<_> = self
+                .issued()
+                .filter(|stats| stats.errors > 0)
+                .map(|stats| format!("{} {}", stats.label(), stats.errors))
+                .collect();
+            println!("⚠️  Errors: {} ({})", self.errors(), by_op.join(", "));
+        }
+    }
+
+    pub fn report(&self, elapsed: Duration) {
+        let sent = self.count();
+        let ops  = sent as f64 / elapsed.as_secs_f64();
+
+        println!("\n✅ Benchmark Complete!");
+        println!("📨 Requests: {}", sent);
+        if self.errors() > 0 {
+            println!("⚠️  Failed: {}", self.errors());
+        }
+        println!("⏱️  Total Time: {:.2?}", elapsed);
+        println!("⚡ Throughput: {:.2} ops/sec", ops);
+        for stats in self.issued() {
+            stats.report(elapsed);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use lumen_core::{Engine, KeyRange};
+use tonic::transport::Channel;
+
+use crate::pb::key_value_store_client::KeyValueStoreClient;
+use crate::pb::{DeleteRequest, GetRequest, MultiGetRequest, PutRequest, ScanRequest};
+use crate::worker::KEY_PREFIX;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// What the benchmark drives.
+#[derive(Clone)]
+pub enum Target {
+    /// A node, over one gRPC channel.
+    Grpc(Channel),
+    /// An engine in this process, called directly, so the numbers leave
+    /// out the network, gRPC and protobuf.
+    Embedded(Arc<Engine>),
+}
+
+/// A node (or the in-process engine) and the channels its workers share.
+pub struct Endpoint {
+    /// Node URL, or `embedded:DIR`.
+    pub name:     String,
+    pub channels: Vec<Target>,
+}
+
+/// One worker's handle on a `Target`.
+pub enum Backend {
+    Grpc(KeyValueStoreClient<Channel>),
+    Embedded(Arc<Engine>),
+}
+
+impl Backend {
+    pub fn new(target: Target) -> Self {
+        match target {
+            Target::Grpc(channel) => Backend::Grpc(KeyValueStoreClient::new(channel)),
+            Target::Embedded(engine) => Backend::Embedded(engine),
+        }
+    }
+
+    pub async fn put(&mut self, key: String, value: Vec<u8>) -> Result<(), Error> {
+        match self {
+            Backend::Grpc(client) => {
+                client.put(PutRequest { key, value, ..Default::default() }).await?;
+            }
+            // Engine calls block (on the WAL fsync), so they run on this
+            // worker's thread with tokio told to move other tasks off it.
+            Backend::Embedded(engine) => tokio::task::block_in_place(|| engine.put(key, value))?,
+        }
+        Ok(())
+    }
+
+    /// GET `key`; returns whether it was found.
+    pub async fn get(&mut self, key: String) -> Result<bool, Error> {
+        Ok(self.read(key).await?.is_some())
+    }
+
+    /// GET `key`, returning its value.
+    pub async fn read(&mut self, key: String) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            Backend::Grpc(client) => {
+                let request  = GetRequest { key, ..Default::default() };
+                let response = client.get(request).await?.into_inner();
+                Ok(response.found.then_some(response.value))
+            }
+            Backend::Embedded(engine) => Ok(tokio::task::block_in_place(|| engine.get(&key))?),
+        }
+    }
+
+    /// DELETE `key`; returns whether it existed.
+    pub async fn delete(&mut self, key: String) -> Result<bool, Error> {
+        match self {
+            Backend::Grpc(client) => Ok(client.delete(DeleteRequest { key }).await?.into_inner().success),
+            Backend::Embedded(engine) => Ok(tokio::task::block_in_place(|| engine.delete(&key))?),
+        }
+    }
+
+    /// GET every key of `keys` in one `MultiGet` call, or from one engine
+    /// snapshot.  Returns how many were found.
+    pub async fn multi_get(&mut self, keys: Vec<String>) -> Result<u64, Error> {
+        match self {
+            Backend::Grpc(client) => {
+                let response = client.multi_get(MultiGetRequest { keys, ..Default::default() }).await?.into_inner();
+                Ok(response.values.iter().map(|value| u64::from(value.found)).sum())
+            }
+            Backend::Embedded(engine) => tokio::task::block_in_place(|| {
+                let snapshot  = engine.snapshot()?;
+                let mut found = 0;
+                for key in keys {
+                    found += u64::from(snapshot.get(&key)?.is_some());
+                }
+                Ok(found)
+            }),
+        }
+    }
+
+    /// SCAN up to `limit` benchmark keys from `start` on, in key order.
+    /// Returns the entries read and the bytes of their values.
+    pub async fn scan(&mut self, start: String, limit: u32) -> Result<(u64, u64), Error> {
+        let (mut entries, mut bytes) = (0, 0);
+        match self {
+            Backend::Grpc(client) => {
+                let request    = ScanRequest { prefix: KEY_PREFIX.to_owned(), start, limit, ..Default::default() };
+                let mut stream = client.scan(request).await?.into_inner();
+                while let Some(response) = stream.message().await? {
+                    entries += response.entries.len() as u64;
+                    bytes += response.entries.iter().map(|entry| entry.value.len() as u64).sum::<u64>();
+                }
+            }
+            // As the server reads a page: from a snapshot taken for it.
+            Backend::Embedded(engine) => {
+                let range = KeyRange {
+                    prefix: KEY_PREFIX.to_owned(),
+                    start:  Bound::Included(start),
+                    end:    Bound::Unbounded,
+                };
+                let page  = tokio::task::block_in_place(|| engine.snapshot()?.scan_range_page(&range, None, limit as usize))?;
+                entries = page.len() as u64;
+                bytes   = page.iter().map(|(_, value)| value.len() as u64).sum();
+            }
+        }
+        Ok((entries, bytes))
+    }
+}
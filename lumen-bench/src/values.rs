@@ -0,0 +1,74 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use rand_distr::{Distribution as _, LogNormal};
+
+/// Sampled sizes are capped so a PUT stays under tonic's default 4 MiB
+/// message limit.
+const MAX_SAMPLED: usize = (4 << 20) - 4096;
+
+/// How large each PUT value is.
+#[derive(Debug, Clone, Copy)]
+pub enum ValueSize {
+    Fixed(usize),
+    /// Uniform over `min..=max` bytes.
+    Range { min: usize, max: usize },
+    /// Log-normal around a median size, with `sigma` the standard deviation
+    /// of the size's natural logarithm: most values are near the median,
+    /// with a long tail of large ones.
+    LogNormal { median: usize, sigma: f64, dist: LogNormal<f64> },
+}
+
+impl ValueSize {
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        match *self {
+            ValueSize::Fixed(size) => size,
+            ValueSize::Range { min, max } => rng.gen_range(min..=max),
+            ValueSize::LogNormal { dist, .. } => (dist.sample(rng).round() as usize).min(MAX_SAMPLED),
+        }
+    }
+}
+
+impl FromStr for ValueSize {
+    type Err = String;
+
+    /// `SIZE`, `MIN..MAX` (inclusive) or `lognormal(MEDIAN,SIGMA)`, in bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = |n: &str| n.trim().parse::<usize>().map_err(|_| format!("invalid size {n:?}"));
+
+        if let Some(args) = s.strip_prefix("lognormal(").and_then(|rest| rest.strip_suffix(')')) {
+            let (median, sigma) = args
+                .split_once(',')
+                .ok_or_else(|| format!("expected lognormal(MEDIAN,SIGMA), got {s:?}"))?;
+            let median = bytes(median)?;
+            let sigma  = sigma.trim().parse::<f64>().map_err(|_| format!("invalid sigma {sigma:?}"))?;
+            if median == 0 {
+                return Err("lognormal median must be at least 1 byte".into());
+            }
+            if !(sigma >= 0.0 && sigma.is_finite()) {
+                return Err(format!("sigma must be a non-negative number, got {sigma}"));
+            }
+            let dist = LogNormal::new((median as f64).ln(), sigma).map_err(|e| e.to_string())?;
+            return Ok(ValueSize::LogNormal { median, sigma, dist });
+        }
+        if let Some((min, max)) = s.split_once("..") {
+            let (min, max) = (bytes(min)?, bytes(max)?);
+            if min > max {
+                return Err(format!("empty size range {s:?}"));
+            }
+            return Ok(ValueSize::Range { min, max });
+        }
+        bytes(s).map(ValueSize::Fixed)
+    }
+}
+
+impl fmt::Display for ValueSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueSize::Fixed(size) => write!(f, "{size}"),
+            ValueSize::Range { min, max } => write!(f, "{min}..{max}"),
+            ValueSize::LogNormal { median, sigma, .. } => write!(f, "lognormal({median},{sigma})"),
+        }
+    }
+}
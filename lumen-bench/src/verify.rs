@@ -0,0 +1,217 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use lumen_core::Engine;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use tonic::transport::Channel;
+
+use crate::node::LocalNode;
+use crate::target::{Backend, Target};
+use crate::values::ValueSize;
+use crate::worker;
+
+/// Set in a key's checksum slot once its PUT succeeded.
+const WRITTEN: u64 = 1 << 32;
+
+/// How many bad keys the report lists by name.
+const LISTED: usize = 10;
+
+/// Where a verify run writes and reads.
+pub enum Store {
+    /// A node at this URL, left running.
+    Remote(String),
+    /// A node spawned from this `lumen-server`, restarted between the
+    /// phases.
+    Local(PathBuf),
+    /// An engine in this process; with `reopen`, it is closed and opened
+    /// again between the phases.
+    Embedded { reopen: bool },
+}
+
+/// A fill-then-verify run: PUT `records` keys once each with values
+/// derived from the key and `seed`, then read every one back.
+pub struct Verify {
+    pub store:       Store,
+    pub data_dir:    PathBuf,
+    pub records:     u64,
+    pub value_size:  ValueSize,
+    pub seed:        u64,
+    pub concurrency: usize,
+}
+
+/// What reading a key back found wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Problem {
+    Missing,
+    Mismatched,
+    /// The GET itself failed.
+    Unreadable,
+}
+
+impl Verify {
+    pub async fn run(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let checksums: Arc<Vec<AtomicU64>> = Arc::new((0..self.records).map(|_| AtomicU64::new(0)).collect());
+        println!("✍️  Filling {} keys with values of {} bytes...", self.records, self.value_size);
+        println!("🎲 Seed: {}", self.seed);
+
+        let problems = match &self.store {
+            Store::Remote(url) => {
+                let targets = self.connect(url).await?;
+                self.fill(&targets, &checksums).await?;
+                self.check(&targets, &checksums).await?
+            }
+            Store::Local(bin) => {
+                let mut node = LocalNode::start(bin, &self.data_dir).await?;
+                self.fill(&self.connect(&node.url()).await?, &checksums).await?;
+                let startup = node.restart(None).await?;
+                println!("🔁 Restarted the node in {:.2?}", startup.listening);
+                let problems = self.check(&self.connect(&node.url()).await?, &checksums).await?;
+                node.stop().await?;
+                problems
+            }
+            Store::Embedded { reopen } => {
+                let open = || {
+                    Engine::open(&self.data_dir).map_err(|e| format!("Failed to open {}: {e}", self.data_dir.display()))
+                };
+                let mut engine = Arc::new(open()?);
+                self.fill(&[Target::Embedded(engine.clone())], &checksums).await?;
+                if *reopen {
+                    drop(engine);
+                    let start = Instant::now();
+                    engine = Arc::new(open()?);
+                    println!("🔁 Reopened the engine in {:.2?}", start.elapsed());
+                }
+                self.check(&[Target::Embedded(engine)], &checksums).await?
+            }
+        };
+        report(&problems, &checksums)
+    }
+
+    async fn connect(&self, url: &str) -> Result<Vec<Target>, Box<dyn std::error::Error>> {
+        let channel = Channel::from_shared(url.to_owned())?
+            .connect()
+            .await
+            .map_err(|e| format!("Failed to connect to LumenKV at {url}: {e}. Is the server running?"))?;
+        Ok(vec![Target::Grpc(channel)])
+    }
+
+    /// PUT every key, recording the checksum of each value written.
+    async fn fill(
+        &self,
+        targets: &[Target],
+        checksums: &Arc<Vec<AtomicU64>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let start   = Instant::now();
+        let next    = Arc::new(AtomicU64::new(0));
+        let workers: Vec<_> = (0..self.concurrency)
+            .map(|i| {
+                let mut target = Backend::new(targets[i % targets.len()].clone());
+                let (next, checksums, value_size, seed) = (next.clone(), checksums.clone(), self.value_size, self.seed);
+                tokio::spawn(async move {
+                    let mut failed = None;
+                    while let Some(slot) = claim(&next, checksums.len()) {
+                        let value    = value(seed, slot, value_size);
+                        let checksum = crc32fast::hash(&value);
+                        match target.put(worker::key(slot), value).await {
+                            Ok(()) => checksums[slot as usize].store(WRITTEN | u64::from(checksum), Ordering::Relaxed),
+                            Err(e) => failed = failed.or(Some(e.to_string())),
+                        }
+                    }
+                    failed
+                })
+            })
+            .collect();
+
+        let mut failed = None;
+        for worker in workers {
+            failed = failed.or(worker.await?);
+        }
+        let written = checksums.iter().filter(|c| c.load(Ordering::Relaxed) & WRITTEN != 0).count() as u64;
+        println!("✍️  Wrote {} keys in {:.2?}", written, start.elapsed());
+        if let Some(error) = failed {
+            println!("⚠️  {} PUTs failed; those keys are not checked", self.records - written);
+            println!("⚠️  First error: {error}");
+        }
+        Ok(())
+    }
+
+    /// Read every key written back; returns the bad ones, in key order.
+    async fn check(
+        &self,
+        targets: &[Target],
+        checksums: &Arc<Vec<AtomicU64>>,
+    ) -> Result<Vec<(u64, Problem)>, Box<dyn std::error::Error>> {
+        let start   = Instant::now();
+        let next    = Arc::new(AtomicU64::new(0));
+        let workers: Vec<_> = (0..self.concurrency)
+            .map(|i| {
+                let mut target = Backend::new(targets[i % targets.len()].clone());
+                let (next, checksums) = (next.clone(), checksums.clone());
+                tokio::spawn(async move {
+                    let mut problems = Vec::new();
+                    while let Some(slot) = claim(&next, checksums.len()) {
+                        let expected = checksums[slot as usize].load(Ordering::Relaxed);
+                        if expected & WRITTEN == 0 {
+                            continue;
+                        }
+                        let problem = match target.read(worker::key(slot)).await {
+                            Ok(Some(value)) if u64::from(crc32fast::hash(&value)) == expected & !WRITTEN => continue,
+                            Ok(Some(_)) => Problem::Mismatched,
+                            Ok(None) => Problem::Missing,
+                            Err(_) => Problem::Unreadable,
+                        };
+                        problems.push((slot, problem));
+                    }
+                    problems
+                })
+            })
+            .collect();
+
+        let mut problems = Vec::new();
+        for worker in workers {
+            problems.extend(worker.await?);
+        }
+        problems.sort_unstable_by_key(|&(slot, _)| slot);
+        println!("🔍 Read back in {:.2?}", start.elapsed());
+        Ok(problems)
+    }
+}
+
+/// Claim the next key to handle, below `records`.
+fn claim(next: &AtomicU64, records: usize) -> Option<u64> {
+    let slot = next.fetch_add(1, Ordering::Relaxed);
+    (slot < records as u64).then_some(slot)
+}
+
+/// The value written to key `slot`: its size and contents depend only on
+/// the seed and the slot.
+fn value(seed: u64, slot: u64, value_size: ValueSize) -> Vec<u8> {
+    let mut rng   = StdRng::seed_from_u64(seed ^ slot);
+    let mut value = vec![0; value_size.sample(&mut rng)];
+    rng.fill_bytes(&mut value);
+    value
+}
+
+fn report(problems: &[(u64, Problem)], checksums: &[AtomicU64]) -> Result<(), Box<dyn std::error::Error>> {
+    let written = checksums.iter().filter(|c| c.load(Ordering::Relaxed) & WRITTEN != 0).count();
+    let count   = |problem| problems.iter().filter(|&&(_, p)| p == problem).count();
+
+    if problems.is_empty() {
+        println!("\n✅ Verified {} keys: every value read back intact", written);
+        return Ok(());
+    }
+    println!("\n❌ Verification failed for {} of {} keys", problems.len(), written);
+    println!("🕳️  Missing: {}", count(Problem::Missing));
+    println!("🧬 Mismatched: {}", count(Problem::Mismatched));
+    println!("⚠️  Unreadable: {}", count(Problem::Unreadable));
+    for &(slot, problem) in problems.iter().take(LISTED) {
+        println!("   {}: {:?}", worker::key(slot), problem);
+    }
+    if problems.len() > LISTED {
+        println!("   ... and {} more", problems.len() - LISTED);
+    }
+    Err(format!("{} keys failed verification", problems.len()).into())
+}
@@ -0,0 +1,308 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::chaos::{Chaos, Injector};
+use crate::keys::{Distribution, KeyChooser};
+use crate::live::LiveRecorder;
+use crate::stats::Stats;
+use crate::target::{Backend, Error, Target};
+use crate::values::ValueSize;
+use crate::Op;
+
+/// How long before a scheduled start `wait_until` stops sleeping.
+const TIMER_SLACK: Duration = Duration::from_millis(2);
+
+/// Spreads the workers' seeds apart (2^64 / golden ratio).
+const SEED_SPREAD: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The run every worker shares.
+pub struct Workload {
+    pub op:           Op,
+    pub read_pct:     u8,
+    /// What the non-GET requests of a mixed workload do.
+    pub write:        WriteKind,
+    pub distribution: Distribution,
+    pub requests:     u64,
+    pub deadline:     Option<Duration>,
+    /// Requests per second to schedule, for an open-loop run.
+    pub rate:         Option<f64>,
+    pub key_space:    u64,
+    /// Keys `0 .. loaded` were written before the run started.
+    pub loaded:       u64,
+    /// Mixed inserts continue after `loaded` and wrap around after this
+    /// many keys; `u64::MAX` lets the key count grow without bound.
+    pub max_keys:     u64,
+    pub value_size:   ValueSize,
+    /// Most entries each SCAN reads.
+    pub scan_length:  u32,
+    /// Keys each MULTI-GET reads.
+    pub batch_size:   u64,
+    /// Seeds the workers' key, operation and value choices; worker `i`
+    /// draws from its own generator, derived from this and `i`.
+    pub seed:         u64,
+    /// Faults to inject in place of some requests.
+    pub chaos:        Option<Chaos>,
+    pub start:        Instant,
+    pub progress:     Progress,
+}
+
+/// What a mixed workload does when it is not reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteKind {
+    /// PUT the next key in order.
+    Insert,
+    /// PUT an existing key, chosen like a GET.
+    Update,
+    /// GET an existing key, then PUT it back.
+    ReadModifyWrite,
+}
+
+/// Counters the workers advance together.
+#[derive(Default)]
+pub struct Progress {
+    /// Requests claimed so far.
+    issued:       AtomicU64,
+    /// Mixed inserts claimed; each writes the next key in order.
+    insert_slots: AtomicU64,
+    /// Mixed inserts completed, which bounds the keys other requests use.
+    inserts_done: AtomicU64,
+    /// Requests that failed.
+    errors:       AtomicU64,
+    first_error:  Mutex<Option<String>>,
+}
+
+impl Workload {
+    /// PUT keys `0 .. records` once each, in order.
+    pub fn load(records: u64, value_size: ValueSize, seed: u64) -> Self {
+        Workload {
+            op: Op::Put,
+            read_pct: 0,
+            write: WriteKind::Insert,
+            distribution: Distribution::Sequential,
+            requests: records,
+            deadline: None,
+            rate: None,
+            key_space: records,
+            loaded: 0,
+            max_keys: records,
+            value_size,
+            scan_length: 1,
+            batch_size: 1,
+            seed,
+            chaos: None,
+            start: Instant::now(),
+            progress: Progress::default(),
+        }
+    }
+
+    /// Keys known to hold a value: those loaded plus those inserted so far.
+    pub fn records(&self) -> u64 {
+        let inserted = self.progress.inserts_done.load(Ordering::Relaxed);
+        self.loaded.saturating_add(inserted).min(self.max_keys)
+    }
+
+    pub fn errors(&self) -> u64 {
+        self.progress.errors.load(Ordering::Relaxed)
+    }
+
+    /// The message of the first request to fail, if any did.
+    pub fn first_error(&self) -> Option<String> {
+        self.progress.first_error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn record_error(&self, error: &Error) {
+        self.progress.errors.fetch_add(1, Ordering::Relaxed);
+        let mut first = self.progress.first_error.lock().unwrap_or_else(|e| e.into_inner());
+        first.get_or_insert_with(|| error.to_string());
+    }
+
+    /// Claim the next request and, at a fixed rate, the time it should
+    /// start; `None` once the run is over.
+    fn next(&self) -> Option<(u64, Option<Instant>)> {
+        if let (Some(limit), None) = (self.deadline, self.rate) {
+            if self.start.elapsed() >= limit {
+                return None;
+            }
+        }
+        let ticket = self.progress.issued.fetch_add(1, Ordering::Relaxed);
+        if self.deadline.is_none() && ticket >= self.requests {
+            return None;
+        }
+        let Some(rate) = self.rate else {
+            return Some((ticket, None));
+        };
+        let offset = Duration::from_secs_f64(ticket as f64 / rate);
+        match self.deadline {
+            Some(limit) if offset >= limit => None,
+            _ => Some((ticket, Some(self.start + offset))),
+        }
+    }
+}
+
+/// One request, with the key index it targets.
+enum Request {
+    Put(u64),
+    Insert(u64),
+    Get(u64),
+    Delete(u64),
+    ReadModifyWrite(u64),
+    /// Starting at the key.
+    Scan(u64),
+    MultiGet(Vec<u64>),
+}
+
+/// What a worker reports to besides its own `Stats`.
+#[derive(Default)]
+pub struct Hooks {
+    pub live:     Option<LiveRecorder>,
+    /// Given when the workload has `chaos`.
+    pub injector: Option<Injector>,
+}
+
+/// Issue requests to `target` as worker `index` until the workload is
+/// exhausted.  Failed requests are counted, and the run carries on.
+pub async fn run(workload: Arc<Workload>, index: u64, target: Target, mut hooks: Hooks) -> Stats {
+    let mut target = Backend::new(target);
+    let mut rng    = StdRng::seed_from_u64(workload.seed ^ index.wrapping_mul(SEED_SPREAD));
+    let mut stats  = Stats::new();
+    let mut keys   = KeyChooser::new(workload.distribution);
+    let mut after_fault = false;
+
+    while let Some((ticket, scheduled)) = workload.next() {
+        if let (Some(chaos), Some(injector)) = (&workload.chaos, &mut hooks.injector) {
+            if rng.gen_bool(chaos.probability) {
+                if let Some(at) = scheduled {
+                    wait_until(at).await;
+                }
+                injector.inject(chaos, &mut rng).await;
+                after_fault = true;
+                continue;
+            }
+        }
+
+        // Mixed requests other than inserts use keys already written, so
+        // a run that starts empty opens with an insert.  With several
+        // workers a GET can still race an insert of its key in flight.
+        let records = workload.records();
+        let request = match workload.op {
+            Op::Put => Request::Put(keys.pick(&mut rng, ticket, workload.key_space)),
+            Op::Get => Request::Get(keys.pick(&mut rng, ticket, workload.key_space)),
+            Op::Delete => Request::Delete(keys.pick(&mut rng, ticket, workload.key_space)),
+            Op::Scan => Request::Scan(keys.pick(&mut rng, ticket, workload.key_space)),
+            // In sequence, each batch takes the keys after the last one's.
+            Op::MultiGet => Request::MultiGet(
+                (0..workload.batch_size)
+                    .map(|i| {
+                        let ticket = ticket.wrapping_mul(workload.batch_size).wrapping_add(i);
+                        keys.pick(&mut rng, ticket, workload.key_space)
+                    })
+                    .collect(),
+            ),
+            Op::Mixed if records > 0 && rng.gen_range(0..100) < workload.read_pct => {
+                Request::Get(keys.pick(&mut rng, ticket, records))
+            }
+            Op::Mixed => match workload.write {
+                WriteKind::Update if records > 0 => Request::Put(keys.pick(&mut rng, ticket, records)),
+                WriteKind::ReadModifyWrite if records > 0 => {
+                    Request::ReadModifyWrite(keys.pick(&mut rng, ticket, records))
+                }
+                _ => {
+                    let slot = workload.progress.insert_slots.fetch_add(1, Ordering::Relaxed);
+                    Request::Insert(workload.loaded.saturating_add(slot) % workload.max_keys)
+                }
+            },
+        };
+
+        // On a fixed schedule, latency is measured from when the request
+        // should have been sent: if the server (or a shortage of workers)
+        // holds it up, the delay counts, rather than being hidden by the
+        // closed loop sending it late (coordinated omission).
+        let op_start = match scheduled {
+            Some(at) => {
+                wait_until(at).await;
+                at
+            }
+            None => Instant::now(),
+        };
+        // `Ok(n)`: a GET found its key, a DELETE removed one, a SCAN read n
+        // entries or a MULTI-GET found n keys.
+        let (outcome, op_stats) = match request {
+            Request::Put(slot) | Request::Insert(slot) => {
+                let outcome = target.put(key(slot), value(&workload, &mut rng)).await.map(|()| 0);
+                if outcome.is_ok() && matches!(request, Request::Insert(_)) {
+                    workload.progress.inserts_done.fetch_add(1, Ordering::Relaxed);
+                }
+                (outcome, &mut stats.puts)
+            }
+            Request::Get(slot) => (target.get(key(slot)).await.map(u64::from), &mut stats.gets),
+            Request::Delete(slot) => (target.delete(key(slot)).await.map(u64::from), &mut stats.dels),
+            Request::ReadModifyWrite(slot) => {
+                let outcome = match target.get(key(slot)).await {
+                    Ok(_) => target.put(key(slot), value(&workload, &mut rng)).await.map(|()| 0),
+                    Err(e) => Err(e),
+                };
+                (outcome, &mut stats.rmws)
+            }
+            Request::Scan(slot) => {
+                let outcome = target.scan(key(slot), workload.scan_length).await.map(|(entries, bytes)| {
+                    stats.scans.bytes += bytes;
+                    entries
+                });
+                (outcome, &mut stats.scans)
+            }
+            Request::MultiGet(slots) => {
+                let keys = slots.into_iter().map(key).collect();
+                (target.multi_get(keys).await, &mut stats.mgets)
+            }
+        };
+
+        if let (true, Some(chaos)) = (after_fault, &workload.chaos) {
+            chaos.record_after_fault(outcome.is_ok());
+            after_fault = false;
+        }
+        match outcome {
+            Ok(hits) => {
+                let latency = op_start.elapsed();
+                op_stats.hits += hits;
+                op_stats.record(latency);
+                if let Some(live) = &mut hooks.live {
+                    live.record(latency);
+                }
+            }
+            Err(e) => {
+                op_stats.errors += 1;
+                workload.record_error(&e);
+            }
+        }
+    }
+
+    stats
+}
+
+/// Sleep until `at`.  The timer only has millisecond resolution, so the
+/// last stretch is spent yielding, lest timer lag show up as latency.
+async fn wait_until(at: Instant) {
+    if let Some(early) = at.checked_sub(TIMER_SLACK) {
+        tokio::time::sleep_until(early.into()).await;
+    }
+    while Instant::now() < at {
+        tokio::task::yield_now().await;
+    }
+}
+
+/// What every benchmark key starts with.
+pub const KEY_PREFIX: &str = "bench-key-";
+
+pub fn key(slot: u64) -> String {
+    format!("{KEY_PREFIX}{slot}")
+}
+
+fn value(workload: &Workload, rng: &mut impl Rng) -> Vec<u8> {
+    let mut value = vec![0; workload.value_size.sample(rng)];
+    rng.fill_bytes(&mut value);
+    value
+}
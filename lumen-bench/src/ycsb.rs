@@ -0,0 +1,61 @@
+use clap::ValueEnum;
+
+use crate::keys::Distribution;
+use crate::worker::WriteKind;
+
+/// The YCSB core workloads.  Each runs against `--key-space` records that
+/// are loaded first, with the mix and request distribution YCSB specifies.
+/// Workload E (short scans) is not included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// Update heavy: 50% reads, 50% updates.
+    #[value(name = "ycsb-a")]
+    A,
+    /// Read mostly: 95% reads, 5% updates.
+    #[value(name = "ycsb-b")]
+    B,
+    /// Read only.
+    #[value(name = "ycsb-c")]
+    C,
+    /// Read latest: 95% reads of recent inserts, 5% inserts.
+    #[value(name = "ycsb-d")]
+    D,
+    /// Read-modify-write: 50% reads, 50% read-modify-writes.
+    #[value(name = "ycsb-f")]
+    F,
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::A => "YCSB-A",
+            Preset::B => "YCSB-B",
+            Preset::C => "YCSB-C",
+            Preset::D => "YCSB-D",
+            Preset::F => "YCSB-F",
+        }
+    }
+
+    pub fn read_pct(self) -> u8 {
+        match self {
+            Preset::A | Preset::F => 50,
+            Preset::B | Preset::D => 95,
+            Preset::C => 100,
+        }
+    }
+
+    pub fn write(self) -> WriteKind {
+        match self {
+            Preset::A | Preset::B | Preset::C => WriteKind::Update,
+            Preset::D => WriteKind::Insert,
+            Preset::F => WriteKind::ReadModifyWrite,
+        }
+    }
+
+    pub fn distribution(self) -> Distribution {
+        match self {
+            Preset::D => Distribution::Latest,
+            _ => Distribution::Zipfian(0.99),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Portable dumps (`.lumen` files), written by `export` and read by
+//! `import --from lumen`.
+//!
+//! Unlike a checkpoint, a dump carries no sequence or node state: it is just
+//! keys and values, to be loaded into any store, under any namespace.
+//!
+//! On-disk format:
+//!   [Magic "LKVDUMP1" (8 bytes)] [Compression (1 byte): 0 none, 1 zstd]
+//!   then the body, as a single zstd frame when compressed:
+//!   Count × { [Key Len (4 bytes, BE)] [Value Len (4 bytes, BE)] [Key] [Value] }
+//!   [End 0xFFFFFFFF (4 bytes)] [Count (8 bytes, BE)] [CRC32 (4 bytes, BE)]
+//!
+//! CRC32 is computed over the body bytes preceding the end marker, so a dump
+//! that was truncated or damaged in transit fails to import at its end.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use crc32fast::Hasher as Crc32Hasher;
+
+const MAGIC: &[u8; 8] = b"LKVDUMP1";
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_ZSTD: u8 = 1;
+const END: u32 = u32::MAX;
+
+enum Body {
+    Plain(BufWriter<File>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Write for Body {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Body::Plain(w) => w.write(buf),
+            Body::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Body::Plain(w) => w.flush(),
+            Body::Zstd(w) => w.flush(),
+        }
+    }
+}
+
+/// Writes a dump to a temporary file, renamed to its path by `finish`.
+pub struct ArchiveWriter {
+    body: Body,
+    hasher: Crc32Hasher,
+    count: u64,
+    tmp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl ArchiveWriter {
+    /// Start a dump at `path`, compressed at `zstd_level` if one is given.
+    pub fn create(path: &Path, zstd_level: Option<i32>) -> anyhow::Result<Self> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".partial");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let file       = File::create(&tmp_path).with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        let body = match zstd_level {
+            Some(level) => {
+                writer.write_all(&[COMPRESSION_ZSTD])?;
+                Body::Zstd(zstd::Encoder::new(writer, level)?)
+            }
+            None => {
+                writer.write_all(&[COMPRESSION_NONE])?;
+                Body::Plain(writer)
+            }
+        };
+        Ok(Self { body, hasher: Crc32Hasher::new(), count: 0, tmp_path, path: path.to_owned() })
+    }
+
+    pub fn append(&mut self, key: &str, value: &[u8]) -> anyhow::Result<()> {
+        let (key_len, value_len) = (u32::try_from(key.len()), u32::try_from(value.len()));
+        let (Ok(key_len), Ok(value_len)) = (key_len, value_len) else {
+            bail!("{key:?} is too large for a dump");
+        };
+        for part in [&key_len.to_be_bytes()[..], &value_len.to_be_bytes(), key.as_bytes(), value] {
+            self.hasher.update(part);
+            self.body.write_all(part)?;
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write the trailer and move the dump into place.  Returns the number
+    /// of entries written.
+    pub fn finish(mut self) -> anyhow::Result<u64> {
+        self.body.write_all(&END.to_be_bytes())?;
+        self.body.write_all(&self.count.to_be_bytes())?;
+        self.body.write_all(&self.hasher.finalize().to_be_bytes())?;
+        let mut writer = match self.body {
+            Body::Plain(w) => w,
+            Body::Zstd(w) => w.finish()?,
+        };
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("failed to move the dump to {}", self.path.display()))?;
+        Ok(self.count)
+    }
+}
+
+/// Reads the entries of a dump, checking its trailer at the end.
+pub struct ArchiveReader {
+    body: Box<dyn Read + Send>,
+    hasher: Crc32Hasher,
+    count: u64,
+    done: bool,
+}
+
+impl ArchiveReader {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file       = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0u8; 9];
+        reader.read_exact(&mut header).context("not a LumenKV dump: the file is too short")?;
+        if &header[..8] != MAGIC {
+            bail!("not a LumenKV dump: it does not start with {}", String::from_utf8_lossy(MAGIC));
+        }
+        let body: Box<dyn Read + Send> = match header[8] {
+            COMPRESSION_NONE => Box::new(reader),
+            COMPRESSION_ZSTD => Box::new(zstd::Decoder::with_buffer(reader)?),
+            other => bail!("dump uses unknown compression {other}"),
+        };
+        Ok(Self { body, hasher: Crc32Hasher::new(), count: 0, done: false })
+    }
+
+    /// The next key and value, or `None` after the trailer was verified.
+    pub fn next_entry(&mut self) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+        if self.done {
+            return Ok(None);
+        }
+        let key_len = self.u32().context("dump ends before its trailer")?;
+        if key_len == END {
+            self.done = true;
+            let mut trailer = [0u8; 12];
+            self.body.read_exact(&mut trailer).context("dump ends in its trailer")?;
+            let count    = u64::from_be_bytes(trailer[..8].try_into().expect("8-byte count"));
+            let stored   = u32::from_be_bytes(trailer[8..].try_into().expect("4-byte checksum"));
+            let computed = self.hasher.clone().finalize();
+            if stored != computed {
+                bail!("dump checksum mismatch: stored {stored:#010x}, computed {computed:#010x}");
+            }
+            if count != self.count {
+                bail!("dump trailer counts {count} entries, but {} were read", self.count);
+            }
+            return Ok(None);
+        }
+        self.hasher.update(&key_len.to_be_bytes());
+        let value_len = self.u32().context("dump ends in the middle of an entry")?;
+        self.hasher.update(&value_len.to_be_bytes());
+
+        let key   = self.bytes(key_len)?;
+        let value = self.bytes(value_len)?;
+        self.count += 1;
+        let key = String::from_utf8(key).context("dump holds a key that is not UTF-8")?;
+        Ok(Some((key, value)))
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        let mut buf = [0u8; 4];
+        self.body.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
+    }
+
+    fn bytes(&mut self, len: u32) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.body).take(u64::from(len)).read_to_end(&mut buf)?;
+        if buf.len() != len as usize {
+            bail!("dump ends in the middle of an entry");
+        }
+        self.hasher.update(&buf);
+        Ok(buf)
+    }
+}
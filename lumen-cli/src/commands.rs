@@ -0,0 +1,531 @@
+//! Subcommands shared by one-shot invocations and the interactive shell.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use base64::Engine as _;
+use clap::Subcommand;
+use serde_json::{json, Map, Value};
+use tokio::io::AsyncReadExt;
+use tonic::transport::Channel;
+use tonic::Status;
+
+use lumen_client::{Client, ClientConfig};
+
+use crate::kv::admin_client::AdminClient;
+use crate::kv::key_value_store_client::KeyValueStoreClient;
+use crate::archive::ArchiveWriter;
+use crate::import::{self, Entry, Format};
+use crate::kv::{
+    BackupRequest, BatchPutRequest, DeleteRequest, GetRequest, NodeRole, PutRequest, RenameRequest,
+    ReplicationStatusRequest,
+};
+
+/// Response metadata header carrying the serving node's applied sequence.
+const APPLIED_SEQUENCE_HEADER: &str = "x-lumen-applied-sequence";
+
+/// `import` sends a batch once it holds this many bytes of keys and values,
+/// well under the 4 MiB message limit.
+const IMPORT_BATCH_BYTES: usize = 1 << 20;
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print the value of KEY.
+    Get { key: String },
+    /// Store VALUE (or stdin) under KEY.
+    Put { key: String, value: Option<String> },
+    /// Delete KEY.
+    Del { key: String },
+    /// Move the value of KEY to NEW_KEY in one write.
+    Mv {
+        key: String,
+        new_key: String,
+        /// Replace NEW_KEY if it exists.
+        #[arg(long)]
+        force: bool,
+    },
+    /// List keys starting with PREFIX.
+    Scan {
+        #[arg(default_value = "")]
+        prefix: String,
+        /// Print values next to keys.
+        #[arg(long)]
+        values: bool,
+        /// Stop after this many keys.
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Show the node's role, applied sequence and replication lag.
+    Stats,
+    /// Take a coordinated backup on the server.
+    Backup {
+        /// Directory on the server to write the backup to (default: its BACKUP_DIR).
+        #[arg(long, default_value = "")]
+        destination: String,
+    },
+    /// Write the keys under PREFIX to a portable dump.
+    Export {
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// File to write the dump to.
+        #[arg(long, short = 'o')]
+        output: PathBuf,
+        /// Compress the dump with zstd, at LEVEL (default 3).
+        #[arg(long, value_name = "LEVEL", num_args = 0..=1, default_missing_value = "3")]
+        zstd: Option<i32>,
+        /// Read this data directory of a stopped node instead of the server.
+        #[arg(long, value_name = "DIR")]
+        data_dir: Option<PathBuf>,
+    },
+    /// Load the keys of a dump, a Redis RDB snapshot or a RocksDB database.
+    Import {
+        /// What PATH holds.
+        #[arg(long)]
+        from: Format,
+        path: PathBuf,
+        /// Redis database to import; keys in the others are skipped.
+        #[arg(long, default_value_t = 0)]
+        redis_db: u64,
+        /// Keys per BatchPut.
+        #[arg(long, default_value_t = 500)]
+        batch_size: usize,
+    },
+}
+
+/// How results are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// Values as raw bytes, so they can be piped.
+    Raw,
+    /// One JSON document per command.
+    Json,
+    /// Values quoted (UTF-8) or hex-dumped, for humans at the shell.
+    Pretty,
+}
+
+/// A connection plus the settings commands run with.
+pub struct Session {
+    kv: KeyValueStoreClient<Channel>,
+    admin: AdminClient<Channel>,
+    /// For the calls the client library wraps with paging and retries.
+    client: Client,
+    pub namespace: Option<String>,
+    pub output: Output,
+    /// Read an omitted `put` value from stdin (off in the shell, where stdin
+    /// carries the commands).
+    pub read_stdin: bool,
+}
+
+/// Reject namespaces that would not round-trip through `NS/key`.
+pub fn check_namespace(namespace: &str) -> anyhow::Result<()> {
+    if namespace.is_empty() || namespace.contains('/') {
+        anyhow::bail!("namespace must be non-empty and must not contain `/`");
+    }
+    Ok(())
+}
+
+impl Session {
+    pub fn new(channel: Channel, namespace: Option<String>, output: Output) -> anyhow::Result<Self> {
+        Ok(Self {
+            kv: KeyValueStoreClient::new(channel.clone()),
+            admin: AdminClient::new(channel.clone()),
+            client: Client::from_channel(channel, ClientConfig::default())?,
+            namespace,
+            output,
+            read_stdin: true,
+        })
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(ns) => format!("{ns}/{key}"),
+            None => key.to_owned(),
+        }
+    }
+
+    /// Run `command`.  Returns `false` if it looked up a key that does not
+    /// exist.
+    pub async fn execute(&mut self, command: &Command) -> anyhow::Result<bool> {
+        match command {
+            Command::Get { key } => return self.get(key).await,
+            Command::Put { key, value } => self.put(key, value.as_deref()).await?,
+            Command::Del { key } => self.del(key).await?,
+            Command::Mv { key, new_key, force } => self.mv(key, new_key, *force).await?,
+            Command::Scan { prefix, values, limit } => self.scan(prefix, *values, *limit).await?,
+            Command::Stats => self.stats().await?,
+            Command::Backup { destination } => self.backup(destination).await?,
+            Command::Export { prefix, output, zstd, data_dir: Some(dir) } => {
+                export_data_dir(dir, self.namespace.as_deref(), prefix, output, *zstd, self.output)?
+            }
+            Command::Export { prefix, output, zstd, data_dir: None } => self.export(prefix, output, *zstd).await?,
+            Command::Import { from, path, redis_db, batch_size } => {
+                self.import(*from, path, *redis_db, *batch_size).await?
+            }
+        }
+        Ok(true)
+    }
+
+    async fn get(&mut self, key: &str) -> anyhow::Result<bool> {
+        let response = self
+            .kv
+            .get(GetRequest { key: self.full_key(key), ..Default::default() })
+            .await
+            .map_err(rpc_error)?;
+        let applied = response
+            .metadata()
+            .get(APPLIED_SEQUENCE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        let resp = response.into_inner();
+
+        match self.output {
+            Output::Json => {
+                let mut doc = Map::new();
+                doc.insert("key".into(), json!(key));
+                doc.insert("found".into(), json!(resp.found));
+                if resp.found {
+                    insert_value(&mut doc, &resp.value);
+                }
+                if let Some(applied) = applied {
+                    doc.insert("applied_sequence".into(), json!(applied));
+                }
+                print_json(Value::Object(doc))?;
+            }
+            Output::Raw if resp.found => std::io::stdout().write_all(&resp.value)?,
+            Output::Pretty if resp.found => println!("{}", pretty(&resp.value)),
+            _ => eprintln!("{key}: not found"),
+        }
+        Ok(resp.found)
+    }
+
+    async fn put(&mut self, key: &str, value: Option<&str>) -> anyhow::Result<()> {
+        let value = match value {
+            Some(value) => value.as_bytes().to_vec(),
+            None if !self.read_stdin => anyhow::bail!("usage: put KEY VALUE"),
+            None => {
+                let mut buf = Vec::new();
+                tokio::io::stdin().read_to_end(&mut buf).await.context("failed to read value from stdin")?;
+                buf
+            }
+        };
+        let bytes = value.len();
+        let resp = self
+            .kv
+            .put(PutRequest { key: self.full_key(key), value, ..Default::default() })
+            .await
+            .map_err(rpc_error)?
+            .into_inner();
+
+        match self.output {
+            Output::Json => print_json(json!({ "key": key, "success": resp.success, "bytes": bytes }))?,
+            _ => println!("OK ({bytes} bytes)"),
+        }
+        Ok(())
+    }
+
+    async fn del(&mut self, key: &str) -> anyhow::Result<()> {
+        let resp = self
+            .kv
+            .delete(DeleteRequest { key: self.full_key(key) })
+            .await
+            .map_err(rpc_error)?
+            .into_inner();
+
+        match self.output {
+            Output::Json => print_json(json!({ "key": key, "deleted": resp.success }))?,
+            _ if resp.success => println!("deleted"),
+            _ => println!("{key}: not found"),
+        }
+        Ok(())
+    }
+
+    async fn mv(&mut self, key: &str, new_key: &str, force: bool) -> anyhow::Result<()> {
+        let resp = self
+            .kv
+            .rename(RenameRequest { key: self.full_key(key), new_key: self.full_key(new_key), overwrite: force })
+            .await
+            .map_err(rpc_error)?
+            .into_inner();
+
+        match self.output {
+            Output::Json => print_json(json!({ "key": key, "new_key": new_key, "renamed": resp.renamed }))?,
+            _ if resp.renamed => println!("renamed"),
+            _ if force => println!("{key}: not found"),
+            _ => println!("{key}: not found, or {new_key} exists (--force replaces it)"),
+        }
+        Ok(())
+    }
+
+    async fn scan(&mut self, prefix: &str, values: bool, limit: Option<usize>) -> anyhow::Result<()> {
+        let full_prefix = self.full_key(prefix);
+        let strip       = full_prefix.len() - prefix.len();
+
+        // Dropping the stream at the limit cancels the rest of the scan.
+        let mut scan    = self.client.scan(full_prefix);
+        let mut matched = Vec::new();
+        while limit.is_none_or(|limit| matched.len() < limit) {
+            let Some(entry) = scan.next().await else { break };
+            let (key, value) = entry?;
+            matched.push((key[strip..].to_owned(), value));
+        }
+
+        let mut out = std::io::stdout().lock();
+        match self.output {
+            Output::Json => {
+                let items: Vec<Value> = matched
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut doc = Map::new();
+                        doc.insert("key".into(), json!(key));
+                        if values {
+                            insert_value(&mut doc, value);
+                        }
+                        Value::Object(doc)
+                    })
+                    .collect();
+                print_json(Value::Array(items))?;
+            }
+            Output::Raw if values => {
+                for (key, value) in &matched {
+                    writeln!(out, "{key}\t{}", String::from_utf8_lossy(value))?;
+                }
+            }
+            Output::Pretty if values => {
+                for (i, (key, value)) in matched.iter().enumerate() {
+                    writeln!(out, "{}) {key} => {}", i + 1, pretty(value))?;
+                }
+            }
+            Output::Raw | Output::Pretty => {
+                for (key, _) in &matched {
+                    writeln!(out, "{key}")?;
+                }
+            }
+        }
+        if self.output == Output::Pretty && matched.is_empty() {
+            writeln!(out, "(empty)")?;
+        }
+        Ok(())
+    }
+
+    async fn stats(&mut self) -> anyhow::Result<()> {
+        let status = self
+            .kv
+            .replication_status(ReplicationStatusRequest {})
+            .await
+            .map_err(rpc_error)?
+            .into_inner();
+        let role = NodeRole::try_from(status.role)
+            .map_or("unknown", |r| r.as_str_name())
+            .trim_start_matches("NODE_ROLE_")
+            .to_lowercase();
+
+        let mut doc = Map::new();
+        doc.insert("role".into(), json!(role));
+        doc.insert("applied_sequence".into(), json!(status.applied_sequence));
+        if status.role == NodeRole::Replica as i32 || status.role == NodeRole::Learner as i32 {
+            doc.insert("primary_addr".into(), json!(status.primary_addr));
+            doc.insert("connected".into(), json!(status.connected));
+            doc.insert("primary_sequence".into(), json!(status.primary_sequence));
+            doc.insert("lag_records".into(), json!(status.lag_records));
+            doc.insert("millis_since_contact".into(), json!(status.millis_since_contact));
+        } else {
+            doc.insert("replicas".into(), json!(status.replicas.len()));
+        }
+
+        match self.output {
+            Output::Json => print_json(Value::Object(doc))?,
+            _ => {
+                for (name, value) in doc {
+                    match value {
+                        Value::String(s) => println!("{name}: {s}"),
+                        other => println!("{name}: {other}"),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn backup(&mut self, destination: &str) -> anyhow::Result<()> {
+        let backup = self
+            .admin
+            .backup(BackupRequest { destination: destination.to_owned() })
+            .await
+            .map_err(rpc_error)?
+            .into_inner();
+
+        match self.output {
+            Output::Json => {
+                let shards: Vec<Value> = backup
+                    .shards
+                    .iter()
+                    .map(|s| json!({ "shard": s.shard, "sequence": s.sequence, "keys": s.keys, "path": s.path }))
+                    .collect();
+                print_json(json!({ "backup_id": backup.backup_id, "path": backup.path, "shards": shards }))?;
+            }
+            _ => println!("{} written to {}", backup.backup_id, backup.path),
+        }
+        Ok(())
+    }
+
+    /// Stream the keys under `prefix` from the server's Scan into a dump at
+    /// `path`.
+    async fn export(&mut self, prefix: &str, path: &Path, zstd: Option<i32>) -> anyhow::Result<()> {
+        let full_prefix = self.full_key(prefix);
+        let strip       = full_prefix.len() - prefix.len();
+
+        let mut scan    = self.client.scan(full_prefix);
+        let mut archive = ArchiveWriter::create(path, zstd)?;
+        while let Some(entry) = scan.next().await {
+            let (key, value) = entry?;
+            archive.append(&key[strip..], &value)?;
+        }
+        report_export(self.output, archive.finish()?, path)
+    }
+
+    /// Stream the keys of `path` into the store through BatchPut, then
+    /// report what was loaded and what was skipped, by kind.
+    async fn import(&mut self, from: Format, path: &Path, redis_db: u64, batch_size: usize) -> anyhow::Result<()> {
+        let mut source  = import::spawn(from, path, redis_db);
+        let mut summary = ImportSummary::default();
+        let mut batch   = Vec::new();
+        let mut bytes   = 0;
+
+        while let Some(entry) = source.recv().await {
+            match entry? {
+                Entry::Put { key, value, expiry_dropped } => {
+                    summary.expiry_dropped += u64::from(expiry_dropped);
+                    bytes += key.len() + value.len();
+                    batch.push(PutRequest { key: self.full_key(&key), value, ..Default::default() });
+                }
+                Entry::Skipped(kind) => *summary.skipped.entry(kind).or_default() += 1,
+            }
+            if batch.len() >= batch_size.max(1) || bytes >= IMPORT_BATCH_BYTES {
+                self.import_batch(std::mem::take(&mut batch), &mut summary).await?;
+                bytes = 0;
+            }
+        }
+        if !batch.is_empty() {
+            self.import_batch(batch, &mut summary).await?;
+        }
+
+        match self.output {
+            Output::Json => print_json(json!({
+                "imported":       summary.imported,
+                "skipped":        summary.skipped,
+                "expiry_dropped": summary.expiry_dropped,
+                "rejected":       summary.rejected,
+                "first_rejection": summary.first_rejection,
+            }))?,
+            _ => {
+                println!("Imported {} keys from {}", summary.imported, path.display());
+                if !summary.skipped.is_empty() {
+                    let kinds: Vec<String> = summary.skipped.iter().map(|(kind, n)| format!("{kind} {n}")).collect();
+                    println!("Skipped: {}", kinds.join(", "));
+                }
+                if summary.expiry_dropped > 0 {
+                    println!("Imported without their TTL: {}", summary.expiry_dropped);
+                }
+                if let Some(first) = &summary.first_rejection {
+                    println!("Rejected by the server: {} (first: {first})", summary.rejected);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_batch(&mut self, entries: Vec<PutRequest>, summary: &mut ImportSummary) -> anyhow::Result<()> {
+        let resp = self.kv.batch_put(BatchPutRequest { entries }).await.map_err(rpc_error)?.into_inner();
+        for result in resp.results {
+            if result.code == 0 {
+                summary.imported += 1;
+            } else {
+                summary.rejected += 1;
+                summary.first_rejection.get_or_insert(result.message);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `export --data-dir`: open the data directory of a stopped node as its
+/// engine would, and dump the keys under `prefix` (in `namespace`, if set).
+pub fn export_data_dir(
+    dir: &Path,
+    namespace: Option<&str>,
+    prefix: &str,
+    path: &Path,
+    zstd: Option<i32>,
+    output: Output,
+) -> anyhow::Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("{} is not a directory", dir.display());
+    }
+    let full_prefix = match namespace {
+        Some(ns) => format!("{ns}/{prefix}"),
+        None => prefix.to_owned(),
+    };
+    let strip  = full_prefix.len() - prefix.len();
+    let engine = lumen_core::Engine::open(dir).with_context(|| format!("failed to open {}", dir.display()))?;
+
+    let mut archive = ArchiveWriter::create(path, zstd)?;
+    for entry in engine.scan(lumen_core::KeyRange::prefix(full_prefix))? {
+        let (key, value) = entry?;
+        archive.append(&key[strip..], &value)?;
+    }
+    report_export(output, archive.finish()?, path)
+}
+
+fn report_export(output: Output, count: u64, path: &Path) -> anyhow::Result<()> {
+    match output {
+        Output::Json => print_json(json!({ "exported": count, "path": path.display().to_string() }))?,
+        _ => println!("Exported {count} keys to {}", path.display()),
+    }
+    Ok(())
+}
+
+/// Running totals of an `import`.
+#[derive(Default)]
+struct ImportSummary {
+    imported: u64,
+    /// Keys with no LumenKV equivalent, by kind.
+    skipped: BTreeMap<String, u64>,
+    /// Keys imported without the TTL they had.
+    expiry_dropped: u64,
+    rejected: u64,
+    first_rejection: Option<String>,
+}
+
+/// A value for humans: quoted if it is printable UTF-8, hex-dumped if not.
+fn pretty(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.chars().any(|c| c.is_control() && c != '\n' && c != '\t') => format!("{text:?}"),
+        _ => {
+            let hex: Vec<String> = value.iter().map(|b| format!("{b:02x}")).collect();
+            format!("(hex, {} bytes) {}", value.len(), hex.join(" "))
+        }
+    }
+}
+
+/// Add `value` to `doc` as `value`, or as `value_base64` if it is not UTF-8.
+fn insert_value(doc: &mut Map<String, Value>, value: &[u8]) {
+    match std::str::from_utf8(value) {
+        Ok(text) => doc.insert("value".into(), json!(text)),
+        Err(_) => doc.insert(
+            "value_base64".into(),
+            json!(base64::engine::general_purpose::STANDARD.encode(value)),
+        ),
+    };
+}
+
+fn print_json(value: Value) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&value)?);
+    Ok(())
+}
+
+/// Reduce an RPC failure to its code and message.
+fn rpc_error(status: Status) -> anyhow::Error {
+    anyhow::anyhow!("{:?}: {}", status.code(), status.message())
+}
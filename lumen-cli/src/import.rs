@@ -0,0 +1,136 @@
+//! Sources for `import`: the keys of another store's files, read on a
+//! blocking thread and handed over a channel, so parsing keeps pace with
+//! the BatchPut calls that load them.
+//!
+//!   lumen       a dump written by `export` (see `archive`)
+//!   redis-rdb   a `dump.rdb` snapshot (see `rdb`); string keys only
+//!   rocksdb     a RocksDB directory, opened read-only; its default column
+//!               family only (needs the `rocksdb` feature)
+
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use tokio::sync::mpsc;
+
+use crate::archive::ArchiveReader;
+use crate::rdb::{RdbReader, Value};
+
+/// Entries read ahead of the loader.
+const READ_AHEAD: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A dump written by `lumen-cli export`.
+    Lumen,
+    /// A Redis RDB snapshot file.
+    RedisRdb,
+    /// A RocksDB database directory.
+    Rocksdb,
+}
+
+/// One key of the source.
+#[derive(Debug)]
+pub enum Entry {
+    Put {
+        key: String,
+        value: Vec<u8>,
+        /// The key had a TTL, which LumenKV cannot keep.
+        expiry_dropped: bool,
+    },
+    /// A key that has no LumenKV equivalent, and why (its Redis type, say).
+    Skipped(String),
+}
+
+/// Start reading `path` as `format`.  Keys of Redis databases other than
+/// `redis_db` are skipped.  A read that fails sends its error last.
+pub fn spawn(format: Format, path: &Path, redis_db: u64) -> mpsc::Receiver<anyhow::Result<Entry>> {
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    let path     = path.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let send = |entry| tx.blocking_send(Ok(entry)).is_ok();
+        let read = match format {
+            Format::Lumen => read_archive(&path, send),
+            Format::RedisRdb => read_rdb(&path, redis_db, send),
+            Format::Rocksdb => read_rocksdb(&path, send),
+        };
+        if let Err(e) = read {
+            let _ = tx.blocking_send(Err(e));
+        }
+    });
+    rx
+}
+
+/// Feed the keys of a dump to `send`, until it returns `false`.
+fn read_archive(path: &Path, mut send: impl FnMut(Entry) -> bool) -> anyhow::Result<()> {
+    let mut reader = ArchiveReader::open(path)?;
+    while let Some((key, value)) = reader.next_entry()? {
+        if !send(Entry::Put { key, value, expiry_dropped: false }) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Feed the keys of an RDB file to `send`, until it returns `false`.
+fn read_rdb(path: &Path, redis_db: u64, mut send: impl FnMut(Entry) -> bool) -> anyhow::Result<()> {
+    let file       = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut reader = RdbReader::new(std::io::BufReader::new(file))?;
+    let now_ms     = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+
+    while let Some(key) = reader.next_key()? {
+        let entry = match (key.value, String::from_utf8(key.key)) {
+            _ if key.db != redis_db => Entry::Skipped(format!("database {}", key.db)),
+            _ if key.expires_at_ms.is_some_and(|at| at <= now_ms) => Entry::Skipped("expired".to_owned()),
+            (Value::Unsupported(kind), _) => Entry::Skipped(kind.to_owned()),
+            (Value::String(_), Err(_)) => Entry::Skipped("non-UTF-8 key".to_owned()),
+            (Value::String(value), Ok(key_str)) => {
+                Entry::Put { key: key_str, value, expiry_dropped: key.expires_at_ms.is_some() }
+            }
+        };
+        if !send(entry) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "rocksdb")]
+fn read_rocksdb(path: &Path, mut send: impl FnMut(Entry) -> bool) -> anyhow::Result<()> {
+    use rocksdb::{IteratorMode, Options, DB, DEFAULT_COLUMN_FAMILY_NAME};
+
+    let options  = Options::default();
+    let families = DB::list_cf(&options, path).with_context(|| format!("failed to open {}", path.display()))?;
+    let db = DB::open_cf_for_read_only(&options, path, &families, false)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+
+    for item in db.iterator(IteratorMode::Start) {
+        let (key, value) = item?;
+        let entry = match String::from_utf8(key.into_vec()) {
+            Ok(key) => Entry::Put { key, value: value.into_vec(), expiry_dropped: false },
+            Err(_) => Entry::Skipped("non-UTF-8 key".to_owned()),
+        };
+        if !send(entry) {
+            return Ok(());
+        }
+    }
+
+    // Other column families are counted, not loaded: LumenKV has one
+    // keyspace, and merging them into it could collide.
+    for name in families.iter().filter(|name| *name != DEFAULT_COLUMN_FAMILY_NAME) {
+        let family = db.cf_handle(name).context("column family listed but not opened")?;
+        for item in db.iterator_cf(&family, IteratorMode::Start) {
+            item?;
+            if !send(Entry::Skipped(format!("column family {name}"))) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "rocksdb"))]
+fn read_rocksdb(_path: &Path, _send: impl FnMut(Entry) -> bool) -> anyhow::Result<()> {
+    anyhow::bail!("this lumen-cli was built without RocksDB support; rebuild it with `--features rocksdb`")
+}
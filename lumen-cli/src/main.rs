@@ -0,0 +1,155 @@
+//! lumen-cli — command-line client for LumenKV.
+//!
+//!   get <KEY>                  print the value; raw bytes, so it can be piped
+//!   put <KEY> [VALUE]          store VALUE, or stdin when it is omitted
+//!   del <KEY>                  delete a key
+//!   mv <KEY> <NEW_KEY>         rename a key in one write (--force replaces NEW_KEY)
+//!   scan [PREFIX]              list keys under PREFIX (through the Scan RPC)
+//!   stats                      role, applied sequence and replication lag
+//!   backup [--destination DIR] coordinated backup through Admin/Backup
+//!   export --output FILE       dump keys (under --prefix) to a portable file
+//!   import --from FORMAT PATH  load a dump, Redis RDB file or RocksDB database
+//!   shell [--file PATH]        interactive shell, or a batch of commands
+//!
+//! `--namespace NS` prefixes every key with `NS/` (and strips it again from
+//! scan output).  `--json` prints one JSON document per command instead of
+//! plain text; values that are not UTF-8 are emitted as `value_base64`.
+//! `get` exits with status 1 when the key does not exist.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+mod archive;
+mod commands;
+mod import;
+mod rdb;
+mod shell;
+
+pub mod kv {
+    tonic::include_proto!("kv");
+}
+
+use commands::{check_namespace, Command, Output, Session};
+
+#[derive(Debug, Parser)]
+#[command(name = "lumen-cli", about = "Read and write a LumenKV store")]
+struct Cli {
+    /// gRPC URL of the node to talk to (`https://` enables TLS).
+    #[arg(long, env = "LUMEN_ADDR", default_value = "http://127.0.0.1:50051", global = true)]
+    addr: String,
+
+    /// Prefix every key with `NAMESPACE/`.
+    #[arg(long, short = 'n', env = "LUMEN_NAMESPACE", global = true)]
+    namespace: Option<String>,
+
+    /// Print JSON instead of plain text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(flatten)]
+    tls: TlsArgs,
+
+    #[command(subcommand)]
+    command: Invocation,
+}
+
+#[derive(Debug, Args)]
+struct TlsArgs {
+    /// Connect over TLS.
+    #[arg(long, global = true)]
+    tls: bool,
+    /// PEM CA certificate to trust instead of the system roots.
+    #[arg(long, global = true, value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+    /// PEM client certificate for mutual TLS (requires --client-key).
+    #[arg(long, global = true, value_name = "PATH", requires = "client_key")]
+    client_cert: Option<PathBuf>,
+    /// PEM private key of --client-cert.
+    #[arg(long, global = true, value_name = "PATH", requires = "client_cert")]
+    client_key: Option<PathBuf>,
+    /// Server name to verify, if it differs from the host in --addr.
+    #[arg(long, global = true, value_name = "NAME")]
+    tls_domain: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Invocation {
+    #[command(flatten)]
+    Run(Command),
+    /// Start an interactive shell (runs commands from stdin when it is not a terminal).
+    Shell {
+        /// Run the commands in this file instead.
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    if let Some(ns) = &cli.namespace {
+        check_namespace(ns).context("invalid --namespace")?;
+    }
+
+    let output = match &cli.command {
+        _ if cli.json => Output::Json,
+        Invocation::Shell { .. } => Output::Pretty,
+        Invocation::Run(_) => Output::Raw,
+    };
+    // Exporting a data directory needs no server.
+    if let Invocation::Run(Command::Export { prefix, output: path, zstd, data_dir: Some(dir) }) = &cli.command {
+        return commands::export_data_dir(dir, cli.namespace.as_deref(), prefix, path, *zstd, output);
+    }
+
+    let channel     = connect(&cli.addr, &cli.tls).await?;
+    let mut session = Session::new(channel, cli.namespace.clone(), output)?;
+
+    match &cli.command {
+        Invocation::Run(command) => {
+            if !session.execute(command).await? {
+                std::process::exit(1);
+            }
+        }
+        Invocation::Shell { file } => shell::run(&mut session, &cli.addr, file.as_deref()).await?,
+    }
+    Ok(())
+}
+
+/// Open a channel to `addr`, over TLS when requested or implied by `https`.
+async fn connect(addr: &str, tls: &TlsArgs) -> anyhow::Result<Channel> {
+    let wants_tls = tls.tls || tls.ca_cert.is_some() || tls.client_cert.is_some() || addr.starts_with("https://");
+
+    // The transport only negotiates TLS for `https` URLs.
+    let url = match addr.strip_prefix("http://") {
+        Some(rest) if wants_tls => format!("https://{rest}"),
+        _ => addr.to_owned(),
+    };
+    let mut endpoint = Endpoint::from_shared(url)
+        .context("--addr must be a URL such as http://127.0.0.1:50051")?;
+
+    if wants_tls {
+        let mut config = ClientTlsConfig::new();
+        if let Some(path) = &tls.ca_cert {
+            let pem = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+            config = config.ca_certificate(Certificate::from_pem(pem));
+        }
+        if let (Some(cert), Some(key)) = (&tls.client_cert, &tls.client_key) {
+            let cert_pem = std::fs::read(cert).with_context(|| format!("failed to read {}", cert.display()))?;
+            let key_pem  = std::fs::read(key).with_context(|| format!("failed to read {}", key.display()))?;
+            config = config.identity(Identity::from_pem(cert_pem, key_pem));
+        }
+        if let Some(domain) = &tls.tls_domain {
+            config = config.domain_name(domain.clone());
+        }
+        endpoint = endpoint.tls_config(config).context("invalid TLS configuration")?;
+    }
+
+    endpoint
+        .connect()
+        .await
+        .with_context(|| format!("failed to connect to {addr}"))
+}
@@ -0,0 +1,433 @@
+//! Reader for Redis RDB snapshots (`dump.rdb`), versions 1 to 12.
+//!
+//! Only string keys have a LumenKV equivalent.  Every other structure
+//! (lists, sets, sorted sets, hashes, streams, module values) is parsed just
+//! far enough to step over it, in each of its encodings, and reported by
+//! type.  Values written by modules of the original, pre-4.0 RDB layout are
+//! not self-describing, and hashes with per-field expiry (Redis 7.4) are
+//! not supported; either ends the read.  The trailing CRC64 (RDB 5+) is
+//! verified unless Redis wrote it as zero (`rdbchecksum no`).
+
+use std::io::Read;
+
+use anyhow::{bail, Context};
+
+const OP_SLOT_INFO:     u8 = 0xF4;
+const OP_FUNCTION2:     u8 = 0xF5;
+const OP_MODULE_AUX:    u8 = 0xF7;
+const OP_IDLE:          u8 = 0xF8;
+const OP_FREQ:          u8 = 0xF9;
+const OP_AUX:           u8 = 0xFA;
+const OP_RESIZE_DB:     u8 = 0xFB;
+const OP_EXPIRETIME_MS: u8 = 0xFC;
+const OP_EXPIRETIME:    u8 = 0xFD;
+const OP_SELECT_DB:     u8 = 0xFE;
+const OP_EOF:           u8 = 0xFF;
+
+/// Encodings of a length's first byte (its top two bits).
+const LEN_6BIT:   u8 = 0;
+const LEN_14BIT:  u8 = 1;
+const LEN_32BIT:  u8 = 0x80;
+const LEN_64BIT:  u8 = 0x81;
+const LEN_ENCVAL: u8 = 3;
+
+/// Special string encodings, after `LEN_ENCVAL`.
+const ENC_INT8:  u64 = 0;
+const ENC_INT16: u64 = 1;
+const ENC_INT32: u64 = 2;
+const ENC_LZF:   u64 = 3;
+
+/// Opcodes of a self-describing module value.
+const MODULE_EOF:    u64 = 0;
+const MODULE_SINT:   u64 = 1;
+const MODULE_UINT:   u64 = 2;
+const MODULE_FLOAT:  u64 = 3;
+const MODULE_DOUBLE: u64 = 4;
+const MODULE_STRING: u64 = 5;
+
+/// Reflected form of the CRC-64/Jones polynomial Redis checksums with.
+const CRC64_POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+
+/// One key of the snapshot.
+#[derive(Debug)]
+pub struct Key {
+    pub db: u64,
+    pub key: Vec<u8>,
+    /// Unix milliseconds the key expires at, if it has a TTL.
+    pub expires_at_ms: Option<u64>,
+    pub value: Value,
+}
+
+#[derive(Debug)]
+pub enum Value {
+    String(Vec<u8>),
+    /// A structure that was stepped over, by its Redis type name.
+    Unsupported(&'static str),
+}
+
+/// Streams the keys of an RDB file.
+pub struct RdbReader<R> {
+    inner: R,
+    crc: u64,
+    table: [u64; 256],
+    /// The file's RDB version; 5 and later end with a checksum.
+    version: u32,
+    db: u64,
+    done: bool,
+}
+
+impl<R: Read> RdbReader<R> {
+    /// Check the `REDISnnnn` header.
+    pub fn new(inner: R) -> anyhow::Result<Self> {
+        let mut reader = Self { inner, crc: 0, table: crc64_table(), version: 0, db: 0, done: false };
+        let header = reader.bytes(9)?;
+        if &header[..5] != b"REDIS" {
+            bail!("not an RDB file: it does not start with REDIS");
+        }
+        reader.version = std::str::from_utf8(&header[5..])
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .with_context(|| format!("invalid RDB version {:?}", String::from_utf8_lossy(&header[5..])))?;
+        if reader.version > 12 {
+            bail!("RDB version {} is newer than this importer understands (12)", reader.version);
+        }
+        Ok(reader)
+    }
+
+    /// The next key, or `None` once the end of the file is reached (and
+    /// its checksum verified).
+    pub fn next_key(&mut self) -> anyhow::Result<Option<Key>> {
+        if self.done {
+            return Ok(None);
+        }
+        let mut expires_at_ms = None;
+        loop {
+            let op = self.u8()?;
+            match op {
+                OP_EOF => {
+                    self.finish()?;
+                    return Ok(None);
+                }
+                OP_SELECT_DB => self.db = self.length()?,
+                OP_RESIZE_DB => {
+                    self.length()?;
+                    self.length()?;
+                }
+                OP_SLOT_INFO => {
+                    for _ in 0..3 {
+                        self.length()?;
+                    }
+                }
+                OP_AUX => {
+                    self.string()?;
+                    self.string()?;
+                }
+                OP_FUNCTION2 => {
+                    self.string()?;
+                }
+                OP_MODULE_AUX => {
+                    self.length()?; // module id
+                    self.length()?; // when opcode
+                    self.length()?; // when
+                    self.skip_module_value()?;
+                }
+                OP_EXPIRETIME_MS => expires_at_ms = Some(self.u64_le()?),
+                OP_EXPIRETIME => expires_at_ms = Some(u64::from(self.u32_le()?) * 1000),
+                OP_IDLE => {
+                    self.length()?;
+                }
+                OP_FREQ => {
+                    self.u8()?;
+                }
+                _ => {
+                    let key   = self.string()?;
+                    let value = self.value(op).with_context(|| {
+                        format!("failed to read the value of {:?}", String::from_utf8_lossy(&key))
+                    })?;
+                    return Ok(Some(Key { db: self.db, key, expires_at_ms, value }));
+                }
+            }
+        }
+    }
+
+    /// Read a value of type `kind`.
+    fn value(&mut self, kind: u8) -> anyhow::Result<Value> {
+        let name = match kind {
+            0 => return Ok(Value::String(self.string()?)),
+            // list, set
+            1 | 2 => {
+                let name = if kind == 1 { "list" } else { "set" };
+                for _ in 0..self.length()? {
+                    self.string()?;
+                }
+                name
+            }
+            // zset, with scores as strings
+            3 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    let len = self.u8()?;
+                    // 253..=255 encode NaN and the infinities in the length.
+                    if len < 253 {
+                        self.bytes(len as usize)?;
+                    }
+                }
+                "zset"
+            }
+            4 => {
+                for _ in 0..self.length()? * 2 {
+                    self.string()?;
+                }
+                "hash"
+            }
+            // zset, with binary scores
+            5 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                    self.bytes(8)?;
+                }
+                "zset"
+            }
+            6 => bail!("module values in the pre-4.0 format cannot be skipped"),
+            7 => {
+                self.length()?; // module id
+                self.skip_module_value()?;
+                "module"
+            }
+            // A structure serialized into a single string: zipmap, ziplist,
+            // intset, or listpack.
+            9 | 13 | 16 => {
+                self.string()?;
+                "hash"
+            }
+            10 => {
+                self.string()?;
+                "list"
+            }
+            11 | 20 => {
+                self.string()?;
+                "set"
+            }
+            12 | 17 => {
+                self.string()?;
+                "zset"
+            }
+            // quicklist of ziplists
+            14 => {
+                for _ in 0..self.length()? {
+                    self.string()?;
+                }
+                "list"
+            }
+            // quicklist of listpacks or plain nodes
+            18 => {
+                for _ in 0..self.length()? {
+                    self.length()?; // container
+                    self.string()?;
+                }
+                "list"
+            }
+            15 | 19 | 21 => {
+                self.skip_stream(kind)?;
+                "stream"
+            }
+            22..=25 => bail!("hashes with field expiry (Redis 7.4) are not supported"),
+            _ => bail!("unknown value type {kind}"),
+        };
+        Ok(Value::Unsupported(name))
+    }
+
+    /// Step over a stream of type `kind` (15, or 19 and 21 for the layouts
+    /// of Redis 7.0 and 7.2).
+    fn skip_stream(&mut self, kind: u8) -> anyhow::Result<()> {
+        for _ in 0..self.length()? {
+            self.string()?; // master ID
+            self.string()?; // listpack
+        }
+        self.length()?; // length
+        self.length()?; // last ID
+        self.length()?;
+        if kind >= 19 {
+            for _ in 0..5 {
+                self.length()?; // first ID, max deleted ID, entries added
+            }
+        }
+        for _ in 0..self.length()? {
+            self.string()?; // group name
+            self.length()?; // last ID
+            self.length()?;
+            if kind >= 19 {
+                self.length()?; // entries read
+            }
+            for _ in 0..self.length()? {
+                self.bytes(16 + 8)?; // ID, delivery time
+                self.length()?; // delivery count
+            }
+            for _ in 0..self.length()? {
+                self.string()?; // consumer name
+                self.bytes(8)?; // seen time
+                if kind >= 21 {
+                    self.bytes(8)?; // active time
+                }
+                for _ in 0..self.length()? {
+                    self.bytes(16)?; // pending ID
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Step over the opcodes of a module value, up to its EOF.
+    fn skip_module_value(&mut self) -> anyhow::Result<()> {
+        loop {
+            match self.length()? {
+                MODULE_EOF => return Ok(()),
+                MODULE_SINT | MODULE_UINT => {
+                    self.length()?;
+                }
+                MODULE_FLOAT => {
+                    self.bytes(4)?;
+                }
+                MODULE_DOUBLE => {
+                    self.bytes(8)?;
+                }
+                MODULE_STRING => {
+                    self.string()?;
+                }
+                op => bail!("unknown module value opcode {op}"),
+            }
+        }
+    }
+
+    /// Verify the checksum after the EOF opcode.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.done = true;
+        if self.version < 5 {
+            return Ok(());
+        }
+        let computed = self.crc;
+        let mut stored = [0u8; 8];
+        self.inner.read_exact(&mut stored).context("RDB file ends before its checksum")?;
+        let stored = u64::from_le_bytes(stored);
+        if stored != 0 && stored != computed {
+            bail!("RDB checksum mismatch: stored {stored:#018x}, computed {computed:#018x}");
+        }
+        Ok(())
+    }
+
+    // ── Primitives ──────────────────────────────────────────────────────────
+
+    fn bytes(&mut self, len: usize) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        (&mut self.inner).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            bail!("RDB file ends in the middle of a record");
+        }
+        for &b in &buf {
+            self.crc = self.table[((self.crc ^ u64::from(b)) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+        Ok(buf)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32_be(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().expect("4 bytes")))
+    }
+
+    fn u32_le(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().expect("4 bytes")))
+    }
+
+    fn u64_le(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().expect("8 bytes")))
+    }
+
+    /// A length, or (`true`) the number of a special string encoding.
+    fn length_or_encoding(&mut self) -> anyhow::Result<(u64, bool)> {
+        let first = self.u8()?;
+        Ok(match (first >> 6, first) {
+            (_, LEN_32BIT) => (u64::from(self.u32_be()?), false),
+            (_, LEN_64BIT) => (u64::from_be_bytes(self.bytes(8)?.try_into().expect("8 bytes")), false),
+            (LEN_6BIT, _) => (u64::from(first & 0x3f), false),
+            (LEN_14BIT, _) => ((u64::from(first & 0x3f) << 8) | u64::from(self.u8()?), false),
+            (LEN_ENCVAL, _) => (u64::from(first & 0x3f), true),
+            _ => bail!("invalid length encoding {first:#04x}"),
+        })
+    }
+
+    fn length(&mut self) -> anyhow::Result<u64> {
+        match self.length_or_encoding()? {
+            (len, false) => Ok(len),
+            (_, true) => bail!("expected a length, found an encoded string"),
+        }
+    }
+
+    fn string(&mut self) -> anyhow::Result<Vec<u8>> {
+        let (len, encoded) = self.length_or_encoding()?;
+        if !encoded {
+            return self.bytes(len as usize);
+        }
+        let int = match len {
+            ENC_INT8 => i64::from(self.u8()? as i8),
+            ENC_INT16 => i64::from(i16::from_le_bytes(self.bytes(2)?.try_into().expect("2 bytes"))),
+            ENC_INT32 => i64::from(self.u32_le()? as i32),
+            ENC_LZF => {
+                let compressed_len = self.length()? as usize;
+                let len            = self.length()? as usize;
+                let compressed     = self.bytes(compressed_len)?;
+                return lzf_decompress(&compressed, len);
+            }
+            _ => bail!("unknown string encoding {len}"),
+        };
+        Ok(int.to_string().into_bytes())
+    }
+}
+
+/// Decompress an LZF block that expands to `len` bytes.
+fn lzf_decompress(input: &[u8], len: usize) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i   = 0;
+    while i < input.len() {
+        let ctrl = usize::from(input[i]);
+        i += 1;
+        if ctrl < 32 {
+            // A run of `ctrl + 1` literal bytes.
+            let run = input.get(i..i + ctrl + 1).context("LZF literal run past the end of the block")?;
+            out.extend_from_slice(run);
+            i += ctrl + 1;
+            continue;
+        }
+        // A back reference.
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += usize::from(*input.get(i).context("truncated LZF back reference")?);
+            i += 1;
+        }
+        let low  = usize::from(*input.get(i).context("truncated LZF back reference")?);
+        i += 1;
+        let back = ((ctrl & 0x1f) << 8) + low + 1;
+        let from = out.len().checked_sub(back).context("LZF back reference before the start of the block")?;
+        for k in 0..run + 2 {
+            out.push(out[from + k]);
+        }
+    }
+    if out.len() != len {
+        bail!("LZF block expanded to {} bytes, expected {len}", out.len());
+    }
+    Ok(out)
+}
+
+fn crc64_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ CRC64_POLY } else { crc >> 1 };
+        }
+        *entry = crc;
+    }
+    table
+}
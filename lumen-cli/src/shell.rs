@@ -0,0 +1,256 @@
+//! Interactive shell: `lumen-cli shell`.
+//!
+//! Each line is one command, split like a POSIX shell line (quotes group
+//! words) and parsed with the same definitions as the one-shot commands,
+//! plus `namespace`, `help` and `exit`.  A line ending in `\` or inside an
+//! open quote continues on the next one.  Values are printed quoted when
+//! they are printable UTF-8 and hex-dumped otherwise.
+//!
+//! On a terminal the shell keeps its history in `~/.lumen_history` and
+//! tab-completes command names.  Otherwise (or with `--file`) it runs the
+//! lines it reads as a batch and fails if any of them did.
+
+use std::io::{BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use clap::{CommandFactory, Parser, Subcommand};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Editor, Helper};
+
+use crate::commands::{check_namespace, Command, Session};
+
+#[derive(Debug, Parser)]
+#[command(multicall = true, help_template = "Commands:\n{subcommands}")]
+struct ShellLine {
+    #[command(subcommand)]
+    command: ShellCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ShellCommand {
+    #[command(flatten)]
+    Kv(Command),
+    /// Show or change the namespace prefixed to keys.
+    Namespace {
+        namespace: Option<String>,
+        /// Stop prefixing keys.
+        #[arg(long, conflicts_with = "namespace")]
+        clear: bool,
+    },
+    /// Leave the shell.
+    #[command(visible_alias = "quit")]
+    Exit,
+}
+
+/// Run the shell against `session`, reading commands from `file`, the
+/// terminal, or piped stdin.
+pub async fn run(session: &mut Session, addr: &str, file: Option<&Path>) -> anyhow::Result<()> {
+    session.read_stdin = false;
+
+    match file {
+        Some(path) => {
+            let file = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+            run_batch(session, std::io::BufReader::new(file)).await
+        }
+        None if std::io::stdin().is_terminal() => run_interactive(session, addr).await,
+        None => run_batch(session, std::io::stdin().lock()).await,
+    }
+}
+
+async fn run_interactive(session: &mut Session, addr: &str) -> anyhow::Result<()> {
+    let mut editor = Editor::<ShellHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ShellHelper::new()));
+
+    let history = history_path();
+    if let Some(path) = &history {
+        // A missing history file just means a first run.
+        let _ = editor.load_history(path);
+    }
+
+    let host = addr.trim_start_matches("http://").trim_start_matches("https://");
+    loop {
+        let prompt = match &session.namespace {
+            Some(ns) => format!("{host}[{ns}]> "),
+            None => format!("{host}> "),
+        };
+        let line = match tokio::task::block_in_place(|| editor.readline(&prompt)) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        match run_line(session, &line).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => eprintln!("{e:#}"),
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("failed to save history to {}: {e}", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Run every command in `input`, reporting failures as they happen.
+async fn run_batch(session: &mut Session, input: impl BufRead) -> anyhow::Result<()> {
+    let mut failed  = 0;
+    let mut pending = String::new();
+    let mut start   = 0;
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line.context("failed to read commands")?;
+        if pending.is_empty() {
+            start = number + 1;
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+        } else {
+            pending.push('\n');
+        }
+        pending.push_str(&line);
+        if !is_complete(&pending) {
+            continue;
+        }
+
+        let command = std::mem::take(&mut pending);
+        match run_line(session, &command).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("line {start}: {e:#}");
+                failed += 1;
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        eprintln!("line {start}: unterminated command");
+        failed += 1;
+    }
+    if failed > 0 {
+        anyhow::bail!("{failed} command(s) failed");
+    }
+    Ok(())
+}
+
+/// Run one command line.  Returns `false` when it ends the session.
+async fn run_line(session: &mut Session, line: &str) -> anyhow::Result<bool> {
+    let words = shlex::split(&line.replace("\\\n", "")).context("unterminated quote")?;
+    if words.is_empty() {
+        return Ok(true);
+    }
+
+    let parsed = match ShellLine::try_parse_from(words) {
+        Ok(parsed) => parsed,
+        Err(e) if !e.use_stderr() => {
+            // `help` and `COMMAND --help`.
+            print!("{e}");
+            return Ok(true);
+        }
+        Err(e) => anyhow::bail!("{}", e.render().to_string().trim_end()),
+    };
+
+    match parsed.command {
+        ShellCommand::Kv(command) => {
+            // A missing key is already reported; it is not an error here.
+            session.execute(&command).await?;
+        }
+        ShellCommand::Namespace { namespace: Some(ns), .. } => {
+            check_namespace(&ns)?;
+            session.namespace = Some(ns);
+        }
+        ShellCommand::Namespace { clear: true, .. } => session.namespace = None,
+        ShellCommand::Namespace { .. } => match &session.namespace {
+            Some(ns) => println!("{ns}"),
+            None => println!("(none)"),
+        },
+        ShellCommand::Exit => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Whether `input` is a whole command rather than the start of one.
+fn is_complete(input: &str) -> bool {
+    !input.ends_with('\\') && shlex::split(&input.replace("\\\n", "")).is_some()
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".lumen_history"))
+}
+
+// ---------------------------------------------------------------------------
+// Line editor
+// ---------------------------------------------------------------------------
+
+/// Completes command names and holds back incomplete lines.
+struct ShellHelper {
+    commands: Vec<String>,
+}
+
+impl ShellHelper {
+    fn new() -> Self {
+        let line = ShellLine::command();
+        let mut commands: Vec<String> = line
+            .get_subcommands()
+            .flat_map(|c| std::iter::once(c.get_name()).chain(c.get_visible_aliases()))
+            .map(str::to_owned)
+            .collect();
+        commands.push("help".to_owned());
+        commands.sort();
+        Self { commands }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let before = &line[..pos];
+        let start  = before.len() - before.trim_start().len();
+        let word   = &before[start..];
+        // Only the command name is completed.
+        if word.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+
+        let matches = self.commands.iter().filter(|c| c.starts_with(word)).cloned().collect();
+        Ok((start, matches))
+    }
+}
+
+impl Validator for ShellHelper {
+    fn validate(&self, ctx: &mut ValidationContext<'_>) -> rustyline::Result<ValidationResult> {
+        Ok(if is_complete(ctx.input()) {
+            ValidationResult::Valid(None)
+        } else {
+            ValidationResult::Incomplete
+        })
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Helper for ShellHelper {}
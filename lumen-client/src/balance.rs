@@ -0,0 +1,202 @@
+//! Load balancing across several server addresses.
+//!
+//! Each address gets its own connection pool.  `PickFirst` sends every call
+//! to the first address, in the order given, that has a healthy connection,
+//! so calls fail over down the list and return once an earlier address
+//! recovers.  `RoundRobin` rotates calls over every healthy address.  When
+//! no address is healthy, calls are still sent (to the first address, or in
+//! rotation) so that a recovering cluster is reached as soon as it is back.
+//!
+//! With `dns_refresh` set, each address names a DNS entry rather than a
+//! single server: its host name is resolved and one pool is kept per
+//! resolved IP.  Names are re-resolved at that interval; pools are opened
+//! for new IPs and closed for IPs that no longer resolve.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+use tonic::transport::Uri;
+use tracing::{info, warn};
+
+use crate::client::ClientError;
+use crate::pool::{Pool, PoolSettings};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LoadBalancing {
+    /// Send every call to the first healthy address.
+    #[default]
+    PickFirst,
+    /// Rotate calls over every healthy address.
+    RoundRobin,
+}
+
+/// The pools a client spreads its calls over.
+#[derive(Debug)]
+pub(crate) struct Endpoints {
+    pools: RwLock<Vec<Arc<Pool>>>,
+    balancing: LoadBalancing,
+    next: AtomicUsize,
+}
+
+impl Endpoints {
+    /// Open a pool per address (or per resolved IP).  Succeeds as long as
+    /// one of them connects; the others are retried by their health checks.
+    pub(crate) async fn open(
+        targets: Vec<String>,
+        balancing: LoadBalancing,
+        settings: PoolSettings,
+        dns_refresh: Option<Duration>,
+    ) -> Result<Arc<Self>, ClientError> {
+        let addrs = match dns_refresh {
+            Some(_) => resolve_all(&targets).await?,
+            None => targets.clone(),
+        };
+        if addrs.is_empty() {
+            return Err(ClientError::NoAddresses);
+        }
+
+        let mut pools       = Vec::with_capacity(addrs.len());
+        let mut connected   = false;
+        let mut first_error = None;
+        for addr in addrs {
+            let endpoint = settings
+                .endpoint(&addr)
+                .map_err(|source| ClientError::InvalidAddress { addr: addr.clone(), source })?;
+            match Pool::open(addr.clone(), endpoint.clone(), &settings).await {
+                Ok(pool) => {
+                    connected = true;
+                    pools.push(pool);
+                }
+                Err(source) => {
+                    warn!(endpoint = %addr, error = %source, "Failed to connect; will keep retrying");
+                    pools.push(Pool::open_lazy(addr.clone(), endpoint, &settings));
+                    first_error.get_or_insert(ClientError::Connect { addr, source });
+                }
+            }
+        }
+        if let (false, Some(error)) = (connected, first_error) {
+            return Err(error);
+        }
+
+        let endpoints = Arc::new(Self {
+            pools: RwLock::new(pools),
+            balancing,
+            next: AtomicUsize::new(0),
+        });
+        if let Some(interval) = dns_refresh {
+            spawn_refresh(&endpoints, targets, settings, interval);
+        }
+        Ok(endpoints)
+    }
+
+    /// `pool` alone, as for a channel the caller made.
+    pub(crate) fn fixed(pool: Arc<Pool>) -> Arc<Self> {
+        Arc::new(Self {
+            pools: RwLock::new(vec![pool]),
+            balancing: LoadBalancing::PickFirst,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// The pool the next call should use.
+    pub(crate) fn pick(&self) -> Arc<Pool> {
+        let pools   = self.pools.read().unwrap_or_else(|e| e.into_inner());
+        let healthy: Vec<&Arc<Pool>> = pools.iter().filter(|pool| pool.is_healthy()).collect();
+        let choices = if healthy.is_empty() { pools.iter().collect() } else { healthy };
+
+        let index = match self.balancing {
+            LoadBalancing::PickFirst => 0,
+            LoadBalancing::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % choices.len(),
+        };
+        choices[index].clone()
+    }
+
+    /// Every pool, in order.
+    pub(crate) fn pools(&self) -> Vec<Arc<Pool>> {
+        self.pools.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Re-resolve `targets` and reconcile the pools with the result.
+    async fn refresh(&self, targets: &[String], settings: &PoolSettings) {
+        let addrs = match resolve_all(targets).await {
+            Ok(addrs) if !addrs.is_empty() => addrs,
+            Ok(_) => {
+                warn!(targets = ?targets, "DNS returned no addresses; keeping the current endpoints");
+                return;
+            }
+            Err(e) => {
+                warn!(error = %e, "DNS refresh failed; keeping the current endpoints");
+                return;
+            }
+        };
+
+        let current = self.pools.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut pools = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            if let Some(pool) = current.iter().find(|pool| pool.addr() == addr) {
+                pools.push(pool.clone());
+                continue;
+            }
+            match settings.endpoint(&addr) {
+                Ok(endpoint) => {
+                    info!(endpoint = %addr, "Discovered endpoint");
+                    pools.push(Pool::open_lazy(addr, endpoint, settings));
+                }
+                Err(e) => warn!(endpoint = %addr, error = %e, "Ignoring unusable endpoint"),
+            }
+        }
+        for pool in &current {
+            if !pools.iter().any(|p| p.addr() == pool.addr()) {
+                info!(endpoint = %pool.addr(), "Endpoint no longer resolves; closing");
+            }
+        }
+
+        *self.pools.write().unwrap_or_else(|e| e.into_inner()) = pools;
+    }
+}
+
+fn spawn_refresh(endpoints: &Arc<Endpoints>, targets: Vec<String>, settings: PoolSettings, interval: Duration) {
+    let endpoints = Arc::downgrade(endpoints);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(endpoints) = endpoints.upgrade() else { break };
+            endpoints.refresh(&targets, &settings).await;
+        }
+    });
+}
+
+/// Every address the targets' host names resolve to, as URLs, in order and
+/// without duplicates.
+async fn resolve_all(targets: &[String]) -> Result<Vec<String>, ClientError> {
+    let mut addrs = Vec::new();
+    for target in targets {
+        let resolved = resolve(target)
+            .await
+            .map_err(|source| ClientError::Resolve { addr: target.clone(), source })?;
+        for addr in resolved {
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+    }
+    Ok(addrs)
+}
+
+async fn resolve(target: &str) -> std::io::Result<Vec<String>> {
+    let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, reason.to_owned());
+
+    let uri: Uri = target.parse().map_err(|_| invalid("not a URL"))?;
+    let scheme   = uri.scheme_str().unwrap_or("http");
+    let host     = uri.host().ok_or_else(|| invalid("URL has no host"))?;
+    let port     = uri.port_u16().unwrap_or(if scheme == "https" { 443 } else { 80 });
+
+    let host     = host.trim_start_matches('[').trim_end_matches(']');
+    let resolved = tokio::net::lookup_host((host, port)).await?;
+    Ok(resolved.map(|addr| format!("{scheme}://{addr}")).collect())
+}
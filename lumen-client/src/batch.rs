@@ -0,0 +1,159 @@
+//! Write batching: coalesce concurrent puts into `BatchPut` calls.
+//!
+//! Puts are queued to a background task, which collects them until the
+//! batch holds `max_entries` puts or `max_bytes` of keys and values, or
+//! `linger` has passed since its first put, and then sends the batch while
+//! it starts collecting the next one.  Each caller's future resolves with
+//! its own entry's result.  A batch is retried (see `RetryPolicy`) only if
+//! every put in it was issued with a request ID.
+//!
+//! While a server lacks `BatchPut` (see `capabilities`), or if a batch is
+//! refused as unimplemented, its puts are sent one `Put` at a time, in
+//! order, instead.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+use tonic::{Code, Status};
+
+use crate::capabilities::BATCH_PUT;
+use crate::client::Transport;
+use crate::kv::{BatchPutRequest, PutRequest};
+
+/// Puts that may wait for the batcher before callers are held up.
+const QUEUE_CAPACITY: usize = 4_096;
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Most puts per batch.
+    pub max_entries: usize,
+    /// Most key and value bytes per batch (a single larger put is sent alone).
+    pub max_bytes: usize,
+    /// Longest a put waits for others to join its batch.
+    pub linger: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 128,
+            max_bytes:   1 << 20,
+            linger:      Duration::from_millis(2),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    entry: PutRequest,
+    retryable: bool,
+    done: oneshot::Sender<Result<(), Status>>,
+}
+
+impl Pending {
+    fn bytes(&self) -> usize {
+        self.entry.key.len() + self.entry.value.len()
+    }
+}
+
+/// Handle to a batching task; the task stops once every handle is dropped.
+#[derive(Debug, Clone)]
+pub(crate) struct Batcher {
+    queue: mpsc::Sender<Pending>,
+}
+
+impl Batcher {
+    pub(crate) fn spawn(transport: Arc<Transport>, config: BatchConfig) -> Self {
+        let (queue, pending) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(collect(transport, config, pending));
+        Self { queue }
+    }
+
+    /// Queue `entry` and wait for the batch carrying it to complete.
+    pub(crate) async fn put(&self, entry: PutRequest, retryable: bool) -> Result<(), Status> {
+        let (done, result) = oneshot::channel();
+        self.queue
+            .send(Pending { entry, retryable, done })
+            .await
+            .map_err(|_| Status::cancelled("write batcher has stopped"))?;
+        match result.await {
+            Ok(result) => result,
+            Err(_) => Err(Status::cancelled("write batch was dropped")),
+        }
+    }
+}
+
+async fn collect(transport: Arc<Transport>, config: BatchConfig, mut pending: mpsc::Receiver<Pending>) {
+    while let Some(first) = pending.recv().await {
+        let deadline  = Instant::now() + config.linger;
+        let mut bytes = first.bytes();
+        let mut batch = vec![first];
+
+        while batch.len() < config.max_entries && bytes < config.max_bytes {
+            let next = match tokio::time::timeout_at(deadline, pending.recv()).await {
+                Ok(Some(next)) => next,
+                Ok(None) | Err(_) => break,
+            };
+            bytes += next.bytes();
+            batch.push(next);
+        }
+
+        tokio::spawn(send(transport.clone(), batch));
+    }
+}
+
+async fn send(transport: Arc<Transport>, batch: Vec<Pending>) {
+    if !transport.supports(BATCH_PUT) {
+        return send_each(&transport, batch).await;
+    }
+
+    let retryable = batch.iter().all(|p| p.retryable);
+    let (entries, waiters): (Vec<_>, Vec<_>) = batch.into_iter().map(|p| (p.entry, (p.retryable, p.done))).unzip();
+    let request = BatchPutRequest { entries };
+
+    let response = transport
+        .call("BatchPut", &request, retryable, |mut kv, request| async move { kv.batch_put(request).await })
+        .await;
+
+    match response {
+        Ok(response) => {
+            transport.observe_write(response.sequence);
+            let mut results = response.results.into_iter();
+            for (_, done) in waiters {
+                let result = match results.next() {
+                    Some(r) if r.code == Code::Ok as i32 => Ok(()),
+                    Some(r) => Err(Status::new(Code::from(r.code), r.message)),
+                    None => Err(Status::internal("BatchPut response is missing an entry")),
+                };
+                let _ = done.send(result);
+            }
+        }
+        Err(status) if status.code() == Code::Unimplemented => {
+            let batch = request
+                .entries
+                .into_iter()
+                .zip(waiters)
+                .map(|(entry, (retryable, done))| Pending { entry, retryable, done })
+                .collect();
+            send_each(&transport, batch).await;
+        }
+        Err(status) => {
+            for (_, done) in waiters {
+                let _ = done.send(Err(status.clone()));
+            }
+        }
+    }
+}
+
+/// Send the puts of `batch` one at a time, for a server without `BatchPut`.
+async fn send_each(transport: &Transport, batch: Vec<Pending>) {
+    for Pending { entry, retryable, done } in batch {
+        let result = transport
+            .call("Put", &entry, retryable, |mut kv, request| async move { kv.put(request).await })
+            .await
+            .map(|response| transport.observe_write(response.sequence));
+        let _ = done.send(result);
+    }
+}
@@ -0,0 +1,129 @@
+//! Capability discovery: what each server serves.
+//!
+//! A fleet upgraded one node at a time runs several server releases at
+//! once, and what a node serves also depends on its role and configuration
+//! (a shard router serves no watches, a multi-region node no atomic
+//! operations).  Each pool asks its server with the `Capabilities` RPC when
+//! it connects, and again whenever a connection recovers from a failed
+//! health check, as the node may have been restarted on another release.
+//! Without health checks, a pool asks once.  A server from before the RPC
+//! answers `UNIMPLEMENTED`; it is taken to speak protocol version 0, with
+//! every feature below but no compression.
+//!
+//! The client adapts to what it learns: batched puts
+//! (`ClientConfig::batching`) are sent one at a time while a server it knows
+//! of lacks `BatchPut`, or when a batch is refused as unimplemented, and
+//! requests are compressed (`ClientConfig::compression`) only to servers
+//! that accept gzip.  A server not heard from yet is assumed to serve every
+//! feature, but not compression.
+
+use std::collections::BTreeSet;
+
+use tonic::Status;
+
+use crate::kv::CapabilitiesResponse;
+
+/// Version of the `KeyValueStore` protocol this client speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// `BatchPut`.
+pub const BATCH_PUT: &str = "batch_put";
+/// `MultiGet`.
+pub const MULTI_GET: &str = "multi_get";
+/// `Scan`.
+pub const SCAN: &str = "scan";
+/// JSON values: `GetField`, `PatchJson` and JSON puts.
+pub const JSON: &str = "json";
+/// Named databases, picked by `ClientConfig::database`.
+pub const DATABASES: &str = "databases";
+/// `Watch`.
+pub const WATCH: &str = "watch";
+/// `QueryIndex`.
+pub const INDEXES: &str = "indexes";
+/// `CompareAndDelete`, `GetAndSet` and `PatchJson`.
+pub const ATOMIC: &str = "atomic";
+/// `Rename`.
+pub const RENAME: &str = "rename";
+/// Leases and the locks taken on them.
+pub const LEASES: &str = "leases";
+/// Interactive transactions (`Session`).
+pub const TRANSACTIONS: &str = "transactions";
+/// Notification channels (`Subscribe`).
+pub const CHANNELS: &str = "channels";
+
+/// The features of a server from before `Capabilities`.
+const LEGACY_FEATURES: &[&str] =
+    &[BATCH_PUT, SCAN, JSON, DATABASES, WATCH, INDEXES, ATOMIC, RENAME, LEASES, TRANSACTIONS, CHANNELS];
+
+/// The gzip encoding, as servers name it.
+pub(crate) const GZIP: &str = "gzip";
+
+/// What a server, or every server a client reaches, serves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub protocol_version: u32,
+    /// Release of the server, e.g. `0.1.0`; empty for a server from before
+    /// protocol version 1, and for servers running different releases.
+    pub server_version: String,
+    /// Optional features served, such as `BATCH_PUT`; names this client
+    /// does not know are kept.
+    pub features: BTreeSet<String>,
+    /// Encodings requests may be compressed with.
+    pub compression: BTreeSet<String>,
+    /// Named databases hosted besides the default one.
+    pub databases: BTreeSet<String>,
+}
+
+impl Capabilities {
+    /// The capabilities a server reported, or those of a server from before
+    /// the RPC if it failed as unimplemented; `None` for other failures.
+    pub(crate) fn of(result: Result<CapabilitiesResponse, Status>) -> Option<Self> {
+        match result {
+            Ok(response) => Some(Self {
+                protocol_version: response.protocol_version,
+                server_version:   response.server_version,
+                features:         response.features.into_iter().collect(),
+                compression:      response.compression.into_iter().collect(),
+                databases:        response.databases.into_iter().collect(),
+            }),
+            Err(status) if status.code() == tonic::Code::Unimplemented => Some(Self::legacy()),
+            Err(_) => None,
+        }
+    }
+
+    fn legacy() -> Self {
+        Self {
+            protocol_version: 0,
+            server_version:   String::new(),
+            features:         LEGACY_FEATURES.iter().map(|&name| name.to_owned()).collect(),
+            compression:      BTreeSet::new(),
+            databases:        BTreeSet::new(),
+        }
+    }
+
+    /// What every server in `servers` serves: the lowest protocol version,
+    /// and the features, encodings and databases they all have.  `None`
+    /// without any.
+    pub(crate) fn common<'a>(servers: impl IntoIterator<Item = &'a Capabilities>) -> Option<Self> {
+        let mut servers = servers.into_iter();
+        let mut common  = servers.next()?.clone();
+        for server in servers {
+            common.protocol_version = common.protocol_version.min(server.protocol_version);
+            if common.server_version != server.server_version {
+                common.server_version.clear();
+            }
+            common.features.retain(|name| server.features.contains(name));
+            common.compression.retain(|name| server.compression.contains(name));
+            common.databases.retain(|name| server.databases.contains(name));
+        }
+        Some(common)
+    }
+
+    pub fn supports(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
+    pub fn accepts_compression(&self, encoding: &str) -> bool {
+        self.compression.contains(encoding)
+    }
+}
@@ -0,0 +1,734 @@
+//! `Client`: connections to one or more LumenKV nodes.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use prost::Message;
+use thiserror::Error;
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::Channel;
+use tonic::{Code, Request, Response, Status};
+use tracing::debug;
+
+use crate::balance::{Endpoints, LoadBalancing};
+use crate::batch::{BatchConfig, Batcher};
+use crate::capabilities::{Capabilities, GZIP, MULTI_GET};
+use crate::instrument::{Instrumentation, RequestEnd, RequestStart};
+use crate::kv::key_value_store_client::KeyValueStoreClient;
+use crate::kv::{
+    CompareAndDeleteRequest, DeleteRequest, ErrorCode, ErrorInfo, GetAndSetRequest, GetFieldRequest, GetRequest,
+    MultiGetRequest, PatchJsonRequest, PutRequest, RenameRequest, RpcStatus, ValueType,
+};
+use crate::lock::Lock;
+use crate::pool::{Pool, PoolSettings};
+use crate::retry::{is_retryable, RetryBudget, RetryHint, RetryPolicy};
+use crate::scan::Scan;
+use crate::watch::Watch;
+
+/// Request metadata carrying the caller's ID for a write.
+pub const REQUEST_ID_HEADER: &str = "x-lumen-request-id";
+
+/// Request metadata carrying the caller's priority class.
+pub const PRIORITY_HEADER: &str = "x-lumen-priority";
+
+/// Request metadata naming the database a request is for.
+pub const DATABASE_HEADER: &str = "x-lumen-database";
+
+type RequestId = MetadataValue<Ascii>;
+
+/// How a server that limits concurrent requests (`QOS_CONCURRENCY`) queues
+/// this client's: while all classes wait, high requests are admitted 8
+/// times as often as background ones, and normal 4 times.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    /// Interactive traffic.
+    High,
+    #[default]
+    Normal,
+    /// Bulk loads and other work that should yield to the rest.
+    Background,
+}
+
+impl Priority {
+    fn header_value(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Background => "background",
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Error type
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Invalid server address {addr}: {source}")]
+    InvalidAddress { addr: String, source: tonic::transport::Error },
+
+    #[error("Failed to connect to {addr}: {source}")]
+    Connect { addr: String, source: tonic::transport::Error },
+
+    #[error("Failed to resolve {addr}: {source}")]
+    Resolve { addr: String, source: std::io::Error },
+
+    #[error("No server addresses to connect to")]
+    NoAddresses,
+
+    #[error("Request ID is not valid metadata: {0:?}")]
+    InvalidRequestId(String),
+
+    #[error("Database name is not valid metadata: {0:?}")]
+    InvalidDatabase(String),
+
+    #[error("Failed to encode or decode a value: {0}")]
+    Codec(crate::typed::CodecError),
+
+    #[error("Failed to render key: {0}")]
+    Key(#[from] serde_json::Error),
+
+    #[error("{:?}: {}", .0.code(), .0.message())]
+    Rpc(#[from] Status),
+}
+
+/// Type URL of the `Any` holding a status's `kv.ErrorInfo`.
+const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/kv.ErrorInfo";
+
+impl ClientError {
+    /// What kind of failure a failed call was: the code the server sent in
+    /// its status details, or one inferred from the gRPC status code if it
+    /// sent none.  `None` for failures that never reached a server.
+    pub fn code(&self) -> Option<ErrorCode> {
+        let ClientError::Rpc(status) = self else { return None };
+        match error_info(status).map(|info| info.code()) {
+            Some(code) if code != ErrorCode::Unspecified => Some(code),
+            _ => Some(inferred_code(status.code())),
+        }
+    }
+
+    /// How long the server asked the caller to wait before retrying, for an
+    /// `ErrorCode::Backpressure` failure.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let ClientError::Rpc(status) = self else { return None };
+        let hint = RetryHint::of(status);
+        (!hint.after.is_zero()).then_some(hint.after)
+    }
+
+    /// Whether the server said the failed call had no effect and may
+    /// succeed if repeated, even if it is not idempotent.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ClientError::Rpc(status) if RetryHint::of(status).safe)
+    }
+}
+
+/// The `kv.ErrorInfo` in `status`'s details, if the server sent one.
+pub(crate) fn error_info(status: &Status) -> Option<ErrorInfo> {
+    let details = RpcStatus::decode(status.details()).ok()?;
+    let info    = details.details.into_iter().find(|any| any.type_url == ERROR_INFO_TYPE_URL)?;
+    ErrorInfo::decode(info.value.as_slice()).ok()
+}
+
+fn inferred_code(code: Code) -> ErrorCode {
+    match code {
+        Code::InvalidArgument | Code::OutOfRange => ErrorCode::InvalidArgument,
+        Code::NotFound => ErrorCode::NotFound,
+        Code::AlreadyExists => ErrorCode::AlreadyExists,
+        Code::FailedPrecondition => ErrorCode::PreconditionFailed,
+        Code::Aborted => ErrorCode::Conflict,
+        Code::ResourceExhausted => ErrorCode::Backpressure,
+        Code::Unavailable | Code::DeadlineExceeded => ErrorCode::Unavailable,
+        Code::DataLoss => ErrorCode::Corruption,
+        _ => ErrorCode::Internal,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Client
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub retry: RetryPolicy,
+    /// Deadline for each attempt; an attempt that exceeds it fails with
+    /// `DEADLINE_EXCEEDED` and may be retried.
+    pub request_timeout: Option<Duration>,
+    pub connect_timeout: Duration,
+    /// HTTP/2 connections to open per server; calls are spread across them.
+    pub connections: usize,
+    /// How calls are spread over several servers.
+    pub load_balancing: LoadBalancing,
+    /// Treat each address as a DNS name, connect to every IP it resolves
+    /// to, and re-resolve at this interval.
+    pub dns_refresh: Option<Duration>,
+    /// How often each connection is probed (`None` disables health checks).
+    pub health_check_interval: Option<Duration>,
+    /// Coalesce puts into `BatchPut` calls (`None` sends each on its own).
+    pub batching: Option<BatchConfig>,
+    /// Hooks told about every call (see `instrument`).
+    pub instrumentation: Option<Arc<dyn Instrumentation>>,
+    /// Send the consistency token of this client's writes with every get,
+    /// so a replica only answers once it has applied them.  Only meaningful
+    /// when every address belongs to one primary or its replicas.
+    pub read_your_writes: bool,
+    /// Class every request is tagged with (see `Priority`).
+    pub priority: Priority,
+    /// Database every request is for, on a server hosting several
+    /// (`DATABASES`); `None` for the server's default one.
+    pub database: Option<String>,
+    /// Compress requests with gzip to servers that accept it (see
+    /// `capabilities`), and accept compressed responses.
+    pub compression: bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            retry:                 RetryPolicy::default(),
+            request_timeout:       Some(Duration::from_secs(10)),
+            connect_timeout:       Duration::from_secs(5),
+            connections:           1,
+            load_balancing:        LoadBalancing::PickFirst,
+            dns_refresh:           None,
+            health_check_interval: Some(Duration::from_secs(5)),
+            batching:              None,
+            instrumentation:       None,
+            read_your_writes:      false,
+            priority:              Priority::Normal,
+            database:              None,
+            compression:           false,
+        }
+    }
+}
+
+/// Cheap to clone; clones share the connections, the retry budget and the
+/// batcher.
+#[derive(Debug, Clone)]
+pub struct Client {
+    transport: Arc<Transport>,
+    batcher: Option<Batcher>,
+}
+
+impl Client {
+    /// Connect to `addr` (e.g. `http://127.0.0.1:50051`) with the default
+    /// configuration.
+    pub async fn connect(addr: impl Into<String>) -> Result<Self, ClientError> {
+        Self::connect_with(addr, ClientConfig::default()).await
+    }
+
+    pub async fn connect_with(addr: impl Into<String>, config: ClientConfig) -> Result<Self, ClientError> {
+        Self::connect_many([addr], config).await
+    }
+
+    /// Connect to several servers and balance calls over them according to
+    /// `config.load_balancing`.
+    pub async fn connect_many<I, S>(addrs: I, config: ClientConfig) -> Result<Self, ClientError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let database = database(&config).map_err(ClientError::InvalidDatabase)?;
+        let settings = PoolSettings {
+            connections:           config.connections,
+            connect_timeout:       config.connect_timeout,
+            request_timeout:       config.request_timeout,
+            health_check_interval: config.health_check_interval,
+            database:              database.clone(),
+        };
+        let targets   = addrs.into_iter().map(Into::into).collect();
+        let endpoints = Endpoints::open(targets, config.load_balancing, settings, config.dns_refresh).await?;
+        Ok(Self::start(endpoints, config, database))
+    }
+
+    /// Send every call over `channel`, made by the caller, e.g. to a server
+    /// in the same process (see `lumen-testing`).  The channel's own
+    /// timeouts apply; the connection, timeout, load balancing, DNS and
+    /// health check settings of `config` are ignored.  Must be called
+    /// within a tokio runtime.
+    #[allow(clippy::result_large_err)]
+    pub fn from_channel(channel: Channel, config: ClientConfig) -> Result<Self, ClientError> {
+        let database = database(&config).map_err(ClientError::InvalidDatabase)?;
+        Ok(Self::start(Endpoints::fixed(Pool::fixed(channel, database.clone())), config, database))
+    }
+
+    fn start(endpoints: Arc<Endpoints>, config: ClientConfig, database: Option<MetadataValue<Ascii>>) -> Self {
+        let transport = Arc::new(Transport {
+            endpoints,
+            budget:           RetryBudget::new(&config.retry),
+            retry:            config.retry,
+            instrumentation:  config.instrumentation,
+            read_your_writes: config.read_your_writes,
+            priority:         config.priority,
+            database,
+            compression:      config.compression,
+            written:          AtomicU64::new(0),
+        });
+        let batcher = config.batching.map(|batching| Batcher::spawn(transport.clone(), batching));
+        Self { transport, batcher }
+    }
+
+    /// The generated gRPC client, for RPCs this type does not wrap.  Calls
+    /// made through it are not retried.
+    pub fn raw(&self) -> KeyValueStoreClient<Channel> {
+        self.transport.connection().2
+    }
+
+    /// What every server this client reaches serves (see `Capabilities`),
+    /// asking those it has not heard from yet.  `None` if none has answered.
+    pub async fn capabilities(&self) -> Option<Capabilities> {
+        let mut known = Vec::new();
+        for pool in self.transport.endpoints.pools() {
+            let capabilities = match pool.capabilities() {
+                Some(capabilities) => Some(capabilities),
+                None => pool.discover().await,
+            };
+            known.extend(capabilities);
+        }
+        Capabilities::common(known.iter().map(|capabilities| &**capabilities))
+    }
+
+    /// Whether every server this client has heard from serves `feature`
+    /// (e.g. `capabilities::WATCH`); true before any has answered.
+    pub fn supports(&self, feature: &str) -> bool {
+        self.transport.supports(feature)
+    }
+
+    /// Value of `key`, or `None` if it does not exist.  Retried.  With
+    /// `read_your_writes`, the read reflects every write this client has
+    /// made.
+    pub async fn get(&self, key: impl Into<String>) -> Result<Option<Vec<u8>>, ClientError> {
+        let min_sequence = if self.transport.read_your_writes { self.consistency_token() } else { 0 };
+        self.get_with_min_sequence(key, min_sequence).await
+    }
+
+    /// Value of `key` as of at least `min_sequence`, e.g. a consistency
+    /// token handed over from another client.  A replica that has not
+    /// applied it yet fails with `UNAVAILABLE` (after waiting briefly),
+    /// which is retried.
+    pub async fn get_with_min_sequence(
+        &self,
+        key: impl Into<String>,
+        min_sequence: u64,
+    ) -> Result<Option<Vec<u8>>, ClientError> {
+        let request = GetRequest { key: key.into(), min_sequence, ..Default::default() };
+        let resp = self
+            .transport
+            .call("Get", &request, true, |mut kv, request| async move { kv.get(request).await })
+            .await?;
+        Ok(resp.found.then_some(resp.value))
+    }
+
+    /// Values of `keys`, in order, `None` for each that does not exist.
+    /// Retried.  Read in one `MultiGet` call, as of one point in time on a
+    /// single node; against a server without `MultiGet` the keys are read
+    /// one `Get` at a time.  Honours `read_your_writes` like `get`.
+    pub async fn multi_get<K: Into<String>>(
+        &self,
+        keys: impl IntoIterator<Item = K>,
+    ) -> Result<Vec<Option<Vec<u8>>>, ClientError> {
+        let keys: Vec<String> = keys.into_iter().map(Into::into).collect();
+        let min_sequence      = if self.transport.read_your_writes { self.consistency_token() } else { 0 };
+        if !self.supports(MULTI_GET) {
+            let mut values = Vec::with_capacity(keys.len());
+            for key in keys {
+                values.push(self.get_with_min_sequence(key, min_sequence).await?);
+            }
+            return Ok(values);
+        }
+
+        let request = MultiGetRequest { keys, min_sequence, ..Default::default() };
+        let resp = self
+            .transport
+            .call("MultiGet", &request, true, |mut kv, request| async move { kv.multi_get(request).await })
+            .await?;
+        Ok(resp.values.into_iter().map(|value| value.found.then_some(value.value)).collect())
+    }
+
+    /// Store `value` under `key`.  Not retried: use `put_with_request_id`
+    /// for a write that may be repeated.  With batching enabled the put
+    /// rides in the next `BatchPut` call.
+    pub async fn put(&self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Result<(), ClientError> {
+        self.write_put(PutRequest { key: key.into(), value: value.into(), ..Default::default() }, None).await
+    }
+
+    /// Store `value` under `key`, retrying transient failures.  Passing a
+    /// request ID declares the write safe to repeat; the ID travels in the
+    /// `x-lumen-request-id` header.  A batch is only retried when every put
+    /// in it carries an ID, and batched IDs are not sent.
+    pub async fn put_with_request_id(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+        request_id: impl Into<String>,
+    ) -> Result<(), ClientError> {
+        let request_id = request_value(request_id.into()).map_err(ClientError::InvalidRequestId)?;
+        let request    = PutRequest { key: key.into(), value: value.into(), ..Default::default() };
+        self.write_put(request, Some(request_id)).await
+    }
+
+    /// Delete `key`; returns whether it existed.  Not retried.
+    pub async fn delete(&self, key: impl Into<String>) -> Result<bool, ClientError> {
+        self.write_delete(DeleteRequest { key: key.into() }, None).await
+    }
+
+    /// Delete `key`, retrying transient failures (see
+    /// `put_with_request_id`).  The server does not deduplicate requests,
+    /// so a delete that was applied before its response was lost reports
+    /// `false` when repeated.
+    pub async fn delete_with_request_id(
+        &self,
+        key: impl Into<String>,
+        request_id: impl Into<String>,
+    ) -> Result<bool, ClientError> {
+        let request_id = request_value(request_id.into()).map_err(ClientError::InvalidRequestId)?;
+        self.write_delete(DeleteRequest { key: key.into() }, Some(request_id)).await
+    }
+
+    /// Delete `key` if its value is `expected`; returns whether it was
+    /// deleted.  Retried: repeating a delete that was applied finds the key
+    /// gone and reports `false`.
+    pub async fn compare_and_delete(
+        &self,
+        key: impl Into<String>,
+        expected: impl Into<Vec<u8>>,
+    ) -> Result<bool, ClientError> {
+        let request = CompareAndDeleteRequest { key: key.into(), expected: expected.into() };
+        let resp = self
+            .transport
+            .call("CompareAndDelete", &request, true, |mut kv, request| async move {
+                kv.compare_and_delete(request).await
+            })
+            .await?;
+        self.transport.observe_write(resp.sequence);
+        Ok(resp.deleted)
+    }
+
+    /// Store `value` under `key` and return the value it replaced, if any.
+    /// Not retried: a repeat would return the value just written.
+    pub async fn get_and_set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, ClientError> {
+        let request = GetAndSetRequest { key: key.into(), value: value.into() };
+        let resp = self
+            .transport
+            .call("GetAndSet", &request, false, |mut kv, request| async move { kv.get_and_set(request).await })
+            .await?;
+        self.transport.observe_write(resp.sequence);
+        Ok(resp.found.then_some(resp.previous))
+    }
+
+    /// Move the value of `key` to `new_key` in one write, replacing a value
+    /// already there only if `overwrite` is set; returns whether it was
+    /// moved.  Retried: repeating a rename that was applied finds `key` gone
+    /// and reports `false`.
+    pub async fn rename(
+        &self,
+        key: impl Into<String>,
+        new_key: impl Into<String>,
+        overwrite: bool,
+    ) -> Result<bool, ClientError> {
+        let request = RenameRequest { key: key.into(), new_key: new_key.into(), overwrite };
+        let resp = self
+            .transport
+            .call("Rename", &request, true, |mut kv, request| async move { kv.rename(request).await })
+            .await?;
+        self.transport.observe_write(resp.sequence);
+        Ok(resp.renamed)
+    }
+
+    /// Store the JSON text `value` under `key`, which the server refuses if
+    /// it does not parse.  Not retried, like `put`.
+    pub async fn put_json(&self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Result<(), ClientError> {
+        let request = PutRequest {
+            key: key.into(),
+            value: value.into(),
+            value_type: ValueType::Json as i32,
+            ..Default::default()
+        };
+        self.write_put(request, None).await
+    }
+
+    /// The field of the JSON value of `key` at JSON Pointer `pointer` (`""`
+    /// for the whole document), as JSON text, or `None` if the key or the
+    /// field does not exist.  Retried.
+    pub async fn get_field(
+        &self,
+        key: impl Into<String>,
+        pointer: impl Into<String>,
+    ) -> Result<Option<Vec<u8>>, ClientError> {
+        let request = GetFieldRequest { key: key.into(), pointer: pointer.into() };
+        let resp = self
+            .transport
+            .call("GetField", &request, true, |mut kv, request| async move { kv.get_field(request).await })
+            .await?;
+        Ok(resp.found.then_some(resp.value))
+    }
+
+    /// Merge the JSON Merge Patch `patch` into the JSON value of `key` on
+    /// the server, and return the patched document.  Retried: applying a
+    /// merge patch again changes nothing more.
+    pub async fn patch_json(
+        &self,
+        key: impl Into<String>,
+        patch: impl Into<Vec<u8>>,
+    ) -> Result<Vec<u8>, ClientError> {
+        let request = PatchJsonRequest { key: key.into(), patch: patch.into() };
+        let resp = self
+            .transport
+            .call("PatchJson", &request, true, |mut kv, request| async move { kv.patch_json(request).await })
+            .await?;
+        self.transport.observe_write(resp.sequence);
+        Ok(resp.value)
+    }
+
+    /// Consistency token covering every write this client (or a clone) has
+    /// completed: pass it to `get_with_min_sequence` to read those writes
+    /// from a replica.  0 before the first write, and through a shard
+    /// router.
+    pub fn consistency_token(&self) -> u64 {
+        self.transport.written.load(Ordering::SeqCst)
+    }
+
+    /// Changes to keys starting with `prefix`, from now on.  The stream
+    /// reconnects after transient failures and resumes where it left off.
+    pub fn watch(&self, prefix: impl Into<String>) -> Watch {
+        self.watch_from(prefix, 0)
+    }
+
+    /// Changes to keys starting with `prefix` committed after sequence
+    /// `from_sequence` (0 = from now on), e.g. to resume a previous watch
+    /// from the last event it delivered.
+    pub fn watch_from(&self, prefix: impl Into<String>, from_sequence: u64) -> Watch {
+        Watch::spawn(self.transport.clone(), prefix.into(), from_sequence)
+    }
+
+    /// The keys starting with `prefix` with their values, in key order,
+    /// read from the servers a page at a time.  The stream resumes after
+    /// transient failures without repeating or skipping a key.
+    pub fn scan(&self, prefix: impl Into<String>) -> Scan {
+        Scan::spawn(self.transport.clone(), prefix.into(), String::new(), String::new())
+    }
+
+    /// Like `scan`, for the keys from `start` (inclusive) up to `end`
+    /// (exclusive); an empty bound leaves that end open.
+    pub fn scan_range(&self, start: impl Into<String>, end: impl Into<String>) -> Scan {
+        Scan::spawn(self.transport.clone(), String::new(), start.into(), end.into())
+    }
+
+    /// Wait until this client holds lock `name`, on a lease of `ttl` (whole
+    /// seconds, at least 1) that is kept alive until the lock is released
+    /// or dropped.
+    pub async fn lock(&self, name: impl Into<String>, ttl: Duration) -> Result<Lock, ClientError> {
+        Lock::acquire(self.transport.clone(), name.into(), ttl).await
+    }
+
+    async fn write_put(&self, request: PutRequest, request_id: Option<RequestId>) -> Result<(), ClientError> {
+        let retry = request_id.is_some();
+        if let Some(batcher) = &self.batcher {
+            return Ok(batcher.put(request, retry).await?);
+        }
+
+        let resp = self
+            .transport
+            .call("Put", &request, retry, |mut kv, request| {
+                let request = with_request_id(request, &request_id);
+                async move { kv.put(request).await }
+            })
+            .await?;
+        self.transport.observe_write(resp.sequence);
+        Ok(())
+    }
+
+    async fn write_delete(&self, request: DeleteRequest, request_id: Option<RequestId>) -> Result<bool, ClientError> {
+        let retry = request_id.is_some();
+        let resp = self
+            .transport
+            .call("Delete", &request, retry, |mut kv, request| {
+                let request = with_request_id(request, &request_id);
+                async move { kv.delete(request).await }
+            })
+            .await?;
+        self.transport.observe_write(resp.sequence);
+        Ok(resp.success)
+    }
+}
+
+/// Connections and retry state, shared by a client's clones and its batcher.
+#[derive(Debug)]
+pub(crate) struct Transport {
+    endpoints: Arc<Endpoints>,
+    retry: RetryPolicy,
+    budget: RetryBudget,
+    instrumentation: Option<Arc<dyn Instrumentation>>,
+    read_your_writes: bool,
+    priority: Priority,
+    database: Option<MetadataValue<Ascii>>,
+    compression: bool,
+    /// Highest consistency token returned for a write.
+    written: AtomicU64,
+}
+
+impl Transport {
+    /// Record the consistency token returned for a write.
+    pub(crate) fn observe_write(&self, sequence: u64) {
+        self.written.fetch_max(sequence, Ordering::SeqCst);
+    }
+
+    /// A connection from the balancer, with the pool and index to report a
+    /// failure against (see `Pool::mark_unhealthy`).
+    pub(crate) fn connection(&self) -> (Arc<Pool>, usize, KeyValueStoreClient<Channel>) {
+        self.connection_to(self.endpoints.pick())
+    }
+
+    /// A connection to `pool`'s server, for calls that must reach the same
+    /// server as the ones before.
+    pub(crate) fn connection_to(&self, pool: Arc<Pool>) -> (Arc<Pool>, usize, KeyValueStoreClient<Channel>) {
+        let (connection, channel) = pool.pick();
+        let kv = self.client(&pool, channel);
+        (pool, connection, kv)
+    }
+
+    /// Whether every server heard from serves `feature`.
+    pub(crate) fn supports(&self, feature: &str) -> bool {
+        self.endpoints
+            .pools()
+            .iter()
+            .all(|pool| pool.capabilities().is_none_or(|capabilities| capabilities.supports(feature)))
+    }
+
+    /// A gRPC client over `channel`, to `pool`'s server, compressing as
+    /// configured and as the server accepts.
+    fn client(&self, pool: &Pool, channel: Channel) -> KeyValueStoreClient<Channel> {
+        let kv = KeyValueStoreClient::new(channel);
+        if !self.compression {
+            return kv;
+        }
+        let kv   = kv.accept_compressed(CompressionEncoding::Gzip);
+        let gzip = pool.capabilities().is_some_and(|capabilities| capabilities.accepts_compression(GZIP));
+        if gzip {
+            kv.send_compressed(CompressionEncoding::Gzip)
+        } else {
+            kv
+        }
+    }
+
+    pub(crate) fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// Send `request` through `attempt` until it succeeds, fails
+    /// permanently, or the retry policy or budget gives up, reporting the
+    /// call as `method` to the instrumentation.  Only `retryable` calls are
+    /// repeated.
+    pub(crate) async fn call<Req, T, F, Fut>(
+        &self,
+        method: &'static str,
+        request: &Req,
+        retryable: bool,
+        attempt: F,
+    ) -> Result<T, Status>
+    where
+        Req: Message + Clone,
+        T: Message,
+        F: FnMut(KeyValueStoreClient<Channel>, Request<Req>) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        let Some(hooks) = &self.instrumentation else {
+            return self.call_with_retries(request, retryable, attempt).await.0;
+        };
+
+        let request_bytes = request.encoded_len();
+        hooks.on_request_start(&RequestStart { method, request_bytes });
+        let started = Instant::now();
+
+        let (result, attempts) = self.call_with_retries(request, retryable, attempt).await;
+
+        hooks.on_request_end(&RequestEnd {
+            method,
+            code:           result.as_ref().map_or_else(|status| status.code(), |_| Code::Ok),
+            latency:        started.elapsed(),
+            attempts,
+            request_bytes,
+            response_bytes: result.as_ref().map_or(0, Message::encoded_len),
+        });
+        result
+    }
+
+    /// `message` as a request tagged with this client's priority class and
+    /// database.
+    pub(crate) fn request<Req>(&self, message: Req) -> Request<Req> {
+        let mut request = Request::new(message);
+        if self.priority != Priority::Normal {
+            let priority = MetadataValue::from_static(self.priority.header_value());
+            request.metadata_mut().insert(PRIORITY_HEADER, priority);
+        }
+        if let Some(database) = &self.database {
+            request.metadata_mut().insert(DATABASE_HEADER, database.clone());
+        }
+        request
+    }
+
+    /// The retry loop behind `call`; also returns the attempts made.
+    async fn call_with_retries<Req, T, F, Fut>(
+        &self,
+        request: &Req,
+        retryable: bool,
+        mut attempt: F,
+    ) -> (Result<T, Status>, u32)
+    where
+        Req: Clone,
+        F: FnMut(KeyValueStoreClient<Channel>, Request<Req>) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Status>>,
+    {
+        self.budget.deposit();
+
+        let mut retries = 0;
+        loop {
+            let pool = self.endpoints.pick();
+            let (connection, channel) = pool.pick();
+            let status = match attempt(self.client(&pool, channel), self.request(request.clone())).await {
+                Ok(response) => return (Ok(response.into_inner()), retries + 1),
+                Err(status) => status,
+            };
+            if status.code() == Code::Unavailable {
+                pool.mark_unhealthy(connection);
+            }
+            let hint    = RetryHint::of(&status);
+            let give_up = !(hint.safe || (retryable && is_retryable(status.code())))
+                || retries + 1 >= self.retry.max_attempts
+                || !self.budget.withdraw();
+            if give_up {
+                return (Err(status), retries + 1);
+            }
+
+            retries += 1;
+            let delay = self.retry.backoff(retries, hint);
+            debug!(code = ?status.code(), retries, delay_ms = delay.as_millis() as u64, "Retrying request");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// The header value of `config`'s database, if it names one.
+fn database(config: &ClientConfig) -> Result<Option<MetadataValue<Ascii>>, String> {
+    config.database.clone().map(request_value).transpose()
+}
+
+/// `request_id` (or a database name) as a header value, if it is
+/// printable ASCII.
+fn request_value(request_id: String) -> Result<RequestId, String> {
+    request_id.parse().map_err(|_| request_id)
+}
+
+fn with_request_id<T>(mut request: Request<T>, request_id: &Option<RequestId>) -> Request<T> {
+    if let Some(id) = request_id {
+        request.metadata_mut().insert(REQUEST_ID_HEADER, id.clone());
+    }
+    request
+}
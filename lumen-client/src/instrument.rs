@@ -0,0 +1,117 @@
+//! Client-side request instrumentation.
+//!
+//! An `Instrumentation` set in `ClientConfig::instrumentation` is told when
+//! each call starts and ends, so applications can observe latency as their
+//! callers see it, separately from the server's own metrics.  A call spans
+//! every attempt the retry policy makes, and a batched put is reported as
+//! the `BatchPut` call carrying it.  Byte counts are protobuf-encoded
+//! message sizes.  Watch streams and health checks are not reported.
+//!
+//! Two implementations are provided: `TracingInstrumentation` logs each
+//! call, and `MetricsInstrumentation` (feature `metrics`) records it through
+//! the `metrics` facade.
+
+use std::fmt;
+use std::time::Duration;
+
+use tonic::Code;
+use tracing::{debug, warn};
+
+/// A call about to be sent.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestStart {
+    /// gRPC method name, e.g. `"Get"`.
+    pub method: &'static str,
+    pub request_bytes: usize,
+}
+
+/// A call that has completed or failed.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestEnd {
+    pub method: &'static str,
+    /// `Code::Ok`, or the code of the error returned to the caller.
+    pub code: Code,
+    /// Time from the start of the first attempt, including retry waits.
+    pub latency: Duration,
+    /// Attempts made, including the first.
+    pub attempts: u32,
+    pub request_bytes: usize,
+    /// Size of the response; 0 when the call failed.
+    pub response_bytes: usize,
+}
+
+/// Hooks called around every unary call a client makes.  They run inline
+/// on the caller's task, so they should return quickly.
+pub trait Instrumentation: fmt::Debug + Send + Sync {
+    fn on_request_start(&self, request: &RequestStart);
+    fn on_request_end(&self, request: &RequestEnd);
+}
+
+/// Logs every completed call at `DEBUG`, and failed ones at `WARN`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingInstrumentation;
+
+impl Instrumentation for TracingInstrumentation {
+    fn on_request_start(&self, _request: &RequestStart) {}
+
+    fn on_request_end(&self, request: &RequestEnd) {
+        let latency_us = request.latency.as_micros() as u64;
+        if request.code == Code::Ok {
+            debug!(
+                method         = request.method,
+                latency_us,
+                attempts       = request.attempts,
+                request_bytes  = request.request_bytes,
+                response_bytes = request.response_bytes,
+                "Request completed"
+            );
+        } else {
+            warn!(
+                method        = request.method,
+                code          = ?request.code,
+                latency_us,
+                attempts      = request.attempts,
+                request_bytes = request.request_bytes,
+                "Request failed"
+            );
+        }
+    }
+}
+
+/// Records calls through the `metrics` facade, labelled by `method` (and
+/// `code` for request counts):
+///
+///   * `lumen_client_requests_in_flight` (gauge)
+///   * `lumen_client_requests_total` (counter)
+///   * `lumen_client_request_duration_seconds` (histogram)
+///   * `lumen_client_retries_total` (counter)
+///   * `lumen_client_request_bytes_total`, `lumen_client_response_bytes_total`
+///     (counters)
+///
+/// The application installs the recorder (e.g. a Prometheus exporter).
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsInstrumentation;
+
+#[cfg(feature = "metrics")]
+impl Instrumentation for MetricsInstrumentation {
+    fn on_request_start(&self, request: &RequestStart) {
+        metrics::gauge!("lumen_client_requests_in_flight", "method" => request.method).increment(1.0);
+    }
+
+    fn on_request_end(&self, request: &RequestEnd) {
+        let method = request.method;
+        let code   = format!("{:?}", request.code);
+
+        metrics::gauge!("lumen_client_requests_in_flight", "method" => method).decrement(1.0);
+        metrics::counter!("lumen_client_requests_total", "method" => method, "code" => code).increment(1);
+        metrics::histogram!("lumen_client_request_duration_seconds", "method" => method)
+            .record(request.latency.as_secs_f64());
+        metrics::counter!("lumen_client_retries_total", "method" => method)
+            .increment(u64::from(request.attempts.saturating_sub(1)));
+        metrics::counter!("lumen_client_request_bytes_total", "method" => method)
+            .increment(request.request_bytes as u64);
+        metrics::counter!("lumen_client_response_bytes_total", "method" => method)
+            .increment(request.response_bytes as u64);
+    }
+}
@@ -0,0 +1,51 @@
+//! Rust client library for LumenKV.
+//!
+//! `Client` wraps the `KeyValueStore` gRPC service, balances calls over one
+//! or more servers and a pool of health-checked connections to each, and
+//! retries transient failures according to a `RetryPolicy`.  Puts can
+//! optionally be coalesced into `BatchPut` calls (`ClientConfig::batching`),
+//! and `TypedClient` layers serde-encoded keys and values on top.
+//! `Client::watch` subscribes to changes under a key prefix as a `Stream`
+//! that resumes from its last event after reconnecting, `Client::scan`
+//! lists a prefix or range in key order a page at a time, and `Client::lock`
+//! takes a distributed lock with a fencing token.  An
+//! `Instrumentation` hook observes each call's method, outcome, latency and
+//! size.  `ClientError::code` tells failures apart by the `ErrorCode` the
+//! server sent with them.  `Client::capabilities` reports what the servers
+//! serve, which the client adapts to while a fleet runs mixed releases.
+//! The generated protobuf types are re-exported under `kv` for callers that
+//! need RPCs not covered here.
+
+pub mod balance;
+pub mod batch;
+pub mod capabilities;
+pub mod client;
+pub mod instrument;
+pub mod lock;
+mod pool;
+pub mod retry;
+pub mod scan;
+pub mod typed;
+pub mod watch;
+
+pub mod kv {
+    tonic::include_proto!("kv");
+}
+
+pub use balance::LoadBalancing;
+pub use batch::BatchConfig;
+pub use capabilities::Capabilities;
+pub use client::{Client, ClientConfig, ClientError, Priority, DATABASE_HEADER, PRIORITY_HEADER, REQUEST_ID_HEADER};
+pub use instrument::{Instrumentation, RequestEnd, RequestStart, TracingInstrumentation};
+pub use kv::ErrorCode;
+pub use lock::Lock;
+#[cfg(feature = "metrics")]
+pub use instrument::MetricsInstrumentation;
+pub use retry::RetryPolicy;
+pub use scan::Scan;
+pub use typed::{Codec, Json, TypedClient};
+pub use watch::{Watch, WatchEvent};
+#[cfg(feature = "bincode")]
+pub use typed::Bincode;
+#[cfg(feature = "msgpack")]
+pub use typed::MessagePack;
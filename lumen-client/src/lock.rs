@@ -0,0 +1,183 @@
+//! Distributed locks: `Client::lock` takes a named lock on the server for a
+//! lease of its own, which a background task keeps alive until the lock is
+//! released or dropped.
+//!
+//! The server hands out a fencing token with each lock, greater than every
+//! token before it.  A holder that stalls for longer than its TTL loses the
+//! lock without knowing it, so storage the lock guards should be sent the
+//! token with each write and refuse tokens lower than the highest it has
+//! seen.  `Lock::is_lost` reports a lease the keep-alive task found gone.
+//!
+//! Locks are served by an unsharded primary; the client's addresses must
+//! lead there.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Code;
+use tracing::{debug, warn};
+
+use crate::client::{ClientError, Transport};
+use crate::kv::{GrantLeaseRequest, KeepAliveRequest, LockRequest, RevokeLeaseRequest, UnlockRequest};
+
+/// How long each `Lock` call waits on the server before asking again.
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+
+/// Keep-alives sent per TTL, so one lost in transit does not end the lease.
+const KEEP_ALIVES_PER_TTL: u32 = 3;
+
+/// A held lock.  Dropping it stops the keep-alives, so the server releases
+/// the lock once the lease's TTL has passed; `unlock` releases it at once.
+#[derive(Debug)]
+pub struct Lock {
+    name: String,
+    fencing_token: u64,
+    lease: u64,
+    transport: Arc<Transport>,
+    lost: Arc<AtomicBool>,
+    keep_alive: JoinHandle<()>,
+}
+
+impl Lock {
+    /// Grant a lease of `ttl`, keep it alive, and wait until it holds lock
+    /// `name`.
+    pub(crate) async fn acquire(transport: Arc<Transport>, name: String, ttl: Duration) -> Result<Self, ClientError> {
+        // Leases are granted in whole seconds.
+        let ttl = Duration::from_secs(ttl.as_secs().max(1));
+        // A retried grant may leave an extra lease behind, which expires
+        // after `ttl` holding nothing.
+        let grant = GrantLeaseRequest { ttl_seconds: ttl.as_secs(), id: 0 };
+        let lease = transport
+            .call("GrantLease", &grant, true, |mut kv, request| async move { kv.grant_lease(request).await })
+            .await?;
+
+        let lost       = Arc::new(AtomicBool::new(false));
+        let keep_alive = tokio::spawn(keep_alive(transport.clone(), lease.id, ttl, lost.clone()));
+
+        let request = LockRequest { name: name.clone(), lease: lease.id, wait_ms: LOCK_WAIT.as_millis() as u64 };
+        let fencing_token = loop {
+            // Locking again for the same lease returns the same token, so
+            // the call is safe to retry.
+            let locked = transport
+                .call("Lock", &request, true, |mut kv, request| async move { kv.lock(request).await })
+                .await;
+            match locked {
+                Ok(resp) => break resp.fencing_token,
+                Err(status) if status.code() == Code::Aborted => debug!(lock = %name, "Lock still held; waiting"),
+                Err(status) => {
+                    keep_alive.abort();
+                    revoke(&transport, lease.id).await;
+                    return Err(status.into());
+                }
+            }
+        };
+
+        Ok(Self { name, fencing_token, lease: lease.id, transport, lost, keep_alive })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Token to send with each write the lock guards.
+    pub fn fencing_token(&self) -> u64 {
+        self.fencing_token
+    }
+
+    /// The lease holding the lock.
+    pub fn lease(&self) -> u64 {
+        self.lease
+    }
+
+    /// Whether the lease was found expired, so the lock may be held by
+    /// someone else.
+    pub fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::SeqCst)
+    }
+
+    /// Release the lock and end its lease.  Returns whether the lock was
+    /// still held.
+    pub async fn unlock(self) -> Result<bool, ClientError> {
+        self.keep_alive.abort();
+        let request = UnlockRequest { name: self.name.clone(), fencing_token: self.fencing_token };
+        let released = self
+            .transport
+            .call("Unlock", &request, true, |mut kv, request| async move { kv.unlock(request).await })
+            .await;
+        revoke(&self.transport, self.lease).await;
+        Ok(released?.released)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        self.keep_alive.abort();
+    }
+}
+
+/// Renew `lease` until aborted, reopening the stream when it breaks.  Sets
+/// `lost` and stops once the server reports the lease gone, or no renewal
+/// has succeeded for a whole TTL.
+///
+/// The requests are sent from this task, so aborting it ends the request
+/// stream (and the server's side of it) too.
+async fn keep_alive(transport: Arc<Transport>, lease: u64, ttl: Duration, lost: Arc<AtomicBool>) {
+    let period      = ttl / KEEP_ALIVES_PER_TTL;
+    let mut renewed = Instant::now();
+    loop {
+        let (_, _, mut kv) = transport.connection();
+        let (tx, rx)  = mpsc::channel(1);
+        let mut ticks = tokio::time::interval(period);
+        match kv.keep_alive(ReceiverStream::new(rx)).await {
+            Ok(response) => {
+                let mut stream = response.into_inner();
+                loop {
+                    tokio::select! {
+                        _ = ticks.tick() => {
+                            if tx.send(KeepAliveRequest { id: lease }).await.is_err() {
+                                break;
+                            }
+                        }
+                        message = stream.message() => match message {
+                            Ok(Some(resp)) if resp.ttl_seconds == 0 => {
+                                warn!(lease, "Lease expired; lock lost");
+                                lost.store(true, Ordering::SeqCst);
+                                return;
+                            }
+                            Ok(Some(_)) => renewed = Instant::now(),
+                            Ok(None) => break,
+                            Err(status) => {
+                                debug!(lease, code = ?status.code(), "Keep-alive stream broken");
+                                break;
+                            }
+                        },
+                    }
+                }
+            }
+            Err(status) => debug!(lease, code = ?status.code(), "Keep-alive stream failed"),
+        }
+
+        if renewed.elapsed() >= ttl {
+            warn!(lease, "No keep-alive succeeded for a whole TTL; lock lost");
+            lost.store(true, Ordering::SeqCst);
+            return;
+        }
+        tokio::time::sleep(period).await;
+    }
+}
+
+/// End `lease`, releasing anything it still holds.  Best effort: a lease
+/// left behind expires on its own.
+async fn revoke(transport: &Transport, lease: u64) {
+    let request = RevokeLeaseRequest { id: lease };
+    let revoked = transport
+        .call("RevokeLease", &request, false, |mut kv, request| async move { kv.revoke_lease(request).await })
+        .await;
+    if let Err(status) = revoked {
+        debug!(lease, code = ?status.code(), "Failed to revoke lease");
+    }
+}
@@ -0,0 +1,274 @@
+//! Connection pool: several HTTP/2 connections to one endpoint.
+//!
+//! A tonic `Channel` is a single HTTP/2 connection, and the server caps how
+//! many streams one connection carries at once, so a busy client opens
+//! `connections` of them and spreads calls across them round-robin.
+//!
+//! With health checking enabled, a connection whose call fails with
+//! `UNAVAILABLE` is skipped until the next check.  Every check probes each
+//! connection with `ReplicationStatus`; a connection that fails is replaced
+//! by a fresh one (dialled on first use) and stays skipped until a check
+//! succeeds.  When no connection is healthy, calls are spread over all of
+//! them rather than failing outright.
+//!
+//! A pool over a channel the caller made (`Client::from_channel`) holds
+//! just that one, and is never checked, as it cannot be redialled.
+//!
+//! Each pool also keeps what its server serves (see `capabilities`), asked
+//! when the pool opens and again when a connection recovers.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::time::MissedTickBehavior;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+use tracing::{info, warn};
+
+use crate::capabilities::{Capabilities, PROTOCOL_VERSION};
+use crate::client::DATABASE_HEADER;
+use crate::kv::key_value_store_client::KeyValueStoreClient;
+use crate::kv::{CapabilitiesRequest, ReplicationStatusRequest};
+
+/// How long a health probe may take before the connection counts as down.
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Connection {
+    channel: RwLock<Channel>,
+    healthy: AtomicBool,
+}
+
+impl Connection {
+    fn channel(&self) -> Channel {
+        self.channel.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Connection settings shared by every pool of a client.
+#[derive(Debug, Clone)]
+pub(crate) struct PoolSettings {
+    pub(crate) connections: usize,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) health_check_interval: Option<Duration>,
+    /// Database the client's requests are for, whose capabilities are asked.
+    pub(crate) database: Option<MetadataValue<Ascii>>,
+}
+
+impl PoolSettings {
+    /// The endpoint for `addr` with these settings applied.
+    pub(crate) fn endpoint(&self, addr: &str) -> Result<Endpoint, tonic::transport::Error> {
+        let mut endpoint = Endpoint::from_shared(addr.to_owned())?.connect_timeout(self.connect_timeout);
+        if let Some(timeout) = self.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        Ok(endpoint)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Pool {
+    addr: String,
+    /// Where failed connections are redialled (`None`: a channel the caller
+    /// made).
+    endpoint: Option<Endpoint>,
+    connections: Vec<Connection>,
+    next: AtomicUsize,
+    /// Whether health checks run; without them nothing is ever skipped.
+    checked: bool,
+    database: Option<MetadataValue<Ascii>>,
+    /// What the server serves; `None` until it has said.
+    capabilities: RwLock<Option<Arc<Capabilities>>>,
+}
+
+impl Pool {
+    /// Open the connections to `addr`, failing if any cannot be made.
+    pub(crate) async fn open(
+        addr: String,
+        endpoint: Endpoint,
+        settings: &PoolSettings,
+    ) -> Result<Arc<Self>, tonic::transport::Error> {
+        let mut channels = Vec::with_capacity(settings.connections.max(1));
+        for _ in 0..settings.connections.max(1) {
+            channels.push(endpoint.connect().await?);
+        }
+        let pool = Self::start(addr, endpoint, channels, true, settings);
+        pool.discover().await;
+        Ok(pool)
+    }
+
+    /// A pool whose connections are dialled on first use.  It counts as
+    /// unhealthy until a health check reaches it.
+    pub(crate) fn open_lazy(addr: String, endpoint: Endpoint, settings: &PoolSettings) -> Arc<Self> {
+        let channels = (0..settings.connections.max(1)).map(|_| endpoint.connect_lazy()).collect();
+        let healthy  = settings.health_check_interval.is_none();
+        let pool     = Self::start(addr, endpoint, channels, healthy, settings);
+        if healthy {
+            // Otherwise the first health check to succeed asks.
+            spawn_discovery(&pool);
+        }
+        pool
+    }
+
+    /// A pool of `channel` alone, made by the caller, for requests to
+    /// `database`.
+    pub(crate) fn fixed(channel: Channel, database: Option<MetadataValue<Ascii>>) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            addr:         "channel".to_owned(),
+            endpoint:     None,
+            connections:  vec![Connection { channel: RwLock::new(channel), healthy: AtomicBool::new(true) }],
+            next:         AtomicUsize::new(0),
+            checked:      false,
+            database,
+            capabilities: RwLock::new(None),
+        });
+        spawn_discovery(&pool);
+        pool
+    }
+
+    fn start(
+        addr: String,
+        endpoint: Endpoint,
+        channels: Vec<Channel>,
+        healthy: bool,
+        settings: &PoolSettings,
+    ) -> Arc<Self> {
+        let connections = channels
+            .into_iter()
+            .map(|channel| Connection {
+                channel: RwLock::new(channel),
+                healthy: AtomicBool::new(healthy),
+            })
+            .collect();
+        let pool = Arc::new(Self {
+            addr,
+            endpoint: Some(endpoint),
+            connections,
+            next: AtomicUsize::new(0),
+            checked: settings.health_check_interval.is_some(),
+            database: settings.database.clone(),
+            capabilities: RwLock::new(None),
+        });
+        if let Some(interval) = settings.health_check_interval {
+            spawn_health_checks(&pool, interval);
+        }
+        pool
+    }
+
+    pub(crate) fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Whether any connection passed its last health check.
+    pub(crate) fn is_healthy(&self) -> bool {
+        self.connections.iter().any(|c| c.healthy.load(Ordering::Relaxed))
+    }
+
+    /// The next connection to use, preferring healthy ones, with its index.
+    pub(crate) fn pick(&self) -> (usize, Channel) {
+        let size  = self.connections.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let index = (0..size)
+            .map(|i| (start + i) % size)
+            .find(|&i| self.connections[i].healthy.load(Ordering::Relaxed))
+            .unwrap_or(start % size);
+        (index, self.connections[index].channel())
+    }
+
+    /// What the server last said it serves, if it has.
+    pub(crate) fn capabilities(&self) -> Option<Arc<Capabilities>> {
+        self.capabilities.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Ask the server what it serves, keeping what it said before if the
+    /// call fails.
+    pub(crate) async fn discover(&self) -> Option<Arc<Capabilities>> {
+        let mut kv      = KeyValueStoreClient::new(self.pick().1);
+        let mut request = Request::new(CapabilitiesRequest { protocol_version: PROTOCOL_VERSION });
+        if let Some(database) = &self.database {
+            request.metadata_mut().insert(DATABASE_HEADER, database.clone());
+        }
+        let result = match tokio::time::timeout(HEALTH_TIMEOUT, kv.capabilities(request)).await {
+            Ok(result) => result.map(tonic::Response::into_inner),
+            Err(_) => return self.capabilities(),
+        };
+        let Some(discovered) = Capabilities::of(result) else { return self.capabilities() };
+
+        let discovered = Arc::new(discovered);
+        let previous   = self.capabilities.write().unwrap_or_else(|e| e.into_inner()).replace(discovered.clone());
+        if previous.as_deref() != Some(&*discovered) {
+            info!(
+                endpoint         = %self.addr,
+                protocol_version = discovered.protocol_version,
+                server_version   = %discovered.server_version,
+                features         = ?discovered.features,
+                "Discovered server capabilities"
+            );
+        }
+        Some(discovered)
+    }
+
+    /// Skip connection `index` until the next successful health check.
+    pub(crate) fn mark_unhealthy(&self, index: usize) {
+        if self.checked {
+            self.connections[index].healthy.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Probe every connection, replacing the ones that fail, and ask the
+    /// server what it serves again once one recovers.
+    async fn check(&self) {
+        let mut recovered = false;
+        for (index, conn) in self.connections.iter().enumerate() {
+            let mut kv = KeyValueStoreClient::new(conn.channel());
+            let probe  = tokio::time::timeout(HEALTH_TIMEOUT, kv.replication_status(ReplicationStatusRequest {}));
+            let ok     = matches!(probe.await, Ok(Ok(_)));
+
+            let was_healthy = conn.healthy.swap(ok, Ordering::Relaxed);
+            if !ok {
+                if was_healthy {
+                    warn!(
+                        endpoint   = %self.addr,
+                        connection = index,
+                        "Connection failed its health check; reconnecting"
+                    );
+                }
+                if let Some(endpoint) = &self.endpoint {
+                    *conn.channel.write().unwrap_or_else(|e| e.into_inner()) = endpoint.connect_lazy();
+                }
+            } else if !was_healthy {
+                info!(endpoint = %self.addr, connection = index, "Connection healthy again");
+                recovered = true;
+            }
+        }
+        if recovered || (self.is_healthy() && self.capabilities().is_none()) {
+            self.discover().await;
+        }
+    }
+}
+
+/// Ask `pool`'s server what it serves, in the background.
+fn spawn_discovery(pool: &Arc<Pool>) {
+    let pool = pool.clone();
+    tokio::spawn(async move {
+        pool.discover().await;
+    });
+}
+
+/// Check `pool` every `interval` until it is dropped.
+fn spawn_health_checks(pool: &Arc<Pool>, interval: Duration) {
+    let pool = Arc::downgrade(pool);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let Some(pool) = pool.upgrade() else { break };
+            pool.check().await;
+        }
+    });
+}
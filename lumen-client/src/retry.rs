@@ -0,0 +1,128 @@
+//! Retry policy for transient RPC failures.
+//!
+//! Only `UNAVAILABLE` and `DEADLINE_EXCEEDED` are retried, and only for calls
+//! that are safe to repeat: reads, and writes that carry a request ID.  The
+//! wait before retry *n* is drawn uniformly from
+//! `[0, min(initial_backoff * 2^(n-1), max_backoff)]` ("full jitter"), so
+//! clients that failed together do not retry together.
+//!
+//! The server's `kv.ErrorInfo` can say more (see `RetryHint`): a failure it
+//! marks `retryable` had no effect, so it is retried whatever its status code
+//! and even for a write without a request ID, and a `retry_after_ms` it
+//! sends is the least the client waits, so a throttled client backs off as
+//! long as the server asks instead of hammering it.
+//!
+//! A client-wide *retry budget* caps retry traffic at a fraction of the
+//! calls made: every call deposits `budget_ratio` tokens (up to
+//! `budget_burst`) and every retry spends one.  When a server is down, the
+//! budget runs dry and calls fail fast instead of multiplying the load.
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tonic::{Code, Status};
+
+use crate::client::error_info;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts per call, including the first; 1 disables retries.
+    pub max_attempts: u32,
+    /// Upper bound of the first retry's wait.
+    pub initial_backoff: Duration,
+    /// Upper bound of any retry's wait.
+    pub max_backoff: Duration,
+    /// Retries each call earns, e.g. 0.1 = one retry per ten calls.
+    pub budget_ratio: f64,
+    /// Retries the budget can save up (and starts with).
+    pub budget_burst: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts:    4,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff:     Duration::from_secs(2),
+            budget_ratio:    0.1,
+            budget_burst:    10,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn disabled() -> Self {
+        Self { max_attempts: 1, ..Self::default() }
+    }
+
+    /// Wait before retry number `retry` (starting at 1) after a failure
+    /// the server hinted `hint` for: never less than it asked for.
+    pub(crate) fn backoff(&self, retry: u32, hint: RetryHint) -> Duration {
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        ceiling.mul_f64(rand::random::<f64>()).max(hint.after)
+    }
+}
+
+/// Failures that may succeed if the call is repeated.
+pub(crate) fn is_retryable(code: Code) -> bool {
+    matches!(code, Code::Unavailable | Code::DeadlineExceeded)
+}
+
+/// What the server said about repeating a failed call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct RetryHint {
+    /// The call had no effect and may succeed if repeated, so it is safe to
+    /// retry even if it is not idempotent.
+    pub(crate) safe: bool,
+    /// The least to wait before repeating it.
+    pub(crate) after: Duration,
+}
+
+impl RetryHint {
+    /// The hint in `status`'s details; the default if it carries none.
+    pub(crate) fn of(status: &Status) -> Self {
+        error_info(status).map_or_else(Self::default, |info| Self {
+            safe:  info.retryable,
+            after: Duration::from_millis(info.retry_after_ms),
+        })
+    }
+}
+
+/// Token bucket shared by every clone of a client.
+#[derive(Debug)]
+pub(crate) struct RetryBudget {
+    ratio: f64,
+    burst: f64,
+    tokens: Mutex<f64>,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(policy: &RetryPolicy) -> Self {
+        let burst = f64::from(policy.budget_burst);
+        Self {
+            ratio: policy.budget_ratio,
+            burst,
+            tokens: Mutex::new(burst),
+        }
+    }
+
+    /// Credit the budget for a new call.
+    pub(crate) fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        *tokens = (*tokens + self.ratio).min(self.burst);
+    }
+
+    /// Spend one retry, if the budget allows it.
+    pub(crate) fn withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap_or_else(|e| e.into_inner());
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,200 @@
+//! Ordered key listings: the `Scan` RPC as a stream across pages.
+//!
+//! A background task asks the server for `PAGE` keys at a time and forwards
+//! each entry, following the cursor each page ends with, so a listing is
+//! never held whole on either side however many keys it covers.  Entries
+//! arrive in key order.  Each page is read from a snapshot of its own, so a
+//! page sees every key as of one point, but keys written between pages may
+//! or may not be listed.
+//!
+//! Cursors live on the server that handed them out, so every page is asked
+//! of the server the first one came from while it answers.  When a stream
+//! breaks with a transient error, the page is fetched again and the keys
+//! already delivered are skipped; when its cursor is gone (the server
+//! restarted, the scan moved to another endpoint, or it sat unused for the
+//! server's `SCAN_CURSOR_TTL_SECS`), the scan starts over after the last key
+//! delivered.  Either way no key is delivered twice or skipped.  Retries
+//! back off per the client's `RetryPolicy`, up to `max_attempts` failures in
+//! a row.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tonic::{Code, Status};
+use tracing::{debug, warn};
+
+use crate::client::{ClientError, Transport};
+use crate::kv::ScanRequest;
+use crate::pool::Pool;
+use crate::retry::{is_retryable, RetryHint};
+use crate::watch::is_broken;
+
+/// Keys asked for per page.
+const PAGE: u32 = 1000;
+
+/// Entries buffered ahead of a slow consumer before the stream stalls.
+const BUFFER: usize = 256;
+
+/// Stream of the keys of a range with their values, in key order, ending
+/// after the last or the first permanent error.  Dropping it cancels the
+/// scan.
+#[derive(Debug)]
+pub struct Scan {
+    entries: mpsc::Receiver<Result<(String, Vec<u8>), ClientError>>,
+}
+
+impl Scan {
+    /// List the keys under `prefix` from `start` (inclusive) up to `end`
+    /// (exclusive), each left open when empty.
+    pub(crate) fn spawn(transport: Arc<Transport>, prefix: String, start: String, end: String) -> Self {
+        let (tx, rx) = mpsc::channel(BUFFER);
+        let request = ScanRequest { prefix, start, end, limit: PAGE, ..Default::default() };
+        tokio::spawn(run(transport, request, tx));
+        Self { entries: rx }
+    }
+
+    /// The next entry, or `None` once the scan has ended.
+    pub async fn next(&mut self) -> Option<Result<(String, Vec<u8>), ClientError>> {
+        self.entries.recv().await
+    }
+}
+
+impl Stream for Scan {
+    type Item = Result<(String, Vec<u8>), ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.entries.poll_recv(cx)
+    }
+}
+
+type Entries = mpsc::Sender<Result<(String, Vec<u8>), ClientError>>;
+
+async fn run(transport: Arc<Transport>, mut request: ScanRequest, tx: Entries) {
+    // The last key delivered, and the server holding the scan's cursor.
+    let mut last: Option<String> = None;
+    let mut server: Option<Arc<Pool>> = None;
+    let mut failures = 0u32;
+    loop {
+        let status = match page(&transport, &mut server, &request, &mut last, &mut failures, &tx).await {
+            Ok(Some(cursor)) => {
+                request.cursor = cursor;
+                continue;
+            }
+            Ok(None) => return,
+            Err(status) => status,
+        };
+
+        if status.code() == Code::NotFound && !request.cursor.is_empty() {
+            debug!(cursor = %request.cursor, "Scan cursor is gone; starting over after the last key");
+            restart(&mut request, &last);
+            continue;
+        }
+
+        let policy = transport.retry_policy();
+        failures = failures.saturating_add(1);
+        if !is_transient(&status) || failures >= policy.max_attempts {
+            let _ = tx.send(Err(status.into())).await;
+            return;
+        }
+        if request.cursor.is_empty() {
+            restart(&mut request, &last);
+        }
+
+        let delay = policy.backoff(failures, RetryHint::of(&status));
+        warn!(
+            code     = ?status.code(),
+            error    = %status.message(),
+            cursor   = %request.cursor,
+            delay_ms = delay.as_millis() as u64,
+            "Scan stream broken; resuming"
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+/// Scan afresh from the last key delivered, which is skipped.
+fn restart(request: &mut ScanRequest, last: &Option<String>) {
+    request.cursor.clear();
+    if let Some(last) = last {
+        request.start = last.clone();
+    }
+}
+
+/// Forward one page until it ends or fails.  `Ok` carries the cursor of the
+/// next page, or `None` once the scan or its consumer is done.
+async fn page(
+    transport: &Transport,
+    server: &mut Option<Arc<Pool>>,
+    request: &ScanRequest,
+    last: &mut Option<String>,
+    failures: &mut u32,
+    tx: &Entries,
+) -> Result<Option<String>, Status> {
+    let (pool, connection, mut kv) = match server.take() {
+        Some(pool) => transport.connection_to(pool),
+        None => transport.connection(),
+    };
+    debug!(addr = pool.addr(), cursor = %request.cursor, "Opening scan stream");
+
+    let opened = tokio::select! {
+        opened = kv.scan(transport.request(request.clone())) => opened,
+        _ = tx.closed() => return Ok(None),
+    };
+    let mut stream = match opened {
+        Ok(response) => response.into_inner(),
+        Err(status) => {
+            if status.code() == Code::Unavailable {
+                pool.mark_unhealthy(connection);
+            } else {
+                *server = Some(pool);
+            }
+            return Err(status);
+        }
+    };
+
+    loop {
+        let message = tokio::select! {
+            message = stream.message() => message,
+            _ = tx.closed() => return Ok(None),
+        };
+        match message {
+            Ok(Some(response)) => {
+                *failures = 0;
+                for entry in response.entries {
+                    // Delivered before the page was fetched again.
+                    if last.as_ref().is_some_and(|last| entry.key <= *last) {
+                        continue;
+                    }
+                    *last = Some(entry.key.clone());
+                    if tx.send(Ok((entry.key, entry.value))).await.is_err() {
+                        return Ok(None);
+                    }
+                }
+                if !response.cursor.is_empty() {
+                    *server = Some(pool);
+                    return Ok(Some(response.cursor));
+                }
+            }
+            Ok(None) => return Ok(None),
+            Err(status) => {
+                if status.code() == Code::Unavailable || is_broken(&status) {
+                    pool.mark_unhealthy(connection);
+                } else {
+                    *server = Some(pool);
+                }
+                return Err(status);
+            }
+        }
+    }
+}
+
+/// Failures a scan recovers from by fetching its page again.
+fn is_transient(status: &Status) -> bool {
+    is_retryable(status.code()) || is_broken(status) || RetryHint::of(status).safe
+}
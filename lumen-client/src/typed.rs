@@ -0,0 +1,146 @@
+//! `TypedClient`: keys and values as Rust types instead of bytes.
+//!
+//! Values are encoded with a `Codec`: `Json` always, `Bincode` with the
+//! `bincode` feature and `MessagePack` with the `msgpack` feature.  Keys
+//! must be strings on the wire, so they are always rendered as JSON text,
+//! except that a key serializing to a plain string is used as is: a
+//! `TypedClient<String, _>` addresses the same keys as `Client`, and a
+//! `u64` key 42 is stored under `"42"`.
+
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::client::{Client, ClientError};
+
+/// Failure reported by a codec.
+pub type CodecError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Turns values into the bytes stored by the server and back.
+pub trait Codec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError>;
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+/// UTF-8 JSON, readable by any other client.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Compact bincode 1.x encoding; only readable with the same Rust types.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// MessagePack with named struct fields, so fields can be added later.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// Marks the types a `TypedClient` works with, without owning any.
+type Types<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+/// A `Client` that stores `V`s under `K`s, encoding values with `C`.
+pub struct TypedClient<K, V, C = Json> {
+    client: Client,
+    types: Types<K, V, C>,
+}
+
+impl<K, V, C> TypedClient<K, V, C>
+where
+    K: Serialize,
+    V: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    pub fn new(client: Client) -> Self {
+        Self { client, types: PhantomData }
+    }
+
+    /// The untyped client underneath.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub async fn get(&self, key: &K) -> Result<Option<V>, ClientError> {
+        match self.client.get(render_key(key)?).await? {
+            Some(bytes) => C::decode(&bytes).map(Some).map_err(ClientError::Codec),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn put(&self, key: &K, value: &V) -> Result<(), ClientError> {
+        let value = C::encode(value).map_err(ClientError::Codec)?;
+        self.client.put(render_key(key)?, value).await
+    }
+
+    /// See `Client::put_with_request_id`.
+    pub async fn put_with_request_id(
+        &self,
+        key: &K,
+        value: &V,
+        request_id: impl Into<String>,
+    ) -> Result<(), ClientError> {
+        let value = C::encode(value).map_err(ClientError::Codec)?;
+        self.client.put_with_request_id(render_key(key)?, value, request_id).await
+    }
+
+    pub async fn delete(&self, key: &K) -> Result<bool, ClientError> {
+        self.client.delete(render_key(key)?).await
+    }
+}
+
+impl<K, V, C> Clone for TypedClient<K, V, C> {
+    fn clone(&self) -> Self {
+        Self { client: self.client.clone(), types: PhantomData }
+    }
+}
+
+impl<K, V, C> fmt::Debug for TypedClient<K, V, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedClient")
+            .field("client", &self.client)
+            .field("codec", &std::any::type_name::<C>())
+            .finish()
+    }
+}
+
+/// The server key for `key` (see the module docs).
+fn render_key<K: Serialize + ?Sized>(key: &K) -> Result<String, serde_json::Error> {
+    Ok(match serde_json::to_value(key)? {
+        serde_json::Value::String(key) => key,
+        other => other.to_string(),
+    })
+}
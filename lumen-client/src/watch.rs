@@ -0,0 +1,183 @@
+//! Change subscriptions: the `Watch` RPC as a resuming stream.
+//!
+//! A background task holds the server stream and forwards each change.  It
+//! remembers the sequence of the last event it received (including the
+//! server's progress markers, which are not forwarded), and when the stream
+//! breaks with a transient error it reconnects — possibly to another
+//! endpoint — and resumes after that sequence, so no change is delivered
+//! twice or skipped.  Reconnects back off per the client's `RetryPolicy`
+//! but are not limited to `max_attempts` or the retry budget: a watch keeps
+//! trying until it is dropped, unless retries are disabled altogether.
+//!
+//! A watch that has not yet heard from any server when it first fails
+//! resumes "from now", so changes committed in between are missed.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use tonic::{Code, Status};
+use tracing::{debug, warn};
+
+use crate::client::{ClientError, Transport};
+use crate::kv::{self, Operation, WatchRequest};
+use crate::retry::{is_retryable, RetryHint};
+
+/// Events buffered ahead of a slow consumer before the stream stalls.
+const BUFFER: usize = 64;
+
+/// One committed change to a watched key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// Commit sequence on the serving node; pass it to `Client::watch_from`
+    /// to resume after this event.
+    pub sequence: u64,
+    pub key: String,
+    /// The new value, or `None` if the key was deleted.
+    pub value: Option<Vec<u8>>,
+    /// The key was deleted by its lease expiring.
+    pub expired: bool,
+    /// Hybrid-logical-clock commit timestamp; 0 where unknown.
+    pub hlc_timestamp: u64,
+}
+
+impl From<kv::WatchEvent> for WatchEvent {
+    fn from(event: kv::WatchEvent) -> Self {
+        let expired = event.op() == Operation::Expired;
+        let deleted = expired || event.op() == Operation::Delete;
+        Self {
+            sequence:      event.sequence,
+            key:           event.key,
+            value:         (!deleted).then_some(event.value),
+            expired,
+            hlc_timestamp: event.hlc_timestamp,
+        }
+    }
+}
+
+/// Stream of `WatchEvent`s, ending after the first permanent error.
+/// Dropping it cancels the watch.
+#[derive(Debug)]
+pub struct Watch {
+    events: mpsc::Receiver<Result<WatchEvent, ClientError>>,
+}
+
+impl Watch {
+    pub(crate) fn spawn(transport: Arc<Transport>, prefix: String, from_sequence: u64) -> Self {
+        let (tx, rx) = mpsc::channel(BUFFER);
+        tokio::spawn(run(transport, prefix, from_sequence, tx));
+        Self { events: rx }
+    }
+
+    /// The next event, or `None` once the watch has ended.
+    pub async fn next(&mut self) -> Option<Result<WatchEvent, ClientError>> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for Watch {
+    type Item = Result<WatchEvent, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+type Events = mpsc::Sender<Result<WatchEvent, ClientError>>;
+
+async fn run(transport: Arc<Transport>, prefix: String, mut sequence: u64, tx: Events) {
+    let mut failures = 0u32;
+    loop {
+        let status = match follow(&transport, &prefix, &mut sequence, &mut failures, &tx).await {
+            Ok(()) => return,
+            Err(status) => status,
+        };
+
+        let policy = transport.retry_policy();
+        if !is_transient(&status) || policy.max_attempts <= 1 {
+            let _ = tx.send(Err(status.into())).await;
+            return;
+        }
+
+        failures = failures.saturating_add(1);
+        let delay = policy.backoff(failures, RetryHint::of(&status));
+        warn!(
+            code     = ?status.code(),
+            error    = %status.message(),
+            sequence,
+            delay_ms = delay.as_millis() as u64,
+            "Watch stream broken; resuming"
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = tx.closed() => return,
+        }
+    }
+}
+
+/// Forward one server stream until it fails; `Ok` means the consumer is gone.
+async fn follow(
+    transport: &Transport,
+    prefix: &str,
+    sequence: &mut u64,
+    failures: &mut u32,
+    tx: &Events,
+) -> Result<(), Status> {
+    let (pool, connection, mut kv) = transport.connection();
+    let request = WatchRequest { prefix: prefix.to_owned(), from_sequence: *sequence };
+    debug!(addr = pool.addr(), from_sequence = *sequence, "Opening watch stream");
+
+    let opened = tokio::select! {
+        opened = kv.watch(request) => opened,
+        _ = tx.closed() => return Ok(()),
+    };
+    let mut stream = match opened {
+        Ok(response) => response.into_inner(),
+        Err(status) => {
+            if status.code() == Code::Unavailable {
+                pool.mark_unhealthy(connection);
+            }
+            return Err(status);
+        }
+    };
+
+    loop {
+        let message = tokio::select! {
+            message = stream.message() => message,
+            _ = tx.closed() => return Ok(()),
+        };
+        match message {
+            Ok(Some(event)) => {
+                *failures = 0;
+                *sequence = event.sequence;
+                if event.progress {
+                    continue;
+                }
+                if tx.send(Ok(event.into())).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(None) => return Err(Status::unavailable("watch stream closed by server")),
+            Err(status) => {
+                if status.code() == Code::Unavailable || is_broken(&status) {
+                    pool.mark_unhealthy(connection);
+                }
+                return Err(status);
+            }
+        }
+    }
+}
+
+/// Failures a watch recovers from by reconnecting.
+fn is_transient(status: &Status) -> bool {
+    // A replica we failed over to may not have caught up to `sequence`.
+    is_retryable(status.code()) || status.code() == Code::OutOfRange || is_broken(status) || RetryHint::of(status).safe
+}
+
+/// The connection failed mid-stream, which tonic reports as `UNKNOWN`
+/// wrapping the transport error rather than as `UNAVAILABLE`.
+pub(crate) fn is_broken(status: &Status) -> bool {
+    status.code() == Code::Unknown && std::error::Error::source(status).is_some()
+}
@@ -0,0 +1,99 @@
+//! lumen-compact — shrink a stopped node's data directories.
+//!
+//! Opens each directory as an engine would, replaying its WAL, then writes
+//! every live key to a fresh checkpoint and empties the WAL, so overwritten
+//! and deleted keys stop taking up space.  The engine's lock on the
+//! directory makes this fail, rather than race, while a server has it open.
+//!
+//!   lumen-compact /var/lib/lumen                    a single-engine node
+//!   lumen-compact /var/lib/lumen/shard-a /var/lib/lumen/shard-b
+//!                                                   each engine of a sharded node
+//!
+//! Sequence numbers carry on from where the log left off, so replicas keep
+//! following; one that had fallen behind the new checkpoint re-bootstraps
+//! from it, as it would after any checkpoint.  Whether the WAL writes commit
+//! markers is kept as it was, and a directory holding SSTables is compacted
+//! into a single table instead of a checkpoint.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+use lumen_core::{Engine, EngineOptions, WalOptions, WalReader};
+
+#[derive(Debug, Parser)]
+#[command(name = "lumen-compact", about = "Compact the data directories of a stopped LumenKV node")]
+struct Cli {
+    /// Data directories to compact, one per engine.
+    #[arg(required = true, value_name = "DATA_DIR")]
+    dirs: Vec<PathBuf>,
+
+    /// Compact a directory even if it holds temp files an interrupted write
+    /// left behind.
+    #[arg(long)]
+    ignore_orphans: bool,
+
+    /// Write each value of at least this many bytes once, however many keys
+    /// hold it, as the server's `DEDUP_MIN_BYTES` does.  Only checkpoints
+    /// share values, so a directory holding tables is refused.
+    #[arg(long, value_name = "BYTES")]
+    dedup_values: Option<usize>,
+}
+
+/// Bytes of a data directory's WAL, checkpoint and tables.
+fn disk_usage(dir: &Path) -> u64 {
+    let files = ["wal.log", "checkpoint"].iter().map(|name| dir.join(name)).chain(tables(dir));
+    files.filter_map(|path| std::fs::metadata(path).ok()).map(|meta| meta.len()).sum()
+}
+
+/// The SSTables in a data directory.
+fn tables(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sst"))
+        .collect()
+}
+
+fn compact(dir: &Path, ignore_orphans: bool, dedup_values: Option<usize>) -> anyhow::Result<()> {
+    let has_tables = !tables(dir).is_empty();
+    if !dir.join("wal.log").exists() && !dir.join("checkpoint").exists() && !has_tables {
+        anyhow::bail!("{} is not a data directory: it has no wal.log, checkpoint or tables", dir.display());
+    }
+    if has_tables && dedup_values.is_some() {
+        anyhow::bail!(
+            "{} holds tables, which keep every copy of a value; --dedup-values only applies to checkpoints",
+            dir.display()
+        );
+    }
+    let commit_markers = match WalReader::open(dir.join("wal.log")) {
+        Ok(reader) => reader.info().commit_markers,
+        Err(_) => false,
+    };
+
+    let before = disk_usage(dir);
+    let wal    = WalOptions { commit_markers, ..Default::default() };
+    // Flushes nothing on its own, but compacts into a table.
+    let memtable_flush_bytes = has_tables.then_some(u64::MAX);
+    let options = EngineOptions { wal, ignore_orphans, dedup_values, memtable_flush_bytes, ..Default::default() };
+    let engine = Engine::open_with(dir, options).with_context(|| format!("failed to open {}", dir.display()))?;
+    let checkpoint = engine.compact().with_context(|| format!("failed to compact {}", dir.display()))?;
+    drop(engine);
+    let after = disk_usage(dir);
+
+    println!(
+        "{}: {} keys at sequence {}, {before} → {after} bytes",
+        dir.display(),
+        checkpoint.entries.len(),
+        checkpoint.sequence
+    );
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    for dir in &cli.dirs {
+        compact(dir, cli.ignore_orphans, cli.dedup_values)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Engine microbenchmarks: writes through the WAL into the memtable, under
+//! each sync policy, memtable reads, and recovery on open.
+//!
+//! `cargo bench -p lumen-core --bench engine`
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use lumen_core::{Engine, EngineOptions, SyncPolicy, WalOptions};
+
+/// Keys preloaded for the read benchmarks.
+const KEYS: usize = 100_000;
+const RECOVERY_RECORDS: [usize; 2] = [10_000, 100_000];
+
+/// A fresh, empty scratch directory for one benchmark.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumen-core-bench-{}", std::process::id())).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn put(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine_put");
+    for size in [128usize, 4096] {
+        let dir    = scratch(&format!("put-{size}"));
+        let engine = Engine::open(&dir).unwrap();
+        let value  = vec![0xABu8; size];
+        let mut i  = 0u64;
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                i += 1;
+                engine.put(format!("bench-key-{}", i % KEYS as u64), value.clone()).unwrap();
+            });
+        });
+
+        drop(engine);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    group.finish();
+}
+
+/// `Engine::put` of 128-byte values under each sync policy, with and
+/// without commit markers, by one writer and by several sharing the syncs.
+/// The time is per put, across the writers.
+fn put_sync(c: &mut Criterion) {
+    let policies = [
+        ("os", SyncPolicy::Os),
+        ("interval-1ms", SyncPolicy::Interval(Duration::from_millis(1))),
+        ("adaptive-5ms", SyncPolicy::Adaptive { target_p99: Duration::from_millis(5) }),
+    ];
+    let mut group = c.benchmark_group("engine_put_sync");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(1));
+    for (name, sync_policy) in policies {
+        for commit_markers in [false, true] {
+            let name = if commit_markers { format!("{name}+markers") } else { name.to_owned() };
+            for writers in [1u64, 8] {
+                let dir     = scratch(&format!("put-sync-{name}-{writers}"));
+                let wal     = WalOptions { commit_markers, ..Default::default() };
+                let engine  = Engine::open_with(&dir, EngineOptions { wal, sync_policy, ..Default::default() }).unwrap();
+                let value   = vec![0xABu8; 128];
+
+                group.bench_function(BenchmarkId::new(&name, format!("{writers}-writers")), |b| {
+                    b.iter_custom(|iters| {
+                        let started = Instant::now();
+                        std::thread::scope(|s| {
+                            for writer in 0..writers {
+                                let (engine, value) = (&engine, &value);
+                                s.spawn(move || {
+                                    for i in (writer..iters).step_by(writers as usize) {
+                                        engine.put(format!("bench-key-{}", i % KEYS as u64), value.clone()).unwrap();
+                                    }
+                                });
+                            }
+                        });
+                        started.elapsed()
+                    });
+                });
+
+                drop(engine);
+                let _ = std::fs::remove_dir_all(&dir);
+            }
+        }
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let dir    = scratch("get");
+    let engine = Engine::open(&dir).unwrap();
+    for i in 0..KEYS {
+        engine.put(format!("bench-key-{i}"), vec![0xAB; 128]).unwrap();
+    }
+
+    let mut group = c.benchmark_group("engine_get");
+    let mut i     = 0usize;
+    group.bench_function("hit", |b| {
+        b.iter(|| {
+            i = (i + 7919) % KEYS;
+            engine.get(&format!("bench-key-{i}")).unwrap()
+        });
+    });
+    group.bench_function("miss", |b| {
+        b.iter(|| engine.get("absent-key").unwrap());
+    });
+    group.finish();
+
+    drop(engine);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+/// `Engine::open` replaying a WAL of 128-byte puts.
+fn open(c: &mut Criterion) {
+    let mut group = c.benchmark_group("engine_open");
+    group.sample_size(10);
+    for records in RECOVERY_RECORDS {
+        let dir = scratch(&format!("open-{records}"));
+        {
+            let engine = Engine::open(&dir).unwrap();
+            for i in 0..records {
+                engine.put(format!("bench-key-{i}"), vec![0xAB; 128]).unwrap();
+            }
+        }
+
+        group.throughput(Throughput::Elements(records as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(records), &dir, |b, dir| {
+            b.iter(|| Engine::open(dir).unwrap());
+        });
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, put, put_sync, get, open);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+//! WAL microbenchmarks: append by value size, recovery, and the CRC32 over
+//! a record.
+//!
+//! `cargo bench -p lumen-core --bench wal`
+
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use crc32fast::Hasher as Crc32Hasher;
+use lumen_core::{WalRecord, WriteAheadLog};
+
+const VALUE_SIZES: [usize; 4] = [16, 128, 1024, 16 * 1024];
+const RECOVERY_RECORDS: [usize; 2] = [10_000, 100_000];
+
+/// A fresh scratch path for one benchmark.
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumen-core-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn put(i: usize, value_size: usize) -> WalRecord {
+    WalRecord::Put { key: format!("bench-key-{i}"), value: vec![0xAB; value_size] }
+}
+
+/// Appends flush every record to the kernel but sync nothing: syncs are the
+/// engine's, by its `SyncPolicy`, which the `engine_put_sync` benches
+/// compare.
+fn append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wal_append");
+    for size in VALUE_SIZES {
+        let path    = scratch(&format!("append-{size}.log"));
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let record  = put(0, size);
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &record, |b, record| {
+            b.iter(|| wal.append(record, 1, 1).unwrap());
+        });
+
+        drop(wal);
+        let _ = std::fs::remove_file(&path);
+    }
+    group.finish();
+}
+
+fn recover(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wal_recover");
+    group.sample_size(10);
+    for records in RECOVERY_RECORDS {
+        let path = scratch(&format!("recover-{records}.log"));
+        {
+            let mut wal = WriteAheadLog::open(&path).unwrap();
+            for i in 0..records {
+                wal.append(&put(i, 128), i as u64 + 1, i as u64 + 1).unwrap();
+            }
+        }
+
+        group.throughput(Throughput::Elements(records as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(records), &path, |b, path| {
+            b.iter(|| WriteAheadLog::recover(path).unwrap());
+        });
+
+        let _ = std::fs::remove_file(&path);
+    }
+    group.finish();
+}
+
+/// The checksum as `WriteAheadLog::append` computes it.
+fn crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("wal_crc32");
+    for size in VALUE_SIZES {
+        let key   = b"bench-key-0";
+        let value = vec![0xABu8; size];
+
+        group.throughput(Throughput::Bytes((size + key.len() + 21) as u64));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_batched(
+                Crc32Hasher::new,
+                |mut h| {
+                    h.update(&[0x01]);
+                    h.update(&1u64.to_be_bytes());
+                    h.update(&1u64.to_be_bytes());
+                    h.update(&(key.len() as u32).to_be_bytes());
+                    h.update(key);
+                    h.update(&value);
+                    h.finalize()
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, append, recover, crc);
+criterion_main!(benches);
@@ -0,0 +1,227 @@
+//! The block cache: decoded blocks of the tables in the cold tier, kept in
+//! memory so that reads of hot keys do not go back to slow storage each
+//! time (see `tier`).
+//!
+//! Blocks are keyed by their table and index, and evicted least recently
+//! used first once they take more than the cache's capacity, counted in the
+//! bytes they take on disk.  A table's blocks are dropped with it.
+//!
+//! A cursor over a cold table that reads past its first block is taken to
+//! be a sequential scan, and asks for the blocks after the one it is on to
+//! be read ahead (see `BlockCache::prefetch`): a thread per cache reads them
+//! into the cache while the cursor works through the current one, so a
+//! scan of cold data waits on the slow storage once per run of blocks
+//! rather than once per block.  Point reads never read ahead, and the
+//! queue is short: what does not fit is dropped, for the cursor to read
+//! itself.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::metrics;
+use crate::sstable::{Entry, Table};
+
+/// Blocks waiting to be read ahead, past which requests are dropped.
+const PREFETCH_QUEUE: usize = 64;
+
+/// A block: its table's ID and its index.
+type BlockId = (u64, usize);
+/// A block to be read ahead: its table and its index.
+type Prefetch = (Arc<Table>, usize);
+
+#[derive(Debug)]
+pub(crate) struct BlockCache {
+    capacity: u64,
+    /// Blocks a sequential cursor keeps read ahead of it.
+    prefetch: usize,
+    state: Mutex<State>,
+    /// Queue of the prefetch thread, once started.
+    prefetcher: Mutex<Option<SyncSender<Prefetch>>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blocks: HashMap<BlockId, Cached>,
+    /// Blocks by when they were last used, oldest first.
+    used: BTreeMap<u64, BlockId>,
+    clock: u64,
+    bytes: u64,
+}
+
+#[derive(Debug)]
+struct Cached {
+    entries: Arc<Vec<Entry>>,
+    bytes: u64,
+    used: u64,
+}
+
+impl BlockCache {
+    /// A cache of at most `capacity` bytes of blocks (0: cache nothing),
+    /// reading `prefetch` blocks ahead of sequential cursors (0: none).
+    pub(crate) fn new(capacity: u64, prefetch: usize) -> Self {
+        let state = Mutex::new(State::default());
+        Self { capacity, prefetch, state, prefetcher: Mutex::new(None) }
+    }
+
+    /// Blocks a sequential cursor keeps read ahead of it.
+    pub(crate) fn prefetch_depth(&self) -> usize {
+        if self.capacity == 0 { 0 } else { self.prefetch }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Block `index` of table `table`, if it is cached.
+    pub(crate) fn get(&self, table: u64, index: usize) -> Option<Arc<Vec<Entry>>> {
+        let mut state = self.state();
+        let State { blocks, used, clock, .. } = &mut *state;
+        let found = blocks.get_mut(&(table, index)).map(|cached| {
+            used.remove(&cached.used);
+            *clock += 1;
+            cached.used = *clock;
+            used.insert(*clock, (table, index));
+            cached.entries.clone()
+        });
+        metrics::block_cache(found.is_some());
+        found
+    }
+
+    /// Whether block `index` of table `table` is cached, without counting
+    /// as a use.
+    pub(crate) fn contains(&self, table: u64, index: usize) -> bool {
+        self.state().blocks.contains_key(&(table, index))
+    }
+
+    /// Read `blocks` of `table` into the cache in the background, those not
+    /// cached already, starting the thread the first time.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn prefetch(&self, table: &Arc<Table>, blocks: Range<usize>) {
+        let mut prefetcher = self.prefetcher.lock().unwrap_or_else(|e| e.into_inner());
+        if prefetcher.is_none() {
+            let (tx, rx) = mpsc::sync_channel::<Prefetch>(PREFETCH_QUEUE);
+            let spawned = std::thread::Builder::new().name("lumen-prefetch".to_owned()).spawn(move || {
+                // Ends once the cache, and with it the queue, is dropped.
+                for (table, index) in rx {
+                    table.prefetch_block(index);
+                }
+            });
+            match spawned {
+                Ok(_) => *prefetcher = Some(tx),
+                Err(e) => {
+                    warn!(error = %e, "Failed to start the block prefetch thread");
+                    return;
+                }
+            }
+        }
+        let Some(queue) = prefetcher.as_ref() else { return };
+        for index in blocks {
+            if self.contains(table.id(), index) {
+                continue;
+            }
+            match queue.try_send((table.clone(), index)) {
+                Ok(()) => metrics::block_prefetch(),
+                Err(TrySendError::Full(_)) => break,
+                Err(TrySendError::Disconnected(_)) => {
+                    *prefetcher = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Cache block `index` of table `table`, `bytes` long on disk, evicting
+    /// the least recently used blocks to make room.
+    pub(crate) fn insert(&self, table: u64, index: usize, entries: Arc<Vec<Entry>>, bytes: u64) {
+        if bytes > self.capacity {
+            return;
+        }
+        let mut state = self.state();
+        state.remove(&(table, index));
+        while state.bytes + bytes > self.capacity {
+            let Some((_, oldest)) = state.used.pop_first() else { break };
+            state.remove(&oldest);
+        }
+        state.clock += 1;
+        let used = state.clock;
+        state.used.insert(used, (table, index));
+        state.blocks.insert((table, index), Cached { entries, bytes, used });
+        state.bytes += bytes;
+    }
+
+    /// Drop every block of table `table`.
+    pub(crate) fn forget(&self, table: u64) {
+        let mut state = self.state();
+        let gone: Vec<BlockId> = state.blocks.keys().copied().filter(|&(id, _)| id == table).collect();
+        for block in gone {
+            state.remove(&block);
+        }
+    }
+
+    /// Bytes of the blocks cached.
+    pub(crate) fn bytes(&self) -> u64 {
+        self.state().bytes
+    }
+}
+
+impl State {
+    fn remove(&mut self, block: &BlockId) {
+        if let Some(cached) = self.blocks.remove(block) {
+            self.used.remove(&cached.used);
+            self.bytes -= cached.bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(key: &str) -> Arc<Vec<Entry>> {
+        Arc::new(vec![(key.to_owned(), Some(b"value".to_vec()))])
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_block() {
+        let cache = BlockCache::new(300, 0);
+        cache.insert(1, 0, block("a"), 100);
+        cache.insert(1, 1, block("b"), 100);
+        cache.insert(2, 0, block("c"), 100);
+        assert!(cache.get(1, 0).is_some());
+
+        cache.insert(2, 1, block("d"), 100);
+        assert!(cache.get(1, 1).is_none());
+        assert_eq!(cache.get(1, 0).unwrap()[0].0, "a");
+        assert!(cache.get(2, 0).is_some());
+        assert!(cache.get(2, 1).is_some());
+        assert_eq!(cache.bytes(), 300);
+    }
+
+    #[test]
+    fn forgets_the_blocks_of_a_table() {
+        let cache = BlockCache::new(1000, 0);
+        cache.insert(1, 0, block("a"), 100);
+        cache.insert(1, 1, block("b"), 100);
+        cache.insert(2, 0, block("c"), 100);
+        cache.forget(1);
+        assert!(cache.get(1, 0).is_none());
+        assert!(cache.get(1, 1).is_none());
+        assert!(cache.get(2, 0).is_some());
+        assert_eq!(cache.bytes(), 100);
+    }
+
+    #[test]
+    fn skips_a_block_larger_than_the_cache() {
+        let cache = BlockCache::new(50, 0);
+        cache.insert(1, 0, block("a"), 100);
+        assert!(cache.get(1, 0).is_none());
+        assert_eq!(cache.bytes(), 0);
+
+        // Re-inserting a block replaces it rather than counting it twice.
+        cache.insert(1, 1, block("b"), 40);
+        cache.insert(1, 1, block("b"), 40);
+        assert_eq!(cache.bytes(), 40);
+    }
+}
@@ -0,0 +1,399 @@
+//! Checkpoint files: a full, sorted dump of the keyspace as of a sequence.
+//!
+//! A checkpoint replaces the WAL prefix it covers — on open the engine loads
+//! the checkpoint first and then replays the WAL, whose records continue at
+//! `sequence + 1`.  Replicas install checkpoints streamed from their primary
+//! when they bootstrap.  A base table (see `sstable`) at least as new
+//! supersedes it.
+//!
+//! On-disk format:
+//!   [Magic "LKVCKPT1" (8 bytes)] [Sequence (8 bytes, BE)] [Count (8 bytes, BE)]
+//!   Count × { [Key Len (8 bytes, BE)] [Value Len (8 bytes, BE)] [Key] [Value] }
+//!   [CRC32 (4 bytes, BE)]
+//!
+//! CRC32 is computed over every byte preceding it.
+//!
+//! With value de-duplication, a value of at least the threshold stored
+//! under more than one key is written once, in a table of shared values
+//! between the header and the entries, and each of its keys refers to it by
+//! content:
+//!   [Magic "LKVCKPT2" (8 bytes)] [Sequence (8 bytes, BE)]
+//!   [Shared Count (8 bytes, BE)] Shared Count × { [Len (8 bytes, BE)] [Value] }
+//!   [Count (8 bytes, BE)]
+//!   Count × { [Key Len (8 bytes, BE)] [Value Len (8 bytes, BE)] [Key] [Value] }
+//!   [CRC32 (4 bytes, BE)]
+//! where an entry whose Value Len has its top bit set has no Value: the rest
+//! of that field is the index of its value in the table.  The table is
+//! built afresh for every checkpoint from the values it holds, so a shared
+//! value is only ever kept while some key still refers to it.  Loading a
+//! checkpoint gives every key its own copy again.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use crc32fast::Hasher as Crc32Hasher;
+
+use crate::metrics;
+use crate::sync::sync_parent;
+use crate::wal::{RecordLimits, WalError};
+
+const MAGIC: &[u8; 8] = b"LKVCKPT1";
+/// Magic of checkpoints written with a table of shared values.
+const MAGIC_SHARED: &[u8; 8] = b"LKVCKPT2";
+/// Set in an entry's value length when it refers to a shared value.
+const SHARED: u64 = 1 << 63;
+
+/// Point-in-time copy of every live key, consistent at `sequence`.
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint {
+    pub sequence: u64,
+    pub entries: Vec<(String, Vec<u8>)>,
+}
+
+/// Writer adapter that feeds every byte through the CRC as well.
+struct Checksummed<W> {
+    inner: W,
+    hasher: Crc32Hasher,
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader adapter that feeds every byte through the CRC as well, and
+/// counts them.
+struct Verified<R> {
+    inner: R,
+    hasher: Crc32Hasher,
+    read: u64,
+}
+
+impl<R: Read> Read for Verified<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R> Verified<R> {
+    /// Check a `len` read off disk for a `field` before allocating for it:
+    /// it must be within `max` and fit in the `file_len` bytes of the file
+    /// left before its CRC.
+    fn check_len(&self, field: &str, len: u64, max: u64, file_len: u64) -> Result<usize, WalError> {
+        let left = file_len.saturating_sub(self.read + 4);
+        let reason = if len > max {
+            format!("checkpoint {field} of {len} bytes exceeds the limit of {max}")
+        } else if len > left {
+            format!("checkpoint {field} of {len} bytes runs past the end of the file ({left} bytes left)")
+        } else {
+            return Ok(len as usize);
+        };
+        warn!(offset = self.read, reason = %reason, "Corrupt checkpoint");
+        Err(WalError::Corrupt { offset: self.read, reason })
+    }
+}
+
+impl Checkpoint {
+    /// Atomically replace the checkpoint at `path` with `self`.
+    ///
+    /// The data is written to a sibling temp file, fsynced and renamed over
+    /// `path`, so readers only ever see the old or the new checkpoint.
+    pub fn write_to(&self, path: &Path) -> Result<(), WalError> {
+        self.write_with(path, None)
+    }
+
+    /// Like `write_to`, but with `Some(min_len)`, values of at least
+    /// `min_len` bytes stored under several keys are written only once.
+    pub fn write_with(&self, path: &Path, dedup_min_len: Option<usize>) -> Result<(), WalError> {
+        let tmp_path = path.with_extension("tmp");
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        let mut w = Checksummed {
+            inner: BufWriter::new(file),
+            hasher: Crc32Hasher::new(),
+        };
+
+        let shared = dedup_min_len.map(|min_len| self.shared_values(min_len)).unwrap_or_default();
+        if dedup_min_len.is_some() {
+            let mut table: Vec<&[u8]> = vec![&[]; shared.len()];
+            for (value, &index) in &shared {
+                table[index] = value;
+            }
+            w.write_all(MAGIC_SHARED)?;
+            w.write_u64::<BigEndian>(self.sequence)?;
+            w.write_u64::<BigEndian>(table.len() as u64)?;
+            for value in table {
+                w.write_u64::<BigEndian>(value.len() as u64)?;
+                w.write_all(value)?;
+            }
+        } else {
+            w.write_all(MAGIC)?;
+            w.write_u64::<BigEndian>(self.sequence)?;
+        }
+
+        w.write_u64::<BigEndian>(self.entries.len() as u64)?;
+        for (key, value) in &self.entries {
+            w.write_u64::<BigEndian>(key.len() as u64)?;
+            match shared.get(value.as_slice()) {
+                Some(&index) => {
+                    w.write_u64::<BigEndian>(SHARED | index as u64)?;
+                    w.write_all(key.as_bytes())?;
+                }
+                None => {
+                    w.write_u64::<BigEndian>(value.len() as u64)?;
+                    w.write_all(key.as_bytes())?;
+                    w.write_all(value)?;
+                }
+            }
+        }
+
+        let checksum = w.hasher.finalize();
+        let mut writer = w.inner;
+        writer.write_u32::<BigEndian>(checksum)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&tmp_path, path)?;
+        sync_parent(path)?;
+
+        info!(
+            path     = %path.display(),
+            sequence = self.sequence,
+            entries  = self.entries.len(),
+            shared   = shared.len(),
+            "Checkpoint written"
+        );
+        Ok(())
+    }
+
+    /// The values of at least `min_len` bytes held by more than one key,
+    /// each with its index in the table of shared values.
+    fn shared_values(&self, min_len: usize) -> HashMap<&[u8], usize> {
+        let mut keys: HashMap<&[u8], usize> = HashMap::new();
+        for (_, value) in &self.entries {
+            if value.len() >= min_len.max(1) {
+                *keys.entry(value.as_slice()).or_default() += 1;
+            }
+        }
+        let mut shared = HashMap::new();
+        for (_, value) in &self.entries {
+            if keys.get(value.as_slice()).is_some_and(|&n| n > 1) && !shared.contains_key(value.as_slice()) {
+                shared.insert(value.as_slice(), shared.len());
+            }
+        }
+        shared
+    }
+
+    /// The sequence of the checkpoint at `path`, from its header, or `None`
+    /// if there is none.  The rest of the file is not read, so not checked.
+    pub fn read_sequence(path: &Path) -> Result<Option<u64>, WalError> {
+        let mut file = match File::open(path) {
+            Ok(f)  => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(WalError::Io(e)),
+        };
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != MAGIC && &magic != MAGIC_SHARED {
+            return Err(WalError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a LumenKV checkpoint file",
+            )));
+        }
+        Ok(Some(file.read_u64::<BigEndian>()?))
+    }
+
+    /// Load the checkpoint at `path`, or `None` if there is none.
+    pub fn read_from(path: &Path) -> Result<Option<Self>, WalError> {
+        Self::read_with(path, RecordLimits::default())
+    }
+
+    /// Like `read_from`, but a key or value longer than `limits` allow, or
+    /// than what is left of the file, is `WalError::Corrupt` before anything
+    /// is allocated for it.
+    pub fn read_with(path: &Path, limits: RecordLimits) -> Result<Option<Self>, WalError> {
+        let file = match File::open(path) {
+            Ok(f)  => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(WalError::Io(e)),
+        };
+        let file_len = file.metadata()?.len();
+
+        let mut r = Verified {
+            inner: BufReader::new(file),
+            hasher: Crc32Hasher::new(),
+            read: 0,
+        };
+
+        let invalid = |message: &str| {
+            WalError::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_owned()))
+        };
+
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC && &magic != MAGIC_SHARED {
+            return Err(invalid("not a LumenKV checkpoint file"));
+        }
+
+        let sequence = r.read_u64::<BigEndian>()?;
+        let mut shared = Vec::new();
+        if &magic == MAGIC_SHARED {
+            for _ in 0..r.read_u64::<BigEndian>()? {
+                let len = r.read_u64::<BigEndian>()?;
+                let len = r.check_len("shared value", len, limits.max_value_len, file_len)?;
+                let mut value = vec![0u8; len];
+                r.read_exact(&mut value)?;
+                shared.push(value);
+            }
+        }
+
+        let count    = r.read_u64::<BigEndian>()?;
+        let mut entries = Vec::new();
+
+        for _ in 0..count {
+            let key_len   = r.read_u64::<BigEndian>()?;
+            let value_len = r.read_u64::<BigEndian>()?;
+
+            let key_len = r.check_len("key", key_len, limits.max_key_len, file_len)?;
+            let mut key_bytes = vec![0u8; key_len];
+            r.read_exact(&mut key_bytes)?;
+            let value = if value_len & SHARED != 0 {
+                shared
+                    .get((value_len & !SHARED) as usize)
+                    .ok_or_else(|| invalid("checkpoint entry refers to a missing shared value"))?
+                    .clone()
+            } else {
+                let value_len = r.check_len("value", value_len, limits.max_value_len, file_len)?;
+                let mut value = vec![0u8; value_len];
+                r.read_exact(&mut value)?;
+                value
+            };
+
+            entries.push((String::from_utf8(key_bytes)?, value));
+        }
+
+        let computed = r.hasher.finalize();
+        let stored   = r.inner.read_u32::<BigEndian>()?;
+        if computed != stored {
+            metrics::checksum_failure();
+            return Err(WalError::ChecksumMismatch { expected: stored, actual: computed });
+        }
+
+        info!(
+            path     = %path.display(),
+            sequence,
+            entries  = entries.len(),
+            "Checkpoint loaded"
+        );
+        Ok(Some(Self { sequence, entries }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint() -> Checkpoint {
+        Checkpoint {
+            sequence: 42,
+            entries:  vec![
+                ("a".to_owned(), b"shared value".to_vec()),
+                ("b".to_owned(), b"own".to_vec()),
+                ("c".to_owned(), b"shared value".to_vec()),
+            ],
+        }
+    }
+
+    /// A checkpoint holding one entry whose key and value lengths are as
+    /// given, followed by `tail` bytes of padding and a CRC that matches.
+    fn forged(key_len: u64, value_len: u64, tail: usize) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.write_u64::<BigEndian>(7).unwrap();
+        bytes.write_u64::<BigEndian>(1).unwrap();
+        bytes.write_u64::<BigEndian>(key_len).unwrap();
+        bytes.write_u64::<BigEndian>(value_len).unwrap();
+        bytes.extend(std::iter::repeat_n(b'k', tail));
+        let crc = crc32fast::hash(&bytes);
+        bytes.write_u32::<BigEndian>(crc).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn round_trips_with_and_without_shared_values() {
+        let dir = tempfile::tempdir().unwrap();
+        for dedup in [None, Some(4)] {
+            let path = dir.path().join("checkpoint");
+            checkpoint().write_with(&path, dedup).unwrap();
+            let read = Checkpoint::read_from(&path).unwrap().unwrap();
+            assert_eq!(read.sequence, 42);
+            assert_eq!(read.entries, checkpoint().entries);
+        }
+    }
+
+    #[test]
+    fn missing_file_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Checkpoint::read_from(&dir.path().join("checkpoint")).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_lengths_over_the_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        fs::write(&path, forged(64, 0, 64)).unwrap();
+
+        let limits = RecordLimits { max_key_len: 16, max_value_len: 16 };
+        let err = Checkpoint::read_with(&path, limits).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: 40, .. }), "{err}");
+        // Within the default limits the same file loads.
+        assert_eq!(Checkpoint::read_from(&path).unwrap().unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_end_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+
+        // Within the limits, but far more than the file holds.
+        fs::write(&path, forged(8, 1 << 29, 8)).unwrap();
+        let err = Checkpoint::read_from(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { .. }), "{err}");
+
+        fs::write(&path, forged(u64::MAX, 0, 8)).unwrap();
+        let err = Checkpoint::read_from(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { .. }), "{err}");
+    }
+
+    #[test]
+    fn rejects_a_shared_value_past_the_end_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        let mut bytes = MAGIC_SHARED.to_vec();
+        bytes.write_u64::<BigEndian>(7).unwrap();
+        bytes.write_u64::<BigEndian>(1).unwrap();
+        bytes.write_u64::<BigEndian>(1 << 20).unwrap();
+        bytes.extend_from_slice(b"short");
+        fs::write(&path, bytes).unwrap();
+
+        let err = Checkpoint::read_from(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: 32, .. }), "{err}");
+    }
+}
@@ -0,0 +1,58 @@
+//! Stable error codes.
+//!
+//! An error's message is for people and may change between releases; its
+//! code is for programs.  `EngineError::code` and `LeaseError::code` say
+//! which code an error carries, the server sends it with every failed call
+//! (as `kv.ErrorInfo`, in the status details), and the client reads it back
+//! with `ClientError::code`.  Codes are only ever added, never renumbered.
+
+/// What kind of failure an error is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// A bug or an I/O failure; nothing the caller can fix.
+    Internal,
+    /// The request itself is malformed, e.g. a record over the size limits.
+    InvalidArgument,
+    /// The key, lease or index named does not exist.
+    NotFound,
+    /// The lease or index to create exists already.
+    AlreadyExists,
+    /// The request cannot be served in the state the store is in, e.g. a
+    /// change out of order on a replica.
+    PreconditionFailed,
+    /// Another holder has what the request needs, e.g. a lock.
+    Conflict,
+    /// The write would take the store past its storage quota.
+    QuotaExceeded,
+    /// The write's namespace is over its write limit; retry later.
+    Backpressure,
+    /// The node only serves reads.
+    ReadOnly,
+    /// The node cannot serve the request now; another node may.
+    Unavailable,
+    /// The changes asked for are no longer retained: resynchronise from a
+    /// snapshot.
+    SequenceUnavailable,
+    /// Data on disk failed its checksum or does not parse.
+    Corruption,
+}
+
+impl ErrorCode {
+    /// The code's name, as in `kv.ErrorCode` without its prefix.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::Internal => "INTERNAL",
+            ErrorCode::InvalidArgument => "INVALID_ARGUMENT",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::AlreadyExists => "ALREADY_EXISTS",
+            ErrorCode::PreconditionFailed => "PRECONDITION_FAILED",
+            ErrorCode::Conflict => "CONFLICT",
+            ErrorCode::QuotaExceeded => "QUOTA_EXCEEDED",
+            ErrorCode::Backpressure => "BACKPRESSURE",
+            ErrorCode::ReadOnly => "READ_ONLY",
+            ErrorCode::Unavailable => "UNAVAILABLE",
+            ErrorCode::SequenceUnavailable => "SEQUENCE_UNAVAILABLE",
+            ErrorCode::Corruption => "CORRUPTION",
+        }
+    }
+}
@@ -1,16 +1,108 @@
 //! Storage engine: coordinates the in-memory BTreeMap (memtable) and the WAL.
 //!
-//! Write path:  WAL append  →  memtable insert  (durable before visible)
-//! Read path:   memtable only  (no SSTables in this iteration)
+//! Write path:  WAL append  →  memtable insert  →  change feed
+//!              (logged before visible; all three under the WAL lock so the
+//!              memtable and the feed observe exactly the WAL order)
+//!              →  sync, shared with concurrent commits, if the sync policy
+//!              asks for one (see `group_commit`)
+//! Read path:   memtable, then the tables it was flushed to, newest first
+//!              (see `sstable`; without `EngineOptions::memtable_flush_bytes`
+//!              there are none, and the memtable holds every key), which a
+//!              background thread merges as they pile up (see `merge`) and
+//!              moves to slower storage as they age (see `tier`)
+//!
+//! Every commit is stamped with the engine's hybrid logical clock (persisted
+//! in `DATA_DIR/hlc`).  Replicated changes keep the primary's timestamp, and
+//! the local clock observes it, so timestamps order writes the same way on
+//! every node.
 
+use std::cell::Cell;
 use std::collections::BTreeMap;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
 use thiserror::Error;
-use tracing::{debug, info};
 
-use crate::wal::{WalError, WalRecord, WriteAheadLog};
+use crate::cache::BlockCache;
+use crate::checkpoint::Checkpoint;
+use crate::code::ErrorCode;
+use crate::feed::{self, Change, ChangeFeed, WalOffsets};
+use crate::group_commit::GroupCommit;
+use crate::hlc::HybridClock;
+use crate::index::{Extractor, Indexes};
+use crate::lease::is_reserved_key;
+use crate::memtable::{Frozen, Memtable, Range};
+use crate::merge::{MergePolicy, MergeStats, Merger};
+use crate::metrics;
+#[cfg(feature = "tracing")]
+use crate::redact::RedactedKey;
+use crate::scan::{KeyRange, Scan};
+use crate::scrub::{self, Pace, ScrubReport};
+use crate::snapshot::{Pins, Snapshot};
+use crate::space::{Garbage, SpaceStats};
+use crate::sstable::{self, Table, Totals};
+use crate::sync::{SyncMethod, SyncPolicy};
+use crate::throttle::Throttle;
+use crate::tier::{self, ColdTier, Tiering};
+use crate::wal::{RecordLimits, WalEntry, WalError, WalOptions, WalReader, WalRecord, WriteAheadLog};
+
+/// Number of recent changes kept in memory for change-feed consumers.
+const FEED_CAPACITY: usize = 65_536;
+
+/// Files an interrupted write leaves behind in a data directory: the temp
+/// files of a checkpoint, of the clock's ceiling, of a WAL upgrade, of a
+/// table, of a merge of tables and of a table being installed.
+const ORPHAN_FILES: &[&str] = &[
+    "checkpoint.tmp",
+    "hlc.tmp",
+    "wal.upgrade",
+    sstable::TEMP_FILE,
+    sstable::MERGE_FILE,
+    sstable::INSTALL_FILE,
+];
+
+/// How an engine is opened.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EngineOptions {
+    pub wal: WalOptions,
+    /// Open even if files an interrupted write left behind are found (see
+    /// `Engine::open`), warning about them instead.
+    pub ignore_orphans: bool,
+    /// When commits are synced.  Has no effect where the WAL is written
+    /// with `O_DSYNC` (see `Engine::sync_method`), as every write is durable.
+    pub sync_policy: SyncPolicy,
+    /// Write each value of at least this many bytes only once per
+    /// checkpoint, however many keys hold it (see `checkpoint`).  Only
+    /// checkpoints share values: the WAL, the memtable and tables keep one
+    /// copy per key, so with `memtable_flush_bytes` set, which replaces the
+    /// checkpoint with tables, this saves nothing.
+    pub dedup_values: Option<usize>,
+    /// Flush the memtable to a table once its keys and values take more
+    /// than this many bytes, so memory holds only recent writes (see
+    /// `sstable`).  `None` keeps every key in memory, and compacts into a
+    /// checkpoint.
+    pub memtable_flush_bytes: Option<u64>,
+    /// When the tables are merged in the background (see `merge`).
+    pub merge: MergePolicy,
+    /// Move tables to slower storage as they age (see `tier`).
+    pub cold_tier: Option<ColdTier>,
+}
+
+impl EngineOptions {
+    /// These options, for an engine in `subdir` of the directory they are
+    /// for: its cold tier is in the same subdirectory of theirs, so that
+    /// engines opened side by side never share one.
+    pub fn within(&self, subdir: impl AsRef<Path>) -> Self {
+        let mut options = self.clone();
+        if let Some(tier) = &mut options.cold_tier {
+            tier.dir = tier.dir.join(subdir);
+        }
+        options
+    }
+}
 
 // ---------------------------------------------------------------------------
 // Error type
@@ -23,6 +115,33 @@ pub enum EngineError {
 
     #[error("Internal lock was poisoned; the process may be in an inconsistent state")]
     LockPoisoned,
+
+    #[error("Replicated change out of order: expected sequence {expected}, got {got}")]
+    SequenceGap { expected: u64, got: u64 },
+
+    #[error("Changes after sequence {requested} are no longer retained (checkpoint covers up to {checkpoint})")]
+    SequenceUnavailable { requested: u64, checkpoint: u64 },
+
+    #[error("Data directory {} is already open in another engine{}", .dir.display(), held_by(.pid))]
+    AlreadyLocked { dir: PathBuf, pid: Option<u32> },
+
+    #[error("Index {0:?} is already registered")]
+    IndexExists(String),
+
+    #[error("No index named {0:?}")]
+    UnknownIndex(String),
+
+    #[error("Data directory {} is inconsistent: {problem}; {hint}", .dir.display())]
+    Inconsistent { dir: PathBuf, problem: String, hint: String },
+
+    #[error("Writes to namespace {namespace:?} are over its limit; retry in {}ms", .retry_after.as_millis().max(1))]
+    Throttled { namespace: String, retry_after: Duration },
+
+    #[error("Write would take the store to {needed} bytes, past its quota of {quota}")]
+    QuotaExceeded { needed: u64, quota: u64 },
+
+    #[error("A WAL sync failed ({0}); writes are refused until the store is reopened")]
+    SyncFailed(String),
 }
 
 /// Map any `PoisonError` variant into `EngineError::LockPoisoned`.
@@ -32,60 +151,318 @@ impl<T> From<PoisonError<T>> for EngineError {
     }
 }
 
+impl EngineError {
+    /// The stable code of this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            EngineError::Wal(e) => e.code(),
+            EngineError::LockPoisoned => ErrorCode::Internal,
+            EngineError::SequenceGap { .. } => ErrorCode::PreconditionFailed,
+            EngineError::SequenceUnavailable { .. } => ErrorCode::SequenceUnavailable,
+            EngineError::AlreadyLocked { .. } => ErrorCode::Conflict,
+            EngineError::IndexExists(_) => ErrorCode::AlreadyExists,
+            EngineError::UnknownIndex(_) => ErrorCode::NotFound,
+            EngineError::Inconsistent { .. } => ErrorCode::Corruption,
+            EngineError::Throttled { .. } => ErrorCode::Backpressure,
+            EngineError::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            EngineError::SyncFailed(_) => ErrorCode::Unavailable,
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Engine
 // ---------------------------------------------------------------------------
 
+/// A test of a value, such as `Engine::set_quota_exempt` takes.
+type ValueTest = fn(&[u8]) -> bool;
+
 /// Thread-safe LSM-inspired key-value engine backed by a WAL.
 ///
 /// Cloning an `Engine` is cheap — both clones share the same storage state.
 #[derive(Clone, Debug)]
 pub struct Engine {
-    /// In-memory sorted map of live key→value pairs.
-    memtable: Arc<RwLock<BTreeMap<String, Vec<u8>>>>,
+    /// Recent writes in a sorted map, over the tables older ones were
+    /// flushed to.
+    memtable: Arc<RwLock<Memtable>>,
+    /// Bytes of the keys and values held in memory.  Only modified while
+    /// the memtable is write-locked.
+    memtable_bytes: Arc<AtomicU64>,
+    /// Bytes of the live keys and values, in memory or not; an estimate
+    /// between flushes (see `memtable`).  Only modified while the memtable
+    /// is write-locked.
+    live_bytes: Arc<AtomicU64>,
+    /// Dead data in the checkpoint, tables and WAL (see `space`).  Only
+    /// modified while the memtable is write-locked.
+    garbage: Arc<Garbage>,
+    /// Secondary indexes over the memtable's values.  Only modified while
+    /// the memtable is write-locked, and locked after it.
+    indexes: Arc<RwLock<Indexes>>,
+    /// Live snapshots, which writes save overwritten values for.  Locked
+    /// after the memtable.
+    pins: Arc<Pins>,
+    /// Per-namespace write limits, checked before the WAL append.
+    throttle: Arc<Throttle>,
+    /// Most bytes of live keys and values puts may grow the store to
+    /// (`u64::MAX`: no quota).
+    storage_quota: Arc<AtomicU64>,
+    /// Values whose puts the storage quota leaves out (see
+    /// `set_quota_exempt`).
+    quota_exempt: Arc<RwLock<Option<ValueTest>>>,
     /// Serialised access to the WAL writer (one writer at a time).
+    /// Commits wait for their sync on `group` after releasing it.
     wal: Arc<Mutex<WriteAheadLog>>,
-    _data_dir: Arc<PathBuf>,
+    /// Syncs shared by concurrent commits, as the sync policy says.
+    group: Arc<GroupCommit>,
+    /// Recently committed records, tagged with their sequence numbers.
+    feed: Arc<ChangeFeed>,
+    /// Where older changes start in the WAL.  Locked after the WAL.
+    wal_offsets: Arc<Mutex<WalOffsets>>,
+    /// Sequence covered by the on-disk checkpoint or tables; the WAL
+    /// continues after it.  Only modified while the WAL lock is held.
+    checkpoint_sequence: Arc<AtomicU64>,
+    /// Stamps every commit; shared with other subsystems of this node.
+    clock: Arc<HybridClock>,
+    /// How the WAL is synced, as found by the self-test at open.
+    sync: SyncMethod,
+    /// Merges the tables in the background.  Dropped, stopping its thread,
+    /// before the data directory is unlocked.
+    merger: Arc<Merger>,
+    /// `DATA_DIR/LOCK`, locked for as long as any clone of the engine lives.
+    _lock: Arc<File>,
+    data_dir: Arc<PathBuf>,
+    /// Smallest value checkpoints store once for all the keys holding it.
+    dedup_values: Option<usize>,
+    /// Memtable size past which it is flushed to a table.
+    flush_bytes: Option<u64>,
+    /// Largest key and value the WAL holds; longer ones read back as
+    /// corruption.
+    limits: RecordLimits,
 }
 
 impl Engine {
     /// Open the engine rooted at `data_dir`.
     ///
-    /// 1. Creates the directory if absent.
-    /// 2. Replays the WAL to rebuild the memtable.
-    /// 3. Opens the WAL in append mode, ready for new writes.
+    /// 1. Creates the directory if absent, locks it against other engines
+    ///    (in this process or another), and checks that files in it can be
+    ///    synced (see `SyncMethod::probe`).
+    /// 2. Opens the tables from the newest base table on, or else loads the
+    ///    checkpoint, if any, whichever is newer; tables and a checkpoint
+    ///    that one supersedes, left by an interrupted `compact`, are deleted.
+    /// 3. Opens the WAL in append mode, rewriting a log in an older format
+    ///    with sequences numbered after the checkpoint's or tables'.
+    /// 4. Replays the WAL on top of them; its records must directly follow
+    ///    them.  Records they already cover, left by a `compact` or flush
+    ///    interrupted before it truncated the log, are skipped.
+    ///
+    /// A directory that fails the consistency check is refused with
+    /// `EngineError::Inconsistent`, naming the problem and how to repair it:
+    /// a WAL that does not continue from the checkpoint, tables with no
+    /// base table beneath them, or temp files a write interrupted by a crash
+    /// left behind (see `ORPHAN_FILES`), which
+    /// `EngineOptions::ignore_orphans` lets through.
     pub fn open(data_dir: impl Into<PathBuf>) -> Result<Self, EngineError> {
+        Self::open_with(data_dir, EngineOptions::default())
+    }
+
+    /// Like `open`, as `options` says.
+    pub fn open_with(data_dir: impl Into<PathBuf>, mut options: EngineOptions) -> Result<Self, EngineError> {
         let data_dir = data_dir.into();
+        if options.memtable_flush_bytes.is_some() {
+            // Longer keys and values would not fit a table.
+            options.wal.limits = options.wal.limits.capped(sstable::MAX_LEN);
+        }
 
         std::fs::create_dir_all(&data_dir).map_err(WalError::Io)?;
+        let lock = lock(&data_dir)?;
+        let sync = SyncMethod::probe(&data_dir).map_err(WalError::Io)?;
+        let policy = match sync {
+            SyncMethod::Fdatasync => options.sync_policy,
+            SyncMethod::Dsync => SyncPolicy::Os,
+        };
+        // Before the WAL is opened, which may clear an upgrade's temp file.
+        check_orphans(&data_dir, options.ignore_orphans)?;
 
         let wal_path = data_dir.join("wal.log");
 
+        // ── Load the newest base: a base table, or the checkpoint ───────────
+        let checkpoint_path = data_dir.join("checkpoint");
+        let checkpointed = Checkpoint::read_sequence(&checkpoint_path)?;
+        let cache = match &options.cold_tier {
+            Some(tier) => BlockCache::new(tier.cache_bytes, tier.prefetch_blocks),
+            None => BlockCache::new(0, 0),
+        };
+        let cache = Arc::new(cache);
+        let mut tables = Table::open_all(&data_dir)?;
+        if let Some(cold_dir) = tier::open(&data_dir, options.cold_tier.as_ref())? {
+            for table in Table::open_all(&cold_dir)? {
+                // Copied to the cold tier, but not yet deleted here, or
+                // merged into a table that took its file here.
+                if tables.iter().any(|hot| hot.sequence() == table.sequence()) {
+                    remove_file(table.path())?;
+                } else {
+                    tables.push(table.into_cold(cache.clone()));
+                }
+            }
+            tables.sort_by_key(|table| table.sequence());
+        }
+        let mut tables: Vec<Arc<Table>> = tables.into_iter().map(Arc::new).collect();
+        let newest_base = tables
+            .iter()
+            .rposition(|table| table.is_base())
+            .filter(|&i| checkpointed.is_none_or(|sequence| tables[i].sequence() >= sequence));
+        let superseded: Vec<Arc<Table>> = match newest_base {
+            Some(i) => tables.drain(..i).collect(),
+            None => {
+                let covered = tables.iter().take_while(|table| table.sequence() <= checkpointed.unwrap_or(0)).count();
+                tables.drain(..covered).collect()
+            }
+        };
+        if let (None, Some(table)) = (newest_base, tables.first()) {
+            return Err(EngineError::Inconsistent {
+                dir:     data_dir,
+                problem: format!(
+                    "table {} has no base table or checkpoint beneath it",
+                    table.path().display()
+                ),
+                hint:    "the tables it was written over are lost; restore the directory from a backup, \
+                          or re-bootstrap a replica from its primary"
+                    .to_owned(),
+            });
+        }
+        for table in &superseded {
+            remove_file(table.path())?;
+        }
+        let (map, totals) = match tables.last() {
+            Some(newest) => {
+                if checkpointed.is_some() {
+                    remove_file(&checkpoint_path)?;
+                }
+                (BTreeMap::new(), newest.totals())
+            }
+            None => {
+                let checkpoint = Checkpoint::read_with(&checkpoint_path, options.wal.limits)?.unwrap_or_default();
+                let map: BTreeMap<String, Vec<u8>> = checkpoint.entries.into_iter().collect();
+                let (live_keys, live_bytes) = (map.len() as u64, memtable_size(&map));
+                (map, Totals { live_keys, live_bytes, ..Default::default() })
+            }
+        };
+        let base = tables.last().map_or(checkpointed.unwrap_or(0), |newest| newest.sequence());
+        let mut mem = Memtable::new(map, tables, totals.live_keys as usize);
+
         // ── Replay WAL ──────────────────────────────────────────────────────
-        let records  = WriteAheadLog::recover(&wal_path)?;
-        let mut map  = BTreeMap::new();
+        // Before the WAL is opened for appending, so that a torn last append,
+        // or a damaged end a tolerant recovery drops, is cut off before an
+        // upgrade rewrites the log or new records follow it.
+        let recovered = WriteAheadLog::recover_from(&wal_path, options.wal.limits, options.wal.recovery)?;
+        if let Some(dropped) = &recovered.dropped {
+            warn!(
+                path   = %wal_path.display(),
+                offset = dropped.offset,
+                bytes  = dropped.bytes,
+                error  = %dropped.error,
+                "Dropping the damaged end of the WAL"
+            );
+            metrics::wal_dropped(matches!(dropped.error, WalError::TornTail { .. }), dropped.bytes);
+            WriteAheadLog::truncate_at(&wal_path, dropped.offset, sync)?;
+        }
 
-        for record in &records {
-            match record {
-                WalRecord::Put { key, value } => { map.insert(key.clone(), value.clone()); }
-                WalRecord::Delete { key }     => { map.remove(key); }
+        // ── Open WAL for appending ──────────────────────────────────────────
+        let wal = WriteAheadLog::open_with(&wal_path, options.wal, base, sync)?;
+        // An older log was numbered on from `base` as it was upgraded.
+        let mut records = if recovered.sequenced {
+            recovered.entries
+        } else {
+            WriteAheadLog::recover_with(&wal_path, options.wal.limits)?
+        };
+        if let Some(first) = records.first() {
+            if first.sequence > base + 1 {
+                return Err(EngineError::Inconsistent {
+                    dir:     data_dir,
+                    problem: format!(
+                        "the WAL starts at sequence {} but the checkpoint and tables only cover up to {base}",
+                        first.sequence
+                    ),
+                    hint:    "the records between are lost; restore the directory from a backup, \
+                              or re-bootstrap a replica from its primary"
+                        .to_owned(),
+                });
             }
         }
+        records.retain(|entry| entry.sequence > base);
+
+        let garbage    = Garbage::default();
+        let live_bytes = AtomicU64::new(totals.live_bytes);
+        let mut indexes = Indexes::default();
+        let pins = Pins::default();
+        garbage.set(totals.dead_bytes, totals.tombstones);
+        for WalEntry { record, .. } in &records {
+            apply(&mut mem, &live_bytes, &garbage, &mut indexes, &pins, record);
+        }
 
         info!(
             data_dir  = %data_dir.display(),
-            recovered = map.len(),
+            recovered = mem.len(),
+            tables    = mem.tables().len(),
             wal_ops   = records.len(),
+            sync      = ?sync,
             "Engine initialised"
         );
 
-        // ── Open WAL for appending ──────────────────────────────────────────
-        let wal = WriteAheadLog::open(&wal_path)?;
+        // ── Seed the change feed with the tail of the log ───────────────────
+        let latest = records.last().map_or(base, |entry| entry.sequence);
+        let feed   = ChangeFeed::new(latest, FEED_CAPACITY);
+        let tail   = records.len().saturating_sub(FEED_CAPACITY);
+
+        // Resume the clock after the newest logged timestamp, even if its
+        // ceiling file was lost.
+        let clock = HybridClock::open(data_dir.join("hlc")).map_err(WalError::Io)?;
+        if let Some(newest) = records.iter().map(|e| e.timestamp).max() {
+            clock.observe(newest);
+        }
+
+        for entry in records.into_iter().skip(tail) {
+            feed.publish(Change {
+                sequence:  entry.sequence,
+                timestamp: entry.timestamp,
+                record:    entry.record,
+            })?;
+        }
+
+        metrics::recovery();
+        let (bytes, tables) = (mem.resident(), mem.tables().len());
+        metrics::memtable(&data_dir, mem.len(), bytes);
+        metrics::tables(&data_dir, tables);
+
+        let memtable = Arc::new(RwLock::new(mem));
+        let garbage  = Arc::new(garbage);
+        let tiering  = options.cold_tier.map(|policy| Tiering::new(policy, cache));
+        let merger   = Merger::new(options.merge, data_dir.clone(), memtable.clone(), garbage.clone(), tiering);
+        merger.request(tables);
 
         Ok(Self {
-            memtable:  Arc::new(RwLock::new(map)),
-            wal:       Arc::new(Mutex::new(wal)),
-            _data_dir: Arc::new(data_dir),
+            memtable,
+            memtable_bytes: Arc::new(AtomicU64::new(bytes)),
+            live_bytes: Arc::new(live_bytes),
+            garbage,
+            indexes:  Arc::new(RwLock::new(indexes)),
+            pins:     Arc::new(pins),
+            throttle: Arc::new(Throttle::default()),
+            storage_quota: Arc::new(AtomicU64::new(u64::MAX)),
+            quota_exempt: Arc::new(RwLock::new(None)),
+            wal:      Arc::new(Mutex::new(wal)),
+            group:    Arc::new(GroupCommit::new(policy, base)),
+            feed:     Arc::new(feed),
+            wal_offsets: Arc::new(Mutex::new(WalOffsets::default())),
+            checkpoint_sequence: Arc::new(AtomicU64::new(base)),
+            clock:    Arc::new(clock),
+            sync,
+            merger:   Arc::new(merger),
+            _lock:    Arc::new(lock),
+            data_dir: Arc::new(data_dir),
+            dedup_values: options.dedup_values,
+            flush_bytes: options.memtable_flush_bytes,
+            limits: options.wal.limits,
         })
     }
 
@@ -96,45 +473,791 @@ impl Engine {
     /// The WAL entry is flushed before the memtable is updated so that a crash
     /// between the two steps is recoverable on restart.
     pub fn put(&self, key: String, value: Vec<u8>) -> Result<(), EngineError> {
-        debug!(key = %key, bytes = value.len(), "PUT");
+        debug!(key = %RedactedKey(&key), bytes = value.len(), "PUT");
+        self.commit(WalRecord::Put { key, value }, None, None)?;
+        Ok(())
+    }
+
+    /// Remove `key` from the store.  
+    /// Returns `true` if the key existed, `false` otherwise.
+    ///
+    /// Writes do not read the tables, so a delete of a key only they hold
+    /// reads them first to say whether it existed, with other writers, but
+    /// not readers, held back meanwhile.
+    pub fn delete(&self, key: &str) -> Result<bool, EngineError> {
+        debug!(key = %RedactedKey(key), "DELETE");
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
+        let existed = self.memtable.read()?.contains_key(key)?;
+        self.commit_locked(&mut wal, WalRecord::Delete { key: key.to_owned() }, None, None)?;
+        self.finish(wal, started)?;
+        Ok(existed)
+    }
 
+    /// Delete `key` if its value is `expected`.  Returns whether it was
+    /// deleted.
+    pub fn compare_and_delete(&self, key: &str, expected: &[u8]) -> Result<bool, EngineError> {
+        debug!(key = %RedactedKey(key), "COMPARE AND DELETE");
+        // Writes are serialised on the WAL lock, so the value compared is
+        // the one the delete removes.
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
+        if self.memtable.read()?.get(key)?.as_deref() != Some(expected) {
+            return Ok(false);
+        }
+        self.commit_locked(&mut wal, WalRecord::Delete { key: key.to_owned() }, None, None)?;
+        self.finish(wal, started)?;
+        Ok(true)
+    }
+
+    /// Set `key` to `value`, returning the value it replaced, if any.
+    pub fn get_and_set(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, EngineError> {
+        debug!(key = %RedactedKey(&key), bytes = value.len(), "GET AND SET");
+        let started  = Instant::now();
+        let mut wal  = self.wal.lock()?;
+        let previous = self.memtable.read()?.get(&key)?;
+        self.commit_locked(&mut wal, WalRecord::Put { key, value }, None, None)?;
+        self.finish(wal, started)?;
+        Ok(previous)
+    }
+
+    /// Move the value of `old_key` to `new_key`, replacing any value there
+    /// only if `overwrite` is set.  Returns whether it was moved: not if
+    /// `old_key` is absent, or `new_key` exists and `overwrite` is not set.
+    ///
+    /// The put of `new_key` and the delete of `old_key` are logged as one
+    /// batch (see `write_batch`), so a crash never leaves the value under
+    /// both keys or under neither.  Replicas receive the put first.
+    pub fn rename(&self, old_key: &str, new_key: String, overwrite: bool) -> Result<bool, EngineError> {
+        debug!(key = %RedactedKey(old_key), to = %RedactedKey(&new_key), overwrite, "RENAME");
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
+        let value = {
+            let mem = self.memtable.read()?;
+            let Some(value) = mem.get(old_key)? else { return Ok(false) };
+            if old_key == new_key {
+                return Ok(true);
+            }
+            if !overwrite && mem.contains_key(&new_key)? {
+                return Ok(false);
+            }
+            value
+        };
+        let records = vec![WalRecord::Put { key: new_key, value }, WalRecord::Delete { key: old_key.to_owned() }];
+        self.write_batch_locked(&mut wal, records)?;
+        self.finish(wal, started)?;
+        Ok(true)
+    }
+
+    /// Replace the value of `key` with what `change` computes from it
+    /// (`None` if the key is absent), or leave it alone if `change` returns
+    /// `None`.  Returns the value written, if any.
+    ///
+    /// No other write can come between the read and the write, so `change`
+    /// should be quick: every writer waits on it.
+    pub fn update(
+        &self,
+        key: String,
+        change: impl FnOnce(Option<&[u8]>) -> Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, EngineError> {
+        debug!(key = %RedactedKey(&key), "UPDATE");
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
+        let current = self.memtable.read()?.get(&key)?;
+        let Some(value) = change(current.as_deref()) else {
+            return Ok(None);
+        };
+        self.commit_locked(&mut wal, WalRecord::Put { key, value: value.clone() }, None, None)?;
+        self.finish(wal, started)?;
+        Ok(Some(value))
+    }
+
+    /// Run `writes`, with their commits returning before they are durable,
+    /// then wait once for a sync covering them all (under a sync policy
+    /// that waits at all; see `group_commit`).  For callers that hold a
+    /// lock of their own across their writes: taken and released inside
+    /// `writes`, it is not held through the wait, so other writers can
+    /// commit meanwhile and share the sync.
+    pub fn deferring_sync<T, E: From<EngineError>>(&self, writes: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let (result, pending) = self.group.deferring(writes);
+        if let Some((sequence, started)) = pending {
+            self.await_sync(sequence, started)?;
+        }
+        result
+    }
+
+    /// Apply every record in `records` atomically.
+    ///
+    /// The batch is logged as a single WAL entry, so after a crash either all
+    /// of it is recovered or none of it, and readers see it applied at once.
+    /// Each record still takes its own sequence number (and is replicated on
+    /// its own), with the batch's timestamp.
+    pub fn write_batch(&self, records: Vec<WalRecord>) -> Result<(), EngineError> {
+        debug!(records = records.len(), "BATCH");
+        if records.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
+        self.write_batch_locked(&mut wal, records)?;
+        self.finish(wal, started)
+    }
+
+    /// Apply `records` atomically, as `write_batch` does, if every key in
+    /// `expected` still holds the value given with it (`None`: absent).
+    /// Returns whether they were applied.
+    pub fn write_batch_if(
+        &self,
+        expected: &[(String, Option<Vec<u8>>)],
+        records: Vec<WalRecord>,
+    ) -> Result<bool, EngineError> {
+        debug!(expected = expected.len(), records = records.len(), "CONDITIONAL BATCH");
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
         {
-            let mut wal = self.wal.lock()?;
-            wal.append(&WalRecord::Put { key: key.clone(), value: value.clone() })?;
+            let mem = self.memtable.read()?;
+            for (key, value) in expected {
+                if mem.get(key)? != *value {
+                    return Ok(false);
+                }
+            }
+        }
+        if !records.is_empty() {
+            self.write_batch_locked(&mut wal, records)?;
+            self.finish(wal, started)?;
         }
+        Ok(true)
+    }
 
-        let mut mem = self.memtable.write()?;
-        mem.insert(key, value);
+    fn write_batch_locked(&self, wal: &mut WriteAheadLog, records: Vec<WalRecord>) -> Result<(), EngineError> {
+        self.group.check()?;
+        self.throttle.admit(&records)?;
+        self.check_quota(&records)?;
+        self.flush_if_full(wal)?;
+        let first     = self.feed.latest()? + 1;
+        let timestamp = self.clock.now();
+        wal.append_batch(&records, first, timestamp)?;
+
+        let keys = {
+            let mut mem     = self.memtable.write()?;
+            let mut indexes = self.indexes.write()?;
+            for record in &records {
+                apply(&mut mem, &self.live_bytes, &self.garbage, &mut indexes, &self.pins, record);
+            }
+            self.memtable_bytes.store(mem.resident(), Ordering::Relaxed);
+            mem.len()
+        };
+        metrics::memtable(&self.data_dir, keys, self.memtable_bytes());
 
+        for (i, record) in records.into_iter().enumerate() {
+            self.feed.publish(Change { sequence: first + i as u64, timestamp, record })?;
+        }
         Ok(())
     }
 
-    /// Remove `key` from the store.  
-    /// Returns `true` if the key existed, `false` otherwise.
-    pub fn delete(&self, key: &str) -> Result<bool, EngineError> {
-        debug!(key = %key, "DELETE");
+    /// Apply a change received from a primary.
+    ///
+    /// The change must carry exactly the next local sequence number, so the
+    /// replica's WAL stays position-for-position identical to the primary's.
+    /// It keeps the primary's timestamp unless it has none.
+    pub fn apply_replicated(&self, change: Change) -> Result<(), EngineError> {
+        debug!(sequence = change.sequence, "APPLY");
+        let timestamp = (change.timestamp > 0).then_some(change.timestamp);
+        self.commit(change.record, Some(change.sequence), timestamp)?;
+        Ok(())
+    }
 
-        {
-            let mut wal = self.wal.lock()?;
-            wal.append(&WalRecord::Delete { key: key.to_owned() })?;
+    /// Replace the entire store with `checkpoint` (replica bootstrap); see
+    /// `install_snapshot`.
+    pub fn install_checkpoint(&self, checkpoint: Checkpoint) -> Result<(), EngineError> {
+        self.install_snapshot(checkpoint.sequence, checkpoint.entries.into_iter().map(Ok))
+    }
+
+    /// Replace the entire store with `entries`, every live key as of
+    /// `sequence` in key order (replica bootstrap).  The store's own history
+    /// must be older than `sequence`, as a bootstrapping replica's is.
+    ///
+    /// With `EngineOptions::memtable_flush_bytes` set, the entries are
+    /// written to a base table as they are read, so they need not fit in
+    /// memory, and the memtable starts out empty; without, they are loaded
+    /// into it and written to the checkpoint.  They are read, and the table
+    /// written, before any lock is taken, so reads and writes go on
+    /// meanwhile; a running merge of the tables is waited for after.  An
+    /// error among the entries, or one out of order, leaves the store as it
+    /// was.
+    ///
+    /// The new table or checkpoint is renamed into place before the WAL is
+    /// truncated, and the tables and checkpoint it supersedes are deleted
+    /// last, so a crash in between leaves records and files it covers,
+    /// which `open` skips and deletes.
+    pub fn install_snapshot(
+        &self,
+        sequence: u64,
+        entries: impl IntoIterator<Item = Result<(String, Vec<u8>), WalError>>,
+    ) -> Result<(), EngineError> {
+        let counted = Cell::new((0u64, 0u64));
+        let mut last: Option<String> = None;
+        let entries = entries.into_iter().map(|entry| {
+            let (key, value) = entry?;
+            if last.as_ref().is_some_and(|last| *last >= key) {
+                return Err(WalError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("snapshot key {key:?} is out of order"),
+                )));
+            }
+            let (keys, bytes) = counted.get();
+            counted.set((keys + 1, bytes + (key.len() + value.len()) as u64));
+            last = Some(key.clone());
+            Ok((key, value))
+        });
+        let totals = || {
+            let (live_keys, live_bytes) = counted.get();
+            Totals { live_keys, live_bytes, ..Default::default() }
+        };
+        let checkpoint = match self.flush_bytes {
+            Some(_) => {
+                let entries = entries.map(|entry| entry.map(|(key, value)| (key, Some(value))));
+                Table::stage(&self.data_dir, sstable::INSTALL_FILE, sequence, true, totals, entries)?;
+                None
+            }
+            None => Some(Checkpoint { sequence, entries: entries.collect::<Result<_, _>>()? }),
+        };
+        let (keys, bytes) = counted.get();
+
+        let _merging = self.merger.exclusive();
+        let mut wal  = self.wal.lock()?;
+        info!(sequence, entries = keys, "Installing snapshot");
+
+        let checkpoint_path = self.data_dir.join("checkpoint");
+        let (resident, tables) = match checkpoint {
+            Some(checkpoint) => {
+                checkpoint.write_with(&checkpoint_path, self.dedup_values)?;
+                (checkpoint.entries.into_iter().collect(), Vec::new())
+            }
+            None => {
+                let table = Table::place(&self.data_dir, sstable::INSTALL_FILE, sequence)?;
+                (BTreeMap::new(), vec![Arc::new(table)])
+            }
+        };
+        wal.truncate()?;
+
+        let next = Memtable::new(resident, tables, keys as usize);
+        let superseded = {
+            let mut mem = self.memtable.write()?;
+            self.pins.preserve_all(&mem, &next)?;
+            self.indexes.write()?.rebuild(next.iter()?)?;
+            let superseded = mem.tables().to_vec();
+            *mem = next;
+            self.memtable_bytes.store(mem.resident(), Ordering::Relaxed);
+            self.live_bytes.store(bytes, Ordering::Relaxed);
+            self.garbage.clear();
+            metrics::tables(&self.data_dir, mem.tables().len());
+            superseded
+        };
+        metrics::memtable(&self.data_dir, keys as usize, self.memtable_bytes());
+        self.checkpoint_sequence.store(sequence, Ordering::SeqCst);
+        self.feed.reset(sequence)?;
+        self.group.reset(sequence);
+
+        // A new base table of the same sequence has replaced the file of an
+        // old one already, unless it was in the cold tier.
+        let table_path = self.flush_bytes.map(|_| self.data_dir.join(sstable::file_name(sequence)));
+        for table in superseded.iter().filter(|table| Some(table.path()) != table_path.as_deref()) {
+            remove_file(table.path())?;
         }
+        if self.flush_bytes.is_some() {
+            remove_file(&checkpoint_path)?;
+        }
+        Ok(())
+    }
 
-        let mut mem = self.memtable.write()?;
-        Ok(mem.remove(key).is_some())
+    /// Append `record` to the WAL, apply it to the memtable and publish it to
+    /// the change feed.
+    ///
+    /// When `expected_sequence` is set the commit is rejected unless it would
+    /// receive exactly that sequence number.  A given `timestamp` (from
+    /// another node) is kept and observed; otherwise the local clock stamps
+    /// the commit.
+    fn commit(
+        &self,
+        record: WalRecord,
+        expected_sequence: Option<u64>,
+        timestamp: Option<u64>,
+    ) -> Result<(), EngineError> {
+        let started = Instant::now();
+        let mut wal = self.wal.lock()?;
+        self.commit_locked(&mut wal, record, expected_sequence, timestamp)?;
+        self.finish(wal, started)
+    }
+
+    /// Release the WAL lock of a write that began at `started` and, if the
+    /// sync policy says so, wait until what it committed is durable.
+    fn finish(&self, wal: MutexGuard<'_, WriteAheadLog>, started: Instant) -> Result<(), EngineError> {
+        if !self.group.enabled() {
+            return Ok(());
+        }
+        let sequence = self.feed.latest()?;
+        drop(wal);
+        if self.group.defer(sequence) {
+            return Ok(());
+        }
+        self.await_sync(sequence, started)
+    }
+
+    /// Wait until `sequence`, committed by a write that began at `started`,
+    /// is durable.
+    fn await_sync(&self, sequence: u64, started: Instant) -> Result<(), EngineError> {
+        self.group.wait(sequence, started, || {
+            let (file, through) = {
+                let mut wal = self.wal.lock()?;
+                (wal.sync_handle()?, self.feed.latest()?)
+            };
+            self.sync.sync(&file).map_err(WalError::Io)?;
+            Ok(through)
+        })
+    }
+
+    /// `commit`, with the WAL lock already held.
+    fn commit_locked(
+        &self,
+        wal: &mut WriteAheadLog,
+        record: WalRecord,
+        expected_sequence: Option<u64>,
+        timestamp: Option<u64>,
+    ) -> Result<(), EngineError> {
+        self.group.check()?;
+        let sequence = self.feed.latest()? + 1;
+
+        if let Some(got) = expected_sequence {
+            if got != sequence {
+                return Err(EngineError::SequenceGap { expected: sequence, got });
+            }
+        } else {
+            self.throttle.admit([&record])?;
+            self.check_quota([&record])?;
+        }
+
+        let timestamp = match timestamp {
+            Some(remote) => {
+                self.clock.observe(remote);
+                remote
+            }
+            None => self.clock.now(),
+        };
+        self.flush_if_full(wal)?;
+        wal.append(&record, sequence, timestamp)?;
+
+        let keys = {
+            let mut mem     = self.memtable.write()?;
+            let mut indexes = self.indexes.write()?;
+            apply(&mut mem, &self.live_bytes, &self.garbage, &mut indexes, &self.pins, &record);
+            self.memtable_bytes.store(mem.resident(), Ordering::Relaxed);
+            mem.len()
+        };
+        metrics::memtable(&self.data_dir, keys, self.memtable_bytes());
+
+        self.feed.publish(Change { sequence, timestamp, record })?;
+        Ok(())
+    }
+
+    /// Refuse `records` with `EngineError::QuotaExceeded` if they would
+    /// grow the live data past the storage quota, net of what their deletes
+    /// free.  Writes that do not
+    /// grow it, and writes to reserved keys, are always let through.  Puts
+    /// of exempt values count like deletes.  What a write replaces is only
+    /// known for keys in memory: one over a key only the tables hold counts
+    /// in full, as it does in `live_bytes` until the next flush.
+    fn check_quota<'a>(&self, records: impl IntoIterator<Item = &'a WalRecord>) -> Result<(), EngineError> {
+        let quota = self.storage_quota.load(Ordering::Relaxed);
+        if quota == u64::MAX {
+            return Ok(());
+        }
+        let exempt = *self.quota_exempt.read()?;
+        let mem = self.memtable.read()?;
+        let (mut added, mut removed) = (0u64, 0u64);
+        for record in records {
+            let key = record_key(record);
+            if is_reserved_key(key) {
+                continue;
+            }
+            if let WalRecord::Put { value, .. } = record {
+                if !exempt.is_some_and(|exempt| exempt(value)) {
+                    added += (key.len() + value.len()) as u64;
+                }
+            }
+            removed += mem.unflushed(key).flatten().map_or(0, |old| (key.len() + old.len()) as u64);
+        }
+        let needed = self.live_bytes() + added.saturating_sub(removed);
+        if added > removed && needed > quota {
+            return Err(EngineError::QuotaExceeded { needed, quota });
+        }
+        Ok(())
     }
 
     // ── Read operations ─────────────────────────────────────────────────────
 
     /// Look up `key`.  Returns `None` if the key does not exist.
     pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, EngineError> {
-        debug!(key = %key, "GET");
+        debug!(key = %RedactedKey(key), "GET");
         let mem = self.memtable.read()?;
-        Ok(mem.get(key).cloned())
+        Ok(mem.get(key)?)
+    }
+
+    /// The live keys in `range` with their values, in key order, as an
+    /// iterator over a snapshot taken now (see `scan`): it holds the
+    /// memtable for a page of keys at a time, and sees no write made after
+    /// it was opened.  `KeyRange::prefix` selects the keys under a prefix.
+    pub fn scan(&self, range: KeyRange) -> Result<Scan, EngineError> {
+        debug!(prefix = %RedactedKey(&range.prefix), "SCAN");
+        Ok(Scan::new(self.snapshot()?, range))
+    }
+
+    /// Up to `limit` live keys starting with `prefix` and sorting after
+    /// `after` (from the first if `None`), with their values, in key order.
+    /// A long scan taken a page at a time holds the memtable for one page
+    /// at a time.
+    pub fn scan_page(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, EngineError> {
+        debug!(prefix = %RedactedKey(prefix), after = ?after, limit, "SCAN PAGE");
+        let range = KeyRange::prefix(prefix);
+        let mem   = self.memtable.read()?;
+        let scan  = page(mem.range(range.first(after))?, &range, limit)?;
+        Ok(scan)
+    }
+
+    /// Pin a consistent view of every key as of the latest commit.  Writes
+    /// made while it is held do not show through it (see `snapshot`).
+    pub fn snapshot(&self) -> Result<Snapshot, EngineError> {
+        let _wal = self.wal.lock()?;
+        self.pins.pin(self.memtable.clone(), self.feed.latest()?)
+    }
+
+    // ── Secondary indexes ───────────────────────────────────────────────────
+
+    /// Register index `name`, which finds each key under the index values
+    /// `extract` derives from its value (see `index`).  It is built from the
+    /// keys stored now, with writes held back meanwhile.
+    pub fn register_index(
+        &self,
+        name: impl Into<String>,
+        extract: impl Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static,
+    ) -> Result<(), EngineError> {
+        let name = name.into();
+        let extract: Extractor = Arc::new(extract);
+
+        let mem = self.memtable.write()?;
+        if !self.indexes.write()?.register(name.clone(), extract, mem.iter()?)? {
+            return Err(EngineError::IndexExists(name));
+        }
+        info!(index = %name, keys = mem.len(), "Index registered");
+        Ok(())
+    }
+
+    /// Keys whose values index `index` finds under `value`, in key order.
+    pub fn query_index(&self, index: &str, value: &[u8]) -> Result<Vec<String>, EngineError> {
+        debug!(index = %index, "QUERY INDEX");
+        // Locked in the writers' order, so the index matches the memtable.
+        let _mem    = self.memtable.read()?;
+        let indexes = self.indexes.read()?;
+        indexes.query(index, value).ok_or_else(|| EngineError::UnknownIndex(index.to_owned()))
+    }
+
+    /// Names of the registered indexes, sorted.
+    pub fn indexes(&self) -> Result<Vec<String>, EngineError> {
+        Ok(self.indexes.read()?.names())
+    }
+
+    // ── Change feed ─────────────────────────────────────────────────────────
+
+    /// This node's hybrid logical clock.
+    pub fn clock(&self) -> &Arc<HybridClock> {
+        &self.clock
+    }
+
+    /// Sequence number of the last committed record (0 for an empty store).
+    pub fn latest_sequence(&self) -> Result<u64, EngineError> {
+        self.feed.latest()
+    }
+
+    /// Sequence covered by the on-disk checkpoint, or the newest table.
+    /// Changes at or below it are no longer available individually; consumers that need them must start
+    /// from a checkpoint instead.
+    pub fn checkpoint_sequence(&self) -> u64 {
+        self.checkpoint_sequence.load(Ordering::SeqCst)
+    }
+
+    /// Up to `limit` committed changes with a sequence greater than `after`,
+    /// in order.
+    ///
+    /// Recent changes are served from memory; older ones are read from the
+    /// WAL, on from the record an earlier read found them to start at (see
+    /// `feed::WalOffsets`).  The WAL lock is held to open the log and to
+    /// check afterwards that no flush emptied it meanwhile, not for the read.
+    pub fn changes_since(&self, after: u64, limit: usize) -> Result<Vec<Change>, EngineError> {
+        loop {
+            if let Some(changes) = self.feed.since(after, limit)? {
+                return Ok(changes);
+            }
+
+            let (mut reader, generation) = {
+                let wal  = self.wal.lock()?;
+                let base = self.checkpoint_sequence();
+                if after < base {
+                    return Err(EngineError::SequenceUnavailable { requested: after, checkpoint: base });
+                }
+                let offset = self.wal_offsets.lock()?.start(wal.generation(), after);
+                (WalReader::follow_with(wal.path(), offset, self.limits)?, wal.generation())
+            };
+            let read = feed::read_wal(&mut reader, after, limit);
+
+            // Emptied under the read, the log may have been read in part or
+            // not at all; the changes are in the feed or past the base now.
+            let wal = self.wal.lock()?;
+            if wal.generation() == generation {
+                let (changes, starts) = read?;
+                self.wal_offsets.lock()?.extend(generation, starts);
+                return Ok(changes);
+            }
+        }
+    }
+
+    /// Consistent copy of every live key together with the sequence it
+    /// reflects.  Writers are blocked while the memtable is copied.
+    pub fn checkpoint(&self) -> Result<Checkpoint, EngineError> {
+        let _wal = self.wal.lock()?;
+        let mem  = self.memtable.read()?;
+        let entries = mem.iter()?.collect::<Result<_, _>>()?;
+
+        Ok(Checkpoint { sequence: self.feed.latest()?, entries })
+    }
+
+    /// Checkpoint every live key and empty the WAL, so the data directory
+    /// holds each key once instead of its whole history.  With
+    /// `EngineOptions::memtable_flush_bytes` set, the keys are written to a
+    /// base table in place of the checkpoint and the tables before it.
+    ///
+    /// The checkpoint is renamed into place before the WAL is truncated; a
+    /// crash in between leaves records the checkpoint covers, which `open`
+    /// skips.  Tables and a checkpoint it supersedes are deleted last, or
+    /// by `open`.  Writers are blocked for the duration, and a running
+    /// merge of the tables is waited for first.  Changes up to the new
+    /// checkpoint are then only available from memory, and from a
+    /// checkpoint once the engine is reopened.
+    pub fn compact(&self) -> Result<Checkpoint, EngineError> {
+        let _merging = self.merger.exclusive();
+        let mut wal  = self.wal.lock()?;
+        let (checkpoint, superseded) = {
+            let mem = self.memtable.read()?;
+            let checkpoint = Checkpoint {
+                sequence: self.feed.latest()?,
+                entries:  mem.iter()?.collect::<Result<_, _>>()?,
+            };
+            (checkpoint, mem.tables().to_vec())
+        };
+
+        let checkpoint_path = self.data_dir.join("checkpoint");
+        let (sequence, keys) = (checkpoint.sequence, checkpoint.entries.len());
+        // Exact, where the live counts are estimates until the next flush.
+        let bytes = checkpoint.entries.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum();
+        let table_path = self.flush_bytes.map(|_| self.data_dir.join(sstable::file_name(sequence)));
+        let next = match self.flush_bytes {
+            Some(_) => {
+                let totals  = Totals { live_keys: keys as u64, live_bytes: bytes, ..Default::default() };
+                let entries = checkpoint.entries.iter().map(|(key, value)| Ok((key, Some(value))));
+                let table   = Table::write(&self.data_dir, sstable::TEMP_FILE, sequence, true, || totals, entries)?;
+                wal.truncate()?;
+                Some(Memtable::new(BTreeMap::new(), vec![Arc::new(table)], keys))
+            }
+            None => {
+                checkpoint.write_with(&checkpoint_path, self.dedup_values)?;
+                wal.truncate()?;
+                // Only needed if the tables hold some of the keys.
+                let resident = || checkpoint.entries.iter().cloned().collect();
+                (!superseded.is_empty()).then(|| Memtable::new(resident(), Vec::new(), keys))
+            }
+        };
+        self.checkpoint_sequence.store(sequence, Ordering::SeqCst);
+        if let Some(next) = next {
+            let mut mem = self.memtable.write()?;
+            *mem = next;
+            self.memtable_bytes.store(mem.resident(), Ordering::Relaxed);
+        }
+        self.live_bytes.store(bytes, Ordering::Relaxed);
+        self.garbage.clear();
+        metrics::memtable(&self.data_dir, keys, self.memtable_bytes());
+        metrics::tables(&self.data_dir, usize::from(self.flush_bytes.is_some()));
+
+        // A new base table of the same sequence has replaced the file of the
+        // newest one already, unless it was in the cold tier.
+        for table in superseded.iter().filter(|table| Some(table.path()) != table_path.as_deref()) {
+            remove_file(table.path())?;
+        }
+        if self.flush_bytes.is_some() {
+            remove_file(&checkpoint_path)?;
+        }
+
+        info!(
+            sequence,
+            entries  = keys,
+            tables   = superseded.len(),
+            "WAL compacted into checkpoint"
+        );
+        Ok(checkpoint)
+    }
+
+    /// Flush the memtable to a table now, whatever its size, and empty the
+    /// WAL, as happens on its own past `EngineOptions::memtable_flush_bytes`
+    /// (see `sstable`).  Returns the table's sequence, or `None` if nothing
+    /// was written since the last flush.
+    ///
+    /// As after `compact`, changes up to the table are then only available
+    /// from memory, and from a checkpoint once the engine is reopened.
+    pub fn flush(&self) -> Result<Option<u64>, EngineError> {
+        let mut wal = self.wal.lock()?;
+        self.flush_locked(&mut wal)
+    }
+
+    /// Flush the memtable if it has grown past the flush threshold.  Called
+    /// before a write is logged, so a failed flush fails the write.
+    fn flush_if_full(&self, wal: &mut WriteAheadLog) -> Result<(), EngineError> {
+        if self.flush_bytes.is_some_and(|limit| self.memtable_bytes() > limit) {
+            self.flush_locked(wal)?;
+        }
+        Ok(())
+    }
+
+    /// `flush`, with the WAL lock already held.
+    ///
+    /// The first table written over none holds every live key, and
+    /// supersedes the checkpoint; later ones hold what was written since.
+    /// The entries are frozen, and the table written, with the memtable
+    /// unlocked, so readers go on reading them meanwhile; writers wait on
+    /// the WAL lock, so nothing is committed in between.  The table is
+    /// renamed into place before the WAL is truncated, so a crash in
+    /// between leaves records it covers, which `open` skips.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn flush_locked(&self, wal: &mut WriteAheadLog) -> Result<Option<u64>, EngineError> {
+        let sequence = self.feed.latest()?;
+        let (frozen, keys, bytes) = {
+            let mut mem = self.memtable.write()?;
+            let bytes = mem.resident();
+            let Some(frozen) = mem.freeze() else { return Ok(None) };
+            (frozen, mem.len(), bytes)
+        };
+        let written = self.write_frozen(&frozen, sequence, keys);
+        let mut mem = self.memtable.write()?;
+        let (table, totals) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                mem.thaw(frozen);
+                self.memtable_bytes.store(mem.resident(), Ordering::Relaxed);
+                return Err(e);
+            }
+        };
+        info!(sequence, entries = table.entries(), bytes, base = frozen.is_base(), "Memtable flushed");
+        let table = Arc::new(table);
+        self.pins.flushed(&table);
+        mem.flushed(table, totals.live_keys as usize);
+        self.memtable_bytes.store(mem.resident(), Ordering::Relaxed);
+        self.live_bytes.store(totals.live_bytes, Ordering::Relaxed);
+        self.garbage.set(totals.dead_bytes, totals.tombstones);
+        let (keys, tables) = (mem.len(), mem.tables().len());
+        drop(mem);
+
+        wal.truncate()?;
+        self.checkpoint_sequence.store(sequence, Ordering::SeqCst);
+        if frozen.is_base() {
+            remove_file(&self.data_dir.join("checkpoint"))?;
+        }
+        metrics::memtable(&self.data_dir, keys, self.memtable_bytes());
+        metrics::tables(&self.data_dir, tables);
+        self.merger.request(tables);
+        Ok(Some(sequence))
+    }
+
+    /// Write `frozen`, the entries up to `sequence`, to a table, with the
+    /// totals as of it, given `keys` live keys as counted so far.  The keys
+    /// written without reading the tables are looked up in them now, and
+    /// what they replaced taken off the live counts (see `memtable`).
+    fn write_frozen(&self, frozen: &Frozen, sequence: u64, keys: usize) -> Result<(Table, Totals), EngineError> {
+        let (shadowed_keys, shadowed_bytes) = frozen.shadowed()?;
+        let (dead_bytes, tombstones) = if frozen.is_base() { (0, 0) } else { self.garbage.after_flush() };
+        let totals = Totals {
+            live_keys: (keys as u64).saturating_sub(shadowed_keys),
+            live_bytes: self.live_bytes().saturating_sub(shadowed_bytes),
+            dead_bytes: dead_bytes + shadowed_bytes,
+            tombstones,
+        };
+        let entries = frozen.entries().map(Ok::<_, WalError>);
+        let table   = Table::write(&self.data_dir, sstable::TEMP_FILE, sequence, frozen.is_base(), || totals, entries)?;
+        Ok((table, totals))
+    }
+
+    /// Prepare for a clean shutdown: `compact`, so the next `open` loads the
+    /// checkpoint instead of replaying every write since the last one.
+    /// Skipped, returning `None`, if the WAL holds nothing past the
+    /// checkpoint.  Later writes are still logged, and replayed on open.
+    ///
+    /// As after any `compact`, a replica or change consumer behind the new
+    /// checkpoint has to start from a checkpoint once the engine reopens.
+    pub fn shutdown(&self) -> Result<Option<u64>, EngineError> {
+        if self.latest_sequence()? == self.checkpoint_sequence() {
+            return Ok(None);
+        }
+        let sequence = self.compact()?.sequence;
+        info!(sequence, "Checkpointed for shutdown");
+        Ok(Some(sequence))
+    }
+
+    /// Delete the files an interrupted write left behind (see
+    /// `ORPHAN_FILES`) that were last modified at least `grace` ago.
+    /// Returns each deleted file with its size.
+    ///
+    /// Writers and merges of the tables are blocked meanwhile, so no
+    /// checkpoint or table is being written; `grace` keeps the clock's temp
+    /// file and a table being installed (see `install_snapshot`), written
+    /// without that lock, out of reach while they are in use.
+    pub fn collect_orphans(&self, grace: Duration) -> Result<Vec<(PathBuf, u64)>, EngineError> {
+        let _merging = self.merger.exclusive();
+        let _wal     = self.wal.lock()?;
+        let mut removed = Vec::new();
+        for name in ORPHAN_FILES {
+            let path = self.data_dir.join(name);
+            let meta = match std::fs::metadata(&path) {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(WalError::Io(e).into()),
+            };
+            let age = meta.modified().map_err(WalError::Io)?.elapsed().unwrap_or_default();
+            if age < grace {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(WalError::Io(e).into()),
+            }
+            info!(path = %path.display(), bytes = meta.len(), "Deleted orphaned file");
+            removed.push((path, meta.len()));
+        }
+        Ok(removed)
+    }
+
+    /// Block until a change newer than `after` is committed or `timeout`
+    /// elapses.  Returns the latest sequence number either way.
+    pub fn wait_for_changes(&self, after: u64, timeout: Duration) -> Result<u64, EngineError> {
+        self.feed.wait(after, timeout)
     }
 
     // ── Diagnostics ─────────────────────────────────────────────────────────
 
-    /// Number of live keys currently held in memory.
+    /// Number of live keys, in memory and in the tables.  Once there are
+    /// tables, a put of a key only they hold counts as a new key until the
+    /// next flush, which corrects the count (see `memtable`).
     pub fn len(&self) -> Result<usize, EngineError> {
         Ok(self.memtable.read()?.len())
     }
@@ -143,4 +1266,1048 @@ impl Engine {
     pub fn is_empty(&self) -> Result<bool, EngineError> {
         Ok(self.len()? == 0)
     }
+
+    /// Bytes of the keys and values currently held in memory, not counting
+    /// the memtable's own overhead.  Without tables, every live key's.
+    pub fn memtable_bytes(&self) -> u64 {
+        self.memtable_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes of the live keys and values, in memory and in the tables,
+    /// estimated between flushes as `len` is.
+    pub fn live_bytes(&self) -> u64 {
+        self.live_bytes.load(Ordering::Relaxed)
+    }
+
+    /// What the background merges of the tables have done since the engine
+    /// opened (see `merge`).
+    pub fn merge_stats(&self) -> MergeStats {
+        self.merger.stats()
+    }
+
+    /// Bytes of the live keys and values, summed by the group `group_of`
+    /// puts each key in.  Writers wait while the memtable and tables are
+    /// walked.
+    pub fn bytes_by(&self, group_of: impl Fn(&str) -> &str) -> Result<BTreeMap<String, u64>, EngineError> {
+        let mem = self.memtable.read()?;
+        let mut groups: BTreeMap<String, u64> = BTreeMap::new();
+        for entry in mem.iter()? {
+            let (key, value) = entry?;
+            let bytes = (key.len() + value.len()) as u64;
+            match groups.get_mut(group_of(&key)) {
+                Some(total) => *total += bytes,
+                None => {
+                    groups.insert(group_of(&key).to_owned(), bytes);
+                }
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Limit puts to keys in `namespace` (see `throttle::namespace_of`) to
+    /// `bytes_per_sec` of keys and values, or lift its limit with `None`.
+    /// Writes over the limit fail with `EngineError::Throttled` before
+    /// reaching the WAL; replicated changes are never throttled.
+    pub fn set_write_limit(&self, namespace: &str, bytes_per_sec: Option<u64>) {
+        self.throttle.set(namespace, bytes_per_sec);
+    }
+
+    /// Every namespace with a write limit, and its limit in bytes per second.
+    pub fn write_limits(&self) -> Vec<(String, u64)> {
+        self.throttle.limits()
+    }
+
+    /// Refuse puts that would grow the store past `bytes` of live keys and
+    /// values with `EngineError::QuotaExceeded`, or lift the quota with
+    /// `None`.  Deletes, and replicated changes, are never refused.
+    pub fn set_storage_quota(&self, bytes: Option<u64>) {
+        self.storage_quota.store(bytes.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    pub fn storage_quota(&self) -> Option<u64> {
+        Some(self.storage_quota.load(Ordering::Relaxed)).filter(|&quota| quota != u64::MAX)
+    }
+
+    /// Count puts of the values `exempt` holds for as deletes against the
+    /// storage quota: they are never refused, and free the value they
+    /// replace.  For values that stand for a delete, such as the tombstones
+    /// of active/active replication, which would otherwise make a full
+    /// store refuse deletes.
+    pub fn set_quota_exempt(&self, exempt: Option<fn(&[u8]) -> bool>) {
+        *self.quota_exempt.write().unwrap_or_else(|e| e.into_inner()) = exempt;
+    }
+
+    /// The directory the engine is rooted at.
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Smallest value checkpoints store once for all the keys holding it.
+    pub fn dedup_values(&self) -> Option<usize> {
+        self.dedup_values
+    }
+
+    /// How this engine's WAL is synced.
+    pub fn sync_method(&self) -> SyncMethod {
+        self.sync
+    }
+
+    /// How long commits currently wait to share a sync: the fixed window of
+    /// `SyncPolicy::Interval`, the tuned one of `SyncPolicy::Adaptive`.
+    pub fn group_commit_window(&self) -> Duration {
+        self.group.window()
+    }
+
+    /// Make every committed write durable, e.g. before the disk is
+    /// snapshotted.  If the sync fails, so does every write after it, as
+    /// after a failed group commit (see `SyncPolicy`).
+    pub fn sync(&self) -> Result<(), EngineError> {
+        self.group.check()?;
+        if let Err(e) = self.wal.lock()?.sync() {
+            let e = EngineError::from(e);
+            self.group.fail(&e);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Re-read the WAL, checkpoint and tables on disk, at most
+    /// `bytes_per_sec` (0: as fast as the disk allows), and verify their
+    /// checksums (see `scrub`).  Writers are only held back while the log's
+    /// length is read.
+    pub fn scrub(&self, bytes_per_sec: u64) -> Result<ScrubReport, EngineError> {
+        let (wal_path, limit) = {
+            let wal = self.wal.lock()?;
+            (wal.path().to_owned(), std::fs::metadata(wal.path()).map_err(WalError::Io)?.len())
+        };
+        let checkpoint = self.checkpoint_sequence.load(Ordering::SeqCst);
+        let created    = scrub::wal_created(&wal_path);
+        let tables     = self.memtable.read()?.tables().to_vec();
+
+        let mut pace   = Pace::new(bytes_per_sec);
+        let mut report = ScrubReport::default();
+        let mut found  = Vec::new();
+        report.wal_records = scrub::wal(&wal_path, limit, &mut pace, &mut found);
+        if self.checkpoint_sequence.load(Ordering::SeqCst) != checkpoint || scrub::wal_created(&wal_path) != created {
+            report.wal_skipped = true;
+        } else {
+            report.corruptions = found;
+        }
+        scrub::checkpoint(&self.data_dir.join("checkpoint"), &mut pace, &mut report.corruptions);
+        for table in &tables {
+            scrub::table(table, &mut pace, &mut report.corruptions);
+        }
+        report.bytes = pace.bytes();
+
+        for _ in &report.corruptions {
+            metrics::checksum_failure();
+        }
+        metrics::scrub(&self.data_dir, report.bytes, report.corruptions.len());
+        Ok(report)
+    }
+
+    /// How much of the checkpoint, tables and WAL is dead data, which
+    /// `compact` would reclaim (see `space`).
+    pub fn space(&self) -> Result<SpaceStats, EngineError> {
+        let wal = self.wal.lock()?;
+        let table_bytes = self.memtable.read()?.tables().iter().map(|table| table.file_len()).sum();
+        let checkpoint_bytes = match std::fs::metadata(self.data_dir.join("checkpoint")) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(WalError::Io(e).into()),
+        };
+        let stats = SpaceStats {
+            live_bytes: self.live_bytes(),
+            checkpoint_bytes,
+            wal_bytes: std::fs::metadata(wal.path()).map_err(WalError::Io)?.len(),
+            tombstones: self.garbage.tombstones(),
+            dead_bytes: self.garbage.bytes(),
+            table_bytes,
+        };
+        metrics::space(&self.data_dir, &stats);
+        Ok(stats)
+    }
+
+    /// Current size of the WAL file in bytes.
+    pub fn wal_size(&self) -> Result<u64, EngineError> {
+        let wal = self.wal.lock()?;
+        Ok(std::fs::metadata(wal.path()).map_err(WalError::Io)?.len())
+    }
+}
+
+/// Create and lock `DATA_DIR/LOCK`, failing if another engine holds it.
+///
+/// The lock is advisory (`flock`) and is released by the OS when the file
+/// is closed, so it cannot outlive a crashed process.  The holder writes its
+/// process ID into the file, for the error another engine gets.  A no-op
+/// outside Unix.
+fn lock(data_dir: &Path) -> Result<File, EngineError> {
+    let path = data_dir.join("LOCK");
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path);
+    let file = file.map_err(WalError::Io)?;
+    #[cfg(unix)]
+    {
+        use std::io::Write as _;
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: `file` owns the descriptor for the duration of the call.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::WouldBlock {
+                let pid = std::fs::read_to_string(&path).ok().and_then(|holder| holder.trim().parse().ok());
+                return Err(EngineError::AlreadyLocked { dir: data_dir.to_owned(), pid });
+            }
+            return Err(WalError::Io(e).into());
+        }
+        file.set_len(0).map_err(WalError::Io)?;
+        writeln!(&file, "{}", std::process::id()).map_err(WalError::Io)?;
+    }
+    Ok(file)
+}
+
+/// Who holds a data directory's lock, for `EngineError::AlreadyLocked`.
+fn held_by(pid: &Option<u32>) -> String {
+    match pid {
+        Some(pid) if *pid == std::process::id() => " in this process".to_owned(),
+        Some(pid) => format!(" (process {pid})"),
+        None => String::new(),
+    }
+}
+
+/// Refuse a data directory holding any of `ORPHAN_FILES`, or only warn
+/// about them if `ignore`.  They are left in place either way.
+fn check_orphans(data_dir: &Path, ignore: bool) -> Result<(), EngineError> {
+    let orphans: Vec<&str> = ORPHAN_FILES.iter().copied().filter(|name| data_dir.join(name).exists()).collect();
+    if orphans.is_empty() {
+        return Ok(());
+    }
+    if ignore {
+        warn!(data_dir = %data_dir.display(), orphans = ?orphans, "Ignoring files left by an interrupted write");
+        return Ok(());
+    }
+    Err(EngineError::Inconsistent {
+        dir:     data_dir.to_owned(),
+        problem: format!("{} left by an interrupted write", orphans.join(", ")),
+        hint:    "check that no other process is writing the directory, then delete them, \
+                  or open with `ignore_orphans` (`--ignore-orphans`)"
+            .to_owned(),
+    })
+}
+
+/// Apply `record` to the memtable, keeping `live_bytes` (as counted by
+/// `memtable_size`) up to date, and saving what it overwrites for `pins`.
+/// Reads no table: what a write of a key only the tables hold replaces is
+/// accounted for by the next flush (see `memtable`).
+fn apply(
+    mem: &mut Memtable,
+    live_bytes: &AtomicU64,
+    garbage: &Garbage,
+    indexes: &mut Indexes,
+    pins: &Pins,
+    record: &WalRecord,
+) {
+    let (key, value) = match record {
+        WalRecord::Put { key, value } => (key, Some(value.as_slice())),
+        WalRecord::Delete { key } => (key, None),
+    };
+    let replaced = mem.insert(key, value.map(<[u8]>::to_vec));
+    if let Some(value) = value {
+        live_bytes.fetch_add((key.len() + value.len()) as u64, Ordering::Relaxed);
+    }
+    if let Some(previous) = replaced.value() {
+        live_bytes.fetch_sub((key.len() + previous.len()) as u64, Ordering::Relaxed);
+    }
+    pins.preserve(key, &replaced);
+    indexes.update(key, value);
+    garbage.add(record, &replaced);
+}
+
+/// Up to `limit` of `entries`, read from `range`'s first key on, while
+/// they are in it.
+fn page(entries: Range<'_>, range: &KeyRange, limit: usize) -> Result<Vec<(String, Vec<u8>)>, WalError> {
+    let mut page = Vec::new();
+    for entry in entries {
+        let (key, value) = entry?;
+        if range.is_past(&key) || page.len() == limit {
+            break;
+        }
+        page.push((key, value));
+    }
+    Ok(page)
+}
+
+/// Delete the file at `path`, superseded by a newer one, if it exists.
+fn remove_file(path: &Path) -> Result<(), EngineError> {
+    match std::fs::remove_file(path) {
+        Ok(()) => {
+            info!(path = %path.display(), "Deleted superseded file");
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(WalError::Io(e).into()),
+    }
+}
+
+fn record_key(record: &WalRecord) -> &str {
+    match record {
+        WalRecord::Put { key, .. } | WalRecord::Delete { key } => key,
+    }
+}
+
+/// Bytes of the keys and values in `mem`.
+fn memtable_size(mem: &BTreeMap<String, Vec<u8>>) -> u64 {
+    mem.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::{RecoveryMode, WalReader};
+
+    fn tolerant() -> EngineOptions {
+        EngineOptions {
+            wal: WalOptions { recovery: RecoveryMode::Tolerant, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    /// Flip a byte inside the payload of the `index`th record of the WAL in
+    /// `dir`, returning where that record starts.
+    fn damage_record(dir: &Path, index: usize) -> u64 {
+        let path = dir.join("wal.log");
+        let mut reader = WalReader::open(&path).unwrap();
+        let offset = std::iter::from_fn(|| reader.next_record().unwrap()).nth(index).unwrap().offset;
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[offset as usize + 9] ^= 0xff;
+        std::fs::write(&path, bytes).unwrap();
+        offset
+    }
+
+    #[test]
+    fn opens_an_older_wal_and_carries_on_in_the_current_format() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+            engine.put("b".to_owned(), b"2".to_vec()).unwrap();
+        }
+        // Rewrite the log as v1 would have left it: no header, no
+        // sequences, 8-byte lengths.
+        let path = dir.path().join("wal.log");
+        let mut v1 = Vec::new();
+        for entry in WriteAheadLog::recover(&path).unwrap() {
+            let WalRecord::Put { key, value } = entry.record else { unreachable!("only puts were written") };
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&(key.len() as u64).to_be_bytes());
+            fields.extend_from_slice(&(value.len() as u64).to_be_bytes());
+            fields.extend_from_slice(key.as_bytes());
+            fields.extend_from_slice(&value);
+            let mut h = crc32fast::Hasher::new();
+            h.update(&[1]);
+            h.update(&fields);
+            v1.push(1);
+            v1.extend_from_slice(&h.finalize().to_be_bytes());
+            v1.extend_from_slice(&fields);
+        }
+        std::fs::write(&path, v1).unwrap();
+
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get("b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.latest_sequence().unwrap(), 2);
+        assert_eq!(WalReader::open(&path).unwrap().info().version, 3);
+
+        engine.put("c".to_owned(), b"3".to_vec()).unwrap();
+        drop(engine);
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.latest_sequence().unwrap(), 3);
+        assert_eq!(engine.len().unwrap(), 3);
+    }
+
+    #[test]
+    fn a_batch_is_recovered_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            engine.put("gone".to_owned(), b"old".to_vec()).unwrap();
+            engine
+                .write_batch(vec![
+                    WalRecord::Put { key: "a".to_owned(), value: b"1".to_vec() },
+                    WalRecord::Put { key: "b".to_owned(), value: b"2".to_vec() },
+                    WalRecord::Delete { key: "gone".to_owned() },
+                ])
+                .unwrap();
+            assert_eq!(engine.latest_sequence().unwrap(), 4);
+        }
+
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(engine.get("b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.get("gone").unwrap(), None);
+        assert_eq!(engine.latest_sequence().unwrap(), 4);
+        drop(engine);
+
+        // Damaged, the batch is dropped whole, with none of it applied.
+        damage_record(dir.path(), 1);
+        let engine = Engine::open_with(dir.path(), tolerant()).unwrap();
+        assert_eq!(engine.get("a").unwrap(), None);
+        assert_eq!(engine.get("b").unwrap(), None);
+        assert_eq!(engine.get("gone").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(engine.latest_sequence().unwrap(), 1);
+    }
+
+    #[test]
+    fn sequences_survive_restarts_and_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            assert_eq!(engine.latest_sequence().unwrap(), 0);
+            engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+            engine.put("b".to_owned(), b"2".to_vec()).unwrap();
+            assert!(engine.delete("a").unwrap());
+            assert_eq!(engine.latest_sequence().unwrap(), 3);
+        }
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            assert_eq!(engine.latest_sequence().unwrap(), 3);
+            assert_eq!(engine.compact().unwrap().sequence, 3);
+            engine.put("c".to_owned(), b"3".to_vec()).unwrap();
+            assert_eq!(engine.latest_sequence().unwrap(), 4);
+        }
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.latest_sequence().unwrap(), 4);
+        let changes = engine.changes_since(3, 10).unwrap();
+        assert_eq!(changes.iter().map(|change| change.sequence).collect::<Vec<_>>(), [4]);
+    }
+
+    #[test]
+    fn refuses_a_wal_that_starts_past_what_it_follows() {
+        let dir = tempfile::tempdir().unwrap();
+        drop(Engine::open(dir.path()).unwrap());
+        let mut wal = WriteAheadLog::open(dir.path().join("wal.log")).unwrap();
+        wal.append(&WalRecord::Put { key: "a".to_owned(), value: Vec::new() }, 5, 0).unwrap();
+        drop(wal);
+
+        let err = Engine::open(dir.path()).unwrap_err();
+        assert!(matches!(err, EngineError::Inconsistent { .. }), "{err}");
+    }
+
+    #[test]
+    fn open_cuts_off_a_torn_last_append() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            for key in ["a", "b", "c"] {
+                engine.put(key.to_owned(), b"value".to_vec()).unwrap();
+            }
+        }
+        let path = dir.path().join("wal.log");
+        let mut reader = WalReader::open(&path).unwrap();
+        let last = std::iter::from_fn(|| reader.next_record().unwrap()).last().unwrap().offset;
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("b").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(engine.get("c").unwrap(), None);
+        assert_eq!(engine.latest_sequence().unwrap(), 2);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), last);
+
+        // The next commit takes the torn one's sequence, and reads back.
+        engine.put("d".to_owned(), b"after".to_vec()).unwrap();
+        assert_eq!(engine.changes_since(2, 10).unwrap().len(), 1);
+        drop(engine);
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("d").unwrap(), Some(b"after".to_vec()));
+        assert_eq!(engine.latest_sequence().unwrap(), 3);
+    }
+
+    #[test]
+    fn tolerant_open_drops_the_damaged_end_of_the_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            for key in ["a", "b", "c"] {
+                engine.put(key.to_owned(), b"value".to_vec()).unwrap();
+            }
+        }
+        let cut = damage_record(dir.path(), 1);
+
+        let err = Engine::open(dir.path()).unwrap_err();
+        assert!(matches!(err, EngineError::Wal(WalError::ChecksumMismatch { .. })), "{err}");
+
+        let engine = Engine::open_with(dir.path(), tolerant()).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(engine.get("b").unwrap(), None);
+        assert_eq!(engine.get("c").unwrap(), None);
+        assert_eq!(std::fs::metadata(dir.path().join("wal.log")).unwrap().len(), cut);
+
+        // Writes go on from the cut, and a strict open reads them back.
+        engine.put("d".to_owned(), b"after".to_vec()).unwrap();
+        drop(engine);
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"value".to_vec()));
+        assert_eq!(engine.get("d").unwrap(), Some(b"after".to_vec()));
+        assert_eq!(engine.len().unwrap(), 2);
+    }
+
+    fn grouped(sync_policy: SyncPolicy) -> EngineOptions {
+        EngineOptions { sync_policy, ..Default::default() }
+    }
+
+    /// Commit `per_writer` puts from each of `writers` threads at once, and
+    /// one batch from each, so commits share syncs.
+    fn commit_concurrently(engine: &Engine, writers: usize, per_writer: usize) {
+        std::thread::scope(|scope| {
+            for writer in 0..writers {
+                scope.spawn(move || {
+                    for i in 0..per_writer {
+                        engine.put(format!("w{writer}-{i}"), i.to_be_bytes().to_vec()).unwrap();
+                    }
+                    engine
+                        .write_batch(vec![
+                            WalRecord::Put { key: format!("w{writer}-batch"), value: b"b".to_vec() },
+                            WalRecord::Delete { key: format!("w{writer}-0") },
+                        ])
+                        .unwrap();
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn group_commits_are_on_disk_when_they_return() {
+        let policies = [
+            SyncPolicy::Interval(Duration::from_millis(2)),
+            SyncPolicy::Adaptive { target_p99: Duration::from_millis(5) },
+        ];
+        for policy in policies {
+            let dir = tempfile::tempdir().unwrap();
+            let engine = Engine::open_with(dir.path(), grouped(policy)).unwrap();
+            commit_concurrently(&engine, 8, 25);
+
+            // Read while the engine is still open, so nothing is flushed by
+            // closing it: every commit that returned is in the file.
+            let sequence = engine.latest_sequence().unwrap();
+            let on_disk  = WriteAheadLog::recover(dir.path().join("wal.log")).unwrap();
+            assert_eq!(sequence, 8 * (25 + 2), "{policy:?}");
+            assert_eq!(on_disk.len() as u64, sequence, "{policy:?}");
+            assert_eq!(on_disk.last().unwrap().sequence, sequence, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn group_commits_survive_a_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = SyncPolicy::Adaptive { target_p99: Duration::from_millis(5) };
+        {
+            let engine = Engine::open_with(dir.path(), grouped(policy)).unwrap();
+            commit_concurrently(&engine, 4, 50);
+            engine.sync().unwrap();
+        }
+
+        let engine = Engine::open_with(dir.path(), grouped(SyncPolicy::Interval(Duration::from_millis(1)))).unwrap();
+        assert_eq!(engine.latest_sequence().unwrap(), 4 * (50 + 2));
+        assert_eq!(engine.len().unwrap(), 4 * 50);
+        for writer in 0..4 {
+            assert_eq!(engine.get(&format!("w{writer}-0")).unwrap(), None);
+            assert_eq!(engine.get(&format!("w{writer}-49")).unwrap(), Some(49usize.to_be_bytes().to_vec()));
+            assert_eq!(engine.get(&format!("w{writer}-batch")).unwrap(), Some(b"b".to_vec()));
+        }
+
+        // Compacted under one policy, carried on under another.
+        engine.compact().unwrap();
+        engine.put("after".to_owned(), b"compaction".to_vec()).unwrap();
+        drop(engine);
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.latest_sequence().unwrap(), 4 * (50 + 2) + 1);
+        assert_eq!(engine.get("after").unwrap(), Some(b"compaction".to_vec()));
+        assert_eq!(engine.len().unwrap(), 4 * 50 + 1);
+    }
+
+    fn flushing() -> EngineOptions {
+        EngineOptions {
+            memtable_flush_bytes: Some(1 << 20),
+            merge: MergePolicy { min_tables: 0, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flushed_tables_read_back_with_their_tombstones() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+            for i in 0..100 {
+                engine.put(format!("key-{i:03}"), format!("v{i}").into_bytes()).unwrap();
+            }
+            assert_eq!(engine.flush().unwrap(), Some(100));
+            assert_eq!(engine.flush().unwrap(), None);
+            assert!(!dir.path().join("checkpoint").exists());
+
+            engine.put("key-000".to_owned(), b"new".to_vec()).unwrap();
+            assert!(engine.delete("key-001").unwrap());
+            assert!(!engine.delete("missing").unwrap());
+            assert_eq!(engine.flush().unwrap(), Some(103));
+            assert_eq!(tables_in(dir.path()), ["00000000000000000100.sst", "00000000000000000103.sst"]);
+            assert!(WriteAheadLog::recover(dir.path().join("wal.log")).unwrap().is_empty());
+        }
+
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        assert_eq!(engine.get("key-000").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(engine.get("key-001").unwrap(), None);
+        assert_eq!(engine.get("key-099").unwrap(), Some(b"v99".to_vec()));
+        assert_eq!(engine.len().unwrap(), 99);
+        let keys: Vec<String> = engine.scan_page("key-00", None, 3).unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["key-000", "key-002", "key-003"]);
+    }
+
+    #[test]
+    fn writes_over_tables_are_counted_at_the_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        for i in 0..10 {
+            engine.put(format!("k{i}"), b"four".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        assert_eq!((engine.len().unwrap(), engine.live_bytes()), (10, 60));
+
+        // Neither write reads the table, so both count as if it did not
+        // hold the key.
+        engine.put("k0".to_owned(), b"FOUR".to_vec()).unwrap();
+        engine.delete("k1").unwrap();
+        assert_eq!((engine.len().unwrap(), engine.live_bytes()), (11, 66));
+
+        engine.flush().unwrap();
+        assert_eq!((engine.len().unwrap(), engine.live_bytes()), (9, 54));
+        let space = engine.space().unwrap();
+        assert_eq!((space.tombstones, space.dead_bytes), (1, 2 * 6 + 2));
+        drop(engine);
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        assert_eq!((engine.len().unwrap(), engine.live_bytes()), (9, 54));
+    }
+
+    #[test]
+    fn a_snapshot_keeps_what_writes_over_tables_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+        engine.put("b".to_owned(), b"2".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.put("c".to_owned(), b"3".to_vec()).unwrap();
+
+        let snapshot = engine.snapshot().unwrap();
+        engine.put("a".to_owned(), b"changed".to_vec()).unwrap();
+        engine.delete("b").unwrap();
+        engine.flush().unwrap();
+        // Flushed since the snapshot, and then overwritten.
+        engine.put("c".to_owned(), b"changed".to_vec()).unwrap();
+        engine.put("d".to_owned(), b"new".to_vec()).unwrap();
+
+        assert_eq!(snapshot.get("a").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(snapshot.get("b").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(snapshot.get("c").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(snapshot.get("d").unwrap(), None);
+        let all = snapshot.scan_page("", None, 10).unwrap();
+        assert_eq!(all, [("a".to_owned(), b"1".to_vec()), ("b".to_owned(), b"2".to_vec()), ("c".to_owned(), b"3".to_vec())]);
+        assert_eq!(engine.get("a").unwrap(), Some(b"changed".to_vec()));
+    }
+
+    #[test]
+    fn indexes_follow_writes_over_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        engine.put("a".to_owned(), b"x1".to_vec()).unwrap();
+        engine.flush().unwrap();
+        engine.register_index("first", |value| vec![value[..1].to_vec()]).unwrap();
+        assert_eq!(engine.query_index("first", b"x").unwrap(), ["a"]);
+
+        engine.put("a".to_owned(), b"y1".to_vec()).unwrap();
+        assert!(engine.query_index("first", b"x").unwrap().is_empty());
+        assert_eq!(engine.query_index("first", b"y").unwrap(), ["a"]);
+        engine.flush().unwrap();
+        engine.delete("a").unwrap();
+        assert!(engine.query_index("first", b"y").unwrap().is_empty());
+    }
+
+    fn tiered(cold: &Path, after: Option<Duration>) -> EngineOptions {
+        EngineOptions {
+            memtable_flush_bytes: Some(1 << 20),
+            merge: MergePolicy { min_tables: 0, ..Default::default() },
+            cold_tier: Some(ColdTier { after, ..ColdTier::new(cold) }),
+            ..Default::default()
+        }
+    }
+
+    /// Wait for the background thread to leave `cold` tables in the cold
+    /// tier.
+    fn await_cold(engine: &Engine, cold: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while engine.merge_stats().cold_tables != cold {
+            assert!(Instant::now() < deadline, "{:?}", engine.merge_stats());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn an_installed_snapshot_replaces_the_tables_and_the_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        for i in 0..10 {
+            engine.put(format!("old-{i}"), b"old".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.put("old-0".to_owned(), b"unflushed".to_vec()).unwrap();
+        let snapshot = engine.snapshot().unwrap();
+
+        let entries = (0..100).map(|i| Ok((format!("new-{i:03}"), format!("v{i}").into_bytes())));
+        engine.install_snapshot(500, entries).unwrap();
+        assert_eq!(tables_in(dir.path()), [sstable::file_name(500)]);
+        assert!(!dir.path().join(sstable::INSTALL_FILE).exists());
+        assert_eq!(engine.memtable_bytes(), 0);
+        assert_eq!(engine.len().unwrap(), 100);
+        assert_eq!(engine.get("old-1").unwrap(), None);
+        assert_eq!(engine.get("new-042").unwrap(), Some(b"v42".to_vec()));
+        // Snapshots taken before it still read what they did.
+        assert_eq!(snapshot.get("old-0").unwrap(), Some(b"unflushed".to_vec()));
+        assert_eq!(snapshot.get("new-042").unwrap(), None);
+
+        engine.put("after".to_owned(), b"install".to_vec()).unwrap();
+        assert_eq!(engine.latest_sequence().unwrap(), 501);
+        drop(engine);
+
+        let engine = Engine::open_with(dir.path(), flushing()).unwrap();
+        assert_eq!(engine.len().unwrap(), 101);
+        assert_eq!(engine.get("old-0").unwrap(), None);
+        assert_eq!(engine.get("new-099").unwrap(), Some(b"v99".to_vec()));
+        assert_eq!(engine.get("after").unwrap(), Some(b"install".to_vec()));
+    }
+
+    #[test]
+    fn a_snapshot_that_fails_partway_leaves_the_store_as_it_was() {
+        for options in [EngineOptions::default(), flushing()] {
+            let dir = tempfile::tempdir().unwrap();
+            let engine = Engine::open_with(dir.path(), options).unwrap();
+            engine.put("kept".to_owned(), b"1".to_vec()).unwrap();
+
+            let broken = (0..10).map(|i| match i {
+                5 => Err(WalError::Io(std::io::Error::other("stream broke"))),
+                _ => Ok((format!("new-{i}"), b"v".to_vec())),
+            });
+            let err = engine.install_snapshot(50, broken).unwrap_err();
+            assert!(err.to_string().contains("stream broke"), "{err}");
+            let unordered = ["b", "a"].map(|key| Ok((key.to_owned(), b"v".to_vec())));
+            let err = engine.install_snapshot(50, unordered).unwrap_err();
+            assert!(err.to_string().contains("out of order"), "{err}");
+
+            assert!(!dir.path().join(sstable::INSTALL_FILE).exists());
+            assert_eq!(engine.latest_sequence().unwrap(), 1);
+            assert_eq!(engine.get("kept").unwrap(), Some(b"1".to_vec()));
+            assert_eq!(engine.get("new-0").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn files_a_crash_leaves_behind_an_install_are_superseded() {
+        for options in [EngineOptions::default(), flushing()] {
+            let (dir, saved) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+            {
+                let engine = Engine::open_with(dir.path(), options.clone()).unwrap();
+                for i in 0..10 {
+                    engine.put(format!("old-{i}"), b"old".to_vec()).unwrap();
+                }
+                match options.memtable_flush_bytes {
+                    Some(_) => engine.flush().map(drop).unwrap(),
+                    None => engine.compact().map(drop).unwrap(),
+                }
+                engine.put("old-0".to_owned(), b"logged".to_vec()).unwrap();
+                for entry in std::fs::read_dir(dir.path()).unwrap() {
+                    let path = entry.unwrap().path();
+                    std::fs::copy(&path, saved.path().join(path.file_name().unwrap())).unwrap();
+                }
+
+                let entries = (0..100).map(|i| Ok((format!("new-{i:03}"), b"new".to_vec())));
+                engine.install_snapshot(500, entries).unwrap();
+
+                // As if the install had crashed right after renaming its
+                // base into place: the old WAL, tables and checkpoint remain.
+                for entry in std::fs::read_dir(saved.path()).unwrap() {
+                    let name = entry.unwrap().file_name();
+                    if name == "wal.log" || !dir.path().join(&name).exists() {
+                        std::fs::copy(saved.path().join(&name), dir.path().join(&name)).unwrap();
+                    }
+                }
+            }
+
+            let engine = Engine::open_with(dir.path(), options.clone()).unwrap();
+            assert_eq!(engine.latest_sequence().unwrap(), 500);
+            assert_eq!(engine.len().unwrap(), 100);
+            assert_eq!(engine.get("old-0").unwrap(), None);
+            assert_eq!(engine.get("old-9").unwrap(), None);
+            assert_eq!(engine.get("new-099").unwrap(), Some(b"new".to_vec()));
+            if options.memtable_flush_bytes.is_some() {
+                assert_eq!(tables_in(dir.path()), [sstable::file_name(500)]);
+                assert!(!dir.path().join("checkpoint").exists());
+            }
+        }
+    }
+
+    fn tables_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".sst"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn the_base_table_moves_to_the_cold_tier_and_is_read_from_there() {
+        let (dir, cold) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        {
+            let engine = Engine::open_with(dir.path(), tiered(cold.path(), None)).unwrap();
+            for i in 0..500 {
+                engine.put(format!("key-{i:03}"), vec![b'v'; 100]).unwrap();
+            }
+            engine.flush().unwrap();
+            await_cold(&engine, 1);
+            assert!(tables_in(dir.path()).is_empty());
+            assert_eq!(tables_in(cold.path()).len(), 1);
+
+            // A newer table stays until it is a base table itself.
+            engine.put("key-000".to_owned(), b"new".to_vec()).unwrap();
+            engine.delete("key-001").unwrap();
+            engine.flush().unwrap();
+            assert_eq!(tables_in(dir.path()).len(), 1);
+
+            assert_eq!(engine.get("key-000").unwrap(), Some(b"new".to_vec()));
+            assert_eq!(engine.get("key-001").unwrap(), None);
+            assert_eq!(engine.get("key-499").unwrap(), Some(vec![b'v'; 100]));
+            assert_eq!(engine.get("key-499").unwrap(), Some(vec![b'v'; 100]));
+            let stats = engine.merge_stats();
+            assert_eq!((stats.tables_tiered, stats.cold_tables), (1, 1));
+            assert!(stats.cache_bytes > 0);
+        }
+
+        let engine = Engine::open_with(dir.path(), tiered(cold.path(), None)).unwrap();
+        assert_eq!(engine.merge_stats().cold_tables, 1);
+        assert_eq!(engine.len().unwrap(), 499);
+        let keys: Vec<String> = engine.scan(KeyRange::prefix("key-49")).unwrap().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(keys.len(), 10);
+
+        // Compacting writes a new base table, which moves in turn.
+        engine.compact().unwrap();
+        await_cold(&engine, 1);
+        assert_eq!(engine.merge_stats().tables, 1);
+        assert!(tables_in(dir.path()).is_empty());
+        assert_eq!(tables_in(cold.path()).len(), 1);
+        assert_eq!(engine.get("key-000").unwrap(), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn tables_move_on_their_age_too() {
+        let (dir, cold) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let engine = Engine::open_with(dir.path(), tiered(cold.path(), Some(Duration::ZERO))).unwrap();
+        for round in 0..3 {
+            engine.put(format!("key-{round}"), b"value".to_vec()).unwrap();
+            engine.flush().unwrap();
+        }
+        await_cold(&engine, 3);
+        assert!(tables_in(dir.path()).is_empty());
+        for round in 0..3 {
+            assert_eq!(engine.get(&format!("key-{round}")).unwrap(), Some(b"value".to_vec()));
+        }
+    }
+
+    #[test]
+    fn a_table_left_in_both_tiers_is_read_from_the_data_directory() {
+        let (dir, cold) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let name = {
+            let engine = Engine::open_with(dir.path(), tiered(cold.path(), None)).unwrap();
+            engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+            engine.flush().unwrap();
+            await_cold(&engine, 1);
+            tables_in(cold.path()).remove(0)
+        };
+        // As a crash after the copy but before the delete leaves it.
+        std::fs::copy(cold.path().join(&name), dir.path().join(&name)).unwrap();
+        std::fs::write(cold.path().join(tier::TIER_FILE), b"partial copy").unwrap();
+
+        let engine = Engine::open_with(dir.path(), tiered(cold.path(), None)).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"1".to_vec()));
+        assert!(!cold.path().join(tier::TIER_FILE).exists());
+        await_cold(&engine, 1);
+        assert_eq!(tables_in(cold.path()), [name]);
+        assert!(tables_in(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn refuses_to_open_without_the_cold_tier_its_tables_are_in() {
+        let (dir, cold) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        {
+            let engine = Engine::open_with(dir.path(), tiered(cold.path(), None)).unwrap();
+            engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+            engine.flush().unwrap();
+            await_cold(&engine, 1);
+        }
+
+        let untiered = EngineOptions { cold_tier: None, ..tiered(cold.path(), None) };
+        let err = Engine::open_with(dir.path(), untiered).unwrap_err();
+        assert!(matches!(err, EngineError::Inconsistent { .. }), "{err}");
+
+        let elsewhere = tempfile::tempdir().unwrap();
+        let err = Engine::open_with(dir.path(), tiered(elsewhere.path(), None)).unwrap_err();
+        assert!(matches!(err, EngineError::Inconsistent { .. }), "{err}");
+
+        // Nor may another data directory use the same cold directory.
+        let other = tempfile::tempdir().unwrap();
+        let err = Engine::open_with(other.path(), tiered(cold.path(), None)).unwrap_err();
+        assert!(matches!(err, EngineError::Inconsistent { .. }), "{err}");
+
+        let engine = Engine::open_with(dir.path(), tiered(cold.path(), None)).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"1".to_vec()));
+    }
+
+    #[test]
+    fn engines_within_a_directory_get_cold_tiers_of_their_own() {
+        let cold = tempfile::tempdir().unwrap();
+        let options = tiered(cold.path(), None);
+        assert_eq!(options.within("shard-a").cold_tier.unwrap().dir, cold.path().join("shard-a"));
+        assert_eq!(EngineOptions::default().within("shard-a"), EngineOptions::default());
+    }
+
+    #[test]
+    fn compare_and_delete_leaves_a_value_it_does_not_match() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            engine.put("k".to_owned(), b"current".to_vec()).unwrap();
+            assert!(!engine.compare_and_delete("k", b"stale").unwrap());
+            assert_eq!(engine.get("k").unwrap(), Some(b"current".to_vec()));
+            assert!(!engine.compare_and_delete("absent", b"").unwrap());
+
+            engine.put("gone".to_owned(), b"v".to_vec()).unwrap();
+            assert!(engine.compare_and_delete("gone", b"v").unwrap());
+            assert!(!engine.compare_and_delete("gone", b"v").unwrap());
+        }
+
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("k").unwrap(), Some(b"current".to_vec()));
+        assert_eq!(engine.get("gone").unwrap(), None);
+    }
+
+    #[test]
+    fn get_and_set_returns_the_value_it_replaced() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get_and_set("k".to_owned(), b"1".to_vec()).unwrap(), None);
+        assert_eq!(engine.get_and_set("k".to_owned(), b"2".to_vec()).unwrap(), Some(b"1".to_vec()));
+        engine.delete("k").unwrap();
+        assert_eq!(engine.get_and_set("k".to_owned(), b"3".to_vec()).unwrap(), None);
+        assert_eq!(engine.get("k").unwrap(), Some(b"3".to_vec()));
+    }
+
+    #[test]
+    fn racing_get_and_sets_each_replace_a_different_value() {
+        let dir     = tempfile::tempdir().unwrap();
+        let engine  = Engine::open(dir.path()).unwrap();
+        let barrier = std::sync::Barrier::new(8);
+
+        let mut replaced: Vec<Option<Vec<u8>>> = std::thread::scope(|scope| {
+            let writers: Vec<_> = (0..8)
+                .map(|writer| {
+                    let (engine, barrier) = (&engine, &barrier);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        (0..50)
+                            .map(|i| engine.get_and_set("k".to_owned(), format!("{writer}-{i}").into_bytes()).unwrap())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            writers.into_iter().flat_map(|writer| writer.join().unwrap()).collect()
+        });
+
+        // Each value was replaced exactly once, bar the one left standing,
+        // and only the first set found nothing.
+        replaced.push(engine.get("k").unwrap());
+        let mut written: Vec<Option<Vec<u8>>> =
+            (0..8).flat_map(|writer| (0..50).map(move |i| Some(format!("{writer}-{i}").into_bytes()))).collect();
+        written.push(None);
+        replaced.sort();
+        written.sort();
+        assert_eq!(replaced, written);
+    }
+
+    #[test]
+    fn racing_compare_and_deletes_delete_once() {
+        let dir     = tempfile::tempdir().unwrap();
+        let engine  = Engine::open(dir.path()).unwrap();
+        let barrier = std::sync::Barrier::new(8);
+        for round in 0..20 {
+            engine.put("k".to_owned(), vec![round]).unwrap();
+            let deleted = std::thread::scope(|scope| {
+                let racers: Vec<_> = (0..8)
+                    .map(|_| {
+                        let (engine, barrier) = (&engine, &barrier);
+                        scope.spawn(move || {
+                            barrier.wait();
+                            engine.compare_and_delete("k", &[round]).unwrap()
+                        })
+                    })
+                    .collect();
+                racers.into_iter().map(|racer| racer.join().unwrap()).filter(|&deleted| deleted).count()
+            });
+            assert_eq!(deleted, 1, "round {round}");
+        }
+    }
+
+    #[test]
+    fn rename_overwrites_only_when_asked() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        engine.put("from".to_owned(), b"moved".to_vec()).unwrap();
+        engine.put("to".to_owned(), b"there".to_vec()).unwrap();
+
+        assert!(!engine.rename("absent", "to".to_owned(), true).unwrap());
+        assert!(!engine.rename("from", "to".to_owned(), false).unwrap());
+        assert_eq!(engine.get("from").unwrap(), Some(b"moved".to_vec()));
+        assert_eq!(engine.get("to").unwrap(), Some(b"there".to_vec()));
+
+        assert!(engine.rename("from", "from".to_owned(), false).unwrap());
+        assert!(engine.rename("from", "to".to_owned(), true).unwrap());
+        assert_eq!(engine.get("from").unwrap(), None);
+        assert_eq!(engine.get("to").unwrap(), Some(b"moved".to_vec()));
+    }
+
+    #[test]
+    fn a_rename_cut_short_by_a_crash_is_recovered_whole_or_not_at_all() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open(dir.path()).unwrap();
+            engine.put("from".to_owned(), b"moved".to_vec()).unwrap();
+            engine.put("to".to_owned(), b"there".to_vec()).unwrap();
+            assert!(engine.rename("from", "to".to_owned(), true).unwrap());
+        }
+        let path = dir.path().join("wal.log");
+        let mut reader = WalReader::open(&path).unwrap();
+        let start = std::iter::from_fn(|| reader.next_record().unwrap()).last().unwrap().offset as usize;
+        let bytes = std::fs::read(&path).unwrap();
+
+        // Wherever the crash tore the rename's append, neither key lost
+        // or duplicated the value.
+        for cut in start..bytes.len() {
+            std::fs::write(&path, &bytes[..cut]).unwrap();
+            let engine = Engine::open(dir.path()).unwrap();
+            assert_eq!(engine.get("from").unwrap(), Some(b"moved".to_vec()), "cut at {cut}");
+            assert_eq!(engine.get("to").unwrap(), Some(b"there".to_vec()), "cut at {cut}");
+            assert_eq!(engine.latest_sequence().unwrap(), 2);
+        }
+
+        std::fs::write(&path, &bytes).unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.get("from").unwrap(), None);
+        assert_eq!(engine.get("to").unwrap(), Some(b"moved".to_vec()));
+        assert_eq!(engine.latest_sequence().unwrap(), 4);
+    }
 }
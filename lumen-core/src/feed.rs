@@ -0,0 +1,302 @@
+//! Change feed: a bounded, in-memory history of committed WAL records.
+//!
+//! Every record committed through the engine is tagged with the next sequence
+//! number (its 1-based position in the log) and published here.  Consumers —
+//! replication streams today — read the changes after a given sequence and
+//! can block until new ones arrive.  Changes that have already fallen out of
+//! the in-memory window are re-read from the WAL by the engine, from where
+//! earlier reads found their records to start (see `WalOffsets`).
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::engine::EngineError;
+use crate::wal::{Checksum, WalError, WalReader, WalRecord};
+
+/// Every how many records a read of the WAL remembers where one starts.
+const OFFSET_EVERY: usize = 256;
+
+/// Where records start in the WAL: the sequence before each, and its offset.
+pub(crate) type Starts = Vec<(u64, u64)>;
+
+/// A committed WAL record tagged with its sequence number.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub sequence: u64,
+    /// HLC commit timestamp (0 if the record predates timestamped logs).
+    pub timestamp: u64,
+    pub record: WalRecord,
+}
+
+#[derive(Debug)]
+struct FeedState {
+    /// Most recent changes, oldest first, contiguous in sequence.
+    buffer: VecDeque<Change>,
+    capacity: usize,
+    /// Sequence of the last committed record (0 for an empty log).
+    latest: u64,
+}
+
+/// Ring buffer of recent changes plus a condition variable for waiters.
+#[derive(Debug)]
+pub(crate) struct ChangeFeed {
+    state: Mutex<FeedState>,
+    cond: Condvar,
+}
+
+impl ChangeFeed {
+    pub(crate) fn new(latest: u64, capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(FeedState {
+                buffer: VecDeque::with_capacity(capacity.min(1024)),
+                capacity,
+                latest,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Append `change` and wake every waiter.
+    ///
+    /// Callers must publish in sequence order (the engine does so while
+    /// holding the WAL lock).
+    pub(crate) fn publish(&self, change: Change) -> Result<(), EngineError> {
+        let mut state = self.state.lock()?;
+        state.latest = change.sequence;
+        if state.capacity > 0 {
+            if state.buffer.len() == state.capacity {
+                state.buffer.pop_front();
+            }
+            state.buffer.push_back(change);
+        }
+        drop(state);
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    /// Drop all buffered changes and continue from `latest` (after a
+    /// checkpoint has replaced the log).
+    pub(crate) fn reset(&self, latest: u64) -> Result<(), EngineError> {
+        let mut state = self.state.lock()?;
+        state.buffer.clear();
+        state.latest = latest;
+        drop(state);
+        self.cond.notify_all();
+        Ok(())
+    }
+
+    /// Sequence of the last published change.
+    pub(crate) fn latest(&self) -> Result<u64, EngineError> {
+        Ok(self.state.lock()?.latest)
+    }
+
+    /// Up to `limit` changes with a sequence greater than `after`.
+    ///
+    /// Returns `None` when some of those changes are no longer buffered and
+    /// must be read from the WAL instead.
+    pub(crate) fn since(&self, after: u64, limit: usize) -> Result<Option<Vec<Change>>, EngineError> {
+        let state = self.state.lock()?;
+        if after >= state.latest {
+            return Ok(Some(Vec::new()));
+        }
+
+        let first = match state.buffer.front() {
+            Some(change) => change.sequence,
+            None => return Ok(None),
+        };
+        if after + 1 < first {
+            return Ok(None);
+        }
+
+        let skip = (after + 1 - first) as usize;
+        Ok(Some(state.buffer.iter().skip(skip).take(limit).cloned().collect()))
+    }
+
+    /// Block until a change newer than `after` is published or `timeout`
+    /// elapses.  Returns the latest sequence either way.
+    pub(crate) fn wait(&self, after: u64, timeout: Duration) -> Result<u64, EngineError> {
+        let state = self.state.lock()?;
+        let (state, _) = self
+            .cond
+            .wait_timeout_while(state, timeout, |s| s.latest <= after)?;
+        Ok(state.latest)
+    }
+}
+
+/// Where records start in the WAL, by the sequence before each, as reads of
+/// older changes found them.  Each read remembers where it stopped, so a
+/// consumer catching up in batches reads every record once, and where every
+/// `OFFSET_EVERY`th record it passed starts, for consumers starting
+/// elsewhere.  Offsets hold for one generation of the log only (see
+/// `WriteAheadLog::generation`).
+#[derive(Debug, Default)]
+pub(crate) struct WalOffsets {
+    generation: u64,
+    starts: BTreeMap<u64, u64>,
+}
+
+impl WalOffsets {
+    /// Where in generation `generation` of the log to read the changes after
+    /// `after` from: the last record known to start at or before the first
+    /// of them, or else the first record (0).
+    pub(crate) fn start(&mut self, generation: u64, after: u64) -> u64 {
+        if generation != self.generation {
+            self.starts.clear();
+            self.generation = generation;
+        }
+        self.starts.range(..=after).next_back().map_or(0, |(_, &offset)| offset)
+    }
+
+    /// Remember `starts`, found in generation `generation` of the log.
+    pub(crate) fn extend(&mut self, generation: u64, starts: Starts) {
+        if generation == self.generation {
+            self.starts.extend(starts);
+        }
+    }
+}
+
+/// Up to `limit` changes with a sequence greater than `after`, read on from
+/// `reader`, which must be at or before the record holding the first of
+/// them.  Also returns where some of the records read start, for
+/// `WalOffsets::extend`.
+pub(crate) fn read_wal(
+    reader: &mut WalReader,
+    after: u64,
+    limit: usize,
+) -> Result<(Vec<Change>, Starts), WalError> {
+    let mut changes: Vec<Change> = Vec::new();
+    let mut starts  = Vec::new();
+    let mut stop    = None;
+    for read in 0.. {
+        if changes.len() >= limit {
+            break;
+        }
+        let offset = reader.offset();
+        let Some(raw) = reader.next_record()? else { break };
+        if let Checksum::Mismatch { expected, actual } = raw.checksum {
+            return Err(WalError::ChecksumMismatch { expected, actual });
+        }
+        let (Some(first), Some(last)) = (raw.entries.first(), raw.entries.last()) else { continue };
+        let (first, last) = (first.sequence, last.sequence);
+        let before = first.saturating_sub(1);
+        if read % OFFSET_EVERY == 0 {
+            starts.push((before, offset));
+        }
+        if last <= after {
+            stop = Some((last, reader.offset()));
+            continue;
+        }
+
+        let follows = match changes.last() {
+            Some(previous) => first == previous.sequence + 1,
+            None => first <= after + 1,
+        };
+        if !follows {
+            let expected = changes.last().map_or(after + 1, |previous| previous.sequence + 1);
+            return Err(WalError::SequenceGap { offset, expected, got: first });
+        }
+        let room = limit - changes.len();
+        changes.extend(
+            raw.entries
+                .into_iter()
+                .filter(|entry| entry.sequence > after)
+                .take(room)
+                .map(|entry| Change { sequence: entry.sequence, timestamp: entry.timestamp, record: entry.record }),
+        );
+        stop = match changes.last() {
+            Some(change) if change.sequence == last => Some((last, reader.offset())),
+            _ => Some((before, offset)),
+        };
+    }
+    starts.extend(stop);
+    Ok((changes, starts))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::wal::WriteAheadLog;
+
+    fn put(key: &str) -> WalRecord {
+        WalRecord::Put { key: key.to_owned(), value: b"value".to_vec() }
+    }
+
+    /// A log at `path` of puts 1 to 10, a batch of 11 to 15 and puts 16 to 20.
+    fn write_log(path: &Path) {
+        let mut wal = WriteAheadLog::open(path).unwrap();
+        for sequence in 1..=10 {
+            wal.append(&put("single"), sequence, 0).unwrap();
+        }
+        wal.append_batch(&[put("a"), put("b"), put("c"), put("d"), put("e")], 11, 0).unwrap();
+        for sequence in 16..=20 {
+            wal.append(&put("single"), sequence, 0).unwrap();
+        }
+        wal.sync().unwrap();
+    }
+
+    #[test]
+    fn each_batch_reads_on_from_where_the_last_stopped() {
+        let dir  = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path);
+
+        let mut offsets = WalOffsets::default();
+        let (mut after, mut start) = (0, 0);
+        while after < 20 {
+            let offset = offsets.start(0, after);
+            assert!(offset >= start, "read from {offset}, before {start}");
+            let mut reader = WalReader::follow(&path, offset).unwrap();
+            let (changes, starts) = read_wal(&mut reader, after, 3).unwrap();
+            let sequences: Vec<u64> = changes.iter().map(|change| change.sequence).collect();
+            assert_eq!(sequences, (after + 1..=(after + 3).min(20)).collect::<Vec<_>>());
+            offsets.extend(0, starts);
+            (after, start) = (after + 3, offset);
+        }
+        // A read stopped inside the batch starts over at the batch.
+        let batch = offsets.start(0, 12);
+        assert_eq!(offsets.start(0, 10), batch);
+        assert!(offsets.start(0, 15) > batch);
+
+        // Offsets of another generation of the log are forgotten.
+        assert_eq!(offsets.start(1, 15), 0);
+        offsets.extend(0, vec![(15, 1000)]);
+        assert_eq!(offsets.start(1, 15), 0);
+    }
+
+    #[test]
+    fn a_read_from_the_first_record_skips_what_it_has_seen() {
+        let dir  = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path);
+
+        let mut reader = WalReader::follow(&path, 0).unwrap();
+        let (changes, _) = read_wal(&mut reader, 12, 100).unwrap();
+        assert_eq!(changes.first().map(|change| change.sequence), Some(13));
+        assert_eq!(changes.len(), 8);
+        assert!(matches!(&changes[0].record, WalRecord::Put { key, .. } if key == "c"));
+    }
+
+    #[test]
+    fn a_gap_in_the_log_fails_the_read() {
+        let dir  = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        for sequence in [1, 2, 4] {
+            wal.append(&put("k"), sequence, 0).unwrap();
+        }
+
+        let mut reader = WalReader::follow(&path, 0).unwrap();
+        let err = read_wal(&mut reader, 0, 10).unwrap_err();
+        assert!(matches!(err, WalError::SequenceGap { expected: 3, got: 4, .. }), "{err}");
+
+        // Nor may a read start past the change asked for.
+        let mut reader = WalReader::follow(&path, 0).unwrap();
+        let last = std::iter::from_fn(|| reader.next_record().unwrap()).last().unwrap().offset;
+        let mut reader = WalReader::follow(&path, last).unwrap();
+        let err = read_wal(&mut reader, 1, 10).unwrap_err();
+        assert!(matches!(err, WalError::SequenceGap { expected: 2, got: 4, .. }), "{err}");
+    }
+}
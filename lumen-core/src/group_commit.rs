@@ -0,0 +1,332 @@
+//! Group commit: sharing one WAL sync between concurrent commits.
+//!
+//! Under `SyncPolicy::Interval` and `SyncPolicy::Adaptive`, a commit
+//! appends its record under the WAL lock as usual, releases the lock, and
+//! then waits until a sync covers its sequence.  The first commit to wait
+//! leads: it sleeps for the window, so the commits arriving meanwhile can
+//! append, then syncs everything appended so far, and the rest are woken
+//! together.
+//!
+//! A commit is applied to the memtable and published to the change feed
+//! before it waits, so readers and replicas can see a write that is not
+//! durable yet, and that a crash before the sync loses.  Only its caller is
+//! held back until the sync.  A failed sync therefore fails the engine: the
+//! leader reports the error, the commits waiting on it and every write
+//! after it are refused with `EngineError::SyncFailed`, and the store has
+//! to be reopened, recovering whatever the log really holds.  Retrying the
+//! sync is no use, as the kernel may have dropped the pages it could not
+//! write and report the next sync a success.
+//!
+//! A caller that holds a lock of its own across its writes, as the lease
+//! registry does, runs them in `Engine::deferring_sync`: their commits
+//! return without waiting, and the scope waits once, after the caller has
+//! let go of its lock, for a sync covering them all.  Waiting under the
+//! lock would make every other writer behind it wait for this sync before
+//! it could even append, so none of them would share it.
+//!
+//! The adaptive window starts at zero.  After each sync its leader looks at
+//! the p99 of recent commit latencies: above the target, or with nobody to
+//! share the sync with, the window halves; comfortably below the target
+//! while commits still share syncs, it widens, up to half the target.
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::engine::EngineError;
+use crate::metrics;
+use crate::sync::SyncPolicy;
+
+/// Commit latencies the adaptive window is tuned by.
+const LATENCY_SAMPLES: usize = 128;
+/// Smallest step the adaptive window widens by.
+const MIN_STEP: Duration = Duration::from_micros(50);
+
+#[derive(Debug)]
+struct State {
+    /// Every sequence up to this one is durable.
+    synced: u64,
+    /// Whether a commit is leading a sync.
+    leading: bool,
+    /// Why a sync failed, once one has.
+    failed: Option<String>,
+    window: Duration,
+    latencies: VecDeque<Duration>,
+}
+
+thread_local! {
+    /// The `GroupCommit::deferring` scope open on this thread, if any.
+    static DEFERRED: Cell<Option<Deferred>> = const { Cell::new(None) };
+}
+
+/// Waits left to a `deferring` scope: its group commit (by address), the
+/// last sequence committed through it in the scope (0: none), and when the
+/// scope opened.
+#[derive(Debug, Clone, Copy)]
+struct Deferred {
+    group: usize,
+    sequence: u64,
+    started: Instant,
+}
+
+/// Puts back the scope a `deferring` scope was opened in, even if its
+/// writes panic.
+struct Restore(Option<Deferred>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        DEFERRED.with(|deferred| deferred.set(self.0));
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct GroupCommit {
+    policy: SyncPolicy,
+    state: Mutex<State>,
+    done: Condvar,
+}
+
+impl GroupCommit {
+    /// Group commits as `policy` says; `synced` is durable already.
+    pub(crate) fn new(policy: SyncPolicy, synced: u64) -> Self {
+        let window = match policy {
+            SyncPolicy::Interval(window) => window,
+            SyncPolicy::Os | SyncPolicy::Adaptive { .. } => Duration::ZERO,
+        };
+        Self {
+            policy,
+            state: Mutex::new(State { synced, leading: false, failed: None, window, latencies: VecDeque::new() }),
+            done: Condvar::new(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether commits wait for a sync at all.
+    pub(crate) fn enabled(&self) -> bool {
+        self.policy != SyncPolicy::Os
+    }
+
+    /// The current window.
+    pub(crate) fn window(&self) -> Duration {
+        self.state().window
+    }
+
+    /// Refuse writes from now on: a sync failed with `error`.
+    pub(crate) fn fail(&self, error: &EngineError) {
+        let mut state = self.state();
+        if state.failed.is_none() {
+            warn!(error = %error, "WAL sync failed; refusing writes until the store is reopened");
+            state.failed = Some(error.to_string());
+        }
+        self.done.notify_all();
+    }
+
+    /// `Err(SyncFailed)` once a sync has failed.
+    pub(crate) fn check(&self) -> Result<(), EngineError> {
+        Self::healthy(&self.state())
+    }
+
+    fn healthy(state: &State) -> Result<(), EngineError> {
+        match &state.failed {
+            Some(error) => Err(EngineError::SyncFailed(error.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// Everything up to `sequence` is durable, whatever was synced before
+    /// (a checkpoint was installed in place of the log).
+    pub(crate) fn reset(&self, sequence: u64) {
+        self.state().synced = sequence;
+        self.done.notify_all();
+    }
+
+    fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// Leave the wait for `sequence` to the `deferring` scope open on this
+    /// thread for this group, if there is one.  Returns whether there was.
+    pub(crate) fn defer(&self, sequence: u64) -> bool {
+        DEFERRED.with(|deferred| match deferred.get() {
+            Some(scope) if scope.group == self.id() => {
+                deferred.set(Some(Deferred { sequence: scope.sequence.max(sequence), ..scope }));
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Run `writes` with the waits of their commits deferred, and return
+    /// the last sequence they committed, with when they began, for the
+    /// caller to `wait` for; `None` if they committed nothing, or if an
+    /// enclosing scope of this group is left to wait instead.
+    pub(crate) fn deferring<T>(&self, writes: impl FnOnce() -> T) -> (T, Option<(u64, Instant)>) {
+        let scope   = Deferred { group: self.id(), sequence: 0, started: Instant::now() };
+        let restore = Restore(DEFERRED.with(|deferred| deferred.replace(Some(scope))));
+        let result  = writes();
+        let ours    = DEFERRED.with(Cell::get).unwrap_or(scope);
+        let outer   = restore.0;
+        drop(restore);
+        if ours.sequence == 0 || (outer.is_some_and(|outer| outer.group == ours.group) && self.defer(ours.sequence)) {
+            return (result, None);
+        }
+        (result, Some((ours.sequence, ours.started)))
+    }
+
+    /// Return once `sequence`, committed by a write that began at `started`,
+    /// is durable, leading a sync with `sync` if none is under way.  `sync`
+    /// makes everything appended durable and returns the last sequence it
+    /// covered.  If it fails the leader returns its error and everyone
+    /// else `SyncFailed`.
+    pub(crate) fn wait(
+        &self,
+        sequence: u64,
+        started: Instant,
+        sync: impl Fn() -> Result<u64, EngineError>,
+    ) -> Result<(), EngineError> {
+        let mut state = self.state();
+        while state.synced < sequence {
+            Self::healthy(&state)?;
+            if state.leading {
+                state = self.done.wait(state).unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+
+            state.leading = true;
+            let (window, before) = (state.window, state.synced);
+            drop(state);
+            if !window.is_zero() {
+                std::thread::sleep(window);
+            }
+            let result = sync();
+
+            state = self.state();
+            state.leading = false;
+            let through = match result {
+                Ok(through) => through,
+                Err(e) => {
+                    drop(state);
+                    self.fail(&e);
+                    return Err(e);
+                }
+            };
+            self.done.notify_all();
+            state.synced = state.synced.max(through);
+            metrics::sync_group(through.saturating_sub(before), window);
+            self.adapt(&mut state, through.saturating_sub(before));
+        }
+
+        if let SyncPolicy::Adaptive { .. } = self.policy {
+            if state.latencies.len() == LATENCY_SAMPLES {
+                state.latencies.pop_front();
+            }
+            state.latencies.push_back(started.elapsed());
+        }
+        Ok(())
+    }
+
+    /// Tune the adaptive window after a sync covering `grouped` commits.
+    fn adapt(&self, state: &mut State, grouped: u64) {
+        let SyncPolicy::Adaptive { target_p99 } = self.policy else { return };
+        let mut sorted: Vec<Duration> = state.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        let p99 = sorted.get(sorted.len() * 99 / 100).copied().unwrap_or_default();
+
+        let window = state.window;
+        state.window = if grouped <= 1 || p99 > target_p99 {
+            window / 2
+        } else if p99 < target_p99 * 3 / 4 {
+            (window + (window / 4).max(MIN_STEP)).min(target_p99 / 2)
+        } else {
+            window
+        };
+        if state.window < MIN_STEP / 4 {
+            state.window = Duration::ZERO;
+        }
+        if state.window != window {
+            debug!(
+                window_us = state.window.as_micros() as u64,
+                p99_us = p99.as_micros() as u64,
+                grouped,
+                "Group commit window adjusted"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::WalError;
+
+    fn failing() -> Result<u64, EngineError> {
+        Err(EngineError::Wal(WalError::Io(std::io::Error::other("disk gone"))))
+    }
+
+    #[test]
+    fn a_sync_covers_every_sequence_it_returns() {
+        let group = GroupCommit::new(SyncPolicy::Interval(Duration::ZERO), 0);
+        group.wait(2, Instant::now(), || Ok(3)).unwrap();
+        group.wait(3, Instant::now(), || panic!("sequence 3 is synced already")).unwrap();
+        group.check().unwrap();
+    }
+
+    #[test]
+    fn a_deferring_scope_leaves_its_commits_waits_to_its_caller() {
+        let group = GroupCommit::new(SyncPolicy::Interval(Duration::ZERO), 0);
+        let other = GroupCommit::new(SyncPolicy::Os, 0);
+        let ((), pending) = group.deferring(|| {
+            assert!(group.defer(3));
+            assert!(group.defer(2));
+            // A nested scope hands its commits to this one.
+            let ((), nested) = group.deferring(|| assert!(group.defer(4)));
+            assert_eq!(nested, None);
+            assert!(!other.defer(1));
+        });
+        assert_eq!(pending.map(|(sequence, _)| sequence), Some(4));
+        assert!(!group.defer(5));
+        assert_eq!(group.deferring(|| ()).1, None);
+    }
+
+    #[test]
+    fn a_failed_sync_refuses_everything_after_it() {
+        let group = GroupCommit::new(SyncPolicy::Interval(Duration::ZERO), 0);
+        let leader = group.wait(1, Instant::now(), failing).unwrap_err();
+        assert!(matches!(leader, EngineError::Wal(WalError::Io(_))), "{leader:?}");
+
+        // The next sync would succeed, but nothing may trust it.
+        let next = group.wait(2, Instant::now(), || Ok(2)).unwrap_err();
+        assert!(matches!(&next, EngineError::SyncFailed(why) if why.contains("disk gone")), "{next:?}");
+        assert!(matches!(group.check(), Err(EngineError::SyncFailed(_))));
+    }
+
+    #[test]
+    fn waiters_on_a_failed_sync_are_refused() {
+        let group = std::sync::Arc::new(GroupCommit::new(SyncPolicy::Interval(Duration::ZERO), 0));
+        let (entered, release) = (std::sync::mpsc::channel(), std::sync::mpsc::channel::<()>());
+        let leader = {
+            let group = group.clone();
+            let (entered, release) = (entered.0, release.1);
+            std::thread::spawn(move || {
+                group.wait(1, Instant::now(), || {
+                    entered.send(()).unwrap();
+                    release.recv().unwrap();
+                    failing()
+                })
+            })
+        };
+        entered.1.recv().unwrap();
+        let waiter = {
+            let group = group.clone();
+            std::thread::spawn(move || group.wait(2, Instant::now(), || Ok(2)))
+        };
+        release.0.send(()).unwrap();
+
+        assert!(leader.join().unwrap().is_err());
+        assert!(matches!(waiter.join().unwrap(), Err(EngineError::SyncFailed(_))));
+    }
+}
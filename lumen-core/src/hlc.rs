@@ -0,0 +1,118 @@
+//! Hybrid logical clock.
+//!
+//! A timestamp packs wall-clock milliseconds since the Unix epoch into its
+//! upper 48 bits and a logical counter into the lower 16.  Timestamps issued
+//! by one clock strictly increase, even if the wall clock steps backwards,
+//! and `observe` folds in timestamps received from other nodes so that every
+//! later local timestamp orders after them.
+//!
+//! Persistence: a clock opened on a file keeps a *ceiling* there, an upper
+//! bound on every timestamp it has issued.  The ceiling is raised by
+//! `RESERVE_MILLIS` at a time, so the file is rewritten about once per
+//! reserve window rather than per timestamp, and a restarted clock resumes
+//! above it — strictly after everything issued before the restart, even if
+//! the wall clock has meanwhile moved backwards.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bits of a timestamp used by the logical counter.
+pub const LOGICAL_BITS: u32 = 16;
+
+/// How far ahead of the latest timestamp the persisted ceiling is set.
+const RESERVE_MILLIS: u64 = 1_000;
+
+fn wall_clock() -> u64 {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    millis << LOGICAL_BITS
+}
+
+/// Wall-clock milliseconds encoded in `timestamp`.
+pub fn physical_millis(timestamp: u64) -> u64 {
+    timestamp >> LOGICAL_BITS
+}
+
+/// A hybrid logical clock shared by every writer of one node.
+#[derive(Debug, Default)]
+pub struct HybridClock {
+    last: AtomicU64,
+    /// Persisted upper bound on issued timestamps (unused without a file).
+    ceiling: AtomicU64,
+    /// Ceiling file; `None` for an in-memory clock.
+    path: Option<PathBuf>,
+    /// Serialises ceiling updates.
+    persist: Mutex<()>,
+}
+
+impl HybridClock {
+    /// An in-memory clock that starts from the wall clock on every run.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A clock persisted at `path`, resuming after every timestamp issued by
+    /// a previous run.
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let ceiling = match std::fs::read_to_string(&path) {
+            Ok(text) => text.trim().parse().map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt HLC ceiling file")
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            last: AtomicU64::new(ceiling),
+            ceiling: AtomicU64::new(ceiling),
+            path: Some(path),
+            persist: Mutex::new(()),
+        })
+    }
+
+    /// A timestamp greater than every one this clock has issued or observed.
+    pub fn now(&self) -> u64 {
+        let wall = wall_clock();
+        let prev = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(wall.max(last + 1)))
+            .expect("update closure always succeeds");
+        let now = wall.max(prev + 1);
+        self.reserve(now);
+        now
+    }
+
+    /// Record a timestamp seen on another node.
+    pub fn observe(&self, remote: u64) {
+        self.last.fetch_max(remote, Ordering::SeqCst);
+        self.reserve(remote);
+    }
+
+    /// Raise the persisted ceiling above `timestamp` if needed.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn reserve(&self, timestamp: u64) {
+        let Some(path) = &self.path else { return };
+        if timestamp < self.ceiling.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let _guard = self.persist.lock().unwrap_or_else(|e| e.into_inner());
+        if timestamp < self.ceiling.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let ceiling = timestamp + (RESERVE_MILLIS << LOGICAL_BITS);
+        let tmp     = path.with_extension("tmp");
+        let written = std::fs::write(&tmp, format!("{ceiling}\n")).and_then(|_| std::fs::rename(&tmp, path));
+        match written {
+            Ok(()) => self.ceiling.store(ceiling, Ordering::SeqCst),
+            // Retried on the next timestamp; until then, only a restart
+            // combined with a backwards clock step could reorder timestamps.
+            Err(e) => warn!(error = %e, path = %path.display(), "Failed to persist HLC ceiling"),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Secondary indexes: primary keys looked up by values derived from what
+//! they store.
+//!
+//! The embedder registers each index with an extractor, which maps a stored
+//! value to the index values it should be found under (none, if the value
+//! is not indexed, or several, e.g. for each element of a JSON array).  The
+//! engine keeps every index current with the writes it applies, inside the
+//! same memtable update, so a query never sees an index value that the
+//! stored value does not (yet, or any more) produce.
+//!
+//! Indexes are derived state, held in memory only: registering one builds it
+//! from the memtable, and an engine opened again starts with none until
+//! they are registered again.  Reserved keys (see `lease`) are not indexed.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::lease::is_reserved_key;
+
+/// Maps a stored value to the index values its key is found under.
+pub type Extractor = Arc<dyn Fn(&[u8]) -> Vec<Vec<u8>> + Send + Sync>;
+
+struct Index {
+    extract: Extractor,
+    /// Index value → the keys whose values produce it.
+    entries: BTreeMap<Vec<u8>, BTreeSet<String>>,
+    /// Key → the index values its value produced, so that a write can move
+    /// the key without the value it replaced, which it does not read.
+    derived: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl Index {
+    fn insert(&mut self, key: &str, value: &[u8]) {
+        let derived = (self.extract)(value);
+        if derived.is_empty() {
+            return;
+        }
+        for derived in &derived {
+            self.entries.entry(derived.clone()).or_default().insert(key.to_owned());
+        }
+        self.derived.insert(key.to_owned(), derived);
+    }
+
+    fn remove(&mut self, key: &str) {
+        for derived in self.derived.remove(key).unwrap_or_default() {
+            if let Some(keys) = self.entries.get_mut(&derived) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.entries.remove(&derived);
+                }
+            }
+        }
+    }
+}
+
+/// Every index registered on an engine.  Only modified while the memtable
+/// is write-locked, so it always matches the memtable.
+#[derive(Default)]
+pub(crate) struct Indexes {
+    by_name: HashMap<String, Index>,
+}
+
+impl fmt::Debug for Indexes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.by_name.keys()).finish()
+    }
+}
+
+impl Indexes {
+    /// Register `name`, built from the live `entries`.  Returns `false` if
+    /// an index of that name exists already.
+    pub(crate) fn register<E>(
+        &mut self,
+        name: String,
+        extract: Extractor,
+        entries: impl IntoIterator<Item = Result<(String, Vec<u8>), E>>,
+    ) -> Result<bool, E> {
+        if self.by_name.contains_key(&name) {
+            return Ok(false);
+        }
+        let mut index = Index { extract, entries: BTreeMap::new(), derived: HashMap::new() };
+        for entry in entries {
+            let (key, value) = entry?;
+            if !is_reserved_key(&key) {
+                index.insert(&key, &value);
+            }
+        }
+        self.by_name.insert(name, index);
+        Ok(true)
+    }
+
+    /// Move `key` from the index values of its previous value to those of
+    /// `value` (`None` if the key is now absent).
+    pub(crate) fn update(&mut self, key: &str, value: Option<&[u8]>) {
+        if self.by_name.is_empty() || is_reserved_key(key) {
+            return;
+        }
+        for index in self.by_name.values_mut() {
+            index.remove(key);
+            if let Some(value) = value {
+                index.insert(key, value);
+            }
+        }
+    }
+
+    /// Re-derive every index from the live `entries`, which replaced the
+    /// memtable.
+    pub(crate) fn rebuild<E>(&mut self, entries: impl IntoIterator<Item = Result<(String, Vec<u8>), E>>) -> Result<(), E> {
+        for index in self.by_name.values_mut() {
+            index.entries.clear();
+            index.derived.clear();
+        }
+        if self.by_name.is_empty() {
+            return Ok(());
+        }
+        for entry in entries {
+            let (key, value) = entry?;
+            if !is_reserved_key(&key) {
+                for index in self.by_name.values_mut() {
+                    index.insert(&key, &value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keys found under `value` in index `name`, in key order, or `None` if
+    /// there is no such index.
+    pub(crate) fn query(&self, name: &str, value: &[u8]) -> Option<Vec<String>> {
+        let index = self.by_name.get(name)?;
+        Some(index.entries.get(value).map(|keys| keys.iter().cloned().collect()).unwrap_or_default())
+    }
+
+    pub(crate) fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.by_name.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// Indexes `name` by each comma-separated part of a value.
+    fn by_parts(name: &str) -> Indexes {
+        let mut indexes = Indexes::default();
+        let extract: Extractor = Arc::new(|value| value.split(|&b| b == b',').map(<[u8]>::to_vec).collect());
+        indexes.register(name.to_owned(), extract, std::iter::empty::<Result<_, Infallible>>()).unwrap();
+        indexes
+    }
+
+    #[test]
+    fn an_overwrite_moves_a_key_and_a_delete_removes_it() {
+        let mut indexes = by_parts("parts");
+        indexes.update("k", Some(b"red,blue"));
+        indexes.update("j", Some(b"red"));
+        assert_eq!(indexes.query("parts", b"red").unwrap(), ["j", "k"]);
+
+        indexes.update("k", Some(b"green"));
+        assert_eq!(indexes.query("parts", b"red").unwrap(), ["j"]);
+        assert!(indexes.query("parts", b"blue").unwrap().is_empty());
+        assert_eq!(indexes.query("parts", b"green").unwrap(), ["k"]);
+
+        indexes.update("k", None);
+        indexes.update("j", None);
+        assert!(indexes.query("parts", b"green").unwrap().is_empty());
+        assert!(indexes.by_name["parts"].entries.is_empty(), "deleted keys leave no index values behind");
+        assert!(indexes.by_name["parts"].derived.is_empty());
+    }
+
+    #[test]
+    fn reserved_keys_are_not_indexed() {
+        let mut indexes = by_parts("parts");
+        indexes.update("\0lease/0000000000000001/k", Some(b"red"));
+        assert!(indexes.query("parts", b"red").unwrap().is_empty());
+        assert_eq!(indexes.query("missing", b"red"), None);
+    }
+
+    #[test]
+    fn a_rebuild_forgets_keys_the_new_entries_lack() {
+        let mut indexes = by_parts("parts");
+        indexes.update("old", Some(b"red"));
+        let entries = [("new".to_owned(), b"red".to_vec())].map(Ok::<_, Infallible>);
+        indexes.rebuild(entries).unwrap();
+        assert_eq!(indexes.query("parts", b"red").unwrap(), ["new"]);
+    }
+
+    #[test]
+    fn a_name_is_registered_once() {
+        let mut indexes = by_parts("parts");
+        let extract: Extractor = Arc::new(|_| Vec::new());
+        assert!(!indexes.register("parts".to_owned(), extract, std::iter::empty::<Result<_, Infallible>>()).unwrap());
+        assert_eq!(indexes.names(), ["parts"]);
+    }
+}
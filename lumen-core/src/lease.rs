@@ -0,0 +1,774 @@
+//! Leases: TTLs kept alive by their holders, with keys attached that are
+//! deleted when the lease expires or is revoked (as in etcd), and the locks
+//! built on them.
+//!
+//! Leases live in the engine itself, under reserved keys (see
+//! `is_reserved_key`) that clients cannot write:
+//!
+//!   \0lease/{id:016x}          the lease, valued with its TTL
+//!                              (8 bytes, BE, milliseconds)
+//!   \0lease/{id:016x}/{key}    `key` is attached to the lease (empty value)
+//!   \0lock/{name}              lock `name`, valued with its fencing token
+//!                              and holder's lease (8 bytes, BE, each), and
+//!                              attached to that lease
+//!
+//! so they are logged, replicated, checkpointed and backed up like any other
+//! key.  Attaching or detaching a key is committed in one batch with the
+//! write that does it, and a revocation deletes the keys, their attachments
+//! and the lease in one batch, so recovery never finds a key attached to a
+//! lease it does not have, or the reverse.  The batch of an expiry starts by
+//! emptying the lease's value, which marks the deletes after it as expiries
+//! for readers of the change feed (see `ExpiryTracker`).
+//!
+//! The registry is locked across each of its writes, but not through the
+//! wait for them to be synced (see `Engine::deferring_sync`), so writers
+//! still share syncs.
+//!
+//! Deadlines are kept in memory only.  A lease read back on open gets its
+//! full TTL again: a restart can extend a lease, but never expires one
+//! early.  Only the node that writes should hold a `Leases`; replicas
+//! receive the deletes of an expiry like any other change.
+//!
+//! A lock is taken by compare-and-swap: its key is written only if absent,
+//! and deleted only by the holder of its current fencing token.  The token
+//! is one past the engine's latest sequence when the lock is taken, so each
+//! is greater than every token handed out before it, for any lock; storage
+//! that records the highest token it has seen can refuse a holder whose
+//! lease expired while it was paused.  A holder's lease expiring releases
+//! its locks.
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::code::ErrorCode;
+use crate::engine::{Engine, EngineError};
+use crate::scan::KeyRange;
+use crate::wal::WalRecord;
+
+/// Keys starting with this byte hold engine metadata, not client data.
+const RESERVED_PREFIX: char = '\0';
+const LEASE_PREFIX: &str = "\0lease/";
+const LOCK_PREFIX: &str = "\0lock/";
+
+/// Whether `key` is reserved for engine metadata, such as leases.
+/// Client writes to such keys should be refused, and listings skip them.
+pub fn is_reserved_key(key: &str) -> bool {
+    key.starts_with(RESERVED_PREFIX)
+}
+
+// ---------------------------------------------------------------------------
+// Error type
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Error)]
+pub enum LeaseError {
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+
+    #[error("Lease {0} not found")]
+    NotFound(u64),
+
+    #[error("Lease {0} already exists")]
+    Exists(u64),
+
+    #[error("Lock {name:?} is held by lease {lease}")]
+    Locked { name: String, lease: u64 },
+}
+
+impl LeaseError {
+    /// The stable code of this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            LeaseError::Engine(e) => e.code(),
+            LeaseError::NotFound(_) => ErrorCode::NotFound,
+            LeaseError::Exists(_) => ErrorCode::AlreadyExists,
+            LeaseError::Locked { .. } => ErrorCode::Conflict,
+        }
+    }
+}
+
+impl<T> From<PoisonError<T>> for LeaseError {
+    fn from(_: PoisonError<T>) -> Self {
+        LeaseError::Engine(EngineError::LockPoisoned)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Leases
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+struct Lease {
+    ttl: Duration,
+    deadline: Instant,
+    keys: BTreeSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    leases: HashMap<u64, Lease>,
+    /// The lease each attached key belongs to.
+    owners: HashMap<String, u64>,
+}
+
+/// The lease registry of one engine.  Writes to keys that may be attached to
+/// a lease must go through it, so it can keep their attachments current.
+#[derive(Debug)]
+pub struct Leases {
+    engine: Engine,
+    /// Held across the engine writes that change it, so its view of the
+    /// attachments follows the log order.
+    state: Mutex<State>,
+}
+
+impl Leases {
+    /// Load the leases stored in `engine`, each due one full TTL from now.
+    pub fn open(engine: Engine) -> Result<Self, EngineError> {
+        let now       = Instant::now();
+        let mut state = State::default();
+        let mut orphans = 0;
+
+        // Each lease sorts before its attachments.
+        for entry in engine.scan(KeyRange::prefix(LEASE_PREFIX))? {
+            let (stored, value) = entry?;
+            match parse_key(&stored) {
+                Some((id, None)) => {
+                    // Empty if a replica stopped partway through an expiry:
+                    // due at once.
+                    let ttl = Duration::from_millis(value.try_into().map_or(0, u64::from_be_bytes));
+                    state.leases.insert(id, Lease { ttl, deadline: now + ttl, keys: BTreeSet::new() });
+                }
+                Some((id, Some(key))) => match state.leases.get_mut(&id) {
+                    Some(lease) => {
+                        lease.keys.insert(key.to_owned());
+                        state.owners.insert(key.to_owned(), id);
+                    }
+                    None => orphans += 1,
+                },
+                None => orphans += 1,
+            }
+        }
+        if orphans > 0 {
+            warn!(orphans, "Ignoring lease keys that belong to no lease");
+        }
+        info!(leases = state.leases.len(), keys = state.owners.len(), "Leases loaded");
+
+        Ok(Self { engine, state: Mutex::new(state) })
+    }
+
+    /// Grant a lease of `ttl`, numbered `id`, or by the registry if `id` is
+    /// 0.  Returns its number.
+    pub fn grant(&self, id: u64, ttl: Duration) -> Result<u64, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            let id = match id {
+                0 => unused_id(&state),
+                id if state.leases.contains_key(&id) => return Err(LeaseError::Exists(id)),
+                id => id,
+            };
+
+            let ttl_ms = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+            self.engine.put(lease_key(id), ttl_ms.to_be_bytes().to_vec())?;
+            state.leases.insert(id, Lease { ttl, deadline: Instant::now() + ttl, keys: BTreeSet::new() });
+            debug!(lease = id, ttl_ms, "LEASE GRANT");
+            Ok(id)
+        })
+    }
+
+    /// Push lease `id`'s deadline one full TTL into the future.  Returns the
+    /// TTL.
+    pub fn keep_alive(&self, id: u64) -> Result<Duration, LeaseError> {
+        let mut state = self.state.lock()?;
+        let lease = state.leases.get_mut(&id).ok_or(LeaseError::NotFound(id))?;
+        lease.deadline = Instant::now() + lease.ttl;
+        Ok(lease.ttl)
+    }
+
+    /// How long lease `id` has left, or `None` if there is no such lease.
+    pub fn remaining(&self, id: u64) -> Result<Option<Duration>, LeaseError> {
+        let state = self.state.lock()?;
+        Ok(state.leases.get(&id).map(|lease| lease.deadline.saturating_duration_since(Instant::now())))
+    }
+
+    /// End lease `id` now, deleting its keys.  Returns how many there were.
+    pub fn revoke(&self, id: u64) -> Result<usize, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            self.remove(&mut state, id, false)
+        })
+    }
+
+    /// Revoke every lease past its deadline.  Returns the number of each,
+    /// with how many keys it deleted.
+    pub fn expire(&self) -> Result<Vec<(u64, usize)>, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            let now = Instant::now();
+            let due: Vec<u64> = state.leases.iter().filter(|(_, l)| l.deadline <= now).map(|(id, _)| *id).collect();
+            due.into_iter().map(|id| Ok((id, self.remove(&mut state, id, true)?))).collect()
+        })
+    }
+
+    /// Put `key`, attached to lease `lease`, or to none if it is 0.  A key
+    /// attached to another lease moves to the new one (or is detached).
+    pub fn put(&self, key: String, value: Vec<u8>, lease: u64) -> Result<(), LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            self.put_locked(&mut state, key, value, lease)
+        })
+    }
+
+    /// Attach each of `keys` that exists to lease `lease`, moving it from
+    /// any lease it had.  Values are not rewritten: only the attachments
+    /// are, in one batch however many keys there are.  Returns how many of
+    /// `keys` are now attached to `lease`.
+    pub fn attach(&self, keys: &[String], lease: u64) -> Result<usize, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            if !state.leases.contains_key(&lease) {
+                return Err(LeaseError::NotFound(lease));
+            }
+
+            let (mut batch, mut moved, mut attached) = (Vec::new(), Vec::new(), 0);
+            for key in keys.iter().filter(|key| !is_reserved_key(key)) {
+                let previous = state.owners.get(key).copied();
+                if previous == Some(lease) {
+                    attached += 1;
+                    continue;
+                }
+                // Every write to a key goes through the registry while it is
+                // locked, so the key cannot be deleted before the batch.
+                if previous.is_none() && self.engine.get(key)?.is_none() {
+                    continue;
+                }
+                if let Some(previous) = previous {
+                    batch.push(WalRecord::Delete { key: attachment_key(previous, key) });
+                }
+                batch.push(WalRecord::Put { key: attachment_key(lease, key), value: Vec::new() });
+                moved.push((key.clone(), previous));
+            }
+            self.engine.write_batch(batch)?;
+
+            attached += moved.len();
+            for (key, previous) in moved {
+                if let Some(previous) = previous.and_then(|previous| state.leases.get_mut(&previous)) {
+                    previous.keys.remove(&key);
+                }
+                state.owners.insert(key.clone(), lease);
+                state.leases.get_mut(&lease).expect("checked above").keys.insert(key);
+            }
+            debug!(lease, keys = attached, "LEASE ATTACH");
+            Ok(attached)
+        })
+    }
+
+    /// Detach each of `keys` from its lease, so it no longer expires, without
+    /// rewriting its value.  Returns how many were detached.
+    pub fn detach(&self, keys: &[String]) -> Result<usize, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            let detached: Vec<(String, u64)> = keys
+                .iter()
+                .filter(|key| !is_reserved_key(key))
+                .filter_map(|key| state.owners.get(key).map(|&lease| (key.clone(), lease)))
+                .collect();
+            let batch = detached.iter().map(|(key, lease)| WalRecord::Delete { key: attachment_key(*lease, key) }).collect();
+            self.engine.write_batch(batch)?;
+
+            for (key, lease) in &detached {
+                state.owners.remove(key);
+                if let Some(lease) = state.leases.get_mut(lease) {
+                    lease.keys.remove(key);
+                }
+            }
+            debug!(keys = detached.len(), "LEASE DETACH");
+            Ok(detached.len())
+        })
+    }
+
+    /// `Engine::get_and_set`, detaching `key` from its lease (as a put
+    /// without one does).
+    pub fn get_and_set(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            if !state.owners.contains_key(&key) {
+                return Ok(self.engine.get_and_set(key, value)?);
+            }
+            // Every write to an attached key goes through the registry, so it
+            // cannot change while the registry is locked.
+            let previous = self.engine.get(&key)?;
+            self.put_locked(&mut state, key, value, 0)?;
+            Ok(previous)
+        })
+    }
+
+    /// `Engine::compare_and_delete`, detaching `key` from its lease if it is
+    /// deleted.
+    pub fn compare_and_delete(&self, key: &str, expected: &[u8]) -> Result<bool, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            if !state.owners.contains_key(key) {
+                return Ok(self.engine.compare_and_delete(key, expected)?);
+            }
+            if self.engine.get(key)?.as_deref() != Some(expected) {
+                return Ok(false);
+            }
+            self.delete_locked(&mut state, key)
+        })
+    }
+
+    /// `Engine::rename`.  The key keeps its lease under its new name, and a
+    /// key it overwrites is detached from its own.
+    pub fn rename(&self, old_key: &str, new_key: String, overwrite: bool) -> Result<bool, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            let (from, to) = (state.owners.get(old_key).copied(), state.owners.get(&new_key).copied());
+            if (from.is_none() && to.is_none()) || old_key == new_key {
+                return Ok(self.engine.rename(old_key, new_key, overwrite)?);
+            }
+            let Some(value) = self.engine.get(old_key)? else { return Ok(false) };
+            if !overwrite && to.is_some() {
+                return Ok(false);
+            }
+
+            // Unattached keys may be written around the registry: the batch is
+            // conditional on the values just read.
+            let mut expected = vec![(old_key.to_owned(), Some(value.clone()))];
+            if !overwrite {
+                expected.push((new_key.clone(), None));
+            }
+            let mut batch = vec![
+                WalRecord::Put { key: new_key.clone(), value },
+                WalRecord::Delete { key: old_key.to_owned() },
+            ];
+            if let Some(lease) = to {
+                batch.push(WalRecord::Delete { key: attachment_key(lease, &new_key) });
+            }
+            if let Some(lease) = from {
+                batch.push(WalRecord::Delete { key: attachment_key(lease, old_key) });
+                batch.push(WalRecord::Put { key: attachment_key(lease, &new_key), value: Vec::new() });
+            }
+            if !self.engine.write_batch_if(&expected, batch)? {
+                return Ok(false);
+            }
+
+            for (key, lease) in [(new_key.as_str(), to), (old_key, from)] {
+                if let Some(lease) = lease {
+                    state.owners.remove(key);
+                    if let Some(lease) = state.leases.get_mut(&lease) {
+                        lease.keys.remove(key);
+                    }
+                }
+            }
+            if let Some(lease) = from {
+                state.owners.insert(new_key.clone(), lease);
+                if let Some(lease) = state.leases.get_mut(&lease) {
+                    lease.keys.insert(new_key);
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    /// `Engine::write_batch_if`, detaching each key `records` writes from its
+    /// lease (as a put without one does).
+    pub fn write_batch_if(
+        &self,
+        expected: &[(String, Option<Vec<u8>>)],
+        mut records: Vec<WalRecord>,
+    ) -> Result<bool, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            let detached: Vec<(String, u64)> = records
+                .iter()
+                .filter_map(|record| {
+                    let (WalRecord::Put { key, .. } | WalRecord::Delete { key }) = record;
+                    state.owners.get(key).map(|&lease| (key.clone(), lease))
+                })
+                .collect();
+            for (key, lease) in &detached {
+                records.push(WalRecord::Delete { key: attachment_key(*lease, key) });
+            }
+            if !self.engine.write_batch_if(expected, records)? {
+                return Ok(false);
+            }
+
+            for (key, lease) in detached {
+                state.owners.remove(&key);
+                if let Some(lease) = state.leases.get_mut(&lease) {
+                    lease.keys.remove(&key);
+                }
+            }
+            Ok(true)
+        })
+    }
+
+    fn put_locked(&self, state: &mut State, key: String, value: Vec<u8>, lease: u64) -> Result<(), LeaseError> {
+        if lease != 0 && !state.leases.contains_key(&lease) {
+            return Err(LeaseError::NotFound(lease));
+        }
+        let previous = state.owners.get(&key).copied();
+        if previous.is_none() && lease == 0 {
+            return Ok(self.engine.put(key, value)?);
+        }
+
+        let mut batch = vec![WalRecord::Put { key: key.clone(), value }];
+        if let Some(previous) = previous.filter(|&previous| previous != lease) {
+            batch.push(WalRecord::Delete { key: attachment_key(previous, &key) });
+        }
+        if lease != 0 && previous != Some(lease) {
+            batch.push(WalRecord::Put { key: attachment_key(lease, &key), value: Vec::new() });
+        }
+        self.engine.write_batch(batch)?;
+
+        if let Some(previous) = previous.filter(|&previous| previous != lease) {
+            state.owners.remove(&key);
+            if let Some(lease) = state.leases.get_mut(&previous) {
+                lease.keys.remove(&key);
+            }
+        }
+        if lease != 0 {
+            state.owners.insert(key.clone(), lease);
+            state.leases.get_mut(&lease).expect("checked above").keys.insert(key);
+        }
+        Ok(())
+    }
+
+    /// Delete `key`, detaching it from its lease.  Returns whether it
+    /// existed.
+    pub fn delete(&self, key: &str) -> Result<bool, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            self.delete_locked(&mut state, key)
+        })
+    }
+
+    fn delete_locked(&self, state: &mut State, key: &str) -> Result<bool, LeaseError> {
+        let Some(lease) = state.owners.remove(key) else {
+            return Ok(self.engine.delete(key)?);
+        };
+        if let Some(lease) = state.leases.get_mut(&lease) {
+            lease.keys.remove(key);
+        }
+        self.engine.write_batch(vec![
+            WalRecord::Delete { key: key.to_owned() },
+            WalRecord::Delete { key: attachment_key(lease, key) },
+        ])?;
+        Ok(true)
+    }
+
+    /// Take lock `name` for lease `lease`, returning its fencing token.  A
+    /// lock the lease already holds is returned as it is.
+    pub fn lock(&self, name: &str, lease: u64) -> Result<u64, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            if !state.leases.contains_key(&lease) {
+                return Err(LeaseError::NotFound(lease));
+            }
+            let key = lock_key(name);
+            match self.engine.get(&key)?.as_deref().and_then(parse_lock) {
+                Some((token, holder)) if holder == lease => return Ok(token),
+                Some((_, holder)) => return Err(LeaseError::Locked { name: name.to_owned(), lease: holder }),
+                None => {}
+            }
+
+            let token = self.engine.latest_sequence()? + 1;
+            let value = [token.to_be_bytes(), lease.to_be_bytes()].concat();
+            self.engine.write_batch(vec![
+                WalRecord::Put { key: attachment_key(lease, &key), value: Vec::new() },
+                WalRecord::Put { key: key.clone(), value },
+            ])?;
+            state.owners.insert(key.clone(), lease);
+            state.leases.get_mut(&lease).expect("checked above").keys.insert(key);
+            debug!(lock = name, lease, token, "LOCK");
+            Ok(token)
+        })
+    }
+
+    /// Release lock `name` if `token` is its current fencing token.
+    /// Returns whether it was released.
+    pub fn unlock(&self, name: &str, token: u64) -> Result<bool, LeaseError> {
+        self.engine.deferring_sync(|| {
+            let mut state = self.state.lock()?;
+            let key = lock_key(name);
+            let Some((current, holder)) = self.engine.get(&key)?.as_deref().and_then(parse_lock) else {
+                return Ok(false);
+            };
+            if current != token {
+                return Ok(false);
+            }
+
+            self.engine.write_batch(vec![
+                WalRecord::Delete { key: key.clone() },
+                WalRecord::Delete { key: attachment_key(holder, &key) },
+            ])?;
+            state.owners.remove(&key);
+            if let Some(lease) = state.leases.get_mut(&holder) {
+                lease.keys.remove(&key);
+            }
+            debug!(lock = name, token, "UNLOCK");
+            Ok(true)
+        })
+    }
+
+    /// Delete lease `id`, its attachments and its keys in one batch, marked
+    /// as an expiry if `expired`.
+    fn remove(&self, state: &mut State, id: u64, expired: bool) -> Result<usize, LeaseError> {
+        let lease = state.leases.get(&id).ok_or(LeaseError::NotFound(id))?;
+        let mut batch = Vec::with_capacity(lease.keys.len() * 2 + 2);
+        if expired {
+            batch.push(WalRecord::Put { key: lease_key(id), value: Vec::new() });
+        }
+        for key in &lease.keys {
+            batch.push(WalRecord::Delete { key: key.clone() });
+            batch.push(WalRecord::Delete { key: attachment_key(id, key) });
+        }
+        batch.push(WalRecord::Delete { key: lease_key(id) });
+        self.engine.write_batch(batch)?;
+
+        let lease = state.leases.remove(&id).expect("looked up above");
+        for key in &lease.keys {
+            state.owners.remove(key);
+        }
+        Ok(lease.keys.len())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// ExpiryTracker
+// ---------------------------------------------------------------------------
+
+/// Tells, for a reader of the change feed, which deletes were made by a
+/// lease expiring rather than by a client or a revocation.
+///
+/// Every change must be passed to `is_expiry` in sequence order.  A reader
+/// that starts in the middle of an expiry's batch sees the rest of it as
+/// plain deletes.
+#[derive(Debug, Clone, Default)]
+pub struct ExpiryTracker {
+    /// The lease whose expiry batch is being read.
+    expiring: Option<u64>,
+}
+
+impl ExpiryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `record` deletes a key because its lease expired.
+    pub fn is_expiry(&mut self, record: &WalRecord) -> bool {
+        match record {
+            WalRecord::Put { key, value } => {
+                if let Some((id, None)) = parse_key(key) {
+                    self.expiring = value.is_empty().then_some(id);
+                }
+                false
+            }
+            WalRecord::Delete { key } => match parse_key(key) {
+                Some((id, None)) if self.expiring == Some(id) => {
+                    self.expiring = None;
+                    false
+                }
+                Some(_) => false,
+                None => self.expiring.is_some(),
+            },
+        }
+    }
+}
+
+/// A random lease number that is neither 0 nor taken.
+fn unused_id(state: &State) -> u64 {
+    loop {
+        let id = random_u64() >> 1;
+        if id != 0 && !state.leases.contains_key(&id) {
+            return id;
+        }
+    }
+}
+
+/// 64 bits from the standard library's randomly keyed hasher (keyed anew
+/// on every call), as the engine takes no dependency for randomness.
+fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+
+    RandomState::new().hash_one(0u8)
+}
+
+fn lease_key(id: u64) -> String {
+    format!("{LEASE_PREFIX}{id:016x}")
+}
+
+fn attachment_key(id: u64, key: &str) -> String {
+    format!("{LEASE_PREFIX}{id:016x}/{key}")
+}
+
+fn lock_key(name: &str) -> String {
+    format!("{LOCK_PREFIX}{name}")
+}
+
+/// The fencing token and holding lease stored in a lock's value.
+fn parse_lock(value: &[u8]) -> Option<(u64, u64)> {
+    let token = u64::from_be_bytes(value.get(..8)?.try_into().ok()?);
+    let lease = u64::from_be_bytes(value.get(8..16)?.try_into().ok()?);
+    Some((token, lease))
+}
+
+/// The lease a stored key belongs to, and the key it attaches, if any.
+fn parse_key(stored: &str) -> Option<(u64, Option<&str>)> {
+    let rest = stored.strip_prefix(LEASE_PREFIX)?;
+    let id   = u64::from_str_radix(rest.get(..16)?, 16).ok()?;
+    match &rest[16..] {
+        "" => Some((id, None)),
+        attached => Some((id, Some(attached.strip_prefix('/')?))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn an_expired_lease_deletes_its_keys_and_no_others() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        let id     = leases.grant(0, TTL).unwrap();
+        leases.put("held".to_owned(), b"1".to_vec(), id).unwrap();
+        leases.put("free".to_owned(), b"2".to_vec(), 0).unwrap();
+
+        assert!(leases.expire().unwrap().is_empty());
+        sleep(TTL);
+        assert_eq!(leases.expire().unwrap(), [(id, 1)]);
+        assert_eq!(engine.get("held").unwrap(), None);
+        assert_eq!(engine.get("free").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(engine.scan(KeyRange::prefix(LEASE_PREFIX)).unwrap().count(), 0);
+        assert!(matches!(leases.keep_alive(id), Err(LeaseError::NotFound(_))));
+    }
+
+    #[test]
+    fn keep_alive_pushes_the_deadline_back_a_full_ttl() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        let id     = leases.grant(7, TTL).unwrap();
+        leases.put("held".to_owned(), b"1".to_vec(), id).unwrap();
+
+        for _ in 0..3 {
+            sleep(TTL / 2);
+            assert_eq!(leases.keep_alive(id).unwrap(), TTL);
+            assert!(leases.expire().unwrap().is_empty());
+        }
+        assert!(leases.remaining(id).unwrap().unwrap() > TTL / 2);
+        assert_eq!(engine.get("held").unwrap(), Some(b"1".to_vec()));
+
+        sleep(TTL);
+        assert_eq!(leases.expire().unwrap(), [(7, 1)]);
+        assert_eq!(engine.get("held").unwrap(), None);
+    }
+
+    #[test]
+    fn a_key_put_without_its_lease_outlives_it() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        let id     = leases.grant(0, TTL).unwrap();
+        leases.put("key".to_owned(), b"1".to_vec(), id).unwrap();
+        leases.put("key".to_owned(), b"2".to_vec(), 0).unwrap();
+
+        assert_eq!(leases.revoke(id).unwrap(), 0);
+        assert_eq!(engine.get("key").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn reopened_leases_keep_their_keys_and_get_a_full_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let leases = Leases::open(Engine::open(dir.path()).unwrap()).unwrap();
+            leases.grant(3, TTL).unwrap();
+            leases.put("held".to_owned(), b"1".to_vec(), 3).unwrap();
+            sleep(TTL);
+        }
+
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        assert!(leases.expire().unwrap().is_empty(), "a restart must not expire a lease early");
+        assert_eq!(leases.remaining(3).unwrap().map(|left| left > TTL / 2), Some(true));
+        assert_eq!(leases.revoke(3).unwrap(), 1);
+        assert_eq!(engine.get("held").unwrap(), None);
+    }
+
+    #[test]
+    fn the_feed_tells_an_expiry_from_a_revocation() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        for id in [1, 2] {
+            leases.grant(id, TTL).unwrap();
+            leases.put(format!("key-{id}"), b"v".to_vec(), id).unwrap();
+        }
+        let start = engine.latest_sequence().unwrap();
+        leases.revoke(1).unwrap();
+        sleep(TTL);
+        leases.expire().unwrap();
+
+        let mut tracker = ExpiryTracker::new();
+        let expired: Vec<String> = engine
+            .changes_since(start, usize::MAX)
+            .unwrap()
+            .into_iter()
+            .filter(|change| tracker.is_expiry(&change.record))
+            .map(|change| match change.record {
+                WalRecord::Put { key, .. } | WalRecord::Delete { key } => key,
+            })
+            .collect();
+        assert_eq!(expired, ["key-2"]);
+    }
+
+    #[test]
+    fn atomic_writes_to_an_attached_key_detach_it_only_if_they_apply() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        let id     = leases.grant(0, TTL).unwrap();
+        leases.put("cas".to_owned(), b"current".to_vec(), id).unwrap();
+        leases.put("swap".to_owned(), b"1".to_vec(), id).unwrap();
+
+        assert!(!leases.compare_and_delete("cas", b"stale").unwrap());
+        assert_eq!(engine.get("cas").unwrap(), Some(b"current".to_vec()));
+        assert_eq!(leases.get_and_set("swap".to_owned(), b"2".to_vec()).unwrap(), Some(b"1".to_vec()));
+
+        // "swap" was detached by its set; "cas" is still the lease's.
+        assert_eq!(leases.revoke(id).unwrap(), 1);
+        assert_eq!(engine.get("cas").unwrap(), None);
+        assert_eq!(engine.get("swap").unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn a_renamed_key_keeps_its_lease_under_its_new_name() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        let leases = Leases::open(engine.clone()).unwrap();
+        let id     = leases.grant(0, TTL).unwrap();
+        leases.put("from".to_owned(), b"moved".to_vec(), id).unwrap();
+        leases.put("to".to_owned(), b"there".to_vec(), 0).unwrap();
+
+        assert!(!leases.rename("from", "to".to_owned(), false).unwrap());
+        assert!(leases.rename("from", "to".to_owned(), true).unwrap());
+        assert_eq!(engine.get("to").unwrap(), Some(b"moved".to_vec()));
+        drop(leases);
+
+        // The attachment moved in the rename's batch, so it survives a reopen.
+        let leases = Leases::open(engine.clone()).unwrap();
+        assert_eq!(leases.revoke(id).unwrap(), 1);
+        assert_eq!(engine.get("to").unwrap(), None);
+        assert_eq!(engine.get("from").unwrap(), None);
+    }
+}
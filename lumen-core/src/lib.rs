@@ -1,5 +1,44 @@
+#[macro_use]
+mod log;
+mod cache;
+mod group_commit;
+mod memtable;
+mod metrics;
+
+pub mod checkpoint;
+pub mod code;
 pub mod engine;
+pub mod feed;
+pub mod hlc;
+pub mod index;
+pub mod lease;
+pub mod merge;
+pub mod redact;
+pub mod scan;
+pub mod scrub;
+pub mod snapshot;
+pub mod space;
+pub mod sstable;
+pub mod sync;
+pub mod throttle;
+pub mod tier;
 pub mod wal;
 
-pub use engine::{Engine, EngineError};
-pub use wal::{WalRecord, WalError, WriteAheadLog};
+pub use checkpoint::Checkpoint;
+pub use code::ErrorCode;
+pub use engine::{Engine, EngineError, EngineOptions};
+pub use feed::Change;
+pub use hlc::HybridClock;
+pub use lease::{is_reserved_key, ExpiryTracker, LeaseError, Leases};
+pub use merge::{MergePolicy, MergeStats};
+pub use redact::{KeyMode, RedactedKey};
+pub use scan::{KeyRange, Scan};
+pub use scrub::{Corruption, ScrubReport};
+pub use snapshot::Snapshot;
+pub use space::SpaceStats;
+pub use sync::{SyncMethod, SyncPolicy};
+pub use tier::ColdTier;
+pub use wal::{
+    Checksum, Dropped, RawRecord, RecordLimits, Recovered, RecoveryMode, WalEntry, WalError, WalInfo, WalOptions, WalReader,
+    WalRecord, WriteAheadLog,
+};
@@ -0,0 +1,24 @@
+//! Logging macros that forward to `tracing` when the `tracing` feature is
+//! enabled and compile to nothing otherwise, so embedders can drop the
+//! dependency.
+
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($arg)*);
+    }};
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::info!($($arg)*);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::warn!($($arg)*);
+    }};
+}
@@ -0,0 +1,283 @@
+//! The memtable: recent writes in memory, over the tables they were flushed
+//! to (see `sstable`).
+//!
+//! Without tables the memtable holds every live key, and a delete removes
+//! its key.  Once there are tables it holds what was written since the last
+//! flush, and a delete of a key the tables may hold leaves a tombstone to
+//! shadow it, written to the next table.  Reads look in memory first and
+//! then in the tables, newest first; scans merge them, with the same
+//! precedence.
+//!
+//! Writes never read the tables: a write of a key not in memory does not
+//! know what the tables held for it, so the live key and byte counts take
+//! it for a new key, and a delete leaves a tombstone whether or not there
+//! was anything to shadow.  The flush that takes such keys to a table looks
+//! them up, off the write path, and corrects the counts (see
+//! `Frozen::shadowed`), so they are estimates between flushes and exact in
+//! every table's totals.
+//!
+//! A flush freezes the entries in memory (see `freeze`), and they go on
+//! being read, beneath the new ones, while the table is written without
+//! the memtable locked; the table takes their place once it is written.
+
+use std::collections::btree_map::{self, BTreeMap};
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::sstable::{Entry, Merged, Table};
+use crate::wal::WalError;
+
+/// Entries written since the last flush (`None`: deleted).
+type Entries = BTreeMap<String, Option<Vec<u8>>>;
+
+#[derive(Debug, Default)]
+pub(crate) struct Memtable {
+    /// Keys written since the last flush (`None`: deleted).
+    entries: Entries,
+    /// Of those, the keys first written without reading what the tables
+    /// held for them.
+    unresolved: BTreeSet<String>,
+    /// Entries being flushed, beneath `entries`.
+    frozen: Option<Arc<Entries>>,
+    /// Tables, oldest first.
+    tables: Vec<Arc<Table>>,
+    /// Live keys, in memory and in the tables; an estimate until the next
+    /// flush once there are tables.
+    len: usize,
+    /// Bytes of the keys and values in memory, and the keys of tombstones.
+    resident: u64,
+}
+
+/// What a write replaced, as far as memory knows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Replaced {
+    /// A value written since the last flush (`None`: a tombstone).
+    Unflushed(Option<Vec<u8>>),
+    /// Nothing: the key was not in memory, and there are no tables.
+    Absent,
+    /// Whatever the tables hold, which the write did not read.
+    Flushed,
+}
+
+impl Replaced {
+    /// The live value replaced, if memory knows of one.
+    pub(crate) fn value(&self) -> Option<&[u8]> {
+        match self {
+            Replaced::Unflushed(value) => value.as_deref(),
+            Replaced::Absent | Replaced::Flushed => None,
+        }
+    }
+}
+
+/// A memtable's entries on their way to a table (see `Memtable::freeze`).
+#[derive(Debug)]
+pub(crate) struct Frozen {
+    entries: Arc<Entries>,
+    unresolved: BTreeSet<String>,
+    /// The tables beneath the entries, as they were frozen.
+    tables: Vec<Arc<Table>>,
+}
+
+impl Frozen {
+    /// The entries, in key order.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&String, Option<&Vec<u8>>)> {
+        self.entries.iter().map(|(key, value)| (key, value.as_ref()))
+    }
+
+    /// Whether the table written from them is the first, the base table.
+    pub(crate) fn is_base(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    /// The live keys, and the bytes of them and their values, that the
+    /// writes of keys first written without reading the tables replaced:
+    /// counted as new keys then, and now dead data.
+    pub(crate) fn shadowed(&self) -> Result<(u64, u64), WalError> {
+        let (mut keys, mut bytes) = (0, 0);
+        for key in &self.unresolved {
+            if let Some(value) = flushed(&self.tables, key)? {
+                keys  += 1;
+                bytes += (key.len() + value.len()) as u64;
+            }
+        }
+        Ok((keys, bytes))
+    }
+}
+
+/// `key`'s value in `tables`, given oldest first.
+pub(crate) fn flushed(tables: &[Arc<Table>], key: &str) -> Result<Option<Vec<u8>>, WalError> {
+    for table in tables.iter().rev() {
+        if let Some(entry) = table.get(key)? {
+            return Ok(entry);
+        }
+    }
+    Ok(None)
+}
+
+impl Memtable {
+    /// A memtable holding `entries`, over `tables`, of `len` live keys in all.
+    pub(crate) fn new(entries: BTreeMap<String, Vec<u8>>, tables: Vec<Arc<Table>>, len: usize) -> Self {
+        let resident = entries.iter().map(|(key, value)| (key.len() + value.len()) as u64).sum();
+        let entries  = entries.into_iter().map(|(key, value)| (key, Some(value))).collect();
+        Self { entries, unresolved: BTreeSet::new(), frozen: None, tables, len, resident }
+    }
+
+    /// `key`'s value.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Vec<u8>>, WalError> {
+        match self.entries.get(key).or_else(|| self.frozen.as_ref()?.get(key)) {
+            Some(entry) => Ok(entry.clone()),
+            None => flushed(&self.tables, key),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> Result<bool, WalError> {
+        match self.entries.get(key).or_else(|| self.frozen.as_ref()?.get(key)) {
+            Some(entry) => Ok(entry.is_some()),
+            None => Ok(flushed(&self.tables, key)?.is_some()),
+        }
+    }
+
+    /// `key`'s value, if it was written since the last flush (`Some(None)`:
+    /// deleted).  Does not read the tables.
+    pub(crate) fn unflushed(&self, key: &str) -> Option<Option<&[u8]>> {
+        self.entries.get(key).map(Option::as_deref)
+    }
+
+    /// Set `key` to `value` (`None`: delete it), without reading the tables.
+    /// Returns what it replaced, as far as memory knows.
+    ///
+    /// Flushes hold every writer back, so no write comes while entries are
+    /// frozen, and a key not in `entries` is in the tables, if anywhere.
+    pub(crate) fn insert(&mut self, key: &str, value: Option<Vec<u8>>) -> Replaced {
+        let (has_tables, put) = (!self.tables.is_empty(), value.is_some());
+        let (old, added) = match value {
+            Some(value) => {
+                let added = key.len() + value.len();
+                (self.entries.insert(key.to_owned(), Some(value)), added)
+            }
+            None if !has_tables => (self.entries.remove(key), 0),
+            // The tables may hold the key, so it needs a tombstone.
+            None => (self.entries.insert(key.to_owned(), None), key.len()),
+        };
+        let replaced = match old {
+            Some(old) => {
+                self.resident -= (key.len() + old.as_ref().map_or(0, Vec::len)) as u64;
+                Replaced::Unflushed(old)
+            }
+            None if has_tables => {
+                self.unresolved.insert(key.to_owned());
+                Replaced::Flushed
+            }
+            None => Replaced::Absent,
+        };
+        self.resident += added as u64;
+        // What the tables held counts as absent until the flush.
+        match (replaced.value().is_some(), put) {
+            (false, true) => self.len += 1,
+            (true, false) => self.len -= 1,
+            _ => {}
+        }
+        replaced
+    }
+
+    /// Every live key from `start` on, with its value, in key order.
+    pub(crate) fn range(&self, start: Bound<&str>) -> Result<Range<'_>, WalError> {
+        let memory = self.entries.range::<str, _>((start, Bound::Unbounded));
+        let mut sources: Vec<Box<dyn Iterator<Item = Result<Entry, WalError>> + '_>> =
+            vec![Box::new(memory.map(|(key, value)| Ok((key.clone(), value.clone()))))];
+        if let Some(frozen) = &self.frozen {
+            let frozen = frozen.range::<str, _>((start, Bound::Unbounded));
+            sources.push(Box::new(frozen.map(|(key, value)| Ok((key.clone(), value.clone())))));
+        }
+        for table in self.tables.iter().rev() {
+            sources.push(Box::new(table.cursor(start)?));
+        }
+        Ok(Range { merged: Merged::new(sources) })
+    }
+
+    /// Every live key, with its value, in key order.
+    pub(crate) fn iter(&self) -> Result<Range<'_>, WalError> {
+        self.range(Bound::Unbounded)
+    }
+
+    /// Live keys, in memory and in the tables.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes of the keys and values held in memory.
+    pub(crate) fn resident(&self) -> u64 {
+        self.resident
+    }
+
+    pub(crate) fn tables(&self) -> &[Arc<Table>] {
+        &self.tables
+    }
+
+    /// Freeze what was written since the last flush, to be written to a
+    /// table, and start over empty above it.  `None` if nothing was.
+    pub(crate) fn freeze(&mut self) -> Option<Frozen> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let entries = Arc::new(std::mem::take(&mut self.entries));
+        self.frozen = Some(entries.clone());
+        self.resident = 0;
+        Some(Frozen { entries, unresolved: std::mem::take(&mut self.unresolved), tables: self.tables.clone() })
+    }
+
+    /// `table` now holds the frozen entries, and the store `len` live keys,
+    /// as counted once the flush looked up those written over the tables.
+    pub(crate) fn flushed(&mut self, table: Arc<Table>, len: usize) {
+        self.tables.push(table);
+        self.frozen = None;
+        self.len = len;
+    }
+
+    /// Take `frozen` back after its table failed to be written.
+    pub(crate) fn thaw(&mut self, frozen: Frozen) {
+        self.frozen = None;
+        let entries = Arc::try_unwrap(frozen.entries).unwrap_or_else(|shared| Entries::clone(&shared));
+        for (key, value) in entries {
+            if let btree_map::Entry::Vacant(slot) = self.entries.entry(key) {
+                self.resident += (slot.key().len() + value.as_ref().map_or(0, Vec::len)) as u64;
+                slot.insert(value);
+            }
+        }
+        self.unresolved.extend(frozen.unresolved);
+    }
+
+    /// Read `table`, a copy of `old`, in its place.  Returns whether `old`
+    /// was still there to replace.
+    pub(crate) fn replace(&mut self, old: &Arc<Table>, table: Table) -> bool {
+        let Some(slot) = self.tables.iter_mut().find(|slot| Arc::ptr_eq(slot, old)) else { return false };
+        *slot = Arc::new(table);
+        true
+    }
+
+    /// `table` now holds what the tables at `run` held.
+    pub(crate) fn merged(&mut self, run: std::ops::Range<usize>, table: Table) {
+        self.tables.splice(run, [Arc::new(table)]);
+    }
+}
+
+/// The live entries of a memtable in key order, merged from memory and its
+/// tables.
+pub(crate) struct Range<'a> {
+    merged: Merged<'a>,
+}
+
+impl Iterator for Range<'_> {
+    type Item = Result<(String, Vec<u8>), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.merged.next()? {
+                Ok((key, Some(value))) => return Some(Ok((key, value))),
+                Ok((_, None)) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
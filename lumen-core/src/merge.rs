@@ -0,0 +1,552 @@
+//! Background merging of tables: size-tiered compaction of the SSTables.
+//!
+//! Every flush adds a table (see `sstable`), and a read that misses the
+//! memtable may look in each of them, so without merging reads slow down as
+//! tables pile up.  A thread per engine merges the newest tables into one
+//! whenever that would leave fewer tables of similar sizes: starting from
+//! the newest, it takes in older tables while each is at most
+//! `MergePolicy::size_ratio` times the size of those taken so far, and
+//! merges once it has `MergePolicy::min_tables`.  Tables flushed at about
+//! the same size are merged a few at a time, and the results merged again
+//! once enough of them build up, so each write is rewritten about once per
+//! tier.
+//!
+//! A merge keeps only the newest entry of each key.  A merge that takes in
+//! the oldest table, the base table, also drops the tombstones, as nothing
+//! older is left for them to shadow, and makes a base table.  The merged
+//! table takes the sequence, and the file, of the newest table it replaces,
+//! so a crash before the others are deleted leaves tables that the merged
+//! one shadows entirely.  Merges only read tables, so writers and readers
+//! carry on meanwhile; `Engine::compact` and `Engine::install_snapshot`,
+//! which replace the tables themselves, wait for a merge to finish.
+//!
+//! The same thread moves tables to the cold tier, if there is one (see
+//! `tier`), after each round of merges, and every `TIER_INTERVAL` while
+//! tables move on their age.
+
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::memtable::Memtable;
+use crate::metrics;
+use crate::space::Garbage;
+use crate::sstable::{self, Merged, Table, Totals};
+use crate::tier::Tiering;
+use crate::wal::WalError;
+
+/// How often tables are checked for moving to the cold tier on their age.
+const TIER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When tables are merged in the background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergePolicy {
+    /// Fewest tables a merge takes in (0: never merge in the background).
+    pub min_tables: usize,
+    /// Take in an older table while it is at most this many times the
+    /// size of the newer ones taken so far.
+    pub size_ratio: u64,
+}
+
+impl Default for MergePolicy {
+    fn default() -> Self {
+        Self { min_tables: 4, size_ratio: 2 }
+    }
+}
+
+/// What the background merges of an engine have done since it opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeStats {
+    /// Tables the engine has now.
+    pub tables: usize,
+    /// Whether a merge is running.
+    pub running: bool,
+    /// Merges completed.
+    pub merges: u64,
+    /// Merges that failed, and were left to be retried after the next
+    /// flush.
+    pub failures: u64,
+    /// Tables the completed merges took in.
+    pub tables_merged: u64,
+    /// Bytes of the tables they took in, and of the tables they wrote.
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Older entries of keys dropped, and tombstones dropped by merges into
+    /// a base table.
+    pub entries_dropped: u64,
+    pub tombstones_dropped: u64,
+    /// How long the last completed merge took.
+    pub last_duration: Duration,
+    /// Of the tables, those in the cold tier (see `tier`).
+    pub cold_tables: usize,
+    /// Tables moved to the cold tier, and their bytes.
+    pub tables_tiered: u64,
+    pub bytes_tiered: u64,
+    /// Bytes of cold blocks in the block cache.
+    pub cache_bytes: u64,
+}
+
+/// The background merges of one engine.  Stops its thread when dropped,
+/// with the last clone of the engine.
+#[derive(Debug)]
+pub(crate) struct Merger {
+    shared: Arc<Shared>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    policy: MergePolicy,
+    data_dir: PathBuf,
+    memtable: Arc<RwLock<Memtable>>,
+    garbage: Arc<Garbage>,
+    tier: Option<Tiering>,
+    /// Held for the length of a merge, and by whatever else replaces the
+    /// tables.
+    exclusive: Mutex<()>,
+    state: Mutex<State>,
+    wake: Condvar,
+    stopping: AtomicBool,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    requested: bool,
+    stats: MergeStats,
+}
+
+impl Merger {
+    pub(crate) fn new(
+        policy: MergePolicy,
+        data_dir: PathBuf,
+        memtable: Arc<RwLock<Memtable>>,
+        garbage: Arc<Garbage>,
+        tier: Option<Tiering>,
+    ) -> Self {
+        let shared = Shared {
+            policy,
+            data_dir,
+            memtable,
+            garbage,
+            tier,
+            exclusive: Mutex::new(()),
+            state: Mutex::new(State::default()),
+            wake: Condvar::new(),
+            stopping: AtomicBool::new(false),
+        };
+        Self { shared: Arc::new(shared), thread: Mutex::new(None) }
+    }
+
+    /// Merge tables if `count` of them call for it, and move them to the
+    /// cold tier if there is one, starting the thread the first time.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn request(&self, count: usize) {
+        let merge = self.shared.policy.min_tables != 0 && count >= self.shared.policy.min_tables.max(2);
+        if !merge && (self.shared.tier.is_none() || count == 0) {
+            return;
+        }
+        let mut thread = self.thread.lock().unwrap_or_else(|e| e.into_inner());
+        if thread.is_none() {
+            let shared = self.shared.clone();
+            let spawned = std::thread::Builder::new().name("lumen-merge".to_owned()).spawn(move || shared.run());
+            match spawned {
+                Ok(handle) => *thread = Some(handle),
+                Err(e) => {
+                    warn!(error = %e, "Failed to start the table merge thread");
+                    return;
+                }
+            }
+        }
+        self.shared.state().requested = true;
+        self.shared.wake.notify_one();
+    }
+
+    /// Wait for a running merge to finish, and hold further ones off until
+    /// the guard is dropped.
+    pub(crate) fn exclusive(&self) -> MutexGuard<'_, ()> {
+        self.shared.exclusive.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn stats(&self) -> MergeStats {
+        let (tables, cold_tables) = {
+            let mem = self.shared.memtable.read().unwrap_or_else(|e| e.into_inner());
+            (mem.tables().len(), mem.tables().iter().filter(|table| table.is_cold()).count())
+        };
+        let cache_bytes = self.shared.tier.as_ref().map_or(0, |tier| tier.cache().bytes());
+        MergeStats { tables, cold_tables, cache_bytes, ..self.shared.state().stats }
+    }
+}
+
+impl Drop for Merger {
+    fn drop(&mut self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.get_mut().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn run(&self) {
+        loop {
+            {
+                let by_age    = self.tier.as_ref().is_some_and(Tiering::by_age);
+                let mut state = self.state();
+                while !state.requested && !self.stopping.load(Ordering::SeqCst) {
+                    if !by_age {
+                        state = self.wake.wait(state).unwrap_or_else(|e| e.into_inner());
+                        continue;
+                    }
+                    let (next, waited) = self.wake.wait_timeout(state, TIER_INTERVAL).unwrap_or_else(|e| e.into_inner());
+                    state = next;
+                    if waited.timed_out() {
+                        break;
+                    }
+                }
+                state.requested = false;
+            }
+            if self.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+            loop {
+                match self.merge_once() {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(_) if self.stopping.load(Ordering::SeqCst) => return,
+                    Err(e) => {
+                        warn!(data_dir = %self.data_dir.display(), error = %e, "Table merge failed");
+                        self.state().stats.failures += 1;
+                        break;
+                    }
+                }
+            }
+            if let Err(e) = self.tier_once() {
+                if self.stopping.load(Ordering::SeqCst) {
+                    return;
+                }
+                warn!(data_dir = %self.data_dir.display(), error = %e, "Moving a table to the cold tier failed");
+            }
+        }
+    }
+
+    /// Merge the tables `plan` picks, if any.  Returns whether it merged.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn merge_once(&self) -> Result<bool, WalError> {
+        let _exclusive = self.exclusive.lock().unwrap_or_else(|e| e.into_inner());
+        let (run, tables) = {
+            let mem = self.memtable.read().unwrap_or_else(|e| e.into_inner());
+            let Some(run) = plan(mem.tables(), self.policy) else { return Ok(false) };
+            (run.clone(), mem.tables()[run].to_vec())
+        };
+        let Some(newest) = tables.last() else { return Ok(false) };
+        let started = Instant::now();
+
+        // Only flushes change the tables meanwhile, and they add newer ones.
+        let base    = run.start == 0;
+        let sources = tables.iter().rev().map(|table| Box::new(table.read_through()) as Box<dyn Iterator<Item = _>>);
+        let mut merged = Merged::new(sources.collect());
+        // (count, key bytes) of the tombstones dropped, and (bytes,
+        // tombstones) of the entries shadowed, so far.
+        let tombstones = Cell::new((0u64, 0u64));
+        let shadowed   = Cell::new((0u64, 0u64));
+        let entries = std::iter::from_fn(|| loop {
+            if self.stopping.load(Ordering::SeqCst) {
+                return Some(Err(cancelled()));
+            }
+            let entry = merged.next();
+            shadowed.set((merged.shadowed_bytes, merged.shadowed_tombstones));
+            match entry? {
+                Ok((key, None)) if base => {
+                    let (count, bytes) = tombstones.get();
+                    tombstones.set((count + 1, bytes + key.len() as u64));
+                }
+                entry => return Some(entry),
+            }
+        });
+        let reclaimed = || {
+            let ((count, bytes), (shadowed_bytes, shadowed_count)) = (tombstones.get(), shadowed.get());
+            (bytes + shadowed_bytes, count + shadowed_count)
+        };
+        let totals = || {
+            let (bytes, count) = reclaimed();
+            let totals = newest.totals();
+            Totals {
+                dead_bytes: totals.dead_bytes.saturating_sub(bytes),
+                tombstones: totals.tombstones.saturating_sub(count),
+                ..totals
+            }
+        };
+        self.state().stats.running = true;
+        let written = Table::write(&self.data_dir, sstable::MERGE_FILE, newest.sequence(), base, totals, entries);
+        self.state().stats.running = false;
+        let table = written?;
+
+        let (dropped_bytes, dropped_tombstones) = reclaimed();
+        let dropped       = merged.shadowed + tombstones.get().0;
+        let bytes_read    = tables.iter().map(|table| table.file_len()).sum::<u64>();
+        let bytes_written = table.file_len();
+        let table_path    = table.path().to_owned();
+        let count = {
+            let mut mem = self.memtable.write().unwrap_or_else(|e| e.into_inner());
+            mem.merged(run, table);
+            self.garbage.reclaim(dropped_bytes, dropped_tombstones);
+            mem.tables().len()
+        };
+        // The newest one's file now holds the merged table, unless it was
+        // in the cold tier.
+        for table in tables.iter().filter(|merged| merged.path() != table_path) {
+            if let Err(e) = std::fs::remove_file(table.path()) {
+                warn!(path = %table.path().display(), error = %e, "Failed to delete a merged table");
+            }
+        }
+
+        let elapsed = started.elapsed();
+        {
+            let stats = &mut self.state().stats;
+            stats.merges             += 1;
+            stats.tables_merged      += tables.len() as u64;
+            stats.bytes_read         += bytes_read;
+            stats.bytes_written      += bytes_written;
+            stats.entries_dropped    += merged.shadowed;
+            stats.tombstones_dropped += tombstones.get().0;
+            stats.last_duration       = elapsed;
+        }
+        metrics::merge(&self.data_dir, bytes_read, bytes_written, elapsed);
+        metrics::tables(&self.data_dir, count);
+        info!(
+            data_dir = %self.data_dir.display(),
+            tables   = tables.len(),
+            base,
+            bytes_read,
+            bytes_written,
+            dropped,
+            "Tables merged"
+        );
+        Ok(true)
+    }
+
+    /// Move the tables the cold tier is due to it, one at a time.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn tier_once(&self) -> Result<(), WalError> {
+        let Some(tier) = &self.tier else { return Ok(()) };
+        let _exclusive = self.exclusive.lock().unwrap_or_else(|e| e.into_inner());
+        let due: Vec<Arc<Table>> = {
+            let mem = self.memtable.read().unwrap_or_else(|e| e.into_inner());
+            mem.tables().iter().filter(|table| tier.is_due(table)).cloned().collect()
+        };
+        for table in due {
+            if self.stopping.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let cold  = tier.copy(&table)?;
+            let bytes = cold.file_len();
+            let path  = cold.path().to_owned();
+            let (replaced, cold_tables) = {
+                let mut mem = self.memtable.write().unwrap_or_else(|e| e.into_inner());
+                let replaced = mem.replace(&table, cold);
+                (replaced, mem.tables().iter().filter(|table| table.is_cold()).count())
+            };
+            if !replaced {
+                // Superseded while it was copied; nothing reads the copy.
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            if let Err(e) = std::fs::remove_file(table.path()) {
+                warn!(path = %table.path().display(), error = %e, "Failed to delete a table moved to the cold tier");
+            }
+
+            {
+                let stats = &mut self.state().stats;
+                stats.tables_tiered += 1;
+                stats.bytes_tiered  += bytes;
+            }
+            metrics::tiered(&self.data_dir, bytes, cold_tables);
+            info!(from = %table.path().display(), to = %path.display(), bytes, "Table moved to the cold tier");
+        }
+        Ok(())
+    }
+}
+
+/// The run of tables to merge next under `policy`, if there is one.
+fn plan(tables: &[Arc<Table>], policy: MergePolicy) -> Option<std::ops::Range<usize>> {
+    if policy.min_tables == 0 {
+        return None;
+    }
+    let (mut start, mut bytes) = (tables.len(), 0u64);
+    while start > 0 {
+        let size = tables[start - 1].file_len();
+        if start < tables.len() && size > bytes.saturating_mul(policy.size_ratio) {
+            break;
+        }
+        bytes += size;
+        start -= 1;
+    }
+    (tables.len() - start >= policy.min_tables.max(2)).then_some(start..tables.len())
+}
+
+fn cancelled() -> WalError {
+    WalError::Io(std::io::Error::new(std::io::ErrorKind::Interrupted, "the engine was closed during the merge"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::engine::{Engine, EngineError, EngineOptions};
+
+    /// A table of `keys` entries, flushed at `sequence`.
+    fn table(dir: &Path, sequence: u64, keys: usize) -> Arc<Table> {
+        let entries = (0..keys).map(|i| Ok::<_, WalError>((format!("key-{i:05}"), Some(vec![b'v'; 32]))));
+        Arc::new(Table::write(dir, sstable::TEMP_FILE, sequence, sequence == 1, Totals::default, entries).unwrap())
+    }
+
+    /// Flush explicitly, and merge `min_tables` at a time of any sizes.
+    fn merging(min_tables: usize) -> EngineOptions {
+        EngineOptions {
+            memtable_flush_bytes: Some(1 << 20),
+            merge: MergePolicy { min_tables, size_ratio: 2 },
+            ..Default::default()
+        }
+    }
+
+    fn await_merges(engine: &Engine, merges: u64) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while engine.merge_stats().merges < merges || engine.merge_stats().running {
+            assert!(Instant::now() < deadline, "{:?}", engine.merge_stats());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn tables_in(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".sst"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn plan_stops_at_a_table_much_larger_than_the_newer_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let tables: Vec<Arc<Table>> =
+            [(1, 1000), (2, 10), (3, 10), (4, 10)].into_iter().map(|(sequence, keys)| table(dir.path(), sequence, keys)).collect();
+        let policy = MergePolicy { min_tables: 2, size_ratio: 2 };
+        assert_eq!(plan(&tables, policy), Some(1..4));
+        assert_eq!(plan(&tables, MergePolicy { min_tables: 4, ..policy }), None);
+        assert_eq!(plan(&tables, MergePolicy { size_ratio: 1000, ..policy }), Some(0..4));
+        assert_eq!(plan(&tables, MergePolicy { min_tables: 0, ..policy }), None);
+
+        // One table is never a merge, whatever the policy says.
+        assert_eq!(plan(&tables[..1], MergePolicy { min_tables: 1, ..policy }), None);
+        assert_eq!(plan(&tables[1..], policy), Some(0..3));
+    }
+
+    #[test]
+    fn a_base_merge_keeps_the_newest_entries_in_the_newest_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::open_with(dir.path(), merging(3)).unwrap();
+        for i in 0..10 {
+            engine.put(format!("key-{i}"), b"old".to_vec()).unwrap();
+        }
+        engine.flush().unwrap();
+        engine.put("key-0".to_owned(), b"new".to_vec()).unwrap();
+        engine.delete("key-1").unwrap();
+        engine.flush().unwrap();
+        engine.put("key-0".to_owned(), b"newest".to_vec()).unwrap();
+        engine.delete("key-2").unwrap();
+        let newest = engine.flush().unwrap().unwrap();
+        await_merges(&engine, 1);
+
+        assert_eq!(tables_in(dir.path()), [sstable::file_name(newest)]);
+        let stats = engine.merge_stats();
+        assert_eq!((stats.tables, stats.tables_merged), (1, 3));
+        // key-0 twice, key-1 and key-2 once each under their tombstones.
+        assert_eq!((stats.entries_dropped, stats.tombstones_dropped), (4, 2));
+        assert_eq!(engine.get("key-0").unwrap(), Some(b"newest".to_vec()));
+        assert_eq!(engine.get("key-1").unwrap(), None);
+        assert_eq!(engine.get("key-2").unwrap(), None);
+        assert_eq!(engine.get("key-9").unwrap(), Some(b"old".to_vec()));
+        assert_eq!(engine.len().unwrap(), 8);
+        drop(engine);
+
+        let engine = Engine::open(dir.path()).unwrap();
+        assert_eq!(engine.len().unwrap(), 8);
+        assert_eq!(engine.get("key-0").unwrap(), Some(b"newest".to_vec()));
+    }
+
+    #[test]
+    fn tables_a_crash_leaves_behind_a_merge_stay_shadowed() {
+        let (dir, saved) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        {
+            let engine = Engine::open_with(dir.path(), merging(2)).unwrap();
+            for i in 0..200 {
+                engine.put(format!("key-{i:03}"), vec![b'v'; 100]).unwrap();
+            }
+            engine.flush().unwrap();
+            engine.put("key-000".to_owned(), b"new".to_vec()).unwrap();
+            engine.delete("key-001").unwrap();
+            let older = sstable::file_name(engine.flush().unwrap().unwrap());
+            std::fs::copy(dir.path().join(&older), saved.path().join(&older)).unwrap();
+
+            // Too small next to the base table to take it in, so the
+            // merged table keeps the tombstones.
+            engine.put("key-000".to_owned(), b"newest".to_vec()).unwrap();
+            engine.put("key-001".to_owned(), b"back".to_vec()).unwrap();
+            engine.delete("key-002").unwrap();
+            engine.flush().unwrap();
+            await_merges(&engine, 1);
+            assert_eq!(engine.merge_stats().tables, 2);
+            assert_eq!(engine.merge_stats().tombstones_dropped, 0);
+            assert!(!dir.path().join(&older).exists());
+
+            // As if the merge had crashed before deleting the older table.
+            std::fs::copy(saved.path().join(&older), dir.path().join(&older)).unwrap();
+        }
+
+        let engine = Engine::open_with(dir.path(), merging(0)).unwrap();
+        assert_eq!(engine.merge_stats().tables, 3);
+        assert_eq!(engine.get("key-000").unwrap(), Some(b"newest".to_vec()));
+        assert_eq!(engine.get("key-001").unwrap(), Some(b"back".to_vec()));
+        assert_eq!(engine.get("key-002").unwrap(), None);
+        assert_eq!(engine.get("key-199").unwrap(), Some(vec![b'v'; 100]));
+    }
+
+    #[test]
+    fn a_merge_cut_short_is_refused_until_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let engine = Engine::open_with(dir.path(), merging(0)).unwrap();
+            engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+            engine.flush().unwrap();
+        }
+        std::fs::write(dir.path().join(sstable::MERGE_FILE), b"half a table").unwrap();
+
+        let err = Engine::open(dir.path()).unwrap_err();
+        assert!(matches!(err, EngineError::Inconsistent { .. }), "{err}");
+        assert!(err.to_string().contains(sstable::MERGE_FILE), "{err}");
+
+        let options = EngineOptions { ignore_orphans: true, ..merging(2) };
+        let engine  = Engine::open_with(dir.path(), options).unwrap();
+        assert_eq!(engine.get("a").unwrap(), Some(b"1".to_vec()));
+        engine.put("b".to_owned(), b"2".to_vec()).unwrap();
+        engine.flush().unwrap();
+        await_merges(&engine, 1);
+
+        // The next merge writes over what was left.
+        assert_eq!(engine.merge_stats().tables, 1);
+        assert!(!dir.path().join(sstable::MERGE_FILE).exists());
+        assert_eq!(engine.get("b").unwrap(), Some(b"2".to_vec()));
+    }
+}
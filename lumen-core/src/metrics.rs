@@ -0,0 +1,193 @@
+//! Engine metrics, recorded through the `metrics` facade when the `metrics`
+//! feature is enabled and compiled to nothing otherwise, so whichever
+//! exporter the embedding process installs picks them up.
+//!
+//! Counters:   lumen_engine_wal_appends_total, lumen_engine_wal_bytes_written_total,
+//!             lumen_engine_recoveries_total, lumen_engine_checksum_failures_total,
+//!             lumen_engine_wal_truncations_total (by `reason`),
+//!             lumen_engine_wal_bytes_dropped_total,
+//!             lumen_engine_scrub_bytes_total, lumen_engine_table_merges_total,
+//!             lumen_engine_table_merge_bytes_read_total,
+//!             lumen_engine_table_merge_bytes_written_total,
+//!             lumen_engine_tables_tiered_total, lumen_engine_tier_bytes_total,
+//!             lumen_engine_block_cache_hits_total,
+//!             lumen_engine_block_cache_misses_total,
+//!             lumen_engine_block_prefetches_total
+//! Gauges:     lumen_engine_memtable_bytes, lumen_engine_keys,
+//!             lumen_engine_scrub_corruptions, lumen_engine_disk_bytes (also by
+//!             `file`), lumen_engine_dead_bytes, lumen_engine_tombstones,
+//!             lumen_engine_space_amplification, lumen_engine_tables,
+//!             lumen_engine_cold_tables (labelled by `data_dir`, as one process
+//!             may run several engines),
+//!             lumen_engine_group_commit_window_seconds
+//! Histograms: lumen_engine_wal_append_seconds, lumen_engine_sync_seconds,
+//!             lumen_engine_group_commit_size, lumen_engine_table_merge_seconds
+//!             (by `data_dir`)
+//!
+//! Metrics are looked up on every event rather than cached, so an exporter
+//! installed after the engine opened still receives them.
+
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use metrics::{counter, gauge, histogram};
+
+use crate::space::SpaceStats;
+
+/// A WAL append of `bytes`, which took `elapsed`.
+pub(crate) fn wal_append(bytes: u64, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        counter!("lumen_engine_wal_appends_total").increment(1);
+        counter!("lumen_engine_wal_bytes_written_total").increment(bytes);
+        histogram!("lumen_engine_wal_append_seconds").record(elapsed.as_secs_f64());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (bytes, elapsed);
+}
+
+/// A sync of a WAL file, which took `elapsed`.
+pub(crate) fn sync(elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    histogram!("lumen_engine_sync_seconds").record(elapsed.as_secs_f64());
+    #[cfg(not(feature = "metrics"))]
+    let _ = elapsed;
+}
+
+/// A group commit synced `commits` commits after waiting `window`.
+pub(crate) fn sync_group(commits: u64, window: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        histogram!("lumen_engine_group_commit_size").record(commits as f64);
+        gauge!("lumen_engine_group_commit_window_seconds").set(window.as_secs_f64());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (commits, window);
+}
+
+/// An engine replayed its WAL on open.
+pub(crate) fn recovery() {
+    #[cfg(feature = "metrics")]
+    counter!("lumen_engine_recoveries_total").increment(1);
+}
+
+/// Recovery cut `bytes` off the end of a WAL, whose last append was torn
+/// or which was damaged.
+pub(crate) fn wal_dropped(torn: bool, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        let reason = if torn { "torn" } else { "corrupt" };
+        counter!("lumen_engine_wal_truncations_total", "reason" => reason).increment(1);
+        counter!("lumen_engine_wal_bytes_dropped_total").increment(bytes);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (torn, bytes);
+}
+
+/// A record or file failed its CRC check.
+pub(crate) fn checksum_failure() {
+    #[cfg(feature = "metrics")]
+    counter!("lumen_engine_checksum_failures_total").increment(1);
+}
+
+/// A scrub of the engine in `data_dir` read `bytes` and found `corruptions`
+/// damaged records or files.
+pub(crate) fn scrub(data_dir: &Path, bytes: u64, corruptions: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        let data_dir = data_dir.display().to_string();
+        counter!("lumen_engine_scrub_bytes_total", "data_dir" => data_dir.clone()).increment(bytes);
+        gauge!("lumen_engine_scrub_corruptions", "data_dir" => data_dir).set(corruptions as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (data_dir, bytes, corruptions);
+}
+
+/// The files of the engine in `data_dir` divide as `stats` says.
+pub(crate) fn space(data_dir: &Path, stats: &SpaceStats) {
+    #[cfg(feature = "metrics")]
+    {
+        let data_dir = data_dir.display().to_string();
+        gauge!("lumen_engine_disk_bytes", "data_dir" => data_dir.clone(), "file" => "checkpoint")
+            .set(stats.checkpoint_bytes as f64);
+        gauge!("lumen_engine_disk_bytes", "data_dir" => data_dir.clone(), "file" => "tables")
+            .set(stats.table_bytes as f64);
+        gauge!("lumen_engine_disk_bytes", "data_dir" => data_dir.clone(), "file" => "wal").set(stats.wal_bytes as f64);
+        gauge!("lumen_engine_dead_bytes", "data_dir" => data_dir.clone()).set(stats.dead_bytes as f64);
+        gauge!("lumen_engine_tombstones", "data_dir" => data_dir.clone()).set(stats.tombstones as f64);
+        gauge!("lumen_engine_space_amplification", "data_dir" => data_dir).set(stats.space_amplification());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (data_dir, stats);
+}
+
+/// The memtable of the engine in `data_dir` now holds `keys` keys, taking
+/// `bytes` bytes.
+pub(crate) fn memtable(data_dir: &Path, keys: usize, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    {
+        let data_dir = data_dir.display().to_string();
+        gauge!("lumen_engine_keys", "data_dir" => data_dir.clone()).set(keys as f64);
+        gauge!("lumen_engine_memtable_bytes", "data_dir" => data_dir).set(bytes as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (data_dir, keys, bytes);
+}
+
+/// The engine in `data_dir` now has `count` tables.
+pub(crate) fn tables(data_dir: &Path, count: usize) {
+    #[cfg(feature = "metrics")]
+    gauge!("lumen_engine_tables", "data_dir" => data_dir.display().to_string()).set(count as f64);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (data_dir, count);
+}
+
+/// A merge of tables of the engine in `data_dir` read `read` bytes and wrote
+/// `written`, taking `elapsed`.
+pub(crate) fn merge(data_dir: &Path, read: u64, written: u64, elapsed: Duration) {
+    #[cfg(feature = "metrics")]
+    {
+        let data_dir = data_dir.display().to_string();
+        counter!("lumen_engine_table_merges_total", "data_dir" => data_dir.clone()).increment(1);
+        counter!("lumen_engine_table_merge_bytes_read_total", "data_dir" => data_dir.clone()).increment(read);
+        counter!("lumen_engine_table_merge_bytes_written_total", "data_dir" => data_dir.clone()).increment(written);
+        histogram!("lumen_engine_table_merge_seconds", "data_dir" => data_dir).record(elapsed.as_secs_f64());
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (data_dir, read, written, elapsed);
+}
+
+/// A table of the engine in `data_dir` of `bytes` moved to the cold tier,
+/// which now holds `cold` tables.
+pub(crate) fn tiered(data_dir: &Path, bytes: u64, cold: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        let data_dir = data_dir.display().to_string();
+        counter!("lumen_engine_tables_tiered_total", "data_dir" => data_dir.clone()).increment(1);
+        counter!("lumen_engine_tier_bytes_total", "data_dir" => data_dir.clone()).increment(bytes);
+        gauge!("lumen_engine_cold_tables", "data_dir" => data_dir).set(cold as f64);
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (data_dir, bytes, cold);
+}
+
+/// A block of a cold table was looked for in the block cache, and `hit`.
+pub(crate) fn block_cache(hit: bool) {
+    #[cfg(feature = "metrics")]
+    {
+        if hit {
+            counter!("lumen_engine_block_cache_hits_total").increment(1);
+        } else {
+            counter!("lumen_engine_block_cache_misses_total").increment(1);
+        }
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = hit;
+}
+
+/// A block of a cold table was queued to be read ahead of a scan.
+pub(crate) fn block_prefetch() {
+    #[cfg(feature = "metrics")]
+    counter!("lumen_engine_block_prefetches_total").increment(1);
+}
@@ -0,0 +1,84 @@
+//! Keys as log lines and error messages show them.
+//!
+//! Keys can be personal data (an e-mail address, a user ID), so every
+//! place that logs a key or puts one in an error message renders it through
+//! `RedactedKey`, which shows it as the process-wide `KeyMode` says:
+//! unchanged, as a stable hash prefix (`#` and 12 hex digits: the same key
+//! always looks the same, so lines about one key can still be correlated,
+//! but the key cannot be read back), or not at all.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How keys are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyMode {
+    #[default]
+    Plain,
+    Hash,
+    Redact,
+}
+
+impl KeyMode {
+    /// The mode named `plain`, `hash` or `redact`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(KeyMode::Plain),
+            "hash" => Some(KeyMode::Hash),
+            "redact" => Some(KeyMode::Redact),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            KeyMode::Plain => "plain",
+            KeyMode::Hash => "hash",
+            KeyMode::Redact => "redact",
+        }
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(KeyMode::Plain as u8);
+
+/// Show keys as `mode` says from now on, everywhere in the process.
+pub fn set_key_mode(mode: KeyMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn key_mode() -> KeyMode {
+    match MODE.load(Ordering::Relaxed) {
+        m if m == KeyMode::Hash as u8 => KeyMode::Hash,
+        m if m == KeyMode::Redact as u8 => KeyMode::Redact,
+        _ => KeyMode::Plain,
+    }
+}
+
+/// A key (or key prefix), displayed as the current `KeyMode` says.
+#[derive(Debug, Clone, Copy)]
+pub struct RedactedKey<'a>(pub &'a str);
+
+impl fmt::Display for RedactedKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match key_mode() {
+            KeyMode::Plain => f.write_str(self.0),
+            KeyMode::Hash => write!(f, "#{:012x}", hash(self.0.as_bytes()) >> 16),
+            KeyMode::Redact => f.write_str("<redacted>"),
+        }
+    }
+}
+
+/// FNV-1a followed by a SplitMix64 finaliser: stable across builds and
+/// platforms, so a key hashes the same on every node and after restarts.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
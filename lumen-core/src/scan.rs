@@ -0,0 +1,189 @@
+//! Key ranges, and ordered scans of them.
+//!
+//! A `KeyRange` selects the keys between a start and an end bound that
+//! start with a prefix; any of the three may be left open.  Scans read the
+//! memtable and the tables under it merged, in key order (see `memtable`).
+//!
+//! `Engine::scan` returns a `Scan`: an iterator over a snapshot (see
+//! `snapshot`) that reads `PAGE` keys at a time.  It holds the memtable for
+//! one page at a time however slowly it is consumed, and every key it
+//! returns is as of the moment it was opened.  Like any snapshot it costs
+//! memory for the keys written while it is alive, so drop it once done.
+
+use std::cmp::Ordering;
+use std::ops::{Bound, RangeBounds};
+
+use crate::engine::EngineError;
+use crate::snapshot::Snapshot;
+
+/// Keys a `Scan` reads from the snapshot at a time.
+const PAGE: usize = 256;
+
+/// The keys between `start` and `end` that start with `prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    /// Empty for any key.
+    pub prefix: String,
+    pub start: Bound<String>,
+    pub end: Bound<String>,
+}
+
+impl Default for KeyRange {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl KeyRange {
+    /// Every key.
+    pub fn all() -> Self {
+        Self { prefix: String::new(), start: Bound::Unbounded, end: Bound::Unbounded }
+    }
+
+    /// The keys starting with `prefix`.
+    pub fn prefix(prefix: impl Into<String>) -> Self {
+        Self { prefix: prefix.into(), ..Self::all() }
+    }
+
+    /// The keys in `range`, e.g. `"user/a".."user/m"` or `"user/k"..`.
+    pub fn new<K: AsRef<str>>(range: impl RangeBounds<K>) -> Self {
+        Self {
+            prefix: String::new(),
+            start:  range.start_bound().map(|key| key.as_ref().to_owned()),
+            end:    range.end_bound().map(|key| key.as_ref().to_owned()),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        key.starts_with(&self.prefix)
+            && (self.start.as_ref().map(String::as_str), self.end.as_ref().map(String::as_str)).contains(&key)
+    }
+
+    /// Where reading the range starts: its first key, or the first after
+    /// `after` if that is later.
+    pub(crate) fn first<'a>(&'a self, after: Option<&'a str>) -> Bound<&'a str> {
+        let first = later(Bound::Included(self.prefix.as_str()), self.start.as_ref().map(String::as_str));
+        later(first, after.map_or(Bound::Unbounded, Bound::Excluded))
+    }
+
+    /// Whether `key`, read from `first` on, is past the range, and so is
+    /// every key after it.
+    pub(crate) fn is_past(&self, key: &str) -> bool {
+        !key.starts_with(&self.prefix)
+            || match &self.end {
+                Bound::Included(end) => key > end.as_str(),
+                Bound::Excluded(end) => key >= end.as_str(),
+                Bound::Unbounded => false,
+            }
+    }
+}
+
+/// The later of two start bounds.
+fn later<'a>(a: Bound<&'a str>, b: Bound<&'a str>) -> Bound<&'a str> {
+    let (x, y) = match (a, b) {
+        (Bound::Unbounded, b) => return b,
+        (a, Bound::Unbounded) => return a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => (x, y),
+    };
+    match x.cmp(y) {
+        Ordering::Greater => a,
+        Ordering::Equal if matches!(a, Bound::Included(_)) => b,
+        Ordering::Equal => a,
+        Ordering::Less => b,
+    }
+}
+
+/// The live keys of a `KeyRange` with their values, in key order, as of a
+/// snapshot.  Taken by `Engine::scan` or `Snapshot::scan`.
+#[derive(Debug)]
+pub struct Scan {
+    snapshot: Snapshot,
+    range: KeyRange,
+    /// Read from the snapshot but not returned yet.
+    page: std::vec::IntoIter<(String, Vec<u8>)>,
+    /// The last key read; the next page starts after it.
+    after: Option<String>,
+    done: bool,
+}
+
+impl Scan {
+    pub(crate) fn new(snapshot: Snapshot, range: KeyRange) -> Self {
+        Self { snapshot, range, page: Vec::new().into_iter(), after: None, done: false }
+    }
+
+    /// The last sequence the scan reflects.
+    pub fn sequence(&self) -> u64 {
+        self.snapshot.sequence()
+    }
+}
+
+impl Iterator for Scan {
+    type Item = Result<(String, Vec<u8>), EngineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(entry) = self.page.next() {
+            return Some(Ok(entry));
+        }
+        if self.done {
+            return None;
+        }
+        match self.snapshot.scan_range_page(&self.range, self.after.as_deref(), PAGE) {
+            Ok(page) => {
+                self.done  = page.len() < PAGE;
+                self.after = page.last().map(|(key, _)| key.clone()).or(self.after.take());
+                self.page  = page.into_iter();
+                self.page.next().map(Ok)
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    fn keys(scan: Scan) -> Vec<String> {
+        scan.map(|entry| entry.unwrap().0).collect()
+    }
+
+    #[test]
+    fn scans_a_prefix_or_a_range_past_a_page() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        for i in 0..PAGE * 2 + 10 {
+            engine.put(format!("user/{i:04}"), b"v".to_vec()).unwrap();
+        }
+        engine.put("other".to_owned(), b"v".to_vec()).unwrap();
+
+        let users = keys(engine.scan(KeyRange::prefix("user/")).unwrap());
+        assert_eq!(users.len(), PAGE * 2 + 10);
+        assert!(users.windows(2).all(|pair| pair[0] < pair[1]));
+        let range = KeyRange::new("user/0010".."user/0013");
+        assert_eq!(keys(engine.scan(range).unwrap()), ["user/0010", "user/0011", "user/0012"]);
+        let range = KeyRange { prefix: "user/".to_owned(), ..KeyRange::new("user/0520"..) };
+        assert_eq!(keys(engine.scan(range).unwrap()), ["user/0520", "user/0521"]);
+        assert_eq!(keys(engine.scan(KeyRange::all()).unwrap()).len(), PAGE * 2 + 11);
+    }
+
+    #[test]
+    fn a_scan_sees_the_store_as_it_was_opened() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        for key in ["a", "b", "c"] {
+            engine.put(key.to_owned(), b"old".to_vec()).unwrap();
+        }
+        let mut scan = engine.scan(KeyRange::all()).unwrap();
+        assert_eq!(scan.next().unwrap().unwrap(), ("a".to_owned(), b"old".to_vec()));
+
+        engine.put("b".to_owned(), b"new".to_vec()).unwrap();
+        engine.delete("c").unwrap();
+        engine.put("d".to_owned(), b"new".to_vec()).unwrap();
+        let rest: Vec<(String, Vec<u8>)> = scan.map(Result::unwrap).collect();
+        assert_eq!(rest, [("b".to_owned(), b"old".to_vec()), ("c".to_owned(), b"old".to_vec())]);
+    }
+}
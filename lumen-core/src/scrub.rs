@@ -0,0 +1,168 @@
+//! Scrubbing: re-reading the files on disk to find latent corruption.
+//!
+//! The WAL is only read back on open, and a checkpoint only on open and
+//! during bootstrap, so damage to either (a failing disk, a bad sector, a
+//! stray write) goes unnoticed until the next restart needs it.  A scrub
+//! reads them through at a bounded rate and checks every CRC: each WAL
+//! record's, the checkpoint's over the whole file, and each table block's
+//! (see `sstable`).
+//!
+//! Only the part of the WAL that had been written when the scrub began is
+//! read.  If a checkpoint replaces the log meanwhile, the log is scrubbed
+//! again on the next pass rather than reported.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crc32fast::Hasher as Crc32Hasher;
+
+use crate::sstable::Table;
+use crate::wal::{Checksum, WalReader};
+
+/// Bytes read between rate checks.
+const CHUNK: usize = 64 * 1024;
+
+/// Damage a scrub found.
+#[derive(Debug, Clone)]
+pub struct Corruption {
+    pub file: PathBuf,
+    /// Where the damaged record starts, or 0 for a whole file.
+    pub offset: u64,
+    pub problem: String,
+}
+
+/// What a scrub read, and what it found.
+#[derive(Debug, Clone, Default)]
+pub struct ScrubReport {
+    pub bytes: u64,
+    pub wal_records: u64,
+    /// Whether the WAL was replaced while it was read, and so not checked.
+    pub wal_skipped: bool,
+    pub corruptions: Vec<Corruption>,
+}
+
+/// Keeps reads to `rate` bytes per second, by sleeping when ahead.
+pub(crate) struct Pace {
+    rate: u64,
+    started: Instant,
+    bytes: u64,
+}
+
+impl Pace {
+    /// No limit if `rate` is 0.
+    pub(crate) fn new(rate: u64) -> Self {
+        Self { rate, started: Instant::now(), bytes: 0 }
+    }
+
+    fn charge(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        if self.rate == 0 {
+            return;
+        }
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.rate as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+/// When the WAL at `path` was created or last truncated, to tell whether
+/// it was replaced while it was scrubbed.
+pub(crate) fn wal_created(path: &Path) -> Option<u64> {
+    WalReader::open(path).ok()?.info().created_unix_ms
+}
+
+/// Check the records of the WAL at `path` that end by `limit`.  Returns how
+/// many were read.
+pub(crate) fn wal(path: &Path, limit: u64, pace: &mut Pace, found: &mut Vec<Corruption>) -> u64 {
+    let mut reader = match WalReader::open(path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            found.push(Corruption { file: path.to_owned(), offset: 0, problem: e.to_string() });
+            return 0;
+        }
+    };
+
+    let mut records = 0;
+    while reader.offset() < limit {
+        let record = match reader.next_record() {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(e) => {
+                found.push(Corruption { file: path.to_owned(), offset: 0, problem: format!("unreadable log: {e}") });
+                break;
+            }
+        };
+        pace.charge(record.len);
+        records += 1;
+        if let Checksum::Mismatch { expected, actual } = record.checksum {
+            found.push(Corruption {
+                file: path.to_owned(),
+                offset: record.offset,
+                problem: format!("record checksum mismatch: stored {expected:#010x}, computed {actual:#010x}"),
+            });
+        }
+    }
+    records
+}
+
+/// Check the CRC of the checkpoint at `path`, if there is one.
+pub(crate) fn checkpoint(path: &Path, pace: &mut Pace, found: &mut Vec<Corruption>) {
+    let mut problem = |problem: String| found.push(Corruption { file: path.to_owned(), offset: 0, problem });
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => return problem(e.to_string()),
+    };
+    match verify(file, pace) {
+        Ok(None) => {}
+        Ok(Some(mismatch)) => problem(mismatch),
+        Err(e) => problem(e.to_string()),
+    }
+}
+
+/// Check the CRC of every block of `table`.  A table deleted meanwhile is
+/// still read, through its open file.
+pub(crate) fn table(table: &Table, pace: &mut Pace, found: &mut Vec<Corruption>) {
+    for block in 0..table.blocks() {
+        let (bytes, problem) = table.verify_block(block);
+        pace.charge(bytes);
+        if let Some(problem) = problem {
+            found.push(Corruption { file: table.path().to_owned(), offset: table.block_offset(block), problem });
+        }
+    }
+}
+
+/// Read `file` through, returning what is wrong with its trailing CRC, if
+/// anything.
+fn verify(file: File, pace: &mut Pace) -> std::io::Result<Option<String>> {
+    let len = file.metadata()?.len();
+    let Some(body) = len.checked_sub(4) else {
+        return Ok(Some(format!("truncated to {len} bytes")));
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut hasher = Crc32Hasher::new();
+    let mut buf    = vec![0u8; CHUNK];
+    let mut left   = body;
+    while left > 0 {
+        let n = (left as usize).min(CHUNK);
+        reader.read_exact(&mut buf[..n])?;
+        hasher.update(&buf[..n]);
+        pace.charge(n as u64);
+        left -= n as u64;
+    }
+    let mut stored = [0u8; 4];
+    reader.read_exact(&mut stored)?;
+    pace.charge(4);
+
+    let (stored, computed) = (u32::from_be_bytes(stored), hasher.finalize());
+    Ok((stored != computed)
+        .then(|| format!("checkpoint checksum mismatch: stored {stored:#010x}, computed {computed:#010x}")))
+}
@@ -0,0 +1,281 @@
+//! Point-in-time views of the memtable.
+//!
+//! A `Snapshot` does not copy the memtable.  While it is alive, every write
+//! first saves the value it is about to overwrite (or the key's absence)
+//! in the snapshot's undo map, once per key, so a read through the snapshot
+//! sees the undo map's entry where there is one and the memtable elsewhere.
+//! A snapshot therefore costs memory in proportion to the keys written
+//! while it is held, not to the keys it covers; holders should drop it
+//! promptly.  Flushing the memtable to a table (see `sstable`) changes
+//! where keys are held but not their values, so it leaves snapshots be.
+//!
+//! A write of a key not in memory does not read the tables (see
+//! `memtable`), so it cannot save the value it overwrites.  It saves which
+//! of the snapshot's tables hold that value instead: a snapshot keeps the
+//! tables there were when it was taken, and those flushed since, and reads
+//! the value from them when asked for it.  A snapshot kept open across
+//! merges therefore keeps the files of the tables merged away open too.
+
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use crate::engine::EngineError;
+use crate::memtable::{self, Memtable, Replaced};
+use crate::scan::{KeyRange, Scan};
+use crate::sstable::Table;
+use crate::wal::WalError;
+
+#[derive(Debug)]
+struct Pinned {
+    sequence: u64,
+    saved: Mutex<Saved>,
+}
+
+impl Pinned {
+    fn saved(&self) -> MutexGuard<'_, Saved> {
+        self.saved.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// What a key held at a snapshot's sequence, saved before a write since.
+#[derive(Debug)]
+enum Undo {
+    /// This value (`None`: absent).
+    Value(Option<Vec<u8>>),
+    /// Whatever the first this many of the snapshot's tables hold.
+    Flushed(usize),
+}
+
+#[derive(Debug, Default)]
+struct Saved {
+    /// Keys written since the snapshot was taken.
+    undo: BTreeMap<String, Undo>,
+    /// The tables as of the snapshot, and those flushed since, oldest first.
+    tables: Vec<Arc<Table>>,
+    /// Whether every key live as of the snapshot is in `undo` (see
+    /// `Pins::preserve_all`), so one that is not was absent.
+    complete: bool,
+}
+
+impl Saved {
+    /// `key`'s value as of the snapshot, if it was written since.
+    fn get(&self, key: &str) -> Result<Option<Option<Vec<u8>>>, WalError> {
+        match self.undo.get(key) {
+            None => Ok(None),
+            Some(undo) => self.value(key, undo).map(Some),
+        }
+    }
+
+    fn value(&self, key: &str, undo: &Undo) -> Result<Option<Vec<u8>>, WalError> {
+        match undo {
+            Undo::Value(value) => Ok(value.clone()),
+            Undo::Flushed(tables) => memtable::flushed(&self.tables[..*tables], key),
+        }
+    }
+}
+
+/// The live snapshots of one engine.
+#[derive(Debug, Default)]
+pub(crate) struct Pins {
+    pinned: Mutex<Vec<Weak<Pinned>>>,
+}
+
+impl Pins {
+    fn pinned(&self) -> MutexGuard<'_, Vec<Weak<Pinned>>> {
+        self.pinned.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Pin the memtable as of `sequence`.  Callers must hold the WAL lock,
+    /// so nothing is committed between reading `sequence` and pinning it.
+    pub(crate) fn pin(&self, memtable: Arc<RwLock<Memtable>>, sequence: u64) -> Result<Snapshot, EngineError> {
+        let tables = memtable.read()?.tables().to_vec();
+        let pinned = Arc::new(Pinned { sequence, saved: Mutex::new(Saved { tables, ..Default::default() }) });
+        self.pinned().push(Arc::downgrade(&pinned));
+        Ok(Snapshot { memtable, pinned, taken: Instant::now() })
+    }
+
+    /// Save what `key` held, which a write is replacing (see `Replaced`),
+    /// for every live snapshot that has not yet saved it.  Call with the
+    /// memtable write-locked, as `key` is written.
+    pub(crate) fn preserve(&self, key: &str, replaced: &Replaced) {
+        let mut pinned = self.pinned();
+        if pinned.is_empty() {
+            return;
+        }
+        pinned.retain(|pin| match pin.upgrade() {
+            Some(pin) => {
+                let saved = &mut *pin.saved();
+                if !saved.undo.contains_key(key) {
+                    let undo = match replaced {
+                        Replaced::Unflushed(value) => Undo::Value(value.clone()),
+                        Replaced::Flushed if !saved.complete => Undo::Flushed(saved.tables.len()),
+                        Replaced::Absent | Replaced::Flushed => Undo::Value(None),
+                    };
+                    saved.undo.insert(key.to_owned(), undo);
+                }
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// `table` now holds the entries flushed from memory.  Call with the
+    /// memtable write-locked.
+    pub(crate) fn flushed(&self, table: &Arc<Table>) {
+        self.pinned().retain(|pin| match pin.upgrade() {
+            Some(pin) => {
+                pin.saved().tables.push(table.clone());
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Save every key of `mem`, about to be replaced by `next`, for every
+    /// live snapshot.  Call with the memtable write-locked.
+    pub(crate) fn preserve_all(&self, mem: &Memtable, next: &Memtable) -> Result<(), WalError> {
+        let live: Vec<Arc<Pinned>> = {
+            let mut pinned = self.pinned();
+            pinned.retain(|pin| pin.strong_count() > 0);
+            pinned.iter().filter_map(Weak::upgrade).collect()
+        };
+        if live.is_empty() {
+            return Ok(());
+        }
+        for entry in mem.iter()? {
+            let (key, value) = entry?;
+            for pin in &live {
+                pin.saved().undo.entry(key.clone()).or_insert_with(|| Undo::Value(Some(value.clone())));
+            }
+        }
+        // Keys of `mem` are saved by now, so these are the ones it lacks.
+        for entry in next.iter()? {
+            let (key, _) = entry?;
+            for pin in &live {
+                pin.saved().undo.entry(key.clone()).or_insert(Undo::Value(None));
+            }
+        }
+        for pin in &live {
+            pin.saved().complete = true;
+        }
+        Ok(())
+    }
+}
+
+/// A consistent view of the engine as of one sequence, taken by
+/// `Engine::snapshot`.  Released when dropped.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    memtable: Arc<RwLock<Memtable>>,
+    pinned: Arc<Pinned>,
+    taken: Instant,
+}
+
+impl Snapshot {
+    /// The last sequence the snapshot reflects.
+    pub fn sequence(&self) -> u64 {
+        self.pinned.sequence
+    }
+
+    /// How long ago the snapshot was taken.
+    pub fn age(&self) -> Duration {
+        self.taken.elapsed()
+    }
+
+    /// `key`'s value as of the snapshot.
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, EngineError> {
+        let mem = self.memtable.read()?;
+        if let Some(saved) = self.pinned.saved().get(key)? {
+            return Ok(saved);
+        }
+        Ok(mem.get(key)?)
+    }
+
+    /// Like `Engine::scan_page`, as of the snapshot.
+    pub fn scan_page(
+        &self,
+        prefix: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, EngineError> {
+        self.scan_range_page(&KeyRange::prefix(prefix), after, limit)
+    }
+
+    /// Like `Engine::scan`, as of the snapshot.
+    pub fn scan(&self, range: KeyRange) -> Scan {
+        Scan::new(self.clone(), range)
+    }
+
+    /// Up to `limit` live keys in `range` sorting after `after` (from the
+    /// first if `None`), with their values, in key order, as of the
+    /// snapshot.
+    pub fn scan_range_page(
+        &self,
+        range: &KeyRange,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, Vec<u8>)>, EngineError> {
+        let start = range.first(after);
+        let mem   = self.memtable.read()?;
+        let saved = self.pinned.saved();
+        let current = mem.range(start)?.map(|entry| entry.map(|(key, value)| (key, Some(value))));
+        let undone  = saved.undo.range::<str, _>((start, Bound::Unbounded));
+        let undone  = undone.map(|(key, undo)| Ok((key.clone(), saved.value(key, undo)?)));
+        let mut page = Vec::new();
+        for entry in (Merge { current: current.peekable(), saved: undone.peekable() }) {
+            let (key, value) = entry?;
+            if range.is_past(&key) || page.len() == limit {
+                break;
+            }
+            if let Some(value) = value {
+                page.push((key, value));
+            }
+        }
+        Ok(page)
+    }
+}
+
+/// The memtable's entries in key order, with each saved in the undo map in
+/// place of the memtable's (`None`: absent as of the snapshot).
+struct Merge<C, S>
+where
+    C: Iterator<Item = Result<(String, Option<Vec<u8>>), WalError>>,
+    S: Iterator<Item = Result<(String, Option<Vec<u8>>), WalError>>,
+{
+    current: Peekable<C>,
+    saved: Peekable<S>,
+}
+
+impl<C, S> Iterator for Merge<C, S>
+where
+    C: Iterator<Item = Result<(String, Option<Vec<u8>>), WalError>>,
+    S: Iterator<Item = Result<(String, Option<Vec<u8>>), WalError>>,
+{
+    type Item = Result<(String, Option<Vec<u8>>), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = match self.current.peek() {
+            Some(Ok((current, _))) => Some(current),
+            Some(Err(_)) => return self.current.next(),
+            None => None,
+        };
+        let saved = match self.saved.peek() {
+            Some(Ok((saved, _))) => Some(saved),
+            Some(Err(_)) => return self.saved.next(),
+            None => None,
+        };
+        match (current, saved) {
+            (Some(current), Some(saved)) if current < saved => self.current.next(),
+            (Some(current), Some(saved)) if current == saved => {
+                self.current.next();
+                self.saved.next()
+            }
+            (_, Some(_)) => self.saved.next(),
+            (Some(_), None) => self.current.next(),
+            (None, None) => None,
+        }
+    }
+}
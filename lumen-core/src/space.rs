@@ -0,0 +1,146 @@
+//! Dead data: what a compaction would reclaim.
+//!
+//! The engine's files are a checkpoint and the WAL written since it.  Every
+//! overwrite and delete leaves the data it replaced in one of them, plus, for
+//! a delete, a tombstone record in the WAL, until `Engine::compact` writes a
+//! fresh checkpoint of the live keys and empties the log.  The engine counts
+//! both as it goes (replaying the WAL at open counts them again), so
+//! `Engine::space` can report how much of the files is dead without reading
+//! them.  Byte counts are of keys and values, and exclude record framing.
+//!
+//! With tables (see `sstable`), a flush drops the WAL along with the dead
+//! data only it held: what was overwritten before it reached a table, and
+//! the deletes that leave no tombstone.  What a table holds stays until a
+//! merge of the tables drops it (see `merge`), or `compact` replaces them.
+//! A write of a key only the tables hold does not read the value it makes
+//! dead (see `memtable`); that is counted when the flush looks it up.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::memtable::Replaced;
+use crate::wal::WalRecord;
+
+/// How an engine's files divide into live and dead data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SpaceStats {
+    /// Keys and values of the live data.
+    pub live_bytes: u64,
+    /// Size of the checkpoint file (0 without one).
+    pub checkpoint_bytes: u64,
+    /// Size of the WAL file.
+    pub wal_bytes: u64,
+    /// Deletes in the WAL and tombstones in the tables.
+    pub tombstones: u64,
+    /// Keys and values in the checkpoint, the tables or the WAL that have
+    /// since been overwritten or deleted, and the keys of the tombstones.
+    pub dead_bytes: u64,
+    /// Size of the table files.
+    pub table_bytes: u64,
+}
+
+impl SpaceStats {
+    /// Bytes on disk per byte of live data; 1.0 for an empty store.
+    pub fn space_amplification(&self) -> f64 {
+        if self.live_bytes == 0 {
+            return 1.0;
+        }
+        (self.checkpoint_bytes + self.table_bytes + self.wal_bytes) as f64 / self.live_bytes as f64
+    }
+
+    /// Share of the keys and values in the files that are dead.
+    pub fn dead_ratio(&self) -> f64 {
+        let total = self.live_bytes + self.dead_bytes;
+        if total == 0 {
+            return 0.0;
+        }
+        self.dead_bytes as f64 / total as f64
+    }
+}
+
+/// Dead data written since the last checkpoint or base table.
+#[derive(Debug, Default)]
+pub(crate) struct Garbage {
+    bytes: AtomicU64,
+    tombstones: AtomicU64,
+    /// Of those, what only the WAL holds, which the next flush drops.
+    pending_bytes: AtomicU64,
+    pending_tombstones: AtomicU64,
+}
+
+impl Garbage {
+    /// Count `record`, applied over what it `replaced`.
+    pub(crate) fn add(&self, record: &WalRecord, replaced: &Replaced) {
+        let (key, tombstone) = match record {
+            WalRecord::Put { key, .. } => (key, false),
+            WalRecord::Delete { key } => (key, true),
+        };
+        let previous = replaced.value();
+        let mut dead = previous.map_or(0, |value| (key.len() + value.len()) as u64);
+        if let Replaced::Flushed = replaced {
+            // Whatever the tables held is counted at the flush, and the
+            // tombstone reaches the table either way.
+            if tombstone {
+                self.tombstones.fetch_add(1, Ordering::Relaxed);
+                self.bytes.fetch_add(key.len() as u64, Ordering::Relaxed);
+            }
+            return;
+        }
+        if let Replaced::Unflushed(_) = replaced {
+            // The value replaced, or a tombstone, never reaches a table.
+            self.pending_bytes.fetch_add(dead, Ordering::Relaxed);
+            if previous.is_none() && !tombstone {
+                self.pend_tombstone(key);
+            }
+        }
+        if tombstone {
+            dead += key.len() as u64;
+            self.tombstones.fetch_add(1, Ordering::Relaxed);
+            if previous.is_none() {
+                // Deletes nothing, so leaves no tombstone.
+                self.pend_tombstone(key);
+            }
+        }
+        self.bytes.fetch_add(dead, Ordering::Relaxed);
+    }
+
+    fn pend_tombstone(&self, key: &str) {
+        self.pending_bytes.fetch_add(key.len() as u64, Ordering::Relaxed);
+        self.pending_tombstones.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A checkpoint or base table of the live data replaced the files.
+    pub(crate) fn clear(&self) {
+        self.set(0, 0);
+    }
+
+    /// Start over from `bytes` of dead data and `tombstones`, none of it
+    /// in the WAL.
+    pub(crate) fn set(&self, bytes: u64, tombstones: u64) {
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.tombstones.store(tombstones, Ordering::Relaxed);
+        self.pending_bytes.store(0, Ordering::Relaxed);
+        self.pending_tombstones.store(0, Ordering::Relaxed);
+    }
+
+    /// A merge of tables dropped `bytes` of dead data and `tombstones`.
+    pub(crate) fn reclaim(&self, bytes: u64, tombstones: u64) {
+        self.bytes.store(self.bytes().saturating_sub(bytes), Ordering::Relaxed);
+        self.tombstones.store(self.tombstones().saturating_sub(tombstones), Ordering::Relaxed);
+    }
+
+    /// Dead bytes and tombstones left once a flush drops the WAL.
+    pub(crate) fn after_flush(&self) -> (u64, u64) {
+        (
+            self.bytes().saturating_sub(self.pending_bytes.load(Ordering::Relaxed)),
+            self.tombstones().saturating_sub(self.pending_tombstones.load(Ordering::Relaxed)),
+        )
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn tombstones(&self) -> u64 {
+        self.tombstones.load(Ordering::Relaxed)
+    }
+}
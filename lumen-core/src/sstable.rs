@@ -0,0 +1,770 @@
+//! SSTables: immutable sorted files the memtable is flushed to.
+//!
+//! With `EngineOptions::memtable_flush_bytes` set, a memtable holding more
+//! keys and values than that is written out as a table, and emptied, before
+//! the next write; the WAL it covered is truncated, as by `Engine::compact`.
+//! Reads that miss the memtable look in the tables, newest first, so memory
+//! holds only what was written since the last flush (see `memtable`).
+//!
+//! A table holds the writes up to its sequence.  The first one written over
+//! an engine without tables holds every live key: it is a base table, and
+//! supersedes the checkpoint.  Later ones hold the keys written since the
+//! table before, with a tombstone for each key deleted, so that it shadows
+//! the key in older tables.  A background thread merges runs of tables of
+//! about the same size as they pile up (see `merge`), and `Engine::compact`
+//! merges every table into one base table again.
+//!
+//! On-disk format (`DATA_DIR/<sequence, 20 digits>.sst`):
+//!   [Magic "LKVSST01" (8 bytes)]
+//!   Blocks × { [Len (4 bytes, BE)] [CRC32 (4 bytes, BE)]
+//!              Entries × { [Key Len (4 bytes, BE)] [Key] [Value Len (4 bytes, BE)] [Value] } }
+//!   Trailer: [Sequence (8 bytes, BE)] [Flags (1 byte)] [Entries (8 bytes, BE)]
+//!            [Live Keys (8 bytes, BE)] [Live Bytes (8 bytes, BE)]
+//!            [Dead Bytes (8 bytes, BE)] [Tombstones (8 bytes, BE)]
+//!            [Block Count (8 bytes, BE)]
+//!            Block Count × { [Offset (8 bytes, BE)] [Len (4 bytes, BE)] [First Key Len (4 bytes, BE)] [First Key] }
+//!   Footer:  [Trailer Offset (8 bytes, BE)] [Trailer CRC32 (4 bytes, BE)] [Magic "LKVSST01"]
+//!
+//! Entries are in key order, in blocks of about `BLOCK_BYTES`; a tombstone
+//! has a Value Len of `u32::MAX` and no Value.  Each block's CRC32 covers
+//! its entries, and the trailer's covers the trailer.  Flag 1 marks a base
+//! table.  The totals are the engine's as of the table (see `Totals`).  The
+//! block index is kept in memory, so a lookup reads one block per table.
+//!
+//! Tables may be moved to a cold tier on slower storage (see `tier`); they
+//! are read alike wherever they are, but the blocks read from a cold table
+//! are kept in the block cache, and read ahead of a scan (see `cache`).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use crate::cache::BlockCache;
+use crate::metrics;
+use crate::sync::sync_parent;
+use crate::wal::WalError;
+
+const MAGIC: &[u8; 8] = b"LKVSST01";
+/// Flag of a table holding every live key.
+const BASE: u8 = 1;
+/// Value length of a tombstone.
+const TOMBSTONE: u32 = u32::MAX;
+/// Longest key or value a table holds: lengths are stored in a `u32`, short
+/// of `TOMBSTONE`.
+pub(crate) const MAX_LEN: u64 = TOMBSTONE as u64 - 1;
+/// Bytes of entries after which a block is closed.
+pub(crate) const BLOCK_BYTES: usize = 4096;
+/// Trailer offset, CRC and magic.
+const FOOTER_LEN: u64 = 20;
+/// Name of the file a flushed table is written to before it is renamed
+/// into place.
+pub(crate) const TEMP_FILE: &str = "sstable.tmp";
+/// Likewise, for a table merged in the background (see `merge`).
+pub(crate) const MERGE_FILE: &str = "merge.tmp";
+/// Likewise, for the base table a replica installs (see
+/// `Engine::install_snapshot`).
+pub(crate) const INSTALL_FILE: &str = "install.tmp";
+
+/// Source of the IDs tables are cached under.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The engine's totals as of a table, so that an engine opened over it knows
+/// them without reading every table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Totals {
+    /// Live keys, in the tables and the memtable.
+    pub live_keys: u64,
+    /// Bytes of their keys and values.
+    pub live_bytes: u64,
+    /// Dead data in the tables (see `space`).
+    pub dead_bytes: u64,
+    /// Deletes since the last base table.
+    pub tombstones: u64,
+}
+
+#[derive(Debug)]
+struct Block {
+    offset: u64,
+    len: u32,
+    first_key: String,
+}
+
+/// One table, open for reading.
+#[derive(Debug)]
+pub struct Table {
+    /// Unique among the tables open in the process.
+    id: u64,
+    path: PathBuf,
+    file: File,
+    /// When the file was last written, if the filesystem says.
+    modified: Option<SystemTime>,
+    /// The cache of a table in the cold tier.
+    cache: Option<Arc<BlockCache>>,
+    sequence: u64,
+    base: bool,
+    entries: u64,
+    totals: Totals,
+    blocks: Vec<Block>,
+    bytes: u64,
+}
+
+/// A key's entry in a table: its value, or `None` for a tombstone.
+pub(crate) type Entry = (String, Option<Vec<u8>>);
+
+impl Table {
+    /// Write `entries` (in key order; `None`: a tombstone) to a table in
+    /// `dir` covering the writes up to `sequence`, and open it.
+    ///
+    /// `totals` is called once the entries are written.  The table is
+    /// written to `temp` (`TEMP_FILE`, `MERGE_FILE` or `INSTALL_FILE`), synced and renamed
+    /// into place, so a crash never leaves a partial one under a table's
+    /// name.
+    pub(crate) fn write<K: AsRef<str>, V: AsRef<[u8]>>(
+        dir: &Path,
+        temp: &str,
+        sequence: u64,
+        base: bool,
+        totals: impl FnOnce() -> Totals,
+        entries: impl IntoIterator<Item = Result<(K, Option<V>), WalError>>,
+    ) -> Result<Table, WalError> {
+        Self::stage(dir, temp, sequence, base, totals, entries)?;
+        Self::place(dir, temp, sequence)
+    }
+
+    /// The first half of `write`: write the table to `temp` in `dir`, and
+    /// sync it.  What was written is deleted if that fails.
+    pub(crate) fn stage<K: AsRef<str>, V: AsRef<[u8]>>(
+        dir: &Path,
+        temp: &str,
+        sequence: u64,
+        base: bool,
+        totals: impl FnOnce() -> Totals,
+        entries: impl IntoIterator<Item = Result<(K, Option<V>), WalError>>,
+    ) -> Result<(), WalError> {
+        let tmp_path = dir.join(temp);
+        if let Err(e) = write_file(&tmp_path, sequence, base, totals, entries) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// The second half of `write`: rename the table `stage` wrote to `temp`
+    /// into place, and open it.
+    pub(crate) fn place(dir: &Path, temp: &str, sequence: u64) -> Result<Table, WalError> {
+        let path = dir.join(file_name(sequence));
+        fs::rename(dir.join(temp), &path)?;
+        sync_parent(&path)?;
+
+        let table = Table::open(&path)?;
+        info!(path = %path.display(), sequence, entries = table.entries, base = table.base, "Table written");
+        Ok(table)
+    }
+
+    /// Open the table at `path`, reading its trailer.
+    pub fn open(path: &Path) -> Result<Table, WalError> {
+        let file     = File::open(path)?;
+        let metadata = file.metadata()?;
+        let bytes    = metadata.len();
+        let invalid = |reason: &str| invalid(path, reason);
+        if bytes < MAGIC.len() as u64 + FOOTER_LEN {
+            return Err(invalid("too short for a table"));
+        }
+
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        read_at(&file, &mut footer, bytes - FOOTER_LEN)?;
+        if &footer[12..] != MAGIC {
+            return Err(invalid("not a LumenKV table, or its footer is damaged"));
+        }
+        let trailer_offset = BigEndian::read_u64(&footer[..8]);
+        if trailer_offset < MAGIC.len() as u64 || trailer_offset > bytes - FOOTER_LEN {
+            return Err(invalid("trailer offset out of range"));
+        }
+        let mut trailer = vec![0u8; (bytes - FOOTER_LEN - trailer_offset) as usize];
+        read_at(&file, &mut trailer, trailer_offset)?;
+        let (expected, actual) = (BigEndian::read_u32(&footer[8..12]), crc32fast::hash(&trailer));
+        if expected != actual {
+            metrics::checksum_failure();
+            return Err(WalError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut r = Fields { bytes: &trailer, path };
+        let sequence = r.u64()?;
+        let base     = r.u8()? & BASE != 0;
+        let entries  = r.u64()?;
+        let totals   = Totals { live_keys: r.u64()?, live_bytes: r.u64()?, dead_bytes: r.u64()?, tombstones: r.u64()? };
+        let count    = r.u64()?;
+        let mut blocks = Vec::new();
+        for _ in 0..count {
+            let offset = r.u64()?;
+            let len    = r.u32()?;
+            let key_len = r.u32()? as usize;
+            let key    = r.take(key_len)?;
+            if offset + 8 + u64::from(len) > trailer_offset {
+                return Err(invalid("block past the end of the data"));
+            }
+            blocks.push(Block { offset, len, first_key: String::from_utf8(key.to_vec())? });
+        }
+
+        let (id, modified) = (NEXT_ID.fetch_add(1, Ordering::Relaxed), metadata.modified().ok());
+        Ok(Table { id, path: path.to_owned(), file, modified, cache: None, sequence, base, entries, totals, blocks, bytes })
+    }
+
+    /// This table, read as one in the cold tier: through `cache`.
+    pub(crate) fn into_cold(mut self, cache: Arc<BlockCache>) -> Table {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Every table in `dir`, in sequence order.
+    pub fn open_all(dir: &Path) -> Result<Vec<Table>, WalError> {
+        let mut tables = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_table = path.extension().is_some_and(|ext| ext == "sst")
+                && path.file_stem().and_then(|stem| stem.to_str()).is_some_and(|stem| stem.parse::<u64>().is_ok());
+            if is_table {
+                tables.push(Table::open(&path)?);
+            }
+        }
+        tables.sort_by_key(|table| table.sequence);
+        Ok(tables)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The last sequence the table covers.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Whether the table holds every live key as of its sequence.
+    pub fn is_base(&self) -> bool {
+        self.base
+    }
+
+    /// Entries in the table, tombstones included.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn totals(&self) -> Totals {
+        self.totals
+    }
+
+    /// Size of the file in bytes.
+    pub fn file_len(&self) -> u64 {
+        self.bytes
+    }
+
+    /// The ID the table's blocks are cached under.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Whether the table is in the cold tier.
+    pub fn is_cold(&self) -> bool {
+        self.cache.is_some()
+    }
+
+    /// How long ago the file was written, if the filesystem says.
+    pub fn age(&self) -> Option<Duration> {
+        SystemTime::now().duration_since(self.modified?).ok()
+    }
+
+    /// `key`'s entry in the table: `Some(None)` for a tombstone, `None` if
+    /// the table does not hold the key.
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Option<Vec<u8>>>, WalError> {
+        let Some(block) = self.blocks.partition_point(|block| block.first_key.as_str() <= key).checked_sub(1) else {
+            return Ok(None);
+        };
+        let entries = self.block(block, true)?;
+        Ok(entries.iter().find(|(found, _)| found == key).map(|(_, value)| value.clone()))
+    }
+
+    /// The table's entries from `start` on, in key order.
+    pub(crate) fn cursor(self: &Arc<Self>, start: Bound<&str>) -> Result<Cursor, WalError> {
+        let block = match start {
+            Bound::Included(key) | Bound::Excluded(key) => {
+                self.blocks.partition_point(|block| block.first_key.as_str() <= key).saturating_sub(1)
+            }
+            Bound::Unbounded => 0,
+        };
+        let mut cursor = Cursor::new(self.clone(), true, block);
+        if let Bound::Included(key) | Bound::Excluded(key) = start {
+            if cursor.load()? {
+                let skip = cursor.entries.as_slice().partition_point(|(found, _)| match start {
+                    Bound::Excluded(_) => found.as_str() <= key,
+                    _ => found.as_str() < key,
+                });
+                if skip > 0 {
+                    cursor.entries.nth(skip - 1);
+                }
+            }
+        }
+        Ok(cursor)
+    }
+
+    /// Every entry of the table in key order, read past the block cache, as
+    /// a merge reads a table once through.
+    pub(crate) fn read_through(self: &Arc<Self>) -> Cursor {
+        Cursor::new(self.clone(), false, 0)
+    }
+
+    /// Block `index`: from the block cache, if the table is cold and
+    /// `cached` is set, and otherwise read from disk, and then cached.
+    fn block(&self, index: usize, cached: bool) -> Result<Arc<Vec<Entry>>, WalError> {
+        let cache = self.cache.as_ref().filter(|_| cached);
+        if let Some(entries) = cache.and_then(|cache| cache.get(self.id, index)) {
+            return Ok(entries);
+        }
+        let entries = Arc::new(self.read_block(index)?);
+        if let Some(cache) = cache {
+            cache.insert(self.id, index, entries.clone(), u64::from(self.blocks[index].len));
+        }
+        Ok(entries)
+    }
+
+    /// Read block `index` into the block cache ahead of a cursor, unless it
+    /// is there already.  A failed read is left for the cursor to report.
+    pub(crate) fn prefetch_block(&self, index: usize) {
+        let Some(cache) = &self.cache else { return };
+        if cache.contains(self.id, index) {
+            return;
+        }
+        if let Ok(entries) = self.read_block(index) {
+            cache.insert(self.id, index, Arc::new(entries), u64::from(self.blocks[index].len));
+        }
+    }
+
+    /// Decode block `index`, verifying its checksum.
+    fn read_block(&self, index: usize) -> Result<Vec<Entry>, WalError> {
+        let data = self.block_data(index)?;
+        let (expected, actual) = (BigEndian::read_u32(&data[4..8]), crc32fast::hash(&data[8..]));
+        if expected != actual {
+            metrics::checksum_failure();
+            return Err(WalError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut r = Fields { bytes: &data[8..], path: &self.path };
+        let mut entries = Vec::new();
+        while !r.bytes.is_empty() {
+            let key_len = r.u32()? as usize;
+            let key   = String::from_utf8(r.take(key_len)?.to_vec())?;
+            let value = match r.u32()? {
+                TOMBSTONE => None,
+                len => Some(r.take(len as usize)?.to_vec()),
+            };
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    /// Read block `index` through for a scrub: its size in bytes, and what
+    /// is wrong with its checksum, if anything.
+    pub(crate) fn verify_block(&self, index: usize) -> (u64, Option<String>) {
+        let data = match self.block_data(index) {
+            Ok(data) => data,
+            Err(e) => return (0, Some(e.to_string())),
+        };
+        let (stored, computed) = (BigEndian::read_u32(&data[4..8]), crc32fast::hash(&data[8..]));
+        let problem = (stored != computed).then(|| {
+            format!("block checksum mismatch: stored {stored:#010x}, computed {computed:#010x}")
+        });
+        (data.len() as u64, problem)
+    }
+
+    /// Number of blocks in the table.
+    pub(crate) fn blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Offset of block `index` in the file.
+    pub(crate) fn block_offset(&self, index: usize) -> u64 {
+        self.blocks[index].offset
+    }
+
+    /// Block `index` as stored, header included.
+    fn block_data(&self, index: usize) -> Result<Vec<u8>, WalError> {
+        let block = &self.blocks[index];
+        let mut data = vec![0u8; 8 + block.len as usize];
+        read_at(&self.file, &mut data, block.offset)?;
+        if BigEndian::read_u32(&data[..4]) != block.len {
+            return Err(invalid(&self.path, "block length does not match the index"));
+        }
+        Ok(data)
+    }
+}
+
+impl Drop for Table {
+    fn drop(&mut self) {
+        if let Some(cache) = &self.cache {
+            cache.forget(self.id);
+        }
+    }
+}
+
+/// A table's entries in key order, tombstones included, read a block at a
+/// time.
+#[derive(Debug)]
+pub(crate) struct Cursor {
+    table: Arc<Table>,
+    /// Whether blocks are read through the block cache.
+    cached: bool,
+    next_block: usize,
+    entries: std::vec::IntoIter<Entry>,
+    /// Blocks read so far, and the end of those asked to be read ahead.
+    loaded: usize,
+    ahead: usize,
+}
+
+impl Cursor {
+    fn new(table: Arc<Table>, cached: bool, next_block: usize) -> Self {
+        Self { table, cached, next_block, entries: Vec::new().into_iter(), loaded: 0, ahead: 0 }
+    }
+
+    /// Read the next block; `false` past the last one.  Past the first
+    /// block of a cold table, keep the ones after it read ahead.
+    fn load(&mut self) -> Result<bool, WalError> {
+        if self.next_block >= self.table.blocks.len() {
+            return Ok(false);
+        }
+        self.loaded += 1;
+        if let Some(cache) = self.table.cache.as_ref().filter(|_| self.cached && self.loaded > 1) {
+            let start = self.ahead.max(self.next_block + 1);
+            let end   = (self.next_block + 1 + cache.prefetch_depth()).min(self.table.blocks.len());
+            if start < end {
+                cache.prefetch(&self.table, start..end);
+                self.ahead = end;
+            }
+        }
+        // A block read for this cursor alone is moved out, not copied.
+        self.entries = match Arc::try_unwrap(self.table.block(self.next_block, self.cached)?) {
+            Ok(entries) => entries.into_iter(),
+            Err(shared) => Vec::clone(&shared).into_iter(),
+        };
+        self.next_block += 1;
+        Ok(true)
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = Result<Entry, WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(Ok(entry));
+            }
+            match self.load() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    self.next_block = self.table.blocks.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Write a table's file, as `Table::write` describes, to `path`, and sync it.
+fn write_file<K: AsRef<str>, V: AsRef<[u8]>>(
+    path: &Path,
+    sequence: u64,
+    base: bool,
+    totals: impl FnOnce() -> Totals,
+    entries: impl IntoIterator<Item = Result<(K, Option<V>), WalError>>,
+) -> Result<(), WalError> {
+    let file = OpenOptions::new().create(true).write(true).truncate(true).open(path)?;
+    let mut w = BufWriter::new(file);
+    w.write_all(MAGIC)?;
+
+    let mut offset = MAGIC.len() as u64;
+    let mut index: Vec<(u64, u32, String)> = Vec::new();
+    let mut block = Vec::with_capacity(BLOCK_BYTES * 2);
+    let mut first_key = None;
+    let mut count = 0u64;
+    let mut close = |w: &mut BufWriter<File>, block: &mut Vec<u8>, first_key: String| -> Result<(), WalError> {
+        let len = stored_len("block", block.len(), u64::from(u32::MAX))?;
+        w.write_u32::<BigEndian>(len)?;
+        w.write_u32::<BigEndian>(crc32fast::hash(block))?;
+        w.write_all(block)?;
+        index.push((offset, len, first_key));
+        offset += 8 + block.len() as u64;
+        block.clear();
+        Ok(())
+    };
+
+    for entry in entries {
+        let (key, value) = entry?;
+        let key = key.as_ref();
+        if first_key.is_none() {
+            first_key = Some(key.to_owned());
+        }
+        block.write_u32::<BigEndian>(stored_len("key", key.len(), MAX_LEN)?)?;
+        block.extend_from_slice(key.as_bytes());
+        match value {
+            Some(value) => {
+                let value = value.as_ref();
+                block.write_u32::<BigEndian>(stored_len("value", value.len(), MAX_LEN)?)?;
+                block.extend_from_slice(value);
+            }
+            None => block.write_u32::<BigEndian>(TOMBSTONE)?,
+        }
+        count += 1;
+        if block.len() >= BLOCK_BYTES {
+            close(&mut w, &mut block, first_key.take().unwrap_or_default())?;
+        }
+    }
+    if let Some(first_key) = first_key {
+        close(&mut w, &mut block, first_key)?;
+    }
+
+    let totals = totals();
+    let mut trailer = Vec::new();
+    trailer.write_u64::<BigEndian>(sequence)?;
+    trailer.write_u8(if base { BASE } else { 0 })?;
+    trailer.write_u64::<BigEndian>(count)?;
+    for total in [totals.live_keys, totals.live_bytes, totals.dead_bytes, totals.tombstones] {
+        trailer.write_u64::<BigEndian>(total)?;
+    }
+    trailer.write_u64::<BigEndian>(index.len() as u64)?;
+    for (block_offset, len, first_key) in &index {
+        trailer.write_u64::<BigEndian>(*block_offset)?;
+        trailer.write_u32::<BigEndian>(*len)?;
+        trailer.write_u32::<BigEndian>(first_key.len() as u32)?;
+        trailer.extend_from_slice(first_key.as_bytes());
+    }
+    w.write_all(&trailer)?;
+    w.write_u64::<BigEndian>(offset)?;
+    w.write_u32::<BigEndian>(crc32fast::hash(&trailer))?;
+    w.write_all(MAGIC)?;
+    w.flush()?;
+    w.get_ref().sync_all()?;
+    Ok(())
+}
+
+/// `len`, as the `u32` a table stores it in, if it is at most `max`.
+fn stored_len(field: &'static str, len: usize, max: u64) -> Result<u32, WalError> {
+    let len = len as u64;
+    match u32::try_from(len) {
+        Ok(stored) if len <= max => Ok(stored),
+        _ => Err(WalError::RecordTooLarge { field, len, max }),
+    }
+}
+
+/// Entries of several sources in key order, each key once, from the first
+/// source holding it: sources are given newest first.  Tombstones are kept.
+pub(crate) struct Merged<'a> {
+    sources: Vec<Box<dyn Iterator<Item = Result<Entry, WalError>> + 'a>>,
+    /// Each source's next entry.
+    heads: Vec<Option<Result<Entry, WalError>>>,
+    failed: bool,
+    /// Older entries of keys a newer source holds, skipped so far.
+    pub(crate) shadowed: u64,
+    /// Of those, tombstones.
+    pub(crate) shadowed_tombstones: u64,
+    /// Bytes of their keys and values.
+    pub(crate) shadowed_bytes: u64,
+}
+
+impl<'a> Merged<'a> {
+    pub(crate) fn new(mut sources: Vec<Box<dyn Iterator<Item = Result<Entry, WalError>> + 'a>>) -> Self {
+        let heads = sources.iter_mut().map(|source| source.next()).collect();
+        Self { sources, heads, failed: false, shadowed: 0, shadowed_tombstones: 0, shadowed_bytes: 0 }
+    }
+}
+
+impl Iterator for Merged<'_> {
+    type Item = Result<Entry, WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        for head in &mut self.heads {
+            if matches!(head, Some(Err(_))) {
+                self.failed = true;
+                return head.take();
+            }
+        }
+
+        let key = self.heads.iter().flatten().flatten().map(|(key, _)| key).min()?.clone();
+        let mut newest = None;
+        for (head, source) in self.heads.iter_mut().zip(&mut self.sources) {
+            if !matches!(head, Some(Ok((next, _))) if *next == key) {
+                continue;
+            }
+            if let Some(Ok((_, value))) = head.take() {
+                if newest.is_none() {
+                    newest = Some(value);
+                } else {
+                    self.shadowed += 1;
+                    self.shadowed_tombstones += u64::from(value.is_none());
+                    self.shadowed_bytes += (key.len() + value.map_or(0, |value| value.len())) as u64;
+                }
+            }
+            *head = source.next();
+        }
+        Some(Ok((key, newest.flatten())))
+    }
+}
+
+/// Name of the table covering the writes up to `sequence`.
+pub(crate) fn file_name(sequence: u64) -> String {
+    format!("{sequence:020}.sst")
+}
+
+fn invalid(path: &Path, reason: &str) -> WalError {
+    WalError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {reason}", path.display()),
+    ))
+}
+
+/// Big-endian fields read off the front of a slice.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    path: &'a Path,
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], WalError> {
+        if len > self.bytes.len() {
+            return Err(invalid(self.path, "entry runs past the end of its block"));
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> Result<u8, WalError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, WalError> {
+        Ok(BigEndian::read_u32(self.take(4)?))
+    }
+
+    fn u64(&mut self) -> Result<u64, WalError> {
+        Ok(BigEndian::read_u64(self.take(8)?))
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// A cold table of enough entries to fill many blocks, read through a
+    /// cache that keeps `prefetch` blocks ahead of a scan.
+    fn cold_table(dir: &Path, prefetch: usize) -> (Arc<Table>, Arc<BlockCache>) {
+        let entries = (0..2000).map(|i| Ok::<_, WalError>((format!("key-{i:05}"), Some(vec![b'v'; 64]))));
+        let table   = Table::write(dir, TEMP_FILE, 1, true, Totals::default, entries).unwrap();
+        let cache   = Arc::new(BlockCache::new(64 << 20, prefetch));
+        (Arc::new(table.into_cold(cache.clone())), cache)
+    }
+
+    #[test]
+    fn a_table_reads_back_its_entries_and_tombstones() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = [("a", Some(&b"1"[..])), ("b", None), ("c", Some(&b""[..]))];
+        let totals  = Totals { live_keys: 2, live_bytes: 3, dead_bytes: 1, tombstones: 1 };
+        let written = Table::write(dir.path(), TEMP_FILE, 7, false, || totals, entries.map(Ok)).unwrap();
+        assert_eq!(written.path(), dir.path().join(file_name(7)));
+        assert!(!dir.path().join(TEMP_FILE).exists());
+
+        let table = Arc::new(Table::open(written.path()).unwrap());
+        assert_eq!((table.sequence(), table.is_base(), table.entries(), table.totals()), (7, false, 3, totals));
+        assert_eq!(table.get("a").unwrap(), Some(Some(b"1".to_vec())));
+        assert_eq!(table.get("b").unwrap(), Some(None));
+        assert_eq!(table.get("c").unwrap(), Some(Some(Vec::new())));
+        assert_eq!(table.get("0").unwrap(), None);
+        assert_eq!(table.get("bb").unwrap(), None);
+        let from_b: Vec<Entry> = table.cursor(Bound::Excluded("a")).unwrap().map(Result::unwrap).collect();
+        assert_eq!(from_b, [("b".to_owned(), None), ("c".to_owned(), Some(Vec::new()))]);
+    }
+
+    #[test]
+    fn refuses_lengths_a_table_cannot_store() {
+        assert_eq!(stored_len("value", MAX_LEN as usize, MAX_LEN).unwrap(), u32::MAX - 1);
+        // `u32::MAX` would read back as a tombstone, and more would wrap.
+        for len in [u64::from(u32::MAX), 1 << 32] {
+            let err = stored_len("value", len as usize, MAX_LEN).unwrap_err();
+            assert!(matches!(err, WalError::RecordTooLarge { field: "value", .. }), "{err}");
+        }
+    }
+
+    fn await_cached(cache: &BlockCache, table: &Table, blocks: std::ops::Range<usize>) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !blocks.clone().all(|index| cache.contains(table.id(), index)) {
+            assert!(Instant::now() < deadline, "blocks {blocks:?} were not read ahead");
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn a_sequential_scan_reads_blocks_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        let (table, cache) = cold_table(dir.path(), 4);
+        assert!(table.blocks() > 10);
+        let per_block = table.read_block(0).unwrap().len();
+
+        // Reading into the second block marks the cursor as sequential.
+        let mut cursor = table.cursor(Bound::Unbounded).unwrap();
+        for entry in cursor.by_ref().take(per_block + 1) {
+            entry.unwrap();
+        }
+        await_cached(&cache, &table, 0..6);
+
+        // And it keeps the same distance ahead as it goes.
+        for entry in cursor.by_ref().take(per_block * 4) {
+            entry.unwrap();
+        }
+        await_cached(&cache, &table, 6..10);
+        assert_eq!(cursor.count(), 2000 - 5 * per_block - 1);
+    }
+
+    #[test]
+    fn point_reads_and_merges_do_not_read_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        let (table, cache) = cold_table(dir.path(), 4);
+        assert_eq!(table.get("key-00500").unwrap(), Some(Some(vec![b'v'; 64])));
+        assert_eq!(table.get("key-00501").unwrap(), Some(Some(vec![b'v'; 64])));
+        assert_eq!(table.read_through().count(), 2000);
+        std::thread::sleep(Duration::from_millis(50));
+
+        let block = table.blocks.partition_point(|block| block.first_key.as_str() <= "key-00500") - 1;
+        assert_eq!(cache.bytes(), u64::from(table.blocks[block].len));
+    }
+}
@@ -0,0 +1,136 @@
+//! Making writes durable.
+//!
+//! A file's data only survives a crash once it has been synced, and a newly
+//! created or renamed file only once the directory holding it has been
+//! synced too — on some filesystems a crash right after the first open can
+//! lose the whole file otherwise.
+//!
+//! `SyncMethod::probe` checks, when the engine opens, that syncing works in
+//! the data directory: it writes a probe file, syncs it with `fdatasync` and
+//! reads it back.  Where `fdatasync` fails (some network and FUSE
+//! filesystems reject it), files are opened with `O_DSYNC` instead, so every
+//! write returns only once it is durable.
+//!
+//! With `fdatasync`, `SyncPolicy` says when commits are synced: by default
+//! the WAL is only flushed to the OS, which writes it out on its own
+//! schedule; otherwise every commit waits for a sync, shared with the
+//! commits around it (see `group_commit`).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+/// Written to, and read back from, the probe file.
+const PROBE: &[u8] = b"lumen-kv sync probe\n";
+
+/// How files are made durable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncMethod {
+    /// `fdatasync` once the writes are done.
+    #[default]
+    Fdatasync,
+    /// Files opened with `O_DSYNC`: each write is durable when it returns.
+    Dsync,
+}
+
+impl SyncMethod {
+    /// The first method that works in `dir`, trying `Fdatasync` before
+    /// `Dsync`.  Fails if neither does.
+    pub fn probe(dir: &Path) -> std::io::Result<Self> {
+        let path = dir.join(".sync-probe");
+
+        let mut failure = None;
+        for method in [SyncMethod::Fdatasync, SyncMethod::Dsync] {
+            match method.check(&path) {
+                Ok(()) => {
+                    let _ = fs::remove_file(&path);
+                    debug!(dir = %dir.display(), method = ?method, "Sync self-test passed");
+                    return Ok(method);
+                }
+                Err(e) => {
+                    warn!(dir = %dir.display(), method = ?method, error = %e, "Sync self-test failed");
+                    failure = Some(e);
+                }
+            }
+        }
+        let _ = fs::remove_file(&path);
+        Err(failure.expect("at least one method was tried"))
+    }
+
+    /// Write `PROBE` to `path` with this method and read it back.
+    fn check(self, path: &Path) -> std::io::Result<()> {
+        let mut file = self.options().create(true).truncate(true).open(path)?;
+        file.write_all(PROBE)?;
+        self.sync(&file)?;
+        drop(file);
+        sync_parent(path)?;
+
+        let mut read = Vec::new();
+        File::open(path)?.read_to_end(&mut read)?;
+        if read != PROBE {
+            return Err(std::io::Error::other("probe file read back differently"));
+        }
+        Ok(())
+    }
+
+    /// Options for opening a file for writing with this method.
+    pub fn options(self) -> OpenOptions {
+        let mut options = OpenOptions::new();
+        options.write(true);
+        if self == SyncMethod::Dsync {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.custom_flags(libc::O_DSYNC);
+            }
+        }
+        options
+    }
+
+    /// Make what has been written to `file` (opened with `options`) durable.
+    pub fn sync(self, file: &File) -> std::io::Result<()> {
+        let started = Instant::now();
+        match self {
+            SyncMethod::Fdatasync => file.sync_data()?,
+            SyncMethod::Dsync if cfg!(unix) => {}
+            SyncMethod::Dsync => file.sync_all()?,
+        }
+        metrics::sync(started.elapsed());
+        Ok(())
+    }
+}
+
+/// When commits are made durable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    /// Commits are flushed to the OS, which writes them out on its own
+    /// schedule; a crash of the machine can lose the latest.
+    /// `Engine::sync` makes them durable on demand.
+    #[default]
+    Os,
+    /// A commit returns once it is synced.  The commits arriving within
+    /// this window of the first waiting one share its sync.  Readers and
+    /// replicas see a commit before it is synced, and a failed sync fails
+    /// every write after it (see `EngineError::SyncFailed`).
+    Interval(Duration),
+    /// `Interval`, with a window tuned to keep the p99 commit latency near
+    /// `target_p99`: wider while commits queue up, narrower when idle.
+    Adaptive { target_p99: Duration },
+}
+
+/// Sync the directory holding `path`, so that a file created in it or
+/// renamed into it survives a crash.  A no-op where directories cannot be
+/// opened (outside Unix).
+pub fn sync_parent(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
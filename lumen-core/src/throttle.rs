@@ -0,0 +1,202 @@
+//! Per-namespace write throttling.
+//!
+//! A namespace is the key prefix before the first `/` (`""` for keys
+//! without one).  A namespace given a limit has a token bucket of that many
+//! bytes per second, holding at most one second's worth.  Each put costs
+//! its key and value bytes and is admitted while the bucket is not in debt,
+//! so a write larger than the bucket still goes through and is paid back
+//! before the next.  Deletes, writes to reserved keys and replicated
+//! changes are never throttled: they free space, keep leases working, or
+//! must follow the primary.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::engine::EngineError;
+use crate::lease::is_reserved_key;
+use crate::wal::WalRecord;
+
+/// The namespace `key` belongs to: its prefix before the first `/`.
+pub fn namespace_of(key: &str) -> &str {
+    key.split_once('/').map_or("", |(namespace, _)| namespace)
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes per second, and the most the bucket holds.
+    rate: u64,
+    /// Negative while in debt.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens   = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled = now;
+    }
+}
+
+/// The write limits of one engine.
+#[derive(Debug, Default)]
+pub(crate) struct Throttle {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Throttle {
+    fn buckets(&self) -> MutexGuard<'_, HashMap<String, Bucket>> {
+        self.buckets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Limit writes to `namespace` to `bytes_per_sec`, or lift its limit.
+    pub(crate) fn set(&self, namespace: &str, bytes_per_sec: Option<u64>) {
+        let mut buckets = self.buckets();
+        match bytes_per_sec {
+            Some(rate) => {
+                let rate = rate.max(1);
+                buckets.insert(namespace.to_owned(), Bucket { rate, tokens: rate as f64, refilled: Instant::now() });
+            }
+            None => {
+                buckets.remove(namespace);
+            }
+        }
+    }
+
+    /// Every limited namespace with its limit, in namespace order.
+    pub(crate) fn limits(&self) -> Vec<(String, u64)> {
+        let mut limits: Vec<_> = self.buckets().iter().map(|(ns, bucket)| (ns.clone(), bucket.rate)).collect();
+        limits.sort();
+        limits
+    }
+
+    /// Admit `records` as one write, charging their namespaces, or refuse
+    /// all of them with `EngineError::Throttled` if any is in debt.
+    pub(crate) fn admit<'a>(&self, records: impl IntoIterator<Item = &'a WalRecord>) -> Result<(), EngineError> {
+        let mut buckets = self.buckets();
+        if buckets.is_empty() {
+            return Ok(());
+        }
+
+        let mut costs: Vec<(&str, u64)> = Vec::new();
+        for record in records {
+            let WalRecord::Put { key, value } = record else { continue };
+            if is_reserved_key(key) || !buckets.contains_key(namespace_of(key)) {
+                continue;
+            }
+            let cost = (key.len() + value.len()) as u64;
+            match costs.iter_mut().find(|(ns, _)| *ns == namespace_of(key)) {
+                Some((_, total)) => *total += cost,
+                None => costs.push((namespace_of(key), cost)),
+            }
+        }
+
+        let now = Instant::now();
+        for (namespace, _) in &costs {
+            let bucket = buckets.get_mut(*namespace).expect("only limited namespaces are charged");
+            bucket.refill(now);
+            if bucket.tokens < 0.0 {
+                return Err(EngineError::Throttled {
+                    namespace: (*namespace).to_owned(),
+                    retry_after: Duration::from_secs_f64(-bucket.tokens / bucket.rate as f64),
+                });
+            }
+        }
+        for (namespace, cost) in costs {
+            buckets.get_mut(namespace).expect("only limited namespaces are charged").tokens -= cost as f64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::feed::Change;
+
+    fn put(key: &str, bytes: usize) -> WalRecord {
+        WalRecord::Put { key: key.to_owned(), value: vec![0; bytes - key.len()] }
+    }
+
+    fn retry_after(result: Result<(), EngineError>) -> Duration {
+        match result {
+            Err(EngineError::Throttled { retry_after, .. }) => retry_after,
+            other => panic!("expected a throttled write, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn a_namespace_in_debt_is_refused_until_it_has_paid_back() {
+        let throttle = Throttle::default();
+        throttle.set("bulk", Some(1000));
+
+        // A write larger than the bucket goes through, into debt.
+        throttle.admit([&put("bulk/a", 1500)]).unwrap();
+        let wait = retry_after(throttle.admit([&put("bulk/b", 10)]));
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500), "{wait:?}");
+
+        // Other namespaces, deletes and reserved keys are not held back.
+        throttle.admit([&put("other/a", 5000), &put("nons", 5000)]).unwrap();
+        throttle.admit([&WalRecord::Delete { key: "bulk/a".to_owned() }]).unwrap();
+        throttle.admit([&put("\0lease/0000000000000001/bulk/a", 100)]).unwrap();
+
+        std::thread::sleep(wait);
+        throttle.admit([&put("bulk/b", 10)]).unwrap();
+    }
+
+    #[test]
+    fn a_batch_is_refused_whole() {
+        let throttle = Throttle::default();
+        throttle.set("a", Some(100));
+        throttle.set("b", Some(100));
+        throttle.admit([&put("b/1", 200)]).unwrap();
+
+        retry_after(throttle.admit([&put("a/1", 50), &put("b/2", 50)]));
+        // "a" was not charged for the refused batch.
+        throttle.admit([&put("a/1", 100)]).unwrap();
+    }
+
+    #[test]
+    fn writes_are_paced_to_the_rate() {
+        let throttle = Throttle::default();
+        throttle.set("ns", Some(20_000));
+
+        let started     = Instant::now();
+        let mut written = 0u64;
+        while started.elapsed() < Duration::from_millis(300) {
+            match throttle.admit([&put("ns/k", 1000)]) {
+                Ok(()) => written += 1000,
+                Err(EngineError::Throttled { retry_after, .. }) => std::thread::sleep(retry_after),
+                Err(e) => panic!("{e}"),
+            }
+        }
+        // One second's burst, then the rate, with one write of slack.
+        let allowed = 20_000.0 + 20_000.0 * started.elapsed().as_secs_f64() + 1000.0;
+        assert!(written > 20_000 && (written as f64) <= allowed, "{written} bytes in {:?}", started.elapsed());
+    }
+
+    #[test]
+    fn the_engine_refuses_a_throttled_put_before_logging_it() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        engine.set_write_limit("bulk", Some(100));
+        assert_eq!(engine.write_limits(), [("bulk".to_owned(), 100)]);
+
+        engine.put("bulk/a".to_owned(), vec![0; 200]).unwrap();
+        let before = engine.latest_sequence().unwrap();
+        let err    = engine.put("bulk/b".to_owned(), vec![0; 10]).unwrap_err();
+        assert_eq!(err.code(), crate::code::ErrorCode::Backpressure);
+        assert_eq!(engine.latest_sequence().unwrap(), before);
+        assert_eq!(engine.get("bulk/b").unwrap(), None);
+
+        // Changes from a primary are applied whatever the limit.
+        let record = put("bulk/c", 200);
+        engine.apply_replicated(Change { sequence: before + 1, timestamp: 0, record }).unwrap();
+        assert!(engine.get("bulk/c").unwrap().is_some());
+
+        engine.set_write_limit("bulk", None);
+        engine.put("bulk/b".to_owned(), vec![0; 10]).unwrap();
+    }
+}
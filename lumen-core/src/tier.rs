@@ -0,0 +1,202 @@
+//! The cold tier: tables moved to slower, cheaper storage.
+//!
+//! With `EngineOptions::cold_tier` set, the background thread that merges
+//! tables (see `merge`) also moves them to `ColdTier::dir`: the base table,
+//! the bottom of the store, which is only rewritten when a merge takes it
+//! in, as soon as it is written, and every other table once it is
+//! `ColdTier::after` old.  A table is copied into `TIER_FILE` there, synced
+//! and renamed into place before its file in the data directory is
+//! deleted, so it is complete in one of the two at all times; if a crash
+//! leaves it in both, `Engine::open` keeps the copy in the data directory.
+//!
+//! Cold tables are read like the others, a block per lookup, but the blocks
+//! read from them are kept in a cache of `ColdTier::cache_bytes` (see
+//! `cache`), so a key read often is fetched from the cold storage once, and
+//! a scan has the blocks ahead of it read in the background.
+//! Merges read cold tables past the cache, and write the merged table to
+//! the data directory, from which it moves again.
+//!
+//! A cold directory serves one data directory.  Each records the other
+//! (`DATA_DIR/cold_tier`, `COLD_DIR/DATA_DIR`), and an engine refuses to
+//! open over a mismatch, or without a cold tier over a data directory whose
+//! tables were moved, as it would miss them.
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::BlockCache;
+use crate::engine::EngineError;
+use crate::sstable::{self, Table};
+use crate::sync::sync_parent;
+use crate::wal::WalError;
+
+/// Name of the file a table is copied to in the cold directory before it
+/// is renamed into place.
+pub(crate) const TIER_FILE: &str = "tier.tmp";
+/// The data directory's record of its cold directory.
+const COLD_MARKER: &str = "cold_tier";
+/// The cold directory's record of its data directory.
+const DATA_MARKER: &str = "DATA_DIR";
+/// Default size of the block cache.
+const DEFAULT_CACHE_BYTES: u64 = 64 << 20;
+/// Default blocks read ahead of a scan.
+const DEFAULT_PREFETCH: usize = 8;
+
+/// Where, and when, tables move to the cold tier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColdTier {
+    /// Directory for cold tables, on the slower storage, used by this
+    /// engine alone.
+    pub dir: PathBuf,
+    /// Move tables other than the base table once they are this old
+    /// (`None`: only the base table).
+    pub after: Option<Duration>,
+    /// Most bytes of blocks read from cold tables kept in memory.
+    pub cache_bytes: u64,
+    /// Blocks read ahead of a scan of a cold table (0: none; see `cache`).
+    pub prefetch_blocks: usize,
+}
+
+impl ColdTier {
+    /// Move base tables to `dir`, with a 64 MiB block cache, reading 8
+    /// blocks ahead of scans.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), after: None, cache_bytes: DEFAULT_CACHE_BYTES, prefetch_blocks: DEFAULT_PREFETCH }
+    }
+}
+
+/// The cold tier of an open engine.
+#[derive(Debug)]
+pub(crate) struct Tiering {
+    policy: ColdTier,
+    cache: Arc<BlockCache>,
+}
+
+impl Tiering {
+    pub(crate) fn new(policy: ColdTier, cache: Arc<BlockCache>) -> Self {
+        Self { policy, cache }
+    }
+
+    /// Whether tables are moved on their age, besides the base table.
+    pub(crate) fn by_age(&self) -> bool {
+        self.policy.after.is_some()
+    }
+
+    /// Whether `table` should move to the cold tier now.
+    pub(crate) fn is_due(&self, table: &Table) -> bool {
+        !table.is_cold()
+            && (table.is_base() || self.policy.after.is_some_and(|after| table.age().is_some_and(|age| age >= after)))
+    }
+
+    /// Copy `table` to the cold directory and open the copy there.  The
+    /// original is left for the caller to delete.
+    pub(crate) fn copy(&self, table: &Table) -> Result<Table, WalError> {
+        let tmp_path = self.policy.dir.join(TIER_FILE);
+        let copied = fs::copy(table.path(), &tmp_path).and_then(|_| File::open(&tmp_path)?.sync_all());
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        let path = self.policy.dir.join(sstable::file_name(table.sequence()));
+        fs::rename(&tmp_path, &path)?;
+        sync_parent(&path)?;
+        Ok(Table::open(&path)?.into_cold(self.cache.clone()))
+    }
+
+    pub(crate) fn cache(&self) -> &BlockCache {
+        &self.cache
+    }
+}
+
+/// Check that `data_dir` and the cold directory `tier` names (if any) belong
+/// together, recording each in the other, and return the directory cold
+/// tables are to be loaded from.
+pub(crate) fn open(data_dir: &Path, tier: Option<&ColdTier>) -> Result<Option<PathBuf>, EngineError> {
+    let marker   = data_dir.join(COLD_MARKER);
+    let recorded = read_marker(&marker)?;
+    let Some(tier) = tier else {
+        let Some(recorded) = recorded else { return Ok(None) };
+        if !has_tables(&recorded)? {
+            fs::remove_file(&marker).map_err(WalError::Io)?;
+            return Ok(None);
+        }
+        return Err(mismatch(data_dir, format!("its tables were moved to the cold tier in {}", recorded.display())));
+    };
+
+    fs::create_dir_all(&tier.dir).map_err(WalError::Io)?;
+    let (data, cold) = (canonical(data_dir)?, canonical(&tier.dir)?);
+    if let Some(recorded) = recorded.filter(|recorded| *recorded != cold) {
+        if has_tables(&recorded)? {
+            return Err(mismatch(
+                data_dir,
+                format!("its tables were moved to the cold tier in {}, not {}", recorded.display(), cold.display()),
+            ));
+        }
+    }
+    let owner = cold.join(DATA_MARKER);
+    if let Some(owner) = read_marker(&owner)?.filter(|owner| *owner != data) {
+        return Err(EngineError::Inconsistent {
+            dir:     data_dir.to_owned(),
+            problem: format!("the cold tier {} holds the tables of {}", cold.display(), owner.display()),
+            hint:    "give each data directory a cold directory of its own".to_owned(),
+        });
+    }
+    write_marker(&owner, &data)?;
+    write_marker(&marker, &cold)?;
+
+    // A copy cut short; the table is still in the data directory.
+    match fs::remove_file(cold.join(TIER_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(WalError::Io(e).into()),
+        _ => {}
+    }
+    Ok(Some(cold))
+}
+
+fn mismatch(data_dir: &Path, problem: String) -> EngineError {
+    EngineError::Inconsistent {
+        dir: data_dir.to_owned(),
+        problem,
+        hint: format!(
+            "open it with that cold tier (`COLD_TIER_DIR`), or move the tables back into the data directory \
+             and delete {}",
+            data_dir.join(COLD_MARKER).display()
+        ),
+    }
+}
+
+fn canonical(dir: &Path) -> Result<PathBuf, EngineError> {
+    Ok(fs::canonicalize(dir).map_err(WalError::Io)?)
+}
+
+fn has_tables(dir: &Path) -> Result<bool, EngineError> {
+    match Table::open_all(dir) {
+        Ok(tables) => Ok(!tables.is_empty()),
+        Err(WalError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The path recorded at `path`, if any.  An empty record, as a crash while
+/// it was first written may leave, counts as none.
+fn read_marker(path: &Path) -> Result<Option<PathBuf>, EngineError> {
+    match fs::read_to_string(path) {
+        Ok(recorded) if recorded.trim().is_empty() => Ok(None),
+        Ok(recorded) => Ok(Some(PathBuf::from(recorded.trim_end_matches('\n')))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(WalError::Io(e).into()),
+    }
+}
+
+fn write_marker(path: &Path, recorded: &Path) -> Result<(), EngineError> {
+    if read_marker(path)?.as_deref() == Some(recorded) {
+        return Ok(());
+    }
+    let write = || -> std::io::Result<()> {
+        fs::write(path, format!("{}\n", recorded.display()))?;
+        File::open(path)?.sync_all()?;
+        sync_parent(path)
+    };
+    Ok(write().map_err(WalError::Io)?)
+}
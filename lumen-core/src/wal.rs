@@ -1,20 +1,79 @@
 //! Write-Ahead Log with CRC32 integrity protection.
 //!
-//! On-disk record format (per entry):
-//!   [Op (1 byte)] [CRC32 (4 bytes, big-endian)]
-//!   [Key Len (8 bytes, big-endian)] [Value Len (8 bytes, big-endian)]
+//! On-disk format (v3): a file header, then one framed record per entry.
+//!
+//! File header (24 bytes):
+//!   [Magic "LKVWALOG" (8 bytes)] [Format Version (2 bytes, BE) = 3]
+//!   [Checksum Algorithm (1 byte) = 1, CRC32] [Flags (1 byte)]
+//!   [Created (8 bytes, BE, Unix milliseconds)] [Header CRC32 (4 bytes, BE)]
+//!
+//! Record envelope:
+//!   [Payload Len (4 bytes, BE)] [Payload CRC32 (4 bytes, BE)] [Payload]
+//!   [Commit Marker (4 bytes, BE), if the header has the COMMIT_MARKERS flag]
+//!
+//! The commit marker is the payload CRC XOR a constant, written after the
+//! payload.  A torn write that keeps the envelope but loses the end of the
+//! record (or leaves zeros, or stale bytes, in its place) then fails on the
+//! marker, independently of the CRC.  Recovery reports a log whose last
+//! record is incomplete, lacks its marker, or is followed by nothing but
+//! zeros as a torn tail — a crash in the middle of an append — rather than
+//! as corruption.
+//!
+//! Payload of a PUT or DELETE:
+//!   [Op (1 byte)] [Sequence (8 bytes, BE)] [Timestamp (8 bytes, BE)] [Key Len (4 bytes, BE)]
 //!   [Key Bytes] [Value Bytes]
+//!   (the value is whatever follows the key)
+//!
+//! Payload of a batch, which commits several records at once:
+//!   [Op (1 byte) = BATCH] [First Sequence (8 bytes, BE)] [Timestamp (8 bytes, BE)]
+//!   [Count (4 bytes, BE)] [Sub-record]*
+//! Sub-record:
+//!   [Op (1 byte)] [Key Len (4 bytes, BE)] [Value Len (4 bytes, BE)] [Key Bytes] [Value Bytes]
+//!
+//! The header CRC covers the 20 bytes before it; a record's CRC covers its
+//! payload, so a batch is recovered whole or not at all.  Recovery returns
+//! a batch's records as separate entries, numbered on from its first
+//! sequence.  The timestamp is the record's hybrid-logical-clock commit time.
 //!
-//! CRC32 is computed over: op || key_len || value_len || key_bytes || value_bytes
+//! Sequence numbers go up by one from each record to the next; recovery
+//! refuses a log where they do not.
+//!
+//! Format v2 is the same without sequence numbers.
+//! Format v1 logs have no file header, and each record is
+//!   [Op (1 byte)] [CRC32 (4 bytes, BE)] [Timestamp (8 bytes, BE)]
+//!   [Key Len (8 bytes, BE)] [Value Len (8 bytes, BE)] [Key Bytes] [Value Bytes]
+//! with the CRC over op || timestamp || key_len || value_len || key || value.
+//! Logs written before timestamps were recorded use legacy op bytes, whose
+//! entries have no timestamp field.  All of these are still read (legacy
+//! entries with timestamp 0), numbered by position from 1 since they carry
+//! no sequence of their own; opening one for appending first rewrites it in
+//! the current format.
+//!
+//! Key and value lengths are bounded by `RecordLimits`: appends over the
+//! limits are refused, and recovery checks every length against them and
+//! against the bytes left in the file before allocating, so a corrupt
+//! length is reported as corruption instead of exhausting memory.
+//!
+//! Recovery fails on the first record it cannot read, unless it is
+//! `RecoveryMode::Tolerant`: it then keeps the records before it and drops
+//! that record and everything after, reporting where the log was cut, how
+//! many bytes went and why (`Recovered::dropped`).  Records past a damaged
+//! one cannot be trusted to start where they seem to, so none are kept.  A
+//! torn tail is dropped the same way in either mode: the append it belongs
+//! to never completed, so no commit is lost with it.
 
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use crc32fast::Hasher as Crc32Hasher;
 use thiserror::Error;
-use tracing::{info, warn};
+
+use crate::code::ErrorCode;
+use crate::metrics;
+use crate::sync::{sync_parent, SyncMethod};
 
 // ---------------------------------------------------------------------------
 // Error type
@@ -33,14 +92,153 @@ pub enum WalError {
 
     #[error("Invalid UTF-8 in stored key: {0}")]
     InvalidKey(#[from] std::string::FromUtf8Error),
+
+    #[error("Corrupt WAL record at offset {offset}: {reason}")]
+    Corrupt { offset: u64, reason: String },
+
+    #[error("WAL record too large: {field} of {len} bytes exceeds the limit of {max}")]
+    RecordTooLarge { field: &'static str, len: u64, max: u64 },
+
+    #[error("Unsupported WAL format: version {version}, checksum algorithm {checksum}")]
+    UnsupportedFormat { version: u16, checksum: u8 },
+
+    #[error("WAL sequence out of order at offset {offset}: expected {expected}, got {got}")]
+    SequenceGap { offset: u64, expected: u64, got: u64 },
+
+    #[error("Unsupported WAL header flags: {0:#04x}")]
+    UnsupportedFlags(u8),
+
+    #[error("WAL ends with a torn record at offset {offset}: the last append did not complete")]
+    TornTail { offset: u64 },
+
+    #[error("WAL was truncated or replaced under a reader at offset {offset}; its records are gone")]
+    Reset { offset: u64 },
+}
+
+impl WalError {
+    /// The stable code of this error.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            WalError::Io(_) => ErrorCode::Internal,
+            WalError::RecordTooLarge { .. } => ErrorCode::InvalidArgument,
+            WalError::UnsupportedFormat { .. } | WalError::UnsupportedFlags(_) => ErrorCode::PreconditionFailed,
+            WalError::ChecksumMismatch { .. }
+            | WalError::UnknownOperation(_)
+            | WalError::InvalidKey(_)
+            | WalError::Corrupt { .. }
+            | WalError::SequenceGap { .. }
+            | WalError::TornTail { .. } => ErrorCode::Corruption,
+            WalError::Reset { .. } => ErrorCode::SequenceUnavailable,
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
-// Record type
+// Format
 // ---------------------------------------------------------------------------
 
+const MAGIC: &[u8; 8]      = b"LKVWALOG";
+const FORMAT_VERSION: u16  = 3;
+/// The last format whose records carry no sequence numbers.
+const UNSEQUENCED_VERSION: u16 = 2;
+const CHECKSUM_CRC32: u8   = 1;
+const FILE_HEADER_LEN: u64 = 8 + 2 + 1 + 1 + 8 + 4;
+
+/// Header flag: every record ends with a commit marker.
+const FLAG_COMMIT_MARKERS: u8 = 0x01;
+/// XORed with a record's CRC to give its commit marker ("LKOK").
+const COMMIT_MARKER_MASK: u32 = 0x4c4b_4f4b;
+const COMMIT_MARKER_LEN: u64  = 4;
+
+/// Payload length and CRC.
+const ENVELOPE_LEN: u64 = 4 + 4;
+/// Op, sequence, timestamp and key length (or, for a batch, record count).
+const PAYLOAD_FIXED_LEN: u64 = 1 + 8 + 8 + 4;
+/// The same in format v2, which has no sequence.
+const V2_PAYLOAD_FIXED_LEN: u64 = PAYLOAD_FIXED_LEN - 8;
+/// Op and the two lengths of a record within a batch.
+const SUB_RECORD_FIXED_LEN: u64 = 1 + 4 + 4;
+
 const OP_PUT: u8    = 0x01;
 const OP_DELETE: u8 = 0x02;
+const OP_BATCH: u8  = 0x03;
+
+const V1_OP_PUT: u8            = 0x01;
+const V1_OP_DELETE: u8         = 0x02;
+const V1_OP_PUT_STAMPED: u8    = 0x03;
+const V1_OP_DELETE_STAMPED: u8 = 0x04;
+
+/// Bytes of a stamped v1 record's header: op, CRC, timestamp and the two
+/// lengths.
+const V1_HEADER_LEN: u64 = 1 + 4 + 8 + 8 + 8;
+
+/// What the start of a log file says about its layout.
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    /// No records: a missing or empty file, or one whose header was torn
+    /// while it was being created.
+    Empty,
+    V1,
+    V2,
+    V3 { commit_markers: bool },
+}
+
+/// How a log is written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WalOptions {
+    pub limits: RecordLimits,
+    /// End every record with a commit marker.  Applies to logs created (or
+    /// truncated) with the option; an existing log keeps its own setting.
+    pub commit_markers: bool,
+    /// What recovery does with a damaged record.
+    pub recovery: RecoveryMode,
+}
+
+/// What recovery does with a record it cannot read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecoveryMode {
+    /// Fail with the error, unless it is a torn tail.
+    #[default]
+    Strict,
+    /// Drop the record and the rest of the log, and carry on without them.
+    Tolerant,
+}
+
+/// Largest key and value a record may hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLimits {
+    pub max_key_len:   u64,
+    pub max_value_len: u64,
+}
+
+impl Default for RecordLimits {
+    /// 16 MiB keys and 1 GiB values: far beyond what the server accepts,
+    /// but small enough that a corrupt length cannot exhaust memory.
+    fn default() -> Self {
+        Self { max_key_len: 16 << 20, max_value_len: 1 << 30 }
+    }
+}
+
+impl RecordLimits {
+    /// These limits, lowered to `max` where they are above it.
+    pub(crate) fn capped(self, max: u64) -> Self {
+        Self { max_key_len: self.max_key_len.min(max), max_value_len: self.max_value_len.min(max) }
+    }
+
+    fn check(&self, key_len: u64, value_len: u64) -> Result<(), (&'static str, u64, u64)> {
+        if key_len > self.max_key_len {
+            return Err(("key", key_len, self.max_key_len));
+        }
+        if value_len > self.max_value_len {
+            return Err(("value", value_len, self.max_value_len));
+        }
+        Ok(())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Record type
+// ---------------------------------------------------------------------------
 
 /// A single logical entry stored in the WAL.
 #[derive(Debug, Clone)]
@@ -49,6 +247,41 @@ pub enum WalRecord {
     Delete { key: String },
 }
 
+/// What recovery read from a log.
+#[derive(Debug)]
+pub struct Recovered {
+    pub entries: Vec<WalEntry>,
+    /// Whether the log stores sequences (format v3); an older log's entries
+    /// are numbered by position, from 1.
+    pub sequenced: bool,
+    /// The end of the log recovery dropped: a torn tail, or in a tolerant
+    /// recovery, a damaged record and what follows it.
+    pub dropped: Option<Dropped>,
+}
+
+/// The end of a log, from a record recovery could not read on.
+#[derive(Debug)]
+pub struct Dropped {
+    /// Where that record starts; the log is intact up to here.
+    pub offset: u64,
+    /// Bytes from there to the end of the file.
+    pub bytes: u64,
+    /// Why the record could not be read.
+    pub error: WalError,
+}
+
+/// A record read back from the log, with its sequence number and commit
+/// timestamp.
+#[derive(Debug, Clone)]
+pub struct WalEntry {
+    pub record: WalRecord,
+    /// Position in the history of the store; for logs older than format v3,
+    /// the position in the file, from 1.
+    pub sequence: u64,
+    /// HLC commit timestamp; 0 for entries written in the legacy format.
+    pub timestamp: u64,
+}
+
 // ---------------------------------------------------------------------------
 // WriteAheadLog
 // ---------------------------------------------------------------------------
@@ -58,27 +291,79 @@ pub enum WalRecord {
 pub struct WriteAheadLog {
     writer: BufWriter<File>,
     path: PathBuf,
+    options: WalOptions,
+    /// Whether the current file has commit markers.
+    commit_markers: bool,
+    sync: SyncMethod,
+    /// How many times the log has been truncated since it was opened.
+    generation: u64,
 }
 
 impl WriteAheadLog {
     /// Open (or create) the WAL at `path` in append mode.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WalError> {
+        Self::open_with(path, WalOptions::default(), 0, SyncMethod::default())
+    }
+
+    /// Like `open`, writing as `options` say and syncing with `sync`.
+    /// `base` is the sequence the log continues from (that of the checkpoint
+    /// it follows): a log in an older format is rewritten with its records
+    /// numbered after it.
+    pub fn open_with<P: AsRef<Path>>(
+        path: P,
+        options: WalOptions,
+        base: u64,
+        sync: SyncMethod,
+    ) -> Result<Self, WalError> {
         let path = path.as_ref().to_path_buf();
-        let file = OpenOptions::new()
+
+        let format = match File::open(&path) {
+            Ok(file) => {
+                let len = file.metadata()?.len();
+                read_format(&mut BufReader::new(file), len)?.0
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Format::Empty,
+            Err(e) => return Err(WalError::Io(e)),
+        };
+        if let Format::V1 | Format::V2 = format {
+            upgrade(&path, options, base, sync)?;
+        }
+        let commit_markers = match format {
+            Format::V3 { commit_markers } => commit_markers,
+            _ => options.commit_markers,
+        };
+
+        let file = sync
+            .options()
             .create(true)
             .append(true)
             .open(&path)?;
 
         info!(path = %path.display(), "WAL file opened in append mode");
 
-        Ok(Self {
+        let mut wal = Self {
             writer: BufWriter::new(file),
             path,
-        })
+            options,
+            commit_markers,
+            sync,
+            generation: 0,
+        };
+        if let Format::Empty = format {
+            // Drop any torn header before starting afresh, and make sure
+            // the new file itself survives a crash.
+            wal.writer.get_ref().set_len(0)?;
+            wal.write_header()?;
+            wal.sync.sync(wal.writer.get_ref())?;
+            sync_parent(&wal.path)?;
+        }
+        Ok(wal)
     }
 
-    /// Append a record to the WAL and fsync.
-    pub fn append(&mut self, record: &WalRecord) -> Result<(), WalError> {
+    /// Append a record with `sequence`, committed at `timestamp`, to the WAL
+    /// and fsync.  The sequence must follow the previous record's.
+    pub fn append(&mut self, record: &WalRecord, sequence: u64, timestamp: u64) -> Result<(), WalError> {
+        let started = Instant::now();
         let (op, key, value): (u8, &str, &[u8]) = match record {
             WalRecord::Put { key, value }  => (OP_PUT,    key.as_str(), value.as_slice()),
             WalRecord::Delete { key }      => (OP_DELETE, key.as_str(), &[]),
@@ -88,102 +373,213 @@ impl WriteAheadLog {
         let key_len   = key_bytes.len() as u64;
         let value_len = value.len()     as u64;
 
-        // Compute CRC32 over: op || key_len (BE) || value_len (BE) || key_bytes || value
+        self.check(key_len, value_len)?;
+        let payload_len = check_payload_len(PAYLOAD_FIXED_LEN + key_len + value_len)?;
+
+        // Compute CRC32 over the payload: op || sequence || timestamp || key_len (all BE) || key_bytes || value
         let checksum = {
             let mut h = Crc32Hasher::new();
             h.update(&[op]);
-            h.update(&key_len.to_be_bytes());
-            h.update(&value_len.to_be_bytes());
+            h.update(&sequence.to_be_bytes());
+            h.update(&timestamp.to_be_bytes());
+            h.update(&(key_len as u32).to_be_bytes());
             h.update(key_bytes);
             h.update(value);
             h.finalize()
         };
 
-        self.writer.write_u8(op)?;
+        self.writer.write_u32::<BigEndian>(payload_len)?;
         self.writer.write_u32::<BigEndian>(checksum)?;
-        self.writer.write_u64::<BigEndian>(key_len)?;
-        self.writer.write_u64::<BigEndian>(value_len)?;
+        self.writer.write_u8(op)?;
+        self.writer.write_u64::<BigEndian>(sequence)?;
+        self.writer.write_u64::<BigEndian>(timestamp)?;
+        self.writer.write_u32::<BigEndian>(key_len as u32)?;
         self.writer.write_all(key_bytes)?;
         self.writer.write_all(value)?;
+        self.write_commit_marker(checksum)?;
         // Flush to kernel buffer; the OS will durably persist this.
         self.writer.flush()?;
 
+        metrics::wal_append(self.record_len(u64::from(payload_len)), started.elapsed());
+        Ok(())
+    }
+
+    /// Append `records`, all committed at `timestamp`, as a single entry and
+    /// fsync: recovery returns either every one of them or none.  They take
+    /// the sequences from `first_sequence` on.  An empty batch writes nothing.
+    pub fn append_batch(&mut self, records: &[WalRecord], first_sequence: u64, timestamp: u64) -> Result<(), WalError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+
+        let mut payload = Vec::new();
+        payload.write_u8(OP_BATCH)?;
+        payload.write_u64::<BigEndian>(first_sequence)?;
+        payload.write_u64::<BigEndian>(timestamp)?;
+        // A batch too long to count overflows the payload length below.
+        payload.write_u32::<BigEndian>(records.len() as u32)?;
+        for record in records {
+            let (op, key, value): (u8, &str, &[u8]) = match record {
+                WalRecord::Put { key, value }  => (OP_PUT,    key.as_str(), value.as_slice()),
+                WalRecord::Delete { key }      => (OP_DELETE, key.as_str(), &[]),
+            };
+            self.check(key.len() as u64, value.len() as u64)?;
+            check_payload_len(payload.len() as u64 + SUB_RECORD_FIXED_LEN + key.len() as u64 + value.len() as u64)?;
+
+            payload.write_u8(op)?;
+            payload.write_u32::<BigEndian>(key.len() as u32)?;
+            payload.write_u32::<BigEndian>(value.len() as u32)?;
+            payload.extend_from_slice(key.as_bytes());
+            payload.extend_from_slice(value);
+        }
+
+        let checksum = crc32fast::hash(&payload);
+        self.writer.write_u32::<BigEndian>(payload.len() as u32)?;
+        self.writer.write_u32::<BigEndian>(checksum)?;
+        self.writer.write_all(&payload)?;
+        self.write_commit_marker(checksum)?;
+        self.writer.flush()?;
+
+        metrics::wal_append(self.record_len(payload.len() as u64), started.elapsed());
+        Ok(())
+    }
+
+    /// Bytes on disk of a record with a `payload_len`-byte payload.
+    fn record_len(&self, payload_len: u64) -> u64 {
+        ENVELOPE_LEN + payload_len + if self.commit_markers { COMMIT_MARKER_LEN } else { 0 }
+    }
+
+    fn write_commit_marker(&mut self, checksum: u32) -> Result<(), WalError> {
+        if self.commit_markers {
+            self.writer.write_u32::<BigEndian>(checksum ^ COMMIT_MARKER_MASK)?;
+        }
+        Ok(())
+    }
+
+    /// Refuse a record recovery would reject.
+    fn check(&self, key_len: u64, value_len: u64) -> Result<(), WalError> {
+        self.options
+            .limits
+            .check(key_len, value_len)
+            .map_err(|(field, len, max)| WalError::RecordTooLarge { field, len, max })
+    }
+
+    /// Make every record appended so far durable.
+    pub fn sync(&mut self) -> Result<(), WalError> {
+        self.writer.flush()?;
+        self.sync.sync(self.writer.get_ref())?;
+        Ok(())
+    }
+
+    /// Flush every record appended so far to the OS, and return a handle to
+    /// the file for syncing them with `SyncMethod::sync` without holding the
+    /// log, so appends can go on meanwhile.
+    pub fn sync_handle(&mut self) -> Result<File, WalError> {
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().try_clone()?)
+    }
+
+    /// Discard every record in the log; subsequent appends start right
+    /// after a fresh file header.
+    pub fn truncate(&mut self) -> Result<(), WalError> {
+        self.writer.flush()?;
+        self.writer.get_ref().set_len(0)?;
+        self.commit_markers = self.options.commit_markers;
+        self.write_header()?;
+        self.sync.sync(self.writer.get_ref())?;
+        self.generation += 1;
+
+        info!(path = %self.path.display(), "WAL truncated");
+        Ok(())
+    }
+
+    /// Bumped by every `truncate`, so an offset read from the log is only
+    /// valid while this is unchanged.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn write_header(&mut self) -> Result<(), WalError> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        let mut header = Vec::with_capacity(FILE_HEADER_LEN as usize);
+        header.extend_from_slice(MAGIC);
+        header.write_u16::<BigEndian>(FORMAT_VERSION)?;
+        header.write_u8(CHECKSUM_CRC32)?;
+        header.write_u8(if self.commit_markers { FLAG_COMMIT_MARKERS } else { 0 })?;
+        header.write_u64::<BigEndian>(created)?;
+        let checksum = crc32fast::hash(&header);
+        header.write_u32::<BigEndian>(checksum)?;
+
+        self.writer.write_all(&header)?;
+        self.writer.flush()?;
         Ok(())
     }
 
     /// Read and validate every record from an existing WAL file.
     ///
     /// Returns an empty `Vec` if the file does not exist yet.
-    /// Stops and returns an error on the first corrupted record.
-    pub fn recover<P: AsRef<Path>>(path: P) -> Result<Vec<WalRecord>, WalError> {
+    /// Stops and returns an error on the first corrupted record; a torn tail
+    /// ends the log instead (see `recover_from`).
+    pub fn recover<P: AsRef<Path>>(path: P) -> Result<Vec<WalEntry>, WalError> {
+        Self::recover_with(path, RecordLimits::default())
+    }
+
+    /// Like `recover`, treating records over `limits` as corrupt.
+    pub fn recover_with<P: AsRef<Path>>(path: P, limits: RecordLimits) -> Result<Vec<WalEntry>, WalError> {
+        Ok(Self::recover_from(path, limits, RecoveryMode::Strict)?.entries)
+    }
+
+    /// Like `recover_with`, handling a damaged record as `mode` says.  The
+    /// file is not changed: once recovery has dropped the end of a log,
+    /// `truncate_at` cuts it there before it is appended to.
+    pub fn recover_from<P: AsRef<Path>>(
+        path: P,
+        limits: RecordLimits,
+        mode: RecoveryMode,
+    ) -> Result<Recovered, WalError> {
         let path = path.as_ref();
 
-        let file = match File::open(path) {
-            Ok(f)  => f,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+        let mut reader = match WalReader::open_with(path, limits) {
+            Ok(reader) => reader,
+            Err(WalError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
                 info!(path = %path.display(), "No WAL found; starting fresh");
-                return Ok(Vec::new());
+                return Ok(Recovered { entries: Vec::new(), sequenced: true, dropped: None });
             }
-            Err(e) => return Err(WalError::Io(e)),
+            Err(e) => return Err(e),
         };
+        let sequenced = reader.info().sequenced;
 
-        let mut reader  = BufReader::new(file);
-        let mut records = Vec::new();
-
-        loop {
-            // Read op byte — EOF here is normal (clean shutdown).
-            let op = match reader.read_u8() {
-                Ok(b)  => b,
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(WalError::Io(e)),
+        let mut records: Vec<WalEntry> = Vec::new();
+        let dropped = loop {
+            let offset = reader.offset();
+            let error = match reader.next_record() {
+                Ok(None) => break None,
+                Ok(Some(raw)) => match check_record(&raw, sequenced, records.last()) {
+                    Ok(()) => {
+                        records.extend(raw.entries);
+                        continue;
+                    }
+                    Err(e) => e,
+                },
+                Err(e) => e,
             };
-
-            if op != OP_PUT && op != OP_DELETE {
-                return Err(WalError::UnknownOperation(op));
-            }
-
-            let stored_checksum = reader.read_u32::<BigEndian>()?;
-            let key_len         = reader.read_u64::<BigEndian>()?;
-            let value_len       = reader.read_u64::<BigEndian>()?;
-
-            let mut key_bytes = vec![0u8; key_len as usize];
-            reader.read_exact(&mut key_bytes)?;
-
-            let mut value = vec![0u8; value_len as usize];
-            reader.read_exact(&mut value)?;
-
-            // Verify integrity
-            let computed = {
-                let mut h = Crc32Hasher::new();
-                h.update(&[op]);
-                h.update(&key_len.to_be_bytes());
-                h.update(&value_len.to_be_bytes());
-                h.update(&key_bytes);
-                h.update(&value);
-                h.finalize()
+            // Only damage to the records is dropped; an unreadable file is
+            // not.
+            let dropped = match mode {
+                RecoveryMode::Strict => matches!(error, WalError::TornTail { .. }),
+                RecoveryMode::Tolerant => error.code() == ErrorCode::Corruption,
             };
-
-            if computed != stored_checksum {
-                warn!(
-                    expected = stored_checksum,
-                    actual   = computed,
-                    "WAL checksum mismatch — truncated or corrupt entry"
-                );
-                return Err(WalError::ChecksumMismatch {
-                    expected: stored_checksum,
-                    actual:   computed,
-                });
+            if !dropped {
+                return Err(error);
             }
-
-            let key = String::from_utf8(key_bytes)?;
-
-            let record = match op {
-                OP_PUT    => WalRecord::Put { key, value },
-                OP_DELETE => WalRecord::Delete { key },
-                _         => unreachable!("op validated above"),
-            };
-
-            records.push(record);
+            break Some(Dropped { offset, bytes: reader.file_len() - offset, error });
+        };
+        if !sequenced {
+            records = number(records);
         }
 
         info!(
@@ -192,7 +588,19 @@ impl WriteAheadLog {
             "WAL recovery complete"
         );
 
-        Ok(records)
+        Ok(Recovered { entries: records, sequenced, dropped })
+    }
+
+    /// Cut the log at `path` down to its first `offset` bytes, where a
+    /// record starts, and sync it with `sync`.
+    pub fn truncate_at<P: AsRef<Path>>(path: P, offset: u64, sync: SyncMethod) -> Result<(), WalError> {
+        let path = path.as_ref();
+        let file = fs::OpenOptions::new().write(true).open(path)?;
+        file.set_len(offset)?;
+        sync.sync(&file)?;
+
+        info!(path = %path.display(), offset, "WAL truncated at a record");
+        Ok(())
     }
 
     /// Return the path this WAL is stored at.
@@ -200,3 +608,988 @@ impl WriteAheadLog {
         &self.path
     }
 }
+
+// ---------------------------------------------------------------------------
+// WalReader
+// ---------------------------------------------------------------------------
+
+/// What a log's file header says about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalInfo {
+    /// On-disk format version; 1 for a log without a file header.
+    pub version: u16,
+    /// When the log was created (or last truncated), if it has a header.
+    pub created_unix_ms: Option<u64>,
+    /// Whether its records store their sequence numbers (format v3).
+    pub sequenced: bool,
+    pub commit_markers: bool,
+}
+
+/// Whether a record's stored CRC matched its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    Ok,
+    Mismatch { expected: u32, actual: u32 },
+}
+
+/// One record as it was found on disk.
+#[derive(Debug, Clone)]
+pub struct RawRecord {
+    /// Offset of the record in the file.
+    pub offset: u64,
+    /// Bytes the record takes up, envelope and commit marker included.
+    pub len: u64,
+    pub checksum: Checksum,
+    /// What it holds — one entry, or several for a batch, with sequences of
+    /// 0 in logs that do not store them.  Empty if the checksum failed.
+    pub entries: Vec<WalEntry>,
+}
+
+/// Reads a log record by record, for recovery and inspection tools, and for
+/// consumers following a live log (`follow`) without opening an engine.
+///
+/// A record whose checksum fails is returned (without entries) rather than
+/// an error, since its length is still known and the records after it can
+/// be read; damage that leaves the layout unknown ends the read with an
+/// error.
+///
+/// A following reader sees the file as it was when opened or last
+/// `refresh`ed.  A record still being appended at the end of that is not
+/// returned, nor reported as torn: the reader stops before it and reads it
+/// whole after a later `refresh`.  An engine empties its log when it
+/// compacts, which `refresh` reports as `WalError::Reset`; the consumer then
+/// has to start over from the checkpoint.
+#[derive(Debug)]
+pub struct WalReader {
+    path: PathBuf,
+    reader: BufReader<File>,
+    file_len: u64,
+    offset: u64,
+    format: Format,
+    info: WalInfo,
+    limits: RecordLimits,
+    /// Whether the last record may still be being written.
+    live: bool,
+}
+
+impl WalReader {
+    /// Open the log at `path` and read its file header.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WalError> {
+        Self::open_with(path, RecordLimits::default())
+    }
+
+    /// Like `open`, treating records over `limits` as corrupt.
+    pub fn open_with<P: AsRef<Path>>(path: P, limits: RecordLimits) -> Result<Self, WalError> {
+        let path       = path.as_ref().to_owned();
+        let file       = File::open(&path)?;
+        let file_len   = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let (format, created_unix_ms) = read_format(&mut reader, file_len)?;
+        let offset = reader.stream_position()?;
+        let info   = format_info(format, created_unix_ms);
+        Ok(Self { path, reader, file_len, offset, format, info, limits, live: false })
+    }
+
+    /// Open the log at `path`, which an engine may be appending to, to read
+    /// from the record at `offset` on (0: the first record).  `offset` must
+    /// be where a record starts, such as the end of one read before
+    /// (`RawRecord::offset` plus `RawRecord::len`).
+    pub fn follow<P: AsRef<Path>>(path: P, offset: u64) -> Result<Self, WalError> {
+        Self::follow_with(path, offset, RecordLimits::default())
+    }
+
+    /// Like `follow`, treating records over `limits` as corrupt.
+    pub fn follow_with<P: AsRef<Path>>(path: P, offset: u64, limits: RecordLimits) -> Result<Self, WalError> {
+        let mut reader = Self::open_with(path, limits)?;
+        reader.live = true;
+        if offset > reader.file_len {
+            return Err(WalError::Reset { offset });
+        }
+        if offset > reader.offset {
+            reader.reader.seek(SeekFrom::Start(offset))?;
+            reader.offset = offset;
+        }
+        Ok(reader)
+    }
+
+    /// See the records appended since the reader was opened or last
+    /// refreshed.  Fails with `WalError::Reset` if the log was emptied
+    /// (compacted) or replaced in the meantime.
+    pub fn refresh(&mut self) -> Result<(), WalError> {
+        let file       = File::open(&self.path)?;
+        let file_len   = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let (format, created_unix_ms) = read_format(&mut reader, file_len)?;
+
+        // A log opened before its header was written has nothing to lose.
+        let was_empty = matches!(self.format, Format::Empty);
+        if file_len < self.offset || (!was_empty && created_unix_ms != self.info.created_unix_ms) {
+            return Err(WalError::Reset { offset: self.offset });
+        }
+        if was_empty {
+            self.offset = self.offset.max(reader.stream_position()?);
+            self.format = format;
+            self.info   = format_info(format, created_unix_ms);
+        }
+        reader.seek(SeekFrom::Start(self.offset))?;
+        self.reader   = reader;
+        self.file_len = file_len;
+        Ok(())
+    }
+
+    pub fn info(&self) -> WalInfo {
+        self.info
+    }
+
+    /// Length of the file in bytes.
+    pub fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    /// Offset of the next record in the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The next record, or `None` at the end of the log (for a following
+    /// reader, also before a record still being written).
+    pub fn next_record(&mut self) -> Result<Option<RawRecord>, WalError> {
+        if self.offset >= self.file_len {
+            return Ok(None);
+        }
+        let record = match self.format {
+            Format::Empty => return Ok(None),
+            Format::V1    => self.next_v1(),
+            Format::V2    => self.next_framed(false, false),
+            Format::V3 { commit_markers } => self.next_framed(true, commit_markers),
+        };
+        let record = match record {
+            Ok(record) if self.live && record.checksum != Checksum::Ok && record.len == self.file_len - self.offset => {
+                None
+            }
+            Ok(record) => Some(record),
+            Err(WalError::TornTail { .. }) if self.live => None,
+            Err(e) => return Err(e),
+        };
+        let Some(record) = record else {
+            // Still being appended: read it again after a refresh.
+            self.reader.seek(SeekFrom::Start(self.offset))?;
+            return Ok(None);
+        };
+        self.offset += record.len;
+        Ok(Some(record))
+    }
+
+    /// Read a record of a v2 or v3 log (`sequenced`), which may have
+    /// `commit_markers`.
+    fn next_framed(&mut self, sequenced: bool, commit_markers: bool) -> Result<RawRecord, WalError> {
+        let (offset, file_len) = (self.offset, self.file_len);
+        let fixed_len  = if sequenced { PAYLOAD_FIXED_LEN } else { V2_PAYLOAD_FIXED_LEN };
+        let marker_len = if commit_markers { COMMIT_MARKER_LEN } else { 0 };
+
+        if file_len - offset < ENVELOPE_LEN {
+            return Err(torn_tail(offset));
+        }
+        let payload_len     = u64::from(self.reader.read_u32::<BigEndian>()?);
+        let stored_checksum = self.reader.read_u32::<BigEndian>()?;
+
+        // Check the length before allocating anything for it.
+        let record_len = ENVELOPE_LEN + payload_len + marker_len;
+        let at_tail    = record_len == file_len - offset;
+        if payload_len < fixed_len {
+            // A crash can leave the file extended with zeros in place of
+            // the records last written.
+            if stored_checksum == 0 && payload_len == 0 && zeros_to_end(&mut self.reader)? {
+                return Err(torn_tail(offset));
+            }
+            return Err(corrupt(offset, format!("payload length {payload_len} is too short")));
+        }
+        if record_len > file_len - offset {
+            return Err(torn_tail(offset));
+        }
+
+        let mut payload = vec![0u8; payload_len as usize];
+        self.reader.read_exact(&mut payload)?;
+
+        if commit_markers && self.reader.read_u32::<BigEndian>()? != stored_checksum ^ COMMIT_MARKER_MASK {
+            if at_tail {
+                return Err(torn_tail(offset));
+            }
+            return Err(corrupt(offset, "commit marker mismatch".to_owned()));
+        }
+
+        // Verify integrity
+        let computed = crc32fast::hash(&payload);
+        let mut record = RawRecord { offset, len: record_len, checksum: Checksum::Ok, entries: Vec::new() };
+        if computed != stored_checksum {
+            record.checksum = Checksum::Mismatch { expected: stored_checksum, actual: computed };
+        } else {
+            decode_payload(&payload, offset, self.limits, sequenced, &mut record.entries)?;
+        }
+        Ok(record)
+    }
+
+    fn next_v1(&mut self) -> Result<RawRecord, WalError> {
+        let (offset, file_len) = (self.offset, self.file_len);
+        let reader = &mut self.reader;
+
+        let op = reader.read_u8()?;
+        let stamped = match op {
+            V1_OP_PUT | V1_OP_DELETE => false,
+            V1_OP_PUT_STAMPED | V1_OP_DELETE_STAMPED => true,
+            _ => return Err(WalError::UnknownOperation(op)),
+        };
+        let header_len = if stamped { V1_HEADER_LEN } else { V1_HEADER_LEN - 8 };
+        if file_len - offset < header_len {
+            return Err(corrupt(offset, "header extends past the end of the file".to_owned()));
+        }
+
+        let stored_checksum = reader.read_u32::<BigEndian>()?;
+        let timestamp       = if stamped { reader.read_u64::<BigEndian>()? } else { 0 };
+        let key_len         = reader.read_u64::<BigEndian>()?;
+        let value_len       = reader.read_u64::<BigEndian>()?;
+
+        // Check the lengths before allocating anything for them.
+        if let Err((field, len, max)) = self.limits.check(key_len, value_len) {
+            return Err(corrupt(offset, format!("{field} length {len} exceeds the limit of {max}")));
+        }
+        let remaining = file_len - offset - header_len;
+        if key_len + value_len > remaining {
+            return Err(corrupt(
+                offset,
+                format!("{key_len}-byte key and {value_len}-byte value extend past the end of the file"),
+            ));
+        }
+
+        let mut key_bytes = vec![0u8; key_len as usize];
+        reader.read_exact(&mut key_bytes)?;
+
+        let mut value = vec![0u8; value_len as usize];
+        reader.read_exact(&mut value)?;
+
+        // Verify integrity
+        let computed = {
+            let mut h = Crc32Hasher::new();
+            h.update(&[op]);
+            if stamped {
+                h.update(&timestamp.to_be_bytes());
+            }
+            h.update(&key_len.to_be_bytes());
+            h.update(&value_len.to_be_bytes());
+            h.update(&key_bytes);
+            h.update(&value);
+            h.finalize()
+        };
+
+        let len = header_len + key_len + value_len;
+        if computed != stored_checksum {
+            let checksum = Checksum::Mismatch { expected: stored_checksum, actual: computed };
+            return Ok(RawRecord { offset, len, checksum, entries: Vec::new() });
+        }
+
+        let key = String::from_utf8(key_bytes)?;
+
+        let record = match op {
+            V1_OP_PUT | V1_OP_PUT_STAMPED       => WalRecord::Put { key, value },
+            V1_OP_DELETE | V1_OP_DELETE_STAMPED => WalRecord::Delete { key },
+            _                                   => unreachable!("op validated above"),
+        };
+
+        let entries = vec![WalEntry { record, sequence: 0, timestamp }];
+        Ok(RawRecord { offset, len, checksum: Checksum::Ok, entries })
+    }
+}
+
+/// Identify the layout of a log of `file_len` bytes, and when it was
+/// created, leaving `reader` at its first record.
+fn read_format(reader: &mut BufReader<File>, file_len: u64) -> Result<(Format, Option<u64>), WalError> {
+    let mut header = vec![0u8; file_len.min(FILE_HEADER_LEN) as usize];
+    reader.read_exact(&mut header)?;
+
+    let magic_len = header.len().min(MAGIC.len());
+    if header[..magic_len] != MAGIC[..magic_len] {
+        // v1 records start with an op byte, never with the magic.
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok((if file_len == 0 { Format::Empty } else { Format::V1 }, None));
+    }
+    if file_len < FILE_HEADER_LEN {
+        return Ok((Format::Empty, None));
+    }
+
+    let (fields, stored) = header.split_at(FILE_HEADER_LEN as usize - 4);
+    let stored   = u32::from_be_bytes(stored.try_into().expect("4-byte checksum"));
+    let computed = crc32fast::hash(fields);
+    if computed != stored {
+        metrics::checksum_failure();
+        return Err(corrupt(0, format!("file header checksum mismatch: expected {stored:#010x}, got {computed:#010x}")));
+    }
+    let version  = u16::from_be_bytes([fields[8], fields[9]]);
+    let checksum = fields[10];
+    let flags    = fields[11];
+    let created  = u64::from_be_bytes(fields[12..20].try_into().expect("8-byte creation time"));
+    let format = match (version, checksum) {
+        (FORMAT_VERSION, CHECKSUM_CRC32) if flags & !FLAG_COMMIT_MARKERS == 0 => {
+            Format::V3 { commit_markers: flags & FLAG_COMMIT_MARKERS != 0 }
+        }
+        (UNSEQUENCED_VERSION, CHECKSUM_CRC32) if flags == 0 => Format::V2,
+        (FORMAT_VERSION | UNSEQUENCED_VERSION, CHECKSUM_CRC32) => return Err(WalError::UnsupportedFlags(flags)),
+        _ => return Err(WalError::UnsupportedFormat { version, checksum }),
+    };
+    Ok((format, Some(created)))
+}
+
+fn format_info(format: Format, created_unix_ms: Option<u64>) -> WalInfo {
+    WalInfo {
+        version: match format {
+            Format::V1 => 1,
+            Format::V2 => UNSEQUENCED_VERSION,
+            Format::Empty | Format::V3 { .. } => FORMAT_VERSION,
+        },
+        created_unix_ms,
+        sequenced:      matches!(format, Format::Empty | Format::V3 { .. }),
+        commit_markers: matches!(format, Format::V3 { commit_markers: true }),
+    }
+}
+
+/// Refuse `raw` if its checksum failed, or (in a `sequenced` log) it does
+/// not follow `previous`.
+fn check_record(raw: &RawRecord, sequenced: bool, previous: Option<&WalEntry>) -> Result<(), WalError> {
+    if let Checksum::Mismatch { expected, actual } = raw.checksum {
+        warn!(
+            expected,
+            actual,
+            "WAL checksum mismatch — truncated or corrupt entry"
+        );
+        metrics::checksum_failure();
+        return Err(WalError::ChecksumMismatch { expected, actual });
+    }
+
+    // A batch's records are numbered consecutively, so only its first
+    // needs checking against what came before.
+    if let (true, Some(previous), Some(entry)) = (sequenced, previous, raw.entries.first()) {
+        let (expected, got) = (previous.sequence + 1, entry.sequence);
+        if got != expected {
+            warn!(offset = raw.offset, expected, got, "WAL sequence out of order");
+            return Err(WalError::SequenceGap { offset: raw.offset, expected, got });
+        }
+    }
+    Ok(())
+}
+
+/// Number entries that carry no sequence by their position, from 1.
+fn number(mut records: Vec<WalEntry>) -> Vec<WalEntry> {
+    for (i, entry) in records.iter_mut().enumerate() {
+        entry.sequence = i as u64 + 1;
+    }
+    records
+}
+
+/// Decode a payload whose checksum has been verified, adding its records
+/// to `records`.  Only `sequenced` (v3) payloads hold a sequence.
+fn decode_payload(
+    payload: &[u8],
+    offset: u64,
+    limits: RecordLimits,
+    sequenced: bool,
+    records: &mut Vec<WalEntry>,
+) -> Result<(), WalError> {
+    let mut cursor = Cursor::new(payload);
+    let remaining  = |cursor: &Cursor<&[u8]>| payload.len() as u64 - cursor.position();
+
+    let op        = cursor.read_u8()?;
+    let sequence  = if sequenced { cursor.read_u64::<BigEndian>()? } else { 0 };
+    let timestamp = cursor.read_u64::<BigEndian>()?;
+
+    match op {
+        OP_PUT | OP_DELETE => {
+            let key_len = u64::from(cursor.read_u32::<BigEndian>()?);
+            let Some(value_len) = remaining(&cursor).checked_sub(key_len) else {
+                return Err(corrupt(offset, format!("key length {key_len} exceeds the payload")));
+            };
+            let record = read_record(&mut cursor, op, key_len, value_len, offset, limits)?;
+            records.push(WalEntry { record, sequence, timestamp });
+        }
+        OP_BATCH => {
+            let count = cursor.read_u32::<BigEndian>()?;
+            for i in 0..count {
+                if remaining(&cursor) < SUB_RECORD_FIXED_LEN {
+                    return Err(corrupt(offset, format!("batch ends before record {i} of {count}")));
+                }
+                let op        = cursor.read_u8()?;
+                let key_len   = u64::from(cursor.read_u32::<BigEndian>()?);
+                let value_len = u64::from(cursor.read_u32::<BigEndian>()?);
+                if key_len + value_len > remaining(&cursor) {
+                    return Err(corrupt(offset, format!("record {i} of {count} extends past the end of the batch")));
+                }
+                let record   = read_record(&mut cursor, op, key_len, value_len, offset, limits)?;
+                let sequence = if sequenced { sequence + u64::from(i) } else { 0 };
+                records.push(WalEntry { record, sequence, timestamp });
+            }
+            let trailing = remaining(&cursor);
+            if trailing > 0 {
+                return Err(corrupt(offset, format!("{trailing} bytes follow the last record of the batch")));
+            }
+        }
+        _ => return Err(WalError::UnknownOperation(op)),
+    }
+    Ok(())
+}
+
+/// Read the key and value of a PUT or DELETE, whose lengths are known to
+/// fit in what is left of `cursor`.
+fn read_record(
+    cursor: &mut Cursor<&[u8]>,
+    op: u8,
+    key_len: u64,
+    value_len: u64,
+    offset: u64,
+    limits: RecordLimits,
+) -> Result<WalRecord, WalError> {
+    if let Err((field, len, max)) = limits.check(key_len, value_len) {
+        return Err(corrupt(offset, format!("{field} length {len} exceeds the limit of {max}")));
+    }
+
+    let mut key_bytes = vec![0u8; key_len as usize];
+    cursor.read_exact(&mut key_bytes)?;
+
+    let mut value = vec![0u8; value_len as usize];
+    cursor.read_exact(&mut value)?;
+
+    let key = String::from_utf8(key_bytes)?;
+
+    match op {
+        OP_PUT    => Ok(WalRecord::Put { key, value }),
+        OP_DELETE => Ok(WalRecord::Delete { key }),
+        _         => Err(WalError::UnknownOperation(op)),
+    }
+}
+
+/// Rewrite the older-format log at `path` in the current format, numbering
+/// its records after `base`.  The new log is written beside it, fsynced and
+/// renamed over it, so a crash leaves one or the other.
+fn upgrade(path: &Path, options: WalOptions, base: u64, sync: SyncMethod) -> Result<(), WalError> {
+    let entries = WriteAheadLog::recover_with(path, options.limits)?;
+    let tmp     = path.with_extension("upgrade");
+    let _ = fs::remove_file(&tmp);
+
+    {
+        let mut wal = WriteAheadLog::open_with(&tmp, options, base, sync)?;
+        for entry in &entries {
+            wal.append(&entry.record, base + entry.sequence, entry.timestamp)?;
+        }
+        sync.sync(wal.writer.get_ref())?;
+    }
+    fs::rename(&tmp, path)?;
+    sync_parent(path)?;
+
+    info!(
+        path    = %path.display(),
+        records = entries.len(),
+        version = FORMAT_VERSION,
+        "Upgraded WAL to the current format"
+    );
+    Ok(())
+}
+
+/// `len`, as the `u32` a payload length is stored in.
+fn check_payload_len(len: u64) -> Result<u32, WalError> {
+    u32::try_from(len).map_err(|_| WalError::RecordTooLarge { field: "record", len, max: u64::from(u32::MAX) })
+}
+
+/// Whether everything left in `reader` is zero.
+fn zeros_to_end(reader: &mut impl Read) -> std::io::Result<bool> {
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(true),
+            n if buf[..n].iter().all(|&b| b == 0) => continue,
+            _ => return Ok(false),
+        }
+    }
+}
+
+fn torn_tail(offset: u64) -> WalError {
+    warn!(offset, "WAL ends with a torn record");
+    WalError::TornTail { offset }
+}
+
+fn corrupt(offset: u64, reason: String) -> WalError {
+    warn!(offset, reason = %reason, "Corrupt WAL record");
+    WalError::Corrupt { offset, reason }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(key: &str, value: &[u8]) -> WalRecord {
+        WalRecord::Put { key: key.to_owned(), value: value.to_vec() }
+    }
+
+    /// A log at `path` holding a put of each of `keys`, sequenced from 1.
+    fn write_log(path: &Path, keys: &[&str]) {
+        let mut wal = WriteAheadLog::open(path).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            wal.append(&put(key, b"value"), i as u64 + 1, 0).unwrap();
+        }
+        wal.sync().unwrap();
+    }
+
+    /// Where each record of the log at `path` starts.
+    fn offsets(path: &Path) -> Vec<u64> {
+        let mut reader = WalReader::open(path).unwrap();
+        std::iter::from_fn(|| reader.next_record().unwrap()).map(|raw| raw.offset).collect()
+    }
+
+    fn keys(entries: &[WalEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| match &entry.record {
+                WalRecord::Put { key, .. } | WalRecord::Delete { key } => key.as_str(),
+            })
+            .collect()
+    }
+
+    /// A headerless v1 log holding `records`, each stamped with a timestamp.
+    fn v1_log(records: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (i, (key, value)) in records.iter().enumerate() {
+            let op = if value.is_some() { V1_OP_PUT_STAMPED } else { V1_OP_DELETE_STAMPED };
+            let value = value.unwrap_or_default();
+            let mut fields = Vec::new();
+            fields.write_u64::<BigEndian>(100 + i as u64).unwrap();
+            fields.write_u64::<BigEndian>(key.len() as u64).unwrap();
+            fields.write_u64::<BigEndian>(value.len() as u64).unwrap();
+            fields.extend_from_slice(key.as_bytes());
+            fields.extend_from_slice(value);
+
+            let mut h = Crc32Hasher::new();
+            h.update(&[op]);
+            h.update(&fields);
+            bytes.push(op);
+            bytes.write_u32::<BigEndian>(h.finalize()).unwrap();
+            bytes.extend_from_slice(&fields);
+        }
+        bytes
+    }
+
+    /// A v2 log (a file header, records without sequences) of puts of `keys`.
+    fn v2_log(keys: &[&str]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.write_u16::<BigEndian>(UNSEQUENCED_VERSION).unwrap();
+        bytes.write_u8(CHECKSUM_CRC32).unwrap();
+        bytes.write_u8(0).unwrap();
+        bytes.write_u64::<BigEndian>(1).unwrap();
+        let crc = crc32fast::hash(&bytes);
+        bytes.write_u32::<BigEndian>(crc).unwrap();
+        for key in keys {
+            let mut payload = vec![OP_PUT];
+            payload.write_u64::<BigEndian>(7).unwrap();
+            payload.write_u32::<BigEndian>(key.len() as u32).unwrap();
+            payload.extend_from_slice(key.as_bytes());
+            payload.extend_from_slice(b"v2");
+            bytes.write_u32::<BigEndian>(payload.len() as u32).unwrap();
+            bytes.write_u32::<BigEndian>(crc32fast::hash(&payload)).unwrap();
+            bytes.extend_from_slice(&payload);
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_v1_logs_numbered_by_position() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        fs::write(&path, v1_log(&[("a", Some(b"1")), ("b", Some(b"2")), ("a", None)])).unwrap();
+
+        let info = WalReader::open(&path).unwrap().info();
+        assert_eq!((info.version, info.created_unix_ms, info.sequenced), (1, None, false));
+        let entries = WriteAheadLog::recover(&path).unwrap();
+        assert_eq!(keys(&entries), ["a", "b", "a"]);
+        assert_eq!(entries.iter().map(|entry| entry.sequence).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(entries[1].timestamp, 101);
+        assert!(matches!(&entries[1].record, WalRecord::Put { value, .. } if value == b"2"));
+        assert!(matches!(entries[2].record, WalRecord::Delete { .. }));
+    }
+
+    #[test]
+    fn opening_an_older_log_upgrades_it_numbered_after_the_base() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, bytes) in [("v1.log", v1_log(&[("a", Some(b"1")), ("b", None)])), ("v2.log", v2_log(&["a", "b"]))] {
+            let path = dir.path().join(name);
+            fs::write(&path, bytes).unwrap();
+
+            let mut wal = WriteAheadLog::open_with(&path, WalOptions::default(), 10, SyncMethod::default()).unwrap();
+            wal.append(&put("c", b""), 13, 0).unwrap();
+            drop(wal);
+
+            let info = WalReader::open(&path).unwrap().info();
+            assert_eq!((info.version, info.sequenced), (FORMAT_VERSION, true), "{name}");
+            let entries = WriteAheadLog::recover(&path).unwrap();
+            assert_eq!(keys(&entries), ["a", "b", "c"], "{name}");
+            assert_eq!(entries.iter().map(|entry| entry.sequence).collect::<Vec<_>>(), [11, 12, 13], "{name}");
+            assert!(!path.with_extension("upgrade").exists(), "{name}");
+        }
+    }
+
+    #[test]
+    fn new_logs_start_with_a_checked_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let options = WalOptions { commit_markers: true, ..Default::default() };
+        drop(WriteAheadLog::open_with(&path, options, 0, SyncMethod::default()).unwrap());
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len() as u64, FILE_HEADER_LEN);
+        assert_eq!(&bytes[..8], MAGIC);
+        let info = WalReader::open(&path).unwrap().info();
+        assert_eq!((info.version, info.commit_markers), (FORMAT_VERSION, true));
+        assert!(info.created_unix_ms.is_some());
+
+        let mut flipped = bytes.clone();
+        flipped[12] ^= 1;
+        fs::write(&path, flipped).unwrap();
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: 0, .. }), "{err}");
+    }
+
+    #[test]
+    fn refuses_an_unknown_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut bytes = v2_log(&[]);
+        bytes[8..10].copy_from_slice(&9u16.to_be_bytes());
+        let crc = crc32fast::hash(&bytes[..20]);
+        bytes[20..24].copy_from_slice(&crc.to_be_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let err = WriteAheadLog::open(&path).unwrap_err();
+        assert!(matches!(err, WalError::UnsupportedFormat { version: 9, checksum: CHECKSUM_CRC32 }), "{err}");
+    }
+
+    #[test]
+    fn batches_recover_as_consecutive_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put("a", b"1"), 1, 5).unwrap();
+        let batch = [put("b", b"2"), WalRecord::Delete { key: "a".to_owned() }, put("c", b"")];
+        wal.append_batch(&batch, 2, 6).unwrap();
+        wal.append_batch(&[], 5, 7).unwrap();
+        wal.append(&put("d", b"4"), 5, 8).unwrap();
+        drop(wal);
+
+        assert_eq!(offsets(&path).len(), 3, "a batch is one record, and an empty one none");
+        let entries = WriteAheadLog::recover(&path).unwrap();
+        assert_eq!(keys(&entries), ["a", "b", "a", "c", "d"]);
+        let numbered: Vec<_> = entries.iter().map(|entry| (entry.sequence, entry.timestamp)).collect();
+        assert_eq!(numbered, [(1, 5), (2, 6), (3, 6), (4, 6), (5, 8)]);
+        assert!(matches!(entries[2].record, WalRecord::Delete { .. }));
+    }
+
+    #[test]
+    fn a_damaged_batch_loses_every_record_in_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put("a", b""), 1, 0).unwrap();
+        wal.append_batch(&[put("b", b"bbbb"), put("c", b"cccc")], 2, 0).unwrap();
+        drop(wal);
+        let batch = offsets(&path)[1];
+        let bytes = fs::read(&path).unwrap();
+
+        // Its last byte flipped: the CRC covers the whole batch.
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, flipped).unwrap();
+        let recovered = WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Tolerant).unwrap();
+        assert_eq!(keys(&recovered.entries), ["a"]);
+        assert_eq!(recovered.dropped.unwrap().offset, batch);
+
+        // Cut short, as by a crash partway through writing it.
+        fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(keys(&WriteAheadLog::recover(&path).unwrap()), ["a"]);
+    }
+
+    #[test]
+    fn recovery_rejects_a_batch_whose_count_overruns_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append_batch(&[put("a", b"1"), put("b", b"2")], 1, 0).unwrap();
+        drop(wal);
+
+        let mut bytes = fs::read(&path).unwrap();
+        let payload = FILE_HEADER_LEN as usize + ENVELOPE_LEN as usize;
+        let count = payload + 1 + 8 + 8;
+        bytes[count..count + 4].copy_from_slice(&3u32.to_be_bytes());
+        let crc = crc32fast::hash(&bytes[payload..]);
+        bytes[payload - 4..payload].copy_from_slice(&crc.to_be_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: FILE_HEADER_LEN, .. }), "{err}");
+    }
+
+    #[test]
+    fn refuses_appends_over_the_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = WalOptions { limits: RecordLimits { max_key_len: 4, max_value_len: 8 }, ..Default::default() };
+        let mut wal = WriteAheadLog::open_with(dir.path().join("wal.log"), options, 0, SyncMethod::default()).unwrap();
+
+        let err = wal.append(&put("too long", b""), 1, 0).unwrap_err();
+        assert!(matches!(err, WalError::RecordTooLarge { field: "key", len: 8, max: 4 }), "{err}");
+        let err = wal.append_batch(&[put("k", b"v"), put("k", &[0; 9])], 1, 0).unwrap_err();
+        assert!(matches!(err, WalError::RecordTooLarge { field: "value", len: 9, max: 8 }), "{err}");
+        wal.append(&put("fits", b"8 bytes!"), 1, 0).unwrap();
+    }
+
+    #[test]
+    fn recovery_rejects_lengths_over_its_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a key of 20 bytes..."]);
+
+        let limits = RecordLimits { max_key_len: 16, max_value_len: 16 };
+        let err = WriteAheadLog::recover_with(&path, limits).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: FILE_HEADER_LEN, .. }), "{err}");
+        assert_eq!(WriteAheadLog::recover(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn recovery_rejects_a_key_length_past_the_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["key"]);
+
+        // Forge the key length, and a CRC that matches it, so only the
+        // bounds check stands between it and a 4 GiB allocation.
+        let mut bytes = fs::read(&path).unwrap();
+        let payload = FILE_HEADER_LEN as usize + ENVELOPE_LEN as usize;
+        let key_len = payload + 1 + 8 + 8;
+        bytes[key_len..key_len + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let crc = crc32fast::hash(&bytes[payload..]);
+        bytes[payload - 4..payload].copy_from_slice(&crc.to_be_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: FILE_HEADER_LEN, .. }), "{err}");
+    }
+
+    #[test]
+    fn recovery_rejects_a_payload_too_short_to_be_a_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a", "b"]);
+
+        let second = offsets(&path)[1] as usize;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[second..second + 4].copy_from_slice(&3u32.to_be_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset, .. } if offset == second as u64), "{err}");
+    }
+
+    #[test]
+    fn recovery_rejects_v1_lengths_past_the_end_of_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+
+        // Within the default limits, but far more than the file holds.
+        let mut bytes = vec![V1_OP_PUT_STAMPED];
+        bytes.write_u32::<BigEndian>(0).unwrap();
+        bytes.write_u64::<BigEndian>(0).unwrap();
+        bytes.write_u64::<BigEndian>(8).unwrap();
+        bytes.write_u64::<BigEndian>(1 << 29).unwrap();
+        bytes.extend_from_slice(b"key");
+        fs::write(&path, &bytes).unwrap();
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: 0, .. }), "{err}");
+
+        // And over the limits, however long the file.
+        bytes[13..21].copy_from_slice(&u64::MAX.to_be_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset: 0, .. }), "{err}");
+    }
+
+    /// Recover `path` strictly, expecting a torn tail from `offset` on.
+    fn assert_torn_at(path: &Path, offset: u64, kept: &[&str]) {
+        let recovered = WriteAheadLog::recover_from(path, RecordLimits::default(), RecoveryMode::Strict).unwrap();
+        assert_eq!(keys(&recovered.entries), kept);
+        let dropped = recovered.dropped.expect("a torn tail");
+        assert!(matches!(dropped.error, WalError::TornTail { .. }), "{}", dropped.error);
+        assert_eq!(dropped.offset, offset);
+        assert_eq!(dropped.bytes, fs::metadata(path).unwrap().len() - offset);
+    }
+
+    #[test]
+    fn strict_recovery_drops_a_torn_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a", "b"]);
+        let last  = offsets(&path)[1];
+        let bytes = fs::read(&path).unwrap();
+
+        // The last record cut short, in its payload or its envelope.
+        for len in [bytes.len() - 1, last as usize + 5] {
+            fs::write(&path, &bytes[..len]).unwrap();
+            assert_torn_at(&path, last, &["a"]);
+        }
+
+        // The file extended with zeros past the records, as a crash can
+        // leave it when the length was updated but not the data.
+        let mut zeroed = bytes.clone();
+        zeroed.extend_from_slice(&[0; 100]);
+        fs::write(&path, zeroed).unwrap();
+        assert_torn_at(&path, bytes.len() as u64, &["a", "b"]);
+
+        // Once cut there, the log reads cleanly.
+        WriteAheadLog::truncate_at(&path, bytes.len() as u64, SyncMethod::default()).unwrap();
+        let recovered = WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Strict).unwrap();
+        assert_eq!(keys(&recovered.entries), ["a", "b"]);
+        assert!(recovered.dropped.is_none());
+    }
+
+    #[test]
+    fn commit_markers_tell_a_torn_tail_from_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let options = WalOptions { commit_markers: true, ..Default::default() };
+        let mut wal = WriteAheadLog::open_with(&path, options, 0, SyncMethod::default()).unwrap();
+        for (i, key) in ["a", "b", "c"].iter().enumerate() {
+            wal.append(&put(key, b"value"), i as u64 + 1, 0).unwrap();
+        }
+        drop(wal);
+        let starts = offsets(&path);
+        let bytes  = fs::read(&path).unwrap();
+
+        // The last record's marker lost: its append did not complete.
+        let mut torn = bytes.clone();
+        *torn.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, torn).unwrap();
+        assert_torn_at(&path, starts[2], &["a", "b"]);
+
+        // An earlier record's marker wrong: that is damage, and fatal.
+        let mut damaged = bytes.clone();
+        damaged[starts[2] as usize - 1] ^= 0xff;
+        fs::write(&path, damaged).unwrap();
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::Corrupt { offset, .. } if offset == starts[1]), "{err}");
+    }
+
+    #[test]
+    fn strict_recovery_still_fails_on_a_damaged_record_before_the_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a", "b", "c"]);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[offsets(&path)[1] as usize + ENVELOPE_LEN as usize] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+        let err = WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Strict).unwrap_err();
+        assert!(matches!(err, WalError::ChecksumMismatch { .. }), "{err}");
+    }
+
+    #[test]
+    fn tolerant_recovery_drops_a_damaged_record_and_the_rest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a", "b", "c"]);
+
+        let second = offsets(&path)[1];
+        let mut bytes = fs::read(&path).unwrap();
+        let file_len = bytes.len() as u64;
+        bytes[second as usize + ENVELOPE_LEN as usize + 1] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::ChecksumMismatch { .. }), "{err}");
+
+        let recovered = WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Tolerant).unwrap();
+        assert_eq!(keys(&recovered.entries), ["a"]);
+        let dropped = recovered.dropped.unwrap();
+        assert_eq!((dropped.offset, dropped.bytes), (second, file_len - second));
+        assert!(matches!(dropped.error, WalError::ChecksumMismatch { .. }), "{}", dropped.error);
+
+        // Once cut there, the log reads cleanly and takes appends again.
+        WriteAheadLog::truncate_at(&path, dropped.offset, SyncMethod::default()).unwrap();
+        WriteAheadLog::open(&path).unwrap().append(&put("d", b""), 2, 0).unwrap();
+        assert_eq!(keys(&WriteAheadLog::recover(&path).unwrap()), ["a", "d"]);
+    }
+
+    #[test]
+    fn recovery_refuses_a_sequence_that_repeats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put("a", b""), 7, 0).unwrap();
+        wal.append_batch(&[put("b", b""), put("c", b"")], 8, 0).unwrap();
+        wal.append(&put("d", b""), 9, 0).unwrap();
+        drop(wal);
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        let third = offsets(&path)[2];
+        assert!(
+            matches!(err, WalError::SequenceGap { offset, expected: 10, got: 9 } if offset == third),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn sequences_start_wherever_the_log_does() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put("a", b""), 41, 0).unwrap();
+        wal.truncate().unwrap();
+        wal.append(&put("b", b""), 42, 0).unwrap();
+        drop(wal);
+
+        let entries = WriteAheadLog::recover(&path).unwrap();
+        assert_eq!(keys(&entries), ["b"]);
+        assert_eq!(entries[0].sequence, 42);
+    }
+
+    #[test]
+    fn tolerant_recovery_drops_an_out_of_order_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.append(&put("a", b""), 1, 0).unwrap();
+        wal.append(&put("b", b""), 3, 0).unwrap();
+        drop(wal);
+
+        let err = WriteAheadLog::recover(&path).unwrap_err();
+        assert!(matches!(err, WalError::SequenceGap { expected: 2, got: 3, .. }), "{err}");
+        let recovered = WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Tolerant).unwrap();
+        assert_eq!(keys(&recovered.entries), ["a"]);
+        assert_eq!(recovered.dropped.unwrap().offset, offsets(&path)[1]);
+    }
+
+    #[test]
+    fn tolerant_recovery_keeps_an_intact_log_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a", "b"]);
+
+        let recovered = WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Tolerant).unwrap();
+        assert_eq!(keys(&recovered.entries), ["a", "b"]);
+        assert!(recovered.sequenced);
+        assert!(recovered.dropped.is_none());
+    }
+
+    #[test]
+    fn tolerant_recovery_still_fails_on_an_unreadable_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        write_log(&path, &["a"]);
+
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[8] = 9;
+        fs::write(&path, bytes).unwrap();
+        assert!(WriteAheadLog::recover_from(&path, RecordLimits::default(), RecoveryMode::Tolerant).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+//! Compile the protobuf definitions into client stubs at build time.
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(true)
+        .compile(&["../proto/kv.proto"], &["../proto"])?;
+
+    println!("cargo:rerun-if-changed=../proto/kv.proto");
+    Ok(())
+}
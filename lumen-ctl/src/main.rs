@@ -0,0 +1,339 @@
+//! lumen-ctl — cluster administration for LumenKV operators.
+//!
+//! Every subcommand is one RPC against the node at `--addr` (or
+//! `LUMEN_ADDR`), printed as a table:
+//!
+//!   members                  Admin/ClusterStatus: every member, its role and state
+//!   add-node <ADDR>          Admin/AddMember: join a node to the cluster
+//!   remove-node <NODE_ID>    Admin/RemoveMember: declare a stopped node dead
+//!   lag                      Admin/ReplicaHealth: per-replica lag and health
+//!   rebalance                Rebalance: move misplaced keys between shards
+//!   snapshot [--destination] Admin/Backup: coordinated snapshot of all shards
+//!   usage                    Admin/Usage: requests, traffic and storage per namespace
+//!   space                    Admin/Space: live and dead data in each engine's files
+//!   maintenance enter|exit   Admin/EnterMaintenance, ExitMaintenance: drain a node for upkeep
+//!   gc [--grace-secs]        Admin/CollectOrphans: delete files interrupted writes left behind
+//!   log [--sample] [M=LEVEL] Admin/Logging: show or change request log levels and sampling
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use tonic::transport::Channel;
+use tonic::Status;
+
+pub mod kv {
+    tonic::include_proto!("kv");
+}
+
+use kv::admin_client::AdminClient;
+use kv::key_value_store_client::KeyValueStoreClient;
+use kv::{
+    AddMemberRequest, BackupRequest, ClusterStatusRequest, ClusterStatusResponse, CollectOrphansRequest,
+    EnterMaintenanceRequest, ExitMaintenanceRequest, HealthState, LoggingRequest, MemberState, NodeRole,
+    RebalanceRequest, RemoveMemberRequest, ReplicaHealthRequest, SpaceRequest, UsageRequest,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "lumen-ctl", about = "Administer a LumenKV cluster")]
+struct Cli {
+    /// gRPC URL of the node to talk to.
+    #[arg(long, env = "LUMEN_ADDR", default_value = "http://127.0.0.1:50051", global = true)]
+    addr: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List cluster members with their role, state and last contact.
+    Members,
+    /// Add the node at ADDR to the cluster.
+    AddNode {
+        #[arg(value_name = "ADDR")]
+        node_addr: String,
+    },
+    /// Remove a node that has been stopped.
+    RemoveNode { node_id: String },
+    /// Show replication lag and health of every replica.
+    Lag,
+    /// Rebalance keys across shards (shard routers only).
+    Rebalance,
+    /// Take a coordinated snapshot of the node or of every shard behind it.
+    Snapshot {
+        /// Directory on the server to write the backup to (default: its BACKUP_DIR).
+        #[arg(long, default_value = "")]
+        destination: String,
+    },
+    /// Show requests, bytes read and written, and storage of each namespace.
+    Usage,
+    /// Show how much of each engine's checkpoint and WAL is dead data.
+    Space,
+    /// Take the node out of service and back, e.g. to snapshot its disk.
+    Maintenance {
+        #[command(subcommand)]
+        action: MaintenanceAction,
+    },
+    /// Delete temp files and partial backups that interrupted writes left behind.
+    Gc {
+        /// Only delete files unmodified for this long (default: the server's ORPHAN_GRACE_SECS).
+        #[arg(long)]
+        grace_secs: Option<u64>,
+    },
+    /// Show request log levels and sampling, changing those given.
+    Log {
+        /// Log one request in this many of each method.
+        #[arg(long, default_value_t = 0)]
+        sample: u64,
+        /// Levels to set, as METHOD=LEVEL (`off`, `trace`, `debug`, `info`; `*` for every method).
+        #[arg(value_name = "METHOD=LEVEL")]
+        levels: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum MaintenanceAction {
+    /// Drain writes, sync the WAL and report NOT_SERVING.
+    Enter {
+        /// Keep answering reads.
+        #[arg(long)]
+        serve_reads: bool,
+    },
+    /// Serve again.
+    Exit,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let channel = Channel::from_shared(cli.addr.clone())
+        .context("--addr must be a URL such as http://127.0.0.1:50051")?
+        .connect()
+        .await
+        .with_context(|| format!("failed to connect to {}", cli.addr))?;
+    let mut kv    = KeyValueStoreClient::new(channel.clone());
+    let mut admin = AdminClient::new(channel);
+
+    match cli.command {
+        Command::Members => {
+            let status = admin.cluster_status(ClusterStatusRequest {}).await.map_err(rpc_error)?.into_inner();
+            print_members(&status);
+        }
+        Command::AddNode { node_addr } => {
+            let resp = admin
+                .add_member(AddMemberRequest { addr: node_addr.clone() })
+                .await
+                .map_err(rpc_error)?
+                .into_inner();
+            println!("Added {node_addr}.\n");
+            if let Some(cluster) = resp.cluster {
+                print_members(&cluster);
+            }
+        }
+        Command::RemoveNode { node_id } => {
+            admin
+                .remove_member(RemoveMemberRequest { node_id: node_id.clone() })
+                .await
+                .map_err(rpc_error)?;
+            println!("Removed {node_id}; the cluster learns of it with the next gossip round.");
+        }
+        Command::Lag => {
+            let health = admin.replica_health(ReplicaHealthRequest {}).await.map_err(rpc_error)?.into_inner();
+            println!("Primary sequence: {}\n", health.primary_sequence);
+            print_table(
+                &["REPLICA", "CONNECTED", "APPLIED", "LAG RECORDS", "LAG BYTES", "LAG", "HEARTBEAT", "HEALTH", "REASONS"],
+                health
+                    .replicas
+                    .iter()
+                    .map(|r| {
+                        vec![
+                            if r.learner { format!("{} (learner)", r.replica_id) } else { r.replica_id.clone() },
+                            yes_no(r.connected),
+                            r.applied_sequence.to_string(),
+                            r.lag_records.to_string(),
+                            r.lag_bytes.to_string(),
+                            format!("{:.1}s", r.lag_seconds),
+                            format!("{:.1}s ago", r.millis_since_heartbeat as f64 / 1000.0),
+                            HealthState::try_from(r.health)
+                                .map_or_else(|_| "unknown".to_owned(), |h| enum_name(h.as_str_name(), "HEALTH_STATE_")),
+                            r.reasons.join("; "),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Rebalance => {
+            let stats = kv.rebalance(RebalanceRequest {}).await.map_err(rpc_error)?.into_inner();
+            print_table(
+                &["SCANNED", "MOVED", "SPLITS", "MERGES"],
+                vec![vec![
+                    stats.scanned_keys.to_string(),
+                    stats.moved_keys.to_string(),
+                    stats.splits.to_string(),
+                    stats.merges.to_string(),
+                ]],
+            );
+        }
+        Command::Snapshot { destination } => {
+            let backup = admin.backup(BackupRequest { destination }).await.map_err(rpc_error)?.into_inner();
+            println!("Backup {} written to {}\n", backup.backup_id, backup.path);
+            print_table(
+                &["SHARD", "SEQUENCE", "KEYS", "PATH"],
+                backup
+                    .shards
+                    .iter()
+                    .map(|s| {
+                        let shard = if s.shard.is_empty() { "-".to_owned() } else { s.shard.clone() };
+                        vec![shard, s.sequence.to_string(), s.keys.to_string(), s.path.clone()]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Usage => {
+            let usage = admin.usage(UsageRequest {}).await.map_err(rpc_error)?.into_inner();
+            print_table(
+                &["NAMESPACE", "REQUESTS", "BYTES READ", "BYTES WRITTEN", "STORAGE BYTES"],
+                usage
+                    .namespaces
+                    .iter()
+                    .map(|n| {
+                        let namespace = if n.namespace.is_empty() { "-".to_owned() } else { n.namespace.clone() };
+                        vec![
+                            namespace,
+                            n.requests.to_string(),
+                            n.bytes_read.to_string(),
+                            n.bytes_written.to_string(),
+                            n.storage_bytes.to_string(),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Space => {
+            let space = admin.space(SpaceRequest {}).await.map_err(rpc_error)?.into_inner();
+            print_table(
+                &["DATA DIR", "LIVE", "CHECKPOINT", "TABLES", "WAL", "TOMBSTONES", "DEAD BYTES", "DEAD", "SPACE AMP"],
+                space
+                    .engines
+                    .iter()
+                    .map(|e| {
+                        vec![
+                            e.data_dir.clone(),
+                            e.live_bytes.to_string(),
+                            e.checkpoint_bytes.to_string(),
+                            e.table_bytes.to_string(),
+                            e.wal_bytes.to_string(),
+                            e.tombstones.to_string(),
+                            e.dead_bytes.to_string(),
+                            format!("{:.1}%", e.dead_ratio * 100.0),
+                            format!("{:.2}x", e.space_amplification),
+                        ]
+                    })
+                    .collect(),
+            );
+        }
+        Command::Maintenance { action: MaintenanceAction::Enter { serve_reads } } => {
+            let request  = EnterMaintenanceRequest { serve_reads };
+            let response = admin.enter_maintenance(request).await.map_err(rpc_error)?.into_inner();
+            println!("In maintenance; WAL synced through sequence {}", response.sequence);
+        }
+        Command::Maintenance { action: MaintenanceAction::Exit } => {
+            admin.exit_maintenance(ExitMaintenanceRequest {}).await.map_err(rpc_error)?;
+            println!("Serving");
+        }
+        Command::Gc { grace_secs } => {
+            let request  = CollectOrphansRequest { grace_secs };
+            let response = admin.collect_orphans(request).await.map_err(rpc_error)?.into_inner();
+            print_table(
+                &["PATH", "BYTES"],
+                response.removed.iter().map(|f| vec![f.path.clone(), f.bytes.to_string()]).collect(),
+            );
+        }
+        Command::Log { sample, levels } => {
+            let levels = levels
+                .iter()
+                .map(|spec| {
+                    let (method, level) =
+                        spec.split_once('=').with_context(|| format!("`{spec}` must be METHOD=LEVEL"))?;
+                    Ok((method.to_owned(), level.to_owned()))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let response = admin.logging(LoggingRequest { levels, sample }).await.map_err(rpc_error)?.into_inner();
+            println!("Logging 1 in {} requests; keys {}\n", response.sample, response.keys);
+            let mut levels: Vec<_> = response.levels.into_iter().collect();
+            levels.sort();
+            print_table(&["METHOD", "LEVEL"], levels.into_iter().map(|(method, level)| vec![method, level]).collect());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reduce an RPC failure to its code and message.
+fn rpc_error(status: Status) -> anyhow::Error {
+    anyhow::anyhow!("{:?}: {}", status.code(), status.message())
+}
+
+fn print_members(status: &ClusterStatusResponse) {
+    print_table(
+        &["NODE ID", "ADDRESS", "ROLE", "STATE", "INCARNATION", "LAST CONTACT"],
+        status
+            .members
+            .iter()
+            .map(|m| {
+                let node_id = if m.local { format!("{} (this node)", m.node_id) } else { m.node_id.clone() };
+                let contact = match (m.local, m.millis_since_contact) {
+                    (true, _) => "-".to_owned(),
+                    (false, 0) => "never".to_owned(),
+                    (false, ms) => format!("{:.1}s ago", ms as f64 / 1000.0),
+                };
+                vec![
+                    node_id,
+                    m.addr.clone(),
+                    NodeRole::try_from(m.role)
+                        .map_or_else(|_| "unknown".to_owned(), |r| enum_name(r.as_str_name(), "NODE_ROLE_")),
+                    MemberState::try_from(m.state)
+                        .map_or_else(|_| "unknown".to_owned(), |s| enum_name(s.as_str_name(), "MEMBER_STATE_")),
+                    m.incarnation.to_string(),
+                    contact,
+                ]
+            })
+            .collect(),
+    );
+}
+
+/// `NODE_ROLE_PRIMARY` → `primary`.
+fn enum_name(name: &str, prefix: &str) -> String {
+    name.strip_prefix(prefix).unwrap_or(name).to_lowercase()
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_owned()
+}
+
+/// Print `rows` under `headers` in left-aligned, space-padded columns.
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+
+    line(headers.to_vec());
+    for row in &rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+    if rows.is_empty() {
+        println!("(none)");
+    }
+}
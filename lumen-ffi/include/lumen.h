@@ -0,0 +1,133 @@
+/* Generated by cbindgen from lumen-ffi; do not edit. */
+
+#ifndef LUMEN_H
+#define LUMEN_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Outcome of a call.
+typedef enum LumenStatus {
+  LUMEN_STATUS_OK = 0,
+  // `lumen_get`: the key does not exist.
+  LUMEN_STATUS_NOT_FOUND = 1,
+  // `lumen_iter_next`: no entries are left.
+  LUMEN_STATUS_ITER_END = 2,
+  // A null pointer, or a key or path that is not valid UTF-8.
+  LUMEN_STATUS_INVALID_ARGUMENT = 3,
+  // Reading or writing the data directory failed, or another engine
+  // has it open.
+  LUMEN_STATUS_IO = 4,
+  // The write-ahead log or checkpoint is damaged.
+  LUMEN_STATUS_CORRUPTION = 5,
+  LUMEN_STATUS_INTERNAL = 6,
+} LumenStatus;
+
+// An open data directory.
+typedef struct LumenDb LumenDb;
+
+// Entries under a prefix, captured when the iterator was created.
+typedef struct LumenIter LumenIter;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Description of the last failed call on this thread, or NULL.  The
+// string stays valid until the next failing call on the same thread.
+const char *lumen_last_error(void);
+
+// Open (or create) the store in directory `path` and write its handle to
+// `*db`.
+//
+// # Safety
+// `path` must be a NUL-terminated string and `db` a writable pointer.
+enum LumenStatus lumen_open(const char *path, struct LumenDb **db);
+
+// Close a store opened with `lumen_open`.  NULL is ignored.
+//
+// # Safety
+// `db` must not be used again, and no other thread may be using it.
+// Iterators created from it remain valid.
+void lumen_close(struct LumenDb *db);
+
+// Store `value` under `key`.  Durable when it returns.
+//
+// # Safety
+// `db` must be an open handle; `key` and `value` must point to
+// `key_len` and `value_len` readable bytes.
+enum LumenStatus lumen_put(const struct LumenDb *db,
+                           const uint8_t *key,
+                           size_t key_len,
+                           const uint8_t *value,
+                           size_t value_len);
+
+// Look up `key`.  On `LUMEN_STATUS_OK`, `*value` and `*value_len` hold a
+// copy of the value, to be released with `lumen_free`; on
+// `LUMEN_STATUS_NOT_FOUND`, `*value` is set to NULL.
+//
+// # Safety
+// `db` must be an open handle, `key` must point to `key_len` readable
+// bytes, and `value` and `value_len` must be writable pointers.
+enum LumenStatus lumen_get(const struct LumenDb *db,
+                           const uint8_t *key,
+                           size_t key_len,
+                           uint8_t **value,
+                           size_t *value_len);
+
+// Release a value returned by `lumen_get`.  NULL is ignored.
+//
+// # Safety
+// `value` and `len` must be exactly as returned by `lumen_get`, and the
+// buffer must not be freed twice.
+void lumen_free(uint8_t *value, size_t len);
+
+// Delete `key`.  `*existed` (if not NULL) reports whether it was present.
+//
+// # Safety
+// `db` must be an open handle, `key` must point to `key_len` readable
+// bytes, and `existed` must be NULL or writable.
+enum LumenStatus lumen_delete(const struct LumenDb *db,
+                              const uint8_t *key,
+                              size_t key_len,
+                              bool *existed);
+
+// Start iterating, in key order, over every key starting with `prefix`
+// (an empty prefix covers the whole store).  The iterator sees the store
+// as of this call.
+//
+// # Safety
+// `db` must be an open handle, `prefix` must point to `prefix_len`
+// readable bytes, and `iter` must be writable.
+enum LumenStatus lumen_iter_new(const struct LumenDb *db,
+                                const uint8_t *prefix,
+                                size_t prefix_len,
+                                struct LumenIter **iter);
+
+// Advance to the next entry and point `*key`/`*value` at it, or return
+// `LUMEN_STATUS_ITER_END` when none are left.  Keys are not
+// NUL-terminated.
+//
+// # Safety
+// `iter` must come from `lumen_iter_new` and not be freed; the output
+// pointers must be writable.
+enum LumenStatus lumen_iter_next(struct LumenIter *iter,
+                                 const uint8_t **key,
+                                 size_t *key_len,
+                                 const uint8_t **value,
+                                 size_t *value_len);
+
+// Release an iterator.  NULL is ignored.
+//
+// # Safety
+// `iter` must come from `lumen_iter_new` and not be used again.
+void lumen_iter_free(struct LumenIter *iter);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LUMEN_H */
@@ -0,0 +1,367 @@
+//! C ABI for embedding the LumenKV engine.
+//!
+//! C, C++ and Go (cgo) programs link `liblumen_ffi` and include
+//! `include/lumen.h` (generated by cbindgen, see `cbindgen.toml`) to use the
+//! storage engine in-process, without running `lumen-server`:
+//!
+//! ```c
+//! LumenDb *db;
+//! if (lumen_open("./data", &db) != LUMEN_STATUS_OK) {
+//!     fprintf(stderr, "%s\n", lumen_last_error());
+//!     return 1;
+//! }
+//! lumen_put(db, (const uint8_t *)"k", 1, (const uint8_t *)"v", 1);
+//! lumen_close(db);
+//! ```
+//!
+//! Conventions:
+//!
+//!   * Every fallible call returns a `LumenStatus`.  On failure the calling
+//!     thread's `lumen_last_error()` describes it.
+//!   * Keys are UTF-8 byte strings and values arbitrary bytes, both passed
+//!     as pointer and length (no NUL terminator needed).
+//!   * Buffers returned by `lumen_get` belong to the caller and are released
+//!     with `lumen_free`.  Pointers returned by `lumen_iter_next` stay valid
+//!     until the next call on that iterator.
+//!   * A `LumenDb` may be used from several threads at once; a `LumenIter`
+//!     may not.
+//!   * Panics never cross the boundary; they are reported as
+//!     `LUMEN_STATUS_INTERNAL`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use lumen_core::{Engine, EngineError, KeyRange, Scan, WalError};
+
+// ---------------------------------------------------------------------------
+// Status codes
+// ---------------------------------------------------------------------------
+
+/// Outcome of a call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LumenStatus {
+    Ok = 0,
+    /// `lumen_get`: the key does not exist.
+    NotFound = 1,
+    /// `lumen_iter_next`: no entries are left.
+    IterEnd = 2,
+    /// A null pointer, or a key or path that is not valid UTF-8.
+    InvalidArgument = 3,
+    /// Reading or writing the data directory failed, or another engine
+    /// has it open.
+    Io = 4,
+    /// The write-ahead log or checkpoint is damaged.
+    Corruption = 5,
+    Internal = 6,
+}
+
+struct Failure {
+    status: LumenStatus,
+    message: String,
+}
+
+impl Failure {
+    fn invalid(message: impl Into<String>) -> Self {
+        Self { status: LumenStatus::InvalidArgument, message: message.into() }
+    }
+}
+
+impl From<EngineError> for Failure {
+    fn from(e: EngineError) -> Self {
+        let status = match &e {
+            EngineError::Wal(WalError::Io(_)) | EngineError::AlreadyLocked { .. } => LumenStatus::Io,
+            EngineError::Wal(_) => LumenStatus::Corruption,
+            _ => LumenStatus::Internal,
+        };
+        Self { status, message: e.to_string() }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_else(|e| {
+        let end = e.nul_position();
+        CString::new(&e.into_vec()[..end]).expect("prefix has no NUL")
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `call`, recording a failure (or a panic) for `lumen_last_error`.
+fn guard(call: impl FnOnce() -> Result<LumenStatus, Failure>) -> LumenStatus {
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(status)) => status,
+        Ok(Err(failure)) => {
+            set_last_error(failure.message);
+            failure.status
+        }
+        Err(_) => {
+            set_last_error("lumen-ffi panicked".to_owned());
+            LumenStatus::Internal
+        }
+    }
+}
+
+/// Description of the last failed call on this thread, or NULL.  The
+/// string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn lumen_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+// ---------------------------------------------------------------------------
+// Argument helpers
+// ---------------------------------------------------------------------------
+
+/// # Safety
+/// `data` must be null (only with `len` 0) or point to `len` readable bytes.
+unsafe fn bytes<'a>(data: *const u8, len: usize, what: &str) -> Result<&'a [u8], Failure> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err(Failure::invalid(format!("{what} is NULL"))),
+        (false, _) => Ok(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// # Safety
+/// As for `bytes`.
+unsafe fn text<'a>(data: *const u8, len: usize, what: &str) -> Result<&'a str, Failure> {
+    std::str::from_utf8(bytes(data, len, what)?).map_err(|_| Failure::invalid(format!("{what} is not valid UTF-8")))
+}
+
+/// # Safety
+/// `db` must be null or a handle returned by `lumen_open` and not closed.
+unsafe fn engine<'a>(db: *const LumenDb) -> Result<&'a Engine, Failure> {
+    db.as_ref().map(|db| &db.engine).ok_or_else(|| Failure::invalid("db is NULL"))
+}
+
+fn out<T>(ptr: *mut T, what: &str) -> Result<*mut T, Failure> {
+    if ptr.is_null() {
+        Err(Failure::invalid(format!("{what} is NULL")))
+    } else {
+        Ok(ptr)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Database
+// ---------------------------------------------------------------------------
+
+/// An open data directory.
+pub struct LumenDb {
+    engine: Engine,
+}
+
+/// Open (or create) the store in directory `path` and write its handle to
+/// `*db`.
+///
+/// # Safety
+/// `path` must be a NUL-terminated string and `db` a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_open(path: *const c_char, db: *mut *mut LumenDb) -> LumenStatus {
+    guard(|| {
+        let db = out(db, "db")?;
+        if path.is_null() {
+            return Err(Failure::invalid("path is NULL"));
+        }
+        let path = CStr::from_ptr(path)
+            .to_str()
+            .map_err(|_| Failure::invalid("path is not valid UTF-8"))?;
+
+        let engine = Engine::open(path)?;
+        *db = Box::into_raw(Box::new(LumenDb { engine }));
+        Ok(LumenStatus::Ok)
+    })
+}
+
+/// Close a store opened with `lumen_open`.  NULL is ignored.
+///
+/// # Safety
+/// `db` must not be used again, and no other thread may be using it.
+/// Iterators created from it remain valid.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_close(db: *mut LumenDb) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Store `value` under `key`.  Durable when it returns.
+///
+/// # Safety
+/// `db` must be an open handle; `key` and `value` must point to
+/// `key_len` and `value_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_put(
+    db: *const LumenDb,
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) -> LumenStatus {
+    guard(|| {
+        let engine = engine(db)?;
+        let key    = text(key, key_len, "key")?;
+        let value  = bytes(value, value_len, "value")?;
+        engine.put(key.to_owned(), value.to_vec())?;
+        Ok(LumenStatus::Ok)
+    })
+}
+
+/// Look up `key`.  On `LUMEN_STATUS_OK`, `*value` and `*value_len` hold a
+/// copy of the value, to be released with `lumen_free`; on
+/// `LUMEN_STATUS_NOT_FOUND`, `*value` is set to NULL.
+///
+/// # Safety
+/// `db` must be an open handle, `key` must point to `key_len` readable
+/// bytes, and `value` and `value_len` must be writable pointers.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_get(
+    db: *const LumenDb,
+    key: *const u8,
+    key_len: usize,
+    value: *mut *mut u8,
+    value_len: *mut usize,
+) -> LumenStatus {
+    guard(|| {
+        let engine    = engine(db)?;
+        let key       = text(key, key_len, "key")?;
+        let value     = out(value, "value")?;
+        let value_len = out(value_len, "value_len")?;
+
+        match engine.get(key)? {
+            Some(found) => {
+                let found  = found.into_boxed_slice();
+                *value_len = found.len();
+                *value     = Box::into_raw(found).cast::<u8>();
+                Ok(LumenStatus::Ok)
+            }
+            None => {
+                *value     = ptr::null_mut();
+                *value_len = 0;
+                Ok(LumenStatus::NotFound)
+            }
+        }
+    })
+}
+
+/// Release a value returned by `lumen_get`.  NULL is ignored.
+///
+/// # Safety
+/// `value` and `len` must be exactly as returned by `lumen_get`, and the
+/// buffer must not be freed twice.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_free(value: *mut u8, len: usize) {
+    if !value.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(value, len)));
+    }
+}
+
+/// Delete `key`.  `*existed` (if not NULL) reports whether it was present.
+///
+/// # Safety
+/// `db` must be an open handle, `key` must point to `key_len` readable
+/// bytes, and `existed` must be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_delete(
+    db: *const LumenDb,
+    key: *const u8,
+    key_len: usize,
+    existed: *mut bool,
+) -> LumenStatus {
+    guard(|| {
+        let engine = engine(db)?;
+        let key    = text(key, key_len, "key")?;
+        let found  = engine.delete(key)?;
+        if !existed.is_null() {
+            *existed = found;
+        }
+        Ok(LumenStatus::Ok)
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Iteration
+// ---------------------------------------------------------------------------
+
+/// Entries under a prefix, read from a snapshot taken when the iterator
+/// was created.
+pub struct LumenIter {
+    entries: Scan,
+    /// The entry last returned by `lumen_iter_next`, kept alive for the
+    /// pointers handed out.
+    current: Option<(String, Vec<u8>)>,
+}
+
+/// Start iterating, in key order, over every key starting with `prefix`
+/// (an empty prefix covers the whole store).  The iterator sees the store
+/// as of this call.
+///
+/// # Safety
+/// `db` must be an open handle, `prefix` must point to `prefix_len`
+/// readable bytes, and `iter` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_iter_new(
+    db: *const LumenDb,
+    prefix: *const u8,
+    prefix_len: usize,
+    iter: *mut *mut LumenIter,
+) -> LumenStatus {
+    guard(|| {
+        let engine = engine(db)?;
+        let prefix = text(prefix, prefix_len, "prefix")?;
+        let iter   = out(iter, "iter")?;
+
+        let entries = engine.scan(KeyRange::prefix(prefix))?;
+        *iter = Box::into_raw(Box::new(LumenIter { entries, current: None }));
+        Ok(LumenStatus::Ok)
+    })
+}
+
+/// Advance to the next entry and point `*key`/`*value` at it, or return
+/// `LUMEN_STATUS_ITER_END` when none are left.  Keys are not
+/// NUL-terminated.
+///
+/// # Safety
+/// `iter` must come from `lumen_iter_new` and not be freed; the output
+/// pointers must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_iter_next(
+    iter: *mut LumenIter,
+    key: *mut *const u8,
+    key_len: *mut usize,
+    value: *mut *const u8,
+    value_len: *mut usize,
+) -> LumenStatus {
+    guard(|| {
+        let iter      = iter.as_mut().ok_or_else(|| Failure::invalid("iter is NULL"))?;
+        let key       = out(key, "key")?;
+        let key_len   = out(key_len, "key_len")?;
+        let value     = out(value, "value")?;
+        let value_len = out(value_len, "value_len")?;
+
+        iter.current = iter.entries.next().transpose()?;
+        let Some((k, v)) = &iter.current else { return Ok(LumenStatus::IterEnd) };
+        *key       = k.as_ptr();
+        *key_len   = k.len();
+        *value     = v.as_ptr();
+        *value_len = v.len();
+        Ok(LumenStatus::Ok)
+    })
+}
+
+/// Release an iterator.  NULL is ignored.
+///
+/// # Safety
+/// `iter` must come from `lumen_iter_new` and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn lumen_iter_free(iter: *mut LumenIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
@@ -0,0 +1,25 @@
+from types import TracebackType
+from typing import Optional, Type, Union
+
+class LumenError(Exception): ...
+
+class Store:
+    def __init__(self, path: str) -> None: ...
+    def get(self, key: str) -> Optional[bytes]: ...
+    def put(self, key: str, value: Union[bytes, str]) -> None: ...
+    def delete(self, key: str) -> bool: ...
+    def scan(self, prefix: str = "") -> list[tuple[str, bytes]]: ...
+    def close(self) -> None: ...
+    @property
+    def closed(self) -> bool: ...
+    def __len__(self) -> int: ...
+    def __contains__(self, key: str) -> bool: ...
+    def __enter__(self) -> "Store": ...
+    def __exit__(
+        self,
+        exc_type: Optional[Type[BaseException]],
+        exc_value: Optional[BaseException],
+        traceback: Optional[TracebackType],
+    ) -> bool: ...
+
+def open(path: str) -> Store: ...
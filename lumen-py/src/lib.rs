@@ -0,0 +1,164 @@
+//! Python bindings for the embedded LumenKV engine.
+//!
+//! `lumen.open(path)` opens (or creates) a store in `path` and returns a
+//! `Store` with `get`, `put`, `delete` and `scan`.  A store is also a
+//! context manager that closes itself on exit:
+//!
+//! ```python
+//! import lumen
+//!
+//! with lumen.open("/var/lib/app/kv") as db:
+//!     db.put("user/42", b"alice")
+//!     for key, value in db.scan("user/"):
+//!         print(key, value)
+//! ```
+//!
+//! Keys are `str` and values `bytes` (a `str` value is stored as UTF-8).
+//! Writes are durable when they return, as with the server.  The GIL is
+//! released while the engine works, so other Python threads keep running.
+//! One data directory must not be opened by two processes at once.
+
+use lumen_core::{Engine, EngineError, KeyRange};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+create_exception!(lumen, LumenError, PyException, "Raised when the storage engine fails.");
+
+fn engine_error(e: EngineError) -> PyErr {
+    LumenError::new_err(e.to_string())
+}
+
+/// A value accepted by `Store.put`.
+#[derive(FromPyObject)]
+enum Value<'py> {
+    #[pyo3(annotation = "bytes")]
+    Bytes(Bound<'py, PyBytes>),
+    #[pyo3(annotation = "str")]
+    Text(String),
+}
+
+impl Value<'_> {
+    fn into_vec(self) -> Vec<u8> {
+        match self {
+            Value::Bytes(bytes) => bytes.as_bytes().to_vec(),
+            Value::Text(text) => text.into_bytes(),
+        }
+    }
+}
+
+/// An open LumenKV data directory.
+#[pyclass(module = "lumen")]
+struct Store {
+    /// `None` once the store has been closed.
+    engine: Option<Engine>,
+    path: String,
+}
+
+impl Store {
+    fn engine(&self) -> PyResult<&Engine> {
+        self.engine
+            .as_ref()
+            .ok_or_else(|| LumenError::new_err(format!("store {} is closed", self.path)))
+    }
+}
+
+#[pymethods]
+impl Store {
+    #[new]
+    fn new(py: Python<'_>, path: String) -> PyResult<Self> {
+        let dir    = path.clone();
+        let engine = py.allow_threads(|| Engine::open(dir)).map_err(engine_error)?;
+        Ok(Self { engine: Some(engine), path })
+    }
+
+    /// The value of `key` as `bytes`, or `None` if it does not exist.
+    fn get<'py>(&self, py: Python<'py>, key: &str) -> PyResult<Option<Bound<'py, PyBytes>>> {
+        let engine = self.engine()?;
+        let value  = py.allow_threads(|| engine.get(key)).map_err(engine_error)?;
+        Ok(value.map(|value| PyBytes::new(py, &value)))
+    }
+
+    /// Store `value` (`bytes` or `str`) under `key`.
+    fn put(&self, py: Python<'_>, key: String, value: Value<'_>) -> PyResult<()> {
+        let engine = self.engine()?;
+        let value  = value.into_vec();
+        py.allow_threads(|| engine.put(key, value)).map_err(engine_error)
+    }
+
+    /// Delete `key`; returns whether it existed.
+    fn delete(&self, py: Python<'_>, key: &str) -> PyResult<bool> {
+        let engine = self.engine()?;
+        py.allow_threads(|| engine.delete(key)).map_err(engine_error)
+    }
+
+    /// `(key, value)` pairs for every key starting with `prefix`, in key
+    /// order, as of the call.
+    #[pyo3(signature = (prefix = ""))]
+    fn scan<'py>(&self, py: Python<'py>, prefix: &str) -> PyResult<Vec<(String, Bound<'py, PyBytes>)>> {
+        let engine  = self.engine()?;
+        let entries = py
+            .allow_threads(|| engine.scan(KeyRange::prefix(prefix))?.collect::<Result<Vec<_>, _>>())
+            .map_err(engine_error)?;
+        Ok(entries
+            .into_iter()
+            .map(|(key, value)| (key, PyBytes::new(py, &value)))
+            .collect())
+    }
+
+    /// Close the store.  Further calls raise `LumenError`; closing twice is
+    /// harmless.
+    fn close(&mut self) {
+        self.engine = None;
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.engine.is_none()
+    }
+
+    fn __len__(&self, py: Python<'_>) -> PyResult<usize> {
+        let engine = self.engine()?;
+        py.allow_threads(|| engine.len()).map_err(engine_error)
+    }
+
+    fn __contains__(&self, py: Python<'_>, key: &str) -> PyResult<bool> {
+        Ok(self.get(py, key)?.is_some())
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyResult<PyRef<'_, Self>> {
+        slf.engine()?;
+        Ok(slf)
+    }
+
+    #[pyo3(signature = (_exc_type = None, _exc_value = None, _traceback = None))]
+    fn __exit__(
+        &mut self,
+        _exc_type: Option<&Bound<'_, PyAny>>,
+        _exc_value: Option<&Bound<'_, PyAny>>,
+        _traceback: Option<&Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close();
+        false
+    }
+
+    fn __repr__(&self) -> String {
+        let state = if self.engine.is_some() { "open" } else { "closed" };
+        format!("<lumen.Store {:?} ({state})>", self.path)
+    }
+}
+
+/// Open (or create) the store in directory `path`.
+#[pyfunction]
+fn open(py: Python<'_>, path: String) -> PyResult<Store> {
+    Store::new(py, path)
+}
+
+#[pymodule]
+fn lumen(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Store>()?;
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    m.add("LumenError", m.py().get_type::<LumenError>())?;
+    Ok(())
+}
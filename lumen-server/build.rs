@@ -5,13 +5,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     tonic_build::configure()
         .build_server(true)
-        .build_client(false)
+        .build_client(true)
         .file_descriptor_set_path(out_dir.join("kv_descriptor.bin"))
         .compile(
-            &["../proto/kv.proto"],
+            &["../proto/kv.proto", "../proto/health.proto"],
             &["../proto"],
         )?;
 
     println!("cargo:rerun-if-changed=../proto/kv.proto");
+    println!("cargo:rerun-if-changed=../proto/health.proto");
     Ok(())
 }
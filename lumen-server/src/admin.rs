@@ -0,0 +1,489 @@
+//! Operator-facing endpoints.
+//!
+//!   * The `Admin` gRPC service, served next to `KeyValueStore`: replica
+//!     health, coordinated backups (see `backup`), cluster membership,
+//!     per-namespace usage (see `usage`), maintenance mode (see
+//!     `maintenance`), orphan file collection (see `gc`) and request
+//!     logging (see `logging`).
+//!   * An optional plain HTTP listener (`ADMIN_ADDR`) serving Prometheus
+//!     metrics at `/metrics` and, with `PPROF=on`, CPU and heap profiles
+//!     at `/debug/pprof/profile` and `/debug/pprof/heap` (see `profiling`).
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Method, Request as HttpRequest, Response as HttpResponse, StatusCode};
+use metrics_exporter_prometheus::PrometheusHandle;
+use tonic::{Request, Response, Status};
+use tracing::{error, info, instrument};
+
+use lumen_core::{Engine, EngineError};
+
+use crate::backup::{self, Captured};
+use crate::kv::{
+    admin_server::Admin,
+    AddMemberRequest, AddMemberResponse,
+    BackupRequest, BackupResponse,
+    ClusterStatusRequest, ClusterStatusResponse,
+    CollectOrphansRequest, CollectOrphansResponse,
+    EngineSpace,
+    EnterMaintenanceRequest, EnterMaintenanceResponse,
+    ExitMaintenanceRequest, ExitMaintenanceResponse,
+    LoggingRequest, LoggingResponse,
+    OrphanFile,
+    RemoveMemberRequest, RemoveMemberResponse,
+    ReplicaHealthRequest, ReplicaHealthResponse,
+    SpaceRequest, SpaceResponse,
+    UsageRequest, UsageResponse,
+};
+use crate::errors::engine_status;
+use crate::gc::OrphanCollector;
+use crate::logging::RequestLog;
+use crate::maintenance::Maintenance;
+use crate::membership::Membership;
+use crate::metrics;
+use crate::profiling::{self, ProfileError};
+use crate::replication::ReplicationState;
+use crate::service::{sharded_status, Backend};
+use crate::usage::Usage;
+
+// ---------------------------------------------------------------------------
+// AdminService
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct AdminService {
+    backend: Backend,
+    replication: Arc<ReplicationState>,
+    membership: Arc<Membership>,
+    usage: Arc<Usage>,
+    maintenance: Arc<Maintenance>,
+    orphans: Arc<OrphanCollector>,
+    log: Arc<RequestLog>,
+    /// Where backups go when a request names no destination.
+    backup_dir: PathBuf,
+    /// Smallest value backups store once for all the keys holding it.
+    dedup_values: Option<usize>,
+}
+
+impl AdminService {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        backend: Backend,
+        replication: Arc<ReplicationState>,
+        membership: Arc<Membership>,
+        usage: Arc<Usage>,
+        maintenance: Arc<Maintenance>,
+        orphans: Arc<OrphanCollector>,
+        log: Arc<RequestLog>,
+        backup_dir: PathBuf,
+        dedup_values: Option<usize>,
+    ) -> Self {
+        Self { backend, replication, membership, usage, maintenance, orphans, log, backup_dir, dedup_values }
+    }
+
+    /// `None` on a shard router, which has no replication stream of its own.
+    fn health(&self) -> Option<anyhow::Result<ReplicaHealthResponse>> {
+        let Backend::Engine(engine) = &self.backend else { return None };
+        Some(
+            engine
+                .latest_sequence()
+                .map(|latest| self.replication.health(latest))
+                .map_err(Into::into),
+        )
+    }
+}
+
+#[tonic::async_trait]
+impl Admin for AdminService {
+    /// Per-replica lag and health as seen from this node.
+    #[instrument(name = "rpc_replica_health", skip(self, _request))]
+    async fn replica_health(
+        &self,
+        _request: Request<ReplicaHealthRequest>,
+    ) -> Result<Response<ReplicaHealthResponse>, Status> {
+        let health = self
+            .health()
+            .ok_or_else(sharded_status)?
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(health))
+    }
+
+    /// Capture a consistent backup of this node, or of every shard behind it.
+    #[instrument(name = "rpc_backup", skip(self, request))]
+    async fn backup(
+        &self,
+        request: Request<BackupRequest>,
+    ) -> Result<Response<BackupResponse>, Status> {
+        let req = request.into_inner();
+        let destination = if req.destination.is_empty() {
+            self.backup_dir.clone()
+        } else {
+            PathBuf::from(req.destination)
+        };
+
+        info!(destination = %destination.display(), "BACKUP");
+
+        let captured = match &self.backend {
+            Backend::Engine(engine) => {
+                Captured::Engine(engine.checkpoint().map_err(engine_status)?)
+            }
+            Backend::Sharded(router) => Captured::Cluster(router.snapshot().await?),
+        };
+
+        let dedup_values = self.dedup_values;
+        let response = tokio::task::spawn_blocking(move || backup::write_backup(&destination, captured, dedup_values))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(|e| Status::internal(format!("{e:#}")))?;
+        Ok(Response::new(response))
+    }
+
+    /// List every known cluster member with its role and health.
+    #[instrument(name = "rpc_cluster_status", skip(self, _request))]
+    async fn cluster_status(
+        &self,
+        _request: Request<ClusterStatusRequest>,
+    ) -> Result<Response<ClusterStatusResponse>, Status> {
+        Ok(Response::new(self.membership.status()))
+    }
+
+    /// Exchange member lists with a new node, joining it to the cluster.
+    #[instrument(name = "rpc_add_member", skip(self, request))]
+    async fn add_member(
+        &self,
+        request: Request<AddMemberRequest>,
+    ) -> Result<Response<AddMemberResponse>, Status> {
+        let req = request.into_inner();
+        if req.addr.is_empty() {
+            return Err(Status::invalid_argument("addr must not be empty"));
+        }
+
+        info!(addr = %req.addr, "ADD_MEMBER");
+
+        if !self.membership.ping(&req.addr).await {
+            return Err(Status::unavailable(format!("{} did not answer", req.addr)));
+        }
+        Ok(Response::new(AddMemberResponse { cluster: Some(self.membership.status()) }))
+    }
+
+    /// Declare a stopped member dead.  Refused while it still answers, since
+    /// a live node would refute the removal anyway.
+    #[instrument(name = "rpc_remove_member", skip(self, request))]
+    async fn remove_member(
+        &self,
+        request: Request<RemoveMemberRequest>,
+    ) -> Result<Response<RemoveMemberResponse>, Status> {
+        let req = request.into_inner();
+        if req.node_id == self.membership.node_id() {
+            return Err(Status::failed_precondition("a node cannot remove itself; ask another member"));
+        }
+        let addr = self
+            .membership
+            .live_member_addr(&req.node_id)
+            .ok_or_else(|| Status::not_found(format!("no live member `{}`", req.node_id)))?;
+
+        info!(node_id = %req.node_id, "REMOVE_MEMBER");
+
+        if self.membership.ping(&addr).await {
+            return Err(Status::failed_precondition(format!(
+                "member `{}` still answers at {addr}; stop it before removing it",
+                req.node_id
+            )));
+        }
+        self.membership.declare_dead(&req.node_id);
+        Ok(Response::new(RemoveMemberResponse {}))
+    }
+
+    /// Requests, traffic and storage of each namespace on this node.
+    #[instrument(name = "rpc_usage", skip(self, _request))]
+    async fn usage(
+        &self,
+        _request: Request<UsageRequest>,
+    ) -> Result<Response<UsageResponse>, Status> {
+        Ok(Response::new(UsageResponse { namespaces: self.usage.report() }))
+    }
+
+    /// Live and dead data of each engine on this node.
+    #[instrument(name = "rpc_space", skip(self, _request))]
+    async fn space(
+        &self,
+        _request: Request<SpaceRequest>,
+    ) -> Result<Response<SpaceResponse>, Status> {
+        let engines = match &self.backend {
+            Backend::Engine(engine) => vec![Engine::clone(engine)],
+            Backend::Sharded(router) => router.local_engines(),
+        };
+        let engines = tokio::task::spawn_blocking(move || {
+            engines
+                .iter()
+                .map(|engine| {
+                    let stats = engine.space()?;
+                    Ok(EngineSpace {
+                        data_dir:            engine.data_dir().display().to_string(),
+                        live_bytes:          stats.live_bytes,
+                        checkpoint_bytes:    stats.checkpoint_bytes,
+                        wal_bytes:           stats.wal_bytes,
+                        tombstones:          stats.tombstones,
+                        dead_bytes:          stats.dead_bytes,
+                        space_amplification: stats.space_amplification(),
+                        dead_ratio:          stats.dead_ratio(),
+                        table_bytes:         stats.table_bytes,
+                    })
+                })
+                .collect::<Result<Vec<_>, EngineError>>()
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(engine_status)?;
+        Ok(Response::new(SpaceResponse { engines }))
+    }
+
+    /// Drain writes and sync the WAL, leaving the node NOT_SERVING until
+    /// `ExitMaintenance`.
+    #[instrument(name = "rpc_enter_maintenance", skip(self, request))]
+    async fn enter_maintenance(
+        &self,
+        request: Request<EnterMaintenanceRequest>,
+    ) -> Result<Response<EnterMaintenanceResponse>, Status> {
+        let req = request.into_inner();
+        let Backend::Engine(engine) = &self.backend else {
+            return Err(sharded_status());
+        };
+
+        info!(serve_reads = req.serve_reads, "ENTER_MAINTENANCE");
+
+        self.maintenance.enter(req.serve_reads).await;
+        let sequence = engine.sync().and_then(|()| engine.latest_sequence()).map_err(|e| {
+            error!(error = %e, "Failed to sync for maintenance");
+            engine_status(e)
+        })?;
+        info!(sequence, "In maintenance");
+        Ok(Response::new(EnterMaintenanceResponse { sequence }))
+    }
+
+    #[instrument(name = "rpc_exit_maintenance", skip(self, _request))]
+    async fn exit_maintenance(
+        &self,
+        _request: Request<ExitMaintenanceRequest>,
+    ) -> Result<Response<ExitMaintenanceResponse>, Status> {
+        info!("EXIT_MAINTENANCE");
+        self.maintenance.exit();
+        Ok(Response::new(ExitMaintenanceResponse {}))
+    }
+
+    /// Delete the orphaned files unmodified for the grace period.
+    #[instrument(name = "rpc_collect_orphans", skip(self, request))]
+    async fn collect_orphans(
+        &self,
+        request: Request<CollectOrphansRequest>,
+    ) -> Result<Response<CollectOrphansResponse>, Status> {
+        let req   = request.into_inner();
+        let grace = req.grace_secs.map_or(self.orphans.grace(), Duration::from_secs);
+
+        info!(grace_secs = grace.as_secs(), "COLLECT_ORPHANS");
+
+        let orphans = self.orphans.clone();
+        let removed = tokio::task::spawn_blocking(move || orphans.collect(grace))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(|e| Status::internal(format!("{e:#}")))?;
+        Ok(Response::new(CollectOrphansResponse {
+            removed: removed
+                .into_iter()
+                .map(|(path, bytes)| OrphanFile { path: path.display().to_string(), bytes })
+                .collect(),
+        }))
+    }
+
+    /// Change request log levels and sampling, and report the result.
+    #[instrument(name = "rpc_logging", skip(self, request))]
+    async fn logging(
+        &self,
+        request: Request<LoggingRequest>,
+    ) -> Result<Response<LoggingResponse>, Status> {
+        let req = request.into_inner();
+        if !req.levels.is_empty() || req.sample > 0 {
+            info!(levels = ?req.levels, sample = req.sample, "LOGGING");
+            self.log.update(req.levels.iter().map(|(method, level)| (method.as_str(), level.as_str())), req.sample)?;
+        }
+        Ok(Response::new(LoggingResponse {
+            levels: self
+                .log
+                .levels()
+                .into_iter()
+                .map(|(method, verbosity)| (method.to_owned(), verbosity.as_str().to_owned()))
+                .collect(),
+            sample: self.log.sample(),
+            keys:   lumen_core::redact::key_mode().as_str().to_owned(),
+        }))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTTP listener
+// ---------------------------------------------------------------------------
+
+/// CPU profile length when the request does not give `seconds`.
+const DEFAULT_CPU_PROFILE: Duration = Duration::from_secs(30);
+
+/// Bind `addr` and serve `/metrics` on it in the background, and the
+/// profiling endpoints too if `profiling` is set.
+pub fn spawn_http(
+    addr: SocketAddr,
+    admin: AdminService,
+    prometheus: PrometheusHandle,
+    profiling: bool,
+) -> anyhow::Result<()> {
+    let make_service = make_service_fn(move |_conn| {
+        let admin      = admin.clone();
+        let prometheus = prometheus.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let admin      = admin.clone();
+                let prometheus = prometheus.clone();
+                async move { Ok::<_, Infallible>(handle(&admin, &prometheus, profiling, req).await) }
+            }))
+        }
+    });
+
+    let server = hyper::Server::try_bind(&addr)
+        .with_context(|| format!("failed to bind ADMIN_ADDR {addr}"))?
+        .serve(make_service);
+    info!(admin_addr = %addr, "Admin HTTP listener started");
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error = %e, "Admin HTTP listener exited with an error");
+        }
+    });
+    Ok(())
+}
+
+async fn handle(
+    admin: &AdminService,
+    prometheus: &PrometheusHandle,
+    profiling: bool,
+    req: HttpRequest<Body>,
+) -> HttpResponse<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            if let Some(Ok(health)) = admin.health() {
+                metrics::record_replica_health(&health);
+            }
+            match &admin.backend {
+                Backend::Engine(engine) => {
+                    if let Err(e) = metrics::record_storage(engine) {
+                        error!(error = %e, "Failed to read storage metrics");
+                    }
+                }
+                Backend::Sharded(router) => {
+                    // The engine records its space gauges itself.
+                    for engine in router.local_engines() {
+                        if let Err(e) = engine.space() {
+                            error!(data_dir = %engine.data_dir().display(), error = %e, "Failed to read space metrics");
+                        }
+                    }
+                }
+            }
+            metrics::record_usage(&admin.usage.report());
+            metrics::record_process();
+            metrics::record_runtime();
+            HttpResponse::builder()
+                .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Body::from(prometheus.render()))
+                .expect("static response parts are valid")
+        }
+        (&Method::GET, "/debug/pprof/profile") if profiling => {
+            let seconds = match query_param(&req, "seconds").map(str::parse::<u64>) {
+                None => DEFAULT_CPU_PROFILE,
+                Some(Ok(seconds)) if seconds > 0 => Duration::from_secs(seconds).min(profiling::MAX_CPU_PROFILE),
+                Some(_) => return plain(StatusCode::BAD_REQUEST, "seconds must be a positive integer\n".to_owned()),
+            };
+            info!(seconds = seconds.as_secs(), "Taking a CPU profile");
+            profile_response(profiling::cpu(seconds).await)
+        }
+        (&Method::GET, "/debug/pprof/heap") if profiling => {
+            // `debug=1` asks for text, as it does of Go's heap endpoint.
+            if query_param(&req, "debug").is_some_and(|v| v != "0") {
+                return match profiling::heap_stats() {
+                    Ok(stats) => plain(StatusCode::OK, stats),
+                    Err(e) => profile_error(e),
+                };
+            }
+            profile_response(profiling::heap().await)
+        }
+        _ => HttpResponse::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("not found\n"))
+            .expect("static response parts are valid"),
+    }
+}
+
+/// The value of `name` in the request's query string.
+fn query_param<'a>(req: &'a HttpRequest<Body>, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn plain(status: StatusCode, body: String) -> HttpResponse<Body> {
+    HttpResponse::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(body))
+        .expect("static response parts are valid")
+}
+
+fn profile_response(profile: Result<Vec<u8>, ProfileError>) -> HttpResponse<Body> {
+    match profile {
+        Ok(encoded) => HttpResponse::builder()
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_DISPOSITION, "attachment; filename=\"profile.pb\"")
+            .body(Body::from(encoded))
+            .expect("static response parts are valid"),
+        Err(e) => profile_error(e),
+    }
+}
+
+fn profile_error(e: ProfileError) -> HttpResponse<Body> {
+    match e {
+        ProfileError::Unsupported(feature) => plain(
+            StatusCode::NOT_IMPLEMENTED,
+            format!("this profile requires lumen-server built with the `{feature}` feature\n"),
+        ),
+        ProfileError::Busy => plain(StatusCode::CONFLICT, "a CPU profile is already being taken\n".to_owned()),
+        ProfileError::Failed(e) => {
+            error!(error = %e, "Failed to take a profile");
+            plain(StatusCode::INTERNAL_SERVER_ERROR, format!("{e}\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lumen_core::Engine;
+
+    use crate::kv::admin_client::AdminClient;
+    use crate::kv::{ClusterStatusRequest, NodeRole};
+    use crate::service::tests::serve;
+
+    #[tokio::test]
+    async fn a_lone_node_lists_itself_in_the_cluster_status() {
+        let dir       = tempfile::tempdir().unwrap();
+        let mut admin = AdminClient::new(serve(Engine::open(dir.path()).unwrap()).await);
+
+        let status = admin.cluster_status(ClusterStatusRequest {}).await.unwrap().into_inner();
+        let local: Vec<_> = status.members.iter().filter(|member| member.local).collect();
+        assert_eq!(local.len(), 1, "{status:?}");
+        assert_eq!(local[0].node_id, status.node_id);
+        assert_eq!(local[0].role(), NodeRole::Primary);
+    }
+}
@@ -0,0 +1,227 @@
+//! Coordinated backups.
+//!
+//! A backup is a directory laid out like a `DATA_DIR`, so restoring means
+//! starting a node on (a copy of) it:
+//!
+//!   <BACKUP_DIR>/<backup_id>/
+//!     MANIFEST              – what was captured, and at which sequences
+//!     checkpoint            – the engine of an unsharded node
+//!     <shard>/checkpoint    – every shard of a router, local or remote
+//!     ring | system/        – the router's placement metadata
+//!
+//! On a shard router all shards are captured at one barrier: client writes
+//! and migrations through the router are held back until every shard has
+//! been snapshotted, so the shards line up with each other and with the
+//! placement metadata.  A remote shard is restored by copying its
+//! `<shard>/` directory to that node's `DATA_DIR`.
+//!
+//! The backup is assembled in `<backup_id>.partial` and renamed into place
+//! once the manifest has been written, so a complete-looking backup is
+//! always a complete one.
+//!
+//! Backups are the node's cold data: written once, and read only to
+//! restore.  With `BACKUP_COLD_DIR` set, those older than
+//! `BACKUP_COLD_AFTER_SECS` are moved there, to slower and cheaper storage,
+//! the same way: copied into `<backup_id>.partial` (unless a rename can move
+//! them), renamed into place, and only then deleted from `BACKUP_DIR`.  A
+//! backup is restored from either directory alike.
+
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use tracing::{error, info};
+
+use lumen_core::Checkpoint;
+
+use crate::kv::{BackupResponse, ShardBackup};
+use crate::sharding::{ClusterSnapshot, PlacementSnapshot};
+
+const MANIFEST_VERSION: &str = "lumen-backup v1";
+
+/// What a backup captured, before it is written out.
+pub enum Captured {
+    Engine(Checkpoint),
+    Cluster(ClusterSnapshot),
+}
+
+/// Write `captured` as a new backup under `destination`, storing values of
+/// at least `dedup_values` bytes once for all the keys holding them.
+pub fn write_backup(
+    destination: &Path,
+    captured: Captured,
+    dedup_values: Option<usize>,
+) -> anyhow::Result<BackupResponse> {
+    let created_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let backup_id = format!("backup-{created_ms}");
+    let final_dir = destination.join(&backup_id);
+    let work_dir  = destination.join(format!("{backup_id}.partial"));
+
+    if final_dir.exists() {
+        anyhow::bail!("backup {} already exists", final_dir.display());
+    }
+    std::fs::create_dir_all(&work_dir)
+        .with_context(|| format!("failed to create {}", work_dir.display()))?;
+
+    let (partitioning, checkpoints, placement) = match captured {
+        Captured::Engine(checkpoint) => ("none", vec![(String::new(), checkpoint)], None),
+        Captured::Cluster(ClusterSnapshot { shards, placement }) => {
+            let kind = match placement {
+                PlacementSnapshot::Ring(_) => "hash",
+                PlacementSnapshot::Range(_) => "range",
+            };
+            (kind, shards, Some(placement))
+        }
+    };
+
+    let mut shards = Vec::with_capacity(checkpoints.len());
+    for (name, checkpoint) in checkpoints {
+        let path = if name.is_empty() { PathBuf::from("checkpoint") } else { Path::new(&name).join("checkpoint") };
+        write_checkpoint(&work_dir.join(&path), &checkpoint, dedup_values)?;
+
+        shards.push(ShardBackup {
+            shard:    name,
+            sequence: checkpoint.sequence,
+            keys:     checkpoint.entries.len() as u64,
+            path:     path.display().to_string(),
+        });
+    }
+
+    match placement {
+        Some(PlacementSnapshot::Ring(specs)) => std::fs::write(work_dir.join("ring"), specs)?,
+        Some(PlacementSnapshot::Range(table)) => {
+            write_checkpoint(&work_dir.join("system").join("checkpoint"), &table, dedup_values)?;
+        }
+        None => {}
+    }
+
+    let mut manifest = format!("{MANIFEST_VERSION}\nid {backup_id}\ncreated_ms {created_ms}\npartitioning {partitioning}\n");
+    for shard in &shards {
+        let name = if shard.shard.is_empty() { "-" } else { &shard.shard };
+        let _ = writeln!(manifest, "shard {name} {} {} {}", shard.sequence, shard.keys, shard.path);
+    }
+    std::fs::write(work_dir.join("MANIFEST"), manifest)?;
+    std::fs::rename(&work_dir, &final_dir)
+        .with_context(|| format!("failed to move backup into {}", final_dir.display()))?;
+
+    info!(backup_id = %backup_id, path = %final_dir.display(), shards = shards.len(), "Backup complete");
+
+    Ok(BackupResponse {
+        backup_id,
+        path: final_dir.display().to_string(),
+        shards,
+    })
+}
+
+fn write_checkpoint(path: &Path, checkpoint: &Checkpoint, dedup_values: Option<usize>) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    checkpoint
+        .write_with(path, dedup_values)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// How often backups are checked for moving to the cold tier.
+const TIER_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Where backups move once they are old.
+#[derive(Debug)]
+pub struct Tiering {
+    pub hot: PathBuf,
+    pub cold: PathBuf,
+    /// Age, from its creation, at which a backup moves.
+    pub after: Duration,
+}
+
+impl Tiering {
+    /// Move every complete backup in the hot directory created at least
+    /// `after` ago to the cold one.  Returns the backups moved.  Blocks;
+    /// call from a blocking thread.
+    pub fn run(&self) -> anyhow::Result<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.hot) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("failed to list {}", self.hot.display())),
+        };
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+
+        let mut moved = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let Some(backup_id) = path.file_name().and_then(|name| name.to_str()).map(str::to_owned) else {
+                continue;
+            };
+            // Partial backups have a suffix, and are never moved.
+            let Some(created_ms) = backup_id.strip_prefix("backup-").and_then(|ms| ms.parse::<u64>().ok()) else {
+                continue;
+            };
+            if now_ms.saturating_sub(created_ms) < self.after.as_millis() as u64 || !path.join("MANIFEST").exists() {
+                continue;
+            }
+            self.move_backup(&path, &backup_id)?;
+            moved.push(backup_id);
+        }
+        Ok(moved)
+    }
+
+    fn move_backup(&self, path: &Path, backup_id: &str) -> anyhow::Result<()> {
+        let final_dir = self.cold.join(backup_id);
+        if final_dir.exists() {
+            anyhow::bail!("backup {} already exists", final_dir.display());
+        }
+        std::fs::create_dir_all(&self.cold).with_context(|| format!("failed to create {}", self.cold.display()))?;
+
+        if std::fs::rename(path, &final_dir).is_err() {
+            // On another filesystem: copy, then let the copy appear at once.
+            let work_dir = self.cold.join(format!("{backup_id}.partial"));
+            if work_dir.exists() {
+                std::fs::remove_dir_all(&work_dir)?;
+            }
+            copy_dir(path, &work_dir).with_context(|| format!("failed to copy {} to the cold tier", path.display()))?;
+            std::fs::rename(&work_dir, &final_dir)
+                .with_context(|| format!("failed to move backup into {}", final_dir.display()))?;
+            std::fs::remove_dir_all(path).with_context(|| format!("failed to delete {}", path.display()))?;
+        }
+        info!(backup_id, path = %final_dir.display(), "Backup moved to the cold tier");
+        Ok(())
+    }
+}
+
+/// Copy the directory `from` to `to`, syncing every file.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry  = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+            std::fs::File::open(&target)?.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// Move old backups to the cold tier every `TIER_INTERVAL`, for as long as
+/// the server runs.
+pub fn spawn_tiering(tiering: Arc<Tiering>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(TIER_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let tiering = tiering.clone();
+            match tokio::task::spawn_blocking(move || tiering.run()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!(error = %format!("{e:#}"), "Moving backups to the cold tier failed"),
+                Err(e) => error!(error = %e, "Backup tiering task panicked"),
+            }
+        }
+    });
+}
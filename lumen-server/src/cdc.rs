@@ -0,0 +1,358 @@
+//! Change-data capture: publish committed changes to Kafka or NATS.
+//!
+//! A background task tails the engine's change feed and publishes every
+//! change, in sequence order, as a protobuf-encoded `ChangeEvent` stamped
+//! with its hybrid-logical-clock commit time (deletes made by a lease
+//! expiring as `OPERATION_EXPIRED`):
+//!
+//!   * Kafka (`cdc-kafka` feature): one record per change on a single
+//!     partition of `CDC_TOPIC` (so consumers see the commit order), keyed by
+//!     the user key, with the sequence in a `sequence` header.
+//!   * NATS (`cdc-nats` feature): a JetStream publish to subject `CDC_TOPIC`
+//!     with `Nats-Msg-Id` set, so the server drops redelivered duplicates.
+//!     A stream covering the subject must already exist.
+//!
+//! Delivery is at least once: each batch is only considered delivered once
+//! the broker has acknowledged it, after which the last sequence is recorded
+//! in `DATA_DIR/cdc.offset`.  A crash between the ack and that write replays
+//! the batch on restart.  If the offset predates the changes this node still
+//! retains (e.g. after a replica bootstrapped from a snapshot), the publisher
+//! re-emits every live key as `snapshot` events and continues from there.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use tracing::{info, warn};
+
+use lumen_core::hlc::physical_millis;
+use lumen_core::{Engine, EngineError, ExpiryTracker, WalRecord};
+
+use crate::kv::{ChangeEvent, Operation};
+use crate::regions::Versioned;
+use crate::replication::{INITIAL_BACKOFF, MAX_BACKOFF};
+
+/// How long the publisher blocks on an idle feed before re-checking.
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+
+/// Broker type selected by `CDC_SINK`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Kafka,
+    Nats,
+}
+
+#[derive(Debug, Clone)]
+pub struct CdcConfig {
+    pub sink: SinkKind,
+    /// Kafka bootstrap brokers, or the NATS server URL.
+    pub brokers: Vec<String>,
+    /// Kafka topic or NATS subject.
+    pub topic: String,
+    /// Kafka partition all events are written to.
+    #[cfg_attr(not(feature = "cdc-kafka"), allow(dead_code))]
+    pub partition: i32,
+    /// Maximum changes per published batch.
+    pub batch: usize,
+    /// Values carry multi-region envelopes that must be unwrapped.
+    pub versioned: bool,
+}
+
+impl CdcConfig {
+    /// Fail at startup, rather than in the background task, when the binary
+    /// lacks the requested sink.
+    pub fn check_supported(&self) -> anyhow::Result<()> {
+        match self.sink {
+            SinkKind::Kafka if !cfg!(feature = "cdc-kafka") => {
+                anyhow::bail!("CDC_SINK=kafka requires lumen-server built with the `cdc-kafka` feature")
+            }
+            SinkKind::Nats if !cfg!(feature = "cdc-nats") => {
+                anyhow::bail!("CDC_SINK=nats requires lumen-server built with the `cdc-nats` feature")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Sinks
+// ---------------------------------------------------------------------------
+
+enum Sink {
+    #[cfg(feature = "cdc-kafka")]
+    Kafka(rskafka::client::partition::PartitionClient),
+    #[cfg(feature = "cdc-nats")]
+    Nats {
+        jetstream: async_nats::jetstream::Context,
+        subject: String,
+    },
+}
+
+impl Sink {
+    async fn connect(config: &CdcConfig) -> anyhow::Result<Self> {
+        match config.sink {
+            #[cfg(feature = "cdc-kafka")]
+            SinkKind::Kafka => {
+                use rskafka::client::{partition::UnknownTopicHandling, ClientBuilder};
+
+                let client = ClientBuilder::new(config.brokers.clone())
+                    .build()
+                    .await
+                    .context("failed to connect to Kafka")?;
+                let partition = client
+                    .partition_client(config.topic.clone(), config.partition, UnknownTopicHandling::Retry)
+                    .await
+                    .context("failed to open Kafka partition")?;
+                Ok(Self::Kafka(partition))
+            }
+            #[cfg(feature = "cdc-nats")]
+            SinkKind::Nats => {
+                let url    = config.brokers.join(",");
+                let client = async_nats::connect(url).await.context("failed to connect to NATS")?;
+                Ok(Self::Nats {
+                    jetstream: async_nats::jetstream::new(client),
+                    subject: config.topic.clone(),
+                })
+            }
+            #[allow(unreachable_patterns)]
+            _ => config.check_supported().map(|_| unreachable!("sink was checked at startup")),
+        }
+    }
+
+    /// Publish `events` and wait until the broker has acknowledged them all.
+    async fn publish(&self, events: &[ChangeEvent]) -> anyhow::Result<()> {
+        #[cfg(any(feature = "cdc-kafka", feature = "cdc-nats"))]
+        use prost::Message;
+
+        match self {
+            #[cfg(feature = "cdc-kafka")]
+            Self::Kafka(partition) => {
+                use rskafka::client::partition::Compression;
+                use rskafka::record::Record;
+
+                let records = events
+                    .iter()
+                    .map(|event| Record {
+                        key:       Some(event.key.clone().into_bytes()),
+                        value:     Some(event.encode_to_vec()),
+                        headers:   [("sequence".to_owned(), event.sequence.to_string().into_bytes())].into(),
+                        timestamp: rskafka::chrono::DateTime::from_timestamp_millis(event.timestamp_ms as i64)
+                            .unwrap_or_default(),
+                    })
+                    .collect();
+                partition
+                    .produce(records, Compression::NoCompression)
+                    .await
+                    .context("Kafka produce failed")?;
+                Ok(())
+            }
+            #[cfg(feature = "cdc-nats")]
+            Self::Nats { jetstream, subject } => {
+                let mut acks = Vec::with_capacity(events.len());
+                for event in events {
+                    let msg_id = if event.snapshot {
+                        format!("{}/{}", event.sequence, event.key)
+                    } else {
+                        event.sequence.to_string()
+                    };
+                    let mut headers = async_nats::HeaderMap::new();
+                    headers.insert("Nats-Msg-Id", msg_id.as_str());
+
+                    acks.push(
+                        jetstream
+                            .publish_with_headers(subject.clone(), headers, event.encode_to_vec().into())
+                            .await
+                            .context("NATS publish failed")?,
+                    );
+                }
+                for ack in acks {
+                    ack.await.context("NATS did not acknowledge publish")?;
+                }
+                Ok(())
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                let _ = events;
+                unreachable!("no CDC sink compiled in")
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Publisher
+// ---------------------------------------------------------------------------
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Build the event for `record`; `timestamp` is its HLC commit time, or 0 to
+/// stamp it with the current time (snapshot events, legacy log entries).
+/// `expired` marks a delete made by a lease expiring.
+fn to_event(
+    sequence: u64,
+    timestamp: u64,
+    record: WalRecord,
+    expired: bool,
+    versioned: bool,
+    snapshot: bool,
+) -> ChangeEvent {
+    let (op, key, value) = match record {
+        WalRecord::Put { key, value } if versioned => match Versioned::decode(value).value {
+            Some(value) => (Operation::Put, key, value),
+            None => (Operation::Delete, key, Vec::new()),
+        },
+        WalRecord::Put { key, value } => (Operation::Put, key, value),
+        WalRecord::Delete { key } if expired => (Operation::Expired, key, Vec::new()),
+        WalRecord::Delete { key } => (Operation::Delete, key, Vec::new()),
+    };
+
+    ChangeEvent {
+        sequence,
+        op: op as i32,
+        key,
+        value,
+        timestamp_ms: if timestamp > 0 { physical_millis(timestamp) } else { now_millis() },
+        snapshot,
+        hlc_timestamp: timestamp,
+    }
+}
+
+/// Sequence published through, persisted in `DATA_DIR/cdc.offset`.
+struct Offset {
+    path: PathBuf,
+    sequence: u64,
+}
+
+impl Offset {
+    fn load(data_dir: &str) -> anyhow::Result<Self> {
+        let path = PathBuf::from(data_dir).join("cdc.offset");
+        let sequence = match std::fs::read_to_string(&path) {
+            Ok(text) => text.trim().parse().context("corrupt CDC offset file")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).context("failed to read CDC offset"),
+        };
+        Ok(Self { path, sequence })
+    }
+
+    fn advance(&mut self, sequence: u64) -> anyhow::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, format!("{sequence}\n"))?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.sequence = sequence;
+        Ok(())
+    }
+}
+
+/// Publish changes forever, reconnecting with back-off on failure.
+pub async fn run_cdc(engine: Arc<Engine>, config: CdcConfig, data_dir: String) {
+    let mut offset = match Offset::load(&data_dir) {
+        Ok(offset) => offset,
+        Err(e) => {
+            warn!(error = %e, "CDC publisher disabled");
+            return;
+        }
+    };
+    info!(
+        sink          = ?config.sink,
+        brokers       = ?config.brokers,
+        topic         = %config.topic,
+        from_sequence = offset.sequence,
+        "CDC publisher started"
+    );
+
+    let mut backoff = INITIAL_BACKOFF;
+    let mut expiry  = ExpiryTracker::new();
+    loop {
+        if let Err(e) = publish(&engine, &config, &mut offset, &mut expiry, &mut backoff).await {
+            warn!(error = %e, sequence = offset.sequence, "CDC publishing failed; retrying");
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn publish(
+    engine: &Arc<Engine>,
+    config: &CdcConfig,
+    offset: &mut Offset,
+    expiry: &mut ExpiryTracker,
+    backoff: &mut Duration,
+) -> anyhow::Result<()> {
+    let sink = Sink::connect(config).await?;
+
+    loop {
+        let eng   = engine.clone();
+        let after = offset.sequence;
+        let limit = config.batch;
+        let fetched = tokio::task::spawn_blocking(move || {
+            // The log was replaced underneath the offset (e.g. a wiped or
+            // re-bootstrapped data directory): resync.
+            if after > eng.latest_sequence()? {
+                return Ok(None);
+            }
+            let changes = match eng.changes_since(after, limit) {
+                Ok(changes) => changes,
+                Err(EngineError::SequenceUnavailable { .. }) => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if changes.is_empty() {
+                eng.wait_for_changes(after, IDLE_WAIT)?;
+            }
+            Ok(Some(changes))
+        })
+        .await??;
+
+        let Some(changes) = fetched else {
+            resync(engine, config, &sink, offset).await?;
+            continue;
+        };
+        let Some(last) = changes.last().map(|c| c.sequence) else { continue };
+
+        // Only moved on with the offset, so a batch published again is
+        // tracked again from the same state.
+        let mut tracked = expiry.clone();
+        let events: Vec<ChangeEvent> = changes
+            .into_iter()
+            .map(|c| {
+                let expired = tracked.is_expiry(&c.record);
+                to_event(c.sequence, c.timestamp, c.record, expired, config.versioned, false)
+            })
+            .collect();
+        sink.publish(&events).await?;
+        offset.advance(last)?;
+        *expiry = tracked;
+        *backoff = INITIAL_BACKOFF;
+    }
+}
+
+/// Re-emit every live key after falling behind the retained log.
+async fn resync(engine: &Arc<Engine>, config: &CdcConfig, sink: &Sink, offset: &mut Offset) -> anyhow::Result<()> {
+    let eng = engine.clone();
+    let checkpoint = tokio::task::spawn_blocking(move || eng.checkpoint()).await??;
+    warn!(
+        from_sequence = offset.sequence,
+        sequence      = checkpoint.sequence,
+        keys          = checkpoint.entries.len(),
+        "CDC offset no longer retained; publishing a full snapshot"
+    );
+
+    let sequence = checkpoint.sequence;
+    let mut entries = checkpoint.entries.into_iter().peekable();
+    while entries.peek().is_some() {
+        let events: Vec<ChangeEvent> = entries
+            .by_ref()
+            .take(config.batch)
+            .map(|(key, value)| to_event(sequence, 0, WalRecord::Put { key, value }, false, config.versioned, true))
+            // Tombstones of deleted keys need not be replayed.
+            .filter(|event| event.op == Operation::Put as i32)
+            .collect();
+        sink.publish(&events).await?;
+    }
+
+    offset.advance(sequence)
+}
@@ -0,0 +1,347 @@
+//! Keyspace notification channels (`CHANNELS`, `Subscribe` RPC).
+//!
+//! Where a `Watch` follows one prefix from a sequence the client picks, a
+//! channel is named and configured on the server, and delivers the classes
+//! of event it was configured with to whoever is subscribed at the time.
+//! Each channel is configured as `name=item+item...`, where an item is an
+//! event class:
+//!
+//!   writes[:NAMESPACE]        puts and deletes (of keys in NAMESPACE)
+//!   expirations[:NAMESPACE]   deletes made by a lease expiring
+//!   checkpoints               the engine's checkpoint advancing (a
+//!                             compaction, or a replica installing a
+//!                             snapshot), with the sequence it covers
+//!
+//! or an option: `buffer=N`, the events held for a subscriber that is not
+//! keeping up (default: 256), and `drop=oldest|newest|disconnect`, what
+//! happens when that buffer is full (default: oldest).  `oldest` and
+//! `newest` drop events from the front or back of the buffer and report how
+//! many on the next event delivered after the gap; `disconnect` ends the
+//! subscriber's stream with RESOURCE_EXHAUSTED, for subscribers that must
+//! not miss events and would rather resynchronise.
+//!
+//! One task tails the change feed for every channel, from the latest commit
+//! when the server starts; subscribers see what is published while they are
+//! subscribed, and nothing from before.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::Context;
+use tokio::sync::{mpsc, Notify};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tracing::{error, info, warn};
+
+use lumen_core::{is_reserved_key, Change, Engine, EngineError, ExpiryTracker};
+
+use crate::kv::{channel_event::Event, ChannelEvent};
+use crate::regions::namespace_of;
+use crate::replication::{BATCH_LIMIT, HEARTBEAT_INTERVAL};
+use crate::watch::{change_key, to_event};
+
+/// Events held for a subscriber unless the channel says otherwise.
+const DEFAULT_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventClass {
+    Writes(Option<String>),
+    Expirations(Option<String>),
+    Checkpoints,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    Oldest,
+    Newest,
+    Disconnect,
+}
+
+/// One configured channel.
+#[derive(Debug, Clone)]
+pub struct ChannelSpec {
+    pub name: String,
+    pub classes: Vec<EventClass>,
+    pub buffer: usize,
+    pub drop: DropPolicy,
+}
+
+/// Parse `CHANNELS`: comma-separated `name=item+item...` entries.
+pub fn parse_specs(specs: &[String]) -> anyhow::Result<Vec<ChannelSpec>> {
+    let mut parsed: Vec<ChannelSpec> = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let (name, items) = spec
+            .split_once('=')
+            .filter(|(name, _)| !name.is_empty())
+            .with_context(|| format!("CHANNELS entry `{spec}` must be `name=class+class...`"))?;
+        if parsed.iter().any(|channel| channel.name == name) {
+            anyhow::bail!("CHANNELS has two channels named `{name}`");
+        }
+
+        let mut channel =
+            ChannelSpec { name: name.to_owned(), classes: Vec::new(), buffer: DEFAULT_BUFFER, drop: DropPolicy::Oldest };
+        for item in items.split('+') {
+            let (kind, arg) = match item.split_once([':', '=']) {
+                Some((kind, arg)) => (kind, Some(arg)),
+                None => (item, None),
+            };
+            let namespace = arg.map(str::to_owned);
+            match (kind, arg) {
+                ("writes", _) => channel.classes.push(EventClass::Writes(namespace)),
+                ("expirations", _) => channel.classes.push(EventClass::Expirations(namespace)),
+                ("checkpoints", None) => channel.classes.push(EventClass::Checkpoints),
+                ("buffer", Some(n)) => {
+                    channel.buffer = n
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .with_context(|| format!("CHANNELS entry `{spec}`: buffer must be a positive integer"))?;
+                }
+                ("drop", Some("oldest")) => channel.drop = DropPolicy::Oldest,
+                ("drop", Some("newest")) => channel.drop = DropPolicy::Newest,
+                ("drop", Some("disconnect")) => channel.drop = DropPolicy::Disconnect,
+                _ => anyhow::bail!(
+                    "CHANNELS entry `{spec}`: `{item}` is not `writes[:NS]`, `expirations[:NS]`, `checkpoints`, \
+                     `buffer=N` or `drop=oldest|newest|disconnect`"
+                ),
+            }
+        }
+        if channel.classes.is_empty() {
+            anyhow::bail!("CHANNELS entry `{spec}` has no event class");
+        }
+        parsed.push(channel);
+    }
+    Ok(parsed)
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    events: VecDeque<ChannelEvent>,
+    /// Dropped after the last event queued, to report on the next one.
+    dropped: u64,
+    /// Set once a `disconnect` channel's buffer overflowed.
+    overflowed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Subscriber {
+    queue: Mutex<Queue>,
+    notify: Notify,
+}
+
+impl Subscriber {
+    fn queue(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queue `event`, making room in a full buffer as `spec` says.
+    fn push(&self, spec: &ChannelSpec, mut event: ChannelEvent) {
+        let mut queue = self.queue();
+        if queue.overflowed {
+            return;
+        }
+        if queue.events.len() >= spec.buffer {
+            match spec.drop {
+                DropPolicy::Oldest => {
+                    let oldest = queue.events.pop_front().expect("buffer is full");
+                    match queue.events.front_mut() {
+                        Some(next) => next.dropped += oldest.dropped + 1,
+                        None => queue.dropped += oldest.dropped + 1,
+                    }
+                }
+                DropPolicy::Newest => {
+                    queue.dropped += 1;
+                    return;
+                }
+                DropPolicy::Disconnect => {
+                    queue.overflowed = true;
+                    self.notify.notify_one();
+                    return;
+                }
+            }
+        }
+        event.dropped = std::mem::take(&mut queue.dropped);
+        queue.events.push_back(event);
+        self.notify.notify_one();
+    }
+}
+
+#[derive(Debug)]
+struct Channel {
+    spec: ChannelSpec,
+    subscribers: Mutex<Vec<Arc<Subscriber>>>,
+}
+
+impl Channel {
+    fn subscribers(&self) -> MutexGuard<'_, Vec<Arc<Subscriber>>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn publish(&self, event: &ChannelEvent) {
+        for subscriber in self.subscribers().iter() {
+            subscriber.push(&self.spec, event.clone());
+        }
+    }
+}
+
+/// The configured channels of one engine.
+#[derive(Debug)]
+pub struct Channels {
+    engine: Arc<Engine>,
+    /// Unwrap multi-region envelopes, turning tombstones into deletes.
+    versioned: bool,
+    channels: BTreeMap<String, Channel>,
+}
+
+impl Channels {
+    pub fn new(engine: Arc<Engine>, specs: Vec<ChannelSpec>, versioned: bool) -> Self {
+        let channels = specs
+            .into_iter()
+            .map(|spec| (spec.name.clone(), Channel { spec, subscribers: Mutex::new(Vec::new()) }))
+            .collect();
+        Self { engine, versioned, channels }
+    }
+
+    /// Publish `change` to every channel it belongs on.
+    fn publish_change(&self, change: Change, expired: bool) {
+        let key = change_key(&change);
+        if is_reserved_key(key) {
+            return;
+        }
+        let namespace = namespace_of(key);
+        let matches   = |class: &EventClass| match class {
+            EventClass::Writes(filter) => !expired && filter.as_deref().is_none_or(|ns| ns == namespace),
+            EventClass::Expirations(filter) => expired && filter.as_deref().is_none_or(|ns| ns == namespace),
+            EventClass::Checkpoints => false,
+        };
+        let targets: Vec<&Channel> =
+            self.channels.values().filter(|channel| channel.spec.classes.iter().any(matches)).collect();
+        if targets.is_empty() {
+            return;
+        }
+
+        let event = ChannelEvent { event: Some(Event::Change(to_event(change, expired, self.versioned))), dropped: 0 };
+        for channel in targets {
+            channel.publish(&event);
+        }
+    }
+
+    /// Publish the checkpoint now covering `sequence`.
+    fn publish_checkpoint(&self, sequence: u64) {
+        let event = ChannelEvent { event: Some(Event::Checkpoint(sequence)), dropped: 0 };
+        for channel in self.channels.values() {
+            if channel.spec.classes.contains(&EventClass::Checkpoints) {
+                channel.publish(&event);
+            }
+        }
+    }
+
+    /// Subscribe to channel `name`: events published to it from now on,
+    /// until the stream is dropped.
+    pub fn subscribe(self: &Arc<Self>, name: &str) -> Result<ReceiverStream<Result<ChannelEvent, Status>>, Status> {
+        let channel = self
+            .channels
+            .get(name)
+            .ok_or_else(|| Status::not_found(format!("no notification channel named {name:?}")))?;
+        let subscriber = Arc::new(Subscriber::default());
+        channel.subscribers().push(subscriber.clone());
+        info!(channel = %name, "Channel subscriber added");
+
+        let (tx, rx) = mpsc::channel(4);
+        let channels = self.clone();
+        let name     = name.to_owned();
+
+        tokio::spawn(async move {
+            'stream: loop {
+                tokio::select! {
+                    _ = subscriber.notify.notified() => {}
+                    _ = tx.closed() => break,
+                }
+                let (events, overflowed) = {
+                    let mut queue = subscriber.queue();
+                    (std::mem::take(&mut queue.events), queue.overflowed)
+                };
+                for event in events {
+                    if tx.send(Ok(event)).await.is_err() {
+                        break 'stream;
+                    }
+                }
+                if overflowed {
+                    let _ = tx
+                        .send(Err(Status::resource_exhausted(format!(
+                            "subscriber fell more than the buffer of channel {name:?} behind"
+                        ))))
+                        .await;
+                    break;
+                }
+            }
+
+            channels.channels[&name].subscribers().retain(|s| !Arc::ptr_eq(s, &subscriber));
+            info!(channel = %name, "Channel subscriber removed");
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+}
+
+/// Tail the change feed into the channels, for as long as the server runs.
+pub fn spawn_publisher(channels: Arc<Channels>) {
+    tokio::spawn(async move {
+        let engine         = channels.engine.clone();
+        let mut checkpoint = engine.checkpoint_sequence();
+        let mut expiry     = ExpiryTracker::new();
+        let mut cursor     = match engine.latest_sequence() {
+            Ok(latest) => latest,
+            Err(e) => {
+                error!(error = %e, "Notification channels stopped");
+                return;
+            }
+        };
+
+        loop {
+            let eng = engine.clone();
+            let fetched = tokio::task::spawn_blocking(move || {
+                let changes = eng.changes_since(cursor, BATCH_LIMIT)?;
+                if !changes.is_empty() {
+                    return Ok(changes);
+                }
+                eng.wait_for_changes(cursor, HEARTBEAT_INTERVAL).map(|_| Vec::new())
+            })
+            .await;
+
+            let changes = match fetched {
+                Ok(Ok(changes)) => changes,
+                // A replica installed a snapshot past the cursor: what came
+                // between is gone, so carry on from the snapshot.
+                Ok(Err(EngineError::SequenceUnavailable { checkpoint, .. })) => {
+                    warn!(from = cursor, to = checkpoint, "Notification channels skipped changes covered by a snapshot");
+                    cursor = checkpoint;
+                    expiry = ExpiryTracker::new();
+                    Vec::new()
+                }
+                Ok(Err(e)) => {
+                    error!(error = %e, "Notification channels failed to read the change feed");
+                    tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+                    continue;
+                }
+                Err(e) => {
+                    error!(error = %e, "Notification channels failed to read the change feed");
+                    continue;
+                }
+            };
+
+            let current = engine.checkpoint_sequence();
+            if current > checkpoint {
+                checkpoint = current;
+                channels.publish_checkpoint(current);
+            }
+
+            for change in changes {
+                cursor = change.sequence;
+                // Every change moves the tracker on.
+                let expired = expiry.is_expiry(&change.record);
+                channels.publish_change(change, expired);
+            }
+        }
+    });
+}
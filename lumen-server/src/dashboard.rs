@@ -0,0 +1,153 @@
+//! Grafana dashboard for the metrics the node exports, printed by
+//! `lumen-server --emit-dashboard` for import into Grafana.
+//!
+//! One row per group of `metrics::METRICS` and one panel per metric in it,
+//! so the dashboard always matches what `/metrics` serves: gauges are graphed
+//! as they are, counters as per-second rates and histograms as their P50 and
+//! P99.  A `datasource` variable picks the Prometheus to query and an
+//! `instance` variable the nodes to show.
+
+use metrics::Unit;
+use serde_json::{json, Value};
+
+use crate::metrics::{Kind, Metric, METRICS};
+
+/// Panels per line of the 24-column grid.
+const PANELS_PER_LINE: u64 = 3;
+const PANEL_WIDTH: u64 = 24 / PANELS_PER_LINE;
+const PANEL_HEIGHT: u64 = 8;
+
+/// Build the dashboard JSON.
+pub fn grafana() -> Value {
+    let mut panels = Vec::new();
+    let mut y      = 0;
+    let mut groups: Vec<&str> = METRICS.iter().map(|m| m.group).collect();
+    groups.dedup();
+
+    for group in groups {
+        panels.push(json!({
+            "id":        panels.len() + 1,
+            "type":      "row",
+            "title":     group,
+            "collapsed": false,
+            "gridPos":   { "x": 0, "y": y, "w": 24, "h": 1 },
+            "panels":    [],
+        }));
+        y += 1;
+
+        let members: Vec<&Metric> = METRICS.iter().filter(|m| m.group == group).collect();
+        for (i, metric) in members.iter().enumerate() {
+            let i = i as u64;
+            let grid = json!({
+                "x": (i % PANELS_PER_LINE) * PANEL_WIDTH,
+                "y": y + (i / PANELS_PER_LINE) * PANEL_HEIGHT,
+                "w": PANEL_WIDTH,
+                "h": PANEL_HEIGHT,
+            });
+            panels.push(panel(panels.len() + 1, metric, grid));
+        }
+        y += (members.len() as u64).div_ceil(PANELS_PER_LINE) * PANEL_HEIGHT;
+    }
+
+    json!({
+        "uid":           "lumen-kv",
+        "title":         "LumenKV",
+        "tags":          ["lumen-kv"],
+        "editable":      true,
+        "graphTooltip":  1,
+        "refresh":       "30s",
+        "schemaVersion": 39,
+        "time":          { "from": "now-1h", "to": "now" },
+        "templating":    { "list": [
+            {
+                "name":  "datasource",
+                "label": "Data source",
+                "type":  "datasource",
+                "query": "prometheus",
+            },
+            {
+                "name":       "instance",
+                "label":      "Instance",
+                "type":       "query",
+                "datasource": datasource(),
+                "query":      { "query": "label_values(lumen_engine_recoveries_total, instance)", "refId": "instance" },
+                "definition": "label_values(lumen_engine_recoveries_total, instance)",
+                "refresh":    2,
+                "multi":      true,
+                "includeAll": true,
+                "allValue":   ".*",
+                "current":    { "text": "All", "value": "$__all" },
+            },
+        ]},
+        "panels": panels,
+    })
+}
+
+fn datasource() -> Value {
+    json!({ "type": "prometheus", "uid": "${datasource}" })
+}
+
+fn panel(id: usize, metric: &Metric, grid: Value) -> Value {
+    let selector = r#"{instance=~"$instance"}"#;
+    let legend   = std::iter::once("{{instance}}".to_owned())
+        .chain(metric.labels.iter().map(|label| format!("{{{{{label}}}}}")))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let (targets, unit) = match metric.kind {
+        Kind::Gauge => (vec![target("A", format!("{}{selector}", metric.name), &legend)], gauge_unit(metric.unit)),
+        Kind::Counter => {
+            let expr = format!("rate({}{selector}[$__rate_interval])", metric.name);
+            (vec![target("A", expr, &legend)], rate_unit(metric.unit))
+        }
+        Kind::Histogram => {
+            let by       = ["le", "instance"].iter().chain(metric.labels).copied().collect::<Vec<_>>().join(", ");
+            let quantile = |q: f64| {
+                format!(
+                    "histogram_quantile({q}, sum by ({by}) (rate({}_bucket{selector}[$__rate_interval])))",
+                    metric.name
+                )
+            };
+            let targets = vec![
+                target("A", quantile(0.5), &format!("{legend} P50")),
+                target("B", quantile(0.99), &format!("{legend} P99")),
+            ];
+            (targets, gauge_unit(metric.unit))
+        }
+    };
+
+    json!({
+        "id":          id,
+        "type":        "timeseries",
+        "title":       metric.name,
+        "description": metric.help,
+        "datasource":  datasource(),
+        "gridPos":     grid,
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "options":     { "legend": { "displayMode": "list", "placement": "bottom" } },
+        "targets":     targets,
+    })
+}
+
+fn target(ref_id: &str, expr: String, legend: &str) -> Value {
+    json!({ "refId": ref_id, "datasource": datasource(), "expr": expr, "legendFormat": legend })
+}
+
+/// Grafana's name for `unit`.
+fn gauge_unit(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Bytes => "bytes",
+        Unit::Seconds => "s",
+        _ => "short",
+    }
+}
+
+/// Grafana's name for `unit` per second.  CPU seconds per second is the
+/// share of one core in use.
+fn rate_unit(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Bytes => "Bps",
+        Unit::Seconds => "percentunit",
+        _ => "ops",
+    }
+}
@@ -0,0 +1,142 @@
+//! Several databases in one process (`DATABASES`, `DATABASE_QUOTAS`).
+//!
+//! Besides its default database (`DATA_DIR`, set up by the rest of the
+//! configuration), a node can host named databases, so small tenants do not
+//! each need a process.  Each is an engine of its own in
+//! `DATA_DIR/databases/NAME`, with its own usage metering, write limits
+//! (`WRITE_LIMITS` applies to every engine separately), scan cursors, leases
+//! and storage quota.
+//!
+//! A request picks its database with the `x-lumen-database` header; one
+//! without it goes to the listener's default.  A database given a listener
+//! (`NAME=host:port`) is that listener's default, so its clients need no
+//! header.  Named databases serve the key-value API only: they are not
+//! replicated, sharded or multi-region, have no indexes, channels or
+//! sessions, and are not covered by the Admin service.  They share the
+//! node's request scheduler, so each tenant queues under `QOS_CONCURRENCY`
+//! like the rest.
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use anyhow::Context as _;
+use tonic::body::BoxBody;
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::server::NamedService;
+use tonic::Status;
+
+use lumen_core::{Engine, EngineOptions};
+
+use crate::kv::key_value_store_server::KeyValueStoreServer;
+use crate::service::KvService;
+
+/// Request metadata naming the database a request is for.
+pub const DATABASE_HEADER: &str = "x-lumen-database";
+
+/// A named database, as configured.
+#[derive(Debug, Clone)]
+pub struct DatabaseSpec {
+    pub name: String,
+    /// Where it is also served as the default database.
+    pub listener: Option<SocketAddr>,
+}
+
+/// Parse `DATABASES`: comma-separated `name` or `name=host:port` entries.
+pub fn parse_specs(specs: &[String]) -> anyhow::Result<Vec<DatabaseSpec>> {
+    let mut parsed: Vec<DatabaseSpec> = Vec::with_capacity(specs.len());
+    for spec in specs {
+        let (name, listener) = match spec.split_once('=') {
+            Some((name, addr)) => {
+                let addr = addr
+                    .parse()
+                    .with_context(|| format!("DATABASES entry `{spec}` must listen on a host:port"))?;
+                (name, Some(addr))
+            }
+            None => (spec.as_str(), None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            anyhow::bail!("DATABASES entry `{spec}`: names are letters, digits, `-` and `_`");
+        }
+        if parsed.iter().any(|db| db.name == name) {
+            anyhow::bail!("DATABASES names `{name}` twice");
+        }
+        parsed.push(DatabaseSpec { name: name.to_owned(), listener });
+    }
+    Ok(parsed)
+}
+
+/// Parse `DATABASE_QUOTAS`: comma-separated `name=bytes` entries, each a
+/// storage quota of a database in `databases`.
+pub fn parse_quotas(specs: &[String], databases: &[DatabaseSpec]) -> anyhow::Result<HashMap<String, u64>> {
+    let mut quotas = HashMap::new();
+    for spec in specs {
+        let (name, bytes) = spec
+            .split_once('=')
+            .and_then(|(name, bytes)| Some((name, bytes.parse::<u64>().ok()?)))
+            .with_context(|| format!("DATABASE_QUOTAS entry `{spec}` must be `name=bytes`"))?;
+        if !databases.iter().any(|db| db.name == name) {
+            anyhow::bail!("DATABASE_QUOTAS entry `{spec}` names no database in DATABASES");
+        }
+        quotas.insert(name.to_owned(), bytes);
+    }
+    Ok(quotas)
+}
+
+/// Open the engine of the database `name`, with its storage quota.
+pub fn open(name: &str, data_dir: &str, options: &EngineOptions, quota: Option<u64>) -> anyhow::Result<Engine> {
+    let dir    = format!("{data_dir}/databases/{name}");
+    let engine = Engine::open_with(&dir, options.within(format!("databases/{name}"))).with_context(|| format!("Failed to open database `{name}`"))?;
+    engine.set_storage_quota(quota);
+    Ok(engine)
+}
+
+type Server = KeyValueStoreServer<KvService>;
+
+/// The key-value service of a listener, passing each request to the service
+/// of the database its header names.
+#[derive(Clone)]
+pub struct DatabaseRouter {
+    default: Server,
+    databases: Arc<HashMap<String, Server>>,
+}
+
+impl DatabaseRouter {
+    pub fn new(default: Server, databases: Arc<HashMap<String, Server>>) -> Self {
+        Self { default, databases }
+    }
+}
+
+impl NamedService for DatabaseRouter {
+    const NAME: &'static str = <Server as NamedService>::NAME;
+}
+
+impl<B> Service<http::Request<B>> for DatabaseRouter
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let Some(name) = request.headers().get(DATABASE_HEADER) else {
+            return self.default.call(request);
+        };
+        let name = String::from_utf8_lossy(name.as_bytes()).into_owned();
+        match self.databases.get(&name) {
+            Some(server) => server.clone().call(request),
+            None => {
+                let status = Status::not_found(format!("no database named {name:?}"));
+                Box::pin(async move { Ok(status.to_http()) })
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! Serving an engine the application opened (`Server::builder`).
+//!
+//! An application that has an `Engine` open can serve it over gRPC from its
+//! own tokio runtime instead of running `lumen-server` beside it, and keep
+//! using the engine directly; integration tests start a node this way:
+//!
+//!   let engine = Engine::open(dir.path())?;
+//!   let server = Server::builder().engine(engine.clone()).bind(addr);
+//!   tokio::spawn(server.serve_with_shutdown(async move { let _ = stop.await; }));
+//!
+//! `serve_connections` serves connections the application makes itself
+//! instead, such as in-memory pipes, so tests reach the node without a
+//! socket (`lumen-testing` does this).
+//!
+//! The node is an unsharded primary over the engine's data directory, with
+//! no admin HTTP listener.  Its other settings are those given with
+//! `ServerBuilder::settings`, none by default: it only reads the environment
+//! if handed `Settings::from_env()`, so tests that start nodes do not depend
+//! on the environment they run in.  The engine stays the application's: the
+//! server does not checkpoint it when it shuts down.
+
+use std::future::Future;
+use std::net::SocketAddr;
+
+use anyhow::Context;
+use lumen_core::{Engine, EngineOptions};
+use tokio::io::DuplexStream;
+use tokio_stream::Stream;
+
+use crate::{Config, Connections, Layers, Role, Settings};
+
+/// Entry point of the embedded server; see `Server::builder`.
+#[derive(Debug)]
+pub struct Server;
+
+impl Server {
+    /// A builder for a server over an engine of the caller's.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+}
+
+/// A server to start over an engine of the caller's.
+#[derive(Debug, Default)]
+pub struct ServerBuilder {
+    engine: Option<Engine>,
+    bind_addr: Option<SocketAddr>,
+    layers: Layers,
+    settings: Settings,
+}
+
+impl ServerBuilder {
+    /// Serve `engine` (required).
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = Some(engine);
+        self
+    }
+
+    /// Listen on `addr` (default: 0.0.0.0:50051).  With
+    /// `serve_connections`, only the node's name.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind_addr = Some(addr);
+        self
+    }
+
+    /// Wrap every gRPC request in `layers`.
+    pub fn layers(mut self, layers: Layers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Read the settings beyond the builder's from `settings` (default:
+    /// none, each at its default).
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Serve until Ctrl-C or SIGTERM.
+    pub async fn serve(self) -> anyhow::Result<()> {
+        self.serve_with_shutdown(crate::shutdown_signal()).await
+    }
+
+    /// Serve until `signal` resolves.
+    pub async fn serve_with_shutdown(self, signal: impl Future<Output = ()> + Send + 'static) -> anyhow::Result<()> {
+        self.start(None, signal).await
+    }
+
+    /// Serve each connection `connections` yields, the server's end of a
+    /// `tokio::io::duplex` pair whose other end a client's channel was
+    /// given, instead of listening on a socket, until `signal` resolves.
+    pub async fn serve_connections(
+        self,
+        connections: impl Stream<Item = DuplexStream> + Send + 'static,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        self.start(Some(Box::pin(connections)), signal).await
+    }
+
+    async fn start(
+        self,
+        connections: Option<Connections>,
+        signal: impl Future<Output = ()> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let engine    = self.engine.context("Server::builder() needs an engine to serve")?;
+        let bind_addr = self.bind_addr.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 50051)));
+        let config    = Config {
+            data_dir: engine.data_dir().display().to_string(),
+            bind_addr,
+            role: Role::Primary,
+            node_id: bind_addr.to_string(),
+            replica_id: bind_addr.to_string(),
+            admin_addr: None,
+            profiling: false,
+            engine: EngineOptions { dedup_values: engine.dedup_values(), ..Default::default() },
+            checkpoint_on_shutdown: false,
+            settings: self.settings,
+        };
+        crate::serve(config, self.layers, Some(engine), connections, signal).await
+    }
+}
@@ -0,0 +1,93 @@
+//! Error codes on the wire (`kv.ErrorInfo`).
+//!
+//! A gRPC status code is too coarse to act on: a storage quota and a write
+//! limit are both RESOURCE_EXHAUSTED, a read-only replica and a trimmed log
+//! both FAILED_PRECONDITION.  Statuses made here also carry the failure's
+//! `lumen_core::ErrorCode` in their details, as a `kv.ErrorInfo` packed in a
+//! `google.rpc.Status`, and the gRPC code that goes with it.  Statuses made
+//! without it (a malformed request, say) carry none; clients fall back to
+//! their gRPC code.
+//!
+//! The details also say whether a failure is safe to retry: BACKPRESSURE
+//! and UNAVAILABLE are only ever returned before a call has any effect, so
+//! a client may repeat even a write without a request ID; a throttled write
+//! also says how long to wait (`retry_after_ms`).
+
+use std::time::Duration;
+
+use prost::Message;
+use tonic::{Code, Status};
+
+use lumen_core::{EngineError, ErrorCode};
+
+use crate::kv;
+
+/// Type URL of the `Any` holding a status's `kv.ErrorInfo`.
+pub const ERROR_INFO_TYPE_URL: &str = "type.googleapis.com/kv.ErrorInfo";
+
+/// A status for a failure of kind `code`, carrying it in its details.
+pub fn coded(code: ErrorCode, message: impl Into<String>) -> Status {
+    with_info(code, message.into(), None)
+}
+
+/// Status for a failed engine operation.
+///
+/// SEQUENCE_UNAVAILABLE tells a replica it has to bootstrap from a snapshot
+/// before it can stream again; BACKPRESSURE tells a client its namespace is
+/// over its write limit and when to retry.
+pub fn engine_status(e: EngineError) -> Status {
+    let retry_after = match &e {
+        EngineError::Throttled { retry_after, .. } => Some(*retry_after),
+        _ => None,
+    };
+    with_info(e.code(), e.to_string(), retry_after)
+}
+
+fn with_info(code: ErrorCode, message: String, retry_after: Option<Duration>) -> Status {
+    let grpc = grpc_code(code);
+    let info = kv::ErrorInfo {
+        code:           to_proto(code) as i32,
+        retry_after_ms: retry_after.map_or(0, |wait| wait.as_millis().max(1) as u64),
+        retryable:      matches!(code, ErrorCode::Backpressure | ErrorCode::Unavailable),
+    };
+    let details = kv::RpcStatus {
+        code:    grpc as i32,
+        message: message.clone(),
+        details: vec![kv::RpcStatusAny { type_url: ERROR_INFO_TYPE_URL.to_owned(), value: info.encode_to_vec() }],
+    };
+    Status::with_details(grpc, message, details.encode_to_vec().into())
+}
+
+/// The gRPC status code failures of kind `code` are sent with.
+fn grpc_code(code: ErrorCode) -> Code {
+    match code {
+        ErrorCode::Internal => Code::Internal,
+        ErrorCode::InvalidArgument => Code::InvalidArgument,
+        ErrorCode::NotFound => Code::NotFound,
+        ErrorCode::AlreadyExists => Code::AlreadyExists,
+        ErrorCode::PreconditionFailed | ErrorCode::ReadOnly | ErrorCode::SequenceUnavailable => {
+            Code::FailedPrecondition
+        }
+        ErrorCode::Conflict => Code::Aborted,
+        ErrorCode::QuotaExceeded | ErrorCode::Backpressure => Code::ResourceExhausted,
+        ErrorCode::Unavailable => Code::Unavailable,
+        ErrorCode::Corruption => Code::DataLoss,
+    }
+}
+
+fn to_proto(code: ErrorCode) -> kv::ErrorCode {
+    match code {
+        ErrorCode::Internal => kv::ErrorCode::Internal,
+        ErrorCode::InvalidArgument => kv::ErrorCode::InvalidArgument,
+        ErrorCode::NotFound => kv::ErrorCode::NotFound,
+        ErrorCode::AlreadyExists => kv::ErrorCode::AlreadyExists,
+        ErrorCode::PreconditionFailed => kv::ErrorCode::PreconditionFailed,
+        ErrorCode::Conflict => kv::ErrorCode::Conflict,
+        ErrorCode::QuotaExceeded => kv::ErrorCode::QuotaExceeded,
+        ErrorCode::Backpressure => kv::ErrorCode::Backpressure,
+        ErrorCode::ReadOnly => kv::ErrorCode::ReadOnly,
+        ErrorCode::Unavailable => kv::ErrorCode::Unavailable,
+        ErrorCode::SequenceUnavailable => kv::ErrorCode::SequenceUnavailable,
+        ErrorCode::Corruption => kv::ErrorCode::Corruption,
+    }
+}
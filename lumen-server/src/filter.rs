@@ -0,0 +1,85 @@
+//! Server-side scan filters (`ScanRequest.filter`).
+//!
+//! A filter tests each entry a scan reads, after region versions are
+//! resolved, and only entries passing every test are streamed: a key
+//! regular expression, value lengths, and JSON field equality.  A value
+//! that is not JSON fails a JSON test rather than the scan, since a range
+//! may well mix documents with other values.  Filters are compiled once per
+//! scan and kept with its cursor.
+
+use regex::{Regex, RegexBuilder};
+use serde_json::Value;
+use tonic::Status;
+
+use crate::kv::{value_predicate::Test, ScanFilter};
+
+/// Largest compiled key expression, so a filter cannot tie up the server.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+#[derive(Debug)]
+enum Predicate {
+    LongerThan(usize),
+    ShorterThan(usize),
+    JsonField { pointer: String, value: Value },
+}
+
+impl Predicate {
+    fn matches(&self, value: &[u8]) -> bool {
+        match self {
+            Predicate::LongerThan(len) => value.len() > *len,
+            Predicate::ShorterThan(len) => value.len() < *len,
+            Predicate::JsonField { pointer, value: expected } => serde_json::from_slice::<Value>(value)
+                .ok()
+                .is_some_and(|document| document.pointer(pointer) == Some(expected)),
+        }
+    }
+}
+
+/// A compiled `ScanFilter`.
+#[derive(Debug, Default)]
+pub struct Filter {
+    key: Option<Regex>,
+    values: Vec<Predicate>,
+}
+
+impl Filter {
+    /// Compile `filter`; INVALID_ARGUMENT if any of it is malformed.
+    pub fn compile(filter: ScanFilter) -> Result<Self, Status> {
+        let key = match filter.key_regex.as_str() {
+            "" => None,
+            pattern => Some(
+                RegexBuilder::new(pattern)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(|e| Status::invalid_argument(format!("invalid key_regex: {e}")))?,
+            ),
+        };
+
+        let mut values = Vec::with_capacity(filter.values.len());
+        for predicate in filter.values {
+            values.push(match predicate.test {
+                Some(Test::LongerThan(len)) => Predicate::LongerThan(len as usize),
+                Some(Test::ShorterThan(len)) => Predicate::ShorterThan(len as usize),
+                Some(Test::JsonField(field)) => {
+                    if !field.pointer.is_empty() && !field.pointer.starts_with('/') {
+                        return Err(Status::invalid_argument(format!(
+                            "JSON pointer {:?} must be empty or start with '/'",
+                            field.pointer
+                        )));
+                    }
+                    let value = serde_json::from_slice(&field.value)
+                        .map_err(|e| Status::invalid_argument(format!("json_field value is not valid JSON: {e}")))?;
+                    Predicate::JsonField { pointer: field.pointer, value }
+                }
+                None => return Err(Status::invalid_argument("value predicate has no test")),
+            });
+        }
+        Ok(Self { key, values })
+    }
+
+    /// Whether the entry of `key` and `value` is to be returned.
+    pub fn matches(&self, key: &str, value: &[u8]) -> bool {
+        self.key.as_ref().is_none_or(|key_regex| key_regex.is_match(key))
+            && self.values.iter().all(|predicate| predicate.matches(value))
+    }
+}
@@ -0,0 +1,149 @@
+//! Orphan file collection (`ORPHAN_GC_SECS`, `Admin/CollectOrphans`).
+//!
+//! A write interrupted by a crash leaves its temp file behind: an engine's
+//! half-written checkpoint, clock ceiling or WAL upgrade (see
+//! `Engine::collect_orphans`), the node's own `usage.tmp`, `cdc.tmp`,
+//! `region.tmp` or `ring.tmp`, or a `<backup_id>.partial` directory of an
+//! aborted backup.  Nothing reads them again, so without collection they
+//! hold their disk space for good.
+//!
+//! A file is only deleted once it has gone unmodified for the grace period
+//! (`ORPHAN_GRACE_SECS`), so a write still in progress, or a backup still
+//! being assembled, is never touched.  Complete backups and everything the
+//! engines read on open are never candidates.
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use tracing::{error, info};
+
+use lumen_core::Engine;
+
+use crate::service::Backend;
+
+/// Temp files of the node's own state files, next to them in `DATA_DIR`.
+const NODE_ORPHANS: &[&str] = &["usage.tmp", "cdc.tmp", "region.tmp", "ring.tmp"];
+
+/// What collects a node's orphaned files.
+#[derive(Debug)]
+pub struct OrphanCollector {
+    /// The node's engine, or a router's local shards and system engine.
+    engines: Vec<Engine>,
+    data_dir: PathBuf,
+    backup_dir: PathBuf,
+    /// Applied when a request names none.
+    grace: Duration,
+}
+
+impl OrphanCollector {
+    pub fn new(backend: &Backend, data_dir: PathBuf, backup_dir: PathBuf, grace: Duration) -> Self {
+        let engines = match backend {
+            Backend::Engine(engine) => vec![Engine::clone(engine.as_ref())],
+            Backend::Sharded(router) => router.local_engines(),
+        };
+        Self { engines, data_dir, backup_dir, grace }
+    }
+
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// Delete every orphan unmodified for `grace`.  Returns each with the
+    /// bytes it held.  Blocks; call from a blocking thread.
+    pub fn collect(&self, grace: Duration) -> anyhow::Result<Vec<(PathBuf, u64)>> {
+        let mut removed = Vec::new();
+        for engine in &self.engines {
+            removed.extend(engine.collect_orphans(grace)?);
+        }
+
+        for name in NODE_ORPHANS {
+            let path = self.data_dir.join(name);
+            let Some((modified, bytes)) = newest(&path)? else { continue };
+            if age(modified) >= grace {
+                remove(&path)?;
+                info!(path = %path.display(), bytes, "Deleted orphaned file");
+                removed.push((path, bytes));
+            }
+        }
+
+        let entries = match std::fs::read_dir(&self.backup_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(removed),
+            Err(e) => return Err(e).with_context(|| format!("failed to list {}", self.backup_dir.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension() != Some(OsStr::new("partial")) {
+                continue;
+            }
+            // A backup being assembled keeps touching its newest file.
+            let Some((modified, bytes)) = newest(&path)? else { continue };
+            if age(modified) >= grace {
+                remove(&path)?;
+                info!(path = %path.display(), bytes, "Deleted aborted backup");
+                removed.push((path, bytes));
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// The latest modification time under `path` and the bytes it holds, or
+/// `None` if it does not exist.
+fn newest(path: &Path) -> anyhow::Result<Option<(SystemTime, u64)>> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("failed to inspect {}", path.display())),
+    };
+    let mut modified = meta.modified()?;
+    let mut bytes    = meta.len();
+    if meta.is_dir() {
+        bytes = 0;
+        for entry in std::fs::read_dir(path)? {
+            if let Some((entry_modified, entry_bytes)) = newest(&entry?.path())? {
+                modified = modified.max(entry_modified);
+                bytes   += entry_bytes;
+            }
+        }
+    }
+    Ok(Some((modified, bytes)))
+}
+
+fn age(modified: SystemTime) -> Duration {
+    modified.elapsed().unwrap_or_default()
+}
+
+fn remove(path: &Path) -> anyhow::Result<()> {
+    let removed = if path.is_dir() { std::fs::remove_dir_all(path) } else { std::fs::remove_file(path) };
+    match removed {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("failed to delete {}", path.display())),
+    }
+}
+
+/// Collect orphans every `interval`, for as long as the server runs.
+pub fn spawn_collection(collector: Arc<OrphanCollector>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let collector = collector.clone();
+            let collected = tokio::task::spawn_blocking(move || collector.collect(collector.grace)).await;
+            match collected {
+                Ok(Ok(removed)) if !removed.is_empty() => {
+                    let bytes: u64 = removed.iter().map(|(_, bytes)| bytes).sum();
+                    info!(files = removed.len(), bytes, "Orphaned files collected");
+                }
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!(error = %format!("{e:#}"), "Orphan collection failed"),
+                Err(e) => error!(error = %e, "Orphan collection failed"),
+            }
+        }
+    });
+}
@@ -0,0 +1,100 @@
+//! The standard gRPC health service (`grpc.health.v1.Health`), for load
+//! balancers and orchestrators.
+//!
+//! The node as a whole (service `""`) and `kv.KeyValueStore` are SERVING,
+//! or NOT_SERVING while the node is in maintenance (see `maintenance`);
+//! `kv.Admin` stays SERVING, since maintenance is ended through it.
+//! `lumen.Storage` is not a gRPC service but the state of the files on
+//! disk: NOT_SERVING while the last scrub of any local engine found
+//! corruption (see `scrub`), so monitoring can tell before a restart needs
+//! the damaged files.
+
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use crate::grpc_health::health_check_response::ServingStatus;
+use crate::grpc_health::health_server::Health;
+use crate::grpc_health::{HealthCheckRequest, HealthCheckResponse};
+use crate::maintenance::{Maintenance, Mode};
+
+#[derive(Debug, Clone)]
+pub struct HealthService {
+    maintenance: Arc<Maintenance>,
+    /// Whether scrubbing has found corruption.
+    corrupt: watch::Receiver<bool>,
+}
+
+impl HealthService {
+    pub fn new(maintenance: Arc<Maintenance>, corrupt: watch::Receiver<bool>) -> Self {
+        Self { maintenance, corrupt }
+    }
+}
+
+/// `service`'s status in `mode`, or `None` if this node does not serve it.
+fn status(service: &str, mode: Mode, corrupt: bool) -> Option<ServingStatus> {
+    match (service, mode) {
+        ("lumen.Storage", _) if corrupt => Some(ServingStatus::NotServing),
+        ("lumen.Storage", _) => Some(ServingStatus::Serving),
+        ("kv.Admin", _) | ("" | "kv.KeyValueStore", Mode::Serving) => Some(ServingStatus::Serving),
+        ("" | "kv.KeyValueStore", Mode::Maintenance { .. }) => Some(ServingStatus::NotServing),
+        _ => None,
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse { status: status as i32 }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    type WatchStream = ReceiverStream<Result<HealthCheckResponse, Status>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = request.into_inner().service;
+        let status  = status(&service, self.maintenance.mode(), *self.corrupt.borrow())
+            .ok_or_else(|| Status::not_found(format!("unknown service `{service}`")))?;
+        Ok(Response::new(response(status)))
+    }
+
+    /// Stream `service`'s status now and on every change, for as long as
+    /// the caller listens.
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let service  = request.into_inner().service;
+        let mut mode = self.maintenance.subscribe();
+        let mut corrupt = self.corrupt.clone();
+        let (tx, rx) = mpsc::channel(4);
+
+        tokio::spawn(async move {
+            let mut sent = None;
+            loop {
+                let current = *mode.borrow_and_update();
+                let damaged = *corrupt.borrow_and_update();
+                let status  = status(&service, current, damaged).unwrap_or(ServingStatus::ServiceUnknown);
+                if sent != Some(status) {
+                    if tx.send(Ok(response(status))).await.is_err() {
+                        return;
+                    }
+                    sent = Some(status);
+                }
+                tokio::select! {
+                    // The sender lives as long as the server.
+                    changed = mode.changed() => if changed.is_err() { return },
+                    // Without scrubbing, nothing ever changes it.
+                    Ok(()) = corrupt.changed() => {}
+                    () = tx.closed() => return,
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
@@ -0,0 +1,120 @@
+//! Secondary indexes over JSON values (`INDEXES`, `QueryIndex` RPC).
+//!
+//! Each index is configured as `name=path`, where `path` is a dot-separated
+//! field path into a JSON object (`address.city`; a numeric segment indexes
+//! an array).  A key is found under the field's value: a string as its
+//! UTF-8 bytes, a number or boolean as its JSON text, and an array as each
+//! of its scalar elements.  Values that are not JSON, or lack the field,
+//! are not indexed.  The engine keeps the indexes (see `lumen_core::index`).
+
+use anyhow::Context;
+use serde_json::Value;
+
+use lumen_core::Engine;
+
+use crate::regions::Versioned;
+
+/// One configured index.
+#[derive(Debug, Clone)]
+pub struct IndexSpec {
+    pub name: String,
+    pub path: Vec<String>,
+}
+
+/// Parse `INDEXES`: comma-separated `name=path` entries.
+pub fn parse_specs(specs: &[String]) -> anyhow::Result<Vec<IndexSpec>> {
+    specs
+        .iter()
+        .map(|spec| {
+            let (name, path) = spec
+                .split_once('=')
+                .with_context(|| format!("INDEXES entry `{spec}` must be `name=field.path`"))?;
+            let path: Vec<String> = path.split('.').map(str::to_owned).collect();
+            if name.is_empty() || path.iter().any(String::is_empty) {
+                anyhow::bail!("INDEXES entry `{spec}` must be `name=field.path`");
+            }
+            Ok(IndexSpec { name: name.to_owned(), path })
+        })
+        .collect()
+}
+
+/// Register every index in `specs` on `engine`.  `versioned` unwraps
+/// multi-region envelopes first, so tombstones are not indexed.
+pub fn register(engine: &Engine, specs: Vec<IndexSpec>, versioned: bool) -> anyhow::Result<()> {
+    for IndexSpec { name, path } in specs {
+        engine
+            .register_index(name.clone(), move |value| match versioned {
+                true => Versioned::decode(value.to_vec()).value.map_or_else(Vec::new, |value| extract(&path, &value)),
+                false => extract(&path, value),
+            })
+            .with_context(|| format!("Failed to register index {name}"))?;
+    }
+    Ok(())
+}
+
+/// The index values of `value` at `path`.
+fn extract(path: &[String], value: &[u8]) -> Vec<Vec<u8>> {
+    let Ok(document) = serde_json::from_slice::<Value>(value) else {
+        return Vec::new();
+    };
+    let field = path.iter().try_fold(&document, |node, segment| match node {
+        Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => node.get(segment),
+    });
+    match field {
+        Some(Value::Array(items)) => items.iter().filter_map(scalar).collect(),
+        Some(field) => scalar(field).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+fn scalar(value: &Value) -> Option<Vec<u8>> {
+    match value {
+        Value::String(s) => Some(s.as_bytes().to_vec()),
+        Value::Number(_) | Value::Bool(_) => Some(value.to_string().into_bytes()),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(path: &str) -> Vec<String> {
+        path.split('.').map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn fields_are_indexed_by_their_scalar_values() {
+        let doc = br#"{"city": "Oslo", "zip": 150, "tags": ["a", 1, null, {}], "rooms": [{"n": 2}]}"#;
+        assert_eq!(extract(&path("city"), doc), [b"Oslo".to_vec()]);
+        assert_eq!(extract(&path("zip"), doc), [b"150".to_vec()]);
+        assert_eq!(extract(&path("tags"), doc), [b"a".to_vec(), b"1".to_vec()]);
+        assert_eq!(extract(&path("rooms.0.n"), doc), [b"2".to_vec()]);
+        assert!(extract(&path("street"), doc).is_empty());
+        assert!(extract(&path("city"), b"not json").is_empty());
+    }
+
+    #[test]
+    fn an_overwrite_or_delete_moves_the_key_out_of_the_index() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        register(&engine, parse_specs(&["by_city=address.city".to_owned()]).unwrap(), false).unwrap();
+
+        engine.put("u1".to_owned(), br#"{"address": {"city": "Oslo"}}"#.to_vec()).unwrap();
+        engine.put("u2".to_owned(), br#"{"address": {"city": "Oslo"}}"#.to_vec()).unwrap();
+        assert_eq!(engine.query_index("by_city", b"Oslo").unwrap(), ["u1", "u2"]);
+
+        engine.put("u1".to_owned(), br#"{"address": {"city": "Bergen"}}"#.to_vec()).unwrap();
+        engine.delete("u2").unwrap();
+        assert!(engine.query_index("by_city", b"Oslo").unwrap().is_empty());
+        assert_eq!(engine.query_index("by_city", b"Bergen").unwrap(), ["u1"]);
+    }
+
+    #[test]
+    fn malformed_specs_are_refused() {
+        for spec in ["by_city", "=city", "by_city=", "by_city=address..city"] {
+            assert!(parse_specs(&[spec.to_owned()]).is_err(), "{spec}");
+        }
+    }
+}
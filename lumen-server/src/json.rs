@@ -0,0 +1,107 @@
+//! JSON values read and changed in place (`GetField`, `PatchJson` RPCs, and
+//! `Put` with `VALUE_TYPE_JSON`).
+//!
+//! Values are stored as JSON text like any other bytes; the value type is
+//! checked when a value is written, not kept with it, so these operations
+//! parse whatever is stored and fail on values that are not JSON.
+//! `GetField` addresses one field with a JSON Pointer (RFC 6901), and
+//! `PatchJson` applies a JSON Merge Patch (RFC 7386) to the stored document.
+
+use serde_json::{Map, Value};
+use tonic::Status;
+
+use lumen_core::{Engine, EngineError, RedactedKey};
+
+use crate::errors::engine_status;
+
+#[derive(Debug)]
+pub enum JsonError {
+    /// The stored value does not parse.
+    NotJson(serde_json::Error),
+    /// A value sent to be stored does not parse.
+    InvalidValue(serde_json::Error),
+    InvalidPatch(serde_json::Error),
+    InvalidPointer(String),
+    /// The key to patch does not exist.
+    NotFound(String),
+    Engine(EngineError),
+}
+
+/// Status for a failed JSON operation: the request's fault, or the stored
+/// value's.
+pub fn json_status(e: JsonError) -> Status {
+    match e {
+        JsonError::NotJson(e) => Status::failed_precondition(format!("stored value is not JSON: {e}")),
+        JsonError::InvalidValue(e) => Status::invalid_argument(format!("value is not valid JSON: {e}")),
+        JsonError::InvalidPatch(e) => Status::invalid_argument(format!("patch is not valid JSON: {e}")),
+        JsonError::InvalidPointer(pointer) => {
+            Status::invalid_argument(format!("JSON pointer {pointer:?} must be empty or start with '/'"))
+        }
+        JsonError::NotFound(key) => Status::not_found(format!("key {} does not exist", RedactedKey(&key))),
+        JsonError::Engine(e) => engine_status(e),
+    }
+}
+
+/// Check that `value` is JSON, as a put of a JSON value requires.
+pub fn validate(value: &[u8]) -> Result<(), JsonError> {
+    serde_json::from_slice::<Value>(value).map_err(JsonError::InvalidValue)?;
+    Ok(())
+}
+
+/// The field of `stored` at `pointer`, as JSON text, or `None` if there is
+/// no such field.
+pub fn get_field(stored: &[u8], pointer: &str) -> Result<Option<Vec<u8>>, JsonError> {
+    if !pointer.is_empty() && !pointer.starts_with('/') {
+        return Err(JsonError::InvalidPointer(pointer.to_owned()));
+    }
+    let document: Value = serde_json::from_slice(stored).map_err(JsonError::NotJson)?;
+    Ok(document.pointer(pointer).map(|field| field.to_string().into_bytes()))
+}
+
+/// Merge `patch` into the value of `key` on `engine`, in one write, and
+/// return the patched document.
+pub fn patch(engine: &Engine, key: String, patch: &[u8]) -> Result<Vec<u8>, JsonError> {
+    // Parsed before the write lock is taken, which other writers wait on.
+    let patch: Value = serde_json::from_slice(patch).map_err(JsonError::InvalidPatch)?;
+
+    let mut failure = None;
+    let written = engine
+        .update(key.clone(), |stored| {
+            let mut document: Value = match serde_json::from_slice(stored?) {
+                Ok(document) => document,
+                Err(e) => {
+                    failure = Some(JsonError::NotJson(e));
+                    return None;
+                }
+            };
+            merge(&mut document, patch);
+            Some(document.to_string().into_bytes())
+        })
+        .map_err(JsonError::Engine)?;
+
+    match (written, failure) {
+        (Some(value), _) => Ok(value),
+        (None, Some(e)) => Err(e),
+        (None, None) => Err(JsonError::NotFound(key)),
+    }
+}
+
+/// RFC 7386: objects merge member by member, a `null` member removes the
+/// target's, and anything else replaces the target outright.
+fn merge(target: &mut Value, patch: Value) {
+    let Value::Object(members) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let Value::Object(target) = target else { unreachable!("made an object above") };
+    for (name, value) in members {
+        if value.is_null() {
+            target.remove(&name);
+        } else {
+            merge(target.entry(name).or_insert(Value::Null), value);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Request layers added by embedders (`Layers`).
+//!
+//! Programs that run the server through `run_server` can wrap every gRPC
+//! request in tower layers of their own: to authenticate callers, pull a
+//! tenant out of the metadata, record metrics of their own.  Layers see each
+//! request before any service does, on the main listener and on the
+//! listeners of named databases, and run in the order they were added: the
+//! first one added sees a request first and its response last.
+//!
+//!   let layers = Layers::new()
+//!       .interceptor(check_token)
+//!       .layer(tower::limit::ConcurrencyLimitLayer::new(512));
+//!   lumen_server::run_server(Config::from_env()?, layers).await
+//!
+//! A layer's service must answer with the `tonic::body::BoxBody` responses
+//! the services produce; one that changes the body type has to box it again.
+
+use std::fmt;
+use std::sync::Arc;
+
+use tonic::body::BoxBody;
+use tonic::codegen::http;
+use tonic::service::Interceptor;
+use tonic::transport::Body;
+use tower::util::BoxCloneService;
+use tower::{Layer, Service, ServiceExt};
+
+/// Errors a layer's service may fail with.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A request as layers see it.
+pub type HttpRequest = http::Request<Body>;
+
+/// A response as layers see it.
+pub type HttpResponse = http::Response<BoxBody>;
+
+/// What a layer wraps: the services, behind the layers added after it.
+pub type BoxService = BoxCloneService<HttpRequest, HttpResponse, BoxError>;
+
+type Wrap = Arc<dyn Fn(BoxService) -> BoxService + Send + Sync>;
+
+/// The layers to wrap every gRPC request in, outermost first.
+#[derive(Clone, Default)]
+pub struct Layers {
+    wraps: Vec<Wrap>,
+}
+
+impl Layers {
+    /// No layers: requests go straight to the services.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `layer`, inside the layers added so far.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxService> + Send + Sync + 'static,
+        L::Service: Service<HttpRequest, Response = HttpResponse> + Clone + Send + 'static,
+        <L::Service as Service<HttpRequest>>::Future: Send + 'static,
+        <L::Service as Service<HttpRequest>>::Error: Into<BoxError>,
+    {
+        self.wraps.push(Arc::new(move |inner| BoxCloneService::new(layer.layer(inner).map_err(Into::into))));
+        self
+    }
+
+    /// Add a tonic interceptor, which sees each request's metadata and may
+    /// reject it with a status (or add extensions for the services).
+    pub fn interceptor<F>(self, interceptor: F) -> Self
+    where
+        F: Interceptor + Clone + Send + Sync + 'static,
+    {
+        self.layer(tonic::service::interceptor(interceptor))
+    }
+}
+
+impl fmt::Debug for Layers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Layers").field("layers", &self.wraps.len()).finish()
+    }
+}
+
+impl<S> Layer<S> for Layers
+where
+    S: Service<HttpRequest, Response = HttpResponse> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+{
+    type Service = BoxService;
+
+    fn layer(&self, inner: S) -> BoxService {
+        let inner = BoxCloneService::new(inner.map_err(Into::into));
+        self.wraps.iter().rev().fold(inner, |service, wrap| wrap(service))
+    }
+}
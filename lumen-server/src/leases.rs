@@ -0,0 +1,119 @@
+//! Server side of leases (`GrantLease`, `KeepAlive`, `RevokeLease` RPCs)
+//! and the locks they hold (`Lock`, `Unlock`): the task that expires
+//! leases, the keep-alive streams that renew them, and the wait for a lock.
+//!
+//! The registry itself, and the keys it stores its leases under, live in
+//! `lumen_core::lease`.  Only an unsharded primary without a REGION keeps
+//! one: expiry deletes keys, which only the node that takes writes may do.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Streaming};
+use tracing::{error, info};
+
+use lumen_core::{Engine, LeaseError, Leases};
+
+use crate::errors::{self, engine_status};
+use crate::kv::{KeepAliveRequest, KeepAliveResponse};
+use crate::maintenance::Maintenance;
+
+/// How often leases are checked for expiry; a lease outlives its deadline
+/// by up to this much.
+const EXPIRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Longest TTL a lease may be granted.
+pub const MAX_TTL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Longest one `Lock` call waits for a lock; clients wanting to wait longer
+/// call again.
+pub const MAX_LOCK_WAIT: Duration = Duration::from_secs(60);
+
+/// Revoke leases as their deadlines pass, for as long as the server runs.
+pub fn spawn_expiry(leases: Arc<Leases>, maintenance: Arc<Maintenance>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let _paused = maintenance.pause().await;
+            match leases.expire() {
+                Ok(expired) => {
+                    for (lease, keys) in expired {
+                        info!(lease, keys, "Lease expired");
+                    }
+                }
+                Err(e) => error!(error = %e, "Lease expiry failed"),
+            }
+        }
+    });
+}
+
+/// Renew the lease of each request on `requests`, answering each with its
+/// TTL, or 0 once the lease is gone.
+pub fn stream_keep_alive(
+    leases: Arc<Leases>,
+    mut requests: Streaming<KeepAliveRequest>,
+) -> ReceiverStream<Result<KeepAliveResponse, Status>> {
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        loop {
+            let request = match requests.message().await {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(status) => {
+                    info!(error = %status.message(), "Keep-alive stream broken");
+                    break;
+                }
+            };
+
+            let response = match leases.keep_alive(request.id) {
+                Ok(ttl) => Ok(KeepAliveResponse { id: request.id, ttl_seconds: ttl.as_secs() }),
+                Err(LeaseError::NotFound(_)) => Ok(KeepAliveResponse { id: request.id, ttl_seconds: 0 }),
+                Err(e) => Err(lease_status(e)),
+            };
+            let failed = response.is_err();
+            if tx.send(response).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// Take lock `name` for `lease`, waiting up to `wait` for its holder to
+/// release it.  Returns the fencing token.
+pub async fn lock(
+    leases: Arc<Leases>,
+    engine: Arc<Engine>,
+    name: String,
+    lease: u64,
+    wait: Duration,
+) -> Result<u64, Status> {
+    let deadline = Instant::now() + wait;
+    tokio::task::spawn_blocking(move || loop {
+        // Any release commits after this sequence, so waiting on it cannot
+        // miss one.
+        let seen = engine.latest_sequence()?;
+        match leases.lock(&name, lease) {
+            Err(LeaseError::Locked { .. }) if Instant::now() < deadline => {
+                engine.wait_for_changes(seen, deadline.saturating_duration_since(Instant::now()))?;
+            }
+            result => return result,
+        }
+    })
+    .await
+    .map_err(|e| Status::internal(e.to_string()))?
+    .map_err(lease_status)
+}
+
+pub fn lease_status(e: LeaseError) -> Status {
+    match e {
+        LeaseError::Engine(e) => engine_status(e),
+        e => errors::coded(e.code(), e.to_string()),
+    }
+}
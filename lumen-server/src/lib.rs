@@ -0,0 +1,757 @@
+//! LumenKV server as a library.
+//!
+//! The `lumen-server` binary is a thin wrapper around `run_server`; forks
+//! and programs that embed the server call it themselves, with a `Config`
+//! built in code or read from the environment (`Config::from_env`) and the
+//! tower layers (`Layers`) to wrap every gRPC request in.  Settings beyond
+//! those in `Config` go by the environment variables documented in the
+//! binary (`src/main.rs`), and are read from the environment or given in
+//! code (`Settings`).  `run_server` does not install a tracing
+//! subscriber; the embedding program chooses its own.
+//!
+//! An application with an engine of its own serves it with
+//! `Server::builder()` instead (see `ServerBuilder`), over a socket or over
+//! connections it makes in-process.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::io::DuplexStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Server as GrpcServer;
+use tracing::{info, warn};
+
+mod admin;
+mod backup;
+mod cdc;
+mod channels;
+pub mod dashboard;
+mod databases;
+mod embedded;
+mod errors;
+mod filter;
+mod gc;
+mod health;
+mod indexes;
+mod json;
+pub mod layers;
+mod leases;
+mod logging;
+mod maintenance;
+mod membership;
+mod metrics;
+mod partitions;
+mod profiling;
+mod qos;
+mod regions;
+mod replication;
+mod scan;
+mod scrub;
+mod service;
+mod sessions;
+mod sharding;
+mod usage;
+mod watch;
+
+/// Generated protobuf / tonic types live inside this module.
+pub mod kv {
+    tonic::include_proto!("kv");
+}
+
+pub mod grpc_health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub use embedded::{Server, ServerBuilder};
+pub use layers::Layers;
+pub use replication::Role;
+
+use grpc_health::health_server::HealthServer;
+use kv::admin_server::AdminServer;
+use admin::AdminService;
+use cdc::{CdcConfig, SinkKind};
+use channels::Channels;
+use databases::DatabaseRouter;
+use gc::OrphanCollector;
+use health::HealthService;
+use logging::RequestLog;
+use lumen_core::KeyMode;
+use maintenance::Maintenance;
+use membership::{Membership, MembershipConfig};
+use regions::Regions;
+use replication::{HealthThresholds, ReplicationState};
+use scan::Cursors;
+use service::{Backend, KvService};
+use sessions::Sessions;
+use sharding::{Partitioning, ShardRouter};
+use qos::Scheduler;
+use usage::Usage;
+
+const FILE_DESCRIPTOR_SET: &[u8] =
+    tonic::include_file_descriptor_set!("kv_descriptor");
+
+/// Where a node keeps its data, what it listens on and the part it plays.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Directory for the WAL and checkpoint (`DATA_DIR`).
+    pub data_dir: String,
+    /// Address of the gRPC listener (`BIND_ADDR`).
+    pub bind_addr: SocketAddr,
+    /// Whether the node takes writes or streams them from a primary (`ROLE`,
+    /// `PRIMARY_ADDR`).
+    pub role: Role,
+    /// Cluster member name (`NODE_ID`).
+    pub node_id: String,
+    /// Name a replica reports to its primary (`REPLICA_ID`).
+    pub replica_id: String,
+    /// Address of the HTTP admin listener serving /metrics, if any
+    /// (`ADMIN_ADDR`).
+    pub admin_addr: Option<SocketAddr>,
+    /// Serve CPU and heap profiles on the admin listener (`PPROF`).
+    pub profiling: bool,
+    /// How the storage engines are opened (`WAL_*`, `DEDUP_MIN_BYTES`,
+    /// `MEMTABLE_FLUSH_BYTES`, `MERGE_MIN_TABLES`, `COLD_TIER_*`,
+    /// `--ignore-orphans`).
+    pub engine: lumen_core::EngineOptions,
+    /// Checkpoint each local engine on shutdown (`CHECKPOINT_ON_SHUTDOWN`).
+    pub checkpoint_on_shutdown: bool,
+    /// Every other setting (`SHARDS`, `SCRUB_RATE_BYTES`, ...), by the name
+    /// of its environment variable.
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Read the configuration from the environment, as the binary does.
+    pub fn from_env() -> anyhow::Result<Self> {
+        let env       = Settings::from_env();
+        let data_dir  = env.var("DATA_DIR").unwrap_or_else(|_| "./data".to_owned());
+        let bind_addr = env.var("BIND_ADDR")
+            .unwrap_or_else(|_| "0.0.0.0:50051".to_owned())
+            .parse::<SocketAddr>()
+            .context("BIND_ADDR must be a valid socket address (e.g. 0.0.0.0:50051)")?;
+
+        let role = match env.var("ROLE").as_deref() {
+            Ok("primary") | Err(_) => Role::Primary,
+            Ok(role @ ("replica" | "learner")) => Role::Replica {
+                primary_addr: env.var("PRIMARY_ADDR")
+                    .with_context(|| format!("PRIMARY_ADDR is required when ROLE={role}"))?,
+                learner: role == "learner",
+            },
+            Ok(other) => anyhow::bail!("ROLE must be `primary`, `replica` or `learner`, got `{other}`"),
+        };
+        let node_id    = env.var("NODE_ID").unwrap_or_else(|_| bind_addr.to_string());
+        let replica_id = env.var("REPLICA_ID").unwrap_or_else(|_| node_id.clone());
+
+        let admin_addr = match env.var("ADMIN_ADDR") {
+            Ok(addr) => Some(
+                addr.parse::<SocketAddr>()
+                    .context("ADMIN_ADDR must be a valid socket address (e.g. 0.0.0.0:9090)")?,
+            ),
+            Err(_) => None,
+        };
+        let profiling = match env.var("PPROF").as_deref() {
+            Ok("on") => true,
+            Ok("off") | Err(_) => false,
+            Ok(other) => anyhow::bail!("PPROF must be `on` or `off`, got `{other}`"),
+        };
+
+        let engine = lumen_core::EngineOptions {
+            wal: lumen_core::WalOptions {
+                commit_markers: match env.var("WAL_COMMIT_MARKERS").as_deref() {
+                    Ok("on") => true,
+                    Ok("off") | Err(_) => false,
+                    Ok(other) => anyhow::bail!("WAL_COMMIT_MARKERS must be `on` or `off`, got `{other}`"),
+                },
+                recovery: match env.var("WAL_RECOVERY").as_deref() {
+                    Ok("strict") | Err(_) => lumen_core::RecoveryMode::Strict,
+                    Ok("tolerant") => lumen_core::RecoveryMode::Tolerant,
+                    Ok(other) => anyhow::bail!("WAL_RECOVERY must be `strict` or `tolerant`, got `{other}`"),
+                },
+                ..Default::default()
+            },
+            ignore_orphans: std::env::args().any(|arg| arg == "--ignore-orphans"),
+            sync_policy: match env.var("WAL_SYNC").as_deref() {
+                Ok("os") | Err(_) => lumen_core::SyncPolicy::Os,
+                Ok("interval") => {
+                    lumen_core::SyncPolicy::Interval(Duration::from_micros(env.number("WAL_SYNC_WINDOW_US", 1000)?))
+                }
+                Ok("adaptive") => lumen_core::SyncPolicy::Adaptive {
+                    target_p99: Duration::from_millis(env.number("WAL_SYNC_TARGET_P99_MS", 10)?.max(1)),
+                },
+                Ok(other) => anyhow::bail!("WAL_SYNC must be `os`, `interval` or `adaptive`, got `{other}`"),
+            },
+            dedup_values: match env.number("DEDUP_MIN_BYTES", 0)? {
+                0 => None,
+                bytes => Some(bytes),
+            },
+            memtable_flush_bytes: match env.number("MEMTABLE_FLUSH_BYTES", 0)? {
+                0 => None,
+                bytes => Some(bytes),
+            },
+            merge: lumen_core::MergePolicy {
+                min_tables: env.number("MERGE_MIN_TABLES", 4)?,
+                ..Default::default()
+            },
+            cold_tier: match env.var("COLD_TIER_DIR") {
+                Ok(dir) => Some(lumen_core::ColdTier {
+                    after: match env.number("COLD_TIER_AFTER_SECS", 0)? {
+                        0 => None,
+                        secs => Some(Duration::from_secs(secs)),
+                    },
+                    cache_bytes:     env.number("COLD_TIER_CACHE_BYTES", 64 << 20)?,
+                    prefetch_blocks: env.number("COLD_TIER_PREFETCH_BLOCKS", 8)?,
+                    ..lumen_core::ColdTier::new(dir)
+                }),
+                Err(_) => None,
+            },
+        };
+        if engine.dedup_values.is_some() && engine.memtable_flush_bytes.is_some() {
+            anyhow::bail!(
+                "DEDUP_MIN_BYTES only shares values within checkpoints, which MEMTABLE_FLUSH_BYTES replaces \
+                 with tables that keep every copy; set one or the other"
+            );
+        }
+        let checkpoint_on_shutdown = match env.var("CHECKPOINT_ON_SHUTDOWN").as_deref() {
+            Ok("on") | Err(_) => true,
+            Ok("off") => false,
+            Ok(other) => anyhow::bail!("CHECKPOINT_ON_SHUTDOWN must be `on` or `off`, got `{other}`"),
+        };
+
+        Ok(Self {
+            data_dir,
+            bind_addr,
+            role,
+            node_id,
+            replica_id,
+            admin_addr,
+            profiling,
+            engine,
+            checkpoint_on_shutdown,
+            settings: env,
+        })
+    }
+}
+
+/// The settings a node reads beyond its `Config`, documented in the binary
+/// (`src/main.rs`) by the names of their environment variables: read from
+/// the environment (`Settings::from_env`), or only those given with `set`,
+/// so that a node embedded in a test reads nothing it was not handed.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    values: HashMap<String, String>,
+    /// Whether settings not given are read from the environment.
+    env: bool,
+}
+
+impl Settings {
+    /// The environment's settings.  Those given with `set` override them.
+    pub fn from_env() -> Self {
+        Self { values: HashMap::new(), env: true }
+    }
+
+    /// Set `name` to `value`, as the environment variable `name` would.
+    pub fn set(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.values.insert(name.into(), value.into());
+        self
+    }
+
+    /// The setting `name`, like `std::env::var`.
+    fn var(&self, name: &str) -> Result<String, std::env::VarError> {
+        match self.values.get(name) {
+            Some(value) => Ok(value.clone()),
+            None if self.env => std::env::var(name),
+            None => Err(std::env::VarError::NotPresent),
+        }
+    }
+
+    /// Parse the numeric setting `name`, or return `default`.
+    fn number<T: std::str::FromStr>(&self, name: &str, default: T) -> anyhow::Result<T> {
+        match self.var(name) {
+            Ok(value) => value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("{name} must be a non-negative integer, got `{value}`")),
+            Err(_) => Ok(default),
+        }
+    }
+
+    /// Parse the comma-separated setting `name` (empty if unset).
+    fn list(&self, name: &str) -> Vec<String> {
+        self.var(name)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+/// Open the node's engines, start its background tasks and serve it, with
+/// every gRPC request passing through `layers`, until Ctrl-C or SIGTERM.
+pub async fn run_server(config: Config, layers: Layers) -> anyhow::Result<()> {
+    serve(config, layers, None, None, shutdown_signal()).await
+}
+
+/// Connections handed to the main listener in-process, instead of accepted
+/// on `Config::bind_addr` (see `ServerBuilder::serve_connections`).
+type Connections = Pin<Box<dyn Stream<Item = DuplexStream> + Send>>;
+
+/// `run_server`, serving `engine` instead of opening one if it is given,
+/// and `connections` instead of the bind address, until `shutdown`
+/// resolves.
+async fn serve(
+    config: Config,
+    layers: Layers,
+    engine: Option<lumen_core::Engine>,
+    connections: Option<Connections>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> anyhow::Result<()> {
+    let Config {
+        data_dir,
+        bind_addr,
+        role,
+        node_id,
+        replica_id,
+        admin_addr,
+        profiling,
+        engine: options,
+        checkpoint_on_shutdown,
+        settings,
+    } = config;
+
+    let keys = settings.var("LOG_KEYS").unwrap_or_else(|_| "plain".to_owned());
+    lumen_core::redact::set_key_mode(
+        KeyMode::from_name(&keys)
+            .with_context(|| format!("LOG_KEYS must be `plain`, `hash` or `redact`, got `{keys}`"))?,
+    );
+    let request_log = Arc::new(RequestLog::new(settings.number("LOG_SAMPLE", 1)?));
+    logging::parse_levels(&request_log, &settings.list("LOG_LEVELS"))?;
+
+    // ── Metrics ──────────────────────────────────────────────────────────────
+    // Installed before the storage engine opens, so that its recovery is
+    // recorded too.
+    let admin_http = match admin_addr {
+        Some(addr) => Some((addr, metrics::install()?)),
+        None => None,
+    };
+    if profiling {
+        if admin_http.is_none() {
+            anyhow::bail!("PPROF=on requires ADMIN_ADDR, which serves the profiles");
+        }
+        profiling::check_supported()?;
+        profiling::activate_heap().await?;
+    }
+
+    // ── Storage engine ───────────────────────────────────────────────────────
+    let dedup_values = options.dedup_values;
+    let backend = match (engine, settings.var("SHARDS")) {
+        (Some(engine), _) => Backend::Engine(Arc::new(engine)),
+        (None, Ok(specs)) => {
+            if matches!(role, Role::Replica { .. }) {
+                anyhow::bail!("SHARDS cannot be combined with ROLE=replica; replicate the shard nodes instead");
+            }
+            let partitioning = match settings.var("PARTITIONING").as_deref() {
+                Ok("hash") | Err(_) => Partitioning::Hash {
+                    vnodes: settings.number("SHARD_VNODES", 128)?.max(1),
+                },
+                Ok("range") => Partitioning::Range {
+                    split_keys: settings.number("PARTITION_SPLIT_KEYS", 100_000)?,
+                    merge_keys: settings.number("PARTITION_MERGE_KEYS", 25_000)?,
+                },
+                Ok(other) => anyhow::bail!("PARTITIONING must be `hash` or `range`, got `{other}`"),
+            };
+
+            let (router, pending) = ShardRouter::open(&specs, &data_dir, partitioning, &options)
+                .context("Failed to open LumenKV shards")?;
+            let router = Arc::new(router);
+            if pending {
+                router.spawn_rebalance();
+            }
+            if let Partitioning::Range { .. } = partitioning {
+                let secs = settings.number("PARTITION_CHECK_SECS", 60)?;
+                router.spawn_partition_monitor(Duration::from_secs(secs.max(1)));
+            }
+            Backend::Sharded(router)
+        }
+        (None, Err(_)) => {
+            let engine = lumen_core::Engine::open_with(&data_dir, options.clone())
+                .context("Failed to open LumenKV storage engine")?;
+            Backend::Engine(Arc::new(engine))
+        }
+    };
+
+    // ── Named databases ──────────────────────────────────────────────────────
+    let database_specs = databases::parse_specs(&settings.list("DATABASES"))?;
+    let quotas = databases::parse_quotas(&settings.list("DATABASE_QUOTAS"), &database_specs)?;
+    if !database_specs.is_empty() && matches!(role, Role::Replica { .. }) {
+        anyhow::bail!("DATABASES cannot be combined with ROLE=replica; named databases are not replicated");
+    }
+    let mut named_engines = Vec::with_capacity(database_specs.len());
+    for spec in database_specs {
+        let engine = databases::open(&spec.name, &data_dir, &options, quotas.get(&spec.name).copied())?;
+        named_engines.push((spec, Arc::new(engine)));
+    }
+
+    let mut local_engines = match &backend {
+        Backend::Engine(engine) => vec![lumen_core::Engine::clone(engine)],
+        Backend::Sharded(router) => router.local_engines(),
+    };
+    local_engines.extend(named_engines.iter().map(|(_, engine)| lumen_core::Engine::clone(engine)));
+
+    // ── Write limits ─────────────────────────────────────────────────────────
+    for spec in settings.list("WRITE_LIMITS") {
+        let (namespace, rate) = spec
+            .split_once('=')
+            .and_then(|(namespace, rate)| Some((namespace, rate.parse::<u64>().ok()?)))
+            .with_context(|| format!("WRITE_LIMITS entry `{spec}` must be `namespace=bytes_per_sec`"))?;
+        for engine in &local_engines {
+            engine.set_write_limit(namespace, Some(rate));
+        }
+        info!(namespace, bytes_per_sec = rate, "Write limit set");
+    }
+
+    // ── Scrubbing ────────────────────────────────────────────────────────────
+    let corrupt = match settings.number("SCRUB_RATE_BYTES", 4 << 20)? {
+        0 => tokio::sync::watch::channel(false).1,
+        rate => {
+            let interval = Duration::from_secs(settings.number("SCRUB_INTERVAL_SECS", 3600)?.max(1));
+            scrub::spawn_scrub(local_engines.clone(), rate, interval)
+        }
+    };
+
+    info!(bind_addr = %bind_addr, data_dir = %data_dir, role = ?role, "LumenKV starting");
+
+    // ── Membership ───────────────────────────────────────────────────────────
+    let membership = Arc::new(Membership::new(MembershipConfig {
+        node_id,
+        advertise_addr: settings.var("ADVERTISE_ADDR").unwrap_or_else(|_| format!("http://{bind_addr}")),
+        role: role.node_role(),
+        seeds: settings.list("SEEDS"),
+        gossip_interval: Duration::from_millis(settings.number("GOSSIP_INTERVAL_MS", 1_000)?.max(1)),
+        suspect_timeout: Duration::from_millis(settings.number("SUSPECT_TIMEOUT_MS", 5_000)?),
+    }));
+    tokio::spawn(membership.clone().run());
+
+    // ── Replication ──────────────────────────────────────────────────────────
+    let thresholds = HealthThresholds {
+        max_lag_records:   settings.number("REPLICA_LAG_DEGRADED_RECORDS", 10_000)?,
+        max_lag:           Duration::from_secs(settings.number("REPLICA_LAG_DEGRADED_SECS", 30)?),
+        heartbeat_timeout: Duration::from_secs(settings.number("REPLICA_HEARTBEAT_TIMEOUT_SECS", 10)?),
+    };
+    let replication = Arc::new(ReplicationState::new(role.clone(), thresholds)?);
+    let maintenance = Arc::new(Maintenance::default());
+
+    // ── Regions ──────────────────────────────────────────────────────────────
+    let regions = match settings.var("REGION") {
+        Ok(region) => {
+            let peer_addr = settings.var("REGION_PEER").ok();
+            let Backend::Engine(engine) = &backend else {
+                anyhow::bail!("REGION cannot be combined with SHARDS; configure it on the shard nodes instead");
+            };
+            if peer_addr.is_some() && matches!(role, Role::Replica { .. }) {
+                anyhow::bail!("REGION_PEER is only valid on a primary");
+            }
+            let namespaces = settings.list("REGION_NAMESPACES");
+            let clock      = engine.clock().clone();
+            Some(Arc::new(Regions::new(region, namespaces, peer_addr, clock, &data_dir)?))
+        }
+        Err(_) if settings.var("REGION_PEER").is_ok() => anyhow::bail!("REGION_PEER requires REGION"),
+        Err(_) => None,
+    };
+
+    if let (Some(regions), Backend::Engine(engine)) = (&regions, &backend) {
+        engine.set_quota_exempt(Some(regions::is_tombstone));
+        if let Some(peer_addr) = regions.peer_addr() {
+            tokio::spawn(regions::run_region_peer(
+                engine.clone(),
+                regions.clone(),
+                maintenance.clone(),
+                peer_addr.to_owned(),
+            ));
+            let interval = Duration::from_secs(settings.number("REGION_TOMBSTONE_GC_SECS", 600)?.max(1));
+            tokio::spawn(regions::run_tombstone_gc(engine.clone(), regions.clone(), interval));
+        }
+    }
+
+    // ── Secondary indexes ────────────────────────────────────────────────────
+    let index_specs = indexes::parse_specs(&settings.list("INDEXES"))?;
+    if !index_specs.is_empty() {
+        let Backend::Engine(engine) = &backend else {
+            anyhow::bail!("INDEXES cannot be combined with SHARDS; configure it on the shard nodes instead");
+        };
+        indexes::register(engine, index_specs, regions.is_some())?;
+    }
+
+    // ── Notification channels ────────────────────────────────────────────────
+    let channel_specs = channels::parse_specs(&settings.list("CHANNELS"))?;
+    let channels = if channel_specs.is_empty() {
+        None
+    } else {
+        let Backend::Engine(engine) = &backend else {
+            anyhow::bail!("CHANNELS cannot be combined with SHARDS; configure it on the shard nodes instead");
+        };
+        let channels = Arc::new(Channels::new(engine.clone(), channel_specs, regions.is_some()));
+        channels::spawn_publisher(channels.clone());
+        Some(channels)
+    };
+
+    // ── Change-data capture ──────────────────────────────────────────────────
+    let cdc_sink = match settings.var("CDC_SINK").as_deref() {
+        Ok("kafka") => Some(SinkKind::Kafka),
+        Ok("nats") => Some(SinkKind::Nats),
+        Ok(other) => anyhow::bail!("CDC_SINK must be `kafka` or `nats`, got `{other}`"),
+        Err(_) => None,
+    };
+    if let Some(sink) = cdc_sink {
+        let Backend::Engine(engine) = &backend else {
+            anyhow::bail!("CDC_SINK cannot be combined with SHARDS; configure it on the shard nodes instead");
+        };
+        let brokers = settings.list("CDC_BROKERS");
+        if brokers.is_empty() {
+            anyhow::bail!("CDC_BROKERS is required when CDC_SINK is set");
+        }
+
+        let config = CdcConfig {
+            sink,
+            brokers,
+            topic: settings.var("CDC_TOPIC").unwrap_or_else(|_| "lumen.changes".to_owned()),
+            partition: settings.number("CDC_PARTITION", 0)?,
+            batch: settings.number("CDC_BATCH", 256)?.max(1),
+            versioned: regions.is_some(),
+        };
+        config.check_supported()?;
+        tokio::spawn(cdc::run_cdc(engine.clone(), config, data_dir.clone()));
+    }
+
+    // ── Leases ───────────────────────────────────────────────────────────────
+    // Expiring a lease deletes keys, so only the node that takes writes keeps
+    // the registry, and multi-region nodes (whose deletes are versioned) do
+    // without.
+    let leases = match (&role, &backend, &regions) {
+        (Role::Primary, Backend::Engine(engine), None) => {
+            let leases = Arc::new(lumen_core::Leases::open(lumen_core::Engine::clone(engine))?);
+            leases::spawn_expiry(leases.clone(), maintenance.clone());
+            Some(leases)
+        }
+        _ => None,
+    };
+
+    // ── Sessions ─────────────────────────────────────────────────────────────
+    // Commits go through the lease registry, so sessions are served where
+    // there is one.
+    let sessions = match (&leases, &backend) {
+        (Some(leases), Backend::Engine(engine)) => {
+            let idle     = Duration::from_secs(settings.number("SESSION_IDLE_SECS", 60)?.max(1));
+            let sessions = Arc::new(Sessions::new(engine.clone(), leases.clone(), idle));
+            sessions::spawn_sweep(sessions.clone());
+            Some(sessions)
+        }
+        _ => None,
+    };
+
+    if let (Role::Replica { primary_addr, .. }, Backend::Engine(engine)) = (role, &backend) {
+        tokio::spawn(replication::run_replica(
+            engine.clone(),
+            replication.clone(),
+            maintenance.clone(),
+            primary_addr,
+            replica_id,
+        ));
+    }
+
+    // ── Scan cursors ─────────────────────────────────────────────────────────
+    let cursor_ttl       = Duration::from_secs(settings.number("SCAN_CURSOR_TTL_SECS", 300)?.max(1));
+    let snapshot_max_age = Duration::from_secs(settings.number("SCAN_SNAPSHOT_MAX_SECS", 60)?.max(1));
+    let cursors = Arc::new(Cursors::new(cursor_ttl, snapshot_max_age));
+    scan::spawn_sweep(cursors.clone());
+
+    // ── Usage metering ───────────────────────────────────────────────────────
+    let usage = Arc::new(Usage::open(&data_dir)?);
+    let measured = match &backend {
+        Backend::Engine(engine) => Some(engine.clone()),
+        Backend::Sharded(_) => None,
+    };
+    usage::spawn_refresh(usage.clone(), measured);
+
+    // ── Request scheduling ───────────────────────────────────────────────────
+    let scheduler = Arc::new(Scheduler::new(settings.number("QOS_CONCURRENCY", 0)?));
+
+    // ── Admin ────────────────────────────────────────────────────────────────
+    let backup_dir = settings.var("BACKUP_DIR").unwrap_or_else(|_| format!("{data_dir}/backups"));
+    let orphans    = Arc::new(OrphanCollector::new(
+        &backend,
+        data_dir.clone().into(),
+        backup_dir.clone().into(),
+        Duration::from_secs(settings.number("ORPHAN_GRACE_SECS", 3600)?),
+    ));
+    if let Ok(cold_dir) = settings.var("BACKUP_COLD_DIR") {
+        backup::spawn_tiering(Arc::new(backup::Tiering {
+            hot:   backup_dir.clone().into(),
+            cold:  cold_dir.into(),
+            after: Duration::from_secs(settings.number("BACKUP_COLD_AFTER_SECS", 7 * 24 * 3600)?),
+        }));
+    }
+    match settings.number("ORPHAN_GC_SECS", 3600)? {
+        0 => {}
+        secs => gc::spawn_collection(orphans.clone(), Duration::from_secs(secs)),
+    }
+    let admin = AdminService::new(
+        backend.clone(),
+        replication.clone(),
+        membership.clone(),
+        usage.clone(),
+        maintenance.clone(),
+        orphans,
+        request_log.clone(),
+        backup_dir.into(),
+        dedup_values,
+    );
+
+    if let Some((addr, handle)) = admin_http {
+        admin::spawn_http(addr, admin.clone(), handle, profiling)?;
+    }
+
+    // ── Named database services ──────────────────────────────────────────────
+    let database_names: Vec<String> = named_engines.iter().map(|(spec, _)| spec.name.clone()).collect();
+    let mut named = HashMap::new();
+    for (spec, engine) in &named_engines {
+        let usage = Arc::new(Usage::open(&format!("{data_dir}/databases/{}", spec.name))?);
+        usage::spawn_refresh(usage.clone(), Some(engine.clone()));
+        let leases = Arc::new(lumen_core::Leases::open(lumen_core::Engine::clone(engine))?);
+        leases::spawn_expiry(leases.clone(), maintenance.clone());
+        let cursors = Arc::new(Cursors::new(cursor_ttl, snapshot_max_age));
+        scan::spawn_sweep(cursors.clone());
+
+        let service = KvService::new(
+            Backend::Engine(engine.clone()),
+            Arc::new(ReplicationState::new(Role::Primary, thresholds)?),
+            membership.clone(),
+            None,
+            Some(leases),
+            None,
+            None,
+            database_names.clone(),
+            cursors,
+            usage,
+            scheduler.clone(),
+            maintenance.clone(),
+            request_log.clone(),
+        );
+        named.insert(spec.name.clone(), service.into_server());
+    }
+    let named = Arc::new(named);
+
+    let (stop, stopped) = tokio::sync::watch::channel(());
+    tokio::spawn(async move {
+        shutdown.await;
+        let _ = stop.send(());
+    });
+    let until_stopped = |mut stopped: tokio::sync::watch::Receiver<()>| async move {
+        let _ = stopped.changed().await;
+    };
+
+    let mut listeners = Vec::new();
+    for (spec, _) in &named_engines {
+        let Some(addr) = spec.listener else { continue };
+        info!(database = %spec.name, addr = %addr, "Serving database on its own listener");
+        let router = DatabaseRouter::new(named[&spec.name].clone(), named.clone());
+        let server = GrpcServer::builder()
+            .layer(layers.clone())
+            .add_service(router)
+            .add_service(HealthServer::new(HealthService::new(maintenance.clone(), corrupt.clone())))
+            .serve_with_shutdown(addr, until_stopped(stopped.clone()));
+        listeners.push((spec.name.clone(), tokio::spawn(server)));
+    }
+
+    // ── gRPC server ──────────────────────────────────────────────────────────
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build()
+        .context("Failed to build gRPC reflection service")?;
+
+    let kv = KvService::new(
+        backend,
+        replication,
+        membership,
+        regions,
+        leases,
+        sessions,
+        channels,
+        database_names,
+        cursors,
+        usage,
+        scheduler,
+        maintenance.clone(),
+        request_log,
+    )
+    .into_server();
+    let server = GrpcServer::builder()
+        .layer(layers)
+        .add_service(DatabaseRouter::new(kv, named))
+        .add_service(AdminServer::new(admin))
+        .add_service(HealthServer::new(HealthService::new(maintenance, corrupt)))
+        .add_service(reflection);
+    let served = match connections {
+        Some(connections) => {
+            let incoming = connections.map(Ok::<_, std::io::Error>);
+            server.serve_with_incoming_shutdown(incoming, until_stopped(stopped)).await
+        }
+        None => server.serve_with_shutdown(bind_addr, until_stopped(stopped)).await,
+    };
+    served.context("gRPC server exited with an error")?;
+    for (database, listener) in listeners {
+        match listener.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!(database, error = %e, "Database listener exited with an error"),
+            Err(e) => warn!(database, error = %e, "Database listener panicked"),
+        }
+    }
+
+    // ── Shutdown ─────────────────────────────────────────────────────────────
+    if checkpoint_on_shutdown {
+        for engine in &local_engines {
+            let started = std::time::Instant::now();
+            match engine.shutdown() {
+                Ok(Some(sequence)) => info!(
+                    data_dir = %engine.data_dir().display(),
+                    sequence,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "Checkpoint written for a fast restart"
+                ),
+                Ok(None) => {}
+                Err(e) => warn!(data_dir = %engine.data_dir().display(), error = %e, "Shutdown checkpoint failed"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Resolve on Ctrl-C or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!(error = %e, "Cannot watch for SIGTERM; only Ctrl-C shuts down cleanly");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+    info!("Shutting down");
+}
@@ -0,0 +1,202 @@
+//! Request logging (`LOG_LEVELS`, `LOG_SAMPLE`, `LOG_KEYS`, `Admin/Logging`).
+//!
+//! Every key-value RPC logs one line per request.  At production rates
+//! that floods the log and writes key material to it, so the lines are
+//! configurable:
+//!
+//!   * per method, the level they are logged at, or `off`
+//!     (`LOG_LEVELS=Get=off,Put=debug`, `*` for every method; default
+//!     info).  `RUST_LOG` still filters them like any other event.
+//!   * sampling: log one request in `LOG_SAMPLE` of each method (default:
+//!     every request).
+//!
+//! Levels and sampling can be changed at runtime through `Admin/Logging`
+//! (`lumen-ctl log`).  Keys in these lines, in failure lines (which are
+//! never sampled) and in error messages are shown as `LOG_KEYS` says (see
+//! `lumen_core::redact`).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{RwLock, RwLockReadGuard};
+
+use anyhow::Context;
+use tonic::Status;
+use tracing::Level;
+
+/// The RPCs whose requests are logged, by their proto names.
+pub const METHODS: &[&str] = &[
+    "Put",
+    "Get",
+    "MultiGet",
+    "Delete",
+    "BatchPut",
+    "CompareAndDelete",
+    "GetAndSet",
+    "Rename",
+    "QueryIndex",
+    "GetField",
+    "PatchJson",
+    "Watch",
+    "Scan",
+    "Subscribe",
+    "GrantLease",
+    "RevokeLease",
+    "Expire",
+    "Persist",
+    "Lock",
+    "Unlock",
+    "Replicate",
+    "Snapshot",
+    "Rebalance",
+    "Capabilities",
+];
+
+/// The level a method's requests are logged at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    Off,
+    Trace,
+    Debug,
+    Info,
+}
+
+impl Verbosity {
+    fn parse(level: &str) -> Option<Self> {
+        match level {
+            "off" => Some(Verbosity::Off),
+            "trace" => Some(Verbosity::Trace),
+            "debug" => Some(Verbosity::Debug),
+            "info" => Some(Verbosity::Info),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verbosity::Off => "off",
+            Verbosity::Trace => "trace",
+            Verbosity::Debug => "debug",
+            Verbosity::Info => "info",
+        }
+    }
+
+    fn level(self) -> Option<Level> {
+        match self {
+            Verbosity::Off => None,
+            Verbosity::Trace => Some(Level::TRACE),
+            Verbosity::Debug => Some(Level::DEBUG),
+            Verbosity::Info => Some(Level::INFO),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Settings {
+    /// Indexed like `METHODS`.
+    levels: Vec<Verbosity>,
+    /// Log one request in this many.
+    sample: u64,
+}
+
+/// Request logging settings, shared by the services.
+#[derive(Debug)]
+pub struct RequestLog {
+    settings: RwLock<Settings>,
+    /// Requests seen of each method, indexed like `METHODS`.
+    seen: Vec<AtomicU64>,
+}
+
+impl RequestLog {
+    pub fn new(sample: u64) -> Self {
+        Self {
+            settings: RwLock::new(Settings { levels: vec![Verbosity::Info; METHODS.len()], sample: sample.max(1) }),
+            seen: METHODS.iter().map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn settings(&self) -> RwLockReadGuard<'_, Settings> {
+        self.settings.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn sample(&self) -> u64 {
+        self.settings().sample
+    }
+
+    /// Every method with its level.
+    pub fn levels(&self) -> Vec<(&'static str, Verbosity)> {
+        METHODS.iter().copied().zip(self.settings().levels.iter().copied()).collect()
+    }
+
+    /// Apply `method=level` settings (`*` for every method), and the sampling
+    /// rate unless it is 0.  Nothing is changed if any setting is invalid.
+    pub fn update<'a>(
+        &self,
+        levels: impl IntoIterator<Item = (&'a str, &'a str)>,
+        sample: u64,
+    ) -> Result<(), Status> {
+        let mut parsed = Vec::new();
+        for (method, level) in levels {
+            let verbosity = Verbosity::parse(level).ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "log level for {method} must be off, trace, debug or info, got {level:?}"
+                ))
+            })?;
+            let index = match method {
+                "*" => None,
+                method => Some(
+                    METHODS
+                        .iter()
+                        .position(|m| *m == method)
+                        .ok_or_else(|| Status::invalid_argument(format!("no logged method named {method:?}")))?,
+                ),
+            };
+            parsed.push((index, verbosity));
+        }
+
+        let mut settings = self.settings.write().unwrap_or_else(|e| e.into_inner());
+        for (index, verbosity) in parsed {
+            match index {
+                Some(index) => settings.levels[index] = verbosity,
+                None => settings.levels.fill(verbosity),
+            }
+        }
+        if sample > 0 {
+            settings.sample = sample;
+        }
+        Ok(())
+    }
+
+    /// The level to log this request of `method` at, or `None` if it is
+    /// off or not sampled.
+    pub fn sampled(&self, method: &str) -> Option<Level> {
+        let index    = METHODS.iter().position(|m| *m == method)?;
+        let settings = self.settings();
+        let level    = settings.levels[index].level()?;
+        if settings.sample > 1 && self.seen[index].fetch_add(1, Ordering::Relaxed) % settings.sample != 0 {
+            return None;
+        }
+        Some(level)
+    }
+}
+
+/// Parse `LOG_LEVELS`: comma-separated `method=level` entries.
+pub fn parse_levels(log: &RequestLog, specs: &[String]) -> anyhow::Result<()> {
+    let mut levels = Vec::with_capacity(specs.len());
+    for spec in specs {
+        levels.push(spec.split_once('=').with_context(|| format!("LOG_LEVELS entry `{spec}` must be `method=level`"))?);
+    }
+    log.update(levels, 0).map_err(|status| anyhow::anyhow!("LOG_LEVELS: {}", status.message()))
+}
+
+/// Log one request of `method` (a name in `METHODS`) through `log`, at the
+/// method's level and if it is sampled; the rest is as for `tracing::info!`.
+macro_rules! rpc_log {
+    ($log:expr, $method:literal, $($arg:tt)+) => {
+        match $log.sampled($method) {
+            Some(tracing::Level::INFO) => tracing::info!($($arg)+),
+            Some(tracing::Level::DEBUG) => tracing::debug!($($arg)+),
+            Some(_) => tracing::trace!($($arg)+),
+            None => {}
+        }
+    };
+}
+pub(crate) use rpc_log;
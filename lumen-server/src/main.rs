@@ -1,69 +1,145 @@
 //! LumenKV — gRPC server entry point.
 //!
 //! Configuration is read from environment variables:
-//!   DATA_DIR  – directory for WAL & future SSTables (default: ./data)
-//!   BIND_ADDR – host:port to listen on              (default: 0.0.0.0:50051)
-//!   RUST_LOG  – tracing filter (default: info)
-
-use std::net::SocketAddr;
-use std::sync::Arc;
+//!   DATA_DIR     – directory for WAL & future SSTables (default: ./data)
+//!   WAL_COMMIT_MARKERS – `on` or `off`: end each record of new WALs with a commit
+//!                  marker, so torn appends are told apart from corruption (default: off)
+//!   WAL_RECOVERY – `strict`: refuse to start on a damaged WAL record; `tolerant`: drop it
+//!                  and the rest of the log, logging where it was cut, and start (default: strict)
+//!   WAL_SYNC     – `os`: leave syncing commits to the OS; `interval`: every commit returns
+//!                  once synced, sharing syncs with the commits within WAL_SYNC_WINDOW_US;
+//!                  `adaptive`: the same, with the window tuned to keep the p99 commit
+//!                  latency near WAL_SYNC_TARGET_P99_MS (default: os)
+//!   WAL_SYNC_WINDOW_US – group commit window of `WAL_SYNC=interval` (default: 1000)
+//!   WAL_SYNC_TARGET_P99_MS – target commit latency of `WAL_SYNC=adaptive` (default: 10)
+//!   DEDUP_MIN_BYTES – write each value of at least this many bytes once per checkpoint and
+//!                  backup, however many keys hold it; the WAL and memory keep every copy,
+//!                  and it cannot be combined with MEMTABLE_FLUSH_BYTES (default: 0, off)
+//!   MEMTABLE_FLUSH_BYTES – flush each engine's memtable to an SSTable once its keys and values
+//!                  take more than this many bytes, so memory holds only recent writes
+//!                  (default: 0, off: every key stays in memory)
+//!   MERGE_MIN_TABLES – merge each engine's newest SSTables in the background once this many
+//!                  of similar sizes pile up, so reads look in fewer (default: 4; 0: never)
+//!   COLD_TIER_DIR – slower, cheaper storage each engine's base SSTable is moved to, in a
+//!                  subdirectory per shard or database like DATA_DIR's (default: none)
+//!   COLD_TIER_AFTER_SECS – also move other SSTables there once they are this old
+//!                  (default: 0, only the base table)
+//!   COLD_TIER_CACHE_BYTES – blocks read from cold SSTables kept in memory, per engine
+//!                  (default: 67108864)
+//!   COLD_TIER_PREFETCH_BLOCKS – blocks of a cold SSTable read ahead of a scan into that cache
+//!                  (default: 8; 0: none)
+//!   CHECKPOINT_ON_SHUTDOWN – `on` or `off`: on SIGTERM or Ctrl-C, checkpoint each local engine
+//!                  and empty its WAL, so the next start loads the checkpoint instead of
+//!                  replaying the log (default: on)
+//!   BIND_ADDR    – host:port to listen on              (default: 0.0.0.0:50051)
+//!   ROLE         – `primary`, `replica`, or `learner` (a replica whose lag never
+//!                  makes it degraded; there is no witness role)
+//!                                                      (default: primary)
+//!   PRIMARY_ADDR – primary URL, required when ROLE=replica or learner (e.g. http://10.0.0.1:50051)
+//!   REPLICA_ID   – name reported to the primary        (default: NODE_ID)
+//!   SHARDS       – comma-separated shard list; `name` opens DATA_DIR/name,
+//!                  `name=http://host:port` routes to a remote node (default: unsharded)
+//!   SHARD_VNODES – virtual nodes per shard on the hash ring (default: 128)
+//!   PARTITIONING – `hash` or `range` placement of keys on shards (default: hash)
+//!   PARTITION_SPLIT_KEYS – range partitions above this many keys are split (default: 100000)
+//!   PARTITION_MERGE_KEYS – adjacent partitions below this many keys are merged (default: 25000)
+//!   PARTITION_CHECK_SECS – interval between split/merge passes (default: 60)
+//!   REGION       – region name; enables versioned values for multi-region replication
+//!   REGION_PEER  – primary URL of the other region to import writes from (primary only)
+//!   REGION_NAMESPACES – comma-separated namespaces to import (default: all)
+//!   REGION_TOMBSTONE_GC_SECS – interval between collections of tombstones both regions
+//!                  are past, on a primary with REGION_PEER (default: 600)
+//!   DATABASES    – comma-separated named databases besides the default one, each `name`
+//!                  (data in DATA_DIR/databases/name, picked by the `x-lumen-database`
+//!                  header) or `name=host:port` (also served there as the default)
+//!                  (default: none)
+//!   DATABASE_QUOTAS – comma-separated `name=bytes` storage quotas of named databases
+//!                  (default: none)
+//!   WRITE_LIMITS – comma-separated `namespace=bytes_per_sec` limits on the puts of each
+//!                  namespace; writes over a limit fail with RESOURCE_EXHAUSTED (default: none)
+//!   INDEXES      – comma-separated secondary indexes over JSON values, each
+//!                  `name=field.path` (default: none)
+//!   CHANNELS     – comma-separated notification channels, each `name=item+item...`
+//!                  with items `writes[:NS]`, `expirations[:NS]`, `checkpoints`,
+//!                  `buffer=N` and `drop=oldest|newest|disconnect` (default: none)
+//!   REPLICA_LAG_DEGRADED_RECORDS – replicas lagging more records are degraded (default: 10000)
+//!   REPLICA_LAG_DEGRADED_SECS – replicas lagging longer are degraded (default: 30)
+//!   REPLICA_HEARTBEAT_TIMEOUT_SECS – replicas silent this long are degraded (default: 10)
+//!   QOS_CONCURRENCY – requests run at once before the rest queue by priority class
+//!                  (default: 0, no limit)
+//!   SCAN_CURSOR_TTL_SECS – scan cursors unused this long are dropped (default: 300)
+//!   SCAN_SNAPSHOT_MAX_SECS – a scan stream older than this ends its page early with a
+//!                  cursor, releasing its snapshot (default: 60)
+//!   SESSION_IDLE_SECS – interactive sessions idle this long are aborted (default: 60)
+//!   BACKUP_DIR   – default destination of the Admin Backup RPC (default: DATA_DIR/backups)
+//!   BACKUP_COLD_DIR – slower, cheaper storage that old backups are moved to
+//!                  (default: backups stay in BACKUP_DIR)
+//!   BACKUP_COLD_AFTER_SECS – age at which a backup moves to BACKUP_COLD_DIR (default: 604800)
+//!   SCRUB_RATE_BYTES – bytes per second at which each local engine's WAL and checkpoint
+//!                  are re-read to verify their checksums; 0 disables scrubbing
+//!                  (default: 4194304)
+//!   SCRUB_INTERVAL_SECS – interval between scrub passes (default: 3600)
+//!   ORPHAN_GC_SECS – interval between deletions of files interrupted writes left behind
+//!                  (default: 3600; 0 leaves them to the CollectOrphans RPC)
+//!   ORPHAN_GRACE_SECS – such files are only deleted once unmodified this long (default: 3600)
+//!   ADMIN_ADDR   – host:port for the HTTP admin listener serving /metrics (default: disabled)
+//!   PPROF        – `on` or `off`: serve CPU and heap profiles under /debug/pprof on ADMIN_ADDR
+//!                  (default: off; needs the `pprof` or `jemalloc` cargo feature)
+//!   CDC_SINK     – `kafka` or `nats`: publish committed changes (needs the matching cargo feature)
+//!   CDC_BROKERS  – Kafka bootstrap brokers or NATS server URLs, comma-separated
+//!   CDC_TOPIC    – Kafka topic / NATS subject            (default: lumen.changes)
+//!   CDC_PARTITION – Kafka partition written to           (default: 0)
+//!   CDC_BATCH    – maximum changes per published batch   (default: 256)
+//!   NODE_ID      – cluster member name                 (default: BIND_ADDR)
+//!   ADVERTISE_ADDR – URL peers use to reach this node   (default: http://BIND_ADDR)
+//!   SEEDS        – comma-separated member URLs to join through (default: none)
+//!   GOSSIP_INTERVAL_MS – membership probe period         (default: 1000)
+//!   SUSPECT_TIMEOUT_MS – suspect → dead timeout          (default: 5000)
+//!   RUST_LOG     – tracing filter (default: info)
+//!   LOG_LEVELS   – comma-separated `Method=level` request log levels, `off`, `trace`,
+//!                  `debug` or `info`; `*` for every method (default: info)
+//!   LOG_SAMPLE   – log one request in this many of each method (default: 1)
+//!   LOG_KEYS     – `plain`, `hash` or `redact`: how keys appear in logs and error
+//!                  messages; `hash` shows a stable hash prefix (default: plain)
+//!   TOKIO_CONSOLE_BIND – where `tokio-console` attaches, with the `tokio-console` feature
+//!                  (default: 127.0.0.1:6669)
+//!
+//! `lumen-server --emit-dashboard` prints a Grafana dashboard for the
+//! metrics served on ADMIN_ADDR and exits.  `lumen-server --ignore-orphans`
+//! starts even if a data directory holds temp files an interrupted write
+//! left behind, which the engine otherwise refuses (see `Engine::open`).
+//!
+//! Everything else lives in the `lumen_server` library, whose `run_server`
+//! embedders call with their own configuration and request layers.
 
-use anyhow::Context;
-use tonic::transport::Server;
-use tracing::info;
+use tracing_subscriber::prelude::*;
 use tracing_subscriber::EnvFilter;
 
-mod service;
-
-/// Generated protobuf / tonic types live inside this module.
-pub mod kv {
-    tonic::include_proto!("kv");
-}
-
-use kv::key_value_store_server::KeyValueStoreServer;
-use service::KvService;
+use lumen_server::{dashboard, Config, Layers};
 
-const FILE_DESCRIPTOR_SET: &[u8] =
-    tonic::include_file_descriptor_set!("kv_descriptor");
+// The console reads task events that tokio only emits when built unstable.
+#[cfg(all(feature = "tokio-console", not(tokio_unstable)))]
+compile_error!("the `tokio-console` feature requires RUSTFLAGS=\"--cfg tokio_unstable\"");
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    if std::env::args().any(|arg| arg == "--emit-dashboard") {
+        println!("{:#}", dashboard::grafana());
+        return Ok(());
+    }
+
     // ── Logging ─────────────────────────────────────────────────────────────
     let filter = EnvFilter::try_from_default_env()
         .unwrap_or_else(|_| EnvFilter::new("lumen_server=info,lumen_core=info"));
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_target(true)
-        .compact()
-        .init();
-
-    // ── Configuration ────────────────────────────────────────────────────────
-    let data_dir  = std::env::var("DATA_DIR").unwrap_or_else(|_| "./data".to_owned());
-    let bind_addr = std::env::var("BIND_ADDR")
-        .unwrap_or_else(|_| "0.0.0.0:50051".to_owned())
-        .parse::<SocketAddr>()
-        .context("BIND_ADDR must be a valid socket address (e.g. 0.0.0.0:50051)")?;
-
-    // ── Storage engine ───────────────────────────────────────────────────────
-    let engine = lumen_core::Engine::open(&data_dir)
-        .context("Failed to open LumenKV storage engine")?;
-    let engine = Arc::new(engine);
-
-    info!(bind_addr = %bind_addr, data_dir = %data_dir, "LumenKV starting");
-
-    // ── gRPC server ──────────────────────────────────────────────────────────
-    let reflection = tonic_reflection::server::Builder::configure()
-        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
-        .build()
-        .context("Failed to build gRPC reflection service")?;
-
-    Server::builder()
-        .add_service(KeyValueStoreServer::new(KvService::new(engine)))
-        .add_service(reflection)
-        .serve(bind_addr)
-        .await
-        .context("gRPC server exited with an error")?;
+    let registry = tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_target(true).compact().with_filter(filter));
+    // The console layer takes the runtime's own task events, whatever
+    // RUST_LOG says, and serves them to `tokio-console` on
+    // TOKIO_CONSOLE_BIND (default: 127.0.0.1:6669).
+    #[cfg(feature = "tokio-console")]
+    let registry = registry.with(console_subscriber::spawn());
+    registry.init();
 
-    Ok(())
+    lumen_server::run_server(Config::from_env()?, Layers::new()).await
 }
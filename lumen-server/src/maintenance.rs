@@ -0,0 +1,99 @@
+//! Maintenance mode (`EnterMaintenance`, `ExitMaintenance` admin RPCs), for
+//! snapshotting a node's disk or upgrading it without killing in-flight
+//! work.
+//!
+//! Entering refuses new writes with UNAVAILABLE, waits for the writes in
+//! flight to finish and reports NOT_SERVING on the gRPC health service (see
+//! `health`), so load balancers move traffic away; the admin service then
+//! syncs the WAL.  Reads are refused too unless the operator asked to keep
+//! serving them.  The node's own writers (lease expiry, a replica applying
+//! its primary's stream, a region importing from its peer) pause until
+//! maintenance ends, so the WAL does not change meanwhile.
+//! Lease keep-alives only touch memory and carry on.
+
+use tokio::sync::{watch, RwLock, RwLockReadGuard};
+use tonic::Status;
+
+use lumen_core::ErrorCode;
+
+use crate::errors;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Serving,
+    Maintenance { serve_reads: bool },
+}
+
+#[derive(Debug)]
+pub struct Maintenance {
+    /// Held shared by every write while it runs, and exclusively to drain
+    /// them.
+    writes: RwLock<()>,
+    mode: watch::Sender<Mode>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self { writes: RwLock::new(()), mode: watch::channel(Mode::Serving).0 }
+    }
+}
+
+impl Maintenance {
+    pub fn mode(&self) -> Mode {
+        *self.mode.borrow()
+    }
+
+    /// Told of every change of mode.
+    pub fn subscribe(&self) -> watch::Receiver<Mode> {
+        self.mode.subscribe()
+    }
+
+    /// Admit a client write, which runs while the guard is held.  Refused in
+    /// maintenance.
+    pub async fn write(&self) -> Result<RwLockReadGuard<'_, ()>, Status> {
+        // Checked under the lock, so a write admitted is one `enter` drains.
+        let guard = self.writes.read().await;
+        match self.mode() {
+            Mode::Serving => Ok(guard),
+            Mode::Maintenance { .. } => Err(maintenance_status()),
+        }
+    }
+
+    /// `Some` if client reads are refused.
+    pub fn check_read(&self) -> Option<Status> {
+        match self.mode() {
+            Mode::Maintenance { serve_reads: false } => Some(maintenance_status()),
+            Mode::Serving | Mode::Maintenance { serve_reads: true } => None,
+        }
+    }
+
+    /// Wait until the node's own writers may write, which they do while the
+    /// guard is held.
+    pub async fn pause(&self) -> RwLockReadGuard<'_, ()> {
+        let mut mode = self.mode.subscribe();
+        loop {
+            let guard = self.writes.read().await;
+            if self.mode() == Mode::Serving {
+                return guard;
+            }
+            drop(guard);
+            // The sender lives as long as `self`.
+            let _ = mode.wait_for(|mode| *mode == Mode::Serving).await;
+        }
+    }
+
+    /// Enter maintenance, returning once every write admitted before has
+    /// finished.  Entering again only changes `serve_reads`.
+    pub async fn enter(&self, serve_reads: bool) {
+        self.mode.send_replace(Mode::Maintenance { serve_reads });
+        drop(self.writes.write().await);
+    }
+
+    pub fn exit(&self) {
+        self.mode.send_replace(Mode::Serving);
+    }
+}
+
+fn maintenance_status() -> Status {
+    errors::coded(ErrorCode::Unavailable, "node is in maintenance; try another node")
+}
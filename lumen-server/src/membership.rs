@@ -0,0 +1,428 @@
+//! Cluster membership and failure detection (SWIM-style gossip).
+//!
+//! Every `GOSSIP_INTERVAL` a node probes one member, chosen by walking a
+//! shuffled member list, with a `Gossip` call that also carries its whole
+//! member list (the reply carries the peer's).  A member that does not answer
+//! is probed indirectly through a few others via `PingReq`; if nobody gets an
+//! answer it becomes `SUSPECT`, and a suspect that does not refute within
+//! `SUSPECT_TIMEOUT` is declared `DEAD`.
+//!
+//! Each node owns an incarnation number.  When it hears that it is suspected
+//! (or dead — e.g. after a restart) it bumps its incarnation past the rumour
+//! and gossips itself as alive again, which overrides the older record.
+//! Dead members are forgotten after `DEAD_RETENTION`.
+//!
+//! Nodes without any known members contact the `SEEDS` list to join.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use tokio::task::JoinSet;
+use tonic::transport::Channel;
+use tracing::{info, warn};
+
+use crate::kv::{
+    key_value_store_client::KeyValueStoreClient,
+    ClusterMember, ClusterStatusResponse, GossipRequest, MemberState, MemberUpdate,
+    NodeRole, PingReqRequest,
+};
+
+/// How long a direct or indirect probe waits for an answer.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+/// Members asked to probe a target that missed a direct probe.
+const INDIRECT_PROBES: usize = 3;
+/// How long dead members are still reported (and gossiped) before removal.
+const DEAD_RETENTION: Duration = Duration::from_secs(300);
+
+/// Static settings of the local node.
+#[derive(Debug, Clone)]
+pub struct MembershipConfig {
+    pub node_id: String,
+    /// URL other members use to reach this node.
+    pub advertise_addr: String,
+    pub role: NodeRole,
+    /// URLs contacted while no member is known.
+    pub seeds: Vec<String>,
+    pub gossip_interval: Duration,
+    pub suspect_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct Member {
+    addr: String,
+    role: NodeRole,
+    incarnation: u64,
+    state: MemberState,
+    /// When `state` last changed.
+    changed: Instant,
+    /// Last successful direct probe.
+    last_contact: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct ProbeOrder {
+    /// Node ids still to probe in this round.
+    pending: Vec<String>,
+}
+
+/// Membership view of the local node.
+#[derive(Debug)]
+pub struct Membership {
+    config: MembershipConfig,
+    incarnation: AtomicU64,
+    members: Mutex<HashMap<String, Member>>,
+    probe_order: Mutex<ProbeOrder>,
+    clients: Mutex<HashMap<String, KeyValueStoreClient<Channel>>>,
+}
+
+/// Whether `update` supersedes a record at `incarnation` in `state`.
+fn supersedes(update: &MemberUpdate, incarnation: u64, state: MemberState) -> bool {
+    update.incarnation > incarnation
+        || (update.incarnation == incarnation && update.state > state as i32)
+}
+
+impl Membership {
+    pub fn new(config: MembershipConfig) -> Self {
+        Self {
+            config,
+            incarnation: AtomicU64::new(0),
+            members: Mutex::new(HashMap::new()),
+            probe_order: Mutex::new(ProbeOrder::default()),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn client(&self, addr: &str) -> Option<KeyValueStoreClient<Channel>> {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(client) = clients.get(addr) {
+            return Some(client.clone());
+        }
+
+        match Channel::from_shared(addr.to_owned()) {
+            Ok(endpoint) => {
+                let client = KeyValueStoreClient::new(endpoint.connect_timeout(PROBE_TIMEOUT).connect_lazy());
+                clients.insert(addr.to_owned(), client.clone());
+                Some(client)
+            }
+            Err(e) => {
+                warn!(addr, error = %e, "Invalid member address");
+                None
+            }
+        }
+    }
+
+    /// Every record this node gossips: itself first, then all members.
+    fn digest(&self) -> Vec<MemberUpdate> {
+        let mut updates = vec![MemberUpdate {
+            node_id:     self.config.node_id.clone(),
+            addr:        self.config.advertise_addr.clone(),
+            role:        self.config.role as i32,
+            incarnation: self.incarnation.load(Ordering::SeqCst),
+            state:       MemberState::Alive as i32,
+        }];
+
+        let members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        updates.extend(members.iter().map(|(id, m)| MemberUpdate {
+            node_id:     id.clone(),
+            addr:        m.addr.clone(),
+            role:        m.role as i32,
+            incarnation: m.incarnation,
+            state:       m.state as i32,
+        }));
+        updates
+    }
+
+    /// Fold gossiped records into the local view.
+    fn merge(&self, updates: Vec<MemberUpdate>) {
+        let now = Instant::now();
+        let mut members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+
+        for update in updates {
+            let state = MemberState::try_from(update.state).unwrap_or(MemberState::Alive);
+
+            if update.node_id == self.config.node_id {
+                // Refute rumours of our own failure.
+                let current = self.incarnation.load(Ordering::SeqCst);
+                if state != MemberState::Alive && update.incarnation >= current {
+                    self.incarnation.store(update.incarnation + 1, Ordering::SeqCst);
+                    info!(incarnation = update.incarnation + 1, "Refuted suspicion of this node");
+                }
+                continue;
+            }
+
+            match members.get_mut(&update.node_id) {
+                Some(member) => {
+                    if !supersedes(&update, member.incarnation, member.state) {
+                        continue;
+                    }
+                    if member.state != state {
+                        info!(node_id = %update.node_id, from = ?member.state, to = ?state, "Member state changed");
+                        member.changed = now;
+                    }
+                    member.addr        = update.addr;
+                    member.role        = NodeRole::try_from(update.role).unwrap_or(NodeRole::Unspecified);
+                    member.incarnation = update.incarnation;
+                    member.state       = state;
+                }
+                // Nothing to learn from the death of a node we never knew.
+                None if state == MemberState::Dead => {}
+                None => {
+                    info!(node_id = %update.node_id, addr = %update.addr, "Member joined");
+                    members.insert(update.node_id, Member {
+                        addr:         update.addr,
+                        role:         NodeRole::try_from(update.role).unwrap_or(NodeRole::Unspecified),
+                        incarnation:  update.incarnation,
+                        state,
+                        changed:      now,
+                        last_contact: None,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Answer a `Gossip` call: merge the caller's view and return ours.
+    pub fn handle_gossip(&self, request: GossipRequest) -> Vec<MemberUpdate> {
+        self.merge(request.members);
+        self.digest()
+    }
+
+    /// Exchange member lists with `addr`; `true` if it answered in time.
+    pub async fn ping(&self, addr: &str) -> bool {
+        let Some(mut client) = self.client(addr) else { return false };
+        let request = GossipRequest { members: self.digest() };
+
+        match tokio::time::timeout(PROBE_TIMEOUT, client.gossip(request)).await {
+            Ok(Ok(response)) => {
+                self.merge(response.into_inner().members);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Address of member `node_id`, unless it is unknown or already dead.
+    pub fn live_member_addr(&self, node_id: &str) -> Option<String> {
+        let members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        members
+            .get(node_id)
+            .filter(|m| m.state != MemberState::Dead)
+            .map(|m| m.addr.clone())
+    }
+
+    /// Declare `node_id` dead without waiting for the failure detector (an
+    /// operator removed it).  The record spreads with the next gossip.
+    pub fn declare_dead(&self, node_id: &str) {
+        let mut members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(member) = members.get_mut(node_id) {
+            warn!(node_id, addr = %member.addr, "Member removed by operator");
+            member.state   = MemberState::Dead;
+            member.changed = Instant::now();
+        }
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.config.node_id
+    }
+
+    /// Ask `via` to probe `target`.
+    async fn ping_via(&self, via: &str, target: &str) -> bool {
+        let Some(mut client) = self.client(via) else { return false };
+        let request = PingReqRequest { target_addr: target.to_owned() };
+
+        // The helper's own probe needs up to `PROBE_TIMEOUT` as well.
+        match tokio::time::timeout(PROBE_TIMEOUT * 2, client.ping_req(request)).await {
+            Ok(Ok(response)) => response.into_inner().ack,
+            _ => false,
+        }
+    }
+
+    /// Next member to probe, reshuffling once every member has had a turn.
+    fn next_target(&self) -> Option<(String, String)> {
+        let members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        let mut order = self.probe_order.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            if order.pending.is_empty() {
+                order.pending = members
+                    .iter()
+                    .filter(|(_, m)| m.state != MemberState::Dead)
+                    .map(|(id, _)| id.clone())
+                    .collect();
+                if order.pending.is_empty() {
+                    return None;
+                }
+                order.pending.shuffle(&mut rand::thread_rng());
+            }
+
+            let id = order.pending.pop()?;
+            if let Some(m) = members.get(&id).filter(|m| m.state != MemberState::Dead) {
+                return Some((id, m.addr.clone()));
+            }
+        }
+    }
+
+    /// One protocol period: probe a member, falling back to indirect probes.
+    async fn probe(self: &Arc<Self>) {
+        let Some((target_id, target_addr)) = self.next_target() else {
+            for seed in &self.config.seeds {
+                if seed != &self.config.advertise_addr && self.ping(seed).await {
+                    info!(seed = %seed, "Joined cluster via seed");
+                    break;
+                }
+            }
+            return;
+        };
+
+        let mut acked = self.ping(&target_addr).await;
+
+        if !acked {
+            let helpers: Vec<String> = {
+                let members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+                let candidates: Vec<String> = members
+                    .iter()
+                    .filter(|(id, m)| **id != target_id && m.state == MemberState::Alive)
+                    .map(|(_, m)| m.addr.clone())
+                    .collect();
+                candidates
+                    .choose_multiple(&mut rand::thread_rng(), INDIRECT_PROBES)
+                    .cloned()
+                    .collect()
+            };
+
+            let mut probes = JoinSet::new();
+            for via in helpers {
+                let membership = self.clone();
+                let target     = target_addr.clone();
+                probes.spawn(async move { membership.ping_via(&via, &target).await });
+            }
+            while let Some(result) = probes.join_next().await {
+                if matches!(result, Ok(true)) {
+                    acked = true;
+                    break;
+                }
+            }
+        }
+
+        let mut members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(member) = members.get_mut(&target_id) else { return };
+
+        if acked {
+            member.last_contact = Some(Instant::now());
+        } else if member.state == MemberState::Alive {
+            warn!(node_id = %target_id, addr = %target_addr, "Member missed probes; suspecting it");
+            member.state   = MemberState::Suspect;
+            member.changed = Instant::now();
+        }
+    }
+
+    /// Declare timed-out suspects dead and forget long-dead members.
+    fn expire(&self) {
+        let mut members = self.members.lock().unwrap_or_else(|e| e.into_inner());
+
+        for (id, member) in members.iter_mut() {
+            if member.state == MemberState::Suspect && member.changed.elapsed() >= self.config.suspect_timeout {
+                warn!(node_id = %id, addr = %member.addr, "Member declared dead");
+                member.state   = MemberState::Dead;
+                member.changed = Instant::now();
+            }
+        }
+        members.retain(|_, m| m.state != MemberState::Dead || m.changed.elapsed() < DEAD_RETENTION);
+    }
+
+    /// Membership snapshot for the `ClusterStatus` RPC.
+    pub fn status(&self) -> ClusterStatusResponse {
+        let mut members = vec![ClusterMember {
+            node_id:     self.config.node_id.clone(),
+            addr:        self.config.advertise_addr.clone(),
+            role:        self.config.role as i32,
+            state:       MemberState::Alive as i32,
+            incarnation: self.incarnation.load(Ordering::SeqCst),
+            local:       true,
+            millis_since_contact: 0,
+        }];
+
+        let known = self.members.lock().unwrap_or_else(|e| e.into_inner());
+        let mut others: Vec<ClusterMember> = known
+            .iter()
+            .map(|(id, m)| ClusterMember {
+                node_id:     id.clone(),
+                addr:        m.addr.clone(),
+                role:        m.role as i32,
+                state:       m.state as i32,
+                incarnation: m.incarnation,
+                local:       false,
+                millis_since_contact: m
+                    .last_contact
+                    .map_or(0, |t| t.elapsed().as_millis() as u64),
+            })
+            .collect();
+        others.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        members.extend(others);
+
+        ClusterStatusResponse {
+            node_id: self.config.node_id.clone(),
+            members,
+        }
+    }
+
+    /// Run the failure detector until the process exits.
+    pub async fn run(self: Arc<Self>) {
+        info!(
+            node_id = %self.config.node_id,
+            addr    = %self.config.advertise_addr,
+            seeds   = ?self.config.seeds,
+            "Membership started"
+        );
+
+        let mut ticker = tokio::time::interval(self.config.gossip_interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            self.probe().await;
+            self.expire();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn membership(role: NodeRole) -> Membership {
+        Membership::new(MembershipConfig {
+            node_id:         "self".to_owned(),
+            advertise_addr:  "http://127.0.0.1:1".to_owned(),
+            role,
+            seeds:           Vec::new(),
+            gossip_interval: Duration::from_secs(1),
+            suspect_timeout: Duration::from_secs(5),
+        })
+    }
+
+    #[test]
+    fn learners_are_listed_as_learners_in_the_cluster_status() {
+        let membership = membership(NodeRole::Learner);
+        let gossiped = |node_id: &str, role: NodeRole| MemberUpdate {
+            node_id:     node_id.to_owned(),
+            addr:        format!("http://{node_id}:50051"),
+            role:        role as i32,
+            incarnation: 1,
+            state:       MemberState::Alive as i32,
+        };
+        membership.merge(vec![gossiped("primary", NodeRole::Primary), gossiped("analytics", NodeRole::Learner)]);
+
+        let status = membership.status();
+        let roles: Vec<(&str, i32)> = status.members.iter().map(|m| (m.node_id.as_str(), m.role)).collect();
+        assert_eq!(roles, [
+            ("self", NodeRole::Learner as i32),
+            ("analytics", NodeRole::Learner as i32),
+            ("primary", NodeRole::Primary as i32),
+        ]);
+        // And it gossips itself as one.
+        assert_eq!(membership.digest()[0].role, NodeRole::Learner as i32);
+    }
+}
@@ -0,0 +1,619 @@
+//! Prometheus metrics.
+//!
+//! Values are recorded through the `metrics` facade and rendered in the
+//! Prometheus text format by the admin HTTP listener.  Replica health gauges
+//! are refreshed from `ReplicationState` on every scrape, labelled by
+//! `replica_id`; series of replicas that have gone away expire after
+//! `IDLE_TIMEOUT`.  Process (CPU, RSS), storage (WAL size, keys), tokio
+//! runtime (worker utilization, queue depths) and per-namespace usage series
+//! are refreshed on every scrape too.
+//!
+//! Every series the node exports, including the engine's, is listed in
+//! `METRICS`: names follow the Prometheus conventions (base units in the
+//! name, `_total` on running totals), the list supplies the `# HELP` lines,
+//! and `dashboard` builds its Grafana panels from it.
+
+use std::time::Duration;
+
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, Unit};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use metrics_util::MetricKindMask;
+
+use lumen_core::{Engine, EngineError};
+
+use crate::kv::{HealthState, NamespaceUsage, ReplicaHealthResponse};
+
+/// How long a gauge that is no longer updated keeps being exported.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Clock ticks per second in `/proc/self/stat`, which Linux fixes at 100
+/// for userspace whatever the kernel's internal tick rate.
+const USER_HZ: f64 = 100.0;
+
+/// Upper bounds of the latency histogram buckets, in seconds: 50µs to 5s.
+/// Buckets rather than summaries, so quantiles can be aggregated across
+/// nodes with `histogram_quantile`.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.000_05, 0.000_1, 0.000_25, 0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+// ---------------------------------------------------------------------------
+// Catalogue
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A running total, graphed as a rate.
+    Counter,
+    Gauge,
+    /// A latency distribution, graphed as quantiles.
+    Histogram,
+}
+
+/// One exported series.
+#[derive(Debug)]
+pub struct Metric {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub unit: Unit,
+    /// The `# HELP` text.
+    pub help: &'static str,
+    /// Labels beyond the scrape's own `instance` and `job`.
+    pub labels: &'static [&'static str],
+    /// The dashboard row it is graphed in.
+    pub group: &'static str,
+}
+
+const fn metric(
+    name: &'static str,
+    kind: Kind,
+    unit: Unit,
+    labels: &'static [&'static str],
+    group: &'static str,
+    help: &'static str,
+) -> Metric {
+    Metric { name, kind, unit, help, labels, group }
+}
+
+/// Everything `/metrics` exports, in dashboard order.
+pub const METRICS: &[Metric] = &[
+    // Storage, refreshed on every scrape of an unsharded node.
+    metric("lumen_keys", Kind::Gauge, Unit::Count, &[], "Storage", "Live keys in the memtable."),
+    metric("lumen_wal_size_bytes", Kind::Gauge, Unit::Bytes, &[], "Storage", "Size of the write-ahead log."),
+    metric("lumen_latest_sequence", Kind::Gauge, Unit::Count, &[], "Storage", "Sequence of the last committed write."),
+    metric(
+        "lumen_checkpoint_sequence",
+        Kind::Gauge,
+        Unit::Count,
+        &[],
+        "Storage",
+        "Sequence the latest checkpoint covers.",
+    ),
+    // Usage by namespace, refreshed on every scrape from `usage`.
+    metric(
+        "lumen_namespace_requests_total",
+        Kind::Counter,
+        Unit::Count,
+        &["namespace"],
+        "Usage",
+        "Key-value requests on each namespace's keys.",
+    ),
+    metric(
+        "lumen_namespace_read_bytes_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &["namespace"],
+        "Usage",
+        "Bytes of values returned from each namespace.",
+    ),
+    metric(
+        "lumen_namespace_written_bytes_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &["namespace"],
+        "Usage",
+        "Bytes of keys and values sent to be written to each namespace.",
+    ),
+    metric(
+        "lumen_namespace_storage_bytes",
+        Kind::Gauge,
+        Unit::Bytes,
+        &["namespace"],
+        "Usage",
+        "Bytes of keys and values stored in each namespace, as of the last usage refresh.",
+    ),
+    // Requests by priority class, recorded by `qos` as they happen.
+    metric(
+        "lumen_request_duration_seconds",
+        Kind::Histogram,
+        Unit::Seconds,
+        &["priority"],
+        "Requests",
+        "Latency of key-value requests of each priority class, including time queued.",
+    ),
+    metric(
+        "lumen_request_queue_seconds",
+        Kind::Histogram,
+        Unit::Seconds,
+        &["priority"],
+        "Requests",
+        "Time queued requests of each priority class waited for a slot under QOS_CONCURRENCY.",
+    ),
+    metric(
+        "lumen_requests_queued",
+        Kind::Gauge,
+        Unit::Count,
+        &["priority"],
+        "Requests",
+        "Requests of each priority class waiting for a slot under QOS_CONCURRENCY.",
+    ),
+    // Engine, recorded by lumen-core as it happens.
+    metric(
+        "lumen_engine_wal_appends_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Engine",
+        "Records appended to the write-ahead log.",
+    ),
+    metric(
+        "lumen_engine_wal_bytes_written_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &[],
+        "Engine",
+        "Bytes appended to the write-ahead log.",
+    ),
+    metric(
+        "lumen_engine_wal_append_seconds",
+        Kind::Histogram,
+        Unit::Seconds,
+        &[],
+        "Engine",
+        "Time to append one record or batch to the write-ahead log.",
+    ),
+    metric(
+        "lumen_engine_sync_seconds",
+        Kind::Histogram,
+        Unit::Seconds,
+        &[],
+        "Engine",
+        "Time to sync the write-ahead log to disk.",
+    ),
+    metric(
+        "lumen_engine_keys",
+        Kind::Gauge,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "Live keys in the memtable of each engine.",
+    ),
+    metric(
+        "lumen_engine_memtable_bytes",
+        Kind::Gauge,
+        Unit::Bytes,
+        &["data_dir"],
+        "Engine",
+        "Keys and values held in the memtable of each engine.",
+    ),
+    metric(
+        "lumen_engine_disk_bytes",
+        Kind::Gauge,
+        Unit::Bytes,
+        &["data_dir", "file"],
+        "Engine",
+        "Size of each engine's checkpoint, table and WAL files.",
+    ),
+    metric(
+        "lumen_engine_dead_bytes",
+        Kind::Gauge,
+        Unit::Bytes,
+        &["data_dir"],
+        "Engine",
+        "Overwritten and deleted data in each engine's files, which a compaction reclaims.",
+    ),
+    metric(
+        "lumen_engine_tombstones",
+        Kind::Gauge,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "Deletes in each engine's WAL.",
+    ),
+    metric(
+        "lumen_engine_space_amplification",
+        Kind::Gauge,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "Bytes on disk per byte of live data in each engine.",
+    ),
+    metric(
+        "lumen_engine_recoveries_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Engine",
+        "Write-ahead log replays, one per engine opened.",
+    ),
+    metric(
+        "lumen_engine_wal_truncations_total",
+        Kind::Counter,
+        Unit::Count,
+        &["reason"],
+        "Engine",
+        "Write-ahead logs cut short on recovery, after a torn last append or a damaged record.",
+    ),
+    metric(
+        "lumen_engine_wal_bytes_dropped_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &[],
+        "Engine",
+        "Bytes cut off the end of write-ahead logs on recovery.",
+    ),
+    metric(
+        "lumen_engine_checksum_failures_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Engine",
+        "Log records and checkpoints that failed their CRC check.",
+    ),
+    metric(
+        "lumen_engine_scrub_bytes_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &["data_dir"],
+        "Engine",
+        "Bytes of log, checkpoint and tables read back by scrubbing, per engine.",
+    ),
+    metric(
+        "lumen_engine_scrub_corruptions",
+        Kind::Gauge,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "Damaged records or files the last scrub of each engine found.",
+    ),
+    metric(
+        "lumen_engine_tables",
+        Kind::Gauge,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "SSTables of each engine, which a read that misses the memtable may look in.",
+    ),
+    metric(
+        "lumen_engine_table_merges_total",
+        Kind::Counter,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "Background merges of SSTables completed, per engine.",
+    ),
+    metric(
+        "lumen_engine_table_merge_bytes_read_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &["data_dir"],
+        "Engine",
+        "Bytes of SSTables read by background merges, per engine.",
+    ),
+    metric(
+        "lumen_engine_table_merge_bytes_written_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &["data_dir"],
+        "Engine",
+        "Bytes of SSTables written by background merges, per engine.",
+    ),
+    metric(
+        "lumen_engine_table_merge_seconds",
+        Kind::Histogram,
+        Unit::Seconds,
+        &["data_dir"],
+        "Engine",
+        "Time to merge a run of SSTables.",
+    ),
+    metric(
+        "lumen_engine_cold_tables",
+        Kind::Gauge,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "SSTables of each engine in its cold tier (COLD_TIER_DIR).",
+    ),
+    metric(
+        "lumen_engine_tables_tiered_total",
+        Kind::Counter,
+        Unit::Count,
+        &["data_dir"],
+        "Engine",
+        "SSTables moved to the cold tier, per engine.",
+    ),
+    metric(
+        "lumen_engine_tier_bytes_total",
+        Kind::Counter,
+        Unit::Bytes,
+        &["data_dir"],
+        "Engine",
+        "Bytes of SSTables moved to the cold tier, per engine.",
+    ),
+    metric(
+        "lumen_engine_block_cache_hits_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Engine",
+        "Reads of a cold SSTable block served from the block cache.",
+    ),
+    metric(
+        "lumen_engine_block_cache_misses_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Engine",
+        "Reads of a cold SSTable block that went to the cold tier.",
+    ),
+    metric(
+        "lumen_engine_block_prefetches_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Engine",
+        "Blocks of a cold SSTable queued to be read ahead of a scan.",
+    ),
+    // Replication, refreshed on every scrape.
+    metric(
+        "lumen_primary_sequence",
+        Kind::Gauge,
+        Unit::Count,
+        &[],
+        "Replication",
+        "Latest sequence of the primary, that replica lag is measured against.",
+    ),
+    metric(
+        "lumen_replica_applied_sequence",
+        Kind::Gauge,
+        Unit::Count,
+        &["replica_id"],
+        "Replication",
+        "Last sequence each replica reported applied.",
+    ),
+    metric(
+        "lumen_replica_lag_records",
+        Kind::Gauge,
+        Unit::Count,
+        &["replica_id"],
+        "Replication",
+        "Records each replica is behind the primary.",
+    ),
+    metric(
+        "lumen_replica_lag_bytes",
+        Kind::Gauge,
+        Unit::Bytes,
+        &["replica_id"],
+        "Replication",
+        "Bytes sent to each replica but not yet reported applied.",
+    ),
+    metric(
+        "lumen_replica_lag_seconds",
+        Kind::Gauge,
+        Unit::Seconds,
+        &["replica_id"],
+        "Replication",
+        "Time since each replica was last fully caught up (0 while it is).",
+    ),
+    metric(
+        "lumen_replica_heartbeat_age_seconds",
+        Kind::Gauge,
+        Unit::Seconds,
+        &["replica_id"],
+        "Replication",
+        "Time since each replica last reported progress.",
+    ),
+    metric(
+        "lumen_replica_connected",
+        Kind::Gauge,
+        Unit::Count,
+        &["replica_id"],
+        "Replication",
+        "1 while each replica is streaming, 0 otherwise.",
+    ),
+    metric(
+        "lumen_replica_healthy",
+        Kind::Gauge,
+        Unit::Count,
+        &["replica_id"],
+        "Replication",
+        "1 while each replica is within its lag and heartbeat thresholds, 0 when degraded.",
+    ),
+    metric(
+        "lumen_region_tombstones_collected_total",
+        Kind::Counter,
+        Unit::Count,
+        &[],
+        "Replication",
+        "Multi-region tombstones deleted once both regions were past them.",
+    ),
+    // Process, refreshed on every scrape (Linux only).
+    metric(
+        "lumen_process_cpu_seconds_total",
+        Kind::Counter,
+        Unit::Seconds,
+        &[],
+        "Process",
+        "User and system CPU time consumed by the server.",
+    ),
+    metric(
+        "lumen_process_resident_memory_bytes",
+        Kind::Gauge,
+        Unit::Bytes,
+        &[],
+        "Process",
+        "Resident memory of the server.",
+    ),
+    // Tokio runtime, refreshed on every scrape.  Engine calls block the
+    // worker that makes them: busy workers and a growing queue mean tasks
+    // are waiting behind them.
+    metric("lumen_runtime_workers", Kind::Gauge, Unit::Count, &[], "Runtime", "Worker threads of the runtime."),
+    metric(
+        "lumen_runtime_alive_tasks",
+        Kind::Gauge,
+        Unit::Count,
+        &[],
+        "Runtime",
+        "Tasks spawned and not yet finished.",
+    ),
+    metric(
+        "lumen_runtime_worker_busy_seconds_total",
+        Kind::Counter,
+        Unit::Seconds,
+        &["worker"],
+        "Runtime",
+        "Time each worker thread spent running tasks; its rate is the worker's utilization.",
+    ),
+    metric(
+        "lumen_runtime_global_queue_depth",
+        Kind::Gauge,
+        Unit::Count,
+        &[],
+        "Runtime",
+        "Tasks waiting in the shared queue for a worker to pick them up.",
+    ),
+    metric(
+        "lumen_runtime_worker_local_queue_depth",
+        Kind::Gauge,
+        Unit::Count,
+        &["worker"],
+        "Runtime",
+        "Tasks waiting in each worker's own queue (tokio_unstable builds only).",
+    ),
+    metric(
+        "lumen_runtime_blocking_threads",
+        Kind::Gauge,
+        Unit::Count,
+        &[],
+        "Runtime",
+        "Threads of the blocking pool, busy or idle (tokio_unstable builds only).",
+    ),
+    metric(
+        "lumen_runtime_blocking_queue_depth",
+        Kind::Gauge,
+        Unit::Count,
+        &[],
+        "Runtime",
+        "Blocking calls waiting for a thread of the blocking pool (tokio_unstable builds only).",
+    ),
+];
+
+/// Install the process-wide Prometheus recorder.
+pub fn install() -> anyhow::Result<PrometheusHandle> {
+    let handle = PrometheusBuilder::new()
+        .idle_timeout(MetricKindMask::GAUGE, Some(IDLE_TIMEOUT))
+        .set_buckets(LATENCY_BUCKETS)?
+        .install_recorder()?;
+
+    for metric in METRICS {
+        match metric.kind {
+            Kind::Counter => describe_counter!(metric.name, metric.unit, metric.help),
+            Kind::Gauge => describe_gauge!(metric.name, metric.unit, metric.help),
+            Kind::Histogram => describe_histogram!(metric.name, metric.unit, metric.help),
+        }
+    }
+    Ok(handle)
+}
+
+/// Publish the latest replica health as gauges.
+pub fn record_replica_health(health: &ReplicaHealthResponse) {
+    gauge!("lumen_primary_sequence").set(health.primary_sequence as f64);
+
+    for replica in &health.replicas {
+        let id = replica.replica_id.clone();
+        let healthy = replica.health == HealthState::Healthy as i32;
+
+        gauge!("lumen_replica_applied_sequence", "replica_id" => id.clone()).set(replica.applied_sequence as f64);
+        gauge!("lumen_replica_lag_records", "replica_id" => id.clone()).set(replica.lag_records as f64);
+        gauge!("lumen_replica_lag_bytes", "replica_id" => id.clone()).set(replica.lag_bytes as f64);
+        gauge!("lumen_replica_lag_seconds", "replica_id" => id.clone()).set(replica.lag_seconds);
+        gauge!("lumen_replica_heartbeat_age_seconds", "replica_id" => id.clone())
+            .set(replica.millis_since_heartbeat as f64 / 1000.0);
+        gauge!("lumen_replica_connected", "replica_id" => id.clone()).set(f64::from(u8::from(replica.connected)));
+        gauge!("lumen_replica_healthy", "replica_id" => id).set(f64::from(u8::from(healthy)));
+    }
+}
+
+/// Publish the usage totals of every namespace.  They are kept (and saved)
+/// by `usage`, so the counters are set rather than incremented.
+pub fn record_usage(report: &[NamespaceUsage]) {
+    for usage in report {
+        let namespace = usage.namespace.clone();
+        counter!("lumen_namespace_requests_total", "namespace" => namespace.clone()).absolute(usage.requests);
+        counter!("lumen_namespace_read_bytes_total", "namespace" => namespace.clone()).absolute(usage.bytes_read);
+        counter!("lumen_namespace_written_bytes_total", "namespace" => namespace.clone())
+            .absolute(usage.bytes_written);
+        gauge!("lumen_namespace_storage_bytes", "namespace" => namespace).set(usage.storage_bytes as f64);
+    }
+}
+
+/// Publish this process's CPU time and resident memory.  Only Linux exposes
+/// them through `/proc`; elsewhere the gauges are left unset.
+pub fn record_process() {
+    // Fields after the parenthesised command name, which may contain spaces:
+    // utime and stime are the 12th and 13th.
+    if let Ok(stat) = std::fs::read_to_string("/proc/self/stat") {
+        let fields: Vec<_> = stat.rsplit_once(')').map_or("", |(_, rest)| rest).split_whitespace().collect();
+        if let (Some(utime), Some(stime)) = (fields.get(11), fields.get(12)) {
+            if let (Ok(utime), Ok(stime)) = (utime.parse::<f64>(), stime.parse::<f64>()) {
+                // A running total, but only readable as a float, which
+                // counters do not take.
+                gauge!("lumen_process_cpu_seconds_total").set((utime + stime) / USER_HZ);
+            }
+        }
+    }
+    if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+        let rss_kib = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse::<f64>().ok());
+        if let Some(rss_kib) = rss_kib {
+            gauge!("lumen_process_resident_memory_bytes").set(rss_kib * 1024.0);
+        }
+    }
+}
+
+/// Publish the load of the tokio runtime the caller runs on.  The queue
+/// depths of single workers and of the blocking pool are only exposed by
+/// tokio built with `--cfg tokio_unstable`.
+pub fn record_runtime() {
+    let runtime = tokio::runtime::Handle::current().metrics();
+    gauge!("lumen_runtime_workers").set(runtime.num_workers() as f64);
+    gauge!("lumen_runtime_alive_tasks").set(runtime.num_alive_tasks() as f64);
+    gauge!("lumen_runtime_global_queue_depth").set(runtime.global_queue_depth() as f64);
+
+    for worker in 0..runtime.num_workers() {
+        let busy = runtime.worker_total_busy_duration(worker).as_secs_f64();
+        gauge!("lumen_runtime_worker_busy_seconds_total", "worker" => worker.to_string()).set(busy);
+        #[cfg(tokio_unstable)]
+        gauge!("lumen_runtime_worker_local_queue_depth", "worker" => worker.to_string())
+            .set(runtime.worker_local_queue_depth(worker) as f64);
+    }
+
+    #[cfg(tokio_unstable)]
+    {
+        gauge!("lumen_runtime_blocking_threads").set(runtime.num_blocking_threads() as f64);
+        gauge!("lumen_runtime_blocking_queue_depth").set(runtime.blocking_queue_depth() as f64);
+    }
+}
+
+/// Publish the size of the engine's log and memtable, and how much of its
+/// files is dead (which the engine records itself).
+pub fn record_storage(engine: &Engine) -> Result<(), EngineError> {
+    engine.space()?;
+    gauge!("lumen_wal_size_bytes").set(engine.wal_size()? as f64);
+    gauge!("lumen_keys").set(engine.len()? as f64);
+    gauge!("lumen_latest_sequence").set(engine.latest_sequence()? as f64);
+    gauge!("lumen_checkpoint_sequence").set(engine.checkpoint_sequence() as f64);
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Range partitioning: contiguous key ranges explicitly assigned to shards.
+//!
+//! The partition table covers the whole keyspace with sorted, adjacent
+//! ranges `[start, end)`.  It is persisted under a single key in the router's
+//! system engine (`DATA_DIR/system`), which stores metadata only and never
+//! user data.  Every change bumps `epoch`, letting smart clients that route
+//! directly to shards detect stale copies of the table.
+//!
+//! A range whose keys are being moved between shards records the shard that
+//! still holds some of them in `moving_from`; the router reads from and
+//! deletes on both until the move completes.
+
+use anyhow::Context;
+
+use lumen_core::Engine;
+
+/// Key of the partition table inside the system engine.
+const TABLE_KEY: &str = "partitions";
+
+/// One contiguous key range and the shard that owns it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// Inclusive lower bound (`""` for the first partition).
+    pub start: String,
+    /// Exclusive upper bound; `None` for the last partition.
+    pub end: Option<String>,
+    /// Name of the owning shard.
+    pub shard: String,
+    /// Shard still holding part of this range while a move is in progress.
+    pub moving_from: Option<String>,
+}
+
+/// Sorted, gap-free list of partitions plus a change counter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    pub epoch: u64,
+    pub partitions: Vec<Partition>,
+}
+
+fn to_hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> anyhow::Result<String> {
+    if !s.len().is_multiple_of(2) {
+        anyhow::bail!("odd-length hex string");
+    }
+    let bytes = (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+impl PartitionTable {
+    /// A single partition covering every key, owned by `shard`.
+    pub fn initial(shard: &str) -> Self {
+        Self {
+            epoch: 1,
+            partitions: vec![Partition {
+                start: String::new(),
+                end: None,
+                shard: shard.to_owned(),
+                moving_from: None,
+            }],
+        }
+    }
+
+    /// Index of the partition containing `key`.
+    pub fn find(&self, key: &str) -> usize {
+        self.partitions
+            .partition_point(|p| p.start.as_str() <= key)
+            .saturating_sub(1)
+    }
+
+    /// Split partition `index` at `at` (which must lie strictly inside it);
+    /// the upper half is assigned to `target`, moving from the current owner
+    /// if that differs.
+    pub fn split(&mut self, index: usize, at: String, target: &str) {
+        let lower = &mut self.partitions[index];
+        let upper = Partition {
+            start: at.clone(),
+            end: lower.end.take(),
+            shard: target.to_owned(),
+            moving_from: (lower.shard != target).then(|| lower.shard.clone()),
+        };
+        lower.end = Some(at);
+        self.partitions.insert(index + 1, upper);
+        self.epoch += 1;
+    }
+
+    /// Merge partition `index + 1` into `index`, keeping the lower
+    /// partition's shard.  Returns the shard the upper range moves from, if
+    /// it differs.
+    pub fn merge(&mut self, index: usize) -> Option<String> {
+        let upper = self.partitions.remove(index + 1);
+        let lower = &mut self.partitions[index];
+        lower.end = upper.end;
+        self.epoch += 1;
+
+        (upper.shard != lower.shard).then(|| {
+            lower.moving_from = Some(upper.shard.clone());
+            upper.shard
+        })
+    }
+
+    /// Mark every in-progress move as finished.
+    pub fn finish_moves(&mut self) {
+        if self.partitions.iter().any(|p| p.moving_from.is_some()) {
+            for p in &mut self.partitions {
+                p.moving_from = None;
+            }
+            self.epoch += 1;
+        }
+    }
+
+    /// Text encoding: an `epoch N` line, then one `start end shard moving`
+    /// line per partition with keys hex-encoded and `-` for absent fields.
+    fn encode(&self) -> String {
+        let mut out = format!("epoch {}\n", self.epoch);
+        for p in &self.partitions {
+            out.push_str(&format!(
+                "{} {} {} {}\n",
+                to_hex(&p.start),
+                p.end.as_deref().map_or_else(|| "-".to_owned(), to_hex),
+                p.shard,
+                p.moving_from.as_deref().unwrap_or("-"),
+            ));
+        }
+        out
+    }
+
+    fn decode(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines();
+        let epoch = lines
+            .next()
+            .and_then(|l| l.strip_prefix("epoch "))
+            .context("partition table is missing its epoch line")?
+            .parse()?;
+
+        let mut partitions = Vec::new();
+        for line in lines.filter(|l| !l.is_empty()) {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let [start, end, shard, moving] = fields[..] else {
+                anyhow::bail!("malformed partition line `{line}`");
+            };
+            partitions.push(Partition {
+                start: from_hex(start)?,
+                end: if end == "-" { None } else { Some(from_hex(end)?) },
+                shard: shard.to_owned(),
+                moving_from: (moving != "-").then(|| moving.to_owned()),
+            });
+        }
+
+        if partitions.is_empty() {
+            anyhow::bail!("partition table has no partitions");
+        }
+        Ok(Self { epoch, partitions })
+    }
+
+    /// Load the table from the system engine, if one was stored.
+    pub fn load(system: &Engine) -> anyhow::Result<Option<Self>> {
+        match system.get(TABLE_KEY)? {
+            Some(bytes) => {
+                let text = String::from_utf8(bytes).context("partition table is not UTF-8")?;
+                Ok(Some(Self::decode(&text)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Persist the table to the system engine.
+    pub fn store(&self, system: &Engine) -> anyhow::Result<()> {
+        system.put(TABLE_KEY.to_owned(), self.encode().into_bytes())?;
+        Ok(())
+    }
+}
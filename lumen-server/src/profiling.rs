@@ -0,0 +1,147 @@
+//! On-demand profiles for the admin HTTP listener, in the pprof format that
+//! `go tool pprof` and most profile viewers read.
+//!
+//!   * CPU (`pprof` feature): samples every thread's stack at
+//!     `CPU_FREQUENCY` Hz for the requested duration.  One profile runs at a
+//!     time, as the sampling timer is process-wide.
+//!   * Heap (`jemalloc` feature): jemalloc replaces the system allocator,
+//!     compiled with profiling support but sampling nothing until `PPROF=on`
+//!     activates it at startup; each request dumps the live allocations
+//!     sampled so far.  jemalloc's allocator statistics are available too.
+//!
+//! Both stay off unless `PPROF=on`, so a binary built with them costs next
+//! to nothing until an operator asks for it.
+
+use std::time::Duration;
+
+/// CPU samples per second.  Not a multiple of common timer periods, so
+/// sampling does not fall into step with periodic work.
+#[cfg(feature = "pprof")]
+const CPU_FREQUENCY: i32 = 99;
+
+/// The longest CPU profile taken on one request.
+pub const MAX_CPU_PROFILE: Duration = Duration::from_secs(300);
+
+/// jemalloc's start-up options: profiling compiled in but inactive, sampling
+/// an allocation every 2^19 bytes (512 KiB) on average once activated.
+#[cfg(feature = "jemalloc")]
+#[allow(non_upper_case_globals)]
+#[export_name = "malloc_conf"]
+pub static malloc_conf: &[u8] = b"prof:true,prof_active:false,lg_prof_sample:19\0";
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+// Which variants are constructed depends on the features built.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum ProfileError {
+    /// This build cannot take the profile; names the cargo feature it needs.
+    Unsupported(&'static str),
+    /// A CPU profile is already being taken.
+    Busy,
+    Failed(String),
+}
+
+/// Fail unless this build can take at least one kind of profile.
+pub fn check_supported() -> anyhow::Result<()> {
+    if !cfg!(feature = "pprof") && !cfg!(feature = "jemalloc") {
+        anyhow::bail!("PPROF=on requires lumen-server built with the `pprof` or `jemalloc` feature");
+    }
+    Ok(())
+}
+
+/// Start sampling heap allocations, so later heap profiles have something
+/// to show.  Does nothing without the `jemalloc` feature.
+pub async fn activate_heap() -> anyhow::Result<()> {
+    #[cfg(feature = "jemalloc")]
+    {
+        let ctl = jemalloc_pprof::PROF_CTL
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("jemalloc profiling is unavailable"))?;
+        ctl.lock().await.activate()?;
+    }
+    Ok(())
+}
+
+/// Profile the CPU for `duration` and return the encoded profile.
+#[cfg(feature = "pprof")]
+pub async fn cpu(duration: Duration) -> Result<Vec<u8>, ProfileError> {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use pprof::protos::Message;
+
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        return Err(ProfileError::Busy);
+    }
+
+    // Symbolising the samples is CPU-bound, so the whole profile is taken on
+    // a blocking thread.
+    let profile = tokio::task::spawn_blocking(move || {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(CPU_FREQUENCY)
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()?;
+        std::thread::sleep(duration);
+        let profile = guard.report().build()?.pprof()?;
+        Ok::<_, pprof::Error>(profile.encode_to_vec())
+    })
+    .await;
+    RUNNING.store(false, Ordering::Release);
+
+    match profile {
+        Ok(Ok(encoded)) => Ok(encoded),
+        Ok(Err(e)) => Err(ProfileError::Failed(e.to_string())),
+        Err(e) => Err(ProfileError::Failed(e.to_string())),
+    }
+}
+
+#[cfg(not(feature = "pprof"))]
+pub async fn cpu(_duration: Duration) -> Result<Vec<u8>, ProfileError> {
+    Err(ProfileError::Unsupported("pprof"))
+}
+
+/// The allocations sampled since heap profiling was activated, as a gzipped
+/// profile.
+#[cfg(feature = "jemalloc")]
+pub async fn heap() -> Result<Vec<u8>, ProfileError> {
+    let Some(ctl) = jemalloc_pprof::PROF_CTL.as_ref() else {
+        return Err(ProfileError::Failed("jemalloc profiling is unavailable".to_owned()));
+    };
+    let mut ctl = ctl.lock().await;
+    if !ctl.activated() {
+        return Err(ProfileError::Failed("heap profiling was not activated".to_owned()));
+    }
+    ctl.dump_pprof().map_err(|e| ProfileError::Failed(e.to_string()))
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub async fn heap() -> Result<Vec<u8>, ProfileError> {
+    Err(ProfileError::Unsupported("jemalloc"))
+}
+
+/// jemalloc's allocator statistics, one `name: bytes` line each.
+#[cfg(feature = "jemalloc")]
+pub fn heap_stats() -> Result<String, ProfileError> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    let failed = |e: tikv_jemalloc_ctl::Error| ProfileError::Failed(e.to_string());
+    // The statistics are a snapshot, refreshed by advancing the epoch.
+    epoch::advance().map_err(failed)?;
+    let lines = [
+        ("allocated", stats::allocated::read().map_err(failed)?),
+        ("active", stats::active::read().map_err(failed)?),
+        ("metadata", stats::metadata::read().map_err(failed)?),
+        ("resident", stats::resident::read().map_err(failed)?),
+        ("mapped", stats::mapped::read().map_err(failed)?),
+        ("retained", stats::retained::read().map_err(failed)?),
+    ];
+    Ok(lines.iter().map(|(name, bytes)| format!("{name}: {bytes}\n")).collect())
+}
+
+#[cfg(not(feature = "jemalloc"))]
+pub fn heap_stats() -> Result<String, ProfileError> {
+    Err(ProfileError::Unsupported("jemalloc"))
+}
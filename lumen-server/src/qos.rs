@@ -0,0 +1,189 @@
+//! Request priority classes (`x-lumen-priority` header) and the scheduler
+//! that admits key-value requests by them.
+//!
+//! Clients tag each request `high`, `normal` (the default) or `background`.
+//! With `QOS_CONCURRENCY` set, at most that many requests run at once and
+//! the rest wait in one queue per class.  Each slot that frees up goes to a
+//! class picked by stride scheduling, a weighted fair queue over requests:
+//! while every class has requests waiting, high is given 8 slots for every
+//! 4 of normal and 1 of background, so a bulk load tagged background cannot
+//! starve interactive traffic, nor be starved itself.  A class that starts
+//! waiting again is not owed the turns it had no use for.  Without
+//! `QOS_CONCURRENCY`, every request is admitted at once.
+//!
+//! Either way each request's latency is recorded by class, from arrival
+//! (so including any time queued) to its response.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use metrics::{gauge, histogram};
+use tokio::sync::oneshot;
+use tonic::{Request, Status};
+
+/// Request metadata carrying the caller's priority class.
+pub const PRIORITY_HEADER: &str = "x-lumen-priority";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Normal,
+    Background,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::High, Priority::Normal, Priority::Background];
+
+    /// The class `request` was tagged with, or `None` if the tag is not one.
+    fn of<T>(request: &Request<T>) -> Option<Self> {
+        let Some(value) = request.metadata().get(PRIORITY_HEADER) else {
+            return Some(Priority::Normal);
+        };
+        match value.to_str() {
+            Ok("high") => Some(Priority::High),
+            Ok("normal") => Some(Priority::Normal),
+            Ok("background") => Some(Priority::Background),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Priority::High => "high",
+            Priority::Normal => "normal",
+            Priority::Background => "background",
+        }
+    }
+
+    /// How far a class's pass advances per slot it is given: the inverse
+    /// of its weight (8, 4 and 1).
+    fn stride(self) -> u64 {
+        match self {
+            Priority::High => 1,
+            Priority::Normal => 2,
+            Priority::Background => 8,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    queues: [VecDeque<oneshot::Sender<Slot>>; 3],
+    /// Each class's pass: the next waiting class is the one lowest.
+    pass: [u64; 3],
+    /// The pass of the class last given a slot.
+    now: u64,
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    /// Requests run at once; 0 for no limit.
+    limit: usize,
+    state: Mutex<State>,
+}
+
+impl Scheduler {
+    pub fn new(limit: usize) -> Self {
+        Self { limit, state: Mutex::default() }
+    }
+
+    /// Wait for a slot for `request`, by the class it was tagged with.  The
+    /// slot is freed when the permit is dropped.
+    pub async fn admit<T>(self: &Arc<Self>, request: &Request<T>) -> Result<Permit, Status> {
+        let arrived  = Instant::now();
+        let priority = Priority::of(request).ok_or_else(|| {
+            Status::invalid_argument(format!("{PRIORITY_HEADER} must be `high`, `normal` or `background`"))
+        })?;
+        let queued   = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if self.limit == 0 || state.running < self.limit {
+                state.running += 1;
+                None
+            } else {
+                let class = priority.index();
+                if state.queues[class].is_empty() {
+                    state.pass[class] = state.pass[class].max(state.now);
+                }
+                let (tx, rx) = oneshot::channel();
+                state.queues[class].push_back(tx);
+                gauge!("lumen_requests_queued", "priority" => priority.name()).increment(1.0);
+                Some(rx)
+            }
+        };
+
+        let slot = match queued {
+            Some(rx) => {
+                let slot = rx.await.expect("a queued request is only dropped by being given a slot");
+                histogram!("lumen_request_queue_seconds", "priority" => priority.name())
+                    .record(arrived.elapsed().as_secs_f64());
+                slot
+            }
+            None => Slot { scheduler: Some(self.clone()) },
+        };
+        Ok(Permit { _slot: slot, priority, arrived })
+    }
+
+    /// Hand a freed slot to the next waiting request, if there is one.
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            // Ties go to the higher class.
+            let next = Priority::ALL
+                .into_iter()
+                .filter(|p| !state.queues[p.index()].is_empty())
+                .min_by_key(|p| state.pass[p.index()]);
+            let Some(next) = next else {
+                state.running -= 1;
+                return;
+            };
+
+            let class = next.index();
+            state.now = state.pass[class];
+            state.pass[class] += next.stride();
+            let waiter = state.queues[class].pop_front().expect("picked a class with waiters");
+            gauge!("lumen_requests_queued", "priority" => next.name()).decrement(1.0);
+
+            match waiter.send(Slot { scheduler: Some(self.clone()) }) {
+                Ok(()) => return,
+                // The request was cancelled while queued: try the next.
+                Err(mut slot) => slot.scheduler = None,
+            }
+        }
+    }
+}
+
+/// A running request's share of the concurrency limit.
+#[derive(Debug)]
+struct Slot {
+    /// `None` once the slot has been passed on by other means.
+    scheduler: Option<Arc<Scheduler>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+/// Held while a request runs; records its latency when dropped.
+#[derive(Debug)]
+pub struct Permit {
+    _slot: Slot,
+    priority: Priority,
+    arrived: Instant,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        histogram!("lumen_request_duration_seconds", "priority" => self.priority.name())
+            .record(self.arrived.elapsed().as_secs_f64());
+    }
+}
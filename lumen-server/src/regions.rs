@@ -0,0 +1,525 @@
+//! Active/active replication between two regions (independent clusters).
+//!
+//! With `REGION` set, every value is stored in a versioned envelope carrying
+//! a hybrid-logical-clock timestamp, the region that wrote it, and whether it
+//! is a tombstone: deletes become tombstone writes so that a concurrent put in
+//! the other region cannot resurrect a deleted key.  Envelopes are opaque to
+//! the engine and to in-region replicas, which unwrap them on read.
+//!
+//! The primary of each region pulls the other's change stream through the
+//! standard `Replicate` RPC, asking it to leave out writes that originated
+//! locally (so nothing echoes back) and keys outside `REGION_NAMESPACES`.
+//! Each incoming write is applied only if its `(timestamp, region)` stamp is
+//! greater than the local one — last writer wins, with the region name as a
+//! deterministic tie-breaker — so both regions converge on the same value.
+//!
+//! A key's namespace is the part before its first `/`.  The import position
+//! is persisted in `DATA_DIR/region.pos`; re-applying a record after a
+//! restart is harmless because its stamp no longer wins.
+//!
+//! Tombstones are collected once every region's watermark has passed their
+//! stamp, every `REGION_TOMBSTONE_GC_SECS`: the peer's is the newest stamp
+//! imported from it (its writes arrive in stamp order, so none older can
+//! still come), and ours is the newest of our tombstones the peer reported
+//! applied.  A tombstone past both cannot be needed to refuse an older
+//! write on either side, and is deleted.  Tombstones of keys outside
+//! `REGION_NAMESPACES` never travel, and are collected at once.  Watermarks
+//! are kept in memory, so after a restart collection waits for new writes.
+//! Tombstones are left out of the storage quota (see
+//! `Engine::set_quota_exempt`), so a full store still takes deletes.
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use metrics::counter;
+use tonic::Code;
+use tracing::{info, warn};
+
+use lumen_core::{is_reserved_key, Change, Engine, EngineError, HybridClock, KeyRange, WalRecord};
+
+use crate::kv::{
+    key_value_store_client::KeyValueStoreClient,
+    Operation, RegionPeerStatus, ReplicateRequest, SnapshotRequest,
+};
+use crate::maintenance::Maintenance;
+use crate::replication::{report_progress, INITIAL_BACKOFF, MAX_BACKOFF, REPORT_INTERVAL};
+
+/// Leading bytes identifying a versioned envelope.
+const ENVELOPE_MAGIC: &[u8; 4] = b"LKR1";
+
+/// Most of our tombstones remembered while the peer has yet to apply them;
+/// past it every other one is forgotten, which only delays collection.
+const MAX_UNACKED: usize = 1 << 16;
+
+// ---------------------------------------------------------------------------
+// Envelope
+// ---------------------------------------------------------------------------
+
+/// Who wrote a value and when; compared as `(timestamp, origin)`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub timestamp: u64,
+    pub origin: String,
+}
+
+/// A stored value together with its stamp.  `value` is `None` for a
+/// tombstone.
+#[derive(Debug, Clone)]
+pub struct Versioned {
+    pub stamp: Stamp,
+    pub value: Option<Vec<u8>>,
+}
+
+impl Versioned {
+    /// Layout: `[magic][timestamp u64 BE][tombstone u8][origin len u8][origin][value]`.
+    pub fn encode(&self) -> Vec<u8> {
+        let origin  = self.stamp.origin.as_bytes();
+        let payload = self.value.as_deref().unwrap_or_default();
+
+        let mut out = Vec::with_capacity(14 + origin.len() + payload.len());
+        out.extend_from_slice(ENVELOPE_MAGIC);
+        out.extend_from_slice(&self.stamp.timestamp.to_be_bytes());
+        out.push(self.value.is_none() as u8);
+        out.push(origin.len() as u8);
+        out.extend_from_slice(origin);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// Decode a stored value.  Values written before `REGION` was enabled
+    /// carry no envelope and are treated as the oldest possible write.
+    pub fn decode(bytes: Vec<u8>) -> Self {
+        Self::parse(&bytes).unwrap_or(Self {
+            stamp: Stamp { timestamp: 0, origin: String::new() },
+            value: Some(bytes),
+        })
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let rest = bytes.strip_prefix(ENVELOPE_MAGIC.as_slice())?;
+        if rest.len() < 10 {
+            return None;
+        }
+
+        let timestamp  = u64::from_be_bytes(rest[..8].try_into().ok()?);
+        let tombstone  = rest[8] == 1;
+        let origin_len = rest[9] as usize;
+        let origin     = std::str::from_utf8(rest.get(10..10 + origin_len)?).ok()?.to_owned();
+        let payload    = &rest[10 + origin_len..];
+
+        Some(Self {
+            stamp: Stamp { timestamp, origin },
+            value: (!tombstone).then(|| payload.to_vec()),
+        })
+    }
+}
+
+/// Whether `value` is a tombstone envelope; checked without decoding it.
+pub fn is_tombstone(value: &[u8]) -> bool {
+    value.strip_prefix(ENVELOPE_MAGIC.as_slice()).is_some_and(|rest| rest.len() >= 10 && rest[8] == 1)
+}
+
+pub use lumen_core::throttle::namespace_of;
+
+fn in_namespaces(key: &str, namespaces: &[String]) -> bool {
+    namespaces.is_empty() || namespaces.iter().any(|n| n == namespace_of(key))
+}
+
+// ---------------------------------------------------------------------------
+// Export filter (serving side)
+// ---------------------------------------------------------------------------
+
+/// Which changes a region peer has asked to receive.
+#[derive(Debug, Clone)]
+pub struct ExportFilter {
+    pub exclude_origin: String,
+    pub namespaces: Vec<String>,
+}
+
+impl ExportFilter {
+    /// Only stamped writes from other origins travel between regions.
+    pub fn admits(&self, change: &Change) -> bool {
+        let WalRecord::Put { key, value } = &change.record else { return false };
+        match Versioned::parse(value) {
+            Some(v) => v.stamp.origin != self.exclude_origin && in_namespaces(key, &self.namespaces),
+            None => false,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Local region
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Default)]
+struct PeerProgress {
+    connected: bool,
+    peer_sequence: u64,
+}
+
+/// Write stamping and conflict resolution for this node's region.
+#[derive(Debug)]
+pub struct Regions {
+    region: String,
+    /// Namespaces imported from the peer; empty means all.
+    namespaces: Vec<String>,
+    peer_addr: Option<String>,
+    /// The engine's clock, so envelope stamps and commit timestamps agree.
+    clock: Arc<HybridClock>,
+    /// Serialises read-compare-write on stamped keys.
+    write_lock: Mutex<()>,
+    position_path: PathBuf,
+    position: AtomicU64,
+    conflicts_lost: AtomicU64,
+    peer: Mutex<PeerProgress>,
+    /// Newest stamp imported from the peer.
+    peer_watermark: AtomicU64,
+    /// `(sequence, stamp)` of our tombstones the peer has yet to apply, in
+    /// order, and the newest stamp of those it has.
+    unacked: Mutex<VecDeque<(u64, u64)>>,
+    acked_watermark: AtomicU64,
+}
+
+impl Regions {
+    pub fn new(
+        region: String,
+        namespaces: Vec<String>,
+        peer_addr: Option<String>,
+        clock: Arc<HybridClock>,
+        data_dir: &str,
+    ) -> anyhow::Result<Self> {
+        if region.is_empty() || region.len() > u8::MAX as usize {
+            anyhow::bail!("REGION must be between 1 and 255 bytes long");
+        }
+
+        let position_path = PathBuf::from(data_dir).join("region.pos");
+        let position = match std::fs::read_to_string(&position_path) {
+            Ok(text) => text.trim().parse().context("corrupt region position file")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).context("failed to read region position"),
+        };
+
+        Ok(Self {
+            region,
+            namespaces,
+            peer_addr,
+            clock,
+            write_lock: Mutex::new(()),
+            position_path,
+            position: AtomicU64::new(position),
+            conflicts_lost: AtomicU64::new(0),
+            peer: Mutex::new(PeerProgress::default()),
+            peer_watermark: AtomicU64::new(0),
+            unacked: Mutex::new(VecDeque::new()),
+            acked_watermark: AtomicU64::new(0),
+        })
+    }
+
+    pub fn peer_addr(&self) -> Option<&str> {
+        self.peer_addr.as_deref()
+    }
+
+    fn current(engine: &Engine, key: &str) -> Result<Option<Versioned>, EngineError> {
+        Ok(engine.get(key)?.map(Versioned::decode))
+    }
+
+    fn write_local(&self, engine: &Engine, key: String, value: Option<Vec<u8>>) -> Result<(), EngineError> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Stay ahead of whatever is stored, so a local write always wins
+        // over the value it replaces.
+        if let Some(current) = Self::current(engine, &key)? {
+            self.clock.observe(current.stamp.timestamp);
+        }
+        let stamp = Stamp { timestamp: self.clock.now(), origin: self.region.clone() };
+        let timestamp = stamp.timestamp;
+        let tombstone = value.is_none();
+        engine.put(key, Versioned { stamp, value }.encode())?;
+
+        if tombstone {
+            // At least the tombstone's sequence: the peer has it once it
+            // reports applying this far.
+            let sequence = engine.latest_sequence()?;
+            let mut unacked = self.unacked.lock().unwrap_or_else(|e| e.into_inner());
+            if unacked.len() >= MAX_UNACKED {
+                let mut index = 0usize;
+                unacked.retain(|_| {
+                    index += 1;
+                    index.is_multiple_of(2)
+                });
+            }
+            unacked.push_back((sequence, timestamp));
+        }
+        Ok(())
+    }
+
+    /// Stamp and store a client write.
+    pub fn put(&self, engine: &Engine, key: String, value: Vec<u8>) -> Result<(), EngineError> {
+        self.write_local(engine, key, Some(value))
+    }
+
+    /// Replace `key` with a tombstone; returns whether a live value existed.
+    pub fn delete(&self, engine: &Engine, key: &str) -> Result<bool, EngineError> {
+        let existed = Self::current(engine, key)?.is_some_and(|v| v.value.is_some());
+        if existed {
+            self.write_local(engine, key.to_owned(), None)?;
+        }
+        Ok(existed)
+    }
+
+    /// Unwrap a stored value for a client read; `None` for tombstones.
+    pub fn read(stored: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        stored.and_then(|bytes| Versioned::decode(bytes).value)
+    }
+
+    /// Apply a write imported from the peer if its stamp wins.
+    fn apply_remote(&self, engine: &Engine, key: String, encoded: Vec<u8>) -> Result<(), EngineError> {
+        let Some(incoming) = Versioned::parse(&encoded) else { return Ok(()) };
+        if incoming.stamp.origin == self.region || !in_namespaces(&key, &self.namespaces) {
+            return Ok(());
+        }
+
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.clock.observe(incoming.stamp.timestamp);
+
+        match Self::current(engine, &key)? {
+            Some(current) if current.stamp >= incoming.stamp => {
+                if current.stamp != incoming.stamp {
+                    self.conflicts_lost.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            _ => engine.put(key, encoded)?,
+        }
+        self.peer_watermark.fetch_max(incoming.stamp.timestamp, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Record a `ReportProgress` call from `consumer_id`: the peer region
+    /// has applied our changes through `applied`.
+    pub fn record_report(&self, consumer_id: &str, applied: u64) {
+        if consumer_id.strip_prefix("region:").is_none_or(|region| region == self.region) {
+            return;
+        }
+        let mut unacked = self.unacked.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(&(sequence, stamp)) = unacked.front() {
+            if sequence > applied {
+                break;
+            }
+            self.acked_watermark.fetch_max(stamp, Ordering::SeqCst);
+            unacked.pop_front();
+        }
+    }
+
+    /// Whether every region is past the tombstone of `key` stamped `stamp`.
+    fn collectable(&self, key: &str, stamp: &Stamp) -> bool {
+        if !in_namespaces(key, &self.namespaces) {
+            return true;
+        }
+        stamp.timestamp < self.peer_watermark.load(Ordering::SeqCst)
+            && (stamp.origin != self.region || stamp.timestamp <= self.acked_watermark.load(Ordering::SeqCst))
+    }
+
+    /// Delete the tombstones every region is past.  Returns how many.
+    pub fn collect_tombstones(&self, engine: &Engine) -> Result<u64, EngineError> {
+        let mut collected = 0;
+        for entry in engine.scan(KeyRange::all())? {
+            let (key, value) = entry?;
+            if is_reserved_key(&key) || !is_tombstone(&value) {
+                continue;
+            }
+            let Some(tombstone) = Versioned::parse(&value) else { continue };
+            if !self.collectable(&key, &tombstone.stamp) {
+                continue;
+            }
+            // Unless a write has replaced it since the scan's snapshot.
+            let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+            if engine.compare_and_delete(&key, &value)? {
+                collected += 1;
+            }
+        }
+        Ok(collected)
+    }
+
+    fn save_position(&self, position: u64) -> anyhow::Result<()> {
+        if self.position.swap(position, Ordering::SeqCst) == position {
+            return Ok(());
+        }
+        let tmp = self.position_path.with_extension("tmp");
+        std::fs::write(&tmp, format!("{position}\n"))?;
+        std::fs::rename(&tmp, &self.position_path)?;
+        Ok(())
+    }
+
+    fn set_peer(&self, connected: bool, peer_sequence: Option<u64>) {
+        let mut peer = self.peer.lock().unwrap_or_else(|e| e.into_inner());
+        peer.connected = connected;
+        if let Some(sequence) = peer_sequence {
+            peer.peer_sequence = sequence;
+        }
+    }
+
+    /// Import progress for the `ReplicationStatus` RPC.
+    pub fn status(&self) -> RegionPeerStatus {
+        let peer = self.peer.lock().unwrap_or_else(|e| e.into_inner());
+        RegionPeerStatus {
+            region:         self.region.clone(),
+            peer_addr:      self.peer_addr.clone().unwrap_or_default(),
+            connected:      peer.connected,
+            peer_position:  self.position.load(Ordering::SeqCst),
+            peer_sequence:  peer.peer_sequence,
+            conflicts_lost: self.conflicts_lost.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tombstone collection
+// ---------------------------------------------------------------------------
+
+/// Collect the tombstones every region is past each `interval`, for as long
+/// as the server runs.
+pub async fn run_tombstone_gc(engine: Arc<Engine>, regions: Arc<Regions>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let (engine, regions) = (engine.clone(), regions.clone());
+        match tokio::task::spawn_blocking(move || regions.collect_tombstones(&engine)).await {
+            Ok(Ok(0)) => {}
+            Ok(Ok(collected)) => {
+                counter!("lumen_region_tombstones_collected_total").increment(collected);
+                info!(collected, "Region tombstones collected");
+            }
+            Ok(Err(e)) => warn!(error = %e, "Region tombstone collection failed; retrying next interval"),
+            Err(e) => warn!(error = %e, "Region tombstone collection panicked"),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Importer
+// ---------------------------------------------------------------------------
+
+/// Import writes from the peer region forever, reconnecting with back-off.
+pub async fn run_region_peer(
+    engine: Arc<Engine>,
+    regions: Arc<Regions>,
+    maintenance: Arc<Maintenance>,
+    peer_addr: String,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let result = import(&engine, &regions, &maintenance, &peer_addr, &mut backoff).await;
+        regions.set_peer(false, None);
+
+        match result {
+            Ok(())  => warn!(peer = %peer_addr, "Region stream ended; reconnecting"),
+            Err(e)  => warn!(peer = %peer_addr, error = %e, "Region stream failed; reconnecting"),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn import(
+    engine: &Engine,
+    regions: &Regions,
+    maintenance: &Maintenance,
+    peer_addr: &str,
+    backoff: &mut Duration,
+) -> anyhow::Result<()> {
+    let mut client = KeyValueStoreClient::connect(peer_addr.to_owned())
+        .await
+        .context("failed to connect to peer region")?;
+    let consumer_id = format!("region:{}", regions.region);
+
+    if regions.position.load(Ordering::SeqCst) == 0 {
+        merge_snapshot(&mut client, engine, regions, &consumer_id).await?;
+    }
+
+    let request = |from_sequence| ReplicateRequest {
+        from_sequence,
+        replica_id:     consumer_id.clone(),
+        exclude_origin: regions.region.clone(),
+        namespaces:     regions.namespaces.clone(),
+        learner:        false,
+    };
+
+    let mut from_sequence = regions.position.load(Ordering::SeqCst);
+    let response = match client.replicate(request(from_sequence)).await {
+        // Also the answer when the peer's log was reset below our position.
+        Err(status) if matches!(status.code(), Code::FailedPrecondition | Code::OutOfRange) => {
+            warn!(from_sequence, reason = %status.message(), "Region position unusable; merging a snapshot");
+            merge_snapshot(&mut client, engine, regions, &consumer_id).await?;
+            from_sequence = regions.position.load(Ordering::SeqCst);
+            client.replicate(request(from_sequence)).await
+        }
+        other => other,
+    };
+    let mut stream = response.context("peer region rejected Replicate")?.into_inner();
+
+    info!(peer = %peer_addr, from_sequence, "Importing from peer region");
+
+    let mut last_report: Option<Instant> = None;
+    while let Some(batch) = stream.message().await? {
+        *backoff = INITIAL_BACKOFF;
+
+        let paused       = maintenance.pause().await;
+        let mut position = batch.through_sequence;
+        for record in batch.records {
+            position = position.max(record.sequence);
+            if record.op == Operation::Put as i32 {
+                regions.apply_remote(engine, record.key, record.value)?;
+            }
+        }
+
+        regions.set_peer(true, Some(batch.primary_sequence));
+        if position > 0 {
+            regions.save_position(position)?;
+        }
+        drop(paused);
+
+        if last_report.is_none_or(|t| t.elapsed() >= REPORT_INTERVAL) {
+            report_progress(&client, &consumer_id, regions.position.load(Ordering::SeqCst)).await;
+            last_report = Some(Instant::now());
+        }
+    }
+
+    Ok(())
+}
+
+/// Merge the peer's whole keyspace, then continue from its sequence.
+async fn merge_snapshot(
+    client: &mut KeyValueStoreClient<tonic::transport::Channel>,
+    engine: &Engine,
+    regions: &Regions,
+    consumer_id: &str,
+) -> anyhow::Result<()> {
+    let mut stream = client
+        .snapshot(SnapshotRequest { replica_id: consumer_id.to_owned() })
+        .await
+        .context("peer region rejected Snapshot")?
+        .into_inner();
+
+    let mut merged = 0u64;
+    while let Some(chunk) = stream.message().await? {
+        let sequence = chunk.sequence;
+        for entry in chunk.entries {
+            regions.apply_remote(engine, entry.key, entry.value)?;
+            merged += 1;
+        }
+        if chunk.last {
+            info!(sequence, entries = merged, "Merged snapshot from peer region");
+            regions.save_position(sequence)?;
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("peer region snapshot ended early after {merged} entries")
+}
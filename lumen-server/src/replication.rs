@@ -0,0 +1,929 @@
+//! Asynchronous primary → replica replication by WAL shipping.
+//!
+//! Primary side: every `Replicate` call gets a task that reads the engine's
+//! change feed after the requested sequence and streams it in batches, then
+//! blocks for new commits, emitting heartbeats while idle.
+//!
+//! Replica side: a background task connects to the primary, resumes from its
+//! own latest sequence, applies each record through
+//! `Engine::apply_replicated` and reconnects with exponential back-off when
+//! the stream breaks.  Both sides record progress in `ReplicationState` so
+//! lag can be reported by the `ReplicationStatus` RPC.
+//!
+//! Health: every consumer reports the sequence it has applied through
+//! `ReportProgress` about once per `REPORT_INTERVAL`.  From those reports the
+//! primary derives each replica's lag in records, bytes and seconds and the
+//! age of its last report, and flags it as degraded once any of these
+//! exceeds its `HealthThresholds`.
+//!
+//! Bootstrap: an empty replica, or one the primary reports as too far behind
+//! (its position is older than the primary's checkpoint), first downloads a
+//! full checkpoint via the `Snapshot` RPC, installs it, and then continues
+//! with incremental streaming from the checkpoint's sequence.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
+use tonic::{Code, Status};
+use tracing::{info, warn};
+
+use lumen_core::{Change, Engine, KeyRange, WalError, WalRecord};
+
+use crate::errors::engine_status;
+use crate::kv::{
+    key_value_store_client::KeyValueStoreClient,
+    HealthState, NodeRole, Operation, ProgressReport, ReadIndexRequest, ReplicaHealth,
+    ReplicaHealthResponse, ReplicaProgress, ReplicateRequest, ReplicatedRecord,
+    ReplicationBatch, ReplicationStatusResponse, SnapshotChunk, SnapshotEntry, SnapshotRequest,
+};
+use crate::maintenance::Maintenance;
+use crate::regions::ExportFilter;
+
+/// Maximum number of records sent in one `ReplicationBatch`.
+pub(crate) const BATCH_LIMIT: usize = 1024;
+/// Approximate payload size of one `SnapshotChunk`.
+const SNAPSHOT_CHUNK_BYTES: usize = 1 << 20;
+/// How long an idle primary stream waits before sending a heartbeat.
+pub(crate) const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// Reconnect back-off bounds for the replica (and region import) worker.
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// How often consumers report their applied sequence.
+pub(crate) const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a disconnected replica is still reported before it is forgotten.
+const DISCONNECTED_RETENTION: Duration = Duration::from_secs(300);
+
+// ---------------------------------------------------------------------------
+// Role & shared state
+// ---------------------------------------------------------------------------
+
+/// Replication role this node was started with.
+#[derive(Debug, Clone)]
+pub enum Role {
+    Primary,
+    /// Follows `primary_addr`.  A `learner` is expected to lag, as an
+    /// analytics replica or one being staged in, so lag alone never makes
+    /// it degraded.
+    Replica { primary_addr: String, learner: bool },
+}
+
+impl Role {
+    /// The role this node reports in `ReplicationStatus` and gossips.
+    pub fn node_role(&self) -> NodeRole {
+        match self {
+            Role::Primary => NodeRole::Primary,
+            Role::Replica { learner: false, .. } => NodeRole::Replica,
+            Role::Replica { learner: true, .. } => NodeRole::Learner,
+        }
+    }
+}
+
+/// Alert thresholds past which a replica is reported as degraded.
+#[derive(Debug, Clone, Copy)]
+pub struct HealthThresholds {
+    pub max_lag_records: u64,
+    pub max_lag: Duration,
+    pub heartbeat_timeout: Duration,
+}
+
+/// Primary side: one consumer of the change stream.
+#[derive(Debug)]
+struct StreamProgress {
+    connected: bool,
+    learner: bool,
+    sent: u64,
+    /// `(sequence, cumulative bytes sent)` after each batch, starting with
+    /// the stream's origin; pruned up to the applied sequence.
+    sent_bytes: VecDeque<(u64, u64)>,
+    bytes_total: u64,
+    applied: u64,
+    opened_at: Instant,
+    last_report: Option<Instant>,
+    caught_up_at: Option<Instant>,
+    disconnected_at: Option<Instant>,
+}
+
+impl StreamProgress {
+    fn new(from_sequence: u64) -> Self {
+        Self {
+            connected: true,
+            learner: false,
+            sent: from_sequence,
+            sent_bytes: VecDeque::from([(from_sequence, 0)]),
+            bytes_total: 0,
+            applied: from_sequence,
+            opened_at: Instant::now(),
+            last_report: None,
+            caught_up_at: None,
+            disconnected_at: None,
+        }
+    }
+
+    fn lag_bytes(&self) -> u64 {
+        let base = self.sent_bytes.front().map_or(0, |&(_, bytes)| bytes);
+        self.bytes_total.saturating_sub(base)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ReplicaSide {
+    connected: bool,
+    primary_sequence: u64,
+    last_contact: Option<Instant>,
+    /// Last time the applied sequence matched the primary's reported one.
+    caught_up_at: Option<Instant>,
+}
+
+/// Progress bookkeeping shared by the service and the replication tasks.
+#[derive(Debug)]
+pub struct ReplicationState {
+    role: Role,
+    replica: Mutex<ReplicaSide>,
+    /// Primary side: progress of every change-stream consumer by id.
+    streams: Mutex<HashMap<String, StreamProgress>>,
+    thresholds: HealthThresholds,
+    /// Replica side: lazily connected channel for read-index requests.
+    primary: Option<Channel>,
+}
+
+impl ReplicationState {
+    pub fn new(role: Role, thresholds: HealthThresholds) -> anyhow::Result<Self> {
+        let primary = match &role {
+            Role::Primary => None,
+            Role::Replica { primary_addr, .. } => Some(
+                Channel::from_shared(primary_addr.clone())
+                    .context("PRIMARY_ADDR must be a valid URL (e.g. http://10.0.0.1:50051)")?
+                    .connect_lazy(),
+            ),
+        };
+
+        Ok(Self {
+            role,
+            replica: Mutex::new(ReplicaSide::default()),
+            streams: Mutex::new(HashMap::new()),
+            thresholds,
+            primary,
+        })
+    }
+
+    /// Replicas reject client writes; only the replication stream mutates them.
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.role, Role::Replica { .. })
+    }
+
+    /// How far this node's data may lag behind the primary: zero on the
+    /// primary, time since the replica was last caught up otherwise, and
+    /// `None` if a replica has never caught up.
+    pub fn staleness(&self) -> Option<Duration> {
+        match self.role {
+            Role::Primary => Some(Duration::ZERO),
+            Role::Replica { .. } => {
+                let side = self.replica.lock().unwrap_or_else(|e| e.into_inner());
+                side.caught_up_at.map(|t| t.elapsed())
+            }
+        }
+    }
+
+    /// Sequence a linearizable read must observe.
+    ///
+    /// The primary is the only writer and there is no leader election, so
+    /// every acknowledged write is already in its memtable: its own latest
+    /// sequence is the read index.  A replica asks its primary instead.
+    pub async fn read_index(&self, engine: &Engine) -> Result<u64, Status> {
+        match &self.primary {
+            None => engine.latest_sequence().map_err(engine_status),
+            Some(channel) => {
+                let mut client = KeyValueStoreClient::new(channel.clone());
+                let resp = client.read_index(ReadIndexRequest {}).await.map_err(|status| {
+                    Status::unavailable(format!("read index from primary failed: {}", status.message()))
+                })?;
+                Ok(resp.into_inner().sequence)
+            }
+        }
+    }
+
+    /// Snapshot of this node's replication progress.
+    pub fn status(&self, applied_sequence: u64) -> ReplicationStatusResponse {
+        let mut resp = ReplicationStatusResponse {
+            applied_sequence,
+            ..Default::default()
+        };
+
+        match &self.role {
+            Role::Primary => {
+                resp.role = self.role.node_role() as i32;
+                let streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+                resp.replicas = streams
+                    .iter()
+                    .filter(|(_, stream)| stream.connected)
+                    .map(|(id, stream)| ReplicaProgress {
+                        replica_id:    id.clone(),
+                        sent_sequence: stream.sent,
+                        lag_records:   applied_sequence.saturating_sub(stream.sent),
+                        learner:       stream.learner,
+                    })
+                    .collect();
+            }
+            Role::Replica { primary_addr, .. } => {
+                resp.role         = self.role.node_role() as i32;
+                resp.primary_addr = primary_addr.clone();
+                let side = self.replica.lock().unwrap_or_else(|e| e.into_inner());
+                resp.connected        = side.connected;
+                resp.primary_sequence = side.primary_sequence;
+                resp.lag_records      = side.primary_sequence.saturating_sub(applied_sequence);
+                resp.millis_since_contact = side
+                    .last_contact
+                    .map_or(0, |t| t.elapsed().as_millis() as u64);
+            }
+        }
+
+        resp
+    }
+
+    /// Progress and health of every consumer (primary), or of this node
+    /// itself (replica).  `latest` is this node's latest sequence.
+    pub fn health(&self, latest: u64) -> ReplicaHealthResponse {
+        let t = self.thresholds;
+        let assess = |lag_records: u64, lag: Duration, since_report: Option<Duration>, connected: bool| {
+            let mut reasons = Vec::new();
+            if !connected {
+                reasons.push("disconnected".to_owned());
+            }
+            if lag_records > t.max_lag_records {
+                reasons.push(format!("lag of {lag_records} records exceeds {}", t.max_lag_records));
+            }
+            if lag > t.max_lag {
+                reasons.push(format!("lag of {:.1}s exceeds {:.1}s", lag.as_secs_f64(), t.max_lag.as_secs_f64()));
+            }
+            match since_report {
+                Some(age) if age > t.heartbeat_timeout => {
+                    reasons.push(format!("no heartbeat for {:.1}s", age.as_secs_f64()));
+                }
+                None => reasons.push("no heartbeat received yet".to_owned()),
+                _ => {}
+            }
+            let health = if reasons.is_empty() { HealthState::Healthy } else { HealthState::Degraded };
+            (health as i32, reasons)
+        };
+
+        match &self.role {
+            Role::Primary => {
+                let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+                streams.retain(|_, s| s.disconnected_at.is_none_or(|t| t.elapsed() < DISCONNECTED_RETENTION));
+
+                let mut replicas: Vec<ReplicaHealth> = streams
+                    .iter()
+                    .map(|(id, s)| {
+                        let lag_records = latest.saturating_sub(s.applied);
+                        let lag = if lag_records == 0 {
+                            Duration::ZERO
+                        } else {
+                            s.caught_up_at.unwrap_or(s.opened_at).elapsed()
+                        };
+                        let since_report = s.last_report.map(|t| t.elapsed());
+                        let (health, reasons) = if s.learner {
+                            assess(0, Duration::ZERO, since_report, s.connected)
+                        } else {
+                            assess(lag_records, lag, since_report, s.connected)
+                        };
+
+                        ReplicaHealth {
+                            replica_id:       id.clone(),
+                            connected:        s.connected,
+                            applied_sequence: s.applied,
+                            lag_records,
+                            lag_bytes:        s.lag_bytes(),
+                            lag_seconds:      lag.as_secs_f64(),
+                            millis_since_heartbeat: since_report.map_or(0, |d| d.as_millis() as u64),
+                            health,
+                            reasons,
+                            learner:          s.learner,
+                        }
+                    })
+                    .collect();
+                replicas.sort_by(|a, b| a.replica_id.cmp(&b.replica_id));
+
+                ReplicaHealthResponse { primary_sequence: latest, replicas }
+            }
+            Role::Replica { learner, .. } => {
+                let side = self.replica.lock().unwrap_or_else(|e| e.into_inner());
+                let lag_records  = side.primary_sequence.saturating_sub(latest);
+                let lag          = match side.caught_up_at {
+                    _ if lag_records == 0 && side.connected => Duration::ZERO,
+                    Some(t) => t.elapsed(),
+                    None => Duration::ZERO,
+                };
+                let since_report = side.last_contact.map(|t| t.elapsed());
+                let (health, reasons) = if *learner {
+                    assess(0, Duration::ZERO, since_report, side.connected)
+                } else {
+                    assess(lag_records, lag, since_report, side.connected)
+                };
+
+                ReplicaHealthResponse {
+                    primary_sequence: side.primary_sequence,
+                    replicas: vec![ReplicaHealth {
+                        replica_id:       "self".to_owned(),
+                        connected:        side.connected,
+                        applied_sequence: latest,
+                        lag_records,
+                        lag_bytes:        0,
+                        lag_seconds:      lag.as_secs_f64(),
+                        millis_since_heartbeat: since_report.map_or(0, |d| d.as_millis() as u64),
+                        health,
+                        reasons,
+                        learner:          *learner,
+                    }],
+                }
+            }
+        }
+    }
+
+    /// Record a consumer's `ReportProgress` call.
+    pub fn record_report(&self, replica_id: &str, applied: u64, latest: u64) {
+        let now = Instant::now();
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        let stream = streams
+            .entry(replica_id.to_owned())
+            .or_insert_with(|| StreamProgress::new(applied));
+
+        stream.applied     = applied;
+        stream.last_report = Some(now);
+        if applied >= latest {
+            stream.caught_up_at = Some(now);
+        }
+        while stream.sent_bytes.get(1).is_some_and(|&(sequence, _)| sequence <= applied) {
+            stream.sent_bytes.pop_front();
+        }
+    }
+
+    fn stream_opened(&self, replica_id: &str, from_sequence: u64, learner: bool) {
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        let stream   = StreamProgress { learner, ..StreamProgress::new(from_sequence) };
+        let previous = streams.insert(replica_id.to_owned(), stream);
+
+        // Keep the heartbeat history across reconnects.
+        if let (Some(previous), Some(stream)) = (previous, streams.get_mut(replica_id)) {
+            stream.last_report  = previous.last_report;
+            stream.caught_up_at = previous.caught_up_at;
+        }
+    }
+
+    fn stream_progress(&self, replica_id: &str, sent: u64, batch_bytes: u64) {
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(stream) = streams.get_mut(replica_id) {
+            stream.sent         = sent;
+            stream.bytes_total += batch_bytes;
+            stream.sent_bytes.push_back((sent, stream.bytes_total));
+        }
+    }
+
+    fn stream_closed(&self, replica_id: &str) {
+        let mut streams = self.streams.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(stream) = streams.get_mut(replica_id) {
+            stream.connected       = false;
+            stream.disconnected_at = Some(Instant::now());
+        }
+    }
+
+    fn contact(&self, primary_sequence: u64, applied_sequence: u64) {
+        let now  = Instant::now();
+        let mut side = self.replica.lock().unwrap_or_else(|e| e.into_inner());
+        side.connected        = true;
+        side.primary_sequence = primary_sequence;
+        side.last_contact     = Some(now);
+        if applied_sequence >= primary_sequence {
+            side.caught_up_at = Some(now);
+        }
+    }
+
+    fn disconnected(&self) {
+        let mut side = self.replica.lock().unwrap_or_else(|e| e.into_inner());
+        side.connected = false;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Record conversion
+// ---------------------------------------------------------------------------
+
+fn to_proto(change: Change) -> ReplicatedRecord {
+    match change.record {
+        WalRecord::Put { key, value } => ReplicatedRecord {
+            sequence: change.sequence,
+            op: Operation::Put as i32,
+            key,
+            value,
+            timestamp: change.timestamp,
+        },
+        WalRecord::Delete { key } => ReplicatedRecord {
+            sequence: change.sequence,
+            op: Operation::Delete as i32,
+            key,
+            value: Vec::new(),
+            timestamp: change.timestamp,
+        },
+    }
+}
+
+fn from_proto(record: ReplicatedRecord) -> anyhow::Result<Change> {
+    let wal_record = match Operation::try_from(record.op) {
+        Ok(Operation::Put)    => WalRecord::Put { key: record.key, value: record.value },
+        Ok(Operation::Delete) => WalRecord::Delete { key: record.key },
+        _ => anyhow::bail!("unknown replicated operation {}", record.op),
+    };
+    Ok(Change { sequence: record.sequence, timestamp: record.timestamp, record: wal_record })
+}
+
+// ---------------------------------------------------------------------------
+// Primary side
+// ---------------------------------------------------------------------------
+
+/// Start streaming changes after `from_sequence` to a replica (a learner
+/// if `learner`), or to a region peer when `filter` is set.
+pub fn stream_changes(
+    engine: Arc<Engine>,
+    state: Arc<ReplicationState>,
+    replica_id: String,
+    from_sequence: u64,
+    learner: bool,
+    filter: Option<ExportFilter>,
+) -> ReceiverStream<Result<ReplicationBatch, Status>> {
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        info!(replica_id = %replica_id, from_sequence, learner, "Replica stream opened");
+        state.stream_opened(&replica_id, from_sequence, learner);
+        let mut cursor = from_sequence;
+
+        loop {
+            let eng = engine.clone();
+            let fetched = tokio::task::spawn_blocking(move || {
+                let changes = eng.changes_since(cursor, BATCH_LIMIT)?;
+                if !changes.is_empty() {
+                    return Ok((changes, eng.latest_sequence()?));
+                }
+                eng.wait_for_changes(cursor, HEARTBEAT_INTERVAL)
+                    .map(|latest| (Vec::new(), latest))
+            })
+            .await;
+
+            let (changes, latest) = match fetched {
+                Ok(Ok(fetched)) => fetched,
+                Ok(Err(e)) => {
+                    let _ = tx.send(Err(engine_status(e))).await;
+                    break;
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+                    break;
+                }
+            };
+
+            // A freshly woken waiter has new changes to fetch; only send a
+            // heartbeat when nothing was committed during the wait.
+            if changes.is_empty() && latest > cursor {
+                continue;
+            }
+
+            if let Some(last) = changes.last() {
+                cursor = last.sequence;
+            }
+
+            let batch = ReplicationBatch {
+                primary_sequence: latest,
+                records: changes
+                    .into_iter()
+                    .filter(|change| filter.as_ref().is_none_or(|f| f.admits(change)))
+                    .map(to_proto)
+                    .collect(),
+                through_sequence: cursor,
+            };
+
+            let batch_bytes = batch
+                .records
+                .iter()
+                .map(|r| (r.key.len() + r.value.len()) as u64)
+                .sum();
+
+            if tx.send(Ok(batch)).await.is_err() {
+                break;
+            }
+            state.stream_progress(&replica_id, cursor, batch_bytes);
+        }
+
+        state.stream_closed(&replica_id);
+        info!(replica_id = %replica_id, sent_sequence = cursor, "Replica stream closed");
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// Stream a consistent checkpoint of the whole keyspace to a replica.
+///
+/// The keys are scanned from a snapshot as they are sent, so the keyspace
+/// need not fit in memory; the scan reads tables off disk, so it runs off
+/// the runtime, as fast as the replica takes the chunks.
+pub fn stream_snapshot(
+    engine: Arc<Engine>,
+    replica_id: String,
+) -> ReceiverStream<Result<SnapshotChunk, Status>> {
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::task::spawn_blocking(move || {
+        let scan = match engine.scan(KeyRange::all()) {
+            Ok(scan) => scan,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(engine_status(e)));
+                return;
+            }
+        };
+
+        let sequence = scan.sequence();
+        info!(replica_id = %replica_id, sequence, "Streaming snapshot");
+
+        let mut entries = Vec::new();
+        let mut bytes   = 0;
+        let mut sent    = 0;
+        let mut scan    = scan.peekable();
+
+        loop {
+            match scan.next() {
+                Some(Ok((key, value))) => {
+                    bytes += key.len() + value.len();
+                    entries.push(SnapshotEntry { key, value });
+                }
+                Some(Err(e)) => {
+                    let _ = tx.blocking_send(Err(engine_status(e)));
+                    return;
+                }
+                None => {}
+            }
+
+            let last = scan.peek().is_none();
+            if !last && bytes < SNAPSHOT_CHUNK_BYTES {
+                continue;
+            }
+
+            sent += entries.len() as u64;
+            let chunk = SnapshotChunk {
+                sequence,
+                total_entries: sent,
+                entries: std::mem::take(&mut entries),
+                last,
+            };
+            bytes = 0;
+
+            if tx.blocking_send(Ok(chunk)).is_err() {
+                return;
+            }
+            if last {
+                break;
+            }
+        }
+        info!(replica_id = %replica_id, sequence, entries = sent, "Snapshot streamed");
+    });
+
+    ReceiverStream::new(rx)
+}
+
+// ---------------------------------------------------------------------------
+// Replica side
+// ---------------------------------------------------------------------------
+
+/// Follow `primary_addr` forever, reconnecting with back-off on failure.
+pub async fn run_replica(
+    engine: Arc<Engine>,
+    state: Arc<ReplicationState>,
+    maintenance: Arc<Maintenance>,
+    primary_addr: String,
+    replica_id: String,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let result = follow(&engine, &state, &maintenance, &primary_addr, &replica_id, &mut backoff).await;
+        state.disconnected();
+
+        match result {
+            Ok(())  => warn!(primary = %primary_addr, "Replication stream ended; reconnecting"),
+            Err(e)  => warn!(primary = %primary_addr, error = %e, "Replication stream failed; reconnecting"),
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn follow(
+    engine: &Engine,
+    state: &ReplicationState,
+    maintenance: &Maintenance,
+    primary_addr: &str,
+    replica_id: &str,
+    backoff: &mut Duration,
+) -> anyhow::Result<()> {
+    let mut client = KeyValueStoreClient::connect(primary_addr.to_owned())
+        .await
+        .context("failed to connect to primary")?;
+
+    // A brand-new replica bootstraps from a snapshot rather than replaying
+    // the primary's whole log.
+    if engine.latest_sequence()? == 0 {
+        bootstrap(&mut client, engine, replica_id).await?;
+    }
+
+    let learner = matches!(state.role, Role::Replica { learner: true, .. });
+    let request = |from_sequence| ReplicateRequest {
+        from_sequence,
+        replica_id: replica_id.to_owned(),
+        learner,
+        ..Default::default()
+    };
+
+    let mut from_sequence = engine.latest_sequence()?;
+    let response = match client.replicate(request(from_sequence)).await {
+        Err(status) if status.code() == Code::FailedPrecondition => {
+            warn!(from_sequence, reason = %status.message(), "Replica too far behind; bootstrapping");
+            bootstrap(&mut client, engine, replica_id).await?;
+            from_sequence = engine.latest_sequence()?;
+            client.replicate(request(from_sequence)).await
+        }
+        other => other,
+    };
+    let mut stream = response.context("primary rejected Replicate")?.into_inner();
+
+    info!(primary = %primary_addr, from_sequence, "Replicating from primary");
+
+    let mut last_report: Option<Instant> = None;
+    while let Some(batch) = stream.message().await? {
+        *backoff = INITIAL_BACKOFF;
+
+        let changes = batch.records.into_iter().map(from_proto).collect::<anyhow::Result<Vec<_>>>()?;
+        let paused  = maintenance.pause().await;
+        // Applying waits on the WAL sync; see `service::blocking`.
+        let replica = engine.clone();
+        tokio::task::spawn_blocking(move || changes.into_iter().try_for_each(|change| replica.apply_replicated(change)))
+            .await??;
+        drop(paused);
+        let applied = engine.latest_sequence()?;
+        state.contact(batch.primary_sequence, applied);
+
+        if last_report.is_none_or(|t| t.elapsed() >= REPORT_INTERVAL) {
+            report_progress(&client, replica_id, applied).await;
+            last_report = Some(Instant::now());
+        }
+    }
+
+    Ok(())
+}
+
+/// Tell the primary how far this consumer has applied its stream.
+///
+/// Failures only cost the primary a heartbeat, so they are not fatal.
+pub(crate) async fn report_progress(
+    client: &KeyValueStoreClient<Channel>,
+    replica_id: &str,
+    applied_sequence: u64,
+) {
+    let report = ProgressReport { replica_id: replica_id.to_owned(), applied_sequence };
+    if let Err(status) = client.clone().report_progress(report).await {
+        warn!(error = %status.message(), "Failed to report replication progress");
+    }
+}
+
+/// Download a full checkpoint from the primary and install it locally.
+///
+/// The entries are handed to `Engine::install_snapshot` as they arrive,
+/// rather than collected first, so the keyspace need not fit in memory.
+async fn bootstrap(
+    client: &mut KeyValueStoreClient<Channel>,
+    engine: &Engine,
+    replica_id: &str,
+) -> anyhow::Result<()> {
+    let mut stream = client
+        .snapshot(SnapshotRequest { replica_id: replica_id.to_owned() })
+        .await
+        .context("primary rejected Snapshot")?
+        .into_inner();
+
+    let Some(first) = stream.message().await? else {
+        anyhow::bail!("snapshot stream ended early: received no chunk");
+    };
+    if first.sequence == 0 {
+        info!("Primary is empty; nothing to bootstrap");
+        return Ok(());
+    }
+
+    let sequence = first.sequence;
+    info!(sequence, "Installing snapshot from primary");
+
+    let (tx, rx) = mpsc::channel(4);
+    let replica  = engine.clone();
+    let install  = tokio::task::spawn_blocking(move || replica.install_snapshot(sequence, Received::new(sequence, rx)));
+
+    let mut chunk = Ok(first);
+    loop {
+        let last = chunk.as_ref().map_or(true, |chunk| chunk.last);
+        // Only fails once the install has, which it reports below.
+        if tx.send(chunk).await.is_err() || last {
+            break;
+        }
+        chunk = match stream.message().await {
+            Ok(Some(chunk)) => Ok(chunk),
+            Ok(None) => break,
+            Err(status) => Err(status),
+        };
+    }
+    drop(tx);
+
+    install.await??;
+    Ok(())
+}
+
+/// The entries of a snapshot stream, as its chunks arrive over `chunks`.
+///
+/// Ends at the chunk marked last; fails if the stream does, ends before
+/// that chunk, or does not hold the entries it counts.
+struct Received {
+    chunks: mpsc::Receiver<Result<SnapshotChunk, Status>>,
+    entries: std::vec::IntoIter<SnapshotEntry>,
+    sequence: u64,
+    received: u64,
+    done: bool,
+}
+
+impl Received {
+    fn new(sequence: u64, chunks: mpsc::Receiver<Result<SnapshotChunk, Status>>) -> Self {
+        Self { chunks, entries: Vec::new().into_iter(), sequence, received: 0, done: false }
+    }
+}
+
+impl Iterator for Received {
+    type Item = Result<(String, Vec<u8>), WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let broken = |reason: String| Some(Err(WalError::Io(std::io::Error::other(reason))));
+        loop {
+            if let Some(entry) = self.entries.next() {
+                self.received += 1;
+                return Some(Ok((entry.key, entry.value)));
+            }
+            if self.done {
+                return None;
+            }
+
+            let chunk = match self.chunks.blocking_recv() {
+                Some(Ok(chunk)) => chunk,
+                Some(Err(status)) => return broken(format!("snapshot stream failed: {}", status.message())),
+                None => return broken(format!("snapshot stream ended early: received {} entries", self.received)),
+            };
+            if chunk.sequence != self.sequence {
+                return broken(format!("snapshot stream moved from sequence {} to {}", self.sequence, chunk.sequence));
+            }
+            let received = self.received + chunk.entries.len() as u64;
+            if chunk.last && received != chunk.total_entries {
+                return broken(format!(
+                    "snapshot stream ended early: received {received} of {} entries",
+                    chunk.total_entries
+                ));
+            }
+            self.done    = chunk.last;
+            self.entries = chunk.entries.into_iter();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lumen_core::EngineOptions;
+
+    use super::*;
+    use crate::service::tests::serve;
+
+    fn thresholds() -> HealthThresholds {
+        HealthThresholds {
+            max_lag_records:   100,
+            max_lag:           Duration::from_secs(3600),
+            heartbeat_timeout: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn a_lagging_learner_is_reported_but_never_degraded() {
+        let state = ReplicationState::new(Role::Primary, thresholds()).unwrap();
+        state.stream_opened("learner", 0, true);
+        state.stream_opened("replica", 0, false);
+        state.record_report("learner", 10, 1000);
+        state.record_report("replica", 10, 1000);
+
+        let health = state.health(1000);
+        let [learner, replica] = &health.replicas[..] else { panic!("{:?}", health.replicas) };
+        assert_eq!((learner.replica_id.as_str(), learner.learner), ("learner", true));
+        assert_eq!(learner.lag_records, 990);
+        assert_eq!(learner.health, HealthState::Healthy as i32, "{:?}", learner.reasons);
+        assert_eq!((replica.replica_id.as_str(), replica.learner), ("replica", false));
+        assert_eq!(replica.health, HealthState::Degraded as i32);
+        assert_eq!(replica.reasons, ["lag of 990 records exceeds 100"]);
+
+        let status = state.status(1000);
+        let mut learners: Vec<_> = status.replicas.iter().map(|r| (r.replica_id.as_str(), r.learner)).collect();
+        learners.sort();
+        assert_eq!(learners, [("learner", true), ("replica", false)]);
+
+        // Losing its stream still counts against it.
+        state.stream_closed("learner");
+        let health = state.health(1000);
+        assert_eq!(health.replicas[0].health, HealthState::Degraded as i32);
+        assert_eq!(health.replicas[0].reasons, ["disconnected"]);
+    }
+
+    #[tokio::test]
+    async fn a_learner_reports_itself_as_one_and_waits_on_no_lag() {
+        let role  = Role::Replica { primary_addr: "http://127.0.0.1:1".to_owned(), learner: true };
+        let state = ReplicationState::new(role, thresholds()).unwrap();
+        state.contact(1000, 10);
+
+        assert_eq!(state.status(10).role, NodeRole::Learner as i32);
+        let health = state.health(10);
+        assert_eq!(health.replicas[0].lag_records, 990);
+        assert!(health.replicas[0].learner);
+        assert_eq!(health.replicas[0].health, HealthState::Healthy as i32, "{:?}", health.replicas[0].reasons);
+    }
+
+    #[tokio::test]
+    async fn the_read_index_waits_for_no_learner() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        for i in 0..5 {
+            engine.put(format!("key-{i}"), b"v".to_vec()).unwrap();
+        }
+        let state = ReplicationState::new(Role::Primary, thresholds()).unwrap();
+        state.stream_opened("learner", 0, true);
+        state.record_report("learner", 0, 5);
+
+        // Only the primary's own writes make up the read index.
+        assert_eq!(state.read_index(&engine).await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn a_replica_installs_a_snapshot_as_its_chunks_arrive() {
+        let (dir, replica_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let primary = Engine::open(dir.path()).unwrap();
+        // Enough for several chunks.
+        for i in 0..3000 {
+            primary.put(format!("key-{i:04}"), vec![b'v'; 1000]).unwrap();
+        }
+        primary.delete("key-0000").unwrap();
+        let mut client = KeyValueStoreClient::new(serve(primary).await);
+
+        let options = EngineOptions { memtable_flush_bytes: Some(1 << 20), ..Default::default() };
+        let replica = Engine::open_with(replica_dir.path(), options).unwrap();
+        replica.put("stale".to_owned(), b"1".to_vec()).unwrap();
+        bootstrap(&mut client, &replica, "replica-1").await.unwrap();
+
+        assert_eq!(replica.latest_sequence().unwrap(), 3001);
+        assert_eq!(replica.len().unwrap(), 2999);
+        assert_eq!(replica.memtable_bytes(), 0);
+        assert_eq!(replica.get("stale").unwrap(), None);
+        assert_eq!(replica.get("key-0000").unwrap(), None);
+        assert_eq!(replica.get("key-2999").unwrap(), Some(vec![b'v'; 1000]));
+    }
+
+    #[test]
+    fn a_snapshot_stream_cut_short_installs_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let replica = Engine::open(dir.path()).unwrap();
+        replica.put("kept".to_owned(), b"1".to_vec()).unwrap();
+
+        let chunk = |entries: &[&str], total_entries, last| SnapshotChunk {
+            sequence: 9,
+            total_entries,
+            entries: entries.iter().map(|key| SnapshotEntry { key: (*key).to_owned(), value: b"v".to_vec() }).collect(),
+            last,
+        };
+        let cases = [
+            (vec![chunk(&["a", "b"], 2, false)], "ended early: received 2 entries"),
+            (vec![chunk(&["a", "b"], 2, false), chunk(&["c"], 4, true)], "received 3 of 4 entries"),
+            (vec![chunk(&["a"], 1, false), SnapshotChunk { sequence: 10, ..chunk(&["b"], 2, true) }], "moved from"),
+        ];
+        for (chunks, reason) in cases {
+            let (tx, rx) = mpsc::channel(4);
+            for chunk in chunks {
+                tx.try_send(Ok(chunk)).unwrap();
+            }
+            drop(tx);
+            let err = replica.install_snapshot(9, Received::new(9, rx)).unwrap_err();
+            assert!(err.to_string().contains(reason), "{err}");
+        }
+
+        assert_eq!(replica.latest_sequence().unwrap(), 1);
+        assert_eq!(replica.get("kept").unwrap(), Some(b"1".to_vec()));
+        assert_eq!(replica.get("a").unwrap(), None);
+    }
+}
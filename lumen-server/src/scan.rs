@@ -0,0 +1,294 @@
+//! Key listings for clients (`Scan` RPC), with server-side cursors for
+//! scans too large to take in one stream.
+//!
+//! A scan lists the keys of a `lumen_core::KeyRange`: those under its
+//! `prefix`, from its `start` (inclusive) up to its `end` (exclusive), each
+//! left open when empty.
+//!
+//! A scan with a `limit` stops after that many entries and, if any are
+//! left, ends with a cursor token.  The cursor lives on the server for
+//! `ttl` after it was last used and remembers where each page starts, so
+//! the next page picks up where the last one stopped without the client
+//! tracking keys.  A token names one page; the page before the current one
+//! can be fetched again, so a stream broken partway through a page costs
+//! that page, not the whole scan.  Cursors live in memory: a restart, or a
+//! cursor left unused for its TTL, ends the scan with NOT_FOUND.
+//!
+//! Each stream reads from a snapshot taken when it opens (see
+//! `lumen_core::snapshot`), whose sequence its first message carries: a
+//! page sees every key as of that one point, however long it takes to
+//! stream.  Entries are read a chunk at a time, so a long page never holds
+//! the memtable for long, but writes made meanwhile cost the snapshot
+//! memory.  A stream still open `snapshot_max_age` after its snapshot was
+//! taken therefore ends its page early, with a cursor for the rest; the
+//! next page gets a snapshot of its own.
+//!
+//! A scan's filter (see `filter`) is kept with its cursor, like its range,
+//! so every page is filtered alike.
+//!
+//! A stream that reads past its first chunk is taken to be a sequential
+//! scan: from then on the next chunk is read ahead, while the one before
+//! it is still being sent, so the client never waits for both.  Scans that
+//! fit in one chunk, and point reads, read nothing ahead.
+
+use std::collections::HashMap;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tracing::{info, warn};
+
+use lumen_core::{is_reserved_key, Engine, KeyRange, RedactedKey};
+
+use crate::errors::engine_status;
+use crate::filter::Filter;
+use crate::kv::{ScanEntry, ScanRequest, ScanResponse};
+use crate::regions::Regions;
+
+/// Entries per streamed message.
+const CHUNK: usize = 256;
+
+/// How often expired cursors are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+struct Cursor {
+    range: KeyRange,
+    filter: Arc<Filter>,
+    /// The page the client may fetch next, and where it starts (after the
+    /// key, or from the first if `None`).
+    page: u64,
+    start: Option<String>,
+    /// Page `page - 1`, kept to be fetched again.
+    previous: Option<Option<String>>,
+    last_used: Instant,
+}
+
+/// The open scan cursors of one engine.
+#[derive(Debug)]
+pub struct Cursors {
+    ttl: Duration,
+    /// Longest a stream holds its snapshot.
+    snapshot_max_age: Duration,
+    cursors: Mutex<HashMap<u64, Cursor>>,
+}
+
+impl Cursors {
+    pub fn new(ttl: Duration, snapshot_max_age: Duration) -> Self {
+        Self { ttl, snapshot_max_age, cursors: Mutex::new(HashMap::new()) }
+    }
+
+    fn cursors(&self) -> MutexGuard<'_, HashMap<u64, Cursor>> {
+        self.cursors.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The cursor and page `token` names, with the cursor's range and
+    /// filter and where the page starts.
+    fn resume(&self, token: &str) -> Result<(u64, u64, KeyRange, Arc<Filter>, Option<String>), Status> {
+        let (id, page) = parse_token(token).ok_or_else(|| Status::invalid_argument("malformed scan cursor"))?;
+        let mut cursors = self.cursors();
+        let cursor = cursors.get_mut(&id).ok_or_else(|| Status::not_found("scan cursor has expired"))?;
+        cursor.last_used = Instant::now();
+
+        let start = if page == cursor.page {
+            cursor.start.clone()
+        } else if page + 1 == cursor.page && cursor.previous.is_some() {
+            // The last page again; the one after it is recomputed.
+            cursor.page     = page;
+            cursor.start    = cursor.previous.take().expect("checked above");
+            cursor.start.clone()
+        } else {
+            return Err(Status::failed_precondition("scan cursor has moved past this page"));
+        };
+        Ok((id, page, cursor.range.clone(), cursor.filter.clone(), start))
+    }
+
+    /// Record that page `page` of cursor `id` ended after `last`; the next
+    /// page starts there.  Returns its token.
+    fn advance(&self, id: u64, page: u64, last: String) -> Option<String> {
+        let mut cursors = self.cursors();
+        let cursor = cursors.get_mut(&id)?;
+        if cursor.page != page {
+            return None;
+        }
+        cursor.previous  = Some(std::mem::replace(&mut cursor.start, Some(last)));
+        cursor.page      = page + 1;
+        cursor.last_used = Instant::now();
+        Some(token(id, page + 1))
+    }
+
+    /// Open a cursor for a scan of `range` through `filter` whose first page
+    /// has been sent, ending after `last`.  Returns the token of the page
+    /// after.
+    fn open(&self, range: KeyRange, filter: Arc<Filter>, last: String) -> String {
+        let mut cursors = self.cursors();
+        let id = loop {
+            let id = rand::random::<u64>();
+            if !cursors.contains_key(&id) {
+                break id;
+            }
+        };
+        cursors.insert(id, Cursor {
+            range,
+            filter,
+            page: 1,
+            start: Some(last),
+            previous: Some(None),
+            last_used: Instant::now(),
+        });
+        token(id, 1)
+    }
+}
+
+fn token(id: u64, page: u64) -> String {
+    format!("{id:016x}.{page}")
+}
+
+fn parse_token(token: &str) -> Option<(u64, u64)> {
+    let (id, page) = token.split_once('.')?;
+    Some((u64::from_str_radix(id, 16).ok()?, page.parse().ok()?))
+}
+
+/// Drop cursors left unused for their TTL, for as long as the server runs.
+pub fn spawn_sweep(cursors: Arc<Cursors>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let now = Instant::now();
+            cursors.cursors().retain(|_, cursor| now.duration_since(cursor.last_used) < cursors.ttl);
+        }
+    });
+}
+
+/// Stream the page `req` asks for: from its cursor, or the first of its
+/// range, as of a snapshot taken now.
+pub fn stream_scan(
+    engine: Arc<Engine>,
+    cursors: Arc<Cursors>,
+    req: ScanRequest,
+    versioned: bool,
+) -> Result<ReceiverStream<Result<ScanResponse, Status>>, Status> {
+    let (resumed, range, filter, mut after) = if req.cursor.is_empty() {
+        let filter = req.filter.map(Filter::compile).transpose()?.unwrap_or_default();
+        (None, request_range(req.prefix, req.start, req.end), Arc::new(filter), None)
+    } else {
+        let (id, page, range, filter, start) = cursors.resume(&req.cursor)?;
+        (Some((id, page)), range, filter, start)
+    };
+    let limit = match req.limit {
+        0 => usize::MAX,
+        limit => limit as usize,
+    };
+    let snapshot = engine.snapshot().map_err(engine_status)?;
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        let mut snapshot_sequence = snapshot.sequence();
+        info!(
+            prefix = %RedactedKey(&range.prefix),
+            cursor = %req.cursor,
+            limit = req.limit,
+            snapshot = snapshot_sequence,
+            "Scan stream opened"
+        );
+        let mut sent = 0;
+
+        let read = |after: Option<String>, want: usize| {
+            let (snap, range) = (snapshot.clone(), range.clone());
+            tokio::task::spawn_blocking(move || snap.scan_range_page(&range, after.as_deref(), want))
+        };
+        // The next chunk, being read while the last is sent.
+        let mut ahead = None;
+
+        loop {
+            let want = CHUNK.min(limit - sent);
+            let page = match ahead.take() {
+                Some(read_ahead) => read_ahead.await,
+                None => read(after.clone(), want).await,
+            };
+            let chunk = match page {
+                Ok(Ok(chunk)) => chunk,
+                Ok(Err(e)) => {
+                    let _ = tx.send(Err(engine_status(e))).await;
+                    return;
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+                    return;
+                }
+            };
+
+            let exhausted = chunk.len() < want;
+            after = chunk.last().map(|(key, _)| key.clone()).or(after);
+            sent += chunk.len();
+
+            let entries: Vec<ScanEntry> = chunk
+                .into_iter()
+                .filter(|(key, _)| !is_reserved_key(key))
+                .filter_map(|(key, value)| {
+                    let value = if versioned { Regions::read(Some(value))? } else { value };
+                    if !filter.matches(&key, &value) {
+                        return None;
+                    }
+                    Some(ScanEntry { key, value: if req.keys_only { Vec::new() } else { value } })
+                })
+                .collect();
+
+            let expired = snapshot.age() >= cursors.snapshot_max_age;
+            if !exhausted && sent < limit && !expired {
+                ahead = Some(read(after.clone(), CHUNK.min(limit - sent)));
+                if !entries.is_empty() {
+                    let response = ScanResponse { entries, cursor: String::new(), snapshot_sequence };
+                    if tx.send(Ok(response)).await.is_err() {
+                        return;
+                    }
+                    snapshot_sequence = 0;
+                }
+                continue;
+            }
+
+            // The page is full, or has held its snapshot for long enough:
+            // hand out a cursor if anything is left.
+            let more = !exhausted && {
+                read(after.clone(), 1)
+                    .await
+                    .map_err(|e| Status::internal(e.to_string()))
+                    .and_then(|rest| rest.map_err(engine_status))
+                    .map(|rest| !rest.is_empty())
+            }
+            .unwrap_or(true);
+            let cursor = match (more, resumed, after) {
+                (true, Some((id, page)), Some(end)) => cursors.advance(id, page, end).unwrap_or_else(|| {
+                    warn!(cursor = %req.cursor, "Scan cursor expired or moved while its page was read");
+                    String::new()
+                }),
+                (true, None, Some(end)) => cursors.open(range.clone(), filter.clone(), end),
+                _ => String::new(),
+            };
+            if expired && !cursor.is_empty() {
+                info!(prefix = %RedactedKey(&range.prefix), entries = sent, "Scan page cut short to release its snapshot");
+            }
+            let _ = tx.send(Ok(ScanResponse { entries, cursor, snapshot_sequence })).await;
+            break;
+        }
+
+        info!(prefix = %RedactedKey(&range.prefix), entries = sent, "Scan stream closed");
+    });
+
+    Ok(ReceiverStream::new(rx))
+}
+
+/// The range a `ScanRequest` names, an empty `start` or `end` leaving it
+/// open at that end.
+fn request_range(prefix: String, start: String, end: String) -> KeyRange {
+    KeyRange {
+        prefix,
+        start: if start.is_empty() { Bound::Unbounded } else { Bound::Included(start) },
+        end:   if end.is_empty() { Bound::Unbounded } else { Bound::Excluded(end) },
+    }
+}
@@ -0,0 +1,63 @@
+//! Background scrubbing (`SCRUB_RATE_BYTES`, `SCRUB_INTERVAL_SECS`).
+//!
+//! Each local engine's WAL and checkpoint are read back and checksummed
+//! (see `lumen_core::scrub`), one engine after another, at no more than
+//! `SCRUB_RATE_BYTES` a second so that serving traffic keeps the disk.  A
+//! pass starts every `SCRUB_INTERVAL_SECS`, or as soon as the last one ends
+//! if it took longer.  Corruption is logged as an error for every damaged
+//! record or file, counted in `lumen_engine_scrub_corruptions`, and reported
+//! by the `lumen.Storage` health service until a pass finds none.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+use lumen_core::Engine;
+
+/// Scrub `engines` for as long as the server runs.  The receiver says
+/// whether the last pass found corruption.
+pub fn spawn_scrub(engines: Vec<Engine>, bytes_per_sec: u64, interval: Duration) -> watch::Receiver<bool> {
+    let (corrupt, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let mut found = 0;
+            for engine in &engines {
+                let scrubbed = engine.clone();
+                let report = match tokio::task::spawn_blocking(move || scrubbed.scrub(bytes_per_sec)).await {
+                    Ok(Ok(report)) => report,
+                    Ok(Err(e)) => {
+                        warn!(data_dir = %engine.data_dir().display(), error = %e, "Scrub failed");
+                        continue;
+                    }
+                    Err(e) => {
+                        warn!(data_dir = %engine.data_dir().display(), error = %e, "Scrub panicked");
+                        continue;
+                    }
+                };
+                for corruption in &report.corruptions {
+                    error!(
+                        file = %corruption.file.display(),
+                        offset = corruption.offset,
+                        problem = %corruption.problem,
+                        "Scrub found corruption"
+                    );
+                }
+                found += report.corruptions.len();
+                info!(
+                    data_dir = %engine.data_dir().display(),
+                    bytes = report.bytes,
+                    wal_records = report.wal_records,
+                    wal_skipped = report.wal_skipped,
+                    corruptions = report.corruptions.len(),
+                    "Scrub pass complete"
+                );
+            }
+            corrupt.send_replace(found > 0);
+        }
+    });
+    receiver
+}
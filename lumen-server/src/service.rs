@@ -2,37 +2,376 @@
 //!
 //! Each RPC handler:
 //!   1. Validates the request.
-//!   2. Delegates to the `Engine`.
+//!   2. Delegates to the `Engine` (or, when sharded, the `ShardRouter`).
 //!   3. Maps engine errors to an appropriate `tonic::Status` code.
 
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tonic::{Request, Response, Status};
-use tracing::{error, info, instrument};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::{error, instrument};
 
-use lumen_core::Engine;
+use lumen_core::{is_reserved_key, Engine, EngineError, ErrorCode, KeyRange, Leases, RedactedKey};
 
 use crate::kv::{
-    key_value_store_server::KeyValueStore,
+    key_value_store_server::{KeyValueStore, KeyValueStoreServer},
+    BatchPutRequest, BatchPutResponse,
+    CapabilitiesRequest, CapabilitiesResponse,
+    ChannelEvent,
+    CompareAndDeleteRequest, CompareAndDeleteResponse,
     DeleteRequest, DeleteResponse,
+    ExpireRequest, ExpireResponse,
+    GetAndSetRequest, GetAndSetResponse,
+    GetFieldRequest, GetFieldResponse,
     GetRequest, GetResponse,
-    PutRequest, PutResponse,
+    GossipRequest, GossipResponse,
+    GrantLeaseRequest, GrantLeaseResponse,
+    KeepAliveRequest, KeepAliveResponse,
+    LockRequest, LockResponse,
+    MultiGetRequest, MultiGetResponse,
+    PartitionInfo, PartitionsRequest, PartitionsResponse,
+    PatchJsonRequest, PatchJsonResponse,
+    PersistRequest, PersistResponse,
+    PingReqRequest, PingReqResponse,
+    ProgressAck, ProgressReport,
+    QueryIndexRequest, QueryIndexResponse,
+    PutRequest, PutResponse, PutResult,
+    ReadConsistency, ReadIndexRequest, ReadIndexResponse,
+    RebalanceRequest, RebalanceResponse,
+    RenameRequest, RenameResponse,
+    ReplicateRequest, ReplicationBatch,
+    ReplicationStatusRequest, ReplicationStatusResponse,
+    RevokeLeaseRequest, RevokeLeaseResponse,
+    ScanRequest, ScanResponse,
+    SessionRequest, SessionResponse,
+    SnapshotChunk, SnapshotRequest,
+    SubscribeRequest,
+    UnlockRequest, UnlockResponse,
+    ValueType,
+    WatchEvent, WatchRequest,
 };
+use crate::channels::Channels;
+use crate::errors;
+use crate::json::{self, json_status};
+use crate::leases::{self, lease_status};
+use crate::logging::{rpc_log, RequestLog};
+use crate::maintenance::Maintenance;
+use crate::membership::Membership;
+use crate::qos::Scheduler;
+use crate::regions::{ExportFilter, Regions};
+use crate::replication::{self, ReplicationState};
+use crate::scan::{self, Cursors};
+use crate::sessions::{self, Sessions};
+use crate::sharding::ShardRouter;
+use crate::usage::Usage;
+use crate::watch;
+
+/// Response metadata header carrying the serving node's applied sequence.
+const APPLIED_SEQUENCE_HEADER: &str = "x-lumen-applied-sequence";
+/// Longest a read waits for `min_sequence` to be applied before giving up.
+const MIN_SEQUENCE_WAIT: Duration = Duration::from_secs(1);
+/// Version of the `KeyValueStore` protocol served, reported by
+/// `Capabilities`; servers from before that RPC speak version 0.
+const PROTOCOL_VERSION: u32 = 1;
+/// Encodings requests may be compressed with, and responses are compressed
+/// with for callers that accept them (see `KvService::into_server`).
+const COMPRESSION: &[&str] = &["gzip"];
 
 // ---------------------------------------------------------------------------
 // KvService
 // ---------------------------------------------------------------------------
 
-/// Stateless wrapper that holds a shared reference to the storage engine.
+/// Where key/value operations are served from.
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// A single local engine (optionally replicated).
+    Engine(Arc<Engine>),
+    /// Keys partitioned across shards on a consistent-hash ring.
+    Sharded(Arc<ShardRouter>),
+}
+
+/// Stateless wrapper that holds a shared reference to the storage backend.
 #[derive(Debug)]
 pub struct KvService {
-    engine: Arc<Engine>,
+    backend: Backend,
+    replication: Arc<ReplicationState>,
+    membership: Arc<Membership>,
+    /// Set when values carry multi-region version stamps.
+    regions: Option<Arc<Regions>>,
+    /// Set on nodes that grant leases; writes to the engine go through it.
+    leases: Option<Arc<Leases>>,
+    /// Set on the same nodes as `leases`, whose registry commits go through.
+    sessions: Option<Arc<Sessions>>,
+    /// Set when CHANNELS configures notification channels.
+    channels: Option<Arc<Channels>>,
+    /// Names of the node's named databases (`DATABASES`).
+    databases: Vec<String>,
+    cursors: Arc<Cursors>,
+    usage: Arc<Usage>,
+    scheduler: Arc<Scheduler>,
+    maintenance: Arc<Maintenance>,
+    log: Arc<RequestLog>,
 }
 
 impl KvService {
-    pub fn new(engine: Arc<Engine>) -> Self {
-        Self { engine }
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        backend: Backend,
+        replication: Arc<ReplicationState>,
+        membership: Arc<Membership>,
+        regions: Option<Arc<Regions>>,
+        leases: Option<Arc<Leases>>,
+        sessions: Option<Arc<Sessions>>,
+        channels: Option<Arc<Channels>>,
+        databases: Vec<String>,
+        cursors: Arc<Cursors>,
+        usage: Arc<Usage>,
+        scheduler: Arc<Scheduler>,
+        maintenance: Arc<Maintenance>,
+        log: Arc<RequestLog>,
+    ) -> Self {
+        Self {
+            backend,
+            replication,
+            membership,
+            regions,
+            leases,
+            sessions,
+            channels,
+            databases,
+            cursors,
+            usage,
+            scheduler,
+            maintenance,
+            log,
+        }
+    }
+
+    /// This service as a gRPC server, accepting and sending compressed
+    /// messages.
+    pub fn into_server(self) -> KeyValueStoreServer<Self> {
+        KeyValueStoreServer::new(self)
+            .accept_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Gzip)
+    }
+
+    /// The optional features this node serves (see `CapabilitiesResponse`),
+    /// which depend on its role and configuration.
+    fn features(&self) -> Vec<String> {
+        let local     = self.engine().is_some();
+        let versioned = self.regions.is_some();
+        let features  = [
+            ("batch_put", true),
+            ("multi_get", true),
+            ("json", true),
+            ("databases", true),
+            ("scan", local),
+            ("watch", local),
+            ("indexes", local),
+            ("atomic", !versioned),
+            ("rename", !versioned && (local || self.leases.is_some())),
+            ("leases", self.leases.is_some()),
+            ("transactions", self.sessions.is_some()),
+            ("channels", self.channels.is_some()),
+        ];
+        features.into_iter().filter(|&(_, served)| served).map(|(name, _)| name.to_owned()).collect()
+    }
+
+    /// The single local engine; `None` on a shard router.
+    fn engine(&self) -> Option<&Arc<Engine>> {
+        match &self.backend {
+            Backend::Engine(engine) => Some(engine),
+            Backend::Sharded(_) => None,
+        }
     }
+
+    /// Enforce a read's freshness bounds and return the applied sequence the
+    /// read will observe (at least).
+    async fn await_freshness(
+        &self,
+        engine: &Arc<Engine>,
+        min_sequence: u64,
+        max_staleness_ms: u64,
+    ) -> Result<u64, Status> {
+        let mut applied = engine.latest_sequence().map_err(errors::engine_status)?;
+
+        if applied < min_sequence {
+            let engine   = engine.clone();
+            let deadline = Instant::now() + MIN_SEQUENCE_WAIT;
+
+            applied = tokio::task::spawn_blocking(move || {
+                let mut applied = engine.latest_sequence()?;
+                while applied < min_sequence {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        break;
+                    }
+                    applied = engine.wait_for_changes(applied, remaining)?;
+                }
+                Ok::<_, lumen_core::EngineError>(applied)
+            })
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .map_err(errors::engine_status)?;
+
+            if applied < min_sequence {
+                return Err(errors::coded(
+                    ErrorCode::Unavailable,
+                    format!("applied sequence {applied} is behind requested min_sequence {min_sequence}"),
+                ));
+            }
+        }
+
+        if max_staleness_ms > 0 {
+            let bound = Duration::from_millis(max_staleness_ms);
+            match self.replication.staleness() {
+                Some(staleness) if staleness <= bound => {}
+                Some(staleness) => {
+                    return Err(errors::coded(
+                        ErrorCode::Unavailable,
+                        format!(
+                            "replica is {}ms stale, exceeding max_staleness_ms {max_staleness_ms}",
+                            staleness.as_millis()
+                        ),
+                    ));
+                }
+                None => {
+                    return Err(errors::coded(
+                        ErrorCode::Unavailable,
+                        "replica has not caught up with its primary yet",
+                    ));
+                }
+            }
+        }
+
+        Ok(applied)
+    }
+
+    /// Consistency token for a write that has just been applied: this node's
+    /// latest sequence, which is at least the write's own.  Sequences are per
+    /// shard, so a router has none to give.
+    fn write_token(&self) -> Result<u64, EngineError> {
+        match &self.backend {
+            Backend::Engine(engine) => engine.latest_sequence(),
+            Backend::Sharded(_) => Ok(0),
+        }
+    }
+
+    /// Validate and apply one put, attached to its lease unless that is 0
+    /// (the caller has checked writability).
+    async fn put_one(&self, entry: PutRequest) -> Result<(), Status> {
+        if let Some(status) = invalid_key(&entry.key) {
+            return Err(status);
+        }
+        if entry.value_type() == ValueType::Json {
+            json::validate(&entry.value).map_err(json_status)?;
+        }
+        let PutRequest { key, value, lease, .. } = entry;
+
+        rpc_log!(self.log, "Put", key = %RedactedKey(&key), value_bytes = value.len(), lease, "PUT");
+
+        let written = key.len() + value.len();
+        match (&self.leases, &self.backend) {
+            (Some(leases), _) => {
+                let (leases, stored) = (leases.clone(), key.clone());
+                blocking(move || leases.put(stored, value, lease)).await?.map_err(|e| {
+                    error!(key = %RedactedKey(&key), error = %e, "PUT failed");
+                    lease_status(e)
+                })
+            }
+            (None, _) if lease != 0 => return Err(no_leases_status()),
+            (None, Backend::Engine(engine)) => {
+                let (engine, regions, stored) = (engine.clone(), self.regions.clone(), key.clone());
+                blocking(move || match &regions {
+                    Some(regions) => regions.put(&engine, stored, value),
+                    None => engine.put(stored, value),
+                })
+                .await?
+            }
+            .map_err(|e| {
+                error!(key = %RedactedKey(&key), error = %e, "PUT failed");
+                errors::engine_status(e)
+            }),
+            (None, Backend::Sharded(router)) => router
+                .put(key.clone(), value)
+                .await
+                .inspect_err(|status| error!(key = %RedactedKey(&key), error = %status.message(), "PUT failed")),
+        }?;
+
+        self.usage.record(&key, 0, written);
+        Ok(())
+    }
+
+    /// The keys an `Expire` or `Persist` names: `keys`, or those stored
+    /// under `prefix`.
+    fn selected_keys(&self, keys: Vec<String>, prefix: &str) -> Result<Vec<String>, Status> {
+        match (keys.is_empty(), prefix.is_empty()) {
+            (false, true) => {
+                if let Some(status) = keys.iter().find_map(|key| invalid_key(key)) {
+                    return Err(status);
+                }
+                Ok(keys)
+            }
+            (true, false) => {
+                let engine = self.engine().ok_or_else(no_leases_status)?;
+                let keys: Result<Vec<String>, _> = engine.scan(KeyRange::prefix(prefix)).and_then(|scan| {
+                    scan.map(|entry| entry.map(|(key, _)| key))
+                        .filter(|key| !key.as_ref().is_ok_and(|key| is_reserved_key(key)))
+                        .collect()
+                });
+                keys.map_err(errors::engine_status)
+            }
+            _ => Err(Status::invalid_argument("give either keys or a prefix")),
+        }
+    }
+}
+
+/// Run `write`, an engine write, on the blocking pool: under
+/// `WAL_SYNC=interval` or `adaptive` it waits out the group commit window
+/// and the sync, which on a runtime worker would hold up every request
+/// scheduled there, and cap the writes a window gathers at the workers.
+pub(crate) async fn blocking<T: Send + 'static>(write: impl FnOnce() -> T + Send + 'static) -> Result<T, Status> {
+    tokio::task::spawn_blocking(write).await.map_err(|e| Status::internal(format!("write task failed: {e}")))
+}
+
+/// Why clients may not read or write `key`, if they may not.
+pub(crate) fn invalid_key(key: &str) -> Option<Status> {
+    if key.is_empty() {
+        return Some(Status::invalid_argument("key must not be empty"));
+    }
+    is_reserved_key(key).then(|| Status::invalid_argument("keys starting with a NUL byte are reserved"))
+}
+
+/// Status returned for client writes sent to a read-only replica.
+fn read_only_status() -> Status {
+    errors::coded(ErrorCode::ReadOnly, "this node is a read-only replica; send writes to the primary")
+}
+
+/// Status returned for atomic operations on a multi-region node, whose
+/// values are versioned and may be overwritten by another region's writes.
+fn versioned_status() -> Status {
+    errors::coded(ErrorCode::PreconditionFailed, "atomic operations are not supported on a node configured with a REGION")
+}
+
+/// Status returned for lease RPCs sent to a node that grants no leases.
+fn no_leases_status() -> Status {
+    errors::coded(ErrorCode::PreconditionFailed, "leases are only granted by an unsharded primary without a REGION")
+}
+
+/// Status returned for `Session` sent to a node that serves no sessions.
+fn no_sessions_status() -> Status {
+    errors::coded(ErrorCode::PreconditionFailed, "sessions are only served by an unsharded primary without a REGION")
+}
+
+/// Status returned for single-engine RPCs sent to a shard router.
+pub(crate) fn sharded_status() -> Status {
+    errors::coded(
+        ErrorCode::PreconditionFailed,
+        "this node routes to several shards; sequence-based RPCs must target a shard node directly",
+    )
 }
 
 // ---------------------------------------------------------------------------
@@ -41,62 +380,219 @@ impl KvService {
 
 #[tonic::async_trait]
 impl KeyValueStore for KvService {
+    type ReplicateStream = ReceiverStream<Result<ReplicationBatch, Status>>;
+    type SnapshotStream  = ReceiverStream<Result<SnapshotChunk, Status>>;
+    type WatchStream     = ReceiverStream<Result<WatchEvent, Status>>;
+    type SubscribeStream = ReceiverStream<Result<ChannelEvent, Status>>;
+    type ScanStream      = ReceiverStream<Result<ScanResponse, Status>>;
+    type KeepAliveStream = ReceiverStream<Result<KeepAliveResponse, Status>>;
+    type SessionStream   = ReceiverStream<Result<SessionResponse, Status>>;
+
+    /// Report the protocol version, features and compression served.
+    #[instrument(name = "rpc_capabilities", skip(self, request))]
+    async fn capabilities(
+        &self,
+        request: Request<CapabilitiesRequest>,
+    ) -> Result<Response<CapabilitiesResponse>, Status> {
+        let req = request.into_inner();
+        rpc_log!(self.log, "Capabilities", client_protocol = req.protocol_version, "CAPABILITIES");
+
+        Ok(Response::new(CapabilitiesResponse {
+            protocol_version: PROTOCOL_VERSION,
+            server_version:   env!("CARGO_PKG_VERSION").to_owned(),
+            features:         self.features(),
+            compression:      COMPRESSION.iter().map(|&name| name.to_owned()).collect(),
+            databases:        self.databases.clone(),
+        }))
+    }
+
     /// Write a key/value pair.
     #[instrument(name = "rpc_put", skip(self, request))]
     async fn put(
         &self,
         request: Request<PutRequest>,
     ) -> Result<Response<PutResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
         let req = request.into_inner();
 
-        if req.key.is_empty() {
-            return Err(Status::invalid_argument("key must not be empty"));
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
         }
 
-        info!(key = %req.key, value_bytes = req.value.len(), "PUT");
-
-        self.engine
-            .put(req.key.clone(), req.value.into())
-            .map_err(|e| {
-                error!(key = %req.key, error = %e, "PUT failed");
-                Status::internal(e.to_string())
-            })?;
+        self.put_one(req).await?;
 
-        Ok(Response::new(PutResponse { success: true }))
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(PutResponse { success: true, sequence }))
     }
 
     /// Read the value for a key.
     ///
     /// Returns `found = false` (and an empty value) when the key is absent —
     /// this is NOT treated as an error at the RPC layer.
+    ///
+    /// Freshness bounds (`min_sequence`, `max_staleness_ms`) are checked first;
+    /// the applied sequence observed by the read is returned as metadata.
+    /// Linearizable reads additionally wait for the primary's read index.
     #[instrument(name = "rpc_get", skip(self, request))]
     async fn get(
         &self,
         request: Request<GetRequest>,
     ) -> Result<Response<GetResponse>, Status> {
-        let req = request.into_inner();
+        if let Some(status) = self.maintenance.check_read() {
+            return Err(status);
+        }
+
+        let _permit = self.scheduler.admit(&request).await?;
 
-        if req.key.is_empty() {
-            return Err(Status::invalid_argument("key must not be empty"));
+        let req = request.into_inner();
+        if let Some(status) = invalid_key(&req.key) {
+            return Err(status);
         }
 
-        info!(key = %req.key, "GET");
+        rpc_log!(self.log, "Get", key = %RedactedKey(&req.key), "GET");
+
+        let engine = match &self.backend {
+            Backend::Engine(engine) => engine,
+            Backend::Sharded(router) => {
+                // Sequences are per shard, so only the consistency level can
+                // be honoured across the router.
+                if req.min_sequence > 0 || req.max_staleness_ms > 0 {
+                    return Err(Status::invalid_argument(
+                        "min_sequence and max_staleness_ms are not supported through a shard router",
+                    ));
+                }
+
+                let maybe_value = router
+                    .get(&req.key, req.consistency)
+                    .await
+                    .inspect_err(|status| {
+                        error!(key = %RedactedKey(&req.key), error = %status.message(), "GET failed")
+                    })?;
+
+                self.usage.record(&req.key, maybe_value.as_ref().map_or(0, Vec::len), 0);
+                return Ok(Response::new(GetResponse {
+                    found: maybe_value.is_some(),
+                    value: maybe_value.unwrap_or_default(),
+                }));
+            }
+        };
+
+        let min_sequence = if req.consistency() == ReadConsistency::Linearizable {
+            let index = self.replication.read_index(engine).await?;
+            req.min_sequence.max(index)
+        } else {
+            req.min_sequence
+        };
+
+        let applied = self
+            .await_freshness(engine, min_sequence, req.max_staleness_ms)
+            .await?;
 
-        let maybe_value = self.engine.get(&req.key).map_err(|e| {
-            error!(key = %req.key, error = %e, "GET failed");
-            Status::internal(e.to_string())
+        let mut maybe_value = engine.get(&req.key).map_err(|e| {
+            error!(key = %RedactedKey(&req.key), error = %e, "GET failed");
+            errors::engine_status(e)
         })?;
+        if self.regions.is_some() {
+            maybe_value = Regions::read(maybe_value);
+        }
+        self.usage.record(&req.key, maybe_value.as_ref().map_or(0, Vec::len), 0);
 
-        match maybe_value {
-            Some(value) => Ok(Response::new(GetResponse {
-                value: value.into(),
+        let mut response = match maybe_value {
+            Some(value) => Response::new(GetResponse {
+                value,
                 found: true,
-            })),
-            None => Ok(Response::new(GetResponse {
+            }),
+            None => Response::new(GetResponse {
                 value: Vec::new(),
                 found: false,
-            })),
+            }),
+        };
+
+        response
+            .metadata_mut()
+            .insert(APPLIED_SEQUENCE_HEADER, MetadataValue::from(applied));
+        Ok(response)
+    }
+
+    /// Read several keys in one call; `values[i]` answers `keys[i]`.
+    ///
+    /// Freshness bounds and consistency are checked once for the whole read,
+    /// as in `get`, and every value is read from one snapshot.  Through a
+    /// shard router each key is read from its shard on its own, so the values
+    /// are not a single point in time.
+    #[instrument(name = "rpc_multi_get", skip(self, request))]
+    async fn multi_get(
+        &self,
+        request: Request<MultiGetRequest>,
+    ) -> Result<Response<MultiGetResponse>, Status> {
+        if let Some(status) = self.maintenance.check_read() {
+            return Err(status);
+        }
+
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+        if let Some(status) = req.keys.iter().find_map(|key| invalid_key(key)) {
+            return Err(status);
         }
+
+        rpc_log!(self.log, "MultiGet", keys = req.keys.len(), "MULTI GET");
+
+        let engine = match &self.backend {
+            Backend::Engine(engine) => engine,
+            Backend::Sharded(router) => {
+                if req.min_sequence > 0 || req.max_staleness_ms > 0 {
+                    return Err(Status::invalid_argument(
+                        "min_sequence and max_staleness_ms are not supported through a shard router",
+                    ));
+                }
+
+                let mut values = Vec::with_capacity(req.keys.len());
+                for key in &req.keys {
+                    let maybe_value = router.get(key, req.consistency).await.inspect_err(|status| {
+                        error!(key = %RedactedKey(key), error = %status.message(), "MULTI GET failed")
+                    })?;
+                    self.usage.record(key, maybe_value.as_ref().map_or(0, Vec::len), 0);
+                    values.push(GetResponse { found: maybe_value.is_some(), value: maybe_value.unwrap_or_default() });
+                }
+                return Ok(Response::new(MultiGetResponse { values }));
+            }
+        };
+
+        let min_sequence = if req.consistency() == ReadConsistency::Linearizable {
+            let index = self.replication.read_index(engine).await?;
+            req.min_sequence.max(index)
+        } else {
+            req.min_sequence
+        };
+
+        let applied = self
+            .await_freshness(engine, min_sequence, req.max_staleness_ms)
+            .await?;
+
+        let snapshot   = engine.snapshot().map_err(errors::engine_status)?;
+        let mut values = Vec::with_capacity(req.keys.len());
+        for key in &req.keys {
+            let mut maybe_value = snapshot.get(key).map_err(|e| {
+                error!(key = %RedactedKey(key), error = %e, "MULTI GET failed");
+                errors::engine_status(e)
+            })?;
+            if self.regions.is_some() {
+                maybe_value = Regions::read(maybe_value);
+            }
+            self.usage.record(key, maybe_value.as_ref().map_or(0, Vec::len), 0);
+            values.push(GetResponse { found: maybe_value.is_some(), value: maybe_value.unwrap_or_default() });
+        }
+        let applied = applied.max(snapshot.sequence());
+        drop(snapshot);
+
+        let mut response = Response::new(MultiGetResponse { values });
+        response
+            .metadata_mut()
+            .insert(APPLIED_SEQUENCE_HEADER, MetadataValue::from(applied));
+        Ok(response)
     }
 
     /// Delete a key from the store.
@@ -107,19 +603,941 @@ impl KeyValueStore for KvService {
         &self,
         request: Request<DeleteRequest>,
     ) -> Result<Response<DeleteResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if let Some(status) = invalid_key(&req.key) {
+            return Err(status);
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+
+        rpc_log!(self.log, "Delete", key = %RedactedKey(&req.key), "DELETE");
+
+        let existed = match (&self.backend, &self.leases) {
+            (_, Some(leases)) => {
+                let (leases, key) = (leases.clone(), req.key.clone());
+                blocking(move || leases.delete(&key)).await?.map_err(|e| {
+                    error!(key = %RedactedKey(&req.key), error = %e, "DELETE failed");
+                    lease_status(e)
+                })?
+            }
+            (Backend::Engine(engine), None) => {
+                let (engine, regions, key) = (engine.clone(), self.regions.clone(), req.key.clone());
+                blocking(move || match &regions {
+                    Some(regions) => regions.delete(&engine, &key),
+                    None => engine.delete(&key),
+                })
+                .await?
+            }
+            .map_err(|e| {
+                error!(key = %RedactedKey(&req.key), error = %e, "DELETE failed");
+                errors::engine_status(e)
+            })?,
+            (Backend::Sharded(router), None) => router
+                .delete(&req.key)
+                .await
+                .inspect_err(|status| {
+                    error!(key = %RedactedKey(&req.key), error = %status.message(), "DELETE failed")
+                })?,
+        };
+
+        self.usage.record(&req.key, 0, req.key.len());
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(DeleteResponse { success: existed, sequence }))
+    }
+
+    /// Apply a batch of puts in order, reporting each entry's outcome.
+    #[instrument(name = "rpc_batch_put", skip(self, request))]
+    async fn batch_put(
+        &self,
+        request: Request<BatchPutRequest>,
+    ) -> Result<Response<BatchPutResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+
+        rpc_log!(self.log, "BatchPut", entries = req.entries.len(), "BATCH PUT");
+
+        let mut results = Vec::with_capacity(req.entries.len());
+        for entry in req.entries {
+            let result = match self.put_one(entry).await {
+                Ok(()) => PutResult::default(),
+                Err(status) => PutResult { code: status.code() as i32, message: status.message().to_owned() },
+            };
+            results.push(result);
+        }
+
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(BatchPutResponse { results, sequence }))
+    }
+
+    /// Delete a key only if it holds the expected value.
+    #[instrument(name = "rpc_compare_and_delete", skip(self, request))]
+    async fn compare_and_delete(
+        &self,
+        request: Request<CompareAndDeleteRequest>,
+    ) -> Result<Response<CompareAndDeleteResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if let Some(status) = invalid_key(&req.key) {
+            return Err(status);
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+        if self.regions.is_some() {
+            return Err(versioned_status());
+        }
+
+        rpc_log!(self.log, "CompareAndDelete", key = %RedactedKey(&req.key), "COMPARE AND DELETE");
+
+        let deleted = match (&self.backend, &self.leases) {
+            (_, Some(leases)) => {
+                let (leases, key, expected) = (leases.clone(), req.key.clone(), req.expected.clone());
+                blocking(move || leases.compare_and_delete(&key, &expected)).await?.map_err(lease_status)
+            }
+            (Backend::Engine(engine), None) => {
+                let (engine, key, expected) = (engine.clone(), req.key.clone(), req.expected.clone());
+                blocking(move || engine.compare_and_delete(&key, &expected)).await?.map_err(errors::engine_status)
+            }
+            (Backend::Sharded(router), None) => router.compare_and_delete(&req.key, &req.expected).await,
+        }
+        .inspect_err(|status| {
+            error!(key = %RedactedKey(&req.key), error = %status.message(), "COMPARE AND DELETE failed")
+        })?;
+
+        self.usage.record(&req.key, 0, req.key.len());
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(CompareAndDeleteResponse { deleted, sequence }))
+    }
+
+    /// Set a key and return the value it replaced.
+    #[instrument(name = "rpc_get_and_set", skip(self, request))]
+    async fn get_and_set(
+        &self,
+        request: Request<GetAndSetRequest>,
+    ) -> Result<Response<GetAndSetResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if let Some(status) = invalid_key(&req.key) {
+            return Err(status);
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+        if self.regions.is_some() {
+            return Err(versioned_status());
+        }
+
+        rpc_log!(self.log, "GetAndSet", key = %RedactedKey(&req.key), value_bytes = req.value.len(), "GET AND SET");
+
+        let key      = req.key.clone();
+        let written  = req.key.len() + req.value.len();
+        let previous = match (&self.backend, &self.leases) {
+            (_, Some(leases)) => {
+                let leases = leases.clone();
+                blocking(move || leases.get_and_set(req.key, req.value)).await?.map_err(lease_status)
+            }
+            (Backend::Engine(engine), None) => {
+                let engine = engine.clone();
+                blocking(move || engine.get_and_set(req.key, req.value)).await?.map_err(errors::engine_status)
+            }
+            (Backend::Sharded(router), None) => router.get_and_set(req.key, req.value).await,
+        }
+        .inspect_err(|status| error!(key = %RedactedKey(&key), error = %status.message(), "GET AND SET failed"))?;
+        self.usage.record(&key, previous.as_ref().map_or(0, Vec::len), written);
+
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(GetAndSetResponse {
+            found: previous.is_some(),
+            previous: previous.unwrap_or_default(),
+            sequence,
+        }))
+    }
+
+    /// Move a key's value to another key.
+    #[instrument(name = "rpc_rename", skip(self, request))]
+    async fn rename(
+        &self,
+        request: Request<RenameRequest>,
+    ) -> Result<Response<RenameResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if let Some(status) = invalid_key(&req.key).or_else(|| invalid_key(&req.new_key)) {
+            return Err(status);
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+        if self.regions.is_some() {
+            return Err(versioned_status());
+        }
+
+        rpc_log!(
+            self.log,
+            "Rename",
+            key = %RedactedKey(&req.key),
+            new_key = %RedactedKey(&req.new_key),
+            overwrite = req.overwrite,
+            "RENAME"
+        );
+
+        let (key, new_key, overwrite) = (req.key.clone(), req.new_key.clone(), req.overwrite);
+        let renamed = match (&self.backend, &self.leases) {
+            (_, Some(leases)) => {
+                let leases = leases.clone();
+                blocking(move || leases.rename(&key, new_key, overwrite)).await?.map_err(lease_status)
+            }
+            (Backend::Engine(engine), None) => {
+                let engine = engine.clone();
+                blocking(move || engine.rename(&key, new_key, overwrite)).await?.map_err(errors::engine_status)
+            }
+            (Backend::Sharded(_), None) => Err(Status::failed_precondition(
+                "Rename is not served by shard routers, as the two keys may live on different shards",
+            )),
+        }
+        .inspect_err(|status| error!(key = %RedactedKey(&req.key), error = %status.message(), "RENAME failed"))?;
+
+        self.usage.record(&req.key, 0, req.key.len() + req.new_key.len());
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(RenameResponse { renamed, sequence }))
+    }
+
+    /// Look up the keys a secondary index finds under a value.
+    #[instrument(name = "rpc_query_index", skip(self, request))]
+    async fn query_index(
+        &self,
+        request: Request<QueryIndexRequest>,
+    ) -> Result<Response<QueryIndexResponse>, Status> {
+        if let Some(status) = self.maintenance.check_read() {
+            return Err(status);
+        }
+
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req    = request.into_inner();
+        let engine = self.engine().ok_or_else(sharded_status)?;
+
+        let keys = engine.query_index(&req.index, &req.value).map_err(|e| match e {
+            EngineError::UnknownIndex(_) => errors::engine_status(e),
+            e => {
+                error!(index = %req.index, error = %e, "QUERY INDEX failed");
+                errors::engine_status(e)
+            }
+        })?;
+
+        rpc_log!(self.log, "QueryIndex", index = %req.index, keys = keys.len(), "QUERY INDEX");
+        Ok(Response::new(QueryIndexResponse { keys }))
+    }
+
+    /// Read one field of a JSON value.  `found = false` if the key or the
+    /// field is absent.
+    #[instrument(name = "rpc_get_field", skip(self, request))]
+    async fn get_field(
+        &self,
+        request: Request<GetFieldRequest>,
+    ) -> Result<Response<GetFieldResponse>, Status> {
+        if let Some(status) = self.maintenance.check_read() {
+            return Err(status);
+        }
+
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+        if let Some(status) = invalid_key(&req.key) {
+            return Err(status);
+        }
+
+        rpc_log!(self.log, "GetField", key = %RedactedKey(&req.key), pointer = %req.pointer, "GET FIELD");
+
+        let stored = match &self.backend {
+            Backend::Engine(engine) => engine
+                .get(&req.key)
+                .map(|value| if self.regions.is_some() { Regions::read(value) } else { value })
+                .map_err(errors::engine_status),
+            Backend::Sharded(router) => router.get(&req.key, ReadConsistency::Default as i32).await,
+        }
+        .inspect_err(|status| error!(key = %RedactedKey(&req.key), error = %status.message(), "GET FIELD failed"))?;
+
+        let field = match stored {
+            Some(stored) => json::get_field(&stored, &req.pointer).map_err(json_status)?,
+            None => None,
+        };
+        self.usage.record(&req.key, field.as_ref().map_or(0, Vec::len), 0);
+        Ok(Response::new(GetFieldResponse { found: field.is_some(), value: field.unwrap_or_default() }))
+    }
+
+    /// Apply a JSON Merge Patch to a stored JSON value in one write.
+    #[instrument(name = "rpc_patch_json", skip(self, request))]
+    async fn patch_json(
+        &self,
+        request: Request<PatchJsonRequest>,
+    ) -> Result<Response<PatchJsonResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
         let req = request.into_inner();
 
-        if req.key.is_empty() {
-            return Err(Status::invalid_argument("key must not be empty"));
+        if let Some(status) = invalid_key(&req.key) {
+            return Err(status);
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
         }
+        if self.regions.is_some() {
+            return Err(versioned_status());
+        }
+
+        rpc_log!(self.log, "PatchJson", key = %RedactedKey(&req.key), patch_bytes = req.patch.len(), "PATCH JSON");
+
+        // A patch keeps the key's lease, so it need not go through the
+        // registry.
+        let key     = req.key.clone();
+        let written = req.key.len() + req.patch.len();
+        let value   = match &self.backend {
+            Backend::Engine(engine) => json::patch(engine, req.key, &req.patch).map_err(json_status),
+            Backend::Sharded(router) => router.patch_json(req.key, req.patch).await,
+        }
+        .inspect_err(|status| error!(key = %RedactedKey(&key), error = %status.message(), "PATCH JSON failed"))?;
+        self.usage.record(&key, value.len(), written);
+
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(PatchJsonResponse { value, sequence }))
+    }
 
-        info!(key = %req.key, "DELETE");
+    /// Stream committed changes to keys under a prefix, starting after
+    /// `from_sequence` (or from the latest commit when it is 0).
+    #[instrument(name = "rpc_watch", skip(self, request))]
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let req    = request.into_inner();
+        let engine = self.engine().ok_or_else(sharded_status)?;
 
-        let existed = self.engine.delete(&req.key).map_err(|e| {
-            error!(key = %req.key, error = %e, "DELETE failed");
-            Status::internal(e.to_string())
+        let latest = engine.latest_sequence().map_err(|e| {
+            error!(error = %e, "WATCH failed");
+            errors::engine_status(e)
         })?;
 
-        Ok(Response::new(DeleteResponse { success: existed }))
+        if req.from_sequence > latest {
+            return Err(Status::out_of_range(format!(
+                "from_sequence {} is ahead of this node's latest sequence {latest}",
+                req.from_sequence
+            )));
+        }
+
+        let checkpoint = engine.checkpoint_sequence();
+        if req.from_sequence > 0 && req.from_sequence < checkpoint {
+            return Err(Status::failed_precondition(format!(
+                "from_sequence {} predates this node's checkpoint at {checkpoint}; changes are no longer retained",
+                req.from_sequence
+            )));
+        }
+
+        let from_sequence = if req.from_sequence == 0 { latest } else { req.from_sequence };
+        rpc_log!(self.log, "Watch", prefix = %RedactedKey(&req.prefix), from_sequence, "WATCH");
+
+        Ok(Response::new(watch::stream_watch(
+            engine.clone(),
+            req.prefix,
+            from_sequence,
+            self.regions.is_some(),
+        )))
+    }
+
+    /// List keys under a prefix, a page at a time when `limit` is set.
+    #[instrument(name = "rpc_scan", skip(self, request))]
+    async fn scan(
+        &self,
+        request: Request<ScanRequest>,
+    ) -> Result<Response<Self::ScanStream>, Status> {
+        if let Some(status) = self.maintenance.check_read() {
+            return Err(status);
+        }
+
+        let req    = request.into_inner();
+        let engine = self.engine().ok_or_else(sharded_status)?;
+
+        rpc_log!(
+            self.log,
+            "Scan",
+            prefix = %RedactedKey(&req.prefix),
+            start = %RedactedKey(&req.start),
+            end = %RedactedKey(&req.end),
+            limit = req.limit,
+            resumed = !req.cursor.is_empty(),
+            filtered = req.filter.is_some(),
+            "SCAN"
+        );
+        Ok(Response::new(scan::stream_scan(
+            engine.clone(),
+            self.cursors.clone(),
+            req,
+            self.regions.is_some(),
+        )?))
+    }
+
+    /// Stream the events published to a notification channel from now on.
+    #[instrument(name = "rpc_subscribe", skip(self, request))]
+    async fn subscribe(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeStream>, Status> {
+        if let Some(status) = self.maintenance.check_read() {
+            return Err(status);
+        }
+
+        let req      = request.into_inner();
+        let channels = self
+            .channels
+            .as_ref()
+            .ok_or_else(|| Status::failed_precondition("no notification channels are configured on this node"))?;
+
+        rpc_log!(self.log, "Subscribe", channel = %req.channel, "SUBSCRIBE");
+        Ok(Response::new(channels.subscribe(&req.channel)?))
+    }
+
+    /// Grant a lease that expires unless kept alive.
+    #[instrument(name = "rpc_grant_lease", skip(self, request))]
+    async fn grant_lease(
+        &self,
+        request: Request<GrantLeaseRequest>,
+    ) -> Result<Response<GrantLeaseResponse>, Status> {
+        let _write = self.maintenance.write().await?;
+
+        let req = request.into_inner();
+
+        let ttl = Duration::from_secs(req.ttl_seconds);
+        if ttl.is_zero() || ttl > leases::MAX_TTL {
+            return Err(Status::invalid_argument(format!(
+                "ttl_seconds must be between 1 and {}",
+                leases::MAX_TTL.as_secs()
+            )));
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+
+        let leases = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        let id = blocking(move || leases.grant(req.id, ttl)).await?.map_err(|e| {
+            error!(lease = req.id, error = %e, "GRANT LEASE failed");
+            lease_status(e)
+        })?;
+
+        rpc_log!(self.log, "GrantLease", lease = id, ttl_seconds = req.ttl_seconds, "GRANT LEASE");
+        Ok(Response::new(GrantLeaseResponse { id, ttl_seconds: req.ttl_seconds }))
+    }
+
+    /// Renew leases for as long as the client keeps sending their numbers.
+    #[instrument(name = "rpc_keep_alive", skip(self, request))]
+    async fn keep_alive(
+        &self,
+        request: Request<Streaming<KeepAliveRequest>>,
+    ) -> Result<Response<Self::KeepAliveStream>, Status> {
+        let leases = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        Ok(Response::new(leases::stream_keep_alive(leases, request.into_inner())))
+    }
+
+    /// End a lease now, deleting its keys.
+    #[instrument(name = "rpc_revoke_lease", skip(self, request))]
+    async fn revoke_lease(
+        &self,
+        request: Request<RevokeLeaseRequest>,
+    ) -> Result<Response<RevokeLeaseResponse>, Status> {
+        let _write = self.maintenance.write().await?;
+
+        let req = request.into_inner();
+
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+
+        let leases  = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        let deleted = blocking(move || leases.revoke(req.id)).await?.map_err(|e| {
+            error!(lease = req.id, error = %e, "REVOKE LEASE failed");
+            lease_status(e)
+        })?;
+
+        rpc_log!(self.log, "RevokeLease", lease = req.id, deleted_keys = deleted, "REVOKE LEASE");
+        Ok(Response::new(RevokeLeaseResponse { deleted_keys: deleted as u64 }))
+    }
+
+    /// Attach many keys to a lease at once.
+    #[instrument(name = "rpc_expire", skip(self, request))]
+    async fn expire(
+        &self,
+        request: Request<ExpireRequest>,
+    ) -> Result<Response<ExpireResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+        let leases = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        let ttl    = Duration::from_secs(req.ttl_seconds);
+        if req.lease == 0 && (ttl.is_zero() || ttl > leases::MAX_TTL) {
+            return Err(Status::invalid_argument(format!(
+                "ttl_seconds must be between 1 and {} without a lease",
+                leases::MAX_TTL.as_secs()
+            )));
+        }
+        let keys = self.selected_keys(req.keys, &req.prefix)?;
+
+        let granted  = req.lease;
+        let attached = blocking(move || {
+            let lease = match granted {
+                0 => leases.grant(0, ttl)?,
+                lease => lease,
+            };
+            match leases.attach(&keys, lease) {
+                Ok(attached) => Ok((lease, attached)),
+                Err(e) => {
+                    if granted == 0 {
+                        let _ = leases.revoke(lease);
+                    }
+                    Err(e)
+                }
+            }
+        });
+        let (lease, attached) = attached.await?.map_err(|e| {
+            error!(lease = req.lease, error = %e, "EXPIRE failed");
+            lease_status(e)
+        })?;
+
+        rpc_log!(
+            self.log,
+            "Expire",
+            prefix = %RedactedKey(&req.prefix),
+            lease,
+            keys = attached,
+            ttl_seconds = req.ttl_seconds,
+            "EXPIRE"
+        );
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(ExpireResponse { lease, keys: attached as u64, sequence }))
+    }
+
+    /// Detach many keys from their leases at once.
+    #[instrument(name = "rpc_persist", skip(self, request))]
+    async fn persist(
+        &self,
+        request: Request<PersistRequest>,
+    ) -> Result<Response<PersistResponse>, Status> {
+        let _write  = self.maintenance.write().await?;
+        let _permit = self.scheduler.admit(&request).await?;
+
+        let req = request.into_inner();
+
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+        let leases = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        let keys   = self.selected_keys(req.keys, &req.prefix)?;
+        let detached = blocking(move || leases.detach(&keys)).await?.map_err(|e| {
+            error!(error = %e, "PERSIST failed");
+            lease_status(e)
+        })?;
+
+        rpc_log!(self.log, "Persist", prefix = %RedactedKey(&req.prefix), keys = detached, "PERSIST");
+        let sequence = self.write_token().map_err(errors::engine_status)?;
+        Ok(Response::new(PersistResponse { keys: detached as u64, sequence }))
+    }
+
+    /// Take a named lock for a lease, waiting for its holder to release it.
+    #[instrument(name = "rpc_lock", skip(self, request))]
+    async fn lock(
+        &self,
+        request: Request<LockRequest>,
+    ) -> Result<Response<LockResponse>, Status> {
+        // A waiting lock is only freed by writes, which maintenance stops, so
+        // it need not be drained.
+        drop(self.maintenance.write().await?);
+
+        let req = request.into_inner();
+
+        if req.name.is_empty() {
+            return Err(Status::invalid_argument("name must not be empty"));
+        }
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+
+        let leases = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        let engine = self.engine().ok_or_else(no_leases_status)?.clone();
+        let wait   = Duration::from_millis(req.wait_ms).min(leases::MAX_LOCK_WAIT);
+
+        let fencing_token = leases::lock(leases, engine, req.name.clone(), req.lease, wait)
+            .await
+            .inspect_err(|status| {
+                // A lock still held when the wait ends is the caller's to retry.
+                if status.code() != Code::Aborted {
+                    error!(lock = %req.name, error = %status.message(), "LOCK failed");
+                }
+            })?;
+
+        rpc_log!(self.log, "Lock", lock = %req.name, lease = req.lease, fencing_token, "LOCK");
+        Ok(Response::new(LockResponse { fencing_token }))
+    }
+
+    /// Release a named lock held with the given fencing token.
+    #[instrument(name = "rpc_unlock", skip(self, request))]
+    async fn unlock(
+        &self,
+        request: Request<UnlockRequest>,
+    ) -> Result<Response<UnlockResponse>, Status> {
+        let _write = self.maintenance.write().await?;
+
+        let req = request.into_inner();
+
+        if self.replication.is_read_only() {
+            return Err(read_only_status());
+        }
+
+        let leases   = self.leases.as_ref().ok_or_else(no_leases_status)?.clone();
+        let (name, fencing_token) = (req.name.clone(), req.fencing_token);
+        let released = blocking(move || leases.unlock(&name, fencing_token)).await?.map_err(|e| {
+            error!(lock = %req.name, error = %e, "UNLOCK failed");
+            lease_status(e)
+        })?;
+
+        rpc_log!(self.log, "Unlock", lock = %req.name, fencing_token = req.fencing_token, released, "UNLOCK");
+        Ok(Response::new(UnlockResponse { released }))
+    }
+
+    /// Serve one interactive transaction, a request and response at a time.
+    #[instrument(name = "rpc_session", skip(self, request))]
+    async fn session(
+        &self,
+        request: Request<Streaming<SessionRequest>>,
+    ) -> Result<Response<Self::SessionStream>, Status> {
+        let sessions = self.sessions.as_ref().ok_or_else(no_sessions_status)?.clone();
+        Ok(Response::new(sessions::stream_session(
+            sessions,
+            self.maintenance.clone(),
+            self.usage.clone(),
+            request.into_inner(),
+        )))
+    }
+
+    /// Stream committed changes to a replica, starting after `from_sequence`.
+    #[instrument(name = "rpc_replicate", skip(self, request))]
+    async fn replicate(
+        &self,
+        request: Request<ReplicateRequest>,
+    ) -> Result<Response<Self::ReplicateStream>, Status> {
+        let req    = request.into_inner();
+        let engine = self.engine().ok_or_else(sharded_status)?;
+
+        let replica_id = if req.replica_id.is_empty() {
+            "anonymous".to_owned()
+        } else {
+            req.replica_id
+        };
+
+        let latest = engine.latest_sequence().map_err(|e| {
+            error!(error = %e, "REPLICATE failed");
+            errors::engine_status(e)
+        })?;
+
+        if req.from_sequence > latest {
+            return Err(Status::out_of_range(format!(
+                "from_sequence {} is ahead of this node's latest sequence {latest}",
+                req.from_sequence
+            )));
+        }
+
+        let checkpoint = engine.checkpoint_sequence();
+        if req.from_sequence < checkpoint {
+            return Err(Status::failed_precondition(format!(
+                "from_sequence {} predates this node's checkpoint at {checkpoint}; bootstrap from a snapshot",
+                req.from_sequence
+            )));
+        }
+
+        let filter = (!req.exclude_origin.is_empty()).then_some(ExportFilter {
+            exclude_origin: req.exclude_origin,
+            namespaces:     req.namespaces,
+        });
+        if filter.is_some() && self.regions.is_none() {
+            return Err(Status::failed_precondition("this node is not configured with a REGION"));
+        }
+
+        rpc_log!(self.log, "Replicate", replica_id = %replica_id, from_sequence = req.from_sequence, "REPLICATE");
+
+        Ok(Response::new(replication::stream_changes(
+            engine.clone(),
+            self.replication.clone(),
+            replica_id,
+            req.from_sequence,
+            req.learner,
+            filter,
+        )))
+    }
+
+    /// Stream a consistent checkpoint of the whole keyspace to a bootstrapping
+    /// replica.
+    #[instrument(name = "rpc_snapshot", skip(self, request))]
+    async fn snapshot(
+        &self,
+        request: Request<SnapshotRequest>,
+    ) -> Result<Response<Self::SnapshotStream>, Status> {
+        let req    = request.into_inner();
+        let engine = self.engine().ok_or_else(sharded_status)?;
+
+        rpc_log!(self.log, "Snapshot", replica_id = %req.replica_id, "SNAPSHOT");
+
+        Ok(Response::new(replication::stream_snapshot(
+            engine.clone(),
+            req.replica_id,
+        )))
+    }
+
+    /// Return the sequence a linearizable read must observe.
+    #[instrument(name = "rpc_read_index", skip(self, _request))]
+    async fn read_index(
+        &self,
+        _request: Request<ReadIndexRequest>,
+    ) -> Result<Response<ReadIndexResponse>, Status> {
+        let engine   = self.engine().ok_or_else(sharded_status)?;
+        let sequence = self.replication.read_index(engine).await?;
+        Ok(Response::new(ReadIndexResponse { sequence }))
+    }
+
+    /// Report this node's role, applied sequence and replication lag.
+    #[instrument(name = "rpc_replication_status", skip(self, _request))]
+    async fn replication_status(
+        &self,
+        _request: Request<ReplicationStatusRequest>,
+    ) -> Result<Response<ReplicationStatusResponse>, Status> {
+        let engine  = self.engine().ok_or_else(sharded_status)?;
+        let applied = engine.latest_sequence().map_err(|e| {
+            error!(error = %e, "REPLICATION_STATUS failed");
+            errors::engine_status(e)
+        })?;
+
+        let mut status = self.replication.status(applied);
+        status.region_peer = self
+            .regions
+            .as_ref()
+            .filter(|regions| regions.peer_addr().is_some())
+            .map(|regions| regions.status());
+        Ok(Response::new(status))
+    }
+
+    /// Record how far a replica (or region importer) has applied its stream.
+    #[instrument(name = "rpc_report_progress", skip(self, request))]
+    async fn report_progress(
+        &self,
+        request: Request<ProgressReport>,
+    ) -> Result<Response<ProgressAck>, Status> {
+        let req = request.into_inner();
+        if req.replica_id.is_empty() {
+            return Err(Status::invalid_argument("replica_id must not be empty"));
+        }
+
+        let engine = self.engine().ok_or_else(sharded_status)?;
+        let latest = engine.latest_sequence().map_err(errors::engine_status)?;
+        self.replication.record_report(&req.replica_id, req.applied_sequence, latest);
+        if let Some(regions) = &self.regions {
+            regions.record_report(&req.replica_id, req.applied_sequence);
+        }
+        Ok(Response::new(ProgressAck {}))
+    }
+
+    /// Move keys that are not on their owning shard (shard routers only).
+    #[instrument(name = "rpc_rebalance", skip(self, _request))]
+    async fn rebalance(
+        &self,
+        _request: Request<RebalanceRequest>,
+    ) -> Result<Response<RebalanceResponse>, Status> {
+        let Backend::Sharded(router) = &self.backend else {
+            return Err(Status::failed_precondition("this node is not a shard router"));
+        };
+
+        rpc_log!(self.log, "Rebalance", "REBALANCE");
+
+        let stats = router.rebalance().await?;
+        Ok(Response::new(RebalanceResponse {
+            scanned_keys: stats.scanned_keys,
+            moved_keys:   stats.moved_keys,
+            splits:       stats.splits,
+            merges:       stats.merges,
+        }))
+    }
+
+    /// Current partition table (range-partitioned shard routers only).
+    #[instrument(name = "rpc_partitions", skip(self, _request))]
+    async fn partitions(
+        &self,
+        _request: Request<PartitionsRequest>,
+    ) -> Result<Response<PartitionsResponse>, Status> {
+        let Backend::Sharded(router) = &self.backend else {
+            return Err(Status::failed_precondition("this node is not a shard router"));
+        };
+        let (table, shards) = router
+            .partition_table()
+            .ok_or_else(|| Status::failed_precondition("shard router is not range-partitioned"))?;
+
+        let partitions = table
+            .partitions
+            .into_iter()
+            .map(|p| {
+                let shard_addr = shards
+                    .iter()
+                    .find(|s| s.name() == p.shard)
+                    .and_then(|s| s.url())
+                    .unwrap_or_default()
+                    .to_owned();
+                PartitionInfo {
+                    start: p.start,
+                    end: p.end,
+                    shard: p.shard,
+                    shard_addr,
+                    moving_from: p.moving_from,
+                }
+            })
+            .collect();
+
+        Ok(Response::new(PartitionsResponse { epoch: table.epoch, partitions }))
+    }
+
+    /// Exchange membership records with a peer (also a direct probe).
+    #[instrument(name = "rpc_gossip", skip(self, request))]
+    async fn gossip(
+        &self,
+        request: Request<GossipRequest>,
+    ) -> Result<Response<GossipResponse>, Status> {
+        let members = self.membership.handle_gossip(request.into_inner());
+        Ok(Response::new(GossipResponse { members }))
+    }
+
+    /// Probe a member on behalf of the caller.
+    #[instrument(name = "rpc_ping_req", skip(self, request))]
+    async fn ping_req(
+        &self,
+        request: Request<PingReqRequest>,
+    ) -> Result<Response<PingReqResponse>, Status> {
+        let req = request.into_inner();
+        if req.target_addr.is_empty() {
+            return Err(Status::invalid_argument("target_addr must not be empty"));
+        }
+
+        let ack = self.membership.ping(&req.target_addr).await;
+        Ok(Response::new(PingReqResponse { ack }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::time::{Duration, Instant};
+
+    use lumen_core::{Engine, EngineOptions, SyncPolicy};
+    use tokio::io::DuplexStream;
+    use tokio::sync::mpsc;
+    use tokio_stream::wrappers::UnboundedReceiverStream;
+    use tonic::transport::{Channel, Endpoint, Uri};
+
+    use crate::kv::key_value_store_client::KeyValueStoreClient;
+    use crate::kv::{GetRequest, MultiGetRequest, PutRequest};
+    use crate::Server;
+
+    const WINDOW: Duration = Duration::from_millis(300);
+
+    /// A channel to a node serving `engine` on this runtime over in-memory
+    /// pipes.
+    pub(crate) async fn serve(engine: Engine) -> Channel {
+        let (connections, incoming) = mpsc::unbounded_channel::<DuplexStream>();
+        let server = Server::builder()
+            .engine(engine)
+            .serve_connections(UnboundedReceiverStream::new(incoming), std::future::pending());
+        tokio::spawn(server);
+        let connector = tower::service_fn(move |_: Uri| {
+            let connections = connections.clone();
+            async move {
+                let (client, server) = tokio::io::duplex(64 * 1024);
+                connections.send(server).map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+                Ok::<_, std::io::Error>(client)
+            }
+        });
+        Endpoint::from_static("http://lumen.test").connect_with_connector(connector).await.unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn writes_waiting_on_a_group_commit_hold_no_worker() {
+        let dir     = tempfile::tempdir().unwrap();
+        let options = EngineOptions { sync_policy: SyncPolicy::Interval(WINDOW), ..Default::default() };
+        let engine  = Engine::open_with(dir.path(), options).unwrap();
+        engine.put("read".to_owned(), b"me".to_vec()).unwrap();
+        let channel = serve(engine).await;
+
+        let started = Instant::now();
+        let writes: Vec<_> = (0..8)
+            .map(|i| {
+                let mut client = KeyValueStoreClient::new(channel.clone());
+                let put = PutRequest { key: format!("key-{i}"), value: b"v".to_vec(), ..Default::default() };
+                tokio::spawn(async move { client.put(put).await })
+            })
+            .collect();
+
+        // With the writes waiting out the window, a read still gets a
+        // worker at once.
+        tokio::time::sleep(WINDOW / 3).await;
+        let read = Instant::now();
+        let got  = KeyValueStoreClient::new(channel.clone())
+            .get(GetRequest { key: "read".to_owned(), ..Default::default() })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(read.elapsed() < WINDOW / 3, "the read took {:?}", read.elapsed());
+        assert_eq!(got.value, b"me");
+
+        // One sync covered every write; two workers held by them would
+        // have let through two per window.
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+        assert!(started.elapsed() < WINDOW * 2, "the writes took {:?}", started.elapsed());
+    }
+
+    #[tokio::test]
+    async fn a_multi_get_answers_each_key_in_order() {
+        let dir    = tempfile::tempdir().unwrap();
+        let engine = Engine::open(dir.path()).unwrap();
+        engine.put("a".to_owned(), b"1".to_vec()).unwrap();
+        engine.put("c".to_owned(), b"3".to_vec()).unwrap();
+        let mut client = KeyValueStoreClient::new(serve(engine).await);
+
+        let keys     = ["c", "b", "a"].map(str::to_owned).to_vec();
+        let response = client.multi_get(MultiGetRequest { keys, ..Default::default() }).await.unwrap();
+        assert!(response.metadata().get(super::APPLIED_SEQUENCE_HEADER).is_some());
+        let values: Vec<_> =
+            response.into_inner().values.into_iter().map(|value| value.found.then_some(value.value)).collect();
+        assert_eq!(values, [Some(b"3".to_vec()), None, Some(b"1".to_vec())]);
+
+        let keys = vec!["a".to_owned(), String::new()];
+        let err  = client.multi_get(MultiGetRequest { keys, ..Default::default() }).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
     }
 }
@@ -0,0 +1,328 @@
+//! Interactive transactions (`Session` RPC): a session held on the server
+//! across several round trips, keyed by an ID the client picks, so a
+//! workflow can read, decide and write without the whole transaction
+//! fitting in one request.
+//!
+//! A session remembers the first value it read of each key, so reading a
+//! key again gives the same answer, and buffers its writes until commit.
+//! Commit applies them in one batch if every key read still holds what was
+//! read (`Leases::write_batch_if`, under the WAL lock), and aborts the
+//! session otherwise.  Keys a session locks stay locked until it ends; other
+//! sessions can neither lock them nor commit writes to them, so a session
+//! that locks what it reads cannot be aborted by another session.  Writes
+//! outside sessions are only caught at commit, by the read check.
+//!
+//! Sessions live in memory.  One left idle for `idle_timeout` (a client that
+//! went away mid-transaction) is dropped with its writes and locks; a
+//! restart drops them all.  Only an unsharded primary without a REGION
+//! serves sessions, as commits go through its lease registry.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Status, Streaming};
+use tracing::{error, info};
+
+use lumen_core::{Engine, Leases, RedactedKey, WalRecord};
+
+use crate::errors::engine_status;
+use crate::kv::{session_request::Op, SessionRequest, SessionResponse};
+use crate::leases::lease_status;
+use crate::maintenance::Maintenance;
+use crate::service::{blocking, invalid_key};
+use crate::usage::Usage;
+
+/// How often idle sessions are looked for; a session outlives its timeout
+/// by up to this much.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+struct Session {
+    /// The first value read of each key (`None`: absent), checked again at
+    /// commit.
+    reads: HashMap<String, Option<Vec<u8>>>,
+    /// Buffered writes, `None` for a delete.
+    writes: BTreeMap<String, Option<Vec<u8>>>,
+    locks: BTreeSet<String>,
+    last_used: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    sessions: HashMap<String, Session>,
+    /// The session holding each locked key.
+    locks: HashMap<String, String>,
+}
+
+impl State {
+    /// End session `id`, releasing its locks.
+    fn end(&mut self, id: &str) -> Option<Session> {
+        let session = self.sessions.remove(id)?;
+        for key in &session.locks {
+            self.locks.remove(key);
+        }
+        Some(session)
+    }
+}
+
+/// The open sessions of one engine.
+#[derive(Debug)]
+pub struct Sessions {
+    engine: Arc<Engine>,
+    leases: Arc<Leases>,
+    idle_timeout: Duration,
+    /// Held across a commit, so no lock is taken between its check and its
+    /// write.
+    state: Mutex<State>,
+}
+
+impl Sessions {
+    pub fn new(engine: Arc<Engine>, leases: Arc<Leases>, idle_timeout: Duration) -> Self {
+        Self { engine, leases, idle_timeout, state: Mutex::new(State::default()) }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Open session `id`, or resume it if it is open already.
+    fn open(&self, id: &str) {
+        let mut state = self.state();
+        let now       = Instant::now();
+        state
+            .sessions
+            .entry(id.to_owned())
+            .and_modify(|session| session.last_used = now)
+            .or_insert_with(|| {
+                info!(session = %id, "SESSION OPEN");
+                Session { reads: HashMap::new(), writes: BTreeMap::new(), locks: BTreeSet::new(), last_used: now }
+            });
+    }
+
+    /// Run `op` on open session `id`, marking it used.
+    fn with<T>(&self, id: &str, op: impl FnOnce(&mut Session) -> Result<T, Status>) -> Result<T, Status> {
+        let mut state = self.state();
+        let session = state.sessions.get_mut(id).ok_or_else(|| expired_status(id))?;
+        session.last_used = Instant::now();
+        op(session)
+    }
+
+    /// `key` as session `id` sees it.
+    fn get(&self, id: &str, key: &str) -> Result<Option<Vec<u8>>, Status> {
+        self.with(id, |session| {
+            if let Some(written) = session.writes.get(key) {
+                return Ok(written.clone());
+            }
+            if let Some(read) = session.reads.get(key) {
+                return Ok(read.clone());
+            }
+            let value = self.engine.get(key).map_err(engine_status)?;
+            session.reads.insert(key.to_owned(), value.clone());
+            Ok(value)
+        })
+    }
+
+    /// Buffer a put (`Some`) or delete (`None`) of `key` in session `id`.
+    fn write(&self, id: &str, key: String, value: Option<Vec<u8>>) -> Result<(), Status> {
+        self.with(id, |session| {
+            session.writes.insert(key, value);
+            Ok(())
+        })
+    }
+
+    /// Lock `key` for session `id`.  Returns false if another session holds
+    /// it.
+    fn lock(&self, id: &str, key: &str) -> Result<bool, Status> {
+        let mut state = self.state();
+        match state.locks.get(key) {
+            Some(holder) if holder != id => return Ok(false),
+            Some(_) => {}
+            None => {
+                let session = state.sessions.get_mut(id).ok_or_else(|| expired_status(id))?;
+                session.locks.insert(key.to_owned());
+                state.locks.insert(key.to_owned(), id.to_owned());
+            }
+        }
+        // A lock held already still marks the session used.
+        state.sessions.get_mut(id).ok_or_else(|| expired_status(id))?.last_used = Instant::now();
+        Ok(true)
+    }
+
+    /// End session `id`, applying its writes.  Returns the key of each, with
+    /// the bytes it wrote.
+    fn commit(&self, id: &str) -> Result<Vec<(String, usize)>, Status> {
+        let mut state = self.state();
+        let session = state.end(id).ok_or_else(|| expired_status(id))?;
+
+        if let Some(key) = session.writes.keys().find(|key| state.locks.contains_key(*key)) {
+            return Err(Status::aborted(format!(
+                "session {id} aborted: key {} is locked by another session",
+                RedactedKey(key)
+            )));
+        }
+
+        let expected: Vec<(String, Option<Vec<u8>>)> = session.reads.into_iter().collect();
+        let mut written = Vec::with_capacity(session.writes.len());
+        let records: Vec<WalRecord> = session
+            .writes
+            .into_iter()
+            .map(|(key, value)| {
+                written.push((key.clone(), key.len() + value.as_ref().map_or(0, Vec::len)));
+                match value {
+                    Some(value) => WalRecord::Put { key, value },
+                    None => WalRecord::Delete { key },
+                }
+            })
+            .collect();
+
+        if !self.leases.write_batch_if(&expected, records).map_err(lease_status)? {
+            return Err(Status::aborted(format!("session {id} aborted: a key it read has changed since")));
+        }
+        Ok(written)
+    }
+
+    /// End session `id`, discarding its writes.
+    fn abort(&self, id: &str) {
+        self.state().end(id);
+    }
+}
+
+fn expired_status(id: &str) -> Status {
+    Status::aborted(format!("session {id} has expired"))
+}
+
+/// Drop sessions left idle for their timeout, for as long as the server
+/// runs.
+pub fn spawn_sweep(sessions: Arc<Sessions>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let mut state = sessions.state();
+            let now       = Instant::now();
+            let idle: Vec<String> = state
+                .sessions
+                .iter()
+                .filter(|(_, session)| now.duration_since(session.last_used) >= sessions.idle_timeout)
+                .map(|(id, _)| id.clone())
+                .collect();
+            for id in idle {
+                state.end(&id);
+                info!(session = %id, "Session expired");
+            }
+        }
+    });
+}
+
+/// Serve the session requests on `requests`, answering each in order until
+/// the session ends or a request fails.
+pub fn stream_session(
+    sessions: Arc<Sessions>,
+    maintenance: Arc<Maintenance>,
+    usage: Arc<Usage>,
+    mut requests: Streaming<SessionRequest>,
+) -> ReceiverStream<Result<SessionResponse, Status>> {
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        let mut session_id = None;
+        loop {
+            let request = match requests.message().await {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(status) => {
+                    info!(error = %status.message(), "Session stream broken");
+                    break;
+                }
+            };
+
+            if session_id.is_none() {
+                if request.session_id.is_empty() {
+                    let _ = tx.send(Err(Status::invalid_argument("session_id must not be empty"))).await;
+                    break;
+                }
+                sessions.open(&request.session_id);
+                session_id = Some(request.session_id);
+            }
+            let id = session_id.as_deref().expect("set above");
+
+            let ends     = matches!(request.op, Some(Op::Commit(_) | Op::Abort(_)));
+            let response = serve(&sessions, &maintenance, &usage, id, request.op).await;
+            let failed   = response.is_err();
+            if tx.send(response).await.is_err() || failed || ends {
+                break;
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+async fn serve(
+    sessions: &Arc<Sessions>,
+    maintenance: &Maintenance,
+    usage: &Usage,
+    id: &str,
+    op: Option<Op>,
+) -> Result<SessionResponse, Status> {
+    match op.ok_or_else(|| Status::invalid_argument("op must be set"))? {
+        Op::Get(get) => {
+            if let Some(status) = maintenance.check_read().or_else(|| invalid_key(&get.key)) {
+                return Err(status);
+            }
+            let value = sessions.get(id, &get.key)?;
+            usage.record(&get.key, value.as_ref().map_or(0, Vec::len), 0);
+            Ok(SessionResponse { found: value.is_some(), value: value.unwrap_or_default(), ..Default::default() })
+        }
+        Op::Put(put) => {
+            if let Some(status) = invalid_key(&put.key) {
+                return Err(status);
+            }
+            sessions.write(id, put.key, Some(put.value))?;
+            Ok(SessionResponse::default())
+        }
+        Op::Delete(delete) => {
+            if let Some(status) = invalid_key(&delete.key) {
+                return Err(status);
+            }
+            sessions.write(id, delete.key, None)?;
+            Ok(SessionResponse::default())
+        }
+        Op::Lock(lock) => {
+            if let Some(status) = invalid_key(&lock.key) {
+                return Err(status);
+            }
+            let locked = sessions.lock(id, &lock.key)?;
+            Ok(SessionResponse { locked, ..Default::default() })
+        }
+        Op::Commit(_) => {
+            let _write = maintenance.write().await?;
+            let (committed, session) = (sessions.clone(), id.to_owned());
+            #[allow(clippy::result_large_err)]
+            let written = blocking(move || committed.commit(&session)).await?.inspect_err(|status| {
+                if status.code() != tonic::Code::Aborted {
+                    error!(session = %id, error = %status.message(), "SESSION COMMIT failed");
+                }
+            })?;
+            for (key, bytes) in &written {
+                usage.record(key, 0, *bytes);
+            }
+            let sequence = if written.is_empty() {
+                0
+            } else {
+                sessions.engine.latest_sequence().map_err(engine_status)?
+            };
+            info!(session = %id, writes = written.len(), sequence, "SESSION COMMIT");
+            Ok(SessionResponse { sequence, ..Default::default() })
+        }
+        Op::Abort(_) => {
+            sessions.abort(id);
+            info!(session = %id, "SESSION ABORT");
+            Ok(SessionResponse::default())
+        }
+    }
+}
@@ -0,0 +1,913 @@
+//! Sharding of the keyspace across several engines.
+//!
+//! Each shard is either a local `Engine` rooted at `DATA_DIR/<name>` or a
+//! remote LumenKV node reached over gRPC.  Keys are placed on a hash ring
+//! with `vnodes` virtual points per shard, so adding or removing a shard only
+//! moves the keys whose arc of the ring changed hands.
+//!
+//! Rebalancing: the last fully balanced shard list is persisted in
+//! `DATA_DIR/ring`.  When the configured list differs, the router keeps the
+//! old ring around while a rebalance moves misplaced keys to their new owner:
+//! reads that miss on the new owner fall back to the old one, and deletes are
+//! applied to both, so no key disappears or resurrects mid-migration.  Keys
+//! are moved in small batches while client operations are held back, and
+//! the new ring is persisted once every shard has been drained.
+//!
+//! Range partitioning (`PARTITIONING=range`) replaces the ring with an
+//! explicit partition table (see `partitions`).  A periodic pass splits
+//! partitions that have grown past a key-count threshold at their median key
+//! (handing the upper half to the least-loaded shard) and merges adjacent
+//! partitions that have shrunk, moving keys with the same fallback scheme.
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::{Mutex as AsyncMutex, RwLock as AsyncRwLock};
+use tonic::transport::Channel;
+use tonic::Status;
+use tracing::{info, warn};
+
+use lumen_core::{is_reserved_key, Checkpoint, Engine, EngineOptions, KeyRange};
+
+use crate::errors::engine_status;
+use crate::kv::{
+    key_value_store_client::KeyValueStoreClient,
+    CompareAndDeleteRequest, DeleteRequest, GetAndSetRequest, GetRequest, PatchJsonRequest, PutRequest,
+    ReadConsistency, ScanRequest, SnapshotRequest,
+};
+use crate::json::{self, json_status};
+use crate::partitions::PartitionTable;
+
+/// Keys moved per migration batch; client operations wait while a batch runs.
+const MIGRATION_BATCH: usize = 256;
+
+/// Keys asked of a remote shard per page when listing its keys.
+const LISTING_PAGE: u32 = 4096;
+/// Reads the router makes itself (migrations, atomic operations) must
+/// observe every acknowledged write.
+const LINEARIZABLE: i32 = ReadConsistency::Linearizable as i32;
+
+// ---------------------------------------------------------------------------
+// Hash ring
+// ---------------------------------------------------------------------------
+
+/// FNV-1a followed by a SplitMix64 finaliser: stable across builds and
+/// platforms (unlike `DefaultHasher`) and well spread for short inputs.
+fn ring_hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for &b in bytes {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// Ring of virtual nodes mapping hash points to shard indices.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    points: BTreeMap<u64, usize>,
+}
+
+impl HashRing {
+    /// Build a ring over `members` (pairs of shard index and shard name).
+    fn new<'a>(members: impl IntoIterator<Item = (usize, &'a str)>, vnodes: u32) -> Self {
+        let mut points = BTreeMap::new();
+        for (index, name) in members {
+            for v in 0..vnodes {
+                points.insert(ring_hash(format!("{name}#{v}").as_bytes()), index);
+            }
+        }
+        Self { points }
+    }
+
+    /// Index of the shard owning `key`: the first point clockwise of its hash.
+    pub fn owner(&self, key: &str) -> usize {
+        let h = ring_hash(key.as_bytes());
+        self.points
+            .range(h..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, &index)| index)
+            .expect("hash ring has at least one shard")
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Shards
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+enum ShardTarget {
+    Local(Arc<Engine>),
+    Remote {
+        url: String,
+        client: KeyValueStoreClient<Channel>,
+    },
+}
+
+/// One partition of the keyspace.
+#[derive(Debug)]
+pub struct Shard {
+    name: String,
+    target: ShardTarget,
+}
+
+impl Shard {
+    /// Open a shard from its spec (`name` or `name=http://host:port`).
+    fn open(spec: &str, data_dir: &str, options: &EngineOptions) -> anyhow::Result<Self> {
+        let (name, target) = match spec.split_once('=') {
+            Some((name, url)) => {
+                let channel = Channel::from_shared(url.to_owned())
+                    .with_context(|| format!("invalid URL for shard `{name}`"))?
+                    .connect_lazy();
+                let client = KeyValueStoreClient::new(channel);
+                (name, ShardTarget::Remote { url: url.to_owned(), client })
+            }
+            None => {
+                let dir    = PathBuf::from(data_dir).join(spec);
+                let engine = Engine::open_with(&dir, options.within(spec))
+                    .with_context(|| format!("failed to open local shard `{spec}`"))?;
+                (spec, ShardTarget::Local(Arc::new(engine)))
+            }
+        };
+
+        if name.is_empty() {
+            anyhow::bail!("shard spec `{spec}` has an empty name");
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            target,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// gRPC URL of a remote shard; `None` for shards hosted by this node.
+    pub fn url(&self) -> Option<&str> {
+        match &self.target {
+            ShardTarget::Local(_) => None,
+            ShardTarget::Remote { url, .. } => Some(url),
+        }
+    }
+
+    async fn put(&self, key: String, value: Vec<u8>) -> Result<(), Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => engine.put(key, value).map_err(engine_status),
+            ShardTarget::Remote { client, .. } => {
+                client
+                    .clone()
+                    .put(PutRequest { key, value, ..Default::default() })
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Read `key`.  `consistency` is forwarded to remote shards; local shards
+    /// are always linearizable.
+    async fn get(&self, key: &str, consistency: i32) -> Result<Option<Vec<u8>>, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => engine.get(key).map_err(engine_status),
+            ShardTarget::Remote { client, .. } => {
+                let resp = client
+                    .clone()
+                    .get(GetRequest { key: key.to_owned(), consistency, ..Default::default() })
+                    .await?
+                    .into_inner();
+                Ok(resp.found.then_some(resp.value))
+            }
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => engine.delete(key).map_err(engine_status),
+            ShardTarget::Remote { client, .. } => {
+                let resp = client
+                    .clone()
+                    .delete(DeleteRequest { key: key.to_owned() })
+                    .await?
+                    .into_inner();
+                Ok(resp.success)
+            }
+        }
+    }
+
+    async fn compare_and_delete(&self, key: &str, expected: &[u8]) -> Result<bool, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => engine.compare_and_delete(key, expected).map_err(engine_status),
+            ShardTarget::Remote { client, .. } => {
+                let request = CompareAndDeleteRequest { key: key.to_owned(), expected: expected.to_vec() };
+                Ok(client.clone().compare_and_delete(request).await?.into_inner().deleted)
+            }
+        }
+    }
+
+    async fn get_and_set(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => engine.get_and_set(key, value).map_err(engine_status),
+            ShardTarget::Remote { client, .. } => {
+                let resp = client.clone().get_and_set(GetAndSetRequest { key, value }).await?.into_inner();
+                Ok(resp.found.then_some(resp.previous))
+            }
+        }
+    }
+
+    async fn patch_json(&self, key: String, patch: Vec<u8>) -> Result<Vec<u8>, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => json::patch(engine, key, &patch).map_err(json_status),
+            ShardTarget::Remote { client, .. } => {
+                Ok(client.clone().patch_json(PatchJsonRequest { key, patch }).await?.into_inner().value)
+            }
+        }
+    }
+
+    /// Every key this shard stores from `start` (inclusive) up to `end`
+    /// (exclusive, or to the last key if `None`), in order, listed by a
+    /// key-only scan so no values are read or sent.
+    async fn keys(&self, start: &str, end: Option<&str>) -> Result<Vec<String>, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => {
+                let range = KeyRange {
+                    prefix: String::new(),
+                    start:  if start.is_empty() { Bound::Unbounded } else { Bound::Included(start.to_owned()) },
+                    end:    end.map_or(Bound::Unbounded, |end| Bound::Excluded(end.to_owned())),
+                };
+                let keys: Result<Vec<String>, _> = engine.scan(range).and_then(|scan| {
+                    scan.filter(|entry| !entry.as_ref().is_ok_and(|(key, _)| is_reserved_key(key)))
+                        .map(|entry| entry.map(|(key, _)| key))
+                        .collect()
+                });
+                keys.map_err(engine_status)
+            }
+            ShardTarget::Remote { client, .. } => {
+                let mut request = ScanRequest {
+                    limit:     LISTING_PAGE,
+                    keys_only: true,
+                    start:     start.to_owned(),
+                    end:       end.unwrap_or_default().to_owned(),
+                    ..Default::default()
+                };
+                let mut keys = Vec::new();
+                loop {
+                    let mut stream = client.clone().scan(request.clone()).await?.into_inner();
+                    let mut cursor = String::new();
+                    while let Some(page) = stream.message().await? {
+                        keys.extend(page.entries.into_iter().map(|entry| entry.key));
+                        cursor = page.cursor;
+                    }
+                    if cursor.is_empty() {
+                        return Ok(keys);
+                    }
+                    request.cursor = cursor;
+                }
+            }
+        }
+    }
+
+    /// Full copy of this shard's contents.
+    async fn snapshot(&self) -> Result<Checkpoint, Status> {
+        match &self.target {
+            ShardTarget::Local(engine) => engine.checkpoint().map_err(engine_status),
+            ShardTarget::Remote { client, .. } => {
+                let mut stream = client
+                    .clone()
+                    .snapshot(SnapshotRequest { replica_id: "shard-router".to_owned() })
+                    .await?
+                    .into_inner();
+
+                let mut checkpoint = Checkpoint::default();
+                while let Some(chunk) = stream.message().await? {
+                    checkpoint.sequence = chunk.sequence;
+                    checkpoint.entries.extend(chunk.entries.into_iter().map(|e| (e.key, e.value)));
+                    if chunk.last {
+                        break;
+                    }
+                }
+                Ok(checkpoint)
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Router
+// ---------------------------------------------------------------------------
+
+/// How keys are assigned to shards.
+#[derive(Debug, Clone, Copy)]
+pub enum Partitioning {
+    /// Consistent hashing with `vnodes` virtual nodes per shard.
+    Hash { vnodes: u32 },
+    /// Explicit key ranges, split above `split_keys` keys and merged with a
+    /// neighbour when the pair holds fewer than `merge_keys`.
+    Range { split_keys: usize, merge_keys: usize },
+}
+
+#[derive(Debug)]
+enum Placement {
+    Hash {
+        ring: HashRing,
+        /// Ring that was in effect before the current one, while rebalancing.
+        previous: RwLock<Option<HashRing>>,
+        /// Specs of the current shards, persisted once balanced.
+        current_specs: Vec<String>,
+        ring_path: PathBuf,
+    },
+    Range {
+        table: RwLock<PartitionTable>,
+        /// Metadata-only engine holding the partition table.
+        system: Engine,
+        split_keys: usize,
+        merge_keys: usize,
+    },
+}
+
+/// Outcome of a rebalance pass.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RebalanceStats {
+    pub scanned_keys: u64,
+    pub moved_keys: u64,
+    pub splits: u64,
+    pub merges: u64,
+}
+
+/// Placement metadata captured with a `ClusterSnapshot`.
+#[derive(Debug)]
+pub enum PlacementSnapshot {
+    /// Contents of `DATA_DIR/ring`.
+    Ring(String),
+    /// The system engine holding the partition table.
+    Range(Checkpoint),
+}
+
+/// Every shard's contents as of one write barrier.
+#[derive(Debug)]
+pub struct ClusterSnapshot {
+    pub shards: Vec<(String, Checkpoint)>,
+    pub placement: PlacementSnapshot,
+}
+
+/// Routes each key to the shard that owns it.
+#[derive(Debug)]
+pub struct ShardRouter {
+    /// Configured shards first, followed by shards only present in the
+    /// previous hash ring (kept until they have been drained).
+    shards: Vec<Shard>,
+    placement: Placement,
+    /// Held shared by client operations, exclusively by migration batches.
+    migration: AsyncRwLock<()>,
+    /// Serialises rebalance passes (RPC, startup and partition monitor).
+    rebalancing: AsyncMutex<()>,
+}
+
+fn parse_specs(specs: &str) -> Vec<String> {
+    specs
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn name_of(spec: &str) -> &str {
+    spec.split_once('=').map_or(spec, |(name, _)| name)
+}
+
+fn router_status(e: anyhow::Error) -> Status {
+    Status::internal(format!("{e:#}"))
+}
+
+impl ShardRouter {
+    /// Open every shard in `specs` (comma-separated) plus any shard that the
+    /// persisted hash ring still references.
+    ///
+    /// Returns the router and whether a rebalance is pending.
+    pub fn open(
+        specs: &str,
+        data_dir: &str,
+        partitioning: Partitioning,
+        options: &EngineOptions,
+    ) -> anyhow::Result<(Self, bool)> {
+        std::fs::create_dir_all(data_dir).context("failed to create DATA_DIR")?;
+
+        let current_specs = parse_specs(specs);
+        if current_specs.is_empty() {
+            anyhow::bail!("SHARDS must list at least one shard");
+        }
+
+        let mut shards = Vec::new();
+        for spec in &current_specs {
+            shards.push(Shard::open(spec, data_dir, options)?);
+        }
+
+        let mut names: Vec<&str> = shards.iter().map(|s| s.name.as_str()).collect();
+        names.sort();
+        if names.windows(2).any(|w| w[0] == w[1]) {
+            anyhow::bail!("SHARDS contains duplicate shard names");
+        }
+
+        let (placement, pending) = match partitioning {
+            Partitioning::Hash { vnodes } => Self::open_ring(&mut shards, current_specs, data_dir, vnodes, options)?,
+            Partitioning::Range { split_keys, merge_keys } => {
+                let system = Engine::open_with(PathBuf::from(data_dir).join("system"), options.within("system"))
+                    .context("failed to open system engine")?;
+
+                let table = match PartitionTable::load(&system)? {
+                    Some(table) => table,
+                    None => {
+                        let table = PartitionTable::initial(&shards[0].name);
+                        table.store(&system)?;
+                        table
+                    }
+                };
+
+                for p in &table.partitions {
+                    for name in std::iter::once(&p.shard).chain(&p.moving_from) {
+                        if !shards.iter().any(|s| &s.name == name) {
+                            anyhow::bail!(
+                                "partition table references shard `{name}`, which is missing from SHARDS"
+                            );
+                        }
+                    }
+                }
+
+                let pending = table.partitions.iter().any(|p| p.moving_from.is_some());
+                let placement = Placement::Range {
+                    table: RwLock::new(table),
+                    system,
+                    split_keys: split_keys.max(2),
+                    // Keep merges from immediately undoing a split.
+                    merge_keys: merge_keys.min(split_keys / 2),
+                };
+                (placement, pending)
+            }
+        };
+
+        let router = Self {
+            shards,
+            placement,
+            migration: AsyncRwLock::new(()),
+            rebalancing: AsyncMutex::new(()),
+        };
+
+        info!(
+            shards = ?router.shards.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+            partitioning = ?partitioning,
+            "Shard router ready"
+        );
+        Ok((router, pending))
+    }
+
+    /// Build the hash placement, comparing against the persisted ring.
+    fn open_ring(
+        shards: &mut Vec<Shard>,
+        current_specs: Vec<String>,
+        data_dir: &str,
+        vnodes: u32,
+        options: &EngineOptions,
+    ) -> anyhow::Result<(Placement, bool)> {
+        let ring_path = PathBuf::from(data_dir).join("ring");
+        let persisted_specs: Option<Vec<String>> = match std::fs::read_to_string(&ring_path) {
+            Ok(text) => Some(text.lines().map(str::to_owned).filter(|l| !l.is_empty()).collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context("failed to read persisted shard ring"),
+        };
+
+        let ring = HashRing::new(
+            shards.iter().enumerate().map(|(i, s)| (i, s.name.as_str())),
+            vnodes,
+        );
+
+        // A missing ring file means a fresh router: nothing to migrate.
+        let previous = match persisted_specs {
+            Some(old) if old != current_specs => {
+                let mut members = Vec::new();
+                for spec in &old {
+                    let name  = name_of(spec);
+                    let index = match shards.iter().position(|s| s.name == name) {
+                        Some(index) => index,
+                        None => {
+                            shards.push(Shard::open(spec, data_dir, options)?);
+                            shards.len() - 1
+                        }
+                    };
+                    members.push((index, name.to_owned()));
+                }
+                info!(previous = ?old, current = ?current_specs, "Shard ring changed; rebalance pending");
+                Some(HashRing::new(
+                    members.iter().map(|(i, n)| (*i, n.as_str())),
+                    vnodes,
+                ))
+            }
+            _ => None,
+        };
+
+        let pending = previous.is_some();
+        let placement = Placement::Hash {
+            ring,
+            previous: RwLock::new(previous),
+            current_specs,
+            ring_path,
+        };
+        if !pending {
+            Self::persist_ring(&placement)?;
+        }
+        Ok((placement, pending))
+    }
+
+    fn persist_ring(placement: &Placement) -> anyhow::Result<()> {
+        if let Placement::Hash { current_specs, ring_path, .. } = placement {
+            let tmp = ring_path.with_extension("tmp");
+            std::fs::write(&tmp, current_specs.join("\n") + "\n")?;
+            std::fs::rename(&tmp, ring_path)?;
+        }
+        Ok(())
+    }
+
+    fn index_of(&self, name: &str) -> usize {
+        self.shards
+            .iter()
+            .position(|s| s.name == name)
+            .expect("partition table only references open shards")
+    }
+
+    /// Shard owning `key`, plus the shard that may still hold it while a
+    /// migration is in progress.
+    fn owners(&self, key: &str) -> (usize, Option<usize>) {
+        match &self.placement {
+            Placement::Hash { ring, previous, .. } => {
+                let owner = ring.owner(key);
+                let old = previous
+                    .read()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .as_ref()
+                    .map(|ring| ring.owner(key))
+                    .filter(|&old| old != owner);
+                (owner, old)
+            }
+            Placement::Range { table, .. } => {
+                let table = table.read().unwrap_or_else(|e| e.into_inner());
+                let p = &table.partitions[table.find(key)];
+                (
+                    self.index_of(&p.shard),
+                    p.moving_from.as_deref().map(|name| self.index_of(name)),
+                )
+            }
+        }
+    }
+
+    /// Current partition table (range partitioning only), with the shards it
+    /// references.
+    pub fn partition_table(&self) -> Option<(PartitionTable, &[Shard])> {
+        match &self.placement {
+            Placement::Range { table, .. } => Some((
+                table.read().unwrap_or_else(|e| e.into_inner()).clone(),
+                &self.shards,
+            )),
+            Placement::Hash { .. } => None,
+        }
+    }
+
+    /// The engines this node hosts: its local shards and, with range
+    /// partitioning, the system engine.
+    pub fn local_engines(&self) -> Vec<Engine> {
+        let mut engines: Vec<Engine> = self
+            .shards
+            .iter()
+            .filter_map(|shard| match &shard.target {
+                ShardTarget::Local(engine) => Some(Engine::clone(engine.as_ref())),
+                ShardTarget::Remote { .. } => None,
+            })
+            .collect();
+        if let Placement::Range { system, .. } = &self.placement {
+            engines.push(system.clone());
+        }
+        engines
+    }
+
+    pub async fn put(&self, key: String, value: Vec<u8>) -> Result<(), Status> {
+        let _guard = self.migration.read().await;
+        let (owner, _) = self.owners(&key);
+        self.shards[owner].put(key, value).await
+    }
+
+    pub async fn get(&self, key: &str, consistency: i32) -> Result<Option<Vec<u8>>, Status> {
+        let _guard = self.migration.read().await;
+        let (owner, old) = self.owners(key);
+
+        if let Some(value) = self.shards[owner].get(key, consistency).await? {
+            return Ok(Some(value));
+        }
+
+        match old {
+            Some(old) => self.shards[old].get(key, consistency).await,
+            None => Ok(None),
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<bool, Status> {
+        let _guard = self.migration.read().await;
+        let (owner, old) = self.owners(key);
+        let mut existed = self.shards[owner].delete(key).await?;
+
+        if let Some(old) = old {
+            existed |= self.shards[old].delete(key).await?;
+        }
+        Ok(existed)
+    }
+
+    /// `Engine::compare_and_delete` on the shard holding `key`.  Mid-migration
+    /// a copy left on the old shard is deleted with it, so it cannot
+    /// resurface.
+    pub async fn compare_and_delete(&self, key: &str, expected: &[u8]) -> Result<bool, Status> {
+        let _guard = self.migration.read().await;
+        let (owner, old) = self.owners(key);
+        let Some(old) = old else {
+            return self.shards[owner].compare_and_delete(key, expected).await;
+        };
+
+        if self.shards[owner].get(key, LINEARIZABLE).await?.is_none() {
+            return self.shards[old].compare_and_delete(key, expected).await;
+        }
+        let deleted = self.shards[owner].compare_and_delete(key, expected).await?;
+        if deleted {
+            self.shards[old].delete(key).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// `Engine::get_and_set` on the shard owning `key`.  Mid-migration the
+    /// previous value may still be on the old shard; its copy there is older
+    /// than the new value, which the migration therefore keeps.
+    pub async fn get_and_set(&self, key: String, value: Vec<u8>) -> Result<Option<Vec<u8>>, Status> {
+        let _guard = self.migration.read().await;
+        let (owner, old) = self.owners(&key);
+        let previous = self.shards[owner].get_and_set(key.clone(), value).await?;
+
+        match (previous, old) {
+            (None, Some(old)) => self.shards[old].get(&key, LINEARIZABLE).await,
+            (previous, _) => Ok(previous),
+        }
+    }
+
+    /// `PatchJson` on the shard holding `key`.  Mid-migration the key may
+    /// still be only on the old shard, which is patched in place for the
+    /// migration to move.
+    pub async fn patch_json(&self, key: String, patch: Vec<u8>) -> Result<Vec<u8>, Status> {
+        let _guard = self.migration.read().await;
+        let (owner, old) = self.owners(&key);
+        match old {
+            Some(old) if self.shards[owner].get(&key, LINEARIZABLE).await?.is_none() => {
+                self.shards[old].patch_json(key, patch).await
+            }
+            _ => self.shards[owner].patch_json(key, patch).await,
+        }
+    }
+
+    /// Move `keys` off shard `from` to whichever shard currently owns each
+    /// of them, in batches that hold back client operations.
+    async fn move_keys(&self, from: usize, keys: &[String]) -> Result<u64, Status> {
+        let shard = &self.shards[from];
+        let mut moved = 0;
+
+        for batch in keys.chunks(MIGRATION_BATCH) {
+            let _guard = self.migration.write().await;
+
+            for key in batch {
+                let (owner, _) = self.owners(key);
+                if owner == from {
+                    continue;
+                }
+
+                // Re-read under the lock: the key-list snapshot may be stale.
+                let Some(value) = shard.get(key, LINEARIZABLE).await? else { continue };
+                let owner = &self.shards[owner];
+
+                // A newer write may already have landed on the owner.
+                if owner.get(key, LINEARIZABLE).await?.is_none() {
+                    owner.put(key.clone(), value).await?;
+                }
+                shard.delete(key).await?;
+                moved += 1;
+            }
+        }
+
+        Ok(moved)
+    }
+
+    /// Snapshot every shard while client operations and migrations are held
+    /// back, so all of them reflect the same point in the router's history.
+    ///
+    /// Writes sent to remote shards directly (not through this router) are
+    /// not held back.
+    pub async fn snapshot(&self) -> Result<ClusterSnapshot, Status> {
+        let _pass  = self.rebalancing.lock().await;
+        let _guard = self.migration.write().await;
+
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            shards.push((shard.name.clone(), shard.snapshot().await?));
+        }
+
+        let placement = match &self.placement {
+            Placement::Hash { ring_path, current_specs, .. } => PlacementSnapshot::Ring(
+                std::fs::read_to_string(ring_path).unwrap_or_else(|_| current_specs.join("\n") + "\n"),
+            ),
+            Placement::Range { system, .. } => {
+                PlacementSnapshot::Range(system.checkpoint().map_err(engine_status)?)
+            }
+        };
+
+        Ok(ClusterSnapshot { shards, placement })
+    }
+
+    /// Move every key that is not on its owning shard; with range
+    /// partitioning, also split and merge partitions as needed.
+    pub async fn rebalance(&self) -> Result<RebalanceStats, Status> {
+        let _pass = self.rebalancing.lock().await;
+        let mut stats = RebalanceStats::default();
+
+        // Every key currently stored, per shard.
+        let mut stored = Vec::with_capacity(self.shards.len());
+        for shard in &self.shards {
+            let keys = shard.keys("", None).await?;
+            stats.scanned_keys += keys.len() as u64;
+            stored.push(keys);
+        }
+
+        for (index, keys) in stored.iter().enumerate() {
+            let moved = self.move_keys(index, keys).await?;
+            if moved > 0 {
+                info!(shard = %self.shards[index].name, moved, "Shard drained of misplaced keys");
+            }
+            stats.moved_keys += moved;
+        }
+
+        match &self.placement {
+            Placement::Hash { previous, .. } => {
+                Self::persist_ring(&self.placement)
+                    .context("failed to persist shard ring")
+                    .map_err(router_status)?;
+                *previous.write().unwrap_or_else(|e| e.into_inner()) = None;
+            }
+            Placement::Range { .. } => {
+                self.update_table(PartitionTable::finish_moves).map_err(router_status)?;
+                let all: Vec<String> = stored.into_iter().flatten().collect();
+                self.repartition(all, &mut stats).await?;
+            }
+        }
+
+        info!(
+            scanned = stats.scanned_keys,
+            moved   = stats.moved_keys,
+            splits  = stats.splits,
+            merges  = stats.merges,
+            "Rebalance complete"
+        );
+        Ok(stats)
+    }
+
+    /// Apply `change` to the partition table and persist it.
+    fn update_table(&self, change: impl FnOnce(&mut PartitionTable)) -> anyhow::Result<()> {
+        let Placement::Range { table, system, .. } = &self.placement else { return Ok(()) };
+
+        let mut table = table.write().unwrap_or_else(|e| e.into_inner());
+        let before = table.epoch;
+        change(&mut table);
+        if table.epoch != before {
+            table.store(system).context("failed to persist partition table")?;
+        }
+        Ok(())
+    }
+
+    /// Split oversized partitions and merge undersized neighbours, given
+    /// every key currently stored (all of which are on their owning shard).
+    async fn repartition(&self, mut keys: Vec<String>, stats: &mut RebalanceStats) -> Result<(), Status> {
+        let Placement::Range { table, split_keys, merge_keys, .. } = &self.placement else {
+            return Ok(());
+        };
+        keys.sort_unstable();
+
+        // ── Splits ──────────────────────────────────────────────────────────
+        let snapshot = table.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let mut splits = Vec::new();
+        for p in &snapshot.partitions {
+            let lo = keys.partition_point(|k| k.as_str() < p.start.as_str());
+            let hi = p.end.as_deref().map_or(keys.len(), |end| keys.partition_point(|k| k.as_str() < end));
+            if hi - lo > *split_keys {
+                let median = keys[lo + (hi - lo) / 2].clone();
+                splits.push((p.shard.clone(), median));
+            }
+        }
+
+        for (from, at) in splits {
+            let target = self.least_loaded_shard();
+            let mut end = None;
+            self.update_table(|t| {
+                let index = t.find(&at);
+                end = t.partitions[index].end.clone();
+                t.split(index, at.clone(), &target);
+            })
+            .map_err(router_status)?;
+            info!(at = %at, from = %from, to = %target, "Partition split");
+
+            stats.moved_keys += self.drain(self.index_of(&from), &at, end.as_deref()).await?;
+            stats.splits += 1;
+        }
+
+        // ── Merges ──────────────────────────────────────────────────────────
+        loop {
+            let snapshot = table.read().unwrap_or_else(|e| e.into_inner()).clone();
+            let count = |start: &str, end: Option<&str>| {
+                let lo = keys.partition_point(|k| k.as_str() < start);
+                let hi = end.map_or(keys.len(), |end| keys.partition_point(|k| k.as_str() < end));
+                hi - lo
+            };
+
+            let candidate = snapshot.partitions.windows(2).position(|pair| {
+                count(&pair[0].start, pair[1].end.as_deref()) < *merge_keys
+            });
+            let Some(index) = candidate else { break };
+
+            let mut from = None;
+            self.update_table(|t| from = t.merge(index)).map_err(router_status)?;
+            info!(start = %snapshot.partitions[index].start, into = %snapshot.partitions[index].shard, "Partitions merged");
+
+            if let Some(from) = from {
+                let moved = &snapshot.partitions[index + 1];
+                stats.moved_keys += self.drain(self.index_of(&from), &moved.start, moved.end.as_deref()).await?;
+            }
+            stats.merges += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Move every key of the range from `start` up to `end` that shard
+    /// `from` no longer owns, then mark the partition table's moves as
+    /// finished.
+    ///
+    /// The key list is taken after the table change, so writes that landed
+    /// on `from` before the change are moved too.
+    async fn drain(&self, from: usize, start: &str, end: Option<&str>) -> Result<u64, Status> {
+        let keys  = self.shards[from].keys(start, end).await?;
+        let moved = self.move_keys(from, &keys).await?;
+        self.update_table(PartitionTable::finish_moves).map_err(router_status)?;
+        Ok(moved)
+    }
+
+    /// Shard owning the fewest partitions (ties go to the earliest listed).
+    fn least_loaded_shard(&self) -> String {
+        let Placement::Range { table, .. } = &self.placement else {
+            return self.shards[0].name.clone();
+        };
+        let table = table.read().unwrap_or_else(|e| e.into_inner());
+
+        self.shards
+            .iter()
+            .min_by_key(|s| table.partitions.iter().filter(|p| p.shard == s.name).count())
+            .map(|s| s.name.clone())
+            .expect("router has at least one shard")
+    }
+
+    /// Run `rebalance` in the background, logging the outcome.
+    pub fn spawn_rebalance(self: &Arc<Self>) {
+        let router = self.clone();
+        tokio::spawn(async move {
+            if let Err(status) = router.rebalance().await {
+                warn!(error = %status.message(), "Rebalance failed; it will be retried at next start or via the Rebalance RPC");
+            }
+        });
+    }
+
+    /// Periodically split and merge range partitions.
+    pub fn spawn_partition_monitor(self: &Arc<Self>, interval: Duration) {
+        if !matches!(self.placement, Placement::Range { .. }) {
+            return;
+        }
+
+        let router = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(status) = router.rebalance().await {
+                    warn!(error = %status.message(), "Partition maintenance failed");
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,174 @@
+//! Per-namespace usage metering (`Usage` admin RPC, `lumen_namespace_*`
+//! metrics), for chargeback and for spotting noisy neighbours.
+//!
+//! Every key-value request that succeeds is counted against the namespace of
+//! its key (see `regions::namespace_of`), with the bytes of the values it
+//! returned and of the keys and values it sent to be written.  The totals are saved to
+//! `DATA_DIR/usage.json` every `REFRESH_INTERVAL`, so a restart carries them
+//! on and a crash loses at most one interval.  Storage per namespace is
+//! measured on the same schedule, by walking the memtable; a shard router
+//! has no engine to measure and reports none.
+//!
+//! Namespaces past the `MAX_NAMESPACES`th are counted together under
+//! `OVERFLOW`, so a client inventing namespaces cannot grow the table, or
+//! the metrics' label set, without bound.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+use tracing::warn;
+
+use lumen_core::{is_reserved_key, Engine};
+
+use crate::kv::NamespaceUsage;
+use crate::regions::namespace_of;
+
+/// How often storage is measured and the totals saved.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Most namespaces counted on their own.
+const MAX_NAMESPACES: usize = 10_000;
+
+/// Namespace the requests of namespaces past `MAX_NAMESPACES` are counted
+/// under.  Contains a `/`, so no key's namespace can be this.
+pub const OVERFLOW: &str = "/other";
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    requests: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    counters: BTreeMap<String, Counters>,
+    /// As of the last refresh.
+    storage: BTreeMap<String, u64>,
+}
+
+#[derive(Debug)]
+pub struct Usage {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+impl Usage {
+    /// Load the totals saved in `data_dir`, if any.
+    pub fn open(data_dir: &str) -> anyhow::Result<Self> {
+        let path = PathBuf::from(data_dir).join("usage.json");
+        let counters = match std::fs::read(&path) {
+            Ok(saved) => parse(&saved).context("corrupt usage file")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).context("failed to read usage file"),
+        };
+        Ok(Self { path, state: Mutex::new(State { counters, storage: BTreeMap::new() }) })
+    }
+
+    /// Count one request on `key`.
+    pub fn record(&self, key: &str, bytes_read: usize, bytes_written: usize) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut namespace = namespace_of(key);
+        if !state.counters.contains_key(namespace) {
+            if state.counters.len() >= MAX_NAMESPACES {
+                namespace = OVERFLOW;
+            }
+            state.counters.entry(namespace.to_owned()).or_default();
+        }
+        let counters = state.counters.get_mut(namespace).expect("inserted above");
+        counters.requests      += 1;
+        counters.bytes_read    += bytes_read as u64;
+        counters.bytes_written += bytes_written as u64;
+    }
+
+    /// Every namespace with requests or storage, in namespace order.
+    pub fn report(&self) -> Vec<NamespaceUsage> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut report: BTreeMap<&str, NamespaceUsage> = BTreeMap::new();
+        for (namespace, counters) in &state.counters {
+            let usage = report.entry(namespace).or_default();
+            usage.requests      = counters.requests;
+            usage.bytes_read    = counters.bytes_read;
+            usage.bytes_written = counters.bytes_written;
+        }
+        for (namespace, bytes) in &state.storage {
+            report.entry(namespace).or_default().storage_bytes = *bytes;
+        }
+        report
+            .into_iter()
+            .map(|(namespace, usage)| NamespaceUsage { namespace: namespace.to_owned(), ..usage })
+            .collect()
+    }
+
+    /// Measure storage on `engine` (if any) and save the totals.
+    fn refresh(&self, engine: Option<&Engine>) -> anyhow::Result<()> {
+        if let Some(engine) = engine {
+            let mut storage = engine.bytes_by(namespace_of)?;
+            storage.retain(|namespace, _| !is_reserved_key(namespace));
+            self.state.lock().unwrap_or_else(|e| e.into_inner()).storage = storage;
+        }
+
+        let saved = {
+            let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let totals: Map<String, Value> = state
+                .counters
+                .iter()
+                .map(|(namespace, c)| {
+                    let counters = json!({
+                        "requests": c.requests,
+                        "bytes_read": c.bytes_read,
+                        "bytes_written": c.bytes_written,
+                    });
+                    (namespace.clone(), counters)
+                })
+                .collect();
+            Value::Object(totals).to_string()
+        };
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, saved)?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Measure storage and save the totals every `REFRESH_INTERVAL`, for as
+/// long as the server runs.
+pub fn spawn_refresh(usage: Arc<Usage>, engine: Option<Arc<Engine>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let (usage, engine) = (usage.clone(), engine.clone());
+            match tokio::task::spawn_blocking(move || usage.refresh(engine.as_deref())).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!(error = %e, "Failed to refresh usage"),
+                Err(e) => warn!(error = %e, "Usage refresh panicked"),
+            }
+        }
+    });
+}
+
+fn parse(saved: &[u8]) -> anyhow::Result<BTreeMap<String, Counters>> {
+    let Value::Object(totals) = serde_json::from_slice(saved)? else {
+        anyhow::bail!("expected an object of namespaces");
+    };
+    totals
+        .into_iter()
+        .map(|(namespace, counters)| {
+            let field = |name: &str| {
+                counters[name].as_u64().with_context(|| format!("namespace {namespace:?} lacks `{name}`"))
+            };
+            let counters = Counters {
+                requests:      field("requests")?,
+                bytes_read:    field("bytes_read")?,
+                bytes_written: field("bytes_written")?,
+            };
+            Ok((namespace, counters))
+        })
+        .collect()
+}
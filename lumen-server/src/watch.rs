@@ -0,0 +1,145 @@
+//! Key-change subscriptions for clients (`Watch` RPC).
+//!
+//! Each watcher gets a task that tails the engine's change feed like a
+//! replica stream does, but forwards only changes to keys under the watched
+//! prefix, as `WatchEvent`s.  Whenever a fetched batch (or an idle wait)
+//! yields nothing to send, the task emits a `progress` event instead, so a
+//! watcher always knows a sequence to resume from that skips everything it
+//! has already seen or would have filtered out.  Deletes made by a lease
+//! expiring are sent as `OPERATION_EXPIRED`, so caches can tell them apart.
+
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::Status;
+use tracing::info;
+
+use lumen_core::{is_reserved_key, Change, Engine, ExpiryTracker, RedactedKey, WalRecord};
+
+use crate::errors::engine_status;
+use crate::kv::{Operation, WatchEvent};
+use crate::regions::Versioned;
+use crate::replication::{BATCH_LIMIT, HEARTBEAT_INTERVAL};
+
+/// Start streaming changes to keys under `prefix` committed after
+/// `from_sequence`.
+///
+/// `versioned` unwraps multi-region envelopes, turning tombstones into
+/// deletes.
+pub fn stream_watch(
+    engine: Arc<Engine>,
+    prefix: String,
+    from_sequence: u64,
+    versioned: bool,
+) -> ReceiverStream<Result<WatchEvent, Status>> {
+    let (tx, rx) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        info!(prefix = %RedactedKey(&prefix), from_sequence, "Watch stream opened");
+        let mut cursor = from_sequence;
+        let mut expiry = ExpiryTracker::new();
+
+        // Tell the watcher where it starts, so it can resume even if the
+        // stream breaks before the first change arrives.
+        if tx.send(Ok(progress(cursor))).await.is_err() {
+            return;
+        }
+
+        loop {
+            let eng = engine.clone();
+            let fetched = tokio::task::spawn_blocking(move || {
+                let changes = eng.changes_since(cursor, BATCH_LIMIT)?;
+                if !changes.is_empty() {
+                    return Ok((changes, cursor));
+                }
+                eng.wait_for_changes(cursor, HEARTBEAT_INTERVAL)
+                    .map(|latest| (Vec::new(), latest))
+            })
+            .await;
+
+            let (changes, latest) = match fetched {
+                Ok(Ok(fetched)) => fetched,
+                Ok(Err(e)) => {
+                    let _ = tx.send(Err(engine_status(e))).await;
+                    break;
+                }
+                Err(e) => {
+                    let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+                    break;
+                }
+            };
+
+            // Woken by a commit: fetch it rather than sending a heartbeat.
+            if changes.is_empty() && latest > cursor {
+                continue;
+            }
+
+            if let Some(last) = changes.last() {
+                cursor = last.sequence;
+            }
+
+            let events: Vec<WatchEvent> = changes
+                .into_iter()
+                .filter_map(|change| {
+                    // Every change, watched or not, moves the tracker on.
+                    let expired = expiry.is_expiry(&change.record);
+                    let key     = change_key(&change);
+                    (key.starts_with(prefix.as_str()) && !is_reserved_key(key))
+                        .then(|| to_event(change, expired, versioned))
+                })
+                .collect();
+
+            let sent = if events.is_empty() {
+                tx.send(Ok(progress(cursor))).await.is_ok()
+            } else {
+                let mut sent = true;
+                for event in events {
+                    if tx.send(Ok(event)).await.is_err() {
+                        sent = false;
+                        break;
+                    }
+                }
+                sent
+            };
+            if !sent {
+                break;
+            }
+        }
+
+        info!(prefix = %RedactedKey(&prefix), sent_sequence = cursor, "Watch stream closed");
+    });
+
+    ReceiverStream::new(rx)
+}
+
+pub(crate) fn change_key(change: &Change) -> &str {
+    match &change.record {
+        WalRecord::Put { key, .. } | WalRecord::Delete { key } => key,
+    }
+}
+
+fn progress(sequence: u64) -> WatchEvent {
+    WatchEvent { sequence, progress: true, ..Default::default() }
+}
+
+pub(crate) fn to_event(change: Change, expired: bool, versioned: bool) -> WatchEvent {
+    let (op, key, value) = match change.record {
+        WalRecord::Put { key, value } if versioned => match Versioned::decode(value).value {
+            Some(value) => (Operation::Put, key, value),
+            None => (Operation::Delete, key, Vec::new()),
+        },
+        WalRecord::Put { key, value } => (Operation::Put, key, value),
+        WalRecord::Delete { key } if expired => (Operation::Expired, key, Vec::new()),
+        WalRecord::Delete { key } => (Operation::Delete, key, Vec::new()),
+    };
+
+    WatchEvent {
+        sequence: change.sequence,
+        op: op as i32,
+        key,
+        value,
+        hlc_timestamp: change.timestamp,
+        progress: false,
+    }
+}
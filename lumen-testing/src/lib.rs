@@ -0,0 +1,196 @@
+//! In-process LumenKV nodes for integration tests.
+//!
+//! `TestServer::start()` opens an engine in a fresh temporary directory,
+//! serves it with `lumen_server::Server::builder()` over in-memory pipes
+//! rather than a socket, and connects a `lumen_client::Client` to it, so an
+//! application's tests run against a real node, with the server's own
+//! semantics, in milliseconds and with no ports to allocate:
+//!
+//! ```
+//! # use lumen_testing::TestServer;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> anyhow::Result<()> {
+//! let server = TestServer::start().await?;
+//! server.client().put("user/42", "alice").await?;
+//! assert_eq!(server.engine().get("user/42")?, Some(b"alice".to_vec()));
+//! server.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The node is an embedded one (see `lumen_server::ServerBuilder`): an
+//! unsharded primary, configured by the `TestConfig` it is started with
+//! alone, so a test runs the same whatever the environment sets.  Its
+//! directory is deleted when the server is shut down or dropped.  Needs a
+//! tokio runtime, such as the one `#[tokio::test]` starts.
+
+use std::io;
+use std::path::Path;
+
+use anyhow::Context;
+use lumen_client::{Client, ClientConfig};
+use lumen_core::{Engine, EngineOptions};
+use lumen_server::{Server, Settings};
+use tempfile::TempDir;
+use tokio::io::DuplexStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::transport::{Endpoint, Uri};
+
+/// Bytes each direction of a connection buffers.
+const PIPE_BYTES: usize = 64 * 1024;
+
+/// How a test node is set up.
+#[derive(Debug, Clone, Default)]
+pub struct TestConfig {
+    /// How its engine is opened.
+    pub engine: EngineOptions,
+    /// The server's settings, by the names of their environment variables
+    /// (see `lumen_server::Settings`; default: none).
+    pub settings: Settings,
+}
+
+/// A node serving an engine in a temporary directory, with a client
+/// connected to it.  Dropping it stops the server without waiting for it.
+#[derive(Debug)]
+pub struct TestServer {
+    client: Client,
+    engine: Engine,
+    /// Hands the server its end of each new connection.
+    connections: mpsc::UnboundedSender<DuplexStream>,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<anyhow::Result<()>>,
+    dir: TempDir,
+}
+
+impl TestServer {
+    /// Start a node with the default configuration.
+    pub async fn start() -> anyhow::Result<Self> {
+        Self::start_with(TestConfig::default()).await
+    }
+
+    /// Start a node configured by `config`.
+    pub async fn start_with(config: TestConfig) -> anyhow::Result<Self> {
+        let dir = tempfile::Builder::new()
+            .prefix("lumen-test-")
+            .tempdir()
+            .context("Failed to create a temporary data directory")?;
+        let engine = Engine::open_with(dir.path(), config.engine).context("Failed to open the test engine")?;
+
+        let (connections, incoming) = mpsc::unbounded_channel();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = Server::builder()
+            .engine(engine.clone())
+            .settings(config.settings)
+            .serve_connections(UnboundedReceiverStream::new(incoming), async move {
+                let _ = stopped.await;
+            });
+        let task = tokio::spawn(server);
+
+        let client = match connect(&connections, ClientConfig::default()).await {
+            Ok(client) => client,
+            Err(e) => {
+                // A server that failed to start explains why better.
+                if task.is_finished() {
+                    task.await.context("The test server panicked")??;
+                }
+                return Err(e);
+            }
+        };
+        Ok(Self { client, engine, connections, stop, task, dir })
+    }
+
+    /// The client connected when the node started, with the default
+    /// configuration.  Clones share its connection.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Another client, with a connection of its own, configured by
+    /// `config`.  Only its connection and request timeouts apply of the
+    /// settings of how to connect (see `Client::from_channel`).
+    pub async fn connect(&self, config: ClientConfig) -> anyhow::Result<Client> {
+        connect(&self.connections, config).await
+    }
+
+    /// The engine the node serves, for checking or preparing what it
+    /// holds directly.
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Stop the server, wait for it to finish, and delete its directory.
+    /// Returns the server's error, if it failed.
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        let Self { stop, task, dir, .. } = self;
+        let _ = stop.send(());
+        let result = task.await.context("The test server panicked")?;
+        drop(dir);
+        result
+    }
+}
+
+/// A client of the server `connections` feeds, connected through an
+/// in-memory pipe.
+async fn connect(connections: &mpsc::UnboundedSender<DuplexStream>, config: ClientConfig) -> anyhow::Result<Client> {
+    let connections  = connections.clone();
+    let mut endpoint = Endpoint::from_static("http://lumen.test").connect_timeout(config.connect_timeout);
+    if let Some(timeout) = config.request_timeout {
+        endpoint = endpoint.timeout(timeout);
+    }
+    let channel = endpoint
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            let connections = connections.clone();
+            async move {
+                let (client, server) = tokio::io::duplex(PIPE_BYTES);
+                connections
+                    .send(server)
+                    .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the test server has stopped"))?;
+                Ok::<_, io::Error>(client)
+            }
+        }))
+        .await
+        .context("Failed to connect to the test server")?;
+    Ok(Client::from_channel(channel, config)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn serves_its_engine_to_its_clients() {
+        let server = TestServer::start().await.unwrap();
+        server.client().put("user/42", "alice").await.unwrap();
+        assert_eq!(server.client().get("user/42").await.unwrap(), Some(b"alice".to_vec()));
+        assert_eq!(server.engine().get("user/42").unwrap(), Some(b"alice".to_vec()));
+
+        // A second client has a connection of its own to the same node.
+        let config = ClientConfig { request_timeout: Some(Duration::from_secs(5)), ..Default::default() };
+        let other  = server.connect(config).await.unwrap();
+        other.put("user/43", "bob").await.unwrap();
+        assert_eq!(server.client().get("user/43").await.unwrap(), Some(b"bob".to_vec()));
+
+        let dir = server.data_dir().to_owned();
+        server.shutdown().await.unwrap();
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn reads_only_the_settings_it_is_given() {
+        let config = TestConfig { settings: Settings::default().set("WRITE_LIMITS", "nope"), ..Default::default() };
+        // The server fails before or after the client connects.
+        let err = match TestServer::start_with(config).await {
+            Ok(server) => server.shutdown().await.unwrap_err(),
+            Err(e) => e,
+        };
+        assert!(format!("{err:#}").contains("WRITE_LIMITS"), "{err:#}");
+    }
+}